@@ -0,0 +1,59 @@
+//! Build metadata for `portablesource version`: commit, build date, target, profile and
+//! enabled cargo features, passed to the crate as `PS_BUILD_*` variables.
+
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn git_commit() -> Option<String> {
+    let output = Command::new("git").args(["rev-parse", "--short=12", "HEAD"]).output().ok()?;
+    let commit = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    (output.status.success() && !commit.is_empty()).then_some(commit)
+}
+
+/// `YYYY-MM-DD` (UTC) of a unix timestamp
+fn utc_date(secs: u64) -> String {
+    // Civil-from-days, Howard Hinnant's algorithm
+    let z = (secs / 86_400) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+fn main() {
+    // Reproducible builds pin the date through SOURCE_DATE_EPOCH
+    let secs = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0));
+    let mut features: Vec<String> = std::env::vars()
+        .filter_map(|(key, _)| key.strip_prefix("CARGO_FEATURE_").map(|f| f.to_lowercase().replace('_', "-")))
+        .collect();
+    features.sort();
+
+    println!("cargo:rustc-env=PS_BUILD_COMMIT={}", git_commit().unwrap_or_else(|| "unknown".into()));
+    println!("cargo:rustc-env=PS_BUILD_DATE={}", utc_date(secs));
+    println!("cargo:rustc-env=PS_BUILD_TARGET={}", std::env::var("TARGET").unwrap_or_default());
+    println!("cargo:rustc-env=PS_BUILD_PROFILE={}", std::env::var("PROFILE").unwrap_or_default());
+    println!("cargo:rustc-env=PS_BUILD_FEATURES={}", features.join(","));
+
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    for path in [".git/HEAD", ".git/packed-refs"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+    let head = std::fs::read_to_string(".git/HEAD").unwrap_or_default();
+    if let Some(reference) = head.trim().strip_prefix("ref: ") {
+        let path = Path::new(".git").join(reference);
+        if path.exists() {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+    }
+}
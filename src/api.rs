@@ -0,0 +1,462 @@
+//! Stable library API
+//!
+//! This module is the supported surface for GUIs and tools embedding PortableSource. It
+//! follows semver: within a major version nothing here is removed or changed incompatibly.
+//! Structs and enums are `#[non_exhaustive]`, so minor releases may add fields, variants
+//! and methods; build values through the provided constructors and match with `_` arms.
+//!
+//! Every other module of the crate is an implementation detail of the CLI and may change
+//! in any release.
+//!
+//! Environment setup runs its downloads in parallel, but events are delivered one at a
+//! time: `Task` events carry an increasing `seq` and the id of the task they belong to, and
+//! `Completed` is always the last event, also when the operation fails.
+//!
+//! ```no_run
+//! # async fn demo() -> Result<(), portablesource_rs::api::Error> {
+//! use portablesource_rs::api::{Event, InstallOptions, PortableSource};
+//!
+//! let ps = PortableSource::open("/opt/portablesource")?;
+//! ps.setup_environment(|event| println!("{:?}", event)).await?;
+//! let repo = ps.install(InstallOptions::new("comfyui").accept_license(true), |_: Event| {}).await?;
+//! println!("installed {} at {}", repo.name, repo.path.display());
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::config::{ConfigManager, InstallEngine};
+use crate::envs_manager::{PortableEnvironmentManager, SetupPhase};
+use crate::repo_index::{RepoIndex, RepoSource};
+use crate::repository_installer::RepositoryInstaller;
+use crate::PortableSourceError;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Error category, for deciding what to show or retry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    Network,
+    /// Repository or file does not exist
+    NotFound,
+    PermissionDenied,
+    DiskFull,
+    /// A package needs a C/C++ compiler that is not installed
+    CompilerMissing,
+    /// Invalid input or configuration
+    InvalidInput,
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub message: String,
+    /// What the user can do about it, when known
+    pub hint: Option<String>,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<PortableSourceError> for Error {
+    fn from(e: PortableSourceError) -> Self {
+        let kind = match &e {
+            PortableSourceError::Network { .. } | PortableSourceError::Reqwest(_) => ErrorKind::Network,
+            PortableSourceError::PermissionDenied { .. } => ErrorKind::PermissionDenied,
+            PortableSourceError::DiskFull { .. } => ErrorKind::DiskFull,
+            PortableSourceError::CompilerMissing { .. } => ErrorKind::CompilerMissing,
+            PortableSourceError::Io(io) if io.kind() == std::io::ErrorKind::NotFound => ErrorKind::NotFound,
+            PortableSourceError::Io(io) if io.kind() == std::io::ErrorKind::PermissionDenied => ErrorKind::PermissionDenied,
+            PortableSourceError::RepositoryNotFound { .. } => ErrorKind::NotFound,
+            PortableSourceError::Config { .. } | PortableSourceError::InvalidPath { .. } | PortableSourceError::Url(_) => ErrorKind::InvalidInput,
+            _ => ErrorKind::Other,
+        };
+        Self { kind, message: e.to_string(), hint: e.remediation_hint() }
+    }
+}
+
+/// Progress reported while an operation runs
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Event {
+    Started { operation: Operation, target: String },
+    /// Step `done` of `total` finished (environment setup). Sent after every `Task` event
+    /// for callers written before `Task` existed.
+    Progress { message: String, done: usize, total: usize },
+    Task(TaskProgress),
+    Finished { operation: Operation, target: String, elapsed: Duration },
+    /// Last event of an operation, after `Finished` on success. `seq` follows the last
+    /// `Task` event (1 when there was none).
+    Completed { operation: Operation, target: String, seq: u64, elapsed: Duration, error: Option<Error> },
+}
+
+/// A state change of one parallel task
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct TaskProgress {
+    /// Position among the events of the operation, starting at 1
+    pub seq: u64,
+    /// "setup" for the plan, then "python", "git", "ffmpeg" or "cuda"
+    pub task: String,
+    pub phase: TaskPhase,
+    /// Steps finished by all tasks together
+    pub done: usize,
+    pub total: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TaskPhase {
+    /// The task list is known, `total` is final
+    Planned,
+    Downloading,
+    Extracting,
+    Finished,
+}
+
+impl From<SetupPhase> for TaskPhase {
+    fn from(phase: SetupPhase) -> Self {
+        match phase {
+            SetupPhase::Planned => TaskPhase::Planned,
+            SetupPhase::Downloading => TaskPhase::Downloading,
+            SetupPhase::Extracting => TaskPhase::Extracting,
+            SetupPhase::Installed | SetupPhase::Completed => TaskPhase::Finished,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Operation {
+    SetupEnvironment,
+    Install,
+    Update,
+    Delete,
+}
+
+/// Package installer used for a repository's dependencies
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum Engine {
+    /// uv, falling back to pip per step
+    #[default]
+    Auto,
+    Uv,
+    Pip,
+}
+
+impl From<Engine> for InstallEngine {
+    fn from(engine: Engine) -> Self {
+        match engine {
+            Engine::Auto => InstallEngine::Auto,
+            Engine::Uv => InstallEngine::Uv,
+            Engine::Pip => InstallEngine::Pip,
+        }
+    }
+}
+
+/// What to install and how
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct InstallOptions {
+    /// Name known to the PortableSource server, or a git URL
+    pub source: String,
+    /// Install under this folder name (a second instance of the same repository)
+    pub name: Option<String>,
+    /// Accept non-permissive licenses without the interactive prompt
+    pub accept_license: bool,
+    pub engine: Option<Engine>,
+}
+
+impl InstallOptions {
+    pub fn new(source: impl Into<String>) -> Self {
+        Self { source: source.into(), name: None, accept_license: false, engine: None }
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn accept_license(mut self, accept: bool) -> Self {
+        self.accept_license = accept;
+        self
+    }
+
+    pub fn engine(mut self, engine: Engine) -> Self {
+        self.engine = Some(engine);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Source {
+    Github,
+    Git,
+    Server,
+}
+
+/// An installed repository
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct Repository {
+    pub name: String,
+    pub path: PathBuf,
+    pub source: Source,
+    /// Upstream repository name when installed under another name
+    pub upstream: Option<String>,
+    /// Start script, if it was generated
+    pub start_script: Option<PathBuf>,
+}
+
+/// Portable tools found in the install directory
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct EnvironmentStatus {
+    pub python: Option<PathBuf>,
+    pub git: Option<PathBuf>,
+    pub ffmpeg: Option<PathBuf>,
+}
+
+impl EnvironmentStatus {
+    /// Everything needed to install repositories is present
+    pub fn is_ready(&self) -> bool {
+        self.python.is_some() && self.git.is_some()
+    }
+}
+
+/// Send `Finished` when `result` is Ok, then `Completed` with its error; the result is passed on
+fn complete<T>(on_event: &dyn Fn(Event), operation: Operation, target: String, started: Instant, seq: u64, result: Result<T>) -> Result<T> {
+    let elapsed = started.elapsed();
+    if result.is_ok() {
+        on_event(Event::Finished { operation, target: target.clone(), elapsed });
+    }
+    on_event(Event::Completed { operation, target, seq, elapsed, error: result.as_ref().err().cloned() });
+    result
+}
+
+/// Handle to one PortableSource install directory
+pub struct PortableSource {
+    install_path: PathBuf,
+    config: ConfigManager,
+}
+
+impl PortableSource {
+    /// Open (and create if needed) an install directory and load its configuration
+    pub fn open(install_path: impl AsRef<Path>) -> Result<Self> {
+        let install_path = crate::utils::validate_and_create_path(install_path.as_ref())?;
+        let mut config = ConfigManager::new(Some(install_path.join("portablesource_config.json")))?;
+        config.set_install_path(install_path.clone())?;
+        Ok(Self { install_path, config })
+    }
+
+    pub fn install_path(&self) -> &Path {
+        &self.install_path
+    }
+
+    fn env_manager(&self) -> PortableEnvironmentManager {
+        PortableEnvironmentManager::with_config(self.install_path.clone(), self.config.clone())
+    }
+
+    fn installer(&self) -> RepositoryInstaller {
+        RepositoryInstaller::new(self.install_path.clone(), self.config.clone())
+    }
+
+    /// Download and unpack the portable Python, git, ffmpeg (and CUDA with an NVIDIA GPU)
+    ///
+    /// `on_event` is never called concurrently, although the downloads run in parallel.
+    pub async fn setup_environment<F>(&self, on_event: F) -> Result<()>
+    where
+        F: Fn(Event) + Send + Sync + 'static,
+    {
+        let target = self.install_path.display().to_string();
+        let started = Instant::now();
+        on_event(Event::Started { operation: Operation::SetupEnvironment, target: target.clone() });
+        let on_event = Arc::new(on_event);
+        let last_seq = Arc::new(AtomicU64::new(0));
+        let (progress, seen) = (on_event.clone(), last_seq.clone());
+        let result = self
+            .env_manager()
+            .setup_environment_with_progress(move |event| {
+                seen.store(event.seq, Ordering::SeqCst);
+                if event.phase == SetupPhase::Completed {
+                    return;
+                }
+                let message = event.task.clone();
+                let (done, total) = (event.done, event.total);
+                progress(Event::Task(TaskProgress { seq: event.seq, task: event.task, phase: event.phase.into(), done, total }));
+                progress(Event::Progress { message, done, total });
+            })
+            .await
+            .map_err(Error::from);
+        complete(&*on_event, Operation::SetupEnvironment, target, started, last_seq.load(Ordering::SeqCst), result)
+    }
+
+    /// `setup_environment` reporting through a channel instead of a callback. Events are
+    /// sent in order; a dropped receiver does not stop the setup.
+    pub async fn setup_environment_events(&self, events: tokio::sync::mpsc::UnboundedSender<Event>) -> Result<()> {
+        self.setup_environment(move |event| {
+            let _ = events.send(event);
+        })
+        .await
+    }
+
+    pub fn environment_status(&self) -> EnvironmentStatus {
+        let env = self.env_manager();
+        EnvironmentStatus {
+            python: env.get_python_executable(),
+            git: env.get_git_executable(),
+            ffmpeg: env.get_ffmpeg_executable(),
+        }
+    }
+
+    pub async fn install<F>(&self, options: InstallOptions, on_event: F) -> Result<Repository>
+    where
+        F: Fn(Event) + Send + Sync,
+    {
+        let started = Instant::now();
+        on_event(Event::Started { operation: Operation::Install, target: options.source.clone() });
+        let mut installer = self
+            .installer()
+            .with_install_engine(options.engine.map(Into::into))
+            .with_license_acceptance(options.accept_license)
+            .with_instance_name(options.name.clone());
+        let result = match installer.install_repository(&options.source).await {
+            Ok(()) => self.installed(installer.installed_name().unwrap_or(&options.source)),
+            Err(e) => Err(e.into()),
+        };
+        complete(&on_event, Operation::Install, options.source, started, 1, result)
+    }
+
+    pub async fn update<F>(&self, name: &str, on_event: F) -> Result<Repository>
+    where
+        F: Fn(Event) + Send + Sync,
+    {
+        let started = Instant::now();
+        on_event(Event::Started { operation: Operation::Update, target: name.to_string() });
+        let result = match self.installer().update_repository(name).await {
+            Ok(()) => self.installed(name),
+            Err(e) => Err(e.into()),
+        };
+        complete(&on_event, Operation::Update, name.to_string(), started, 1, result)
+    }
+
+    fn installed(&self, name: &str) -> Result<Repository> {
+        self.repository(name)?.ok_or_else(|| PortableSourceError::repository_not_found(name).into())
+    }
+
+    /// Remove the repository folder and its environment
+    pub fn delete(&self, name: &str) -> Result<()> {
+        Ok(self.installer().delete_repository(name)?)
+    }
+
+    /// Installed repositories, sorted by name
+    pub fn repositories(&self) -> Result<Vec<Repository>> {
+        let index = RepoIndex::load_or_refresh(&self.install_path)?;
+        Ok(index
+            .repos
+            .into_iter()
+            .map(|(name, entry)| self.describe(name, entry.source, entry.upstream))
+            .collect())
+    }
+
+    pub fn repository(&self, name: &str) -> Result<Option<Repository>> {
+        Ok(self.repositories()?.into_iter().find(|r| r.name.eq_ignore_ascii_case(name)))
+    }
+
+    fn describe(&self, name: String, source: RepoSource, upstream: Option<String>) -> Repository {
+        let path = self.install_path.join("repos").join(&name);
+        let script = crate::launch_command::start_script(&self.install_path, &name);
+        let source = match source {
+            RepoSource::Github => Source::Github,
+            RepoSource::Git => Source::Git,
+            RepoSource::Server => Source::Server,
+        };
+        Repository { start_script: script.exists().then_some(script), name, path, source, upstream }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(windows)]
+    const DEMO_SCRIPT: &str = "start_demo.bat";
+    #[cfg(not(windows))]
+    const DEMO_SCRIPT: &str = "start_demo.sh";
+
+    /// Install with the GitHub repository `demo` and its start script
+    fn demo_install(root: &Path) -> PortableSource {
+        let ps = PortableSource::open(root).unwrap();
+        let repo = root.join("repos").join("demo");
+        std::fs::create_dir_all(&repo).unwrap();
+        std::fs::write(repo.join("link.txt"), "https://github.com/acme/demo").unwrap();
+        std::fs::write(repo.join(DEMO_SCRIPT), "").unwrap();
+        ps
+    }
+
+    #[test]
+    fn repositories_are_listed_with_their_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let listed = demo_install(dir.path()).repositories().unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].name, "demo");
+        assert_eq!(listed[0].source, Source::Github);
+    }
+
+    #[test]
+    fn repositories_name_their_start_script() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = demo_install(dir.path()).repository("demo").unwrap().unwrap();
+        assert_eq!(repo.start_script, Some(dir.path().join("repos/demo").join(DEMO_SCRIPT)));
+    }
+
+    #[test]
+    fn deleting_a_missing_repository_is_not_found() {
+        let dir = tempfile::tempdir().unwrap();
+        let err = demo_install(dir.path()).delete("missing").unwrap_err();
+        assert_eq!(err.kind, ErrorKind::NotFound);
+        assert_eq!(err.message, "Repository not found: missing");
+    }
+
+    #[test]
+    fn other_repository_errors_are_not_classified_as_not_found() {
+        let err = Error::from(PortableSourceError::repository("Repository 'x' not found in the cache"));
+        assert_eq!(err.kind, ErrorKind::Other);
+    }
+
+    #[tokio::test]
+    async fn failed_install_ends_with_a_completed_event_carrying_the_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let events = std::sync::Mutex::new(Vec::new());
+        let err = demo_install(dir.path())
+            .install(InstallOptions::new("https://"), |event| events.lock().unwrap().push(event))
+            .await
+            .unwrap_err();
+
+        let events = events.into_inner().unwrap();
+        assert_eq!(events.len(), 2, "{:?}", events);
+        assert!(matches!(&events[0], Event::Started { operation: Operation::Install, .. }));
+        let Event::Completed { operation, target, seq, error, .. } = &events[1] else { panic!("{:?}", events[1]) };
+        assert_eq!((*operation, target.as_str(), *seq), (Operation::Install, "https://", 1));
+        assert_eq!(error.as_ref(), Some(&err));
+    }
+
+    #[test]
+    fn environment_without_tools_is_not_ready() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(!demo_install(dir.path()).environment_status().is_ready());
+    }
+}
@@ -0,0 +1,180 @@
+//! Crash-safe file writes
+//!
+//! Start scripts, repo metadata and the config are written to `<file>.pstmp`, fsynced and
+//! renamed over the target, so a crash or power loss leaves either the old or the new
+//! file, never a truncated one. The writer locks its temp until the rename is done, so a
+//! leftover `.pstmp` nobody holds a lock on means a write was interrupted; those are cleaned
+//! up on startup by [`recover_stale_temps`].
+
+use crate::Result;
+use tracing::{debug, warn};
+use std::fs::{self, File, TryLockError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+pub const TEMP_SUFFIX: &str = ".pstmp";
+
+/// Sibling temp file used while writing `path`
+pub fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(TEMP_SUFFIX);
+    path.with_file_name(name)
+}
+
+/// Replace `path` with `contents` atomically
+pub fn write(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> Result<()> {
+    write_with_mode(path.as_ref(), contents.as_ref(), None)
+}
+
+/// Like [`write`], but the file is executable (0755) on Unix from the moment it appears
+pub fn write_executable(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> Result<()> {
+    write_with_mode(path.as_ref(), contents.as_ref(), Some(0o755))
+}
+
+fn write_with_mode(path: &Path, contents: &[u8], mode: Option<u32>) -> Result<()> {
+    #[cfg(not(unix))]
+    let _ = mode;
+    let tmp = temp_path(path);
+    let result = (|| -> Result<()> {
+        let mut file = File::create(&tmp)?;
+        // Marks the temp as in use for `recover_stale_temps` of other processes
+        if let Err(e) = file.lock() {
+            debug!("Cannot lock {:?}: {}", tmp, e);
+        }
+        file.write_all(contents)?;
+        #[cfg(unix)]
+        if let Some(mode) = mode {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(fs::Permissions::from_mode(mode))?;
+        }
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
+        drop(file);
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+        return result;
+    }
+    sync_parent_dir(path);
+    Ok(())
+}
+
+/// Persist the rename itself; directories cannot be opened for syncing on Windows
+fn sync_parent_dir(path: &Path) {
+    #[cfg(unix)]
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        if let Err(e) = File::open(parent).and_then(|d| d.sync_all()) {
+            debug!("Failed to sync directory {:?}: {}", parent, e);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+}
+
+/// A temp file left behind by an interrupted write
+#[derive(Debug, Clone, PartialEq)]
+pub struct StaleTemp {
+    pub temp: PathBuf,
+    pub target: PathBuf,
+    /// The target does not exist, so the interrupted write was its first one
+    pub target_missing: bool,
+}
+
+/// Lock on a temp no writer holds any more; None while a write (maybe of another process) is
+/// still running. Where locks are not supported every temp counts as abandoned
+fn lock_abandoned(temp: &Path) -> Option<File> {
+    let file = File::open(temp).ok()?;
+    match file.try_lock() {
+        Ok(()) => Some(file),
+        Err(TryLockError::WouldBlock) => None,
+        Err(TryLockError::Error(e)) => {
+            debug!("Cannot lock {:?}: {}", temp, e);
+            Some(file)
+        }
+    }
+}
+
+/// Remove leftover temp files in `install_path` and each `repos/<name>/`.
+/// The temp may be incomplete, so the target (old version) always wins. Temps another
+/// process is still writing are left alone.
+pub fn recover_stale_temps(install_path: &Path) -> Vec<StaleTemp> {
+    let mut dirs = vec![install_path.to_path_buf()];
+    if let Ok(entries) = fs::read_dir(install_path.join("repos")) {
+        dirs.extend(entries.flatten().map(|e| e.path()).filter(|p| p.is_dir()));
+    }
+
+    let mut stale = Vec::new();
+    for dir in dirs {
+        let Ok(entries) = fs::read_dir(&dir) else { continue };
+        for entry in entries.flatten() {
+            let temp = entry.path();
+            let Some(target_name) = temp.file_name().and_then(|n| n.to_str()).and_then(|n| n.strip_suffix(TEMP_SUFFIX)) else {
+                continue;
+            };
+            let target = temp.with_file_name(target_name);
+            let Some(_lock) = lock_abandoned(&temp) else {
+                debug!("{:?} is being written by another process", temp);
+                continue;
+            };
+            if let Err(e) = fs::remove_file(&temp) {
+                warn!("Failed to remove interrupted write {:?}: {}", temp, e);
+                continue;
+            }
+            stale.push(StaleTemp { target_missing: !target.exists(), temp, target });
+        }
+    }
+    stale
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_replaces_file_without_leaving_temp() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("start_app.sh");
+        write(&path, "old").unwrap();
+        write_executable(&path, "new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert!(!temp_path(&path).exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o755);
+        }
+    }
+
+    #[test]
+    fn stale_temps_are_removed_and_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = dir.path().join("repos").join("comfyui");
+        fs::create_dir_all(&repo).unwrap();
+        fs::write(repo.join(".portablesource_meta.json"), "{}").unwrap();
+        fs::write(repo.join(".portablesource_meta.json.pstmp"), "{\"na").unwrap();
+        fs::write(dir.path().join("portablesource_config.json.pstmp"), "").unwrap();
+
+        let mut stale = recover_stale_temps(dir.path());
+        stale.sort_by(|a, b| a.temp.cmp(&b.temp));
+        assert_eq!(stale.len(), 2);
+        assert!(stale[0].target_missing);
+        assert!(!stale[1].target_missing);
+        assert_eq!(fs::read_to_string(&stale[1].target).unwrap(), "{}");
+        assert!(!stale[1].temp.exists());
+    }
+
+    #[test]
+    fn temps_still_being_written_are_left_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let temp = temp_path(&dir.path().join("portablesource_config.json"));
+        let writer = File::create(&temp).unwrap();
+        writer.lock().unwrap();
+
+        assert!(recover_stale_temps(dir.path()).is_empty());
+        assert!(temp.exists());
+        drop(writer);
+        assert_eq!(recover_stale_temps(dir.path()).len(), 1);
+        assert!(!temp.exists());
+    }
+}
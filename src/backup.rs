@@ -0,0 +1,488 @@
+//! Whole-installation backup and restore
+//!
+//! `backup create <target.tar.zst>` packs the configuration and every repository (sources,
+//! markers, run settings, start scripts) into one zstd tarball; `--with-envs` adds the
+//! environments. Models, outputs and caches are left out by default, see
+//! [`DEFAULT_EXCLUDES`]. The portable tools (`ps_env`) are never included: `setup-env`
+//! downloads them again.
+//!
+//! `backup restore <archive>` unpacks onto the current install path, which may differ
+//! from the one recorded in the archive: the configuration, the repository markers, the
+//! start scripts and the text files of the environments are rewritten for the new path.
+//! Environments made on another platform are skipped; `update-repo` rebuilds them.
+
+use crate::installer::script_generator;
+use crate::path_rewrite;
+use crate::repo_state;
+use crate::utils::unix_timestamp;
+use crate::{PortableSourceError, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use tracing::{debug, info};
+use walkdir::WalkDir;
+
+/// Manifest stored at the archive root
+pub const BACKUP_MANIFEST_FILE: &str = ".portablesource_backup.json";
+const BACKUP_FORMAT_VERSION: u32 = 1;
+/// Configuration file inside the install directory
+const CONFIG_FILE: &str = "portablesource_config.json";
+
+/// Paths relative to each repository that are not backed up unless `--no-default-excludes`.
+/// Patterns are matched from the repository root; `**/` matches at any depth.
+pub const DEFAULT_EXCLUDES: &[&str] = &[
+    "models",
+    "checkpoints",
+    "weights",
+    ".assets",
+    "output",
+    "outputs",
+    "huggingface_home",
+    "**/__pycache__",
+    "**/*.safetensors",
+    "**/*.ckpt",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupManifest {
+    pub format_version: u32,
+    /// Install path at backup time, rewritten to the new one on restore
+    pub install_path: String,
+    /// `os-arch` of the machine the backup was made on
+    pub platform: String,
+    pub repos: Vec<String>,
+    pub envs: Vec<String>,
+    pub excludes: Vec<String>,
+    pub has_config: bool,
+    pub created_at: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BackupOptions {
+    pub with_envs: bool,
+    /// Added to [`DEFAULT_EXCLUDES`] (or used alone with `no_default_excludes`)
+    pub excludes: Vec<String>,
+    pub no_default_excludes: bool,
+}
+
+impl BackupOptions {
+    fn patterns(&self) -> Vec<String> {
+        let defaults = if self.no_default_excludes { &[][..] } else { DEFAULT_EXCLUDES };
+        defaults.iter().map(|p| p.to_string()).chain(self.excludes.iter().cloned()).collect()
+    }
+}
+
+fn current_platform() -> String {
+    format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH)
+}
+
+/// `*` and `?` wildcards within one path component
+fn wildcard(pattern: &[u8], text: &[u8]) -> bool {
+    match (pattern.first(), text.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => wildcard(&pattern[1..], text) || (!text.is_empty() && text[0] != b'/' && wildcard(pattern, &text[1..])),
+        (Some(b'?'), Some(c)) if *c != b'/' => wildcard(&pattern[1..], &text[1..]),
+        (Some(p), Some(c)) if p == c => wildcard(&pattern[1..], &text[1..]),
+        _ => false,
+    }
+}
+
+/// Whether `rel` (relative to the repository, '/'-separated) matches an exclude pattern
+pub fn is_excluded(patterns: &[String], rel: &str) -> bool {
+    patterns.iter().any(|pattern| match pattern.strip_prefix("**/") {
+        Some(rest) => {
+            let mut tail = rel;
+            loop {
+                if wildcard(rest.as_bytes(), tail.as_bytes()) {
+                    return true;
+                }
+                match tail.split_once('/') {
+                    Some((_, next)) => tail = next,
+                    None => return false,
+                }
+            }
+        }
+        None => wildcard(pattern.trim_end_matches('/').as_bytes(), rel.as_bytes()),
+    })
+}
+
+fn sorted_dirs(path: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(path)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|e| e.path().is_dir() && !e.file_name().to_string_lossy().starts_with('.'))
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .collect();
+    names.sort();
+    names
+}
+
+fn append_tree<W: Write>(builder: &mut tar::Builder<W>, root: &Path, name: &str, patterns: &[String]) -> Result<()> {
+    let walker = WalkDir::new(root).follow_links(false).into_iter().filter_entry(|e| {
+        let rel = e.path().strip_prefix(root).unwrap_or(e.path()).to_string_lossy().replace('\\', "/");
+        rel.is_empty() || !is_excluded(patterns, &rel)
+    });
+    for entry in walker {
+        let entry = entry.map_err(|e| PortableSourceError::environment(format!("Failed to read {:?}: {}", root, e)))?;
+        let rel = entry.path().strip_prefix(root).unwrap_or(entry.path());
+        let archive_path = Path::new(name).join(rel);
+        if entry.file_type().is_dir() {
+            builder.append_dir(&archive_path, entry.path())?;
+        } else {
+            builder.append_path_with_name(entry.path(), &archive_path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Write a backup of `install_path` to `target`
+pub fn create(install_path: &Path, target: &Path, options: &BackupOptions) -> Result<BackupManifest> {
+    let patterns = options.patterns();
+    let repos = sorted_dirs(&install_path.join("repos"));
+    let envs = if options.with_envs { sorted_dirs(&install_path.join("envs")) } else { Vec::new() };
+    let config = install_path.join(CONFIG_FILE);
+    let manifest = BackupManifest {
+        format_version: BACKUP_FORMAT_VERSION,
+        install_path: install_path.to_string_lossy().to_string(),
+        platform: current_platform(),
+        repos,
+        envs,
+        excludes: patterns.clone(),
+        has_config: config.exists(),
+        created_at: unix_timestamp(),
+    };
+    let manifest_json = serde_json::to_vec_pretty(&manifest)?;
+
+    if let Some(parent) = target.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let encoder = zstd::stream::Encoder::new(fs::File::create(target)?, 3)?.auto_finish();
+    let mut builder = tar::Builder::new(encoder);
+    builder.follow_symlinks(false);
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(manifest_json.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(manifest.created_at);
+    header.set_cksum();
+    builder.append_data(&mut header, BACKUP_MANIFEST_FILE, manifest_json.as_slice())?;
+    if manifest.has_config {
+        builder.append_path_with_name(&config, CONFIG_FILE)?;
+    }
+    for repo in &manifest.repos {
+        info!("Backing up repository {}", repo);
+        append_tree(&mut builder, &install_path.join("repos").join(repo), &format!("repos/{}", repo), &patterns)?;
+        let state = install_path.join(repo_state::STATE_DIR).join(repo);
+        if state.is_dir() {
+            append_tree(&mut builder, &state, &format!("{}/{}", repo_state::STATE_DIR, repo), &[])?;
+        }
+    }
+    for env in &manifest.envs {
+        info!("Backing up environment {}", env);
+        append_tree(&mut builder, &install_path.join("envs").join(env), &format!("envs/{}", env), &["**/__pycache__".to_string()])?;
+    }
+    builder.into_inner()?.flush()?;
+    Ok(manifest)
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RestoreReport {
+    pub repos: Vec<String>,
+    pub envs: Vec<String>,
+    /// Already present on this install path (restore with `force` to replace)
+    pub skipped: Vec<String>,
+    /// Environments not restored because the backup comes from another platform
+    pub foreign_envs: Vec<String>,
+    pub config_restored: bool,
+    pub old_install_path: String,
+}
+
+/// Replace the old install path prefix in every string of a JSON document
+fn rewrite_json(value: &mut JsonValue, old: &str, new: &str) {
+    match value {
+        JsonValue::String(s) if s.strip_prefix(old).is_some_and(|rest| rest.is_empty() || rest.starts_with(['/', '\\'])) => {
+            *s = format!("{}{}", new, &s[old.len()..])
+        }
+        JsonValue::Array(items) => items.iter_mut().for_each(|v| rewrite_json(v, old, new)),
+        JsonValue::Object(map) => map.values_mut().for_each(|v| rewrite_json(v, old, new)),
+        _ => {}
+    }
+}
+
+/// Rewrite the old install path in one file: JSON by value, other text files by whole path token
+fn rewrite_file(path: &Path, old: &str, new: &str) -> Result<bool> {
+    if path.extension().is_some_and(|e| e == "json") {
+        if let Ok(mut value) = serde_json::from_str::<JsonValue>(&fs::read_to_string(path).unwrap_or_default()) {
+            let before = value.clone();
+            rewrite_json(&mut value, old, new);
+            if value != before {
+                crate::atomic_write::write(path, serde_json::to_string_pretty(&value)?)?;
+                return Ok(true);
+            }
+            return Ok(false);
+        }
+    }
+    path_rewrite::rewrite_file(path, old, new)
+}
+
+/// Rewrite files (and on Unix, symlinks) under `root`; only top-level files unless `recursive`
+pub(crate) fn rewrite_tree(root: &Path, old: &str, new: &str, recursive: bool) -> Result<usize> {
+    let mut rewritten = 0;
+    let depth = if recursive { usize::MAX } else { 1 };
+    for entry in WalkDir::new(root).max_depth(depth).follow_links(false).into_iter().flatten() {
+        let path = entry.path();
+        if entry.file_type().is_file() {
+            if rewrite_file(path, old, new)? {
+                rewritten += 1;
+            }
+        } else if entry.file_type().is_symlink() {
+            #[cfg(unix)]
+            {
+                let target = fs::read_link(path)?.to_string_lossy().to_string();
+                if let Some(retargeted) = target.starts_with(old).then(|| path_rewrite::replace_path(&target, old, new)).flatten() {
+                    fs::remove_file(path)?;
+                    std::os::unix::fs::symlink(retargeted, path)?;
+                    rewritten += 1;
+                }
+            }
+        }
+    }
+    Ok(rewritten)
+}
+
+/// Move what exists in `existing` but not in `staged` over into `staged`: models, outputs
+/// and other files the backup does not contain
+fn carry_over(existing: &Path, staged: &Path) -> Result<()> {
+    for entry in fs::read_dir(existing)?.flatten() {
+        let target = staged.join(entry.file_name());
+        match fs::symlink_metadata(&target) {
+            Err(_) => fs::rename(entry.path(), &target)?,
+            Ok(meta) if meta.is_dir() && entry.file_type()?.is_dir() => carry_over(&entry.path(), &target)?,
+            Ok(_) => {}
+        }
+    }
+    Ok(())
+}
+
+/// Put `from` at `to`. An existing `to` is left alone unless `force`; then, with
+/// `keep_extra`, what only it has is carried over into the restored copy, and the old copy is
+/// set aside until the new one is in place
+fn move_into_place(from: &Path, to: &Path, force: bool, keep_extra: bool) -> Result<bool> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if !to.exists() {
+        fs::rename(from, to)?;
+        return Ok(true);
+    }
+    if !force {
+        return Ok(false);
+    }
+    if keep_extra {
+        carry_over(to, from)?;
+    }
+    let mut aside_name = std::ffi::OsString::from(".");
+    aside_name.push(to.file_name().unwrap_or_default());
+    aside_name.push(format!(".replaced-{}", std::process::id()));
+    let aside = to.with_file_name(aside_name);
+    fs::rename(to, &aside)?;
+    if let Err(e) = fs::rename(from, to) {
+        let _ = fs::rename(&aside, to);
+        return Err(e.into());
+    }
+    fs::remove_dir_all(&aside)?;
+    Ok(true)
+}
+
+/// Restore a backup onto `install_path`; existing repositories are kept unless `force`
+pub fn restore(install_path: &Path, archive: &Path, force: bool) -> Result<RestoreReport> {
+    if !archive.exists() {
+        return Err(PortableSourceError::environment(format!("Backup not found: {:?}", archive)));
+    }
+    fs::create_dir_all(install_path)?;
+    // Unpack next to the destination so moving into place is a rename
+    let staging = tempfile::Builder::new().prefix(".restore-").tempdir_in(install_path)?;
+    let mut tar = tar::Archive::new(zstd::stream::Decoder::new(fs::File::open(archive)?)?);
+    tar.set_preserve_permissions(true);
+    tar.unpack(staging.path())
+        .map_err(|e| PortableSourceError::environment(format!("Failed to extract {:?}: {}", archive, e)))?;
+
+    let manifest: BackupManifest = serde_json::from_str(&fs::read_to_string(staging.path().join(BACKUP_MANIFEST_FILE)).map_err(|_| {
+        PortableSourceError::environment(format!("{:?} is not a portablesource backup (missing manifest)", archive))
+    })?)?;
+    if manifest.format_version > BACKUP_FORMAT_VERSION {
+        return Err(PortableSourceError::environment(format!(
+            "Backup format v{} is newer than supported v{}; update portablesource",
+            manifest.format_version, BACKUP_FORMAT_VERSION
+        )));
+    }
+
+    let old = manifest.install_path.as_str();
+    let new = install_path.to_string_lossy().to_string();
+    let relocate = old != new;
+    let mut report = RestoreReport { old_install_path: manifest.install_path.clone(), ..Default::default() };
+
+    for repo in &manifest.repos {
+        let staged = staging.path().join("repos").join(repo);
+        // A generated start script stays recognised as generated once the path is rewritten
+        let script = script_generator::script_file_name(repo);
+        let generated = fs::read(staged.join(&script)).is_ok_and(|content| script_generator::is_generated(&staged, &content));
+        if relocate {
+            let n = rewrite_tree(&staged, old, &new, false)?;
+            debug!("Rewrote {} file(s) of repository {}", n, repo);
+        }
+        let installed = install_path.join("repos").join(repo);
+        if move_into_place(&staged, &installed, force, true)? {
+            let state = staging.path().join(repo_state::STATE_DIR).join(repo);
+            if state.is_dir() {
+                move_into_place(&state, &repo_state::dir(&installed), true, false)?;
+            }
+            if relocate && generated {
+                script_generator::record_script_hash(&installed, &fs::read(installed.join(&script))?)?;
+            }
+            report.repos.push(repo.clone());
+        } else {
+            report.skipped.push(repo.clone());
+        }
+    }
+
+    let same_platform = manifest.platform == current_platform();
+    for env in &manifest.envs {
+        if !same_platform {
+            report.foreign_envs.push(env.clone());
+            continue;
+        }
+        let staged = staging.path().join("envs").join(env);
+        if relocate {
+            let n = rewrite_tree(&staged, old, &new, true)?;
+            debug!("Rewrote {} file(s) of environment {}", n, env);
+        }
+        if move_into_place(&staged, &install_path.join("envs").join(env), force, false)? {
+            report.envs.push(env.clone());
+        } else {
+            report.skipped.push(format!("envs/{}", env));
+        }
+    }
+
+    if manifest.has_config {
+        let staged = staging.path().join(CONFIG_FILE);
+        rewrite_file(&staged, old, &new)?;
+        let config = install_path.join(CONFIG_FILE);
+        if config.exists() {
+            fs::copy(&config, install_path.join(format!("{}.bak", CONFIG_FILE)))?;
+        }
+        fs::rename(&staged, &config)?;
+        report.config_restored = true;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Backup with envs of an install at `old` holding ComfyUI with models, caches and outputs
+    fn backed_up_install(old: &Path) -> std::path::PathBuf {
+        let repo = old.join("repos/comfyui");
+        for sub in ["models/checkpoints", "comfy/ldm/models", "comfy/__pycache__", "output"] {
+            fs::create_dir_all(repo.join(sub)).unwrap();
+        }
+        fs::write(repo.join("main.py"), "print()").unwrap();
+        fs::write(repo.join("models/checkpoints/sd.safetensors"), "weights").unwrap();
+        fs::write(repo.join("comfy/ldm/models/unet.py"), "class UNet: pass").unwrap();
+        fs::write(repo.join("comfy/__pycache__/x.pyc"), "").unwrap();
+        script_generator::write_script_file(&repo, &script_generator::script_file_name("comfyui"), &format!("INSTALL=\"{}\"\n", old.display())).unwrap();
+        repo_state::write(&repo, crate::installer::ENGINE_MARKER_FILE, "uv").unwrap();
+        fs::create_dir_all(old.join("envs/comfyui/bin")).unwrap();
+        fs::write(old.join("envs/comfyui/bin/pip"), format!("#!{}/envs/comfyui/bin/python\n", old.display())).unwrap();
+        let config = serde_json::json!({"install_path": old, "version": "1.0.0"});
+        fs::write(old.join(CONFIG_FILE), config.to_string()).unwrap();
+
+        let archive = old.join("backup.tar.zst");
+        let manifest = create(old, &archive, &BackupOptions { with_envs: true, ..Default::default() }).unwrap();
+        assert_eq!(manifest.repos, ["comfyui"]);
+        archive
+    }
+
+    #[test]
+    fn restore_brings_back_repos_and_envs() {
+        let (src, dst) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let archive = backed_up_install(src.path());
+        let report = restore(dst.path(), &archive, false).unwrap();
+        assert_eq!(report.repos, ["comfyui"]);
+        assert_eq!(report.envs, ["comfyui"]);
+        let restored = dst.path().join("repos/comfyui");
+        assert!(restored.join("main.py").exists() && restored.join("comfy/ldm/models/unet.py").exists());
+        assert_eq!(repo_state::read(&restored, crate::installer::ENGINE_MARKER_FILE).as_deref(), Some("uv"));
+    }
+
+    #[test]
+    fn backup_skips_models_outputs_and_caches() {
+        let (src, dst) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let archive = backed_up_install(src.path());
+        restore(dst.path(), &archive, false).unwrap();
+        let restored = dst.path().join("repos/comfyui");
+        assert!(!restored.join("models").exists());
+        assert!(!restored.join("output").exists());
+        assert!(!restored.join("comfy/__pycache__").exists());
+    }
+
+    #[test]
+    fn restore_rewrites_the_old_install_path() {
+        let (src, dst) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let archive = backed_up_install(src.path());
+        let new = dst.path().join("ps");
+        restore(&new, &archive, false).unwrap();
+
+        let repo = new.join("repos/comfyui");
+        let script_name = script_generator::script_file_name("comfyui");
+        let script = fs::read_to_string(repo.join(&script_name)).unwrap();
+        assert_eq!(script, format!("INSTALL=\"{}\"\n", new.display()));
+        let regenerated = script_generator::regenerate_script(&repo, &script_name, "INSTALL=\"regenerated\"\n", false, false).unwrap();
+        assert_eq!(regenerated, script_generator::RegenOutcome::Updated);
+        let pip = fs::read_to_string(new.join("envs/comfyui/bin/pip")).unwrap();
+        assert!(pip.starts_with(&format!("#!{}/envs", new.display())));
+        let config: JsonValue = serde_json::from_str(&fs::read_to_string(new.join(CONFIG_FILE)).unwrap()).unwrap();
+        assert_eq!(config["install_path"], new.to_string_lossy().as_ref());
+    }
+
+    #[test]
+    fn restoring_again_skips_what_exists_and_keeps_the_config() {
+        let (src, dst) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let archive = backed_up_install(src.path());
+        restore(dst.path(), &archive, false).unwrap();
+        let again = restore(dst.path(), &archive, false).unwrap();
+        assert_eq!(again.skipped, ["comfyui", "envs/comfyui"]);
+        assert!(dst.path().join(format!("{}.bak", CONFIG_FILE)).exists());
+    }
+
+    #[test]
+    fn forced_restore_keeps_files_the_backup_does_not_contain() {
+        let src = tempfile::tempdir().unwrap();
+        let repo = src.path().join("repos/comfyui");
+        fs::create_dir_all(repo.join("app")).unwrap();
+        fs::write(repo.join("main.py"), "print('backup')").unwrap();
+        let archive = src.path().join("backup.tar.zst");
+        create(src.path(), &archive, &BackupOptions::default()).unwrap();
+
+        let dst = tempfile::tempdir().unwrap();
+        let installed = dst.path().join("repos/comfyui");
+        for sub in ["models/checkpoints", "outputs", "app"] {
+            fs::create_dir_all(installed.join(sub)).unwrap();
+        }
+        fs::write(installed.join("models/checkpoints/sd.safetensors"), "weights").unwrap();
+        fs::write(installed.join("app/local.py"), "").unwrap();
+        fs::write(installed.join("main.py"), "print('edited')").unwrap();
+
+        let report = restore(dst.path(), &archive, true).unwrap();
+        assert_eq!(report.repos, ["comfyui"]);
+        assert_eq!(fs::read_to_string(installed.join("main.py")).unwrap(), "print('backup')");
+        assert!(installed.join("models/checkpoints/sd.safetensors").exists());
+        assert!(installed.join("outputs").is_dir() && installed.join("app/local.py").exists());
+        let leftovers: Vec<_> = fs::read_dir(dst.path().join("repos")).unwrap().flatten().map(|e| e.file_name()).collect();
+        assert_eq!(leftovers, ["comfyui"]);
+    }
+}
@@ -0,0 +1,269 @@
+//! Bootstrap scripts for fresh machines
+//!
+//! `bootstrap --output setup.sh|setup.ps1` writes a self-contained script that downloads
+//! the portablesource binary, runs setup-env with this machine's settings (install engine
+//! per repo, GPU queue) and installs a list of repositories.
+
+use crate::config::{GpuQueueConfig, InstallEngine, VERSION};
+use crate::installer::ENGINE_MARKER_FILE;
+use crate::repo_metadata::RepoMetadata;
+use crate::{PortableSourceError, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const RELEASES_URL: &str = "https://github.com/portablesource/portablesource-cli/releases";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootstrapShell {
+    Bash,
+    PowerShell,
+}
+
+impl BootstrapShell {
+    /// Pick the script flavour from the output extension (.sh or .ps1)
+    pub fn from_path(path: &Path) -> Result<Self> {
+        match path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).as_deref() {
+            Some("sh") | Some("bash") => Ok(Self::Bash),
+            Some("ps1") => Ok(Self::PowerShell),
+            _ => Err(PortableSourceError::config(format!(
+                "Cannot tell script type from {:?}; use a .sh or .ps1 file name", path
+            ))),
+        }
+    }
+
+    fn binary_name(&self) -> &'static str {
+        match self {
+            Self::Bash => "portablesource-rs",
+            Self::PowerShell => "portablesource-rs.exe",
+        }
+    }
+}
+
+/// One `install-repo` call in the script
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BootstrapRepo {
+    /// URL or server name passed to install-repo
+    pub source: String,
+    /// `--as` instance name
+    pub instance: Option<String>,
+    pub engine: Option<InstallEngine>,
+    /// License was accepted on this machine, pass `--accept-license`
+    pub accept_license: bool,
+}
+
+impl BootstrapRepo {
+    pub fn new(source: &str) -> Self {
+        Self { source: source.to_string(), ..Default::default() }
+    }
+
+    fn args(&self) -> Vec<String> {
+        let mut args = vec!["install-repo".to_string(), self.source.clone()];
+        if let Some(instance) = &self.instance {
+            args.extend(["--as".to_string(), instance.clone()]);
+        }
+        if let Some(engine) = self.engine {
+            args.extend(["--engine".to_string(), engine.as_str().to_string()]);
+        }
+        if self.accept_license {
+            args.push("--accept-license".to_string());
+        }
+        args
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BootstrapPlan {
+    /// Default install path on the target machine (overridable with PORTABLESOURCE_HOME)
+    pub install_path: PathBuf,
+    /// Release tag to download; None for the latest release
+    pub release: Option<String>,
+    pub gpu_queue: GpuQueueConfig,
+    pub repos: Vec<BootstrapRepo>,
+}
+
+impl BootstrapPlan {
+    fn binary_url(&self, shell: BootstrapShell) -> String {
+        match &self.release {
+            Some(tag) => format!("{}/download/{}/{}", RELEASES_URL, tag, shell.binary_name()),
+            None => format!("{}/latest/download/{}", RELEASES_URL, shell.binary_name()),
+        }
+    }
+
+    /// Portablesource invocations after the download, in order
+    fn commands(&self) -> Vec<Vec<String>> {
+        let mut commands = vec![vec!["setup-env".to_string()]];
+        if self.gpu_queue != GpuQueueConfig::default() {
+            let q = &self.gpu_queue;
+            commands.push(vec![
+                "gpu-queue".to_string(),
+                if q.enabled { "--enable" } else { "--disable" }.to_string(),
+                "--min-free-vram".to_string(),
+                q.min_free_vram_mb.to_string(),
+                "--timeout".to_string(),
+                q.timeout_secs.to_string(),
+            ]);
+        }
+        commands.extend(self.repos.iter().map(|r| r.args()));
+        commands
+    }
+}
+
+/// Repositories installed under `install_path`, as install-repo calls reproducing them
+pub fn installed_repos(install_path: &Path) -> Result<Vec<BootstrapRepo>> {
+    let repos_dir = install_path.join("repos");
+    if !repos_dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut repos = Vec::new();
+    for entry in fs::read_dir(&repos_dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let repo_dir = entry.path();
+        let folder = entry.file_name().to_string_lossy().to_string();
+        let metadata = RepoMetadata::load(&repo_dir).ok().flatten().unwrap_or_default();
+
+        // Repos installed from URL keep it in link.txt; server repos are installed by name
+        let link = fs::read_to_string(repo_dir.join("link.txt")).ok().map(|l| l.trim().to_string()).filter(|l| !l.is_empty());
+        let source = link.or_else(|| metadata.upstream.clone()).unwrap_or_else(|| folder.clone());
+        let engine = crate::repo_state::read(&repo_dir, ENGINE_MARKER_FILE).and_then(|e| e.parse().ok());
+        repos.push(BootstrapRepo {
+            source,
+            instance: metadata.upstream.is_some().then_some(folder),
+            engine,
+            accept_license: metadata.license_accepted,
+        });
+    }
+    repos.sort_by(|a, b| a.instance.as_ref().unwrap_or(&a.source).cmp(b.instance.as_ref().unwrap_or(&b.source)));
+    Ok(repos)
+}
+
+fn bash_quote(s: &str) -> String {
+    if !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || "-_./:=@".contains(c)) {
+        return s.to_string();
+    }
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+fn powershell_quote(s: &str) -> String {
+    if !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || "-_./:\\".contains(c)) {
+        return s.to_string();
+    }
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+pub fn render_bash(plan: &BootstrapPlan) -> String {
+    let mut s = String::new();
+    s.push_str("#!/usr/bin/env bash\n");
+    s.push_str(&format!("# Generated by portablesource {} bootstrap\n", VERSION));
+    s.push_str("set -euo pipefail\n\n");
+    s.push_str(&format!("DEFAULT_HOME={}\n", bash_quote(&plan.install_path.to_string_lossy())));
+    s.push_str("PS_HOME=\"${PORTABLESOURCE_HOME:-$DEFAULT_HOME}\"\n");
+    s.push_str(&format!("BINARY_URL={}\n", bash_quote(&plan.binary_url(BootstrapShell::Bash))));
+    s.push_str(&format!("PS_BIN=\"$PS_HOME/{}\"\n\n", BootstrapShell::Bash.binary_name()));
+    s.push_str("mkdir -p \"$PS_HOME\"\n");
+    s.push_str("echo \"[INFO] Downloading portablesource to $PS_BIN\"\n");
+    s.push_str("curl -fL --retry 3 -o \"$PS_BIN\" \"$BINARY_URL\"\n");
+    s.push_str("chmod +x \"$PS_BIN\"\n\n");
+    s.push_str("portablesource() { \"$PS_BIN\" --install-path \"$PS_HOME\" \"$@\"; }\n\n");
+    for cmd in plan.commands() {
+        let args: Vec<String> = cmd.iter().map(|a| bash_quote(a)).collect();
+        s.push_str(&format!("portablesource {}\n", args.join(" ")));
+    }
+    s.push_str("\necho \"[INFO] Bootstrap complete: $PS_HOME\"\n");
+    s
+}
+
+pub fn render_powershell(plan: &BootstrapPlan) -> String {
+    let mut s = String::new();
+    s.push_str(&format!("# Generated by portablesource {} bootstrap\r\n", VERSION));
+    s.push_str("$ErrorActionPreference = 'Stop'\r\n\r\n");
+    s.push_str(&format!(
+        "$InstallPath = if ($env:PORTABLESOURCE_HOME) {{ $env:PORTABLESOURCE_HOME }} else {{ {} }}\r\n",
+        powershell_quote(&plan.install_path.to_string_lossy())
+    ));
+    s.push_str(&format!("$BinaryUrl = {}\r\n", powershell_quote(&plan.binary_url(BootstrapShell::PowerShell))));
+    s.push_str(&format!("$Exe = Join-Path $InstallPath '{}'\r\n\r\n", BootstrapShell::PowerShell.binary_name()));
+    s.push_str("New-Item -ItemType Directory -Force -Path $InstallPath | Out-Null\r\n");
+    s.push_str("Write-Host \"[INFO] Downloading portablesource to $Exe\"\r\n");
+    s.push_str("Invoke-WebRequest -Uri $BinaryUrl -OutFile $Exe -UseBasicParsing\r\n\r\n");
+    s.push_str("function Invoke-PortableSource {\r\n");
+    s.push_str("    & $Exe --install-path $InstallPath @args\r\n");
+    s.push_str("    if ($LASTEXITCODE -ne 0) { throw \"portablesource $args failed with exit code $LASTEXITCODE\" }\r\n");
+    s.push_str("}\r\n\r\n");
+    for cmd in plan.commands() {
+        let args: Vec<String> = cmd.iter().map(|a| powershell_quote(a)).collect();
+        s.push_str(&format!("Invoke-PortableSource {}\r\n", args.join(" ")));
+    }
+    s.push_str("\r\nWrite-Host \"[INFO] Bootstrap complete: $InstallPath\"\r\n");
+    s
+}
+
+pub fn render(plan: &BootstrapPlan, shell: BootstrapShell) -> String {
+    match shell {
+        BootstrapShell::Bash => render_bash(plan),
+        BootstrapShell::PowerShell => render_powershell(plan),
+    }
+}
+
+/// Render the script for `output`'s extension and write it (executable on Unix)
+pub fn write_script(plan: &BootstrapPlan, output: &Path) -> Result<()> {
+    let shell = BootstrapShell::from_path(output)?;
+    if let Some(parent) = output.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(output, render(plan, shell))?;
+
+    #[cfg(unix)]
+    if shell == BootstrapShell::Bash {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(output, fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plan() -> BootstrapPlan {
+        BootstrapPlan {
+            install_path: PathBuf::from("/home/me/portable source"),
+            release: None,
+            gpu_queue: GpuQueueConfig { enabled: true, ..Default::default() },
+            repos: vec![
+                BootstrapRepo::new("comfyui"),
+                BootstrapRepo {
+                    source: "https://github.com/acme/it's.git".into(),
+                    instance: Some("video".into()),
+                    engine: Some(InstallEngine::Uv),
+                    accept_license: true,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn renders_setup_then_repos_with_quoting() {
+        let bash = render_bash(&plan());
+        assert!(bash.contains("DEFAULT_HOME='/home/me/portable source'\n"));
+        assert!(bash.contains("BINARY_URL=https://github.com/portablesource/portablesource-cli/releases/latest/download/portablesource-rs\n"));
+        let setup = bash.find("portablesource setup-env\n").unwrap();
+        let queue = bash.find("portablesource gpu-queue --enable --min-free-vram 4096 --timeout 1800\n").unwrap();
+        let repo = bash.find("portablesource install-repo comfyui\n").unwrap();
+        assert!(setup < queue && queue < repo);
+        assert!(bash.contains("install-repo 'https://github.com/acme/it'\\''s.git' --as video --engine uv --accept-license\n"));
+
+        let ps = render_powershell(&BootstrapPlan { release: Some("v1.2.6".into()), ..plan() });
+        assert!(ps.contains("releases/download/v1.2.6/portablesource-rs.exe"));
+        assert!(ps.contains("Invoke-PortableSource install-repo 'https://github.com/acme/it''s.git' --as video"));
+    }
+
+    #[test]
+    fn script_type_follows_extension() {
+        assert_eq!(BootstrapShell::from_path(Path::new("setup.sh")).unwrap(), BootstrapShell::Bash);
+        assert_eq!(BootstrapShell::from_path(Path::new("setup.PS1")).unwrap(), BootstrapShell::PowerShell);
+        assert!(BootstrapShell::from_path(Path::new("setup.txt")).is_err());
+    }
+}
@@ -0,0 +1,168 @@
+//! What `portablesource version` reports
+//!
+//! Build metadata comes from build.rs; the environment fingerprint is probed at run time
+//! and fits on one line so it can be pasted into a bug report as is.
+
+use crate::config::VERSION;
+use crate::gpu::GpuDetector;
+use serde::Serialize;
+use std::process::Command;
+
+pub const COMMIT: &str = env!("PS_BUILD_COMMIT");
+pub const BUILD_DATE: &str = env!("PS_BUILD_DATE");
+pub const TARGET: &str = env!("PS_BUILD_TARGET");
+pub const PROFILE: &str = env!("PS_BUILD_PROFILE");
+const FEATURES: &str = env!("PS_BUILD_FEATURES");
+
+pub fn features() -> Vec<String> {
+    FEATURES.split(',').filter(|f| !f.is_empty()).map(str::to_string).collect()
+}
+
+/// OS, GPU and CUDA of the machine
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct EnvFingerprint {
+    pub os: String,
+    pub gpu: Option<String>,
+    /// Highest CUDA version the NVIDIA driver supports
+    pub cuda: Option<String>,
+}
+
+impl EnvFingerprint {
+    pub fn detect() -> Self {
+        let gpu = GpuDetector::new().get_best_gpu().ok().flatten().map(|g| match g.driver_version {
+            Some(driver) => format!("{} (driver {})", g.name, driver),
+            None => g.name,
+        });
+        let cuda = run(&["nvidia-smi"]).and_then(|out| parse_driver_cuda(&out));
+        Self { os: os_version(), gpu, cuda }
+    }
+
+    pub fn line(&self) -> String {
+        format!(
+            "os={}; gpu={}; cuda={}",
+            self.os,
+            self.gpu.as_deref().unwrap_or("none"),
+            self.cuda.as_deref().unwrap_or("none")
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VersionReport {
+    pub version: &'static str,
+    pub commit: &'static str,
+    pub build_date: &'static str,
+    pub target: &'static str,
+    pub profile: &'static str,
+    pub features: Vec<String>,
+    pub environment: EnvFingerprint,
+}
+
+impl VersionReport {
+    pub fn collect() -> Self {
+        Self {
+            version: VERSION,
+            commit: COMMIT,
+            build_date: BUILD_DATE,
+            target: TARGET,
+            profile: PROFILE,
+            features: features(),
+            environment: EnvFingerprint::detect(),
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let features = if self.features.is_empty() { "none".to_string() } else { self.features.join(", ") };
+        format!(
+            "PortableSource version: {}\nCommit: {}\nBuild date: {}\nTarget: {} ({})\nFeatures: {}\nEnvironment: {}\n",
+            self.version,
+            self.commit,
+            self.build_date,
+            self.target,
+            self.profile,
+            features,
+            self.environment.line()
+        )
+    }
+}
+
+/// stdout of a successful command
+pub(crate) fn run(args: &[&str]) -> Option<String> {
+    let mut cmd = Command::new(args[0]);
+    cmd.args(&args[1..]);
+
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
+    }
+
+    let output = cmd.output().ok().filter(|o| o.status.success())?;
+    Some(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// "CUDA Version: 12.4" from the nvidia-smi header
+pub fn parse_driver_cuda(nvidia_smi: &str) -> Option<String> {
+    let rest = &nvidia_smi[nvidia_smi.find("CUDA Version:")? + "CUDA Version:".len()..];
+    rest.split_whitespace().next().map(str::to_string)
+}
+
+/// `PRETTY_NAME` of an os-release file
+pub fn parse_os_release(content: &str) -> Option<String> {
+    content
+        .lines()
+        .find_map(|l| l.strip_prefix("PRETTY_NAME="))
+        .map(|v| v.trim().trim_matches('"').to_string())
+        .filter(|v| !v.is_empty())
+}
+
+pub fn os_version() -> String {
+    let arch = std::env::consts::ARCH;
+    if cfg!(windows) {
+        // "Microsoft Windows [Version 10.0.22631.3447]"
+        let ver = run(&["cmd", "/c", "ver"]).map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+        return format!("{} {}", ver.unwrap_or_else(|| "Windows".into()), arch);
+    }
+    if cfg!(target_os = "macos") {
+        let ver = run(&["sw_vers", "-productVersion"]).map(|v| v.trim().to_string()).unwrap_or_default();
+        return format!("macOS {} {}", ver, arch).replace("  ", " ");
+    }
+    let name = std::fs::read_to_string("/etc/os-release").ok().and_then(|c| parse_os_release(&c)).unwrap_or_else(|| "Linux".into());
+    match std::fs::read_to_string("/proc/sys/kernel/osrelease") {
+        Ok(kernel) => format!("{} (kernel {}) {}", name, kernel.trim(), arch),
+        Err(_) => format!("{} {}", name, arch),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprint_parts_are_parsed_and_rendered() {
+        let smi = "+------+\n| NVIDIA-SMI 550.54.15   Driver Version: 550.54.15   CUDA Version: 12.4     |\n";
+        assert_eq!(parse_driver_cuda(smi).as_deref(), Some("12.4"));
+        assert_eq!(parse_driver_cuda("No devices were found"), None);
+        let release = "NAME=\"Ubuntu\"\nPRETTY_NAME=\"Ubuntu 22.04.4 LTS\"\nID=ubuntu\n";
+        assert_eq!(parse_os_release(release).as_deref(), Some("Ubuntu 22.04.4 LTS"));
+
+        let env = EnvFingerprint { os: "Ubuntu 22.04.4 LTS x86_64".into(), gpu: Some("NVIDIA GeForce RTX 4090".into()), cuda: None };
+        assert_eq!(env.line(), "os=Ubuntu 22.04.4 LTS x86_64; gpu=NVIDIA GeForce RTX 4090; cuda=none");
+
+        let report = VersionReport {
+            version: VERSION,
+            commit: "abc123",
+            build_date: "2026-10-16",
+            target: "x86_64-unknown-linux-gnu",
+            profile: "release",
+            features: Vec::new(),
+            environment: env,
+        };
+        let text = report.render();
+        assert!(text.starts_with(&format!("PortableSource version: {}\nCommit: abc123\n", VERSION)));
+        assert!(text.contains("Target: x86_64-unknown-linux-gnu (release)\nFeatures: none\n"));
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["environment"]["cuda"], serde_json::Value::Null);
+        assert_eq!(json["build_date"], "2026-10-16");
+    }
+}
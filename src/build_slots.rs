@@ -0,0 +1,241 @@
+//! Machine-wide limit on native package builds
+//!
+//! A source build (insightface without a wheel, flash-attn) starts one compiler per core, so
+//! two installs building at once can freeze the machine. Build-type invocations take one of
+//! `max_native_builds` slots first. A slot is a lock on `slot-<n>.lock` in a folder every
+//! user shares (ProgramData on Windows, /tmp elsewhere), so installs from every
+//! PortableSource folder, process and account share the limit, and the OS frees the slot of
+//! a process that dies. Builds beyond the limit wait for a free slot; when the slots cannot
+//! be used at all, builds run without a limit.
+
+use crate::progress::Progress;
+use crate::Result;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Default of `max_native_builds`; 0 means no limit
+pub const DEFAULT_MAX_NATIVE_BUILDS: usize = 1;
+
+/// Packages that are compiled on install when no wheel matches
+const SOURCE_BUILD_PACKAGES: &[&str] = &["flash-attn", "flash_attn", "sageattention", "xformers", "causal-conv1d", "mamba-ssm"];
+
+/// Compiled on Linux and macOS, prebuilt wheel on Windows
+const UNIX_SOURCE_BUILD_PACKAGES: &[&str] = &["insightface"];
+
+/// Folder of the slot locks, the same for every user of the machine
+pub fn slots_dir() -> PathBuf {
+    #[cfg(windows)]
+    {
+        let program_data = std::env::var_os("ProgramData").map(PathBuf::from).unwrap_or_else(|| PathBuf::from(r"C:\ProgramData"));
+        program_data.join("portablesource").join("build-slots")
+    }
+    #[cfg(not(windows))]
+    {
+        PathBuf::from("/tmp").join("portablesource-build-slots")
+    }
+}
+
+/// Create the slots folder so that other users can add slot files too (sticky, world
+/// writable like /tmp); best effort when another user created it
+fn create_shared_dir(dir: &Path) -> Result<()> {
+    std::fs::create_dir_all(dir)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let _ = std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o1777));
+    }
+    Ok(())
+}
+
+/// Open a slot file for locking. A lock needs no write access, so slot files created by
+/// another user are opened read-only
+fn open_slot(path: &Path) -> std::io::Result<File> {
+    if let Ok(file) = File::open(path) {
+        return Ok(file);
+    }
+    let file = OpenOptions::new().create(true).truncate(false).write(true).open(path)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let _ = file.set_permissions(std::fs::Permissions::from_mode(0o666));
+    }
+    Ok(file)
+}
+
+/// Held while a native build runs; dropping it frees the slot
+#[derive(Debug)]
+pub struct BuildSlot {
+    _lock: File,
+    pub index: usize,
+}
+
+/// Try each slot once; None when all `max` are taken
+pub fn try_acquire(dir: &Path, max: usize) -> Result<Option<BuildSlot>> {
+    create_shared_dir(dir)?;
+    for index in 0..max {
+        let file = open_slot(&dir.join(format!("slot-{}.lock", index)))?;
+        match file.try_lock() {
+            Ok(()) => return Ok(Some(BuildSlot { _lock: file, index })),
+            Err(TryLockError::WouldBlock) => continue,
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+    }
+    Ok(None)
+}
+
+/// Wait for a free slot, showing how long `label` has been queued; None when `max` is 0 or
+/// the slots cannot be used (the build then runs without a limit)
+pub fn acquire(dir: &Path, max: usize, label: &str) -> Result<Option<BuildSlot>> {
+    if max == 0 {
+        return Ok(None);
+    }
+    match try_acquire(dir, max) {
+        Ok(Some(slot)) => return Ok(Some(slot)),
+        Ok(None) => {}
+        Err(e) => {
+            tracing::warn!("Native build slots in {:?} are not usable ({}); building without a limit", dir, e);
+            return Ok(None);
+        }
+    }
+    let started = Instant::now();
+    let progress = Progress::spinner("Build queue");
+    loop {
+        progress.set_message(format!(
+            "{}: waiting for one of {} native build slot(s), {}s",
+            label,
+            max,
+            started.elapsed().as_secs()
+        ));
+        std::thread::sleep(Duration::from_secs(2));
+        if let Some(slot) = try_acquire(dir, max).unwrap_or(None) {
+            progress.finish_with_message(&format!("{}: build slot free after {}s", label, started.elapsed().as_secs()));
+            return Ok(Some(slot));
+        }
+    }
+}
+
+/// Whether one requirement (a name with a version or marker, a URL, an option) may be
+/// compiled on install
+fn builds_from_source(requirement: &str) -> bool {
+    let lower = requirement.trim().to_lowercase();
+    let name = lower.split(['=', '<', '>', '!', '~', '[', ';', ' ', '@']).next().unwrap_or("");
+    lower.starts_with("--no-binary")
+        || lower == "--no-build-isolation"
+        || lower.contains("git+")
+        || SOURCE_BUILD_PACKAGES.contains(&name)
+        || (!cfg!(windows) && UNIX_SOURCE_BUILD_PACKAGES.contains(&name))
+}
+
+/// Whether a requirements file (and the files it includes) names a source build
+fn requirements_file_builds(path: &Path, depth: usize) -> bool {
+    let Ok(content) = std::fs::read_to_string(path) else { return false };
+    let base = path.parent().unwrap_or(Path::new(""));
+    content.lines().map(|line| line.split(" #").next().unwrap_or("").trim()).any(|line| {
+        match line.strip_prefix("-r").or_else(|| line.strip_prefix("--requirement")) {
+            Some(nested) if depth < 4 => requirements_file_builds(&base.join(nested.trim_start_matches('=').trim()), depth + 1),
+            Some(_) => false,
+            None => builds_from_source(line),
+        }
+    })
+}
+
+/// Whether a pip/uv command may compile native code: it installs a package known to be
+/// built from source (also through `-r` files, relative to `cwd`), installs from git, or
+/// asks for source builds
+pub fn is_native_build(args: &[String], cwd: Option<&Path>) -> bool {
+    let Some(install) = args.iter().position(|a| a == "install") else { return false };
+    let rest = &args[install + 1..];
+    let base = cwd.unwrap_or(Path::new(""));
+    rest.iter().enumerate().any(|(i, arg)| {
+        let file = match arg.as_str() {
+            "-r" | "--requirement" => rest.get(i + 1).map(String::as_str),
+            other => other.strip_prefix("--requirement=").or_else(|| other.strip_prefix("-r").filter(|f| !f.is_empty())),
+        };
+        match file {
+            Some(file) => requirements_file_builds(&base.join(file), 0),
+            None => builds_from_source(arg),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split(' ').map(str::to_string).collect()
+    }
+
+    #[test]
+    fn builds_beyond_the_limit_wait_for_a_slot() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = try_acquire(dir.path(), 2).unwrap().unwrap();
+        let second = try_acquire(dir.path(), 2).unwrap().unwrap();
+        assert_ne!(first.index, second.index);
+        assert!(try_acquire(dir.path(), 2).unwrap().is_none());
+        drop(first);
+        assert!(try_acquire(dir.path(), 2).unwrap().is_some());
+        assert!(acquire(dir.path(), 0, "flash-attn").unwrap().is_none());
+    }
+
+    #[test]
+    fn unusable_slots_mean_no_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let not_a_dir = dir.path().join("file");
+        std::fs::write(&not_a_dir, "").unwrap();
+        assert!(acquire(&not_a_dir, 1, "flash-attn").unwrap().is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn slot_files_can_be_locked_without_write_access() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let slot = dir.path().join("slot-0.lock");
+        std::fs::write(&slot, "").unwrap();
+        std::fs::set_permissions(&slot, std::fs::Permissions::from_mode(0o444)).unwrap();
+        assert!(try_acquire(dir.path(), 1).unwrap().is_some());
+    }
+
+    #[test]
+    fn source_builds_are_recognised_in_arguments() {
+        assert!(is_native_build(&args("python -m pip install flash-attn==2.7.4 --no-build-isolation"), None));
+        assert!(is_native_build(&args("python -m pip install --no-binary :all: somepkg"), None));
+    }
+
+    #[test]
+    fn git_requirements_are_source_builds() {
+        let line = ["uv", "pip", "install", "flash-attn @ git+https://github.com/Dao-AILab/flash-attention"];
+        assert!(is_native_build(&line.map(String::from), None));
+    }
+
+    #[test]
+    fn wheels_and_other_pip_commands_are_not_source_builds() {
+        assert!(!is_native_build(&args("python -m pip install torch numpy<2"), None));
+        assert!(!is_native_build(&args("python -m pip show flash-attn"), None));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn insightface_installs_from_a_wheel_on_windows() {
+        assert!(!is_native_build(&args("python -m pip install -U insightface"), None));
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn insightface_builds_from_source_elsewhere() {
+        assert!(is_native_build(&args("python -m pip install -U insightface"), None));
+    }
+
+    #[test]
+    fn source_builds_are_recognised_in_requirements_files() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("requirements.txt"), "torch\n-r extra.txt\n").unwrap();
+        std::fs::write(dir.path().join("extra.txt"), "numpy<2\nflash_attn>=2.6  # fast attention\n").unwrap();
+        std::fs::write(dir.path().join("plain.txt"), "torch\nnumpy\n").unwrap();
+        assert!(is_native_build(&args("python -m pip install -r requirements.txt"), Some(dir.path())));
+        assert!(!is_native_build(&args("python -m pip install -r plain.txt"), Some(dir.path())));
+        assert!(!is_native_build(&args("python -m pip install -r missing.txt"), Some(dir.path())));
+    }
+}
@@ -0,0 +1,199 @@
+//! Garbage collection of the shared wheel cache
+//!
+//! `cache/wheels` only grows: prefetch and installs download into it, and deleting a
+//! repository leaves its packages behind. A package file is garbage when no environment in
+//! `envs/` has that name and version installed. Two kinds of files are never collected:
+//! files younger than the minimum age (a prefetch for a repository that is not installed
+//! yet, a download of an install in progress) and files a running process has open.
+
+use crate::planned_actions::ActionPlan;
+use crate::prefetch::wheel_cache_dir;
+use crate::Result;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Packages downloaded more recently are kept even when nothing uses them yet
+pub const DEFAULT_MIN_AGE_DAYS: u64 = 14;
+
+/// (normalized name, version)
+pub type Distribution = (String, String);
+
+/// Lowercase with runs of `-`, `_` and `.` as one `_` (PEP 503, in wheel file spelling)
+pub fn normalize_name(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    for c in name.chars() {
+        if matches!(c, '-' | '_' | '.') {
+            if !out.ends_with('_') {
+                out.push('_');
+            }
+        } else {
+            out.push(c.to_ascii_lowercase());
+        }
+    }
+    out
+}
+
+/// Name and version of a cached wheel (`name-ver-py-abi-plat.whl`) or sdist (`name-ver.tar.gz`)
+pub fn parse_package_file(file_name: &str) -> Option<Distribution> {
+    if let Some(stem) = file_name.strip_suffix(".whl") {
+        let mut parts = stem.split('-');
+        let (name, version) = (parts.next()?, parts.next()?);
+        // build tag is optional: 5 or 6 parts in total
+        return (parts.count() >= 3).then(|| (normalize_name(name), version.to_string()));
+    }
+    let stem = [".tar.gz", ".zip", ".tar.bz2"].iter().find_map(|ext| file_name.strip_suffix(ext))?;
+    let (name, version) = stem.rsplit_once('-')?;
+    Some((normalize_name(name), version.to_string()))
+}
+
+/// Name and version of a `name-version.dist-info` folder
+pub fn parse_dist_info(dir_name: &str) -> Option<Distribution> {
+    let (name, version) = dir_name.strip_suffix(".dist-info")?.split_once('-')?;
+    Some((normalize_name(name), version.to_string()))
+}
+
+/// Everything installed in the repository environments, shared ones (`--no-venv`) included
+pub fn installed_distributions(install_path: &Path) -> HashSet<Distribution> {
+    let mut installed: HashSet<Distribution> = crate::shared_env::shared_distributions(install_path).into_iter().collect();
+    let envs = fs::read_dir(install_path.join("envs")).into_iter().flatten().flatten().map(|e| e.path());
+    for env in envs {
+        for site_packages in site_packages_dirs(&env) {
+            let names = fs::read_dir(site_packages).into_iter().flatten().flatten();
+            installed.extend(names.filter_map(|e| parse_dist_info(&e.file_name().to_string_lossy())));
+        }
+    }
+    installed
+}
+
+/// `Lib/site-packages` (Windows copies) and `lib*/python3.X/site-packages` (venvs)
+pub fn site_packages_dirs(env: &Path) -> Vec<PathBuf> {
+    let mut dirs = vec![env.join("Lib").join("site-packages")];
+    for lib in ["lib", "lib64"] {
+        let pythons = fs::read_dir(env.join(lib)).into_iter().flatten().flatten();
+        dirs.extend(pythons.map(|e| e.path().join("site-packages")));
+    }
+    dirs.retain(|d| d.is_dir());
+    dirs
+}
+
+/// Files under `dir` that a running process has open, with a description of the process
+#[cfg(target_os = "linux")]
+pub fn open_files(dir: &Path) -> HashMap<PathBuf, String> {
+    let dir = fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+    let mut open = HashMap::new();
+    for proc_entry in fs::read_dir("/proc").into_iter().flatten().flatten() {
+        let pid = proc_entry.file_name().to_string_lossy().to_string();
+        if !pid.chars().all(|c| c.is_ascii_digit()) {
+            continue;
+        }
+        for fd in fs::read_dir(proc_entry.path().join("fd")).into_iter().flatten().flatten() {
+            if let Ok(target) = fs::read_link(fd.path()) {
+                if target.starts_with(&dir) {
+                    open.insert(target, format!("pid {}", pid));
+                }
+            }
+        }
+    }
+    open
+}
+
+/// Without /proc open files are found per file while planning ([`is_locked`])
+#[cfg(not(target_os = "linux"))]
+pub fn open_files(_dir: &Path) -> HashMap<PathBuf, String> {
+    HashMap::new()
+}
+
+/// Windows: a file another process has open cannot be opened without sharing
+#[cfg(windows)]
+fn is_locked(path: &Path) -> bool {
+    use std::os::windows::fs::OpenOptionsExt;
+    const ERROR_SHARING_VIOLATION: i32 = 32;
+    match fs::OpenOptions::new().read(true).share_mode(0).open(path) {
+        Ok(_) => false,
+        Err(e) => e.raw_os_error() == Some(ERROR_SHARING_VIOLATION),
+    }
+}
+
+#[cfg(not(windows))]
+fn is_locked(_path: &Path) -> bool {
+    false
+}
+
+/// What a GC pass removes and why the rest of the unused files stay
+#[derive(Debug, Clone, Default)]
+pub struct GcPlan {
+    pub actions: ActionPlan,
+    pub reclaimable: u64,
+}
+
+/// Plan a GC pass over the wheel cache; nothing is deleted here
+pub fn plan(install_path: &Path, min_age: Duration) -> Result<GcPlan> {
+    let cache = wheel_cache_dir(install_path);
+    let mut gc = GcPlan::default();
+    let Ok(entries) = fs::read_dir(&cache) else { return Ok(gc) };
+    let installed = installed_distributions(install_path);
+    let open = open_files(&cache);
+    let now = SystemTime::now();
+
+    let mut files: Vec<PathBuf> = entries.flatten().map(|e| e.path()).filter(|p| p.is_file()).collect();
+    files.sort();
+    for path in files {
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let Some(dist) = parse_package_file(&file_name) else { continue };
+        if installed.contains(&dist) {
+            continue;
+        }
+        let resolved = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+        if let Some(process) = open.get(&resolved) {
+            gc.actions.keep(path, &format!("open in a running process ({})", process));
+            continue;
+        }
+        if is_locked(&path) {
+            gc.actions.keep(path, "open in a running process");
+            continue;
+        }
+        let metadata = fs::metadata(&path)?;
+        let age = metadata.modified().ok().and_then(|m| now.duration_since(m).ok()).unwrap_or_default();
+        if age < min_age {
+            gc.actions.keep(path, &format!("downloaded less than {} days ago", min_age.as_secs() / 86_400));
+            continue;
+        }
+        gc.reclaimable += metadata.len();
+        gc.actions.delete(path, "not installed in any environment");
+    }
+    Ok(gc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_old_packages_no_environment_uses_are_collected() {
+        assert_eq!(parse_package_file("torch-2.3.1+cu121-cp311-cp311-linux_x86_64.whl"), Some(("torch".into(), "2.3.1+cu121".into())));
+        assert_eq!(parse_package_file("antlr4-python3-runtime-4.9.3.tar.gz"), Some(("antlr4_python3_runtime".into(), "4.9.3".into())));
+        assert_eq!(parse_dist_info("Pillow-10.4.0.dist-info"), Some(("pillow".into(), "10.4.0".into())));
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let site = root.join("envs/comfyui/lib/python3.11/site-packages");
+        fs::create_dir_all(site.join("pillow-10.4.0.dist-info")).unwrap();
+        let cache = wheel_cache_dir(root);
+        fs::create_dir_all(&cache).unwrap();
+        for name in ["pillow-10.4.0-cp311-cp311-manylinux_2_28_x86_64.whl", "pillow-10.3.0-cp311-cp311-manylinux_2_28_x86_64.whl", "notes.txt"] {
+            fs::write(cache.join(name), b"data").unwrap();
+        }
+
+        let gc = plan(root, Duration::ZERO).unwrap();
+        let deleted: Vec<_> = gc.actions.items.iter().map(|i| i.path.file_name().unwrap().to_string_lossy().to_string()).collect();
+        assert_eq!(deleted, ["pillow-10.3.0-cp311-cp311-manylinux_2_28_x86_64.whl"]);
+        assert_eq!(gc.reclaimable, 4);
+
+        // A fresh download is kept for a prefetch or an install in progress
+        let gc = plan(root, Duration::from_secs(DEFAULT_MIN_AGE_DAYS * 86_400)).unwrap();
+        assert!(gc.actions.is_empty());
+        assert_eq!(gc.reclaimable, 0);
+    }
+}
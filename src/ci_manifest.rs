@@ -0,0 +1,186 @@
+//! `ci-manifest`: reproduce a repository environment in CI
+//!
+//! The packages installed in `envs/<repo>` are frozen into a requirements lock. GitHub
+//! runners have no GPU, so CUDA builds are swapped for their CPU counterparts (torch from
+//! the PyTorch CPU index, onnxruntime instead of onnxruntime-gpu, NVIDIA runtime wheels
+//! dropped). A workflow snippet installs the lock on the venv's Python version, on a runner of
+//! the operating system the environment was frozen on (Windows-only wheels such as
+//! triton-windows or pywin32 do not install on Linux).
+
+use crate::{PortableSourceError, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+pub const LOCK_FILE: &str = "requirements-ci.txt";
+pub const WORKFLOW_FILE: &str = "portablesource-ci.yml";
+pub const TORCH_CPU_INDEX: &str = "https://download.pytorch.org/whl/cpu";
+
+const TORCH_PACKAGES: &[&str] = &["torch", "torchvision", "torchaudio"];
+
+/// GitHub-hosted runner matching the platform environments are frozen on
+#[cfg(windows)]
+pub const RUNNER: &str = "windows-latest";
+#[cfg(not(windows))]
+pub const RUNNER: &str = "ubuntu-latest";
+
+/// Requirements lock with CPU substitutions applied
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CpuLock {
+    pub requirements: Vec<String>,
+    /// Human-readable list of substitutions and dropped lines
+    pub changes: Vec<String>,
+    /// torch packages come from the PyTorch CPU index
+    pub needs_torch_index: bool,
+}
+
+impl CpuLock {
+    /// Turn `pip freeze` output of a GPU environment into a lock installable on CPU runners
+    pub fn from_freeze(freeze: &str) -> Self {
+        let mut lock = CpuLock::default();
+        for line in freeze.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+            if line.starts_with("-e ") || line.contains(" @ ") {
+                // Editable or local installs (usually the repository itself) cannot be reproduced by name
+                lock.changes.push(format!("dropped local install: {}", line));
+                continue;
+            }
+            let Some((name, version)) = line.split_once("==") else {
+                lock.requirements.push(line.to_string());
+                continue;
+            };
+            let lname = name.trim().to_lowercase().replace('_', "-");
+
+            if lname.starts_with("nvidia-") || lname == "triton" || lname == "triton-windows" {
+                lock.changes.push(format!("dropped GPU runtime: {}", line));
+            } else if TORCH_PACKAGES.contains(&lname.as_str()) {
+                let base = version.split('+').next().unwrap_or(version);
+                let cpu = format!("{}=={}+cpu", lname, base);
+                if cpu != line {
+                    lock.changes.push(format!("{} -> {}", line, cpu));
+                }
+                lock.requirements.push(cpu);
+                lock.needs_torch_index = true;
+            } else if lname == "onnxruntime-gpu" || lname == "onnxruntime-directml" {
+                let cpu = format!("onnxruntime=={}", version);
+                lock.changes.push(format!("{} -> {}", line, cpu));
+                lock.requirements.push(cpu);
+            } else {
+                lock.requirements.push(line.to_string());
+            }
+        }
+        lock
+    }
+
+    pub fn render(&self, repo: &str) -> String {
+        let mut out = format!("# CPU reproduction of the '{}' environment built by portablesource\n", repo);
+        if self.needs_torch_index {
+            out.push_str(&format!("--extra-index-url {}\n", TORCH_CPU_INDEX));
+        }
+        for req in &self.requirements {
+            out.push_str(req);
+            out.push('\n');
+        }
+        out
+    }
+}
+
+/// GitHub Actions workflow installing the lock on `runner`
+pub fn render_workflow(repo: &str, python_version: &str, runner: &str) -> String {
+    format!(
+        "# Reproduces the environment portablesource builds for '{repo}' (CPU variant).
+# Generated by `portablesource ci-manifest {repo}`; commit it together with {lock}.
+name: portablesource-env
+
+on: [push, pull_request]
+
+jobs:
+  portablesource-env:
+    runs-on: {runner}
+    steps:
+      - uses: actions/checkout@v4
+      - uses: actions/setup-python@v5
+        with:
+          python-version: \"{python}\"
+          cache: pip
+          cache-dependency-path: {lock}
+      - name: Install locked requirements
+        run: |
+          python -m pip install --upgrade pip
+          python -m pip install -r {lock}
+      - name: Check dependency consistency
+        run: python -m pip check
+",
+        repo = repo,
+        lock = LOCK_FILE,
+        python = python_version,
+        runner = runner,
+    )
+}
+
+fn run_python(python: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new(python).args(args).output().ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// `pip freeze` of the repo venv (through uv when the venv has no pip) and its `major.minor` Python
+pub fn freeze_environment(install_path: &Path, repo: &str) -> Result<(String, String)> {
+    let python = crate::shared_env::repo_python(install_path, repo)?;
+    if !python.exists() {
+        return Err(PortableSourceError::environment(format!(
+            "Environment of '{}' not found at {}", repo, python.display()
+        )));
+    }
+    let freeze = run_python(&python, &["-m", "pip", "freeze"])
+        .or_else(|| run_python(&python, &["-m", "uv", "pip", "freeze", "--python", &python.to_string_lossy()]))
+        .ok_or_else(|| PortableSourceError::command(format!("Could not list packages of '{}' (pip and uv failed)", repo)))?;
+    let version = run_python(&python, &["-c", "import sys; print('%d.%d' % sys.version_info[:2])"])
+        .map(|v| v.trim().to_string())
+        .unwrap_or_else(|| "3.11".to_string());
+    Ok((freeze, version))
+}
+
+/// Write the lock and the workflow into `output_dir`; returns both paths and the lock
+pub fn write_manifest(install_path: &Path, repo: &str, output_dir: &Path) -> Result<(PathBuf, PathBuf, CpuLock)> {
+    let (freeze, python_version) = freeze_environment(install_path, repo)?;
+    let lock = CpuLock::from_freeze(&freeze);
+    fs::create_dir_all(output_dir)?;
+    let lock_path = output_dir.join(LOCK_FILE);
+    let workflow_path = output_dir.join(WORKFLOW_FILE);
+    fs::write(&lock_path, lock.render(repo))?;
+    fs::write(&workflow_path, render_workflow(repo, &python_version, RUNNER))?;
+    Ok((lock_path, workflow_path, lock))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gpu_packages_are_replaced_with_cpu_builds() {
+        let freeze = "\
+gradio==4.44.0
+torch==2.5.1+cu124
+torchvision==0.20.1+cu124
+nvidia-cublas-cu12==12.4.5.8
+onnxruntime-gpu==1.20.1
+triton==3.1.0
+-e git+https://github.com/acme/demo@abc#egg=demo
+numpy==1.26.4
+";
+        let lock = CpuLock::from_freeze(freeze);
+        assert_eq!(
+            lock.requirements,
+            ["gradio==4.44.0", "torch==2.5.1+cpu", "torchvision==0.20.1+cpu", "onnxruntime==1.20.1", "numpy==1.26.4"]
+        );
+        assert!(lock.needs_torch_index);
+        assert_eq!(lock.changes.len(), 6);
+        assert!(lock.render("demo").contains(&format!("--extra-index-url {}\n", TORCH_CPU_INDEX)));
+    }
+
+    #[test]
+    fn windows_environments_are_reproduced_on_a_windows_runner() {
+        let workflow = render_workflow("demo", "3.11", "windows-latest");
+        assert!(workflow.contains("    runs-on: windows-latest\n"), "{}", workflow);
+        assert!(workflow.contains("python-version: \"3.11\""));
+    }
+}
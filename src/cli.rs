@@ -2,20 +2,68 @@
 
 use clap::{Parser, Subcommand};
 use std::path::PathBuf;
+use crate::config::InstallEngine;
+use crate::error::ErrorFormat;
+#[cfg(unix)]
+use crate::install_sandbox::SandboxPolicy;
+use crate::installer::OnStepError;
+use crate::log_levels::LogSpec;
+use crate::performance::PerformanceProfile;
+use crate::repo_metadata::Backend;
 
 #[derive(Parser)]
 #[command(name = "portablesource")]
 #[command(about = "PortableSource - Portable AI/ML Environment Manager")]
+#[command(long_about = "PortableSource - Portable AI/ML Environment Manager\n\n\
+Installs AI/ML repositories with their own Python environment, portable git/ffmpeg/CUDA \
+and a start script, without touching the system Python.\n\n\
+Run `portablesource examples` for common workflows, or `portablesource <command> --help` \
+for details and examples of a command.")]
 #[command(version = env!("CARGO_PKG_VERSION"))]
 pub struct Cli {
     /// Enable debug logging
     #[arg(long)]
     pub debug: bool,
     
-    /// Installation path
+    /// Per-subsystem log levels, e.g. `installer=debug,gpu=warn` (subsystems: installer, env, gpu, download, config, run, scheduler)
+    #[arg(long, global = true, value_name = "SPEC", value_parser = parse_log_spec)]
+    pub log: Option<LogSpec>,
+    
+    /// Print the duration of each operation (download, extract, clone, ...) as it finishes
+    #[arg(long, global = true)]
+    pub trace: bool,
+    
+    /// Installation path (falls back to PORTABLESOURCE_INSTALL_PATH)
     #[arg(long)]
     pub install_path: Option<PathBuf>,
     
+    /// Suppress progress output (errors are still shown)
+    #[arg(long, short, global = true)]
+    pub quiet: bool,
+    
+    /// Plain output without colors (also honours the NO_COLOR environment variable)
+    #[arg(long, global = true)]
+    pub no_color: bool,
+    
+    /// Screen-reader friendly output: progress as discrete lines instead of bars and
+    /// spinners, no colors, and numbered answers listed for every question
+    #[arg(long, global = true)]
+    pub plain: bool,
+    
+    /// Seconds between plain-text progress lines when output is not a terminal or with --plain
+    #[arg(long, global = true, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub progress_interval: Option<u64>,
+    
+    /// Treat the installation as read-only: refuse commands that change it (also
+    /// PORTABLESOURCE_READ_ONLY=1, or permanently with `config read-only`)
+    #[arg(long, global = true)]
+    pub read_only: bool,
+    
+    /// How a failure is printed: text, or json (one object on stderr; bulk operations list
+    /// every failed item). Partial failures of bulk operations exit with code 3
+    #[arg(long, global = true, value_name = "FORMAT", default_value = "text")]
+    pub error_format: ErrorFormat,
+    
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
@@ -23,7 +71,68 @@ pub struct Cli {
 #[derive(Subcommand)]
 pub enum Commands {
     /// Setup environment (Portable)
-    SetupEnv,
+    ///
+    /// Lists every download (source, size, destination) and the total before starting.
+    #[command(after_help = SETUP_ENV_EXAMPLES)]
+    SetupEnv {
+        /// Windows: reuse system Python (3.10-3.12) and git (2.30+) found on PATH instead of
+        /// downloading the portable ones; tools that do not qualify are still downloaded
+        #[arg(long)]
+        use_system_tools: bool,
+        /// Start the downloads without asking (never asked when stdin is not a terminal)
+        #[arg(long, short = 'y')]
+        yes: bool,
+    },
+    
+    /// Create a project-local workspace (.portablesource/) in the current folder
+    ///
+    /// Commands run in the project or below it then keep repositories, environments, tools
+    /// and configuration in the workspace instead of the machine-wide install.
+    #[command(after_help = INIT_EXAMPLES)]
+    Init {
+        /// Project folder (default: current directory)
+        path: Option<PathBuf>,
+    },
+    
+    /// Upgrade a portable tool (python, git, ffmpeg or cuda) without touching the version in use
+    ///
+    /// The new version is unpacked and verified next to the current one, then ps_env/<tool>
+    /// is switched to it; if it fails after the switch the previous version is restored.
+    /// The previous version is kept for --rollback.
+    #[command(after_help = UPGRADE_TOOL_EXAMPLES)]
+    UpgradeTool {
+        /// python, git, ffmpeg or cuda
+        #[arg(value_parser = ["python", "git", "ffmpeg", "cuda"])]
+        tool: String,
+        /// Switch back to the version before the last upgrade
+        #[arg(long)]
+        rollback: bool,
+    },
+    
+    /// Set up, install and launch a repository with one command and no questions (demos)
+    ///
+    /// Uses ./portablesource unless an install path is given, installs CPU packages when no
+    /// CUDA GPU is detected within 5 seconds, and skips optional extras such as Triton on
+    /// CPU or when they fail to install (`--on-error`). Tool logs are hidden; the steps are
+    /// shown as numbered stages.
+    #[command(after_help = QUICKSTART_EXAMPLES)]
+    Quickstart {
+        /// Repository URL or name
+        repo: String,
+        /// Install even if the license is not a known permissive one
+        #[arg(long)]
+        accept_license: bool,
+        /// Stop after installing and print the launch command instead of starting the UI
+        #[arg(long)]
+        no_run: bool,
+        /// When an install step fails: skip-optional (skip Triton and InsightFace, abort on
+        /// other steps) or abort; quickstart asks no questions, so ask means abort
+        #[arg(long, value_name = "POLICY", default_value = "skip-optional")]
+        on_error: OnStepError,
+        /// Arguments passed to the repository when it starts
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
     
     /// Register installation path in registry (Unix only)
     #[cfg(unix)]
@@ -33,33 +142,170 @@ pub enum Commands {
     #[cfg(unix)]
     Unregister,
     
-    /// Uninstall PortableSource completely (Linux only)
+    /// Uninstall PortableSource, or parts of it (Linux only)
+    ///
+    /// Everything that will be deleted, moved or kept is listed before confirmation.
     #[cfg(unix)]
-    Uninstall,
+    #[command(after_help = UNINSTALL_EXAMPLES)]
+    Uninstall {
+        /// Keep repos/ (sources, models, outputs); tools, environments and config are removed
+        #[arg(long, conflicts_with_all = ["tools_only", "repo"])]
+        keep_repos: bool,
+        /// Remove only ps_env and the package cache; repositories and environments stay
+        #[arg(long, conflicts_with = "repo")]
+        tools_only: bool,
+        /// Remove one repository and its environment
+        #[arg(long, value_name = "NAME")]
+        repo: Option<String>,
+        /// Delete model folders too instead of moving them to models/<name> and keeping models/
+        #[arg(long, conflicts_with = "tools_only")]
+        with_models: bool,
+        /// List what would be deleted, moved and kept, with sizes, and change nothing
+        #[arg(long)]
+        dry_run: bool,
+    },
     
     /// Change installation path (Unix only)
     #[cfg(unix)]
     ChangePath,
     
     /// Install repository (alias: ir)
-    #[command(alias = "ir")]
+    ///
+    /// Accepts a git URL or a repository name known to the PortableSource server.
+    /// The repository is cloned into repos/<name>, gets its own environment in envs/<name>,
+    /// dependencies are installed and a start script is generated.
+    #[command(alias = "ir", after_help = INSTALL_REPO_EXAMPLES)]
     InstallRepo {
         /// Repository URL or name
         repo: String,
+        /// Install without confirming a non-permissive license
+        #[arg(long)]
+        accept_license: bool,
+        /// Package installer for this repository: auto, uv or pip (remembered for updates)
+        #[arg(long)]
+        engine: Option<InstallEngine>,
+        /// Install as a separate instance under this folder/venv name
+        #[arg(long = "as", value_name = "NAME")]
+        instance: Option<String>,
+        /// Check out this branch instead of the default one (followed by updates)
+        #[arg(long)]
+        branch: Option<String>,
+        /// Add the checkout as a git worktree of this installed repository, sharing its
+        /// object store; the instance keeps its own environment and start script
+        #[arg(long, value_name = "NAME", requires_all = ["instance", "branch"])]
+        worktree_of: Option<String>,
+        /// Linux CLOUD mode: install packages into the micromamba base environment instead
+        /// of a venv, for images where venvs are slow or not allowed
+        #[arg(long)]
+        no_venv: bool,
+        /// With --no-venv: install into this existing conda environment instead of the base
+        #[arg(long, value_name = "NAME", requires = "no_venv")]
+        conda_env: Option<String>,
+        /// Performance tuning for the start script: balanced, low-vram or max-speed
+        #[arg(long)]
+        profile: Option<PerformanceProfile>,
+        /// Show the server installation plan as a diff against the repository's own
+        /// requirements and confirm steps the repository does not declare
+        #[arg(long)]
+        review_plan: bool,
+        /// When an install step fails: ask (retry, skip or abort; abort without a terminal),
+        /// abort, or skip-optional (skip Triton and InsightFace, abort on other steps)
+        #[arg(long, value_name = "POLICY", default_value = "ask")]
+        on_error: OnStepError,
+        /// Start from an empty venv instead of a clone of the installed environment that
+        /// already has most of the requirements
+        #[arg(long)]
+        clean_env: bool,
+        /// Extras of a poetry or pdm project to install with it, comma separated
+        /// (remembered for updates)
+        #[arg(long, value_name = "EXTRA", value_delimiter = ',')]
+        extras: Vec<String>,
+        /// Copy the command that starts the repository to the clipboard
+        #[arg(long)]
+        copy: bool,
     },
     
     /// Update repository (alias: ur)
-    #[command(alias = "ur")]
+    ///
+    /// Pulls the latest code and reinstalls dependencies. Without a name a selector is shown.
+    #[command(alias = "ur", after_help = UPDATE_REPO_EXAMPLES)]
     UpdateRepo {
         /// Repository name (optional; if omitted, a TUI selector will be shown)
         repo: Option<String>,
+        /// Update every installed repository
+        #[arg(long, conflicts_with = "repo")]
+        all: bool,
+        /// Package installer for this repository: auto, uv or pip (remembered for updates)
+        #[arg(long)]
+        engine: Option<InstallEngine>,
+        /// Show the server installation plan as a diff against the repository's own
+        /// requirements and confirm steps the repository does not declare
+        #[arg(long)]
+        review_plan: bool,
+        /// When an install step fails: ask (retry, skip or abort; abort without a terminal),
+        /// abort, or skip-optional (skip Triton and InsightFace, abort on other steps)
+        #[arg(long, value_name = "POLICY", default_value = "ask")]
+        on_error: OnStepError,
+        /// List what would be modified and removed, with sizes, and change nothing
+        #[arg(long)]
+        dry_run: bool,
+    },
+    
+    /// Download the packages of repositories into the shared cache without installing
+    ///
+    /// Resolves each repository's install plan (server plan or requirements) and runs
+    /// `pip download` into cache/wheels; later installs and updates use the cache first.
+    /// Targets can be repository names, git URLs or `.recipe` files listing them one per line.
+    #[command(after_help = PREFETCH_EXAMPLES)]
+    Prefetch {
+        /// Repository name, git URL or recipe file (*.recipe)
+        #[arg(required = true, value_name = "REPO|RECIPE")]
+        targets: Vec<String>,
+    },
+    
+    /// Download the models listed in a repository's model manifest
+    ///
+    /// Reads repos/<repo>/.portablesource_models.json (or --manifest), downloads the files
+    /// in parallel with resume, checks the sha256 of each and places it in the folder the
+    /// repository expects for that kind of model.
+    #[command(after_help = DOWNLOAD_MODELS_EXAMPLES)]
+    DownloadModels {
+        /// Installed repository name
+        repo: String,
+        /// Manifest file to use instead of the one in the repository
+        #[arg(long, value_name = "FILE")]
+        manifest: Option<PathBuf>,
+        /// Files downloaded at the same time
+        #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u16).range(1..=16))]
+        jobs: u16,
+    },
+
+    /// Share model folders between repositories
+    ///
+    /// Replaces the model folders of each repository with links into shared_models/<kind>,
+    /// moving files already there into the shared folder. Windows uses a directory symlink
+    /// when developer mode allows it and a junction otherwise; no admin rights are needed.
+    #[command(after_help = SHARE_MODELS_EXAMPLES)]
+    ShareModels {
+        /// Installed repositories (default: all)
+        repos: Vec<String>,
     },
     
+    /// List installer plugins
+    ///
+    /// Plugins live in <install>/plugins/<name>/plugin.json and hook into resolving,
+    /// installing and post-install steps of the repositories they name.
+    #[command(after_help = PLUGINS_EXAMPLES)]
+    Plugins,
+    
     /// Delete repository (alias: dr)
     #[command(alias = "dr")]
     DeleteRepo {
         /// Repository name
         repo: String,
+        /// List what would be removed, with sizes, and change nothing
+        #[arg(long)]
+        dry_run: bool,
     },
     
     /// List installed repositories (alias: lr)
@@ -67,8 +313,33 @@ pub enum Commands {
     ListRepos,
 
     /// Run repository start script (alias: rr)
-    #[command(alias = "rr")]
+    ///
+    /// Everything after the repository name is passed to the repository unchanged,
+    /// including arguments that start with '-'. Options of run-repo itself (--wait-gpu,
+    /// --no-network, ...) must therefore come before the repository name.
+    #[command(alias = "rr", after_help = RUN_REPO_EXAMPLES)]
     RunRepo {
+        /// Wait for free VRAM before launching (place before the repository name)
+        #[arg(long, conflicts_with = "no_wait_gpu")]
+        wait_gpu: bool,
+        /// Launch immediately even if GPU queueing is configured
+        #[arg(long)]
+        no_wait_gpu: bool,
+        /// Free VRAM in MB required to launch when queueing
+        #[arg(long)]
+        min_free_vram: Option<u64>,
+        /// Seconds to wait for free VRAM (0 = forever)
+        #[arg(long)]
+        gpu_timeout: Option<u64>,
+        /// Block network access (Linux: network namespace, Windows: firewall rules; place before the repository name)
+        #[arg(long)]
+        no_network: bool,
+        /// GPU for this launch: index (0), MIG instance (0:1) or GPU/MIG UUID; sets CUDA_VISIBLE_DEVICES
+        #[arg(long, value_name = "DEVICE")]
+        gpu: Option<String>,
+        /// Keep the end of stderr for out-of-memory advice; the repository's stderr is then no terminal (always on when stderr is redirected)
+        #[arg(long)]
+        capture_log: bool,
         /// Repository name to run
         repo: String,
         /// Additional arguments to pass to the repository script
@@ -76,11 +347,289 @@ pub enum Commands {
         args: Vec<String>,
     },
     
+    /// Configure idle-GPU queueing for run-repo, globally or for one repository
+    #[command(after_help = GPU_QUEUE_EXAMPLES)]
+    GpuQueue {
+        /// Repository name (omit to change the global default)
+        repo: Option<String>,
+        /// Queue launches while VRAM is busy
+        #[arg(long, conflicts_with = "disable")]
+        enable: bool,
+        /// Launch immediately without queueing
+        #[arg(long)]
+        disable: bool,
+        /// Free VRAM in MB required to launch
+        #[arg(long)]
+        min_free_vram: Option<u64>,
+        /// Seconds to wait for free VRAM (0 = forever)
+        #[arg(long)]
+        timeout: Option<u64>,
+        /// Drop the repository override and use the global settings
+        #[arg(long, requires = "repo")]
+        reset: bool,
+    },
+    
+    /// Rebuild the cached repository list after changing repos/ manually
+    RebuildIndex,
+    
+    /// Import an install made by the Python version of portablesource
+    ///
+    /// Repositories without metadata get it from their git remote, checkouts and
+    /// environments of an install elsewhere are moved here, the old config is migrated and
+    /// start scripts are regenerated. What cannot be carried over is listed at the end.
+    #[command(after_help = IMPORT_LEGACY_EXAMPLES)]
+    ImportLegacy {
+        /// Root of the old install (default: the current install path)
+        from: Option<PathBuf>,
+        /// List what would be moved and recorded, and change nothing
+        #[arg(long)]
+        dry_run: bool,
+    },
+    
+    /// Show run statistics of a repository (duration, exit codes, GPU utilization, VRAM)
+    ///
+    /// Collection is off by default; turn it on with --enable. Each run-repo then samples
+    /// nvidia-smi and stores the run in the repository metadata.
+    #[command(after_help = STATS_EXAMPLES)]
+    Stats {
+        /// Repository name
+        repo: Option<String>,
+        /// Start recording run statistics
+        #[arg(long, conflicts_with = "disable")]
+        enable: bool,
+        /// Stop recording run statistics
+        #[arg(long)]
+        disable: bool,
+        /// Delete the recorded runs of the repository
+        #[arg(long, requires = "repo")]
+        clear: bool,
+    },
+    
+    /// Summarize local install, update and delete history for administrators
+    ///
+    /// Reads logs/history.jsonl, which every install-repo, update-repo and delete-repo
+    /// appends to. Shows installs, updates and failures per repository, average install
+    /// time, days between updates and failures by category. Nothing leaves the machine.
+    #[command(after_help = REPORT_USAGE_EXAMPLES)]
+    ReportUsage {
+        /// Period to cover, e.g. 30d, 12h or 2w
+        #[arg(long, default_value = "30d")]
+        since: String,
+        /// Output format: text, csv or json
+        #[arg(long, default_value = "text")]
+        format: crate::history::ReportFormat,
+        /// Write the report to this file instead of printing it
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    
+    /// Show license and provenance of an installed repository and how to start it
+    InfoRepo {
+        /// Repository name
+        repo: String,
+        /// Print metadata as JSON (for compliance export)
+        #[arg(long)]
+        json: bool,
+        /// Copy the command that starts the repository to the clipboard
+        #[arg(long, conflicts_with = "json")]
+        copy: bool,
+    },
+    
+    /// Set the performance profile of a repository and regenerate its start script
+    ///
+    /// The profile picks allocator (PYTORCH_CUDA_ALLOC_CONF), CUDA module loading and
+    /// OMP_NUM_THREADS for the detected GPU and its VRAM. Without options the current
+    /// profile and the variables it sets are shown.
+    #[command(after_help = TUNE_REPO_EXAMPLES)]
+    TuneRepo {
+        /// Repository name
+        repo: String,
+        /// balanced, low-vram or max-speed
+        #[arg(long, conflicts_with = "reset")]
+        profile: Option<PerformanceProfile>,
+        /// Remove the profile; the start script exports no tuning variables
+        #[arg(long)]
+        reset: bool,
+    },
+
+    /// Switch a repository between CPU and CUDA builds of its packages
+    ///
+    /// Only the backend-dependent packages are reinstalled: torch, torchvision and torchaudio
+    /// (same versions) from the matching index, and the ONNX Runtime variant. The start script
+    /// is regenerated with or without the CUDA section, and updates keep the chosen backend.
+    #[command(after_help = SWITCH_BACKEND_EXAMPLES)]
+    SwitchBackend {
+        /// Repository name
+        repo: String,
+        /// cpu or cuda
+        backend: Backend,
+    },
+    
+    /// Declare native library components (cudnn, zlib, msvc) a repository needs on Windows
+    ///
+    /// Components are unpacked once into ps_env/components and their DLL folder is added to
+    /// the PATH of the repository's start script. Without a repository the known components
+    /// are listed, without options the repository's components are shown.
+    #[command(after_help = COMPONENTS_EXAMPLES)]
+    Components {
+        /// Repository name
+        repo: Option<String>,
+        /// Component to add (repeatable)
+        #[arg(long, value_name = "NAME", requires = "repo")]
+        add: Vec<String>,
+        /// Component to remove (repeatable); its files stay for other repositories
+        #[arg(long, value_name = "NAME", requires = "repo")]
+        remove: Vec<String>,
+    },
+    
+    /// Print the executable a repository uses for python, git, ffmpeg or nvcc
+    ///
+    /// Follows the order of the start script: the venv (or shared environment) interpreter,
+    /// portable tools, native library components, CUDA, the micromamba base in DESK mode, then
+    /// the system PATH.
+    #[command(after_help = WHICH_EXAMPLES)]
+    Which {
+        /// Repository name
+        repo: String,
+        /// Tool to look up
+        #[arg(value_parser = ["python", "git", "ffmpeg", "nvcc"])]
+        tool: String,
+        /// List every candidate in search order and where it comes from
+        #[arg(long)]
+        all: bool,
+    },
+    
+    /// Regenerate repository start script, or print it with --dry-run
+    RenderScript {
+        /// Repository name
+        repo: String,
+        /// Print the script instead of writing it
+        #[arg(long)]
+        dry_run: bool,
+    },
+    
+    /// Regenerate the start scripts of all installed repositories with the current templates
+    ///
+    /// Run after upgrading portablesource. Scripts you edited are left alone and the new
+    /// version is written next to them as .new; --force replaces them and keeps a .bak.
+    #[command(after_help = REGEN_SCRIPTS_EXAMPLES)]
+    RegenScripts {
+        /// Only this repository
+        #[arg(long)]
+        repo: Option<String>,
+        /// Replace scripts you edited (a .bak copy is kept)
+        #[arg(long)]
+        force: bool,
+        /// Report which scripts would change without writing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    
+    /// Pack a repository venv into a .tar.zst (Unix only)
+    #[cfg(unix)]
+    ExportEnv {
+        /// Repository name
+        repo: String,
+        /// Output archive (default: <repo>-env.tar.zst)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Record hard-coded paths so import-env can fix them on another install path
+        #[arg(long)]
+        relocatable: bool,
+    },
+    
+    /// Unpack a venv made by export-env into envs/ (Unix only)
+    #[cfg(unix)]
+    ImportEnv {
+        /// Archive produced by export-env
+        archive: PathBuf,
+        /// Repository name to install the environment under (default: name from the archive)
+        #[arg(long)]
+        name: Option<String>,
+    },
+    
+    /// Write a requirements lock and GitHub Actions workflow reproducing a repository environment
+    ///
+    /// Packages are frozen from envs/<repo>; CUDA builds are replaced with CPU ones
+    /// (torch from the PyTorch CPU index, onnxruntime instead of onnxruntime-gpu) so
+    /// the environment installs on standard CI runners. The workflow runs on Windows
+    /// when the environment was built on Windows, on Ubuntu otherwise.
+    #[command(after_help = CI_MANIFEST_EXAMPLES)]
+    CiManifest {
+        /// Repository name
+        repo: String,
+        /// Directory for requirements-ci.txt and portablesource-ci.yml (default: current directory)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    
+    /// Write a setup script that installs portablesource and repositories on a fresh machine
+    ///
+    /// The script downloads the portablesource binary, runs setup-env and installs the
+    /// repositories (by default the ones installed here, with their instance names and engines).
+    #[command(after_help = BOOTSTRAP_EXAMPLES)]
+    Bootstrap {
+        /// Script to write: .sh for bash, .ps1 for PowerShell
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Repository URL or name to install (repeatable; default: repositories installed here)
+        #[arg(long = "repo", value_name = "REPO")]
+        repos: Vec<String>,
+        /// Release tag of portablesource to download (default: latest)
+        #[arg(long)]
+        release: Option<String>,
+    },
+    
     /// Show system information
     SystemInfo,
     
     /// Check environment status and tools
+    ///
+    /// Also creates one CUDA context, since Remote Desktop and service sessions may see the GPU
+    /// in nvidia-smi but not be allowed to use it.
     CheckEnv,
+
+    /// Smoke-test a repository environment: import torch and run one CUDA matmul
+    ///
+    /// GPU utilization and VRAM are sampled while the check runs; a failure on a busy GPU
+    /// names the process holding it.
+    #[command(after_help = TEST_REPO_EXAMPLES)]
+    TestRepo {
+        /// Repository name
+        repo: String,
+    },
+
+    /// Check that the CUDA libraries the repository's native extensions load are provided
+    ///
+    /// Reads the library dependencies of every extension in the environment and compares
+    /// the CUDA/cuDNN versions they need with those of nvidia-* wheels, bundled libraries,
+    /// the portable CUDA toolkit and components, without starting Python.
+    #[command(after_help = VERIFY_REPO_EXAMPLES)]
+    VerifyRepo {
+        /// Repository name
+        repo: String,
+        /// List the CUDA libraries each package needs
+        #[arg(long)]
+        verbose: bool,
+    },
+
+    /// Measure fp16 matmul throughput in a repository environment (GPU sampled as in test-repo)
+    #[command(after_help = TEST_REPO_EXAMPLES)]
+    Benchmark {
+        /// Repository name
+        repo: String,
+        /// Timed matmul runs
+        #[arg(long, default_value_t = 200)]
+        iterations: u32,
+    },
+    
+    /// Check the environment and report where the last command spent its time
+    #[command(after_help = DOCTOR_EXAMPLES)]
+    Doctor {
+        /// Show operation timings (download, extract, clone, venv, requirements) of the last command
+        #[arg(long)]
+        timings: bool,
+    },
     
     #[cfg(windows)]
     /// Install MSVC Build Tools
@@ -90,13 +639,400 @@ pub enum Commands {
     /// Check MSVC Build Tools installation
     CheckMsvc,
     
+    /// Back up or restore the whole installation (repositories, configuration, environments)
+    #[command(after_help = BACKUP_EXAMPLES)]
+    Backup {
+        #[command(subcommand)]
+        action: BackupAction,
+    },
+    
+    /// Scheduled maintenance tasks (Task Scheduler / systemd timer / cron)
+    Schedule {
+        #[command(subcommand)]
+        action: ScheduleAction,
+    },
+    
+    /// Configuration file maintenance
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    
+    /// Maintain the shared package cache (cache/wheels)
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+    
+    /// Inspect the environment repositories are launched with
+    Env {
+        #[command(subcommand)]
+        action: EnvAction,
+    },
+    
+    /// Print common workflows with example commands
+    Examples,
+    
     /// Show True if gpu nvidia. Else False
-    CheckGpu,
+    CheckGpu {
+        /// List every NVIDIA GPU with its MIG instances and their UUIDs
+        #[arg(long)]
+        all: bool,
+    },
+    
+    /// Show version, build metadata and an environment line for bug reports
+    Version {
+        /// Print as JSON
+        #[arg(long)]
+        json: bool,
+    },
     
-    /// Show version
-    Version,
+    /// Describe this machine for fleet management: host, CPU, RAM, GPUs, installed
+    /// repositories with their commits and sizes, and tool versions
+    #[command(after_help = INVENTORY_EXAMPLES)]
+    Inventory {
+        /// Print as JSON, for collection by fleet tooling
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ScheduleAction {
+    /// Register maintenance commands, e.g. --weekly "update-repo comfyui; check-env"
+    Enable {
+        /// Commands to run weekly, separated by ';'
+        #[arg(long, conflicts_with = "daily", required_unless_present = "daily")]
+        weekly: Option<String>,
+        /// Commands to run daily, separated by ';'
+        #[arg(long)]
+        daily: Option<String>,
+    },
+    /// Remove scheduled maintenance
+    Disable,
+    /// Show schedule and last report
+    Status,
+    /// Run scheduled commands now and write report (used by the scheduler)
+    Run,
 }
 
+#[derive(Subcommand)]
+pub enum BackupAction {
+    /// Pack repositories and configuration into a .tar.zst (models and outputs are left out)
+    Create {
+        /// Archive to write, e.g. portablesource-backup.tar.zst
+        target: PathBuf,
+        /// Include the environments (large; only restorable on the same OS and architecture)
+        #[arg(long)]
+        with_envs: bool,
+        /// Extra path pattern to leave out, relative to each repository ('**/' for any depth)
+        #[arg(long, value_name = "PATTERN")]
+        exclude: Vec<String>,
+        /// Back up models, outputs and caches too
+        #[arg(long)]
+        no_default_excludes: bool,
+    },
+    /// Unpack a backup onto this install path, rewriting paths recorded in it
+    Restore {
+        /// Archive made by backup create
+        archive: PathBuf,
+        /// Replace repositories and environments that already exist
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum CacheAction {
+    /// Remove cached packages that no environment has installed
+    ///
+    /// Files a running process has open and files younger than --min-age-days are kept.
+    /// delete-repo runs this automatically.
+    #[command(after_help = CACHE_GC_EXAMPLES)]
+    Gc {
+        /// List what would be removed and the space it frees, and change nothing
+        #[arg(long)]
+        dry_run: bool,
+        /// Keep packages downloaded fewer days ago (prefetched for repositories not installed yet)
+        #[arg(long, value_name = "DAYS", default_value_t = crate::cache_gc::DEFAULT_MIN_AGE_DAYS)]
+        min_age_days: u64,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum EnvAction {
+    /// Print the environment run-repo would start a repository with: PATH in lookup order
+    /// and every variable, tagged inherited, run-repo or script; secrets are masked
+    #[command(after_help = ENV_SHOW_EXAMPLES)]
+    Show {
+        /// Repository name
+        repo: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigAction {
+    /// Upgrade an old config file to the current schema (the original is kept as .bak)
+    Migrate {
+        /// Config file (default: the one portablesource loads)
+        #[arg(long)]
+        file: Option<PathBuf>,
+        /// Show the steps and the files that would change without writing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Mark the installation read-only for everyone using it (shared lab installs)
+    ///
+    /// Commands that change the installation then refuse; run-repo and inspecting commands
+    /// keep working and write their own files to a per-user directory.
+    ReadOnly {
+        /// Note shown to users when a command is refused
+        #[arg(long, conflicts_with = "off")]
+        message: Option<String>,
+        /// Make the installation writable again
+        #[arg(long)]
+        off: bool,
+    },
+    /// Show or set how many native package builds (flash-attn, insightface from source) may
+    /// run at once on this machine; further builds wait for a free slot
+    BuildSlots {
+        /// Builds allowed at once (0 = no limit)
+        max: Option<usize>,
+    },
+    /// Show or set whether install hooks and source builds run in a sandbox (Linux:
+    /// bubblewrap or firejail; only the installation stays writable)
+    #[cfg(unix)]
+    InstallSandbox {
+        /// off, prefer (sandbox when available, warn otherwise) or require (refuse without one)
+        policy: Option<SandboxPolicy>,
+    },
+}
+
+const QUICKSTART_EXAMPLES: &str = "\
+Examples:
+  portablesource quickstart comfyui
+  portablesource quickstart comfyui --listen 0.0.0.0                   # arguments go to the repository
+  portablesource quickstart --accept-license --no-run facefusion       # options before the name";
+
+const SETUP_ENV_EXAMPLES: &str = "\
+Examples:
+  portablesource setup-env                                             # shows downloads and total size, then asks
+  portablesource setup-env --yes                                       # unattended
+  portablesource setup-env --use-system-tools                          # Windows: skip Python/git downloads if on PATH";
+
+const INSTALL_REPO_EXAMPLES: &str = "\
+Examples:
+  portablesource install-repo comfyui                                  # by name from the server list
+  portablesource install-repo https://github.com/comfyanonymous/ComfyUI  # by git URL
+  portablesource install-repo comfyui --as comfyui-video               # second, independent instance
+  portablesource install-repo comfyui --as comfyui-dev --branch dev --worktree-of comfyui  # branch sharing one clone
+  portablesource install-repo comfyui --profile max-speed              # tuned start script
+  portablesource install-repo comfyui --no-venv                        # Linux CLOUD: into the mamba base
+  portablesource install-repo comfyui --review-plan                    # confirm server plan steps first
+  portablesource install-repo facefusion --on-error skip-optional      # unattended, Triton/InsightFace may be skipped
+  portablesource install-repo comfyui --clean-env                      # no warm start from a similar environment
+  portablesource install-repo comfyui --copy                           # launch command to the clipboard
+  portablesource install-repo https://github.com/user/repo --engine pip --accept-license";
+
+const PREFETCH_EXAMPLES: &str = "\
+Examples:
+  portablesource prefetch comfyui facefusion                           # warm the cache for two repositories
+  portablesource prefetch travel.recipe                                # every repository listed in a recipe
+  portablesource install-repo comfyui                                  # later, installs from the cache";
+
+#[cfg(unix)]
+const UNINSTALL_EXAMPLES: &str = "\
+Examples:
+  portablesource uninstall --repo comfyui-video                       # models are moved to models/comfyui-video
+  portablesource uninstall --repo comfyui-video --with-models
+  portablesource uninstall --tools-only                               # free ps_env, keep repositories
+  portablesource uninstall --keep-repos                               # remove PortableSource, keep repos/
+  portablesource uninstall --keep-repos --dry-run                     # only list what would be removed";
+
+const DOWNLOAD_MODELS_EXAMPLES: &str = "\
+Examples:
+  portablesource download-models comfyui                              # models from the repository manifest
+  portablesource download-models comfyui --manifest flux.json --jobs 4
+  portablesource download-models comfyui                              # again: resumes, skips verified files";
+
+const SHARE_MODELS_EXAMPLES: &str = "\
+Examples:
+  portablesource share-models                                          # every installed repository
+  portablesource share-models comfyui stable-diffusion-webui           # checkpoints, loras, ... shared by both
+  portablesource delete-repo comfyui                                   # removes the links, shared files stay";
+
+const PLUGINS_EXAMPLES: &str = "\
+Examples:
+  portablesource plugins                                               # name, hooks and repositories of each plugin
+  portablesource install-repo kohya_ss                                 # resolved and installed with its plugin";
+
+const BACKUP_EXAMPLES: &str = "\
+Examples:
+  portablesource backup create ps-backup.tar.zst                       # repositories and configuration
+  portablesource backup create ps-backup.tar.zst --with-envs --exclude 'custom_nodes/*/models'
+  portablesource --install-path D:\\ps backup restore ps-backup.tar.zst    # on the new machine, then setup-env";
+
+const TEST_REPO_EXAMPLES: &str = "\
+Examples:
+  portablesource test-repo comfyui                                     # torch imports, CUDA works
+  portablesource benchmark comfyui --iterations 500                    # fp16 TFLOPS of the GPU
+  portablesource test-repo comfyui     # on failure: \"GPU busy: 96% utilization, ... held by chrome.exe\"";
+
+const DOCTOR_EXAMPLES: &str = "\
+Examples:
+  portablesource install-repo comfyui
+  portablesource doctor --timings                                      # where the install spent its time
+  portablesource --trace install-repo comfyui                          # timings live, as operations finish";
+
+const UPDATE_REPO_EXAMPLES: &str = "\
+Examples:
+  portablesource update-repo comfyui
+  portablesource update-repo --all
+  portablesource update-repo comfyui --engine uv
+  portablesource update-repo --all --dry-run                          # what would be reset and rebuilt
+  portablesource update-repo --all --on-error skip-optional           # keep going when Triton fails to build
+  portablesource update-repo --all --error-format json                # failed repositories as JSON, exit code 3 if some updated";
+
+const RUN_REPO_EXAMPLES: &str = "\
+Examples:
+  portablesource run-repo comfyui
+  portablesource run-repo comfyui --listen 0.0.0.0 --port 8188         # arguments go to the repository
+  portablesource run-repo --wait-gpu --min-free-vram 8000 comfyui     # run-repo options before the name
+  portablesource run-repo --no-network comfyui -- --help              # optional '--' is dropped, --help goes to the repo
+  portablesource run-repo --gpu 0:1 comfyui                           # MIG instance 1 of GPU 0 (see check-gpu --all)";
+
+const GPU_QUEUE_EXAMPLES: &str = "\
+Examples:
+  portablesource gpu-queue --enable --min-free-vram 6000               # global default
+  portablesource gpu-queue comfyui --timeout 0                         # wait forever for this repo
+  portablesource gpu-queue comfyui --reset";
+
+const TUNE_REPO_EXAMPLES: &str = "\
+Examples:
+  portablesource tune-repo comfyui                                     # show the current profile
+  portablesource tune-repo comfyui --profile low-vram                  # 8 GB cards, large models
+  portablesource tune-repo comfyui --reset";
+
+const SWITCH_BACKEND_EXAMPLES: &str = "\
+Examples:
+  portablesource switch-backend comfyui cpu                            # run without a GPU, e.g. on a laptop
+  portablesource switch-backend comfyui cuda                           # back to the CUDA builds";
+
+const CACHE_GC_EXAMPLES: &str = "\
+Examples:
+  portablesource cache gc --dry-run                                    # reclaimable space, nothing removed
+  portablesource cache gc --min-age-days 0                             # also packages prefetched recently";
+
+const ENV_SHOW_EXAMPLES: &str = "\
+Examples:
+  portablesource env show comfyui                                      # compare with your terminal's env
+  portablesource env show comfyui | grep -i cuda";
+
+const COMPONENTS_EXAMPLES: &str = "\
+Examples:
+  portablesource components                                            # list known components
+  portablesource components facefusion --add cudnn --add zlib          # download and put on PATH
+  portablesource components facefusion --remove zlib";
+
+const VERIFY_REPO_EXAMPLES: &str = "\
+Examples:
+  portablesource verify-repo facefusion                                # e.g. onnxruntime-gpu needs libcudnn 9, provisioned 8.9
+  portablesource verify-repo comfyui --verbose                         # CUDA libraries of every package";
+
+const WHICH_EXAMPLES: &str = "\
+Examples:
+  portablesource which comfyui python                                  # interpreter run-repo starts
+  portablesource which facefusion ffmpeg --all                         # every candidate, first one wins";
+
+const STATS_EXAMPLES: &str = "\
+Examples:
+  portablesource stats --enable                                        # record every run-repo from now on
+  portablesource stats comfyui                                         # aggregates, grouped by run arguments
+  portablesource stats comfyui --clear";
+
+const INIT_EXAMPLES: &str = "\
+Examples:
+  portablesource init                                                  # .portablesource/ in this project
+  portablesource setup-env                                             # tools into the workspace
+  portablesource install-repo comfyui                                  # from any folder of the project";
+
+const UPGRADE_TOOL_EXAMPLES: &str = "\
+Examples:
+  portablesource upgrade-tool python                                   # stage, verify, switch
+  portablesource upgrade-tool cuda --rollback                          # back to the previous toolkit";
+
+const REGEN_SCRIPTS_EXAMPLES: &str = "\
+Examples:
+  portablesource regen-scripts --dry-run                               # which scripts the new templates change
+  portablesource regen-scripts                                         # all repositories; edited scripts are kept
+  portablesource regen-scripts --repo comfyui --force                  # replace an edited script (backup kept)";
+
+const IMPORT_LEGACY_EXAMPLES: &str = "\
+Examples:
+  portablesource import-legacy --dry-run                               # old install in the current install path
+  portablesource import-legacy D:\\portablesource_old                   # move repositories over from another folder";
+
+const REPORT_USAGE_EXAMPLES: &str = "\
+Examples:
+  portablesource report-usage                                          # last 30 days as a table
+  portablesource report-usage --since 2w --format json
+  portablesource report-usage --since 90d --format csv -o usage.csv";
+
+const CI_MANIFEST_EXAMPLES: &str = "\
+Examples:
+  portablesource ci-manifest comfyui                                   # files in the current directory
+  portablesource ci-manifest comfyui --output ../ComfyUI              # straight into a checkout of the project";
+
+const BOOTSTRAP_EXAMPLES: &str = "\
+Examples:
+  portablesource bootstrap --output setup.sh
+  portablesource bootstrap --output setup.ps1 --repo comfyui --repo https://github.com/user/repo";
+
+const INVENTORY_EXAMPLES: &str = "\
+Examples:
+  portablesource inventory                                             # summary
+  portablesource inventory --json > %COMPUTERNAME%.json                # for fleet collection";
+
+/// Printed by `portablesource examples`
+pub const EXAMPLES: &str = "\
+Common workflows
+
+First setup
+  portablesource setup-env                      # portable Python, git, ffmpeg and CUDA
+  portablesource setup-env --use-system-tools   # Windows: keep the installed Python and git
+  portablesource quickstart comfyui             # demo machine: set up, install and launch, no questions
+  portablesource check-env                      # verify the tools
+
+Install a repository
+  portablesource install-repo comfyui           # name known to the PortableSource server
+  portablesource install-repo https://github.com/comfyanonymous/ComfyUI
+  portablesource install-repo comfyui --as comfyui-video   # several instances side by side
+  portablesource prefetch comfyui facefusion    # download packages now, install later offline
+
+Run with arguments
+  portablesource run-repo comfyui --listen 0.0.0.0 --port 8188
+  Everything after the repository name goes to the repository, so put run-repo's own
+  options first:
+  portablesource run-repo --wait-gpu --no-network comfyui --port 8188
+
+Keep things up to date
+  portablesource update-repo comfyui
+  portablesource update-repo --all
+  portablesource schedule enable --weekly \"update-repo comfyui; check-env\"
+
+Inspect and clean up
+  portablesource list-repos
+  portablesource info-repo comfyui
+  portablesource stats comfyui                  # after 'stats --enable': runtimes and VRAM per argument set
+  portablesource delete-repo comfyui-video
+
+Troubleshoot
+  portablesource --log installer=debug install-repo comfyui   # verbose pip phase, quiet downloads
+
+Share your setup
+  portablesource bootstrap --output setup.sh    # or setup.ps1 on Windows
+";
+
 impl Cli {
     /// Parse command line arguments
     pub fn parse_args() -> Self {
@@ -112,4 +1048,94 @@ impl Cli {
     pub fn get_command(&self) -> &Commands {
         self.command.as_ref().unwrap_or(&Commands::SystemInfo)
     }
-}
\ No newline at end of file
+}
+
+impl Commands {
+    /// Whether the command may run on a read-only installation: it launches or inspects
+    /// repositories, writes only outside the installation, or is a dry run
+    pub fn allowed_read_only(&self) -> bool {
+        match self {
+            Commands::RunRepo { .. }
+            | Commands::ListRepos
+            | Commands::InfoRepo { .. }
+            | Commands::ReportUsage { .. }
+            | Commands::Plugins
+            | Commands::CiManifest { .. }
+            | Commands::Bootstrap { .. }
+            | Commands::SystemInfo
+            | Commands::CheckEnv
+            | Commands::TestRepo { .. }
+            | Commands::VerifyRepo { .. }
+            | Commands::Benchmark { .. }
+            | Commands::Doctor { .. }
+            | Commands::Env { .. }
+            | Commands::Examples
+            | Commands::Init { .. }
+            | Commands::Which { .. }
+            | Commands::CheckGpu { .. }
+            | Commands::Version { .. }
+            | Commands::Inventory { .. }
+            | Commands::Backup { action: BackupAction::Create { .. } }
+            | Commands::Schedule { action: ScheduleAction::Status }
+            | Commands::Config { action: ConfigAction::ReadOnly { .. } } => true,
+            Commands::Config { action: ConfigAction::BuildSlots { max } } => max.is_none(),
+            #[cfg(unix)]
+            Commands::Config { action: ConfigAction::InstallSandbox { policy } } => policy.is_none(),
+            #[cfg(windows)]
+            Commands::CheckMsvc => true,
+            #[cfg(unix)]
+            Commands::ExportEnv { output, .. } => output.is_some(),
+            #[cfg(unix)]
+            Commands::Uninstall { dry_run, .. } => *dry_run,
+            Commands::UpdateRepo { dry_run, .. }
+            | Commands::DeleteRepo { dry_run, .. }
+            | Commands::ImportLegacy { dry_run, .. }
+            | Commands::RenderScript { dry_run, .. }
+            | Commands::RegenScripts { dry_run, .. }
+            | Commands::Cache { action: CacheAction::Gc { dry_run, .. } }
+            | Commands::Config { action: ConfigAction::Migrate { dry_run, .. } } => *dry_run,
+            Commands::Stats { enable, disable, clear, .. } => !(*enable || *disable || *clear),
+            Commands::GpuQueue { enable, disable, min_free_vram, timeout, reset, .. } => {
+                !(*enable || *disable || *reset) && min_free_vram.is_none() && timeout.is_none()
+            }
+            Commands::TuneRepo { profile, reset, .. } => profile.is_none() && !*reset,
+            Commands::Components { add, remove, .. } => add.is_empty() && remove.is_empty(),
+            _ => false,
+        }
+    }
+}
+
+fn parse_log_spec(spec: &str) -> Result<LogSpec, String> {
+    LogSpec::parse(spec).map_err(|e| e.to_string())
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_repo_args(argv: &[&str]) -> (String, Vec<String>) {
+        match Cli::try_parse_from(argv).unwrap().command {
+            Some(Commands::RunRepo { repo, args, .. }) => (repo, args),
+            _ => panic!("expected run-repo"),
+        }
+    }
+
+    #[test]
+    fn run_repo_passes_arguments_through() {
+        let (repo, args) = run_repo_args(&["portablesource", "run-repo", "--no-network", "comfyui", "--port", "8188", "--help"]);
+        assert_eq!(repo, "comfyui");
+        assert_eq!(args, ["--port", "8188", "--help"]);
+        let (_, args) = run_repo_args(&["portablesource", "rr", "comfyui", "--", "--listen"]);
+        assert_eq!(args, ["--listen"]);
+    }
+
+    #[test]
+    fn only_launching_inspecting_and_dry_runs_pass_read_only_mode() {
+        let allowed = |argv: &[&str]| Cli::try_parse_from(argv).unwrap().command.unwrap().allowed_read_only();
+        assert!(allowed(&["portablesource", "--read-only", "run-repo", "comfyui", "--listen"]));
+        assert!(allowed(&["portablesource", "update-repo", "comfyui", "--dry-run"]));
+        assert!(allowed(&["portablesource", "tune-repo", "comfyui"]));
+        assert!(!allowed(&["portablesource", "tune-repo", "comfyui", "--reset"]));
+        assert!(!allowed(&["portablesource", "install-repo", "comfyui"]));
+        assert!(!allowed(&["portablesource", "cache", "gc"]));
+    }
+}
@@ -0,0 +1,179 @@
+//! Auxiliary native libraries for Windows repositories
+//!
+//! Some repositories load DLLs the portable CUDA archive does not ship: cuDNN for
+//! onnxruntime-gpu, zlibwapi.dll for older cuDNN builds, the MSVC runtime for wheels built
+//! against a newer one than the system has. A repository declares the components it needs
+//! in its run settings (`components` command); they are unpacked once into
+//! `ps_env/components/<name>` and their DLL folder goes on the PATH of the start script.
+//!
+//! Every archive is published with a `<archive>.sha256` file and is checked against it before
+//! unpacking. CUDA-specific components record the CUDA major version they were built for, so
+//! switching CUDA 11 and 12 downloads the matching build again.
+
+use crate::config::CudaVersion;
+use crate::{PortableSourceError, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const COMPONENTS_DIR: &str = "components";
+/// File in the component folder naming the CUDA major version of a CUDA-specific build
+const CUDA_MAJOR_FILE: &str = ".cuda_major";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Component {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Archive URL; `{cuda}` is replaced by the CUDA major version ("11" or "12")
+    url: &'static str,
+    /// Folder with the DLLs, relative to the component folder ("" for its root)
+    pub dll_dir: &'static str,
+}
+
+pub const REGISTRY: &[Component] = &[
+    Component {
+        name: "cudnn",
+        description: "cuDNN 9 for the installed CUDA (onnxruntime-gpu, TensorRT)",
+        url: "https://files.portables.dev/components/cudnn_cu{cuda}.tar.zst",
+        dll_dir: "bin",
+    },
+    Component {
+        name: "zlib",
+        description: "zlibwapi.dll needed by cuDNN 8 builds",
+        url: "https://files.portables.dev/components/zlib.tar.zst",
+        dll_dir: "",
+    },
+    Component {
+        name: "msvc",
+        description: "Visual C++ 2015-2022 runtime DLLs (x64)",
+        url: "https://files.portables.dev/components/msvc_runtime.tar.zst",
+        dll_dir: "",
+    },
+];
+
+impl Component {
+    /// Download URL for the CUDA build in use; CUDA 12 when none is configured
+    pub fn url(&self, cuda: Option<&CudaVersion>) -> String {
+        self.url.replace("{cuda}", cuda_major(cuda))
+    }
+
+    /// URL of the file holding the sha256 of the archive
+    pub fn checksum_url(&self, cuda: Option<&CudaVersion>) -> String {
+        format!("{}.sha256", self.url(cuda))
+    }
+
+    /// There is one build per CUDA major version
+    pub fn is_cuda_specific(&self) -> bool {
+        self.url.contains("{cuda}")
+    }
+
+    pub fn dir(&self, install_path: &Path) -> PathBuf {
+        install_path.join("ps_env").join(COMPONENTS_DIR).join(self.name)
+    }
+
+    /// Folder that goes on PATH
+    pub fn dll_path(&self, install_path: &Path) -> PathBuf {
+        match self.dll_dir {
+            "" => self.dir(install_path),
+            dir => self.dir(install_path).join(dir),
+        }
+    }
+
+    /// Unpacked, and for a CUDA-specific component built for the CUDA major version in use
+    pub fn is_installed(&self, install_path: &Path, cuda: Option<&CudaVersion>) -> bool {
+        if !self.dll_path(install_path).is_dir() {
+            return false;
+        }
+        !self.is_cuda_specific()
+            || fs::read_to_string(self.dir(install_path).join(CUDA_MAJOR_FILE)).is_ok_and(|major| major.trim() == cuda_major(cuda))
+    }
+
+    /// Record the CUDA major version a freshly unpacked CUDA-specific component was built for
+    pub fn mark_installed(&self, install_path: &Path, cuda: Option<&CudaVersion>) -> Result<()> {
+        if self.is_cuda_specific() {
+            fs::write(self.dir(install_path).join(CUDA_MAJOR_FILE), cuda_major(cuda))?;
+        }
+        Ok(())
+    }
+
+    /// PATH entry of the Windows start script, relative to `%env_path%` so it also works
+    /// on the X: drive
+    pub fn script_path(&self) -> String {
+        match self.dll_dir {
+            "" => format!("%env_path%\\{}\\{}", COMPONENTS_DIR, self.name),
+            dir => format!("%env_path%\\{}\\{}\\{}", COMPONENTS_DIR, self.name, dir.replace('/', "\\")),
+        }
+    }
+}
+
+fn cuda_major(cuda: Option<&CudaVersion>) -> &'static str {
+    match cuda {
+        Some(CudaVersion::Cuda118) => "11",
+        Some(CudaVersion::Cuda124 | CudaVersion::Cuda128) | None => "12",
+    }
+}
+
+/// The sha256 in the content of a `.sha256` file (`<hex>` or `<hex>  <file name>`)
+pub fn parse_checksum(text: &str) -> Option<String> {
+    let hash = text.split_whitespace().next()?;
+    (hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit())).then(|| hash.to_ascii_lowercase())
+}
+
+pub fn find(name: &str) -> Option<&'static Component> {
+    REGISTRY.iter().find(|c| c.name.eq_ignore_ascii_case(name.trim()))
+}
+
+/// Registry entries for `names`; unknown names are an error listing the known ones
+pub fn resolve(names: &[String]) -> Result<Vec<&'static Component>> {
+    names
+        .iter()
+        .map(|name| {
+            find(name).ok_or_else(|| {
+                let known: Vec<&str> = REGISTRY.iter().map(|c| c.name).collect();
+                PortableSourceError::config(format!("Unknown component '{}' (known: {})", name, known.join(", ")))
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn components_resolve_to_cuda_specific_urls_and_script_paths() {
+        let cudnn = find("cuDNN").unwrap();
+        assert_eq!(cudnn.url(Some(&CudaVersion::Cuda118)), "https://files.portables.dev/components/cudnn_cu11.tar.zst");
+        assert_eq!(cudnn.url(None), "https://files.portables.dev/components/cudnn_cu12.tar.zst");
+        assert_eq!(cudnn.script_path(), "%env_path%\\components\\cudnn\\bin");
+        assert_eq!(find("zlib").unwrap().script_path(), "%env_path%\\components\\zlib");
+
+        let install = Path::new("/opt/ps");
+        assert_eq!(cudnn.dll_path(install), install.join("ps_env/components/cudnn/bin"));
+        assert!(!cudnn.is_installed(install, None));
+
+        let err = resolve(&["zlib".into(), "cublas".into()]).unwrap_err();
+        assert!(err.to_string().contains("Unknown component 'cublas' (known: cudnn, zlib, msvc)"));
+    }
+
+    #[test]
+    fn a_cuda_specific_component_is_only_installed_for_the_cuda_major_it_was_built_for() {
+        let dir = tempfile::tempdir().unwrap();
+        let (cudnn, zlib) = (find("cudnn").unwrap(), find("zlib").unwrap());
+        fs::create_dir_all(cudnn.dll_path(dir.path())).unwrap();
+        fs::create_dir_all(zlib.dll_path(dir.path())).unwrap();
+        assert!(!cudnn.is_installed(dir.path(), Some(&CudaVersion::Cuda124)));
+
+        cudnn.mark_installed(dir.path(), Some(&CudaVersion::Cuda118)).unwrap();
+        assert!(cudnn.is_installed(dir.path(), Some(&CudaVersion::Cuda118)));
+        assert!(!cudnn.is_installed(dir.path(), Some(&CudaVersion::Cuda128)));
+        assert!(zlib.is_installed(dir.path(), Some(&CudaVersion::Cuda128)));
+    }
+
+    #[test]
+    fn checksum_files_hold_a_sha256_and_maybe_a_file_name() {
+        let hash = "AB".repeat(32);
+        assert_eq!(parse_checksum(&format!("{}  cudnn_cu12.tar.zst\n", hash)), Some(hash.to_lowercase()));
+        assert_eq!(parse_checksum("<html>Not Found</html>"), None);
+        assert_eq!(find("msvc").unwrap().checksum_url(None), "https://files.portables.dev/components/msvc_runtime.tar.zst.sha256");
+    }
+}
@@ -2,10 +2,12 @@
 
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::path::{PathBuf};
+use std::path::{Path, PathBuf};
 use crate::{Result, PortableSourceError};
-use crate::gpu::{GpuDetector, GpuInfo};
-use log::{info, warn};
+use crate::gpu::{Backend, ComputeCapability, GpuDetector, GpuInfo};
+use crate::config_migration::{self, CURRENT_SCHEMA_VERSION};
+use crate::install_sandbox::SandboxPolicy;
+use tracing::{info, warn};
 
 // Constants
 pub const SERVER_DOMAIN: &str = "server.portables.dev";
@@ -84,23 +86,137 @@ impl ToolLinks {
 
 
 
+/// Package installer used for repository environments
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum InstallEngine {
+    /// Prefer uv, fall back to pip per step when uv fails
+    #[default]
+    Auto,
+    Uv,
+    Pip,
+}
+
+impl InstallEngine {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InstallEngine::Auto => "auto",
+            InstallEngine::Uv => "uv",
+            InstallEngine::Pip => "pip",
+        }
+    }
+}
+
+impl std::fmt::Display for InstallEngine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for InstallEngine {
+    type Err = PortableSourceError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "auto" => Ok(InstallEngine::Auto),
+            "uv" => Ok(InstallEngine::Uv),
+            "pip" => Ok(InstallEngine::Pip),
+            other => Err(PortableSourceError::config(format!("Unknown install engine '{}' (expected auto, uv or pip)", other))),
+        }
+    }
+}
+
 // GpuConfig removed - all GPU parameters are now computed dynamically
 
+/// Idle-GPU queue for run-repo: hold a launch until enough VRAM is free
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct GpuQueueConfig {
+    pub enabled: bool,
+    /// Launch once some visible GPU has at least this much free VRAM
+    pub min_free_vram_mb: u64,
+    /// Give up waiting after this many seconds (0 = wait forever)
+    pub timeout_secs: u64,
+    pub poll_interval_secs: u64,
+}
+
+impl Default for GpuQueueConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_free_vram_mb: 4096,
+            timeout_secs: 1800,
+            poll_interval_secs: 10,
+        }
+    }
+}
+
+/// System installs used in place of the portable python/git (`setup-env --use-system-tools`)
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct SystemTools {
+    pub python: Option<PathBuf>,
+    pub git: Option<PathBuf>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PortableSourceConfig {
     pub version: String,
+    /// Layout version of this file, see `config_migration`
+    #[serde(default)]
+    pub schema_version: u32,
     pub install_path: PathBuf,
     pub environment_vars: Option<HashMap<String, String>>,
     pub environment_setup_completed: bool,
+    #[serde(default)]
+    pub install_engine: InstallEngine,
+    #[serde(default)]
+    pub gpu_queue: GpuQueueConfig,
+    /// Record per-run statistics of run-repo into repo metadata
+    #[serde(default)]
+    pub collect_run_stats: bool,
+    /// Use git/ffmpeg/python from PATH when the portable ones are missing
+    #[serde(default)]
+    pub system_tool_fallback: bool,
+    /// Validated system python/git recorded by setup; their portable archives are not downloaded
+    #[serde(default)]
+    pub system_tools: SystemTools,
+    /// Per-subsystem log levels, same syntax as `--log` (e.g. "installer=debug,download=warn")
+    #[serde(default)]
+    pub log_levels: Option<String>,
+    /// CUDA compute capability to assume instead of the detected one (e.g. "12.0"); decides
+    /// whether nightly torch builds are installed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compute_capability: Option<String>,
+    /// Native package builds allowed at once on this machine, across installs; 0 = no limit
+    #[serde(default = "default_max_native_builds")]
+    pub max_native_builds: usize,
+    /// Run install hooks and source builds in a sandbox: off, prefer or require
+    #[serde(default)]
+    pub install_sandbox: SandboxPolicy,
+}
+
+fn default_max_native_builds() -> usize {
+    crate::build_slots::DEFAULT_MAX_NATIVE_BUILDS
 }
 
 impl Default for PortableSourceConfig {
     fn default() -> Self {
         Self {
             version: VERSION.to_string(),
+            schema_version: CURRENT_SCHEMA_VERSION,
             install_path: PathBuf::new(),
             environment_vars: None,
             environment_setup_completed: false,
+            install_engine: InstallEngine::default(),
+            gpu_queue: GpuQueueConfig::default(),
+            collect_run_stats: false,
+            system_tool_fallback: false,
+            system_tools: SystemTools::default(),
+            log_levels: None,
+            compute_capability: None,
+            max_native_builds: default_max_native_builds(),
+            install_sandbox: SandboxPolicy::default(),
         }
     }
 }
@@ -111,6 +227,12 @@ pub struct ConfigManager {
     config_path: PathBuf,
     gpu_patterns: HashMap<GpuGeneration, Vec<&'static str>>,
     cuda_mapping: HashMap<GpuGeneration, CudaVersion>,
+    /// Session-only: treat the machine as GPU-less (quickstart fallback)
+    cpu_only: bool,
+    /// Schema of the file when it was loaded, if it was migrated in memory
+    migrated_from: Option<u32>,
+    /// The installation is read-only: nothing is written, not even a pending migration
+    read_only: bool,
 }
 
 impl ConfigManager {
@@ -146,11 +268,12 @@ impl ConfigManager {
     
     /// Get recommended backend based on available hardware
     pub fn get_recommended_backend(&self) -> String {
-        if self.has_cuda() {
-            "cuda".to_string()
-        } else {
-            "cpu".to_string()
-        }
+        self.get_backend().as_str().to_string()
+    }
+
+    /// Compute backend of the detected GPU
+    pub fn get_backend(&self) -> Backend {
+        Backend::from_gpu(self.detect_gpu().as_ref())
     }
     
     /// Check if TensorRT is supported
@@ -192,18 +315,27 @@ impl ConfigManager {
         self.get_cuda_base_path().map(|base| base.join("include"))
     }
 
+    /// Config file used when no path is given
+    pub fn default_config_path() -> PathBuf {
+        // Prefer install path from registry if present
+        if let Ok(Some(p)) = crate::utils::load_install_path_from_registry() {
+            return p.join("portablesource_config.json");
+        }
+        dirs::config_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("portablesource")
+            .join("config.json")
+    }
+
+    /// `log_levels` of a config file, read from the raw JSON: logging is set up before the
+    /// command runs, and `config migrate --dry-run` must find the file as it was
+    pub fn log_levels_from_file(path: &Path) -> Option<String> {
+        let value: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(path).ok()?).ok()?;
+        value.get("log_levels")?.as_str().map(str::to_string)
+    }
+
     pub fn new(config_path: Option<PathBuf>) -> Result<Self> {
-        let default_path = || {
-            // Prefer install path from registry if present
-            if let Ok(Some(p)) = crate::utils::load_install_path_from_registry() {
-                return p.join("portablesource_config.json");
-            }
-            dirs::config_dir()
-                .unwrap_or_else(|| PathBuf::from("."))
-                .join("portablesource")
-                .join("config.json")
-        };
-        let config_path = config_path.unwrap_or_else(default_path);
+        let config_path = config_path.unwrap_or_else(Self::default_config_path);
         
         // Initialize GPU patterns
         let mut gpu_patterns = HashMap::new();
@@ -241,6 +373,9 @@ impl ConfigManager {
             config_path,
             gpu_patterns,
             cuda_mapping,
+            cpu_only: false,
+            migrated_from: None,
+            read_only: false,
         };
         
         // Try to load existing config
@@ -297,6 +432,20 @@ impl ConfigManager {
         self.cuda_mapping.get(generation).cloned()
     }
     
+    /// Compute capability of the NVIDIA GPU and where it came from: the `compute_capability`
+    /// setting, else the driver (queried once per process)
+    pub fn compute_capability(&self) -> Option<(ComputeCapability, &'static str)> {
+        static DETECTED: std::sync::OnceLock<Option<ComputeCapability>> = std::sync::OnceLock::new();
+        if let Some(text) = &self.config.compute_capability {
+            match text.parse() {
+                Ok(cc) => return Some((cc, "config")),
+                Err(e) => warn!("Ignoring compute_capability setting: {}", e),
+            }
+        }
+        let detected = *DETECTED.get_or_init(|| GpuDetector::new().query_compute_capability());
+        detected.map(|cc| (cc, "driver"))
+    }
+
     pub fn get_gpu_name(&self) -> String {
         if let Some(gpu_info) = self.detect_gpu() {
             gpu_info.name
@@ -305,13 +454,21 @@ impl ConfigManager {
         }
     }
     
+    /// Install CPU packages for the rest of this session, whatever GPU is present
+    pub fn set_cpu_only(&mut self) {
+        self.cpu_only = true;
+    }
+
+    pub fn cpu_only(&self) -> bool {
+        self.cpu_only
+    }
+
     pub fn detect_gpu(&self) -> Option<GpuInfo> {
-        let detector = GpuDetector::new();
-        if let Ok(gpu_info) = detector.get_best_gpu() {
-            gpu_info
-        } else {
-            None
+        if self.cpu_only {
+            return None;
         }
+        let detector = GpuDetector::new();
+        detector.get_best_gpu().unwrap_or_default()
     }
     
 
@@ -328,7 +485,9 @@ impl ConfigManager {
         let python_exe = if cfg!(windows) { ps_env.join("python").join("python.exe") } else { ps_env.join("python").join("bin").join("python") };
         let git_exe = if cfg!(windows) { ps_env.join("git").join("cmd").join("git.exe") } else { ps_env.join("git").join("bin").join("git") };
         let ffmpeg_exe = if cfg!(windows) { ps_env.join("ffmpeg").join("ffmpeg.exe") } else { ps_env.join("ffmpeg").join("ffmpeg") };
-        if python_exe.exists() && git_exe.exists() && ffmpeg_exe.exists() {
+        let python_ok = python_exe.exists() || self.system_tool("python").is_some();
+        let git_ok = git_exe.exists() || self.system_tool("git").is_some();
+        if python_ok && git_ok && ffmpeg_exe.exists() {
             self.config.environment_setup_completed = true;
         }
 
@@ -441,6 +600,28 @@ impl ConfigManager {
         }
     }
     
+    /// Global install engine; PORTABLESOURCE_ENGINE overrides the config value
+    pub fn get_install_engine(&self) -> InstallEngine {
+        if let Ok(value) = std::env::var("PORTABLESOURCE_ENGINE") {
+            match value.parse() {
+                Ok(engine) => return engine,
+                Err(e) => warn!("Ignoring PORTABLESOURCE_ENGINE: {}", e),
+            }
+        }
+        self.config.install_engine
+    }
+    
+    /// Recorded system executable for "python" or "git", if it still exists
+    pub fn system_tool(&self, key: &str) -> Option<&Path> {
+        let tools = &self.config.system_tools;
+        let path = match key {
+            "python" => tools.python.as_deref(),
+            "git" => tools.git.as_deref(),
+            _ => None,
+        }?;
+        path.exists().then_some(path)
+    }
+
     pub fn is_environment_setup_completed(&self) -> bool {
         self.config.environment_setup_completed
     }
@@ -451,19 +632,37 @@ impl ConfigManager {
         Ok(())
     }
     
+    /// Refuse every save from now on (read-only installation)
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
     pub fn save_config(&self) -> Result<()> {
+        if self.read_only {
+            return Err(PortableSourceError::config(format!("{:?} belongs to a read-only installation and is not changed", self.config_path)));
+        }
         // Ensure config directory exists
         if let Some(parent) = self.config_path.parent() {
             std::fs::create_dir_all(parent)?;
         }
         
+        // The first save after an in-memory migration keeps the original, as `config migrate` does
+        if let Some(from) = self.migrated_from {
+            let backup = config_migration::backup_path(&self.config_path, from);
+            if self.config_path.exists() && !backup.exists() {
+                std::fs::copy(&self.config_path, &backup)?;
+                info!("Config written at schema {}, original kept at {:?}", CURRENT_SCHEMA_VERSION, backup);
+            }
+        }
         let json = serde_json::to_string_pretty(&self.config)?;
-        std::fs::write(&self.config_path, json)?;
+        crate::atomic_write::write(&self.config_path, json)?;
         
         info!("Configuration saved to: {:?}", self.config_path);
         Ok(())
     }
     
+    /// Load the config file; an older schema is migrated in memory only and written on the
+    /// next save or by `config migrate`
     pub fn load_config(&mut self) -> Result<()> {
         if !self.config_path.exists() {
             info!("No configuration file found, creating default configuration");
@@ -471,7 +670,16 @@ impl ConfigManager {
         }
         
         let content = std::fs::read_to_string(&self.config_path)?;
-        self.config = serde_json::from_str(&content)?;
+        let mut value: serde_json::Value = serde_json::from_str(&content)?;
+        let schema = config_migration::schema_version(&value);
+        if schema < CURRENT_SCHEMA_VERSION {
+            config_migration::migrate_value(&mut value)?;
+            self.migrated_from = Some(schema);
+            info!("Configuration is at schema {}, read as {}; run 'portablesource config migrate' to update the file", schema, CURRENT_SCHEMA_VERSION);
+        } else if schema > CURRENT_SCHEMA_VERSION {
+            warn!("Config schema {} is newer than supported ({}), unknown fields are ignored", schema, CURRENT_SCHEMA_VERSION);
+        }
+        self.config = serde_json::from_value(value)?;
         
         info!("Configuration loaded from: {:?}", self.config_path);
         Ok(())
@@ -481,6 +689,7 @@ impl ConfigManager {
 
 // Detect CUDA version by parsing `nvcc --version` output (Linux)
 #[cfg(unix)]
+#[allow(dead_code)]
 fn detect_cuda_version_from_nvcc() -> Option<CudaVersion> {
     let output = std::process::Command::new("nvcc")
         .arg("--version")
@@ -497,7 +706,7 @@ fn detect_cuda_version_from_nvcc() -> Option<CudaVersion> {
                 let rest = &l[pos + "release".len()..];
                 let rest = rest.trim().trim_start_matches(':').trim_start_matches(',').trim();
                 // rest starts like "12.4, v12.4.131"
-                let ver = rest.split(|c| c == ',' || c == ' ').next().unwrap_or("");
+                let ver = rest.split([',', ' ']).next().unwrap_or("");
                 if ver.starts_with("12.8") { return Some(CudaVersion::Cuda128); }
                 if ver.starts_with("12.4") { return Some(CudaVersion::Cuda124); }
                 if ver.starts_with("11.8") { return Some(CudaVersion::Cuda118); }
@@ -509,6 +718,7 @@ fn detect_cuda_version_from_nvcc() -> Option<CudaVersion> {
 
 // Fallback: detect CUDA Toolkit installed on filesystem
 #[cfg(unix)]
+#[allow(dead_code)]
 fn detect_cuda_version_from_filesystem() -> Option<CudaVersion> {
     use std::fs;
     use std::path::Path;
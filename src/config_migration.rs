@@ -0,0 +1,168 @@
+//! Versioned migrations of `portablesource_config.json`
+//!
+//! The config carries a `schema_version`; files without one are legacy (Python version or
+//! Rust builds before migrations) and count as schema 0. Each step transforms the raw JSON
+//! from version N to N+1, so old files load instead of failing or losing fields. Loading
+//! migrates in memory only; the file is rewritten by `config migrate` or the next save, and
+//! the original is kept as `<config>.v<N>.bak` before the migrated one is written.
+
+use crate::{PortableSourceError, Result};
+use tracing::info;
+use serde_json::{Map, Value};
+use std::path::{Path, PathBuf};
+
+pub const CURRENT_SCHEMA_VERSION: u32 = 1;
+
+type MigrationFn = fn(&mut Map<String, Value>);
+
+/// Step `i` migrates schema `i` to `i + 1`
+const MIGRATIONS: &[(&str, MigrationFn)] = &[
+    ("fill required fields of legacy config, drop removed gpu_config", v0_to_v1),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigrationReport {
+    pub from: u32,
+    pub to: u32,
+    /// Descriptions of the applied steps
+    pub steps: Vec<&'static str>,
+    /// Copy of the original file, if one was written
+    pub backup: Option<PathBuf>,
+}
+
+impl MigrationReport {
+    pub fn is_noop(&self) -> bool {
+        self.steps.is_empty()
+    }
+}
+
+pub fn schema_version(value: &Value) -> u32 {
+    value.get("schema_version").and_then(|v| v.as_u64()).unwrap_or(0) as u32
+}
+
+/// Apply all pending steps to a parsed config; returns the descriptions of applied steps
+pub fn migrate_value(value: &mut Value) -> Result<Vec<&'static str>> {
+    let from = schema_version(value);
+    if from > CURRENT_SCHEMA_VERSION {
+        return Err(PortableSourceError::config(format!(
+            "Config schema {} is newer than this build supports ({}); update portablesource",
+            from, CURRENT_SCHEMA_VERSION
+        )));
+    }
+    let obj = value
+        .as_object_mut()
+        .ok_or_else(|| PortableSourceError::config("Config file is not a JSON object"))?;
+
+    let mut applied = Vec::new();
+    for (description, step) in &MIGRATIONS[from as usize..] {
+        step(obj);
+        applied.push(*description);
+    }
+    obj.insert("schema_version".to_string(), Value::from(CURRENT_SCHEMA_VERSION));
+    Ok(applied)
+}
+
+pub fn backup_path(config_path: &Path, from: u32) -> PathBuf {
+    let mut name = config_path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".v{}.bak", from));
+    config_path.with_file_name(name)
+}
+
+/// Migrate the config file in place (backing up the original); with `dry_run` nothing is written.
+/// Returns the migrated JSON and what was done.
+pub fn migrate_file(config_path: &Path, dry_run: bool) -> Result<(Value, MigrationReport)> {
+    let content = std::fs::read_to_string(config_path)?;
+    let mut value: Value = serde_json::from_str(&content)?;
+    let from = schema_version(&value);
+    let steps = migrate_value(&mut value)?;
+    let mut report = MigrationReport { from, to: CURRENT_SCHEMA_VERSION, steps, backup: None };
+
+    if !dry_run && !report.is_noop() {
+        let backup = backup_path(config_path, from);
+        std::fs::write(&backup, &content)?;
+        crate::atomic_write::write(config_path, serde_json::to_string_pretty(&value)?)?;
+        info!("Config migrated from schema {} to {}, original kept at {:?}", from, report.to, backup);
+        report.backup = Some(backup);
+    }
+    Ok((value, report))
+}
+
+fn v0_to_v1(obj: &mut Map<String, Value>) {
+    // GPU parameters are computed dynamically now
+    obj.remove("gpu_config");
+
+    obj.entry("version").or_insert_with(|| Value::from(crate::config::VERSION));
+    if !obj.get("install_path").is_some_and(|v| v.is_string()) {
+        obj.insert("install_path".to_string(), Value::from(""));
+    }
+    if !obj.get("environment_setup_completed").is_some_and(|v| v.is_boolean()) {
+        obj.insert("environment_setup_completed".to_string(), Value::Bool(false));
+    }
+    if !obj.get("environment_vars").is_some_and(|v| v.is_object()) {
+        obj.insert("environment_vars".to_string(), Value::Null);
+    }
+
+    // Engine names were written in any case; unknown ones fall back to the default
+    let engine = obj.get("install_engine").and_then(|v| v.as_str()).map(|s| s.trim().to_lowercase());
+    match engine.as_deref() {
+        Some(e @ ("auto" | "uv" | "pip")) => {
+            obj.insert("install_engine".to_string(), Value::from(e));
+        }
+        _ => {
+            obj.remove("install_engine");
+        }
+    }
+    if obj.get("gpu_queue").is_some_and(|v| !v.is_object()) {
+        obj.remove("gpu_queue");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PortableSourceConfig;
+
+    #[test]
+    fn legacy_config_migrates_with_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("portablesource_config.json");
+        let legacy = r#"{"version": "0.9", "install_path": "C:\\portablesource", "gpu_config": {"name": "RTX 3090"}, "install_engine": "UV"}"#;
+        std::fs::write(&path, legacy).unwrap();
+
+        let (value, report) = migrate_file(&path, false).unwrap();
+        assert_eq!((report.from, report.to, report.steps.len()), (0, CURRENT_SCHEMA_VERSION, 1));
+        assert_eq!(std::fs::read_to_string(report.backup.unwrap()).unwrap(), legacy);
+
+        let config: PortableSourceConfig = serde_json::from_value(value).unwrap();
+        assert_eq!(config.schema_version, CURRENT_SCHEMA_VERSION);
+        assert_eq!(config.install_engine, crate::config::InstallEngine::Uv);
+        assert!(!config.environment_setup_completed);
+
+        let (_, again) = migrate_file(&path, false).unwrap();
+        assert!(again.is_noop());
+    }
+
+    #[test]
+    fn loading_migrates_in_memory_and_saving_keeps_the_original() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("portablesource_config.json");
+        let legacy = r#"{"version": "0.9", "install_path": "", "gpu_config": {"name": "RTX 3090"}}"#;
+        std::fs::write(&path, legacy).unwrap();
+
+        let manager = crate::config::ConfigManager::new(Some(path.clone())).unwrap();
+        assert_eq!(manager.get_config().schema_version, CURRENT_SCHEMA_VERSION);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), legacy);
+        assert!(!backup_path(&path, 0).exists());
+
+        manager.save_config().unwrap();
+        assert_eq!(std::fs::read_to_string(backup_path(&path, 0)).unwrap(), legacy);
+        let saved: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(schema_version(&saved), CURRENT_SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn newer_schema_is_rejected() {
+        let mut value = serde_json::json!({ "schema_version": CURRENT_SCHEMA_VERSION + 1 });
+        assert!(migrate_value(&mut value).is_err());
+    }
+}
@@ -0,0 +1,160 @@
+//! Free-space checks during long operations
+//!
+//! Extracting tools and installing packages can fill the disk minutes into the work, and
+//! the resulting IO error says nothing useful. [`SpaceCheck`] is polled while writing
+//! (at most once per [`CHECK_INTERVAL`]) and aborts early with a specific
+//! `DiskFull` error ("need ~4.2 GB more on D:"); the caller then removes what the aborted
+//! operation created.
+
+use crate::{PortableSourceError, Result};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Abort once free space drops below this, before the OS starts failing writes
+pub const MIN_FREE_BYTES: u64 = 512 * 1024 * 1024;
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Nearest existing ancestor (the target of an install may not exist yet)
+fn existing_ancestor(path: &Path) -> Option<&Path> {
+    path.ancestors().find(|p| p.exists())
+}
+
+/// Bytes available to the current user on the volume holding `path`
+#[cfg(unix)]
+pub fn free_bytes(path: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+    let dir = existing_ancestor(path)?;
+    let c_path = std::ffi::CString::new(dir.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// Bytes available to the current user on the volume holding `path`
+#[cfg(windows)]
+pub fn free_bytes(path: &Path) -> Option<u64> {
+    use windows::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+    let dir = existing_ancestor(path)?;
+    let wide = windows::core::HSTRING::from(dir);
+    let mut free = 0u64;
+    unsafe { GetDiskFreeSpaceExW(&wide, Some(&mut free), None, None) }.ok()?;
+    Some(free)
+}
+
+/// Name of the volume for messages: the drive on Windows ("D:"), the path elsewhere
+pub fn volume_name(path: &Path) -> String {
+    match path.components().next() {
+        Some(std::path::Component::Prefix(prefix)) => prefix.as_os_str().to_string_lossy().to_string(),
+        _ => existing_ancestor(path).unwrap_or(path).display().to_string(),
+    }
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const MB: f64 = 1024.0 * 1024.0;
+    if bytes as f64 >= 1024.0 * MB {
+        format!("{:.1} GB", bytes as f64 / (1024.0 * MB))
+    } else {
+        format!("{:.0} MB", bytes as f64 / MB)
+    }
+}
+
+/// `DiskFull` error for `missing` bytes on the volume of `path`
+pub fn low_space_error(path: &Path, missing: u64, free: u64) -> PortableSourceError {
+    PortableSourceError::disk_full(format!(
+        "need ~{} more on {} ({} free)",
+        format_size(missing),
+        volume_name(path),
+        format_size(free)
+    ))
+}
+
+/// Throttled free-space watch for one operation writing under `path`
+#[derive(Debug)]
+pub struct SpaceCheck {
+    path: PathBuf,
+    min_free: u64,
+    last: Option<Instant>,
+}
+
+impl SpaceCheck {
+    pub fn new(path: &Path) -> Self {
+        Self { path: path.to_path_buf(), min_free: MIN_FREE_BYTES, last: None }
+    }
+
+    pub fn with_min_free(mut self, bytes: u64) -> Self {
+        self.min_free = bytes;
+        self
+    }
+
+    /// Check now if the interval has passed; `remaining` is the estimated bytes still to write
+    pub fn check(&mut self, remaining: Option<u64>) -> Result<()> {
+        if self.last.is_some_and(|t| t.elapsed() < CHECK_INTERVAL) {
+            return Ok(());
+        }
+        self.last = Some(Instant::now());
+        self.check_now(remaining)
+    }
+
+    pub fn check_now(&self, remaining: Option<u64>) -> Result<()> {
+        let Some(free) = free_bytes(&self.path) else { return Ok(()) };
+        let needed = remaining.unwrap_or(0) + self.min_free;
+        // Without an estimate only the reserve is enforced
+        if free < self.min_free || (remaining.is_some() && free < needed) {
+            return Err(low_space_error(&self.path, needed.saturating_sub(free), free));
+        }
+        Ok(())
+    }
+}
+
+/// Entries of `dir` that exist right now, to tell later which ones an operation created
+pub fn snapshot(dir: &Path) -> Vec<PathBuf> {
+    std::fs::read_dir(dir).into_iter().flatten().flatten().map(|e| e.path()).collect()
+}
+
+/// Remove entries of `dir` that are not in `before` (and, with `since`, were modified after it)
+pub fn remove_new_entries(dir: &Path, before: &[PathBuf], since: Option<SystemTime>) -> usize {
+    let mut removed = 0;
+    for path in snapshot(dir) {
+        if before.contains(&path) {
+            continue;
+        }
+        // Shared folders (the system temp dir) also receive files from other programs
+        let recent = since.is_none_or(|since| std::fs::symlink_metadata(&path).and_then(|m| m.modified()).is_ok_and(|m| m >= since));
+        if !recent {
+            continue;
+        }
+        let result = if path.is_dir() { std::fs::remove_dir_all(&path) } else { std::fs::remove_file(&path) };
+        if result.is_ok() {
+            removed += 1;
+        }
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_reserve_and_estimate_and_cleans_new_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let free = free_bytes(&dir.path().join("not/yet/created")).unwrap();
+        assert!(free > 0);
+
+        let check = SpaceCheck::new(dir.path()).with_min_free(0);
+        assert!(check.check_now(Some(1)).is_ok());
+        let err = check.check_now(Some(free + 4 * 1024 * 1024 * 1024)).unwrap_err();
+        assert!(matches!(err, PortableSourceError::DiskFull { .. }));
+        assert!(err.to_string().contains("need ~4.0 GB more on"), "{}", err);
+        assert!(SpaceCheck::new(dir.path()).with_min_free(u64::MAX).check_now(None).is_err());
+
+        std::fs::write(dir.path().join("kept"), b"").unwrap();
+        let before = snapshot(dir.path());
+        let since = SystemTime::now() - Duration::from_secs(1);
+        std::fs::create_dir_all(dir.path().join("partial/bin")).unwrap();
+        assert_eq!(remove_new_entries(dir.path(), &before, Some(since)), 1);
+        assert!(dir.path().join("kept").exists() && !dir.path().join("partial").exists());
+    }
+}
@@ -0,0 +1,143 @@
+//! What setup-env is about to download
+//!
+//! Portable tools and CUDA add up to several gigabytes. Before anything is fetched,
+//! setup-env lists each download with its source, size (from a HEAD request) and
+//! destination, plus the total, so users on metered connections can back out.
+
+use crate::disk_space::format_size;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlannedDownload {
+    pub tool: String,
+    pub url: String,
+    pub destination: PathBuf,
+    /// Size reported by the server; None when it did not say
+    pub size: Option<u64>,
+    /// Bytes of an interrupted earlier download that will be resumed
+    pub partial: u64,
+}
+
+impl PlannedDownload {
+    pub fn new(tool: impl Into<String>, url: impl Into<String>, destination: PathBuf) -> Self {
+        let partial = std::fs::metadata(&destination).map(|m| m.len()).unwrap_or(0);
+        Self { tool: tool.into(), url: url.into(), destination, size: None, partial }
+    }
+
+    /// Bytes still to transfer, when the size is known
+    pub fn remaining(&self) -> Option<u64> {
+        self.size.map(|size| size.saturating_sub(self.partial))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DownloadPlan {
+    pub items: Vec<PlannedDownload>,
+    /// Downloads whose size is only known once they run (micromamba packages)
+    pub notes: Vec<String>,
+}
+
+impl DownloadPlan {
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty() && self.notes.is_empty()
+    }
+
+    /// Ask the servers for the size of every download, all at once
+    pub async fn measure_sizes(&mut self) {
+        let client = crate::system::http_client();
+        let sizes = futures_util::future::join_all(self.items.iter().map(|item| async move {
+            let response = client.head(&item.url).send().await.ok()?;
+            crate::system::head_content_length(&response)
+        }))
+        .await;
+        for (item, size) in self.items.iter_mut().zip(sizes) {
+            item.size = size;
+        }
+    }
+
+    /// Bytes still to transfer over the downloads of known size
+    pub fn total(&self) -> u64 {
+        self.items.iter().filter_map(PlannedDownload::remaining).sum()
+    }
+
+    /// Table of the downloads and the total line
+    pub fn render(&self) -> String {
+        let tool_width = self.items.iter().map(|i| i.tool.len()).max().unwrap_or(0).max(4);
+        let mut out = String::new();
+        for item in &self.items {
+            let size = match (item.remaining(), item.partial) {
+                (Some(remaining), 0) => format_size(remaining),
+                (Some(remaining), partial) => format!("{} ({} done)", format_size(remaining), format_size(partial)),
+                (None, _) => "unknown".to_string(),
+            };
+            out.push_str(&format!("  {:<tool_width$}  {:>10}  {}\n", item.tool, size, item.url));
+            out.push_str(&format!("  {:<tool_width$}  {:>10}  -> {}\n", "", "", item.destination.display()));
+        }
+        for note in &self.notes {
+            out.push_str(&format!("  {}\n", note));
+        }
+        let unknown = self.items.iter().filter(|i| i.size.is_none()).count();
+        out.push_str(&format!("Total: {}", format_size(self.total())));
+        if unknown > 0 {
+            out.push_str(&format!(" + {} download(s) of unknown size", unknown));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn total_counts_what_is_left_to_download() {
+        let dir = tempfile::tempdir().unwrap();
+        let partial = dir.path().join("CUDA_128.tar.zst");
+        std::fs::write(&partial, vec![0u8; 1024 * 1024]).unwrap();
+
+        let mut cuda = PlannedDownload::new("CUDA", "https://example.com/CUDA_128.tar.zst", partial);
+        cuda.size = Some(3 * 1024 * 1024 * 1024);
+        let mut python = PlannedDownload::new("python", "https://example.com/python.tar.zst", dir.path().join("python.tar.zst"));
+        python.size = Some(50 * 1024 * 1024);
+        let git = PlannedDownload::new("git", "https://example.com/git.tar.zst", dir.path().join("git.tar.zst"));
+        let plan = DownloadPlan { items: vec![cuda, python, git], notes: Vec::new() };
+
+        assert_eq!(plan.total(), 3 * 1024 * 1024 * 1024 - 1024 * 1024 + 50 * 1024 * 1024);
+        let table = plan.render();
+        assert!(table.contains("(1 MB done)"), "{}", table);
+        assert!(table.contains("unknown"));
+        assert!(table.ends_with("Total: 3.0 GB + 1 download(s) of unknown size"), "{}", table);
+    }
+
+    #[tokio::test]
+    async fn sizes_come_from_the_content_length_of_head_responses() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = [0u8; 1024];
+                let read = stream.read(&mut request).await.unwrap_or(0);
+                let response = if String::from_utf8_lossy(&request[..read]).starts_with("HEAD /missing") {
+                    "HTTP/1.1 404 Not Found\r\nContent-Length: 9\r\nConnection: close\r\n\r\n"
+                } else {
+                    "HTTP/1.1 200 OK\r\nContent-Length: 52428800\r\nConnection: close\r\n\r\n"
+                };
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut plan = DownloadPlan {
+            items: vec![
+                PlannedDownload::new("python", format!("http://{}/python.tar.zst", address), dir.path().join("python.tar.zst")),
+                PlannedDownload::new("git", format!("http://{}/missing", address), dir.path().join("git.tar.zst")),
+            ],
+            notes: Vec::new(),
+        };
+        plan.measure_sizes().await;
+        assert_eq!(plan.items[0].size, Some(50 * 1024 * 1024));
+        assert_eq!(plan.items[1].size, None);
+        assert_eq!(plan.total(), 50 * 1024 * 1024);
+    }
+}
@@ -0,0 +1,171 @@
+//! Sidecar state for resumable downloads
+//!
+//! A partial file `<name>` is accompanied by `<name>.download.json` describing what it is a
+//! prefix of. Before resuming, the sidecar is checked against the current URL and the
+//! server's ETag/size, so a partial left by a different URL or a changed file is discarded
+//! instead of being glued to the wrong bytes.
+
+use crate::Result;
+use tracing::debug;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+pub const STATE_SUFFIX: &str = ".download.json";
+/// Bytes at the start of a partial file covered by `prefix_sha256`
+pub const PREFIX_HASH_LEN: u64 = 1024 * 1024;
+/// Partials untouched for this long are removed by `clean_stale_partials`
+pub const STALE_PARTIAL_AGE_SECS: u64 = 7 * 24 * 60 * 60;
+
+/// What the server reports for a URL (from HEAD)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RemoteInfo {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub size: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct DownloadState {
+    pub url: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// Full size of the file being downloaded
+    pub expected_size: Option<u64>,
+    /// sha256 of the first `PREFIX_HASH_LEN` bytes, once that much has been written
+    pub prefix_sha256: Option<String>,
+    /// Unix time of the last write
+    pub updated_at: u64,
+}
+
+impl DownloadState {
+    pub fn new(url: &str, remote: &RemoteInfo, now: u64) -> Self {
+        Self {
+            url: url.to_string(),
+            etag: remote.etag.clone(),
+            last_modified: remote.last_modified.clone(),
+            expected_size: remote.size,
+            prefix_sha256: None,
+            updated_at: now,
+        }
+    }
+
+    pub fn path_for(destination: &Path) -> PathBuf {
+        let mut name = destination.file_name().unwrap_or_default().to_os_string();
+        name.push(STATE_SUFFIX);
+        destination.with_file_name(name)
+    }
+
+    pub fn load(destination: &Path) -> Option<Self> {
+        let content = fs::read_to_string(Self::path_for(destination)).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    pub fn save(&self, destination: &Path) -> Result<()> {
+        fs::write(Self::path_for(destination), serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn remove(destination: &Path) {
+        let _ = fs::remove_file(Self::path_for(destination));
+    }
+
+    /// Same URL and, where both sides know it, the same ETag / Last-Modified / size
+    pub fn matches(&self, url: &str, remote: &RemoteInfo) -> bool {
+        fn same<T: PartialEq>(a: &Option<T>, b: &Option<T>) -> bool {
+            match (a, b) {
+                (Some(a), Some(b)) => a == b,
+                _ => true,
+            }
+        }
+        self.url == url
+            && same(&self.etag, &remote.etag)
+            && same(&self.last_modified, &remote.last_modified)
+            && same(&self.expected_size, &remote.size)
+    }
+
+    /// Partial file on disk is still the prefix this state was written for
+    pub fn partial_is_intact(&self, destination: &Path) -> Result<bool> {
+        let len = fs::metadata(destination)?.len();
+        if self.expected_size.is_some_and(|size| len > size) {
+            return Ok(false);
+        }
+        match (&self.prefix_sha256, len >= PREFIX_HASH_LEN) {
+            (Some(expected), true) => Ok(prefix_sha256(destination)?.as_deref() == Some(expected.as_str())),
+            _ => Ok(true),
+        }
+    }
+}
+
+/// sha256 (hex) of the first `PREFIX_HASH_LEN` bytes; None if the file is shorter
+pub fn prefix_sha256(path: &Path) -> Result<Option<String>> {
+    let mut buf = Vec::with_capacity(PREFIX_HASH_LEN as usize);
+    fs::File::open(path)?.take(PREFIX_HASH_LEN).read_to_end(&mut buf)?;
+    if (buf.len() as u64) < PREFIX_HASH_LEN {
+        return Ok(None);
+    }
+    Ok(Some(format!("{:x}", Sha256::digest(&buf))))
+}
+
+/// Remove partial downloads in `dir` whose state is older than `max_age_secs`, and orphaned sidecars
+pub fn clean_stale_partials(dir: &Path, max_age_secs: u64, now: u64) -> usize {
+    let Ok(entries) = fs::read_dir(dir) else { return 0 };
+    let mut removed = 0;
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let Some(partial_name) = name.strip_suffix(STATE_SUFFIX) else { continue };
+        let partial = dir.join(partial_name);
+        let stale = match fs::read_to_string(entry.path()).ok().and_then(|c| serde_json::from_str::<DownloadState>(&c).ok()) {
+            Some(state) => !partial.exists() || now.saturating_sub(state.updated_at) > max_age_secs,
+            None => true,
+        };
+        if stale {
+            debug!("Removing stale partial download {:?}", partial);
+            let _ = fs::remove_file(&partial);
+            let _ = fs::remove_file(entry.path());
+            removed += 1;
+        }
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn remote(etag: &str, size: u64) -> RemoteInfo {
+        RemoteInfo { etag: Some(etag.into()), last_modified: None, size: Some(size) }
+    }
+
+    #[test]
+    fn state_rejects_changed_url_or_etag() {
+        let state = DownloadState::new("https://host/cuda_124.tar.zst", &remote("\"v1\"", 100), 0);
+        assert!(state.matches("https://host/cuda_124.tar.zst", &remote("\"v1\"", 100)));
+        assert!(state.matches("https://host/cuda_124.tar.zst", &RemoteInfo::default()));
+        assert!(!state.matches("https://host/cuda_128.tar.zst", &remote("\"v1\"", 100)));
+        assert!(!state.matches("https://host/cuda_124.tar.zst", &remote("\"v2\"", 100)));
+        assert!(!state.matches("https://host/cuda_124.tar.zst", &remote("\"v1\"", 200)));
+    }
+
+    #[test]
+    fn detects_replaced_partial_and_cleans_stale_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("python.tar.zst");
+        fs::write(&dest, vec![1u8; PREFIX_HASH_LEN as usize + 10]).unwrap();
+
+        let mut state = DownloadState::new("https://host/python.tar.zst", &RemoteInfo::default(), 1_000);
+        state.prefix_sha256 = prefix_sha256(&dest).unwrap();
+        state.save(&dest).unwrap();
+        assert!(DownloadState::load(&dest).unwrap().partial_is_intact(&dest).unwrap());
+
+        fs::write(&dest, vec![2u8; PREFIX_HASH_LEN as usize + 10]).unwrap();
+        assert!(!state.partial_is_intact(&dest).unwrap());
+
+        assert_eq!(clean_stale_partials(dir.path(), STALE_PARTIAL_AGE_SECS, 1_000 + 60), 0);
+        assert_eq!(clean_stale_partials(dir.path(), STALE_PARTIAL_AGE_SECS, 1_000 + STALE_PARTIAL_AGE_SECS + 1), 1);
+        assert!(!dest.exists());
+        assert!(!DownloadState::path_for(&dest).exists());
+    }
+}
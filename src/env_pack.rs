@@ -0,0 +1,303 @@
+//! Relocatable venv export/import (conda-pack / venv-pack style)
+//!
+//! `export-env` packs `envs/<repo>` into a `.tar.zst`. With `--relocatable` the archive
+//! carries a manifest listing every text file and symlink that mentions the original
+//! prefix; `import-env` unpacks it and rewrites those paths for the new location, so
+//! identical worker nodes can skip dependency installation entirely. Files are streamed and
+//! only whole path tokens are rewritten (see [`crate::path_rewrite`]).
+
+use crate::{PortableSourceError, Result};
+use crate::path_rewrite;
+use crate::utils::unix_timestamp;
+use tracing::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Manifest stored at the archive root
+pub const PACK_MANIFEST_FILE: &str = ".portablesource_pack.json";
+const PACK_FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PackManifest {
+    pub format_version: u32,
+    pub repo_name: String,
+    /// Absolute venv path at export time
+    pub prefix: String,
+    /// Install path at export time (base python and ps_env may live under it)
+    pub install_path: String,
+    pub relocatable: bool,
+    /// `os-arch` of the exporting machine, e.g. "linux-x86_64"
+    pub platform: String,
+    /// Text files (relative to the venv) containing the old paths
+    pub text_files: Vec<String>,
+    /// Symlinks (relative to the venv) whose target contains the old paths
+    pub symlinks: Vec<String>,
+    /// Binary files that mention the old prefix and cannot be rewritten
+    pub binary_files: Vec<String>,
+    pub created_at: u64,
+}
+
+fn current_platform() -> String {
+    format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH)
+}
+
+/// Old path and its replacement, the venv prefix first: it is the more specific path
+fn replacements(manifest: &PackManifest, new_prefix: &Path, new_install_path: &Path) -> [(String, String); 2] {
+    [
+        (manifest.prefix.clone(), new_prefix.to_string_lossy().to_string()),
+        (manifest.install_path.clone(), new_install_path.to_string_lossy().to_string()),
+    ]
+}
+
+/// Walk the venv and collect everything that references `needles`
+fn scan_prefix_references(venv_path: &Path, needles: &[&str]) -> Result<(Vec<String>, Vec<String>, Vec<String>)> {
+    let mut text_files = Vec::new();
+    let mut symlinks = Vec::new();
+    let mut binary_files = Vec::new();
+
+    for entry in WalkDir::new(venv_path).follow_links(false) {
+        let entry = entry.map_err(|e| PortableSourceError::environment(format!("Failed to scan venv: {}", e)))?;
+        let path = entry.path();
+        let rel = path.strip_prefix(venv_path).unwrap_or(path).to_string_lossy().to_string();
+        let file_type = entry.file_type();
+
+        if file_type.is_symlink() {
+            let target = fs::read_link(path)?.to_string_lossy().to_string();
+            if needles.iter().any(|n| path_rewrite::mentions_path(&target, n)) {
+                symlinks.push(rel);
+            }
+        } else if file_type.is_file() {
+            // Candidates only: import rewrites whole path tokens and leaves the rest alone
+            let mut mentions = false;
+            for needle in needles {
+                if path_rewrite::file_contains(path, needle.as_bytes())? {
+                    mentions = true;
+                    break;
+                }
+            }
+            if !mentions {
+                continue;
+            }
+            if path_rewrite::is_binary(path)? {
+                binary_files.push(rel);
+            } else {
+                text_files.push(rel);
+            }
+        }
+    }
+    Ok((text_files, symlinks, binary_files))
+}
+
+/// Pack `envs/<repo_name>` into a zstd tarball. Returns the archive path.
+pub fn export_env(install_path: &Path, repo_name: &str, output: Option<&Path>, relocatable: bool) -> Result<PathBuf> {
+    let venv_path = install_path.join("envs").join(repo_name);
+    if !venv_path.exists() {
+        return Err(PortableSourceError::environment(format!(
+            "Environment for '{}' not found at {:?}", repo_name, venv_path
+        )));
+    }
+    let output = output
+        .map(|p| p.to_path_buf())
+        .unwrap_or_else(|| PathBuf::from(format!("{}-env.tar.zst", repo_name)));
+
+    let prefix = venv_path.to_string_lossy().to_string();
+    let install_str = install_path.to_string_lossy().to_string();
+    let (text_files, symlinks, binary_files) = if relocatable {
+        info!("Scanning {:?} for hard-coded paths...", venv_path);
+        scan_prefix_references(&venv_path, &[&prefix, &install_str])?
+    } else {
+        (Vec::new(), Vec::new(), Vec::new())
+    };
+    if !binary_files.is_empty() {
+        warn!(
+            "{} binary file(s) reference {} and will keep the old path after import",
+            binary_files.len(), prefix
+        );
+        for f in &binary_files { debug!("  binary with prefix: {}", f); }
+    }
+
+    let manifest = PackManifest {
+        format_version: PACK_FORMAT_VERSION,
+        repo_name: repo_name.to_string(),
+        prefix,
+        install_path: install_str,
+        relocatable,
+        platform: current_platform(),
+        text_files,
+        symlinks,
+        binary_files,
+        created_at: unix_timestamp(),
+    };
+    let manifest_json = serde_json::to_vec_pretty(&manifest)?;
+
+    if let Some(parent) = output.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let file = fs::File::create(&output)?;
+    let encoder = zstd::stream::Encoder::new(file, 3)?.auto_finish();
+    let mut builder = tar::Builder::new(encoder);
+    // Симлинки bin/python -> базовый python пакуем как есть, без разворачивания
+    builder.follow_symlinks(false);
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(manifest_json.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(manifest.created_at);
+    header.set_cksum();
+    builder.append_data(&mut header, PACK_MANIFEST_FILE, manifest_json.as_slice())?;
+    builder.append_dir_all(".", &venv_path)?;
+    builder.into_inner()?.flush()?;
+
+    Ok(output)
+}
+
+/// Unpack an archive made by `export_env` into `envs/<name>`, fixing paths if it is relocatable.
+/// Returns the repo name the environment was installed under.
+pub fn import_env(install_path: &Path, archive: &Path, name: Option<&str>) -> Result<String> {
+    if !archive.exists() {
+        return Err(PortableSourceError::environment(format!("Archive not found: {:?}", archive)));
+    }
+    let envs_path = install_path.join("envs");
+    fs::create_dir_all(&envs_path)?;
+
+    // Распаковываем во временную папку рядом, чтобы rename был атомарным
+    let staging = tempfile::Builder::new().prefix(".import-").tempdir_in(&envs_path)?;
+    let file = fs::File::open(archive)?;
+    let decoder = zstd::stream::Decoder::new(file)?;
+    let mut tar = tar::Archive::new(decoder);
+    tar.set_preserve_permissions(true);
+    tar.unpack(staging.path())
+        .map_err(|e| PortableSourceError::environment(format!("Failed to extract {:?}: {}", archive, e)))?;
+
+    let manifest_path = staging.path().join(PACK_MANIFEST_FILE);
+    let manifest: PackManifest = serde_json::from_str(&fs::read_to_string(&manifest_path).map_err(|_| {
+        PortableSourceError::environment(format!("{:?} is not an export-env archive (missing manifest)", archive))
+    })?)?;
+    fs::remove_file(&manifest_path)?;
+
+    if manifest.format_version > PACK_FORMAT_VERSION {
+        return Err(PortableSourceError::environment(format!(
+            "Archive format v{} is newer than supported v{}; update portablesource",
+            manifest.format_version, PACK_FORMAT_VERSION
+        )));
+    }
+    if manifest.platform != current_platform() {
+        return Err(PortableSourceError::environment(format!(
+            "Archive was built on {} and cannot be used on {}", manifest.platform, current_platform()
+        )));
+    }
+
+    let repo_name = name.unwrap_or(&manifest.repo_name).to_string();
+    let target = envs_path.join(&repo_name);
+    if target.exists() {
+        return Err(PortableSourceError::environment(format!(
+            "Environment '{}' already exists at {:?}; delete it first", repo_name, target
+        )));
+    }
+
+    if manifest.relocatable {
+        fix_paths(staging.path(), &manifest, &target, install_path)?;
+    } else if Path::new(&manifest.prefix) != target {
+        warn!(
+            "Archive is not relocatable: it was built for {} and may not work at {:?}. Re-export with --relocatable",
+            manifest.prefix, target
+        );
+    }
+
+    fs::rename(staging.keep(), &target)?;
+    Ok(repo_name)
+}
+
+fn fix_paths(root: &Path, manifest: &PackManifest, new_prefix: &Path, new_install_path: &Path) -> Result<()> {
+    let replacements = replacements(manifest, new_prefix, new_install_path);
+    for rel in &manifest.text_files {
+        let path = root.join(rel);
+        for (old, new) in &replacements {
+            // Keeps the permissions (shebang scripts in bin/ stay executable)
+            path_rewrite::rewrite_file(&path, old, new)?;
+        }
+    }
+    for rel in &manifest.symlinks {
+        let path = root.join(rel);
+        let mut fixed = fs::read_link(&path)?.to_string_lossy().to_string();
+        for (old, new) in &replacements {
+            if let Some(replaced) = path_rewrite::replace_path(&fixed, old, new) {
+                fixed = replaced;
+            }
+        }
+        fs::remove_file(&path)?;
+        std::os::unix::fs::symlink(&fixed, &path)?;
+    }
+    debug!(
+        "Rewrote {} text file(s) and {} symlink(s) for {:?}",
+        manifest.text_files.len(), manifest.symlinks.len(), new_prefix
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relocatable_round_trip_rewrites_paths() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        let venv = src.path().join("envs").join("demo");
+        let base_python = src.path().join("ps_env").join("mamba_env").join("bin");
+        fs::create_dir_all(venv.join("bin")).unwrap();
+        fs::create_dir_all(&base_python).unwrap();
+        fs::write(base_python.join("python3"), "").unwrap();
+
+        fs::write(venv.join("pyvenv.cfg"), format!("home = {}\n", base_python.display())).unwrap();
+        fs::write(venv.join("bin").join("pip"), format!("#!{}/bin/python\nimport pip\n", venv.display())).unwrap();
+        fs::write(venv.join("bin").join("blob.so"), [b"\0\0".as_slice(), venv.to_string_lossy().as_bytes()].concat()).unwrap();
+        std::os::unix::fs::symlink(base_python.join("python3"), venv.join("bin").join("python")).unwrap();
+
+        let archive = src.path().join("demo.tar.zst");
+        export_env(src.path(), "demo", Some(&archive), true).unwrap();
+        let name = import_env(dst.path(), &archive, Some("worker")).unwrap();
+        assert_eq!(name, "worker");
+
+        let new_venv = dst.path().join("envs").join("worker");
+        let new_base = dst.path().join("ps_env").join("mamba_env").join("bin");
+        let cfg = fs::read_to_string(new_venv.join("pyvenv.cfg")).unwrap();
+        assert_eq!(cfg, format!("home = {}\n", new_base.display()));
+        let pip = fs::read_to_string(new_venv.join("bin").join("pip")).unwrap();
+        assert!(pip.starts_with(&format!("#!{}/bin/python\n", new_venv.display())));
+        assert_eq!(fs::read_link(new_venv.join("bin").join("python")).unwrap(), new_base.join("python3"));
+        assert!(!new_venv.join(PACK_MANIFEST_FILE).exists());
+    }
+
+    #[test]
+    fn sibling_paths_sharing_the_prefix_are_kept() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        let venv = src.path().join("envs").join("demo");
+        fs::create_dir_all(&venv).unwrap();
+        let lines = format!("{0}/lib\n{0}-cache/models\n{1}-old/models\n", venv.display(), src.path().display());
+        fs::write(venv.join("paths.pth"), lines).unwrap();
+
+        let archive = src.path().join("demo.tar.zst");
+        export_env(src.path(), "demo", Some(&archive), true).unwrap();
+        import_env(dst.path(), &archive, None).unwrap();
+
+        let new_venv = dst.path().join("envs").join("demo");
+        assert_eq!(
+            fs::read_to_string(new_venv.join("paths.pth")).unwrap(),
+            format!("{}/lib\n{}-cache/models\n{}-old/models\n", new_venv.display(), new_venv.display(), src.path().display())
+        );
+    }
+
+    #[test]
+    fn import_refuses_existing_env() {
+        let src = tempfile::tempdir().unwrap();
+        fs::create_dir_all(src.path().join("envs").join("demo")).unwrap();
+        let archive = src.path().join("demo.tar.zst");
+        export_env(src.path(), "demo", Some(&archive), false).unwrap();
+        assert!(import_env(src.path(), &archive, None).is_err());
+    }
+}
@@ -0,0 +1,475 @@
+//! Preview of the environment a repository is launched with
+//!
+//! run-repo starts the start script with the CLI's own environment plus the GPU vendor
+//! variables of [`run_environment`]; the script then sets paths, caches and CUDA variables
+//! of its own. [`preview`] replays both layers without starting anything, so "works in my
+//! terminal, fails from the script" can be compared variable by variable. The script layer
+//! is derived from the same [`ScriptContext`] the script is rendered from.
+
+use crate::envs_manager::run_environment;
+use crate::installer::ScriptContext;
+use std::collections::{BTreeMap, HashSet};
+
+/// Where a variable's value comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Origin {
+    /// The shell run-repo was started from
+    Inherited,
+    /// Added by run-repo (ROCm, oneAPI, MPS variables)
+    RunRepo,
+    /// Set by the start script
+    Script,
+}
+
+impl Origin {
+    fn label(self) -> &'static str {
+        match self {
+            Origin::Inherited => "inherited",
+            Origin::RunRepo => "run-repo",
+            Origin::Script => "script",
+        }
+    }
+}
+
+/// One change the start script makes, in script order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EnvOp {
+    Set(String, String),
+    /// Only when not set already (performance profile variables)
+    SetDefault(String, String),
+    /// `dir` in front of a list variable such as PATH
+    Prepend(String, String),
+    Unset(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreviewVar {
+    pub name: String,
+    pub value: String,
+    pub origin: Origin,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvPreview {
+    /// Sorted by name, secrets masked
+    pub vars: Vec<PreviewVar>,
+    /// PATH entries in lookup order
+    pub path: Vec<(String, Origin)>,
+}
+
+const SEP: &str = if cfg!(windows) { ";" } else { ":" };
+
+/// Environment of a launch of the repository described by `ctx`
+pub fn preview(ctx: &ScriptContext) -> EnvPreview {
+    let inherited: BTreeMap<String, String> = std::env::vars().collect();
+    let launched: BTreeMap<String, String> = run_environment().into_iter().collect();
+    let ops = if cfg!(windows) { windows_ops(ctx) } else { unix_ops(ctx, desk_mode(), has_activate(ctx)) };
+    build(&inherited, &launched, &ops)
+}
+
+/// Apply the script's changes on top of the run-repo environment and tag every value
+pub fn build(inherited: &BTreeMap<String, String>, launched: &BTreeMap<String, String>, ops: &[EnvOp]) -> EnvPreview {
+    let mut env = launched.clone();
+    let mut from_script: HashSet<String> = HashSet::new();
+    let mut script_paths: HashSet<String> = HashSet::new();
+    for op in ops {
+        match op {
+            EnvOp::Set(name, value) => {
+                let key = key_for(&env, name);
+                env.insert(key.clone(), value.clone());
+                from_script.insert(key);
+            }
+            EnvOp::SetDefault(name, value) => {
+                let key = key_for(&env, name);
+                if !env.contains_key(&key) {
+                    env.insert(key.clone(), value.clone());
+                    from_script.insert(key);
+                }
+            }
+            EnvOp::Prepend(name, dir) => {
+                let key = key_for(&env, name);
+                let current = env.get(&key).cloned().unwrap_or_default();
+                env.insert(key.clone(), format!("{}{}{}", dir, SEP, current));
+                if key.eq_ignore_ascii_case("PATH") {
+                    script_paths.insert(dir.clone());
+                }
+                from_script.insert(key);
+            }
+            EnvOp::Unset(name) => {
+                let key = key_for(&env, name);
+                env.remove(&key);
+            }
+        }
+    }
+
+    let origin_of = |name: &str, value: &str| {
+        if from_script.contains(name) {
+            Origin::Script
+        } else if inherited.get(name).map(String::as_str) == Some(value) {
+            Origin::Inherited
+        } else {
+            Origin::RunRepo
+        }
+    };
+    let inherited_paths: HashSet<&str> = inherited.get(&key_for(inherited, "PATH")).map(|p| p.split(SEP).collect()).unwrap_or_default();
+    let path = env
+        .get(&key_for(&env, "PATH"))
+        .map(|p| {
+            p.split(SEP)
+                .filter(|entry| !entry.is_empty())
+                .map(|entry| {
+                    let origin = if script_paths.contains(entry) {
+                        Origin::Script
+                    } else if inherited_paths.contains(entry) {
+                        Origin::Inherited
+                    } else {
+                        Origin::RunRepo
+                    };
+                    (entry.to_string(), origin)
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let vars = env
+        .iter()
+        .map(|(name, value)| PreviewVar { name: name.clone(), value: mask(name, value), origin: origin_of(name, value) })
+        .collect();
+    EnvPreview { vars, path }
+}
+
+impl EnvPreview {
+    pub fn render(&self) -> String {
+        let mut out = String::from("PATH, searched in this order:\n");
+        for (i, (entry, origin)) in self.path.iter().enumerate() {
+            out.push_str(&format!("  {:>3}. {}  ({})\n", i + 1, entry, origin.label()));
+        }
+        out.push_str("\nVariables:\n");
+        for var in self.vars.iter().filter(|v| !v.name.eq_ignore_ascii_case("PATH")) {
+            out.push_str(&format!("  {}={}  ({})\n", var.name, var.value, var.origin.label()));
+        }
+        out
+    }
+}
+
+/// Existing spelling of `name`: Windows variable names are case-insensitive ("Path")
+fn key_for(env: &BTreeMap<String, String>, name: &str) -> String {
+    if cfg!(windows) {
+        if let Some(existing) = env.keys().find(|k| k.eq_ignore_ascii_case(name)) {
+            return existing.clone();
+        }
+    }
+    name.to_string()
+}
+
+/// Tokens, passwords and keys are never printed; credentials in URLs (proxies) neither
+pub fn mask(name: &str, value: &str) -> String {
+    const SECRET_PARTS: &[&str] = &["TOKEN", "SECRET", "PASSWORD", "PASSWD", "API_KEY", "ACCESS_KEY", "PRIVATE_KEY", "CREDENTIAL", "AUTH"];
+    let upper = name.to_ascii_uppercase();
+    if !value.is_empty() && SECRET_PARTS.iter().any(|part| upper.contains(part)) {
+        return format!("<masked, {} chars>", value.chars().count());
+    }
+    match (value.find("://"), value.rfind('@')) {
+        (Some(scheme), Some(at)) if at > scheme && value[scheme + 3..at].contains(':') => {
+            format!("{}://<masked>@{}", &value[..scheme], &value[at + 1..])
+        }
+        _ => value.to_string(),
+    }
+}
+
+/// Linux scripts put the micromamba base on PATH unless git, python3 and ffmpeg are all
+/// available already (the same test the script makes)
+fn desk_mode() -> bool {
+    match std::env::var("PORTABLESOURCE_MODE") {
+        Ok(mode) if !mode.is_empty() => mode == "desk",
+        _ => !["git", "python3", "ffmpeg"].iter().all(|tool| which::which(tool).is_ok()),
+    }
+}
+
+fn has_activate(ctx: &ScriptContext) -> bool {
+    ctx.install_path.join("envs").join(&ctx.repo_name).join("bin").join("activate").exists()
+}
+
+/// Script changes in order; both platforms describe their script through the same steps
+struct Ops {
+    /// Directory separator inside the script
+    sep: char,
+    list: Vec<EnvOp>,
+}
+
+impl Ops {
+    fn new(sep: char) -> Self {
+        Self { sep, list: Vec::new() }
+    }
+
+    /// `base` and a `/`-separated relative path, joined with the script's separator
+    fn join(&self, base: &str, rel: &str) -> String {
+        format!("{}{}{}", base, self.sep, rel.replace('/', &self.sep.to_string()))
+    }
+
+    fn set(&mut self, name: &str, value: impl Into<String>) {
+        self.list.push(EnvOp::Set(name.to_string(), value.into()));
+    }
+
+    /// Every variable of `names` set to `value`
+    fn set_all(&mut self, names: &[&str], value: &str) {
+        for name in names {
+            self.set(name, value);
+        }
+    }
+
+    /// Variables pointing at folders under `root` (home, temp and cache redirections)
+    fn redirect(&mut self, root: &str, dirs: &[(&str, &str)]) {
+        for (name, rel) in dirs {
+            self.set(name, self.join(root, rel));
+        }
+    }
+
+    fn prepend(&mut self, name: &str, dir: impl Into<String>) {
+        self.list.push(EnvOp::Prepend(name.to_string(), dir.into()));
+    }
+
+    /// `dirs` in front of PATH, in script order (the last one is searched first)
+    fn prepend_path<I: IntoIterator<Item = String>>(&mut self, dirs: I) {
+        for dir in dirs {
+            self.prepend("PATH", dir);
+        }
+    }
+
+    /// Performance profile defaults and assigned resources, which both scripts set last
+    fn finish(mut self, ctx: &ScriptContext) -> Vec<EnvOp> {
+        if let Some(tuning) = &ctx.tuning {
+            for (name, value) in &tuning.env {
+                self.list.push(EnvOp::SetDefault(name.to_string(), value.to_string()));
+            }
+        }
+        for (name, value) in &ctx.launch_env {
+            self.set(name, value.clone());
+        }
+        self.list
+    }
+}
+
+/// CUDA toolkit folders of the Windows script, as (variable, folder under `CUDA`)
+const WINDOWS_CUDA_DIRS: &[(&str, &str)] = &[
+    ("cuda_bin", "bin"),
+    ("cuda_lib", "lib"),
+    ("cuda_lib_64", "lib/x64"),
+    ("cuda_nvml_bin", "nvml/bin"),
+    ("cuda_nvml_lib", "nvml/lib"),
+    ("cuda_nvvm_bin", "nvvm/bin"),
+    ("cuda_nvvm_lib", "nvvm/lib"),
+];
+
+/// Changes made by `render_windows_script`
+pub fn windows_ops(ctx: &ScriptContext) -> Vec<EnvOp> {
+    let mut ops = Ops::new('\\');
+    let base = if ctx.virtual_drive { "X:".to_string() } else { ctx.install_path.display().to_string() };
+    let env_path = ops.join(&base, "ps_env");
+    let python_path = ops.join(&base, &format!("envs/{}", ctx.repo_name));
+    let repo_path = ops.join(&base, &format!("repos/{}", ctx.repo_name));
+    let tmp_path = ops.join(&base, "tmp");
+    let git_path = match &ctx.system_git {
+        Some(dir) => dir.display().to_string(),
+        None => ops.join(&env_path, "git/bin"),
+    };
+    let hf_home = ops.join(&repo_path, "huggingface_home");
+
+    if ctx.virtual_drive {
+        ops.set("ROOT_PATH", format!("{}\\", ctx.install_path.display()));
+    }
+    ops.set("base_path", base.clone());
+    ops.set("env_path", env_path.clone());
+    ops.redirect(&base, &[("envs_path", "envs"), ("repos_path", "repos")]);
+    ops.set("ffmpeg_path", ops.join(&env_path, "ffmpeg"));
+    ops.set("git_path", git_path.clone());
+    ops.set("python_path", python_path.clone());
+    ops.set("python_exe", ops.join(&python_path, "python.exe"));
+    ops.set("repo_path", repo_path);
+    ops.set("tmp_path", tmp_path.clone());
+    ops.set("USERPROFILE", tmp_path.clone());
+    ops.redirect(&tmp_path, &[("TEMP", "Temp"), ("TMP", "Temp"), ("APPDATA", "AppData/Roaming"), ("LOCALAPPDATA", "AppData/Local")]);
+    ops.set("HF_HOME", hf_home.clone());
+    ops.set("XDG_CACHE_HOME", tmp_path);
+    ops.set("HF_DATASETS_CACHE", ops.join(&hf_home, "datasets"));
+    ops.set("PYTHONIOENCODING", "utf-8");
+    ops.set("PYTHONUNBUFFERED", "1");
+    ops.set("PYTHONDONTWRITEBYTECODE", "1");
+    if ctx.cuda.is_some() {
+        let cuda = ops.join(&env_path, "CUDA");
+        ops.redirect(&cuda, WINDOWS_CUDA_DIRS);
+        let dirs: Vec<String> = WINDOWS_CUDA_DIRS.iter().map(|(_, dir)| ops.join(&cuda, dir)).collect();
+        ops.prepend_path(dirs);
+    }
+    ops.prepend_path(ctx.components.iter().map(|component| component.replace("%env_path%", &env_path)));
+    let tools = [python_path.clone(), ops.join(&python_path, "Scripts"), git_path, ops.join(&env_path, "ffmpeg")];
+    ops.prepend_path(tools);
+    ops.finish(ctx)
+}
+
+/// Changes made by `render_unix_script`; `desk` and `activate` are the script's run-time tests
+pub fn unix_ops(ctx: &ScriptContext, desk: bool, activate: bool) -> Vec<EnvOp> {
+    let mut ops = Ops::new('/');
+    let install = ctx.install_path.display().to_string();
+    if desk {
+        ops.prepend_path([ops.join(&install, "ps_env/mamba_env/bin")]);
+    }
+    if activate {
+        // What a venv's bin/activate exports
+        let venv = ops.join(&install, &format!("envs/{}", ctx.repo_name));
+        ops.set("VIRTUAL_ENV", venv.clone());
+        ops.prepend_path([ops.join(&venv, "bin")]);
+        ops.list.push(EnvOp::Unset("PYTHONHOME".into()));
+    }
+    if let Some(prefix) = &ctx.shared_prefix {
+        ops.prepend_path([ops.join(&prefix.display().to_string(), "bin")]);
+    }
+    if ctx.portable {
+        ops.redirect(&install, &[("HOME", "tmp/home"), ("XDG_CACHE_HOME", "tmp/cache"), ("XDG_CONFIG_HOME", "tmp/config"), ("XDG_DATA_HOME", "tmp/data"), ("TMPDIR", "tmp/tmp")]);
+    }
+    if let Some(cuda) = &ctx.cuda {
+        ops.set_all(&["CUDA_PATH", "CUDA_HOME", "CUDA_ROOT"], &cuda.base.display().to_string());
+        ops.prepend_path([cuda.bin.display().to_string()]);
+        ops.prepend("LD_LIBRARY_PATH", format!("{}:{}", cuda.lib.display(), cuda.lib64.display()));
+    }
+    ops.finish(ctx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::installer::script_generator::{render_unix_script, render_windows_script, CudaPaths};
+    use crate::installer::LaunchTarget;
+    use std::path::PathBuf;
+
+    /// ComfyUI at /opt/ps with CUDA from the micromamba base and an assigned port
+    fn context() -> ScriptContext {
+        ScriptContext {
+            repo_name: "comfyui".into(),
+            repo_dir_name: "ComfyUI".into(),
+            install_path: PathBuf::from("/opt/ps"),
+            repo_path: PathBuf::from("/opt/ps/repos/ComfyUI"),
+            target: LaunchTarget::MainFile("main.py".into()),
+            program_args: String::new(),
+            cuda: Some(CudaPaths {
+                base: PathBuf::from("/opt/ps/ps_env/mamba_env"),
+                bin: PathBuf::from("/opt/ps/ps_env/mamba_env/bin"),
+                lib: PathBuf::from("/opt/ps/ps_env/mamba_env/lib"),
+                lib64: PathBuf::from("/opt/ps/ps_env/mamba_env/lib64"),
+            }),
+            virtual_drive: false,
+            portable: false,
+            tuning: None,
+            launch_env: vec![("PORT", "8189".into())],
+            system_git: None,
+            components: Vec::new(),
+            shared_prefix: None,
+        }
+    }
+
+    fn names(ops: &[EnvOp]) -> Vec<&str> {
+        ops.iter()
+            .filter_map(|op| match op {
+                EnvOp::Set(name, _) | EnvOp::Prepend(name, _) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn path_dirs(ops: &[EnvOp]) -> Vec<&str> {
+        ops.iter()
+            .filter_map(|op| match op {
+                EnvOp::Prepend(name, dir) if name == "PATH" => Some(dir.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unix_script_sets_every_variable_of_the_preview() {
+        let ops = unix_ops(&context(), true, true);
+        let script = render_unix_script(&context());
+        for name in names(&ops).into_iter().filter(|n| *n != "VIRTUAL_ENV") {
+            assert!(script.contains(&format!("export {}=", name)), "{} not in script", name);
+        }
+    }
+
+    #[test]
+    fn windows_script_sets_every_variable_of_the_preview() {
+        let ops = windows_ops(&context());
+        let script = render_windows_script(&context());
+        for name in names(&ops) {
+            assert!(script.contains(&format!("set {}=", name)) || script.contains(&format!("set \"{}=", name)), "{} not in script", name);
+        }
+    }
+
+    #[test]
+    fn windows_path_puts_the_tools_in_front_of_cuda() {
+        let ops = windows_ops(&context());
+        let dirs = path_dirs(&ops);
+        assert_eq!(dirs.len(), WINDOWS_CUDA_DIRS.len() + 4);
+        assert_eq!(dirs[0], "/opt/ps\\ps_env\\CUDA\\bin");
+        assert_eq!(dirs[2], "/opt/ps\\ps_env\\CUDA\\lib\\x64");
+        assert_eq!(dirs[dirs.len() - 4..], ["/opt/ps\\envs\\comfyui", "/opt/ps\\envs\\comfyui\\Scripts", "/opt/ps\\ps_env\\git\\bin", "/opt/ps\\ps_env\\ffmpeg"]);
+    }
+
+    #[test]
+    fn windows_temp_folders_are_redirected_into_the_install() {
+        let ops = windows_ops(&context());
+        assert!(ops.contains(&EnvOp::Set("TEMP".into(), "/opt/ps\\tmp\\Temp".into())));
+        assert!(ops.contains(&EnvOp::Set("APPDATA".into(), "/opt/ps\\tmp\\AppData\\Roaming".into())));
+        assert!(ops.contains(&EnvOp::Set("USERPROFILE".into(), "/opt/ps\\tmp".into())));
+    }
+
+    #[test]
+    fn windows_virtual_drive_and_system_git_change_the_paths() {
+        let ctx = ScriptContext { virtual_drive: true, system_git: Some(PathBuf::from("C:\\Git\\cmd")), ..context() };
+        let ops = windows_ops(&ctx);
+        assert!(ops.contains(&EnvOp::Set("base_path".into(), "X:".into())));
+        assert!(ops.contains(&EnvOp::Set("git_path".into(), "C:\\Git\\cmd".into())));
+        assert!(path_dirs(&ops).contains(&"X:\\envs\\comfyui\\Scripts"));
+    }
+
+    #[test]
+    fn launch_variables_come_last_on_both_platforms() {
+        let port = EnvOp::Set("PORT".into(), "8189".into());
+        assert_eq!(windows_ops(&context()).last(), Some(&port));
+        assert_eq!(unix_ops(&context(), false, false).last(), Some(&port));
+    }
+
+    #[test]
+    fn secrets_and_proxy_credentials_are_masked() {
+        assert_eq!(mask("HF_TOKEN", "hf_abcdef"), "<masked, 9 chars>");
+        assert_eq!(mask("HTTPS_PROXY", "http://me:pw@proxy:3128"), "http://<masked>@proxy:3128");
+        assert_eq!(mask("HOME", "/home/me"), "/home/me");
+    }
+
+    #[cfg(unix)]
+    fn unix_preview() -> EnvPreview {
+        let inherited: BTreeMap<String, String> = [("PATH", "/usr/bin"), ("HOME", "/home/me")]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        build(&inherited, &inherited, &unix_ops(&context(), true, true))
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn path_lists_script_entries_before_inherited_ones() {
+        let preview = unix_preview();
+        let path: Vec<&str> = preview.path.iter().map(|(p, _)| p.as_str()).collect();
+        assert_eq!(path, ["/opt/ps/ps_env/mamba_env/bin", "/opt/ps/envs/comfyui/bin", "/opt/ps/ps_env/mamba_env/bin", "/usr/bin"]);
+        assert_eq!(preview.path[0].1, Origin::Script);
+        assert_eq!(preview.path[3].1, Origin::Inherited);
+        assert!(preview.render().contains("    1. /opt/ps/ps_env/mamba_env/bin  (script)\n"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn variables_are_tagged_with_their_origin() {
+        let preview = unix_preview();
+        let var = |name: &str| preview.vars.iter().find(|v| v.name == name).unwrap();
+        assert_eq!(var("LD_LIBRARY_PATH").value, "/opt/ps/ps_env/mamba_env/lib:/opt/ps/ps_env/mamba_env/lib64:");
+        assert_eq!((var("PORT").value.as_str(), var("PORT").origin), ("8189", Origin::Script));
+        assert_eq!(var("HOME").origin, Origin::Inherited);
+    }
+}
@@ -3,19 +3,27 @@
 //! This module handles downloading and managing portable tools
 //! like Python, Git, FFMPEG, and CUDA.
 
-use crate::{Result, PortableSourceError};
-use crate::config::{ConfigManager, ToolLinks};
+use crate::{output, Result, PortableSourceError};
+use crate::components::{self, Component};
+use crate::config::{ConfigManager, CudaVersion, SystemTools, ToolLinks};
+use crate::tool_slots;
 use url::Url;
-use std::fs::{self, OpenOptions};
-use std::io::{self, Seek, SeekFrom, Read, Write};
+use std::fs;
+use tokio::io::AsyncWriteExt;
+use tracing::Instrument;
 use std::path::Path;
-use std::process::{Command, Stdio};
-use crate::gpu::GpuDetector;
-use std::collections::HashMap;
+use std::process::Command;
+use crate::system::{CommandOutput, CommandRequest, Services};
+use crate::gpu::{Backend, GpuDetector, oneapi_root, rocm_root};
+use crate::download_state::{clean_stale_partials, prefix_sha256, DownloadState, RemoteInfo, PREFIX_HASH_LEN, STALE_PARTIAL_AGE_SECS};
+use std::collections::{HashMap, VecDeque};
 use std::path::{PathBuf};
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use indicatif::{ProgressBar, ProgressStyle};
+use crate::progress::{self, Progress};
+use crate::disk_space::{self, SpaceCheck};
+use crate::download_plan::{DownloadPlan, PlannedDownload};
+use crate::extraction;
 use std::time::Instant;
 
 #[derive(Clone, Debug)]
@@ -23,7 +31,278 @@ struct PortableToolSpec {
     name: String,
     url: String,
     extract_path: String,
+    /// Where the portable archive puts the executable (relative to ps_env)
     executable_path: String,
+    /// Where to look for the executable, in order of preference (relative to ps_env)
+    search_paths: Vec<&'static str>,
+    /// Names looked up on PATH when `system_tool_fallback` is on
+    system_names: &'static [&'static str],
+}
+
+/// Env var to turn off overlapping download/extract during setup ("0" or "false")
+pub const SETUP_PIPELINE_ENV: &str = "PORTABLESOURCE_SETUP_PIPELINE";
+
+/// Python versions accepted from a system install, inclusive
+const SYSTEM_PYTHON_MIN: (u32, u32) = (3, 10);
+const SYSTEM_PYTHON_MAX: (u32, u32) = (3, 12);
+/// Oldest system git accepted
+const SYSTEM_GIT_MIN: (u32, u32) = (2, 30);
+
+/// Written into `ps_env/CUDA` once the toolkit is completely in place; holds the archive
+/// folder name (e.g. `cuda_128`), or `CUDA_PARTIAL` while files are copied in
+pub const CUDA_MARKER_FILE: &str = ".portablesource_cuda";
+const CUDA_PARTIAL: &str = "partial";
+
+/// Files a usable toolkit has, as (folder under `CUDA`, file name prefix): nvcc and the
+/// CUDA runtime and cuBLAS libraries
+fn cuda_key_files() -> &'static [(&'static str, &'static str)] {
+    if cfg!(windows) {
+        &[("bin", "nvcc.exe"), ("bin", "cudart64_"), ("bin", "cublas64_")]
+    } else {
+        &[("bin", "nvcc"), ("lib64", "libcudart.so"), ("lib64", "libcublas.so")]
+    }
+}
+
+/// State of a portable CUDA toolkit folder
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CudaInstall {
+    Missing,
+    /// Interrupted extraction or copy; the reason names what is missing
+    Incomplete(String),
+    Complete,
+}
+
+/// First key file missing from a toolkit folder
+fn missing_cuda_file(cuda_dir: &Path) -> Option<String> {
+    cuda_key_files().iter().find_map(|(folder, prefix)| {
+        let found = fs::read_dir(cuda_dir.join(folder))
+            .map(|entries| entries.flatten().any(|e| e.file_name().to_string_lossy().starts_with(prefix)))
+            .unwrap_or(false);
+        (!found).then(|| format!("{}/{}* is missing", folder, prefix))
+    })
+}
+
+/// Check `ps_env/CUDA` for its key files without touching it. A complete folder without the
+/// marker (installed before it existed) counts as complete
+pub fn inspect_cuda(cuda_dir: &Path) -> CudaInstall {
+    if !cuda_dir.exists() {
+        return CudaInstall::Missing;
+    }
+    let marker = cuda_dir.join(CUDA_MARKER_FILE);
+    if fs::read_to_string(&marker).is_ok_and(|m| m.trim() == CUDA_PARTIAL) {
+        return CudaInstall::Incomplete("copying the files was interrupted".into());
+    }
+    if let Some(reason) = missing_cuda_file(cuda_dir) {
+        return CudaInstall::Incomplete(reason);
+    }
+    CudaInstall::Complete
+}
+
+/// Validate a freshly moved toolkit and write its marker; an incomplete one is removed
+fn finish_cuda_install(cuda_dir: &Path, expected_folder: &str) -> Result<()> {
+    if let Some(reason) = missing_cuda_file(cuda_dir) {
+        let _ = fs::remove_dir_all(cuda_dir);
+        return Err(PortableSourceError::environment(format!("CUDA installation is incomplete ({}); run setup-env again", reason)));
+    }
+    crate::atomic_write::write(cuda_dir.join(CUDA_MARKER_FILE), expected_folder)
+}
+
+/// Top-level folder of a CUDA archive: `cuda_128` for CUDA_128.tar.zst
+fn cuda_archive_folder(version: &CudaVersion) -> String {
+    let version_debug = format!("{:?}", version).to_lowercase();
+    format!("cuda_{}", version_debug.replace("cuda", "").replace(['_', '"'], ""))
+}
+
+/// `12.8` from `nvcc --version` ("Cuda compilation tools, release 12.8, V12.8.61")
+pub fn parse_nvcc_release(output: &str) -> Option<String> {
+    Some(output.split("release ").nth(1)?.split(',').next()?.trim().to_string()).filter(|r| !r.is_empty())
+}
+
+/// Outcome of `upgrade-tool`, as slot names (`python@3.11.10`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ToolUpgrade {
+    UpToDate(String),
+    Upgraded { from: String, to: String },
+}
+
+/// Why `upgrade-tool python` must not move from `old` to `new`: every repository venv links
+/// against the base interpreter's `major.minor`, so only patch releases are installed
+pub fn python_upgrade_rejection(key: &str, old: &str, new: &str) -> Option<String> {
+    if key != "python" {
+        return None;
+    }
+    let (Some(old), Some(new)) = (parse_tool_version(old), parse_tool_version(new)) else { return None };
+    ((old.0, old.1) != (new.0, new.1)).then(|| {
+        format!(
+            "The new portable Python is {}.{}.{}, but the environments in envs/ were built with {}.{}; upgrade-tool only installs {}.{} patch releases. Nothing changed",
+            new.0, new.1, new.2, old.0, old.1, old.0, old.1
+        )
+    })
+}
+
+/// `major.minor.patch` of the first version-like word in `--version` output
+/// ("Python 3.11.9", "git version 2.43.0.windows.1")
+pub fn parse_tool_version(output: &str) -> Option<(u32, u32, u32)> {
+    output.split_whitespace().find_map(|word| {
+        let mut parts = word.split('.').map(|p| p.parse::<u32>().ok());
+        let major = parts.next()??;
+        let minor = parts.next()??;
+        Some((major, minor, parts.next().flatten().unwrap_or(0)))
+    })
+}
+
+/// Why a system install of `key` at `version` cannot replace the portable tool, if it cannot
+pub fn system_tool_rejection(key: &str, version: (u32, u32, u32)) -> Option<String> {
+    let (major, minor, patch) = version;
+    match key {
+        "python" if (major, minor) < SYSTEM_PYTHON_MIN || (major, minor) > SYSTEM_PYTHON_MAX => Some(format!(
+            "Python {}.{}.{} is outside the supported {}.{}-{}.{}",
+            major, minor, patch, SYSTEM_PYTHON_MIN.0, SYSTEM_PYTHON_MIN.1, SYSTEM_PYTHON_MAX.0, SYSTEM_PYTHON_MAX.1
+        )),
+        "git" if (major, minor) < SYSTEM_GIT_MIN => Some(format!(
+            "git {}.{}.{} is older than {}.{}",
+            major, minor, patch, SYSTEM_GIT_MIN.0, SYSTEM_GIT_MIN.1
+        )),
+        _ => None,
+    }
+}
+
+fn setup_pipeline_enabled() -> bool {
+    match std::env::var(SETUP_PIPELINE_ENV) {
+        Ok(v) => !matches!(v.trim().to_ascii_lowercase().as_str(), "0" | "false" | "off" | "no"),
+        Err(_) => true,
+    }
+}
+
+/// One archive to download and unpack into ps_env
+struct SetupJob {
+    tool: String,
+    label: String,
+    url: String,
+    archive_path: PathBuf,
+    kind: SetupJobKind,
+}
+
+enum SetupJobKind {
+    /// Unpack into a temp dir, then move `<expected_folder>` to `ps_env/CUDA`
+    Cuda { expected_folder: String },
+    /// Unpack into ps_env and check that the executable appeared
+    Tool { executable_path: String },
+}
+
+/// Step counter shared between the download and extract threads
+struct SetupProgress {
+    total: usize,
+    completed: AtomicUsize,
+}
+
+impl SetupProgress {
+    fn new(total: usize) -> Self {
+        Self { total, completed: AtomicUsize::new(0) }
+    }
+
+    fn done(&self) -> usize {
+        self.completed.load(Ordering::SeqCst)
+    }
+
+    fn summary(&self, done: usize) -> String {
+        let pct = if self.total > 0 { (done as f32 / self.total as f32) * 100.0 } else { 100.0 };
+        format!("{}/{} ({:.0}%)", done, self.total, pct)
+    }
+
+    fn println(&self, msg: &str) {
+        progress::println(msg);
+    }
+
+    fn step(&self, msg: &str) {
+        let done = self.completed.fetch_add(1, Ordering::SeqCst) + 1;
+        progress::println(&format!("{}\n[Setup] Progress: {}", msg, self.summary(done)));
+    }
+}
+
+/// Stage of one setup task
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetupPhase {
+    /// First event: the plan is known, `total` is final
+    Planned,
+    Downloading,
+    Extracting,
+    Installed,
+    /// Last event, sent whether the setup succeeded or failed
+    Completed,
+}
+
+/// One progress report of `setup_environment_with_progress`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetupEvent {
+    /// 1, 2, 3... in the order the callback is called
+    pub seq: u64,
+    /// "setup" for the plan, then "python", "git", "ffmpeg" or "cuda"
+    pub task: String,
+    pub phase: SetupPhase,
+    /// Finished steps (a download or an extraction) of all tasks
+    pub done: usize,
+    pub total: usize,
+}
+
+#[derive(Default)]
+struct ReporterState {
+    seq: u64,
+    done: usize,
+    total: usize,
+    /// Numbered events not handed to the callback yet
+    queue: VecDeque<SetupEvent>,
+    /// A caller is draining `queue`
+    delivering: bool,
+}
+
+/// Serializes reports from the parallel setup tasks. Events are numbered under the lock and
+/// queued; whichever caller finds nobody delivering drains the queue with the lock released,
+/// so the callback sees `seq` and `done` only increase, is never called concurrently and may
+/// itself report without deadlocking
+struct SetupReporter {
+    state: std::sync::Mutex<ReporterState>,
+    callback: Box<dyn Fn(SetupEvent) + Send + Sync>,
+}
+
+impl SetupReporter {
+    fn new(callback: impl Fn(SetupEvent) + Send + Sync + 'static) -> Self {
+        Self { state: std::sync::Mutex::new(ReporterState::default()), callback: Box::new(callback) }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, ReporterState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The step count is known: sends the `Planned` event
+    fn plan(&self, total: usize) {
+        self.lock().total = total;
+        self.report("setup", SetupPhase::Planned, false);
+    }
+
+    /// `finished_step`: a download or extraction just ended
+    fn report(&self, task: &str, phase: SetupPhase, finished_step: bool) {
+        let mut state = self.lock();
+        state.seq += 1;
+        if finished_step {
+            state.done += 1;
+        }
+        let event = SetupEvent { seq: state.seq, task: task.to_string(), phase, done: state.done, total: state.total };
+        state.queue.push_back(event);
+        if state.delivering {
+            return;
+        }
+        state.delivering = true;
+        loop {
+            let Some(event) = state.queue.pop_front() else {
+                state.delivering = false;
+                return;
+            };
+            drop(state);
+            (self.callback)(event);
+            state = self.lock();
+        }
+    }
 }
 
 pub struct PortableEnvironmentManager {
@@ -32,6 +311,7 @@ pub struct PortableEnvironmentManager {
     config_manager: ConfigManager,
     gpu_detector: GpuDetector,
     tool_specs: HashMap<String, PortableToolSpec>,
+    services: Services,
 }
 
 impl PortableEnvironmentManager {
@@ -39,28 +319,46 @@ impl PortableEnvironmentManager {
         let ps_env_path = install_path.join("ps_env");
         let config_manager = ConfigManager::new(None).expect("ConfigManager init failed");
         let tool_specs = Self::build_tool_specs();
-        Self { install_path, ps_env_path, config_manager, gpu_detector: GpuDetector::new(), tool_specs }
+        Self { install_path, ps_env_path, config_manager, gpu_detector: GpuDetector::new(), tool_specs, services: Services::system() }
     }
 
     pub fn with_config(install_path: PathBuf, config_manager: ConfigManager) -> Self {
         let ps_env_path = install_path.join("ps_env");
         let tool_specs = Self::build_tool_specs();
-        Self { install_path, ps_env_path, config_manager, gpu_detector: GpuDetector::new(), tool_specs }
+        Self { install_path, ps_env_path, config_manager, gpu_detector: GpuDetector::new(), tool_specs, services: Services::system() }
+    }
+
+    /// Replace network/process/clock providers (mocks in tests)
+    pub fn with_services(mut self, services: Services) -> Self {
+        self.services = services;
+        self
+    }
+
+    pub fn services(&self) -> &Services {
+        &self.services
     }
 
     /// Check if portable tool with given key is already installed (by executable presence)
     fn is_tool_installed(&self, key: &str) -> bool {
-        if let Some(spec) = self.tool_specs.get(key) {
-            let exe_path = self.ps_env_path.join(&spec.executable_path);
-            return exe_path.exists();
-        }
-        false
+        self.config_manager.system_tool(key).is_some()
+            || self
+                .tool_specs
+                .get(key)
+                .is_some_and(|spec| spec.search_paths.iter().any(|rel| self.ps_env_path.join(rel).exists()))
     }
 
-    /// Check if CUDA is already installed (by CUDA/bin presence)
+    /// Check if CUDA is completely installed. Planning calls this too, so an incomplete
+    /// toolkit (interrupted extraction) is only reported; the install step replaces it
     fn is_cuda_installed(&self) -> bool {
         let cuda_dir = self.ps_env_path.join("CUDA");
-        cuda_dir.join("bin").exists()
+        match inspect_cuda(&cuda_dir) {
+            CudaInstall::Complete => true,
+            CudaInstall::Missing => false,
+            CudaInstall::Incomplete(reason) => {
+                tracing::warn!("Portable CUDA in {:?} is incomplete ({}); setup reinstalls it", cuda_dir, reason);
+                false
+            }
+        }
     }
 
     fn build_tool_specs() -> HashMap<String, PortableToolSpec> {
@@ -73,6 +371,12 @@ impl PortableEnvironmentManager {
                 url: ToolLinks::Ffmpeg.url().to_string(),
                 extract_path: "ffmpeg".to_string(),
                 executable_path: if is_windows { "ffmpeg/ffmpeg.exe" } else { "ffmpeg/ffmpeg" }.to_string(),
+                search_paths: if is_windows {
+                    vec!["ffmpeg/ffmpeg.exe", "ffmpeg/bin/ffmpeg.exe"]
+                } else {
+                    vec!["mamba_env/bin/ffmpeg", "ffmpeg/ffmpeg", "ffmpeg/bin/ffmpeg"]
+                },
+                system_names: &["ffmpeg"],
             },
         );
         map.insert(
@@ -82,6 +386,13 @@ impl PortableEnvironmentManager {
                 url: ToolLinks::Git.url().to_string(),
                 extract_path: "git".to_string(),
                 executable_path: if is_windows { "git/cmd/git.exe" } else { "git/bin/git" }.to_string(),
+                // PortableGit: cmd/ is the documented entry point, bin/ and mingw64/bin/ are wrappers/internals
+                search_paths: if is_windows {
+                    vec!["git/cmd/git.exe", "git/bin/git.exe", "git/mingw64/bin/git.exe"]
+                } else {
+                    vec!["mamba_env/bin/git", "git/bin/git"]
+                },
+                system_names: &["git"],
             },
         );
         map.insert(
@@ -91,260 +402,258 @@ impl PortableEnvironmentManager {
                 url: ToolLinks::Python311.url().to_string(),
                 extract_path: "python".to_string(),
                 executable_path: if is_windows { "python/python.exe" } else { "python/bin/python" }.to_string(),
+                search_paths: if is_windows {
+                    vec!["python/python.exe"]
+                } else {
+                    vec!["mamba_env/bin/python", "python/bin/python"]
+                },
+                system_names: &["python3", "python"],
             },
         );
         map
     }
 
-    // --- Downloads ---
-    fn download_with_resume(&self, url: &str, destination: &Path) -> Result<()> {
-        use reqwest::blocking::Client;
-        use reqwest::header::{RANGE, CONTENT_RANGE};
-
-        let client = Client::builder()
-            .timeout(std::time::Duration::from_secs(600))
-            .build()?;
-
-        let mut existing_len: u64 = 0;
-        if destination.exists() {
-            existing_len = destination.metadata()?.len();
-        } else if let Some(parent) = destination.parent() { fs::create_dir_all(parent)?; }
-
-        // Проверяем полный размер файла с сервера
-        let head_resp = client.head(url).send()?;
-        if let Some(total_size) = head_resp.content_length() {
-            if existing_len == total_size {
-                // Файл уже полностью скачан
-                let file_name = destination.file_name().map(|s| s.to_string_lossy().to_string()).unwrap_or_else(|| "file".into());
-                println!("[Setup] {} already downloaded.", file_name);
-                return Ok(());
-            }
+    /// Locate a tool executable: portable locations from the tool spec first, then the system
+    /// install recorded by `--use-system-tools`, then PATH if `system_tool_fallback` is enabled
+    pub fn find_tool_executable(&self, key: &str) -> Option<PathBuf> {
+        let spec = self.tool_specs.get(key)?;
+        if let Some(found) = spec.search_paths.iter().map(|rel| self.ps_env_path.join(rel)).find(|p| p.exists()) {
+            return Some(found);
         }
+        if let Some(path) = self.config_manager.system_tool(key) {
+            return Some(path.to_path_buf());
+        }
+        if !self.config_manager.get_config().system_tool_fallback {
+            return None;
+        }
+        let found = spec.system_names.iter().find_map(|name| which::which(name).ok());
+        if let Some(path) = &found {
+            tracing::debug!("Using system {} at {:?}", spec.name, path);
+        }
+        found
+    }
 
-        // Try ranged request if we have partial file
-        let mut resp = if existing_len > 0 {
-            client.get(url).header(RANGE, format!("bytes={}-", existing_len)).send()?
+    /// Find a system install of "python" or "git" on PATH that can replace the portable one:
+    /// its path and version, or why none qualifies
+    pub fn probe_system_tool(&self, key: &str) -> std::result::Result<(PathBuf, String), String> {
+        let spec = self.tool_specs.get(key).ok_or_else(|| format!("Unknown tool: {}", key))?;
+        let mut rejections = Vec::new();
+        // WindowsApps holds the Microsoft Store alias stubs, not an interpreter
+        let candidates = spec
+            .system_names
+            .iter()
+            .filter_map(|name| which::which(name).ok())
+            .filter(|p| !p.to_string_lossy().contains("WindowsApps"));
+        for path in candidates {
+            let request = CommandRequest::from_args(&[path.to_string_lossy().to_string(), "--version".to_string()])
+                .ok_or_else(|| "empty command".to_string())?;
+            let output = match self.services.executor.execute(&request) {
+                Ok(output) => output,
+                Err(e) => {
+                    rejections.push(format!("{:?}: {}", path, e));
+                    continue;
+                }
+            };
+            let text = if output.stdout.trim().is_empty() { &output.stderr } else { &output.stdout };
+            let Some(version) = parse_tool_version(text) else {
+                rejections.push(format!("{:?}: unrecognized version output", path));
+                continue;
+            };
+            if let Some(reason) = system_tool_rejection(key, version) {
+                rejections.push(format!("{:?}: {}", path, reason));
+                continue;
+            }
+            // Repository environments on Windows are copies of the base install next to python.exe
+            if cfg!(windows) && key == "python" && !path.parent().is_some_and(|dir| dir.join("Lib").is_dir()) {
+                rejections.push(format!("{:?}: not a full Python install (no Lib folder)", path));
+                continue;
+            }
+            return Ok((path, format!("{}.{}.{}", version.0, version.1, version.2)));
+        }
+        if rejections.is_empty() {
+            Err(format!("no system {} found on PATH", spec.name))
         } else {
-            client.get(url).send()?
-        };
+            Err(rejections.join("; "))
+        }
+    }
 
-        if !resp.status().is_success() {
-            // If ranged not supported, retry from start
-            if existing_len > 0 {
-                resp = client.get(url).send()?;
-                if !resp.status().is_success() {
-                    return Err(PortableSourceError::environment(format!(
-                        "Download failed: HTTP {}", resp.status()
-                    )));
-                }
-                // truncate file
-                let _ = fs::remove_file(destination);
-                let mut f = OpenOptions::new().create(true).write(true).open(destination)?;
-                // Setup progress bar
-                let total_opt = resp.content_length();
-                let file_name = destination.file_name().map(|s| s.to_string_lossy().to_string()).unwrap_or_else(|| "download".into());
-                let pb = create_download_progress_bar(total_opt, &format!("Downloading {}", file_name));
-                let mut downloaded: u64 = 0;
-                let start = Instant::now();
-                let mut buf = [0u8; 64 * 1024];
-                loop {
-                    let n = resp.read(&mut buf)?;
-                    if n == 0 { break; }
-                    f.write_all(&buf[..n])?;
-                    downloaded += n as u64;
-                    if let Some(total) = total_opt { pb.set_position(downloaded.min(total)); } else { pb.set_position(downloaded); }
-                    update_download_pb_message(&pb, downloaded, total_opt, start);
+    /// Probe system python and git for `--use-system-tools`; each one that qualifies is
+    /// returned for the config, the others keep their portable download
+    pub fn detect_system_tools(&self) -> SystemTools {
+        let mut tools = SystemTools::default();
+        for key in ["python", "git"] {
+            match self.probe_system_tool(key) {
+                Ok((path, version)) => {
+                    output::success(&format!("Using system {} {} at {}", key, version, path.display()));
+                    match key {
+                        "python" => tools.python = Some(path),
+                        _ => tools.git = Some(path),
+                    }
                 }
-                finish_progress(pb, &format!("Downloaded {}", file_name));
-                return Ok(());
-            } else {
-                return Err(PortableSourceError::environment(format!(
-                    "Download failed: HTTP {}", resp.status()
-                )));
+                Err(reason) => output::warn(&format!("System {} not used, the portable one will be downloaded: {}", key, reason)),
             }
         }
+        tools
+    }
 
-        // Write response to file (append or create)
-        let mut file = if destination.exists() && existing_len > 0 {
-            let mut f = OpenOptions::new().read(true).write(true).open(destination)?;
-            f.seek(SeekFrom::End(0))?;
-            f
-        } else {
-            OpenOptions::new().create(true).write(true).open(destination)?
-        };
-        // Setup progress bar with total length if available
-        let total_opt = match resp.headers().get(CONTENT_RANGE) {
-            Some(hv) => parse_total_from_content_range(hv.to_str().unwrap_or("")),
-            None => resp.content_length().map(|len| existing_len + len),
-        };
+    // --- Downloads ---
+    pub(crate) async fn download_with_resume(url: &str, destination: &Path) -> Result<()> {
         let file_name = destination.file_name().map(|s| s.to_string_lossy().to_string()).unwrap_or_else(|| "download".into());
-        let pb = create_download_progress_bar(total_opt, &format!("Downloading {}", file_name));
-        if let Some(total) = total_opt { pb.set_position(existing_len.min(total)); }
-        let mut downloaded = existing_len;
-        let start = Instant::now();
-        let mut buf = [0u8; 64 * 1024];
-        loop {
-            let n = resp.read(&mut buf)?;
-            if n == 0 { break; }
-            file.write_all(&buf[..n])?;
-            downloaded += n as u64;
-            if let Some(total) = total_opt { pb.set_position(downloaded.min(total)); } else { pb.set_position(downloaded); }
-            update_download_pb_message(&pb, downloaded, total_opt, start);
-        }
-        finish_progress(pb, &format!("Downloaded {}", file_name));
-        Ok(())
+        let span = tracing::info_span!("download", file = %file_name);
+        Self::download_with_resume_inner(url, destination, &file_name).instrument(span).await
     }
 
-    // Static helpers for parallel tasks
-    fn download_with_resume_static(url: String, destination: PathBuf) -> Result<()> {
-        use reqwest::blocking::Client;
-        use reqwest::header::{RANGE, CONTENT_RANGE};
-        let client = Client::builder().timeout(std::time::Duration::from_secs(600)).build()?;
-        if let Some(parent) = destination.parent() { fs::create_dir_all(parent)?; }
-        let existing_len: u64 = if destination.exists() { destination.metadata()?.len() } else { 0 };
-        
-        // Проверяем полный размер файла с сервера
-        let head_resp = client.head(&url).send()?;
-        if let Some(total_size) = head_resp.content_length() {
-            if existing_len == total_size {
-                // Файл уже полностью скачан
-                let file_name = destination.file_name().map(|s| s.to_string_lossy().to_string()).unwrap_or_else(|| "file".into());
-                println!("[Setup] {} already downloaded.", file_name);
+    async fn download_with_resume_inner(url: &str, destination: &Path, file_name: &str) -> Result<()> {
+        use reqwest::header::{CONTENT_RANGE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE};
+        use reqwest::StatusCode;
+        let client = crate::system::http_client();
+        if let Some(parent) = destination.parent() { tokio::fs::create_dir_all(parent).await?; }
+        let mut existing_len: u64 = match tokio::fs::metadata(destination).await { Ok(m) => m.len(), Err(_) => 0 };
+
+        // Проверяем полный размер файла и ETag с сервера
+        let head_resp = client.head(url).send().await?;
+        let header = |name| head_resp.headers().get(name).and_then(|v: &reqwest::header::HeaderValue| v.to_str().ok()).map(|s| s.to_string());
+        let remote = RemoteInfo {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+            size: crate::system::head_content_length(&head_resp),
+        };
+
+        if existing_len > 0 {
+            let complete = remote.size == Some(existing_len);
+            let resumable = match DownloadState::load(destination) {
+                Some(state) => state.matches(url, &remote) && state.partial_is_intact(destination).unwrap_or(false),
+                // Без sidecar доверяем только полностью скачанному файлу
+                None => complete,
+            };
+            if resumable && complete {
+                DownloadState::remove(destination);
+                progress::println(&format!("[Setup] {} already downloaded.", file_name));
                 return Ok(());
             }
-        }
-        
-        let mut resp = if existing_len > 0 {
-            client.get(&url).header(RANGE, format!("bytes={}-", existing_len)).send()?
-        } else { client.get(&url).send()? };
-        if !resp.status().is_success() {
-            if existing_len > 0 { resp = client.get(&url).send()?; }
-            if !resp.status().is_success() {
-                return Err(PortableSourceError::environment(format!("Download failed: HTTP {}", resp.status())));
+            if !resumable {
+                progress::println(&format!("[Setup] Discarding stale partial download of {}", file_name));
+                tokio::fs::remove_file(destination).await?;
+                DownloadState::remove(destination);
+                existing_len = 0;
             }
-            let _ = fs::remove_file(&destination);
-            let mut f = OpenOptions::new().create(true).write(true).open(&destination)?;
-            let total_opt = resp.content_length();
-            let file_name = destination.file_name().map(|s| s.to_string_lossy().to_string()).unwrap_or_else(|| "download".into());
-            let pb = create_download_progress_bar(total_opt, &format!("Downloading {}", file_name));
-            let mut downloaded: u64 = 0;
-            let start = Instant::now();
-            let mut buf = [0u8; 64 * 1024];
-            loop {
-                let n = resp.read(&mut buf)?;
-                if n == 0 { break; }
-                f.write_all(&buf[..n])?;
-                downloaded += n as u64;
-                if let Some(total) = total_opt { pb.set_position(downloaded.min(total)); } else { pb.set_position(downloaded); }
-                update_download_pb_message(&pb, downloaded, total_opt, start);
+        }
+
+        let mut state = DownloadState::new(url, &remote, crate::utils::unix_timestamp());
+        let mut req = client.get(url);
+        if existing_len > 0 {
+            req = req.header(RANGE, format!("bytes={}-", existing_len));
+            // Сервер отдаст файл целиком, если он изменился
+            if let Some(validator) = remote.etag.as_ref().or(remote.last_modified.as_ref()) {
+                req = req.header(IF_RANGE, validator.as_str());
             }
-            finish_progress(pb, &format!("Downloaded {}", file_name));
-            return Ok(());
         }
-        let mut file = if destination.exists() && existing_len > 0 {
-            let mut f = OpenOptions::new().read(true).write(true).open(&destination)?;
-            use std::io::Seek; use std::io::SeekFrom;
-            f.seek(SeekFrom::End(0))?; f
-        } else { OpenOptions::new().create(true).write(true).open(&destination)? };
-        let total_opt = match resp.headers().get(CONTENT_RANGE) {
-            Some(hv) => parse_total_from_content_range(hv.to_str().unwrap_or("")),
-            None => resp.content_length().map(|len| existing_len + len),
+        let mut resp = req.send().await?;
+        if !resp.status().is_success() && existing_len > 0 {
+            resp = client.get(url).send().await?;
+        }
+        if !resp.status().is_success() {
+            return Err(PortableSourceError::environment(format!("Download failed: HTTP {}", resp.status())));
+        }
+
+        let resuming = existing_len > 0 && resp.status() == StatusCode::PARTIAL_CONTENT;
+        let (mut file, total_opt) = if resuming {
+            let f = tokio::fs::OpenOptions::new().append(true).open(destination).await?;
+            let total = match resp.headers().get(CONTENT_RANGE) {
+                Some(hv) => parse_total_from_content_range(hv.to_str().unwrap_or("")),
+                None => resp.content_length().map(|len| existing_len + len),
+            };
+            (f, total)
+        } else {
+            if existing_len > 0 {
+                progress::println(&format!("[Setup] Server did not resume {}, downloading from scratch", file_name));
+                existing_len = 0;
+            }
+            (tokio::fs::OpenOptions::new().create(true).write(true).truncate(true).open(destination).await?, resp.content_length())
         };
-        let file_name = destination.file_name().map(|s| s.to_string_lossy().to_string()).unwrap_or_else(|| "download".into());
-        let pb = create_download_progress_bar(total_opt, &format!("Downloading {}", file_name));
+        if state.expected_size.is_none() { state.expected_size = total_opt; }
+        if resuming {
+            state.prefix_sha256 = DownloadState::load(destination).and_then(|s| s.prefix_sha256);
+        }
+        state.save(destination)?;
+
+        let pb = Progress::download(total_opt, &format!("Downloading {}", file_name));
         if let Some(total) = total_opt { pb.set_position(existing_len.min(total)); }
         let mut downloaded = existing_len;
         let start = Instant::now();
-        let mut buf = [0u8; 64 * 1024];
-        loop {
-            let n = resp.read(&mut buf)?;
-            if n == 0 { break; }
-            file.write_all(&buf[..n])?;
-            downloaded += n as u64;
+        while let Some(chunk) = resp.chunk().await? {
+            file.write_all(&chunk).await?;
+            let before = downloaded;
+            downloaded += chunk.len() as u64;
+            // Фиксируем хэш начала файла, чтобы после перезапуска проверить partial
+            if before < PREFIX_HASH_LEN && downloaded >= PREFIX_HASH_LEN {
+                file.flush().await?;
+                state.prefix_sha256 = prefix_sha256(destination)?;
+                state.updated_at = crate::utils::unix_timestamp();
+                state.save(destination)?;
+            }
             if let Some(total) = total_opt { pb.set_position(downloaded.min(total)); } else { pb.set_position(downloaded); }
             update_download_pb_message(&pb, downloaded, total_opt, start);
         }
-        finish_progress(pb, &format!("Downloaded {}", file_name));
+        file.flush().await?;
+        if let Some(total) = total_opt {
+            if downloaded < total {
+                state.updated_at = crate::utils::unix_timestamp();
+                state.save(destination)?;
+                pb.abandon();
+                return Err(PortableSourceError::network(format!(
+                    "Download of {} interrupted at {} of {} bytes; run again to resume", file_name, downloaded, total
+                )));
+            }
+        }
+        DownloadState::remove(destination);
+        pb.finish_with_message(&format!("Downloaded {}", file_name));
         Ok(())
     }
 
     // --- Extraction (via tar zstd) ---
-    fn extract_tar_zstd(&self, archive_path: &Path, extract_to: &Path) -> Result<()> {
-        if let Some(parent) = extract_to.parent() { fs::create_dir_all(parent)?; }
-        fs::create_dir_all(extract_to)?;
-        self.extract_with_tar_zstd_binary(archive_path, extract_to)
+    /// Extract on the blocking pool so downloads and progress output keep running meanwhile
+    async fn extract_archive(archive_path: &Path, extract_to: &Path) -> Result<()> {
+        let (archive_path, extract_to) = (archive_path.to_path_buf(), extract_to.to_path_buf());
+        let parent = tracing::Span::current();
+        tokio::task::spawn_blocking(move || parent.in_scope(|| Self::extract_archive_static(archive_path, extract_to)))
+            .await
+            .map_err(|e| PortableSourceError::environment(format!("Extraction task failed: {}", e)))?
     }
-    fn extract_tar_zstd_static(archive_path: PathBuf, extract_to: PathBuf) -> Result<()> {
+    fn extract_archive_static(archive_path: PathBuf, extract_to: PathBuf) -> Result<()> {
+        let _span = tracing::info_span!("extract", archive = %archive_path.display()).entered();
         if let Some(parent) = extract_to.parent() { fs::create_dir_all(parent)?; }
         fs::create_dir_all(&extract_to)?;
-        Self::extract_with_tar_zstd_binary_static(&archive_path, &extract_to)
+        Self::extract_cleaning_up_static(&archive_path, &extract_to)
     }
 
     // ensure_tar_binary больше не нужна - используем Rust крейты напрямую
 
-    fn extract_with_tar_zstd_binary(&self, archive_path: &Path, extract_to: &Path) -> Result<()> {
-        use std::fs::File;
-        use std::io::BufReader;
-        
-        let file_label = archive_path.file_name().map(|s| s.to_string_lossy().to_string()).unwrap_or_else(|| "archive".into());
-        let pb = create_extract_progress_bar(&format!("Extracting {}", file_label));
-        
-        pb.set_position(25);
-        
-        // Открываем файл и создаем zstd декодер
-        let file = File::open(archive_path)
-            .map_err(|e| PortableSourceError::environment(format!("Failed to open archive: {}", e)))?;
-        let buf_reader = BufReader::new(file);
-        let zstd_decoder = zstd::stream::Decoder::new(buf_reader)
-            .map_err(|e| PortableSourceError::environment(format!("Failed to create zstd decoder: {}", e)))?;
-        
-        pb.set_position(50);
-        
-        // Создаем tar архив из декодированного потока
-        let mut archive = tar::Archive::new(zstd_decoder);
-        
-        pb.set_position(75);
-        
-        // Извлекаем архив
-        archive.unpack(extract_to)
-            .map_err(|e| PortableSourceError::environment(format!("Failed to extract tar archive: {}", e)))?;
-        
-        finish_progress(pb, &format!("Extracted {}", file_label));
-        Ok(())
+    fn extract_cleaning_up_static(archive_path: &Path, extract_to: &Path) -> Result<()> {
+        let before = disk_space::snapshot(extract_to);
+        let result = Self::extract_entries_checked(archive_path, extract_to);
+        if let Err(PortableSourceError::DiskFull { .. }) = &result {
+            // Only what this archive added: extract_to may be the shared ps_env
+            let removed = disk_space::remove_new_entries(extract_to, &before, None);
+            tracing::warn!("Extraction of {:?} aborted for lack of space; removed {} partial entries", archive_path, removed);
+        }
+        result
     }
 
-    fn extract_with_tar_zstd_binary_static(archive_path: &Path, extract_to: &Path) -> Result<()> {
-        use std::fs::File;
-        use std::io::BufReader;
-        
+    fn extract_entries_checked(archive_path: &Path, extract_to: &Path) -> Result<()> {
         let file_label = archive_path.file_name().map(|s| s.to_string_lossy().to_string()).unwrap_or_else(|| "archive".into());
-        let pb = create_extract_progress_bar(&format!("Extracting {}", file_label));
-        
-        pb.set_position(25);
-        
-        // Открываем файл и создаем zstd декодер
-        let file = File::open(archive_path)
-            .map_err(|e| PortableSourceError::environment(format!("Failed to open archive: {}", e)))?;
-        let buf_reader = BufReader::new(file);
-        let zstd_decoder = zstd::stream::Decoder::new(buf_reader)
-            .map_err(|e| PortableSourceError::environment(format!("Failed to create zstd decoder: {}", e)))?;
-        
-        pb.set_position(50);
-        
-        // Создаем tar архив из декодированного потока
-        let mut archive = tar::Archive::new(zstd_decoder);
-        
-        pb.set_position(75);
-        
-        // Извлекаем архив
-        archive.unpack(extract_to)
-            .map_err(|e| PortableSourceError::environment(format!("Failed to extract tar archive: {}", e)))?;
-        
-        finish_progress(pb, &format!("Extracted {}", file_label));
+        let pb = Progress::extract(&format!("Extracting {}", file_label));
+
+        // Распаковываем параллельно, проверяя свободное место
+        let mut space = SpaceCheck::new(extract_to);
+        space.check_now(None)?;
+        extraction::unpack(archive_path, extract_to, &mut space, &mut |percent| pb.set_position(percent))?;
+
+        pb.finish_with_message(&format!("Extracted {}", file_label));
         Ok(())
     }
     
-    fn install_portable_tool(&self, key: &str) -> Result<()> {
+    async fn install_portable_tool(&self, key: &str) -> Result<()> {
         let spec = self.tool_specs.get(key).ok_or_else(|| PortableSourceError::environment(format!("Unknown tool: {}", key)))?;
         let exe_path = self.ps_env_path.join(&spec.executable_path);
         if exe_path.exists() { return Ok(()); }
@@ -356,9 +665,9 @@ impl PortableEnvironmentManager {
             .unwrap_or_else(|| format!("{}.tar.zst", spec.name));
         let archive_path = self.ps_env_path.join(&archive_name);
 
-        self.download_with_resume(&spec.url, &archive_path)?;
+        self.services.downloader.download(&spec.url, &archive_path).await?;
         // Extract to ps_env root; archives are structured with top-level folder (ffmpeg/git/python)
-        self.extract_tar_zstd(&archive_path, &self.ps_env_path)?;
+        Self::extract_archive(&archive_path, &self.ps_env_path).await?;
         let _ = fs::remove_file(&archive_path);
 
         if !exe_path.exists() {
@@ -370,16 +679,225 @@ impl PortableEnvironmentManager {
         Ok(())
     }
 
+    /// Download, verify and unpack a native library component into ps_env/components/<name>;
+    /// a build for another CUDA major version is replaced
+    pub async fn install_component(&self, component: &Component) -> Result<()> {
+        let cuda = self.config_manager.get_cuda_version();
+        if component.is_installed(&self.install_path, cuda.as_ref()) {
+            return Ok(());
+        }
+        let target = component.dir(&self.install_path);
+        let archive_path = self.ps_env_path.join(format!("{}.tar.zst", component.name));
+        let checksum_path = self.ps_env_path.join(format!("{}.tar.zst.sha256", component.name));
+        self.services.downloader.download(&component.checksum_url(cuda.as_ref()), &checksum_path).await?;
+        let expected = components::parse_checksum(&fs::read_to_string(&checksum_path).unwrap_or_default());
+        let _ = fs::remove_file(&checksum_path);
+        let expected = expected.ok_or_else(|| {
+            PortableSourceError::environment(format!("No sha256 published for component {}", component.name))
+        })?;
+
+        self.services.downloader.download(&component.url(cuda.as_ref()), &archive_path).await?;
+        let hashed = archive_path.clone();
+        let actual = tokio::task::spawn_blocking(move || crate::models::file_sha256(&hashed))
+            .await
+            .map_err(|e| PortableSourceError::environment(format!("Hashing failed: {}", e)))??;
+        if actual != expected {
+            let _ = fs::remove_file(&archive_path);
+            return Err(PortableSourceError::environment(format!(
+                "{} archive is corrupt: sha256 {} instead of {}",
+                component.name, actual, expected
+            )));
+        }
+        if target.exists() {
+            fs::remove_dir_all(&target)?;
+        }
+        Self::extract_archive(&archive_path, &target).await?;
+        let _ = fs::remove_file(&archive_path);
+        if component.dll_path(&self.install_path).is_dir() {
+            component.mark_installed(&self.install_path, cuda.as_ref())?;
+        }
+
+        if !component.is_installed(&self.install_path, cuda.as_ref()) {
+            return Err(PortableSourceError::environment(format!(
+                "{} installation failed: no DLL folder at {:?}",
+                component.name,
+                component.dll_path(&self.install_path)
+            )));
+        }
+        Ok(())
+    }
+
+    /// Folder `upgrade-tool` manages for a tool: its archive folder under ps_env
+    fn slot_tool_dir(&self, key: &str) -> Result<String> {
+        match key {
+            "cuda" => Ok("CUDA".to_string()),
+            _ => self
+                .tool_specs
+                .get(key)
+                .map(|spec| spec.extract_path.clone())
+                .ok_or_else(|| PortableSourceError::environment(format!("Unknown tool: {} (expected python, git, ffmpeg or cuda)", key))),
+        }
+    }
+
+    /// Version reported by the tool unpacked in `dir`, after checking that it runs
+    fn verify_tool_dir(&self, key: &str, dir: &Path) -> Result<String> {
+        let run = |exe: &Path, args: &[&str]| -> Result<String> {
+            let argv: Vec<String> = std::iter::once(exe.to_string_lossy().to_string()).chain(args.iter().map(|a| a.to_string())).collect();
+            let request = CommandRequest::from_args(&argv).ok_or_else(|| PortableSourceError::environment("empty command"))?;
+            let output = self.services.executor.execute(&request)?;
+            if !output.success() {
+                return Err(PortableSourceError::environment(format!("'{}' failed: {}", argv.join(" "), output.stderr.trim())));
+            }
+            Ok(if output.stdout.trim().is_empty() { output.stderr } else { output.stdout })
+        };
+        if key == "cuda" {
+            if let Some(reason) = missing_cuda_file(dir) {
+                return Err(PortableSourceError::environment(format!("CUDA in {} is incomplete ({})", dir.display(), reason)));
+            }
+            let out = run(&dir.join("bin").join(if cfg!(windows) { "nvcc.exe" } else { "nvcc" }), &["--version"])?;
+            return parse_nvcc_release(&out).ok_or_else(|| PortableSourceError::environment("nvcc did not report a release"));
+        }
+        let spec = self.tool_specs.get(key).ok_or_else(|| PortableSourceError::environment(format!("Unknown tool: {}", key)))?;
+        let relative = Path::new(&spec.executable_path).strip_prefix(&spec.extract_path).unwrap_or(Path::new(&spec.executable_path));
+        let exe = dir.join(relative);
+        let out = run(&exe, &[if key == "ffmpeg" { "-version" } else { "--version" }])?;
+        if key == "python" {
+            // What repository environments need from the base interpreter
+            run(&exe, &["-c", "import ctypes, sqlite3, ssl, venv"])?;
+        }
+        parse_tool_version(&out)
+            .map(|(major, minor, patch)| format!("{}.{}.{}", major, minor, patch))
+            .or_else(|| {
+                // "ffmpeg version 7.1-essentials_build-www.gyan.dev"
+                let word = out.split_whitespace().skip_while(|w| *w != "version").nth(1)?;
+                Some(word.chars().filter(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_')).collect())
+            })
+            .filter(|v: &String| !v.is_empty())
+            .ok_or_else(|| PortableSourceError::environment(format!("{} did not report a version", exe.display())))
+    }
+
+    /// Download the current archive of a portable tool into its own slot, verify it, switch
+    /// `ps_env/<tool>` to it and verify again; the previous version stays for `rollback_tool`
+    pub async fn upgrade_tool(&self, key: &str) -> Result<ToolUpgrade> {
+        let tool_dir = self.slot_tool_dir(key)?;
+        let current_dir = self.ps_env_path.join(&tool_dir);
+        if !current_dir.exists() {
+            return Err(PortableSourceError::environment(format!(
+                "No portable {} in {}; upgrade-tool replaces tools that setup-env unpacked there",
+                key,
+                self.ps_env_path.display()
+            )));
+        }
+
+        let (url, folder) = if key == "cuda" {
+            let version = self.config_manager.get_cuda_version().ok_or_else(|| PortableSourceError::environment("No CUDA GPU configured"))?;
+            let url = self
+                .config_manager
+                .get_cuda_download_link(Some(&version))
+                .ok_or_else(|| PortableSourceError::environment("CUDA download link not available"))?;
+            (url, cuda_archive_folder(&version))
+        } else {
+            let spec = &self.tool_specs[key];
+            (spec.url.clone(), spec.extract_path.clone())
+        };
+
+        let (staging, archive_path) = tool_slots::upgrade_staging(&self.ps_env_path, &tool_dir);
+        if staging.exists() {
+            fs::remove_dir_all(&staging)?;
+        }
+        output::step(&format!("Downloading {}", key));
+        self.services.downloader.download(&url, &archive_path).await?;
+        let staged = async {
+            Self::extract_archive(&archive_path, &staging).await?;
+            let staged = staging.join(&folder);
+            let version = self.verify_tool_dir(key, &staged)?;
+            Ok::<_, PortableSourceError>((staged, version))
+        }
+        .await;
+        let _ = fs::remove_file(&archive_path);
+        let (staged, version) = match staged {
+            Ok(staged) => staged,
+            Err(e) => {
+                let _ = fs::remove_dir_all(&staging);
+                return Err(PortableSourceError::environment(format!("New {} failed verification, nothing changed: {}", key, e)));
+            }
+        };
+
+        let slot = tool_slots::slot_name(&tool_dir, &version);
+        let current = tool_slots::current_slot(&self.ps_env_path, &tool_dir);
+        let old_version = match &current {
+            Some(current) => current.rsplit_once('@').map(|(_, v)| v.to_string()).unwrap_or_default(),
+            None => self.verify_tool_dir(key, &current_dir).unwrap_or_else(|_| "previous".to_string()),
+        };
+        if tool_slots::slot_name(&tool_dir, &old_version) == slot {
+            let _ = fs::remove_dir_all(&staging);
+            return Ok(ToolUpgrade::UpToDate(slot));
+        }
+        if let Some(reason) = python_upgrade_rejection(key, &old_version, &version) {
+            let _ = fs::remove_dir_all(&staging);
+            return Err(PortableSourceError::environment(reason));
+        }
+        let previous = match current {
+            Some(current) => current,
+            None => tool_slots::adopt(&self.ps_env_path, &tool_dir, &old_version)?,
+        };
+
+        let slot_path = self.ps_env_path.join(&slot);
+        if slot_path.exists() {
+            fs::remove_dir_all(&slot_path)?;
+        }
+        fs::rename(&staged, &slot_path)?;
+        let _ = fs::remove_dir_all(&staging);
+        if key == "cuda" {
+            crate::atomic_write::write(slot_path.join(CUDA_MARKER_FILE), &folder)?;
+        }
+
+        tool_slots::switch(&self.ps_env_path, &tool_dir, &slot)?;
+        if let Err(e) = self.verify_tool_dir(key, &current_dir) {
+            tool_slots::switch(&self.ps_env_path, &tool_dir, &previous)?;
+            return Err(PortableSourceError::environment(format!("{} failed after switching ({}); rolled back to {}", slot, e, previous)));
+        }
+        for removed in tool_slots::prune(&self.ps_env_path, &tool_dir, &[&slot, &previous]) {
+            tracing::info!("Removed old {}", removed);
+        }
+        Ok(ToolUpgrade::Upgraded { from: previous, to: slot })
+    }
+
+    /// Switch a tool back to the version before the last upgrade
+    pub fn rollback_tool(&self, key: &str) -> Result<ToolUpgrade> {
+        let tool_dir = self.slot_tool_dir(key)?;
+        let current = tool_slots::current_slot(&self.ps_env_path, &tool_dir)
+            .ok_or_else(|| PortableSourceError::environment(format!("{} has not been upgraded; there is nothing to roll back to", key)))?;
+        let previous = tool_slots::previous_slot(&self.ps_env_path, &tool_dir)
+            .ok_or_else(|| PortableSourceError::environment(format!("No other version of {} is kept", key)))?;
+        self.verify_tool_dir(key, &self.ps_env_path.join(&previous))?;
+        tool_slots::switch(&self.ps_env_path, &tool_dir, &previous)?;
+        Ok(ToolUpgrade::Upgraded { from: current, to: previous })
+    }
+
     // --- Env for subprocess ---
+    pub fn install_path(&self) -> &Path {
+        &self.install_path
+    }
+
+    pub fn config_manager(&self) -> &ConfigManager {
+        &self.config_manager
+    }
+
     pub fn setup_environment_for_subprocess(&self) -> HashMap<String, String> {
         let mut env_vars: HashMap<String, String> = std::env::vars().collect();
         if !self.ps_env_path.exists() { return env_vars; }
 
         let mut tool_paths: Vec<String> = Vec::new();
-        for (_name, spec) in &self.tool_specs {
+        for spec in self.tool_specs.values() {
             let exe_dir = self.ps_env_path.join(&spec.executable_path).parent().map(|p| p.to_path_buf());
             if let Some(exe_dir) = exe_dir { if exe_dir.exists() { tool_paths.push(exe_dir.to_string_lossy().to_string()); } }
         }
+        for key in ["python", "git"] {
+            if let Some(dir) = self.config_manager.system_tool(key).and_then(Path::parent) {
+                tool_paths.push(dir.to_string_lossy().to_string());
+            }
+        }
 
         // Linux: prepend micromamba base bin and libraries so all tools/rt are visible to project venv
         #[cfg(unix)]
@@ -399,6 +917,11 @@ impl PortableEnvironmentManager {
                 let merged = if current.is_empty() { ld_paths.join(sep) } else { format!("{}{}{}", ld_paths.join(sep), sep, current) };
                 env_vars.insert("LD_LIBRARY_PATH".to_string(), merged);
             }
+
+            // Portable mode: keep caches and user state inside install dir
+            if crate::utils::linux_portable_root().is_some() {
+                env_vars.extend(crate::utils::portable_home_env_vars(&self.install_path));
+            }
         }
 
         // CUDA PATH vars
@@ -425,6 +948,9 @@ impl PortableEnvironmentManager {
             }
         }
 
+        // ROCm / oneAPI / MPS variables; CUDA is handled above with the portable toolkit
+        apply_backend_env(self.config_manager.get_backend(), &mut env_vars);
+
         if !tool_paths.is_empty() {
             let sep = if cfg!(windows) { ";" } else { ":" };
             let current = env_vars.get("PATH").cloned().unwrap_or_default();
@@ -434,33 +960,11 @@ impl PortableEnvironmentManager {
         env_vars
     }
 
-    fn run_in_activated_environment(&self, command: &[String], cwd: Option<&Path>) -> io::Result<std::process::Output> {
-        let envs = self.setup_environment_for_subprocess();
-    
-        // Универсальная логика для всех ОС
-        if command.is_empty() {
-            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Command cannot be empty"));
-        }
-
-        let mut cmd = Command::new(&command[0]); // 1. Запускаем саму программу напрямую (например, "git.exe")
-        cmd.args(&command[1..]);                 // 2. Передаем ей аргументы
-
-        if let Some(dir) = cwd { 
-            cmd.current_dir(dir); 
-        }
-    
-        // Применяем флаг скрытия окна ТОЛЬКО на Windows
-        #[cfg(target_os = "windows")]
-        {
-            use std::os::windows::process::CommandExt;
-            cmd.creation_flags(0x08000000); // 3. Прячем окно для "git.exe", а не для "cmd.exe"
-        }
-    
-        // Остальная часть функции без изменений
-        cmd.envs(&envs)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .output()
+    fn run_in_activated_environment(&self, command: &[String], cwd: Option<&Path>) -> Result<CommandOutput> {
+        // Запускаем саму программу напрямую (например, "git.exe"), окно скрывает исполнитель
+        let request = CommandRequest::from_args(command)
+            .ok_or_else(|| PortableSourceError::command("Command cannot be empty"))?;
+        self.services.executor.execute(&request.cwd(cwd).envs(self.setup_environment_for_subprocess()))
     }
 
     fn extract_version_from_output(&self, tool_name: &str, output: &str) -> String {
@@ -516,20 +1020,19 @@ impl PortableEnvironmentManager {
             };
             match self.run_in_activated_environment(&cmd, None) {
                 Ok(output) => {
-                    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-                    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-                    let text = if stdout.trim().is_empty() { &stderr } else { &stdout };
+                    let (stdout, stderr) = (&output.stdout, &output.stderr);
+                    let text = if stdout.trim().is_empty() { stderr } else { stdout };
                     let version = self.extract_version_from_output(tool, text);
                     if version != "Unknown version" {
-                        log::info!("[OK] {}: {}", tool, version);
+                        tracing::info!("[OK] {}: {}", tool, version);
                     } else {
-                        log::error!("[ERROR] {}: Failed to run (code {:?})", tool, output.status.code());
-                        if !stderr.trim().is_empty() { log::error!("   Error: {}", stderr.trim()); }
+                        tracing::error!("[ERROR] {}: Failed to run (code {:?})", tool, output.code);
+                        if !stderr.trim().is_empty() { tracing::error!("   Error: {}", stderr.trim()); }
                         all_ok = false;
                     }
                 }
                 Err(e) => {
-                    log::error!("[ERROR] {}: Exception occurred - {}", tool, e);
+                    tracing::error!("[ERROR] {}: Exception occurred - {}", tool, e);
                     all_ok = false;
                 }
             }
@@ -538,167 +1041,170 @@ impl PortableEnvironmentManager {
         // Явная проверка CUDA, даже если nvcc отсутствует
         if expect_cuda {
             let cuda_dir = self.ps_env_path.join("CUDA");
-            if !cuda_dir.exists() || !cuda_dir.join("bin").exists() {
-                log::warn!("[WARN] cuda: CUDA not installed in {:?}", cuda_dir);
-                all_ok = false;
+            match inspect_cuda(&cuda_dir) {
+                CudaInstall::Complete => {}
+                CudaInstall::Missing => {
+                    tracing::warn!("[WARN] cuda: CUDA not installed in {:?}", cuda_dir);
+                    all_ok = false;
+                }
+                CudaInstall::Incomplete(reason) => {
+                    tracing::warn!("[WARN] cuda: CUDA in {:?} is incomplete ({}); setup-env reinstalls it", cuda_dir, reason);
+                    all_ok = false;
+                }
             }
         }
         Ok(all_ok)
     }
     
-    /// Setup the portable environment
-    pub async fn setup_environment(&self) -> Result<()> {
-        log::info!("Setting up portable environment...");
-        fs::create_dir_all(&self.ps_env_path)?;
-        // Ensure install_path recorded
-        let mut cfgm = self.config_manager.clone();
-        if cfgm.get_config().install_path.as_os_str().is_empty() {
-            cfgm.set_install_path(self.install_path.clone())?;
-        }
-
-        // Configure GPU inside manager
-        // GPU detection is now handled dynamically
-        // let cfg_now = cfgm.get_config().clone();
+    async fn download_setup_job(&self, job: &SetupJob, progress: &SetupProgress) -> Result<()> {
+        progress.println(&format!("[Setup] Downloading {}... (step {}/{})", job.label, progress.done() + 1, progress.total));
+        self.services.downloader.download(&job.url, &job.archive_path).await?;
+        progress.step(&format!("[Setup] {} downloaded.", job.label));
+        Ok(())
+    }
 
-        // Prepare progress tracking
-        let print_lock = Arc::new(Mutex::new(()));
-        let completed = Arc::new(AtomicUsize::new(0));
-        let mut total_steps: usize = 0;
+    async fn extract_setup_job(ps_env: &Path, job: &SetupJob, progress: &SetupProgress) -> Result<()> {
+        progress.println(&format!("[Setup] Extracting {}...", job.label));
+        match &job.kind {
+            SetupJobKind::Cuda { expected_folder } => {
+                let temp_extract = ps_env.join("__cuda_extract_temp__");
+                if temp_extract.exists() { let _ = fs::remove_dir_all(&temp_extract); }
+                Self::extract_archive(&job.archive_path, &temp_extract).await?;
+                let extracted_sub = temp_extract.join(expected_folder);
+                let cuda_dir = ps_env.join("CUDA");
+                if cuda_dir.exists() { let _ = fs::remove_dir_all(&cuda_dir); }
+                if !extracted_sub.exists() { return Err(PortableSourceError::environment("Expected CUDA folder missing after extraction")); }
+                fs::rename(&extracted_sub, &cuda_dir)?;
+                let _ = fs::remove_dir_all(&temp_extract);
+                let _ = fs::remove_file(&job.archive_path);
+                finish_cuda_install(&cuda_dir, expected_folder)?;
+                progress.step("[Setup] CUDA extracted.");
+            }
+            SetupJobKind::Tool { executable_path } => {
+                Self::extract_archive(&job.archive_path, ps_env).await?;
+                let _ = fs::remove_file(&job.archive_path);
+                let exe_path = ps_env.join(executable_path);
+                if !exe_path.exists() {
+                    return Err(PortableSourceError::environment(format!("Executable not found: {:?}", exe_path)));
+                }
+                progress.step(&format!("[Setup] {} installed.", executable_path));
+            }
+        }
+        Ok(())
+    }
 
-        // Determine total steps before starting any tasks
-        let mut cuda_plan: Option<(String, String)> = None; // (download_link, expected_folder)
+    /// Downloads setup-env still has to make: CUDA and the tools that are not installed
+    fn setup_jobs(&self) -> Vec<SetupJob> {
+        let mut jobs: Vec<SetupJob> = Vec::new();
         if self.config_manager.has_cuda() {
             if let Some(cuda_ver) = self.config_manager.get_cuda_version() {
                 if self.config_manager.get_recommended_backend().contains("cuda") {
                     if let Some(link) = self.config_manager.get_cuda_download_link(Some(&cuda_ver)) {
-                        // count CUDA steps only if not installed
+                        // Skip CUDA task if already installed
                         if !self.is_cuda_installed() {
-                            total_steps += 2; // CUDA download + extract
+                            let version_debug = format!("{:?}", cuda_ver).to_lowercase();
+                            let cleaned = version_debug.replace("cuda", "").replace(['_', '"'], "");
+                            let expected_folder = format!("cuda_{}", cleaned);
+                            let archive_path = self.ps_env_path.join(format!("CUDA_{}.tar.zst", cleaned.to_uppercase()));
+                            jobs.push(SetupJob {
+                                tool: "CUDA".to_string(),
+                                label: "CUDA".to_string(),
+                                url: link,
+                                archive_path,
+                                kind: SetupJobKind::Cuda { expected_folder },
+                            });
                         }
-                        let version_debug = format!("{:?}", cuda_ver).to_lowercase();
-                        let cleaned = version_debug.replace("cuda", "").replace(['_', '"'], "");
-                        let expected_folder = format!("cuda_{}", cleaned);
-                        cuda_plan = Some((link, expected_folder));
                     }
                 }
             }
         }
         // Each tool: download + extract (only for missing ones)
-        let mut tools_to_install: Vec<&str> = Vec::new();
         for key in ["python", "git", "ffmpeg"] {
-            if !self.is_tool_installed(key) {
-                total_steps += 2;
-                tools_to_install.push(key);
+            if self.is_tool_installed(key) { continue; }
+            if let Some(spec) = self.tool_specs.get(key) {
+                let archive_name = Url::parse(&spec.url)
+                    .ok()
+                    .and_then(|u| u.path_segments().and_then(|mut s| s.next_back()).map(|s| s.to_string()))
+                    .unwrap_or_else(|| format!("{}.tar.zst", spec.name));
+                jobs.push(SetupJob {
+                    tool: key.to_string(),
+                    archive_path: self.ps_env_path.join(&archive_name),
+                    label: archive_name,
+                    url: spec.url.clone(),
+                    kind: SetupJobKind::Tool { executable_path: spec.executable_path.clone() },
+                });
             }
         }
+        jobs
+    }
 
-        // Announce total steps
-        {
-            let _g = print_lock.lock().unwrap();
-            println!("[Setup] Total steps: {}", total_steps);
-        }
-
-        // Переходим на последовательную установку для стабильного вывода прогресса
-        let total_c = total_steps; // используем для сообщений
-
-        if let Some((link, expected_folder)) = cuda_plan {
-            // Skip CUDA task if already installed
-            if !self.is_cuda_installed() {
-                let ps_env = self.ps_env_path.clone();
-                let archive_path = ps_env.join(format!(
-                    "CUDA_{}.tar.zst",
-                    expected_folder.trim_start_matches("cuda_").to_uppercase()
-                ));
-                {
-                    let _g = print_lock.lock().unwrap();
-                    let done = completed.load(Ordering::SeqCst);
-                    println!("[Setup] Downloading CUDA archive... (step {}/{})", done + 1, total_c);
-                }
-                PortableEnvironmentManager::download_with_resume_static(link, archive_path.clone())?;
-                completed.fetch_add(1, Ordering::SeqCst);
-                {
-                    let _g = print_lock.lock().unwrap();
-                    let done = completed.load(Ordering::SeqCst);
-                    println!("[Setup] Progress: {}/{} ({:.0}%)", done, total_c, (done as f32/ total_c as f32)*100.0);
-                    println!("[Setup] CUDA downloaded.\n[Setup] Extracting CUDA... (next step)");
-                }
-                let temp_extract = ps_env.join("__cuda_extract_temp__");
-                if temp_extract.exists() { let _ = fs::remove_dir_all(&temp_extract); }
-                PortableEnvironmentManager::extract_tar_zstd_static(archive_path.clone(), temp_extract.clone())?;
-                let extracted_sub = temp_extract.join(&expected_folder);
-                let cuda_dir = ps_env.join("CUDA");
-                if cuda_dir.exists() { let _ = fs::remove_dir_all(&cuda_dir); }
-                if !extracted_sub.exists() { return Err(PortableSourceError::environment("Expected CUDA folder missing after extraction")); }
-                fs::rename(&extracted_sub, &cuda_dir)?;
-                let _ = fs::remove_dir_all(&temp_extract);
-                let _ = fs::remove_file(&archive_path);
-                completed.fetch_add(1, Ordering::SeqCst);
-                {
-                    let _g = print_lock.lock().unwrap();
-                    let done = completed.load(Ordering::SeqCst);
-                    println!("[Setup] CUDA extracted.");
-                    println!("[Setup] Progress: {}/{} ({:.0}%)", done, total_c, (done as f32/ total_c as f32)*100.0);
-                }
-            }
+    /// What `setup_environment` would download, sizes not yet measured
+    pub fn planned_downloads(&self) -> DownloadPlan {
+        let items = self
+            .setup_jobs()
+            .into_iter()
+            .map(|job| PlannedDownload::new(job.tool, job.url, job.archive_path))
+            .collect();
+        DownloadPlan { items, notes: Vec::new() }
+    }
+
+    /// Setup the portable environment
+    #[tracing::instrument(name = "setup_env", skip_all)]
+    pub async fn setup_environment(&self) -> Result<()> {
+        tracing::info!("Setting up portable environment...");
+        fs::create_dir_all(&self.ps_env_path)?;
+        let removed = clean_stale_partials(&self.ps_env_path, STALE_PARTIAL_AGE_SECS, self.services.clock.unix_timestamp());
+        if removed > 0 {
+            tracing::info!("Removed {} stale partial download(s)", removed);
+        }
+        // Ensure install_path recorded
+        let mut cfgm = self.config_manager.clone();
+        if cfgm.get_config().install_path.as_os_str().is_empty() {
+            cfgm.set_install_path(self.install_path.clone())?;
         }
 
-        // Other tools — последовательная установка для корректного отображения прогресса
-        for key in tools_to_install {
-            if let Some(spec) = self.tool_specs.get(key) {
-                let url = spec.url.clone();
-                let archive_name = Url::parse(&url)
-                    .ok()
-                    .and_then(|u| u.path_segments().and_then(|mut s| s.next_back()).map(|s| s.to_string()))
-                    .unwrap_or_else(|| format!("{}.tar.zst", spec.name));
-                let ps_env = self.ps_env_path.clone();
-                let exe_rel = spec.executable_path.clone();
-                {
-                    let _g = print_lock.lock().unwrap();
-                    let done = completed.load(Ordering::SeqCst);
-                    println!("[Setup] Downloading {}... (step {}/{})", archive_name, done + 1, total_c);
-                }
-                let archive_path = ps_env.join(&archive_name);
-                PortableEnvironmentManager::download_with_resume_static(url, archive_path.clone())?;
-                completed.fetch_add(1, Ordering::SeqCst);
-                {
-                    let _g = print_lock.lock().unwrap();
-                    let done = completed.load(Ordering::SeqCst);
-                    println!("[Setup] Progress: {}/{} ({:.0}%)", done, total_c, (done as f32/ total_c as f32)*100.0);
-                    println!("[Setup] Extracting {}...", archive_name);
-                }
-                PortableEnvironmentManager::extract_tar_zstd_static(archive_path.clone(), ps_env.clone())?;
-                let _ = fs::remove_file(&archive_path);
-                let exe_path = ps_env.join(&exe_rel);
-                if !exe_path.exists() {
-                    return Err(PortableSourceError::environment(format!("Executable not found: {:?}", exe_path)));
+        // Configure GPU inside manager
+        // GPU detection is now handled dynamically
+        // let cfg_now = cfgm.get_config().clone();
+
+        // Determine total steps before starting any tasks
+        let jobs = self.setup_jobs();
+
+        let progress = SetupProgress::new(jobs.len() * 2);
+        progress.println(&format!("[Setup] Total steps: {}", progress.total));
+
+        if setup_pipeline_enabled() && jobs.len() > 1 {
+            // Скачиваем следующий архив, пока распаковывается предыдущий.
+            // Один распаковщик и очередь на один архив: диск не перегружается, tmp не разрастается.
+            let (tx, mut rx) = tokio::sync::mpsc::channel::<SetupJob>(1);
+            let downloads = async {
+                let tx = tx;
+                for job in jobs {
+                    self.download_setup_job(&job, &progress).await?;
+                    // Распаковщик упал — его ошибка вернётся ниже
+                    if tx.send(job).await.is_err() { break; }
                 }
-                completed.fetch_add(1, Ordering::SeqCst);
-                {
-                    let _g = print_lock.lock().unwrap();
-                    let done = completed.load(Ordering::SeqCst);
-                    println!("[Setup] {} installed.", exe_rel);
-                    println!("[Setup] Progress: {}/{} ({:.0}%)", done, total_c, (done as f32/ total_c as f32)*100.0);
+                Ok::<(), PortableSourceError>(())
+            };
+            let extractions = async {
+                while let Some(job) = rx.recv().await {
+                    Self::extract_setup_job(&self.ps_env_path, &job, &progress).await?;
                 }
+                Ok::<(), PortableSourceError>(())
+            };
+            let (download_result, extract_result) = tokio::join!(downloads, extractions);
+            extract_result.and(download_result)?;
+        } else {
+            for job in &jobs {
+                self.download_setup_job(job, &progress).await?;
+                Self::extract_setup_job(&self.ps_env_path, job, &progress).await?;
             }
         }
 
         // Итоговая печать прогресса (только если не было 100%)
-        let total = total_steps;
-        let done = completed.load(Ordering::SeqCst);
-        if done < total {
-            let pct = if total > 0 { (done as f32 / total as f32) * 100.0 } else { 100.0 };
-            let _g = print_lock.lock().unwrap();
-            println!("[Setup] Progress: {}/{} ({:.0}%)", done, total, pct);
-        }
-
-        // Ensure final 100% line if not printed
-        {
-            let done = completed.load(Ordering::SeqCst);
-            if done < total {
-                let pct = if total > 0 { (done as f32 / total as f32) * 100.0 } else { 100.0 };
-                let _g = print_lock.lock().unwrap();
-                println!("[Setup] Progress: {}/{} ({:.0}%)", done, total, pct);
-            }
+        let done = progress.done();
+        if done < progress.total {
+            progress.println(&format!("[Setup] Progress: {}", progress.summary(done)));
         }
 
         // Install Git LFS (always run to ensure it's initialized)
@@ -715,14 +1221,26 @@ impl PortableEnvironmentManager {
     }
 
     /// Setup environment with progress callback.
-    /// The callback receives `(tool_key, steps_done, total_steps)`.
-    /// tool_key is one of: "python", "git", "ffmpeg", "cuda".
+    /// Tasks run in parallel, but the callback is never called concurrently and receives
+    /// events in `seq` order: one `Planned` event, then per task `Downloading`,
+    /// `Extracting` and `Installed`, and finally `Completed`, also when the setup fails.
     pub async fn setup_environment_with_progress<F>(&self, progress_cb: F) -> Result<()>
     where
-        F: Fn(String, usize, usize) + Send + Sync + 'static,
+        F: Fn(SetupEvent) + Send + Sync + 'static,
     {
-        log::info!("Setting up portable environment...");
+        let reporter = Arc::new(SetupReporter::new(progress_cb));
+        let result = self.run_setup(&reporter).await;
+        reporter.report("setup", SetupPhase::Completed, false);
+        result
+    }
+
+    async fn run_setup(&self, reporter: &Arc<SetupReporter>) -> Result<()> {
+        tracing::info!("Setting up portable environment...");
         fs::create_dir_all(&self.ps_env_path)?;
+        let removed = clean_stale_partials(&self.ps_env_path, STALE_PARTIAL_AGE_SECS, self.services.clock.unix_timestamp());
+        if removed > 0 {
+            tracing::info!("Removed {} stale partial download(s)", removed);
+        }
         let mut cfgm = self.config_manager.clone();
         if cfgm.get_config().install_path.as_os_str().is_empty() {
             cfgm.set_install_path(self.install_path.clone())?;
@@ -731,116 +1249,57 @@ impl PortableEnvironmentManager {
         // GPU detection is now handled dynamically
         // let cfg_now = cfgm.get_config().clone();
 
-        let completed = Arc::new(AtomicUsize::new(0));
-        let cb_arc: Arc<dyn Fn(String, usize, usize) + Send + Sync> = Arc::new(progress_cb);
-        let mut total_steps: usize = 0;
-
-        // CUDA plan detection same as in setup_environment
-        let mut cuda_plan: Option<(String, String)> = None; // (download_link, expected_folder)
-        if self.config_manager.has_cuda() {
-            if let Some(cuda_ver) = self.config_manager.get_cuda_version() {
-                if self.config_manager.get_recommended_backend().contains("cuda") {
-                    if let Some(link) = self.config_manager.get_cuda_download_link(Some(&cuda_ver)) {
-                        if !self.is_cuda_installed() { total_steps += 2; }
-                        let version_debug = format!("{:?}", cuda_ver).to_lowercase();
-                        let cleaned = version_debug.replace("cuda", "").replace(['_', '"'], "");
-                        let expected_folder = format!("cuda_{}", cleaned);
-                        cuda_plan = Some((link, expected_folder));
-                    }
-                }
-            }
-        }
-        // python, git, ffmpeg each: download + extract (only for missing ones)
-        let mut tools_to_install: Vec<&str> = Vec::new();
-        for key in ["python", "git", "ffmpeg"] {
-            if !self.is_tool_installed(key) {
-                total_steps += 2;
-                tools_to_install.push(key);
-            }
-        }
+        // Same plan as setup_environment and planned_downloads
+        let jobs = self.setup_jobs();
 
         // Tell UI initial total
-        cb_arc.clone()("init".to_string(), 0, total_steps);
+        reporter.plan(jobs.len() * 2);
 
+        // All tasks in parallel
         let mut handles = Vec::new();
-        let total_c = total_steps;
-        let cb_cuda = cb_arc.clone();
-        if let Some((link, expected_folder)) = cuda_plan {
-            if !self.is_cuda_installed() {
+        for job in jobs {
+            let task = job.tool.to_lowercase();
             let ps_env = self.ps_env_path.clone();
-            let archive_path = ps_env.join(format!(
-                "CUDA_{}.tar.zst",
-                expected_folder.trim_start_matches("cuda_").to_uppercase()
-            ));
-            let completed_c = completed.clone();
-            handles.push(tokio::task::spawn_blocking(move || {
-                // Step: CUDA download
-                let done_now = completed_c.load(Ordering::SeqCst);
-                cb_cuda("cuda".to_string(), done_now, total_c);
-                PortableEnvironmentManager::download_with_resume_static(link, archive_path.clone())?;
-                completed_c.fetch_add(1, Ordering::SeqCst);
-                // Step: CUDA extract
-                let done_now = completed_c.load(Ordering::SeqCst);
-                cb_cuda("cuda".to_string(), done_now, total_c);
-                let temp_extract = ps_env.join("__cuda_extract_temp__");
-                if temp_extract.exists() { let _ = fs::remove_dir_all(&temp_extract); }
-                PortableEnvironmentManager::extract_tar_zstd_static(archive_path.clone(), temp_extract.clone())?;
-                let extracted_sub = temp_extract.join(&expected_folder);
-                let cuda_dir = ps_env.join("CUDA");
-                if cuda_dir.exists() { let _ = fs::remove_dir_all(&cuda_dir); }
-                if !extracted_sub.exists() { return Err(PortableSourceError::environment("Expected CUDA folder missing after extraction")); }
-                fs::rename(&extracted_sub, &cuda_dir)?;
-                let _ = fs::remove_dir_all(&temp_extract);
-                let _ = fs::remove_file(&archive_path);
-                completed_c.fetch_add(1, Ordering::SeqCst);
-                // Emit final state after finishing CUDA extraction
-                let done_now = completed_c.load(Ordering::SeqCst);
-                cb_cuda("cuda".to_string(), done_now, total_c);
+            let reporter = reporter.clone();
+            let downloader = self.services.downloader.clone();
+            handles.push(tokio::spawn(async move {
+                // Step: download
+                reporter.report(&task, SetupPhase::Downloading, false);
+                downloader.download(&job.url, &job.archive_path).await?;
+                // Step: extract
+                reporter.report(&task, SetupPhase::Extracting, true);
+                match &job.kind {
+                    SetupJobKind::Cuda { expected_folder } => {
+                        let temp_extract = ps_env.join("__cuda_extract_temp__");
+                        if temp_extract.exists() { let _ = fs::remove_dir_all(&temp_extract); }
+                        PortableEnvironmentManager::extract_archive(&job.archive_path, &temp_extract).await?;
+                        let extracted_sub = temp_extract.join(expected_folder);
+                        let cuda_dir = ps_env.join("CUDA");
+                        if cuda_dir.exists() { let _ = fs::remove_dir_all(&cuda_dir); }
+                        if !extracted_sub.exists() { return Err(PortableSourceError::environment("Expected CUDA folder missing after extraction")); }
+                        fs::rename(&extracted_sub, &cuda_dir)?;
+                        let _ = fs::remove_dir_all(&temp_extract);
+                        let _ = fs::remove_file(&job.archive_path);
+                        finish_cuda_install(&cuda_dir, expected_folder)?;
+                    }
+                    SetupJobKind::Tool { executable_path } => {
+                        PortableEnvironmentManager::extract_archive(&job.archive_path, &ps_env).await?;
+                        let _ = fs::remove_file(&job.archive_path);
+                        let exe_path = ps_env.join(executable_path);
+                        if !exe_path.exists() {
+                            return Err(PortableSourceError::environment(format!("Executable not found: {:?}", exe_path)));
+                        }
+                    }
+                }
+                // Emit final state after the extraction finished
+                reporter.report(&task, SetupPhase::Installed, true);
                 Ok::<(), PortableSourceError>(())
             }));
-            }
-        }
-
-        // Other tools in parallel
-        for key in tools_to_install {
-            if let Some(spec) = self.tool_specs.get(key) {
-                let url = spec.url.clone();
-                let archive_name = Url::parse(&url)
-                    .ok()
-                    .and_then(|u| u.path_segments().and_then(|mut s| s.next_back()).map(|s| s.to_string()))
-                    .unwrap_or_else(|| format!("{}.tar.zst", spec.name));
-                let ps_env = self.ps_env_path.clone();
-                let exe_rel = spec.executable_path.clone();
-                let completed_t = completed.clone();
-                let cb_t = cb_arc.clone();
-                handles.push(tokio::task::spawn_blocking(move || {
-                    // Step: download
-                    let done_now = completed_t.load(Ordering::SeqCst);
-                    cb_t(key.to_string(), done_now, total_c);
-                    let archive_path = ps_env.join(&archive_name);
-                    PortableEnvironmentManager::download_with_resume_static(url, archive_path.clone())?;
-                    completed_t.fetch_add(1, Ordering::SeqCst);
-                    // Step: extract
-                    let done_now = completed_t.load(Ordering::SeqCst);
-                    cb_t(key.to_string(), done_now, total_c);
-                    PortableEnvironmentManager::extract_tar_zstd_static(archive_path.clone(), ps_env.clone())?;
-                    let _ = fs::remove_file(&archive_path);
-                    let exe_path = ps_env.join(&exe_rel);
-                    if !exe_path.exists() {
-                        return Err(PortableSourceError::environment(format!("Executable not found: {:?}", exe_path)));
-                    }
-                    completed_t.fetch_add(1, Ordering::SeqCst);
-                    // Emit final update after tool extraction completes
-                    let done_now = completed_t.load(Ordering::SeqCst);
-                    cb_t(key.to_string(), done_now, total_c);
-                    Ok::<(), PortableSourceError>(())
-                }));
-            }
         }
 
         for h in handles {
             let res = h.await.map_err(|e| PortableSourceError::environment(format!("Join error: {}", e)))?;
-            if let Err(err) = res { return Err(err); }
+            res?
         }
 
         // CUDA paths are now computed dynamically when needed
@@ -863,7 +1322,7 @@ impl PortableEnvironmentManager {
     
     /// Install a specific tool
     pub async fn install_tool(&self, tool_name: &str) -> Result<()> {
-        log::info!("Installing tool: {}", tool_name);
+        tracing::info!("Installing tool: {}", tool_name);
         
         match tool_name {
             "python" => self.install_python().await,
@@ -876,11 +1335,11 @@ impl PortableEnvironmentManager {
         }
     }
     
-    async fn install_python(&self) -> Result<()> { self.install_portable_tool("python") }
+    async fn install_python(&self) -> Result<()> { self.install_portable_tool("python").await }
     
     async fn install_git(&self) -> Result<()> {
         // Install Git first
-        self.install_portable_tool("git")?;
+        self.install_portable_tool("git").await?;
         
         // Configure Git to use OpenSSL backend to prevent SSL/TLS issues
         if let Some(git_exe) = self.get_git_executable() {
@@ -898,24 +1357,24 @@ impl PortableEnvironmentManager {
             
             match output {
                 Ok(result) if result.status.success() => {
-                    log::info!("Git configured to use OpenSSL backend");
+                    tracing::info!("Git configured to use OpenSSL backend");
                 }
                 Ok(result) => {
                     let error_msg = String::from_utf8_lossy(&result.stderr);
-                    log::warn!("Failed to configure Git SSL backend: {}", error_msg);
+                    tracing::warn!("Failed to configure Git SSL backend: {}", error_msg);
                 }
                 Err(e) => {
-                    log::warn!("Failed to run git config command: {}", e);
+                    tracing::warn!("Failed to run git config command: {}", e);
                 }
             }
         } else {
-            log::warn!("Git executable not found after installation, cannot configure SSL backend");
+            tracing::warn!("Git executable not found after installation, cannot configure SSL backend");
         }
         
         Ok(())
     }
     
-    async fn install_ffmpeg(&self) -> Result<()> { self.install_portable_tool("ffmpeg") }
+    async fn install_ffmpeg(&self) -> Result<()> { self.install_portable_tool("ffmpeg").await }
     
     async fn install_cuda(&self) -> Result<()> {
         if self.config_manager.has_cuda() {
@@ -923,7 +1382,7 @@ impl PortableEnvironmentManager {
                 if !self.config_manager.get_recommended_backend().contains("cuda") { return Ok(()); }
 
                 let cuda_dir = self.ps_env_path.join("CUDA");
-                if cuda_dir.join("bin").exists() { return Ok(()); }
+                if self.is_cuda_installed() { return Ok(()); }
 
                 // Ссылка на архив
                 let link = self
@@ -932,17 +1391,16 @@ impl PortableEnvironmentManager {
                     .ok_or_else(|| PortableSourceError::environment("CUDA download link not available"))?;
 
                 // Вычисляем версию в имени папки: CUDA_118.tar.zst -> cuda_118
-                let version_debug = format!("{:?}", cuda_ver).to_lowercase();
-                let cleaned = version_debug.replace("cuda", "").replace(['_', '"'], "");
-                let expected_folder = format!("cuda_{}", cleaned);
+                let expected_folder = cuda_archive_folder(&cuda_ver);
+                let cleaned = expected_folder.trim_start_matches("cuda_");
 
                 let archive_path = self.ps_env_path.join(format!("CUDA_{}.tar.zst", cleaned.to_uppercase()));
-                self.download_with_resume(&link, &archive_path)?;
+                self.services.downloader.download(&link, &archive_path).await?;
 
                 // Распаковка во временную директорию
                 let temp_extract = self.ps_env_path.join("__cuda_extract_temp__");
                 if temp_extract.exists() { let _ = fs::remove_dir_all(&temp_extract); }
-                self.extract_tar_zstd(&archive_path, &temp_extract)?;
+                Self::extract_archive(&archive_path, &temp_extract).await?;
 
                 // Переименование папки cuda_{ver} -> CUDA (строго без манкипатчей)
                 let extracted_sub = temp_extract.join(&expected_folder);
@@ -966,12 +1424,14 @@ impl PortableEnvironmentManager {
                         Ok(_) => break,
                         Err(e) if attempts < max_attempts => {
                             attempts += 1;
-                            log::warn!("Attempt {} to rename CUDA folder failed: {}", attempts, e);
+                            tracing::warn!("Attempt {} to rename CUDA folder failed: {}", attempts, e);
                             std::thread::sleep(std::time::Duration::from_millis(500));
                         }
                         Err(e) => {
                             // Если переименование не удалось, попробуем копирование
-                            log::warn!("Rename failed, trying copy: {}", e);
+                            tracing::warn!("Rename failed, trying copy: {}", e);
+                            fs::create_dir_all(&cuda_dir)?;
+                            crate::atomic_write::write(cuda_dir.join(CUDA_MARKER_FILE), CUDA_PARTIAL)?;
                             Self::copy_dir_recursive(&extracted_sub, &cuda_dir)?;
                             break;
                         }
@@ -980,11 +1440,9 @@ impl PortableEnvironmentManager {
                 let _ = fs::remove_dir_all(&temp_extract);
                 let _ = fs::remove_file(&archive_path);
 
-                if !cuda_dir.join("bin").exists() {
-                    return Err(PortableSourceError::environment("CUDA installation failed: bin not found"));
-                }
+                finish_cuda_install(&cuda_dir, &expected_folder)?;
                 // CUDA paths are now computed dynamically when needed
-                log::info!("Successfully processed CUDA");
+                tracing::info!("Successfully processed CUDA");
             }
         }
         Ok(())
@@ -992,48 +1450,19 @@ impl PortableEnvironmentManager {
     
     /// Get path to Python executable
     pub fn get_python_executable(&self) -> Option<PathBuf> {
-        if cfg!(windows) {
-            let p = self.ps_env_path.join("python").join("python.exe");
-            if p.exists() { return Some(p); }
-        } else {
-            // Linux: prefer micromamba base if present
-            let base = self.install_path.join("ps_env").join("mamba_env").join("bin").join("python");
-            if base.exists() { return Some(base); }
-            let p = self.ps_env_path.join("python").join("bin").join("python");
-            if p.exists() { return Some(p); }
-        }
-        None
+        self.find_tool_executable("python")
     }
 
     // Removed: we universally use `python -m pip` via repository_installer
     
     /// Get path to Git executable
     pub fn get_git_executable(&self) -> Option<PathBuf> {
-        if cfg!(windows) {
-            let git_path = self.ps_env_path.join("git").join("bin").join("git.exe");
-            return if git_path.exists() { Some(git_path) } else { None };
-        } else {
-            // Prefer micromamba base
-            let m_git = self.install_path.join("ps_env").join("mamba_env").join("bin").join("git");
-            if m_git.exists() { return Some(m_git); }
-            let p = self.ps_env_path.join("git").join("bin").join("git");
-            if p.exists() { return Some(p); }
-            None
-        }
+        self.find_tool_executable("git")
     }
 
     /// Get path to FFmpeg executable
     pub fn get_ffmpeg_executable(&self) -> Option<PathBuf> {
-        if cfg!(windows) {
-            let ffmpeg_path = self.ps_env_path.join("ffmpeg").join("ffmpeg.exe");
-            return if ffmpeg_path.exists() { Some(ffmpeg_path) } else { None };
-        } else {
-            let m_ff = self.install_path.join("ps_env").join("mamba_env").join("bin").join("ffmpeg");
-            if m_ff.exists() { return Some(m_ff); }
-            let p = self.ps_env_path.join("ffmpeg").join("ffmpeg");
-            if p.exists() { return Some(p); }
-            None
-        }
+        self.find_tool_executable("ffmpeg")
     }
     
     /// Detailed environment status (summary)
@@ -1068,13 +1497,12 @@ impl PortableEnvironmentManager {
             let cmd: Vec<String> = std::iter::once(tool.to_string()).chain(args.into_iter().map(|s| s.to_string())).collect();
             match self.run_in_activated_environment(&cmd, None) {
                 Ok(output) => {
-                    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-                    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-                    let version = self.extract_version_from_output(tool, &stdout);
+                    let (stdout, stderr) = (&output.stdout, &output.stderr);
+                    let version = self.extract_version_from_output(tool, stdout);
                     if version != "Unknown version" {
                         status.tools_status.insert(tool.to_string(), ToolStatus { working: true, version: Some(version), error: None, stderr: None });
                     } else {
-                        status.tools_status.insert(tool.to_string(), ToolStatus { working: false, version: None, error: Some(format!("Exit code {:?}", output.status.code())), stderr: if stderr.trim().is_empty() { None } else { Some(stderr.trim().to_string()) } });
+                        status.tools_status.insert(tool.to_string(), ToolStatus { working: false, version: None, error: Some(format!("Exit code {:?}", output.code)), stderr: if stderr.trim().is_empty() { None } else { Some(stderr.trim().to_string()) } });
                         status.all_tools_working = false;
                     }
                 }
@@ -1095,7 +1523,7 @@ impl PortableEnvironmentManager {
         let mut installed_tools = HashMap::new();
         for (name, spec) in &self.tool_specs {
             let tool_dir = self.ps_env_path.join(&spec.extract_path);
-            installed_tools.insert(name.clone(), tool_dir.exists());
+            installed_tools.insert(name.clone(), tool_dir.exists() || self.config_manager.system_tool(name).is_some());
         }
         EnvironmentInfo {
             base_env_exists,
@@ -1112,11 +1540,11 @@ impl PortableEnvironmentManager {
             if let Some(_cv) = self.config_manager.get_cuda_version() {
                 if let Some(base) = self.config_manager.get_cuda_base_path() {
                     if !base.exists() {
-                        log::warn!("CUDA is configured but not installed at {}", base.display());
+                        tracing::warn!("CUDA is configured but not installed at {}", base.display());
                     } else {
                         if let Some(bin) = self.config_manager.get_cuda_bin() {
                             if !bin.exists() {
-                                log::warn!("CUDA installation incomplete: bin not found at {}", bin.display());
+                                tracing::warn!("CUDA installation incomplete: bin not found at {}", bin.display());
                             }
                         }
                     }
@@ -1152,7 +1580,7 @@ impl PortableEnvironmentManager {
 
     /// Install Git LFS
     async fn install_git_lfs(&self) -> Result<()> {
-        log::info!("Installing Git LFS...");
+        tracing::info!("Installing Git LFS...");
         
         // Check if git is available first
         if let Some(git_exe) = self.get_git_executable() {
@@ -1171,7 +1599,7 @@ impl PortableEnvironmentManager {
                 .map_err(|e| PortableSourceError::environment(format!("Failed to run git lfs install: {}", e)))?;
             
             if output.status.success() {
-                log::info!("Git LFS initialized successfully!");
+                tracing::info!("Git LFS initialized successfully!");
                 Ok(())
             } else {
                 let error_msg = String::from_utf8_lossy(&output.stderr);
@@ -1188,42 +1616,78 @@ impl PortableEnvironmentManager {
 // Удалены функции sanitize_windows_path_for_7z и format_7z_out_arg
 // так как они больше не нужны для tar zstd
 
-// ===== Progress helpers =====
-fn create_download_progress_bar(total_opt: Option<u64>, prefix: &str) -> ProgressBar {
-    match total_opt {
-        Some(total) if total > 0 => {
-            let pb = ProgressBar::new(total);
-            let style = ProgressStyle::with_template("{prefix:.bold} [{bar:40.cyan/blue}] {percent:>3}% {msg} ETA {eta}")
-                .unwrap()
-                .progress_chars("=>-");
-            pb.set_style(style);
-            pb.set_prefix(prefix.to_string());
-            pb
-        }
-        _ => {
-            let pb = ProgressBar::new_spinner();
-            pb.set_style(ProgressStyle::with_template("{prefix:.bold} {spinner} {msg}").unwrap());
-            pb.set_prefix(prefix.to_string());
-            pb.enable_steady_tick(std::time::Duration::from_millis(120));
-            pb
+// ===== GPU vendor environment =====
+
+/// Prepend existing directories to a path-list variable
+fn prepend_env_paths(env_vars: &mut HashMap<String, String>, key: &str, dirs: &[PathBuf]) {
+    let dirs: Vec<String> = dirs.iter().filter(|d| d.exists()).map(|d| d.to_string_lossy().to_string()).collect();
+    if dirs.is_empty() { return; }
+    let sep = if cfg!(windows) { ";" } else { ":" };
+    let merged = match env_vars.get(key).filter(|v| !v.is_empty()) {
+        Some(current) => format!("{}{}{}", dirs.join(sep), sep, current),
+        None => dirs.join(sep),
+    };
+    env_vars.insert(key.to_string(), merged);
+}
+
+/// Add vendor runtime variables for non-CUDA backends. Variables already set by the user win.
+pub fn apply_backend_env(backend: Backend, env_vars: &mut HashMap<String, String>) {
+    let mut bin_dirs: Vec<PathBuf> = Vec::new();
+    let mut lib_dirs: Vec<PathBuf> = Vec::new();
+    let mut set_default = |key: &str, value: String| { env_vars.entry(key.to_string()).or_insert(value); };
+
+    match backend {
+        Backend::Rocm => {
+            if let Some(root) = rocm_root() {
+                set_default("ROCM_PATH", root.to_string_lossy().to_string());
+                set_default("HIP_PATH", root.to_string_lossy().to_string());
+                bin_dirs.push(root.join("bin"));
+                lib_dirs.push(root.join("lib"));
+            }
+        }
+        Backend::Xpu => {
+            if let Some(root) = oneapi_root() {
+                set_default("ONEAPI_ROOT", root.to_string_lossy().to_string());
+                // Кэш JIT-ядер SYCL между запусками
+                set_default("SYCL_CACHE_PERSISTENT", "1".to_string());
+                bin_dirs.push(root.join("compiler").join("latest").join("bin"));
+                lib_dirs.push(root.join("compiler").join("latest").join("lib"));
+                lib_dirs.push(root.join("mkl").join("latest").join("lib"));
+            }
+        }
+        Backend::Mps => set_default("PYTORCH_ENABLE_MPS_FALLBACK", "1".to_string()),
+        Backend::Cuda | Backend::Cpu => {}
+    }
+
+    // ROCm ignores CUDA_VISIBLE_DEVICES on some builds; mirror the user's device selection at
+    // the runtime level, which every ROCm library honors. HIP_VISIBLE_DEVICES filters the
+    // devices ROCR left (renumbered from 0), so it must not repeat the physical indices
+    if backend == Backend::Rocm
+        && !env_vars.contains_key("ROCR_VISIBLE_DEVICES")
+        && !env_vars.contains_key("HIP_VISIBLE_DEVICES")
+    {
+        if let Some(devices) = env_vars.get("CUDA_VISIBLE_DEVICES").cloned() {
+            env_vars.insert("ROCR_VISIBLE_DEVICES".to_string(), devices);
         }
     }
-}
 
-fn create_extract_progress_bar(prefix: &str) -> ProgressBar {
-    let pb = ProgressBar::new(100);
-    let style = ProgressStyle::with_template("{prefix:.bold} [{bar:40.magenta/blue}] {pos:>3}% ETA {eta}")
-        .unwrap()
-        .progress_chars("=>-");
-    pb.set_style(style);
-    pb.set_prefix(prefix.to_string());
-    pb
+    prepend_env_paths(env_vars, "PATH", &bin_dirs);
+    if cfg!(windows) {
+        // DLLs are resolved via PATH on Windows
+        prepend_env_paths(env_vars, "PATH", &lib_dirs);
+    } else {
+        prepend_env_paths(env_vars, "LD_LIBRARY_PATH", &lib_dirs);
+    }
 }
 
-fn finish_progress(pb: ProgressBar, msg: &str) {
-    pb.finish_with_message(msg.to_string());
+/// Current process environment plus vendor variables for the detected backend (run-repo)
+pub fn run_environment() -> HashMap<String, String> {
+    let mut env_vars: HashMap<String, String> = std::env::vars().collect();
+    apply_backend_env(Backend::detect(), &mut env_vars);
+    env_vars
 }
 
+// ===== Progress helpers =====
 fn parse_total_from_content_range(hv: &str) -> Option<u64> {
     // Expected like: "bytes start-end/total"
     if let Some(slash_pos) = hv.rfind('/') {
@@ -1235,7 +1699,7 @@ fn parse_total_from_content_range(hv: &str) -> Option<u64> {
 
 // Функция extract_percent удалена, так как tar не выводит прогресс в процентах
 
-fn update_download_pb_message(pb: &ProgressBar, downloaded: u64, total_opt: Option<u64>, start: Instant) {
+fn update_download_pb_message(pb: &Progress, downloaded: u64, total_opt: Option<u64>, start: Instant) {
     let elapsed = start.elapsed().as_secs_f64();
     let mb_downloaded = bytes_to_mb(downloaded);
     let speed_mb_s = if elapsed > 0.0 { bytes_to_mb((downloaded as f64 / elapsed) as u64) } else { 0.0 };
@@ -1277,4 +1741,245 @@ pub struct EnvironmentInfo {
     pub base_env_pip: Option<String>,
     pub installed_tools: HashMap<String, bool>,
     pub paths: EnvironmentPaths,
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{MockDownloader, MockServices};
+
+    const PYTHON_EXE: &str = if cfg!(windows) { "python.exe" } else { "bin/python" };
+
+    fn env(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn rocm_gets_the_cuda_device_selection_at_the_runtime_level() {
+        let mut vars = env(&[("CUDA_VISIBLE_DEVICES", "1")]);
+        apply_backend_env(Backend::Rocm, &mut vars);
+        assert_eq!(vars.get("ROCR_VISIBLE_DEVICES").map(String::as_str), Some("1"));
+        // Device 1 is device 0 for HIP once ROCR selected it
+        assert!(!vars.contains_key("HIP_VISIBLE_DEVICES"));
+    }
+
+    #[test]
+    fn rocm_device_selection_set_by_the_user_wins() {
+        let mut vars = env(&[("CUDA_VISIBLE_DEVICES", "1"), ("HIP_VISIBLE_DEVICES", "0")]);
+        apply_backend_env(Backend::Rocm, &mut vars);
+        assert!(!vars.contains_key("ROCR_VISIBLE_DEVICES"));
+
+        let mut vars = env(&[("CUDA_VISIBLE_DEVICES", "1"), ("ROCR_VISIBLE_DEVICES", "0,1")]);
+        apply_backend_env(Backend::Rocm, &mut vars);
+        assert_eq!(vars.get("ROCR_VISIBLE_DEVICES").map(String::as_str), Some("0,1"));
+    }
+
+    #[test]
+    fn cuda_device_selection_is_not_mirrored_for_other_backends() {
+        let mut vars = env(&[("CUDA_VISIBLE_DEVICES", "1")]);
+        apply_backend_env(Backend::Cuda, &mut vars);
+        assert!(!vars.contains_key("ROCR_VISIBLE_DEVICES") && !vars.contains_key("HIP_VISIBLE_DEVICES"));
+    }
+
+    /// Portable Python archive whose interpreter file holds `content`
+    fn python_archive(content: &str) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o755);
+        header.set_cksum();
+        builder.append_data(&mut header, format!("python/{}", PYTHON_EXE), content.as_bytes()).unwrap();
+        zstd::encode_all(&builder.into_inner().unwrap()[..], 3).unwrap()
+    }
+
+    /// Install with python 3.11.9 in a slot; the archive of the next Python says `reports`
+    fn slotted_install(install: &Path, reports: &str) -> (PortableEnvironmentManager, MockServices) {
+        let ps_env = install.join("ps_env");
+        fs::create_dir_all(ps_env.join("python").join(PYTHON_EXE).parent().unwrap()).unwrap();
+        fs::write(ps_env.join("python").join(PYTHON_EXE), "old").unwrap();
+        tool_slots::adopt(&ps_env, "python", "3.11.9").unwrap();
+
+        let mocks = MockServices::with_downloader(MockDownloader::new().with_file(ToolLinks::Python311.url(), python_archive("new")));
+        mocks.executor.succeed_with("--version", reports);
+        let config = ConfigManager::new(Some(install.join("portablesource_config.json"))).unwrap();
+        let manager = PortableEnvironmentManager::with_config(install.to_path_buf(), config).with_services(mocks.services());
+        (manager, mocks)
+    }
+
+    #[tokio::test]
+    async fn components_are_unpacked_only_when_the_archive_matches_its_sha256() {
+        use sha2::{Digest, Sha256};
+        let dir = tempfile::tempdir().unwrap();
+        let zlib = components::find("zlib").unwrap();
+        let archive = python_archive("dll");
+        let published = format!("{:x}  zlib.tar.zst", Sha256::digest(&archive));
+        let config = ConfigManager::new(Some(dir.path().join("portablesource_config.json"))).unwrap();
+
+        let corrupt = MockDownloader::new().with_file(&zlib.checksum_url(None), published.clone()).with_file(&zlib.url(None), b"truncated".to_vec());
+        let manager = PortableEnvironmentManager::with_config(dir.path().to_path_buf(), config.clone())
+            .with_services(MockServices::with_downloader(corrupt).services());
+        let err = manager.install_component(zlib).await.unwrap_err();
+        assert!(err.to_string().contains("zlib archive is corrupt"), "{}", err);
+        assert!(!zlib.dir(dir.path()).exists());
+        assert!(!dir.path().join("ps_env/zlib.tar.zst").exists());
+
+        let intact = MockDownloader::new().with_file(&zlib.checksum_url(None), published).with_file(&zlib.url(None), archive);
+        let manager = PortableEnvironmentManager::with_config(dir.path().to_path_buf(), config)
+            .with_services(MockServices::with_downloader(intact).services());
+        manager.install_component(zlib).await.unwrap();
+        assert!(zlib.dir(dir.path()).join("python").join(PYTHON_EXE).is_file());
+    }
+
+    #[tokio::test]
+    async fn upgrade_tool_installs_a_patch_release_beside_the_old_one_and_rolls_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let (manager, _mocks) = slotted_install(dir.path(), "Python 3.11.10");
+        let ps_env = dir.path().join("ps_env");
+
+        let upgrade = manager.upgrade_tool("python").await.unwrap();
+        assert_eq!(upgrade, ToolUpgrade::Upgraded { from: "python@3.11.9".into(), to: "python@3.11.10".into() });
+        assert_eq!(fs::read_to_string(ps_env.join("python").join(PYTHON_EXE)).unwrap(), "new");
+        assert!(ps_env.join("python@3.11.9").is_dir());
+        assert!(!tool_slots::upgrade_staging(&ps_env, "python").0.exists());
+
+        let rollback = manager.rollback_tool("python").unwrap();
+        assert_eq!(rollback, ToolUpgrade::Upgraded { from: "python@3.11.10".into(), to: "python@3.11.9".into() });
+        assert_eq!(fs::read_to_string(ps_env.join("python").join(PYTHON_EXE)).unwrap(), "old");
+    }
+
+    #[tokio::test]
+    async fn upgrade_tool_refuses_another_python_minor_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let (manager, _mocks) = slotted_install(dir.path(), "Python 3.12.1");
+        let ps_env = dir.path().join("ps_env");
+
+        let err = manager.upgrade_tool("python").await.unwrap_err();
+        assert!(err.to_string().contains("only installs 3.11 patch releases"), "{}", err);
+        assert_eq!(tool_slots::current_slot(&ps_env, "python").as_deref(), Some("python@3.11.9"));
+        assert_eq!(tool_slots::slots(&ps_env, "python"), ["python@3.11.9"]);
+        assert!(!tool_slots::upgrade_staging(&ps_env, "python").0.exists());
+    }
+
+    /// `ps_env/CUDA` with the key files of this platform, all but those named in `skip`
+    fn cuda_folder(root: &Path, skip: &[&str]) -> PathBuf {
+        let cuda = root.join("CUDA");
+        for (folder, prefix) in cuda_key_files() {
+            if !skip.iter().any(|s| prefix.contains(s)) {
+                fs::create_dir_all(cuda.join(folder)).unwrap();
+                fs::write(cuda.join(folder).join(format!("{}12", prefix)), "").unwrap();
+            }
+        }
+        cuda
+    }
+
+    #[test]
+    fn absent_cuda_folder_is_missing() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(inspect_cuda(&dir.path().join("CUDA")), CudaInstall::Missing);
+    }
+
+    #[test]
+    fn cuda_folder_without_cublas_is_incomplete() {
+        let dir = tempfile::tempdir().unwrap();
+        let cuda = cuda_folder(dir.path(), &["cublas"]);
+        assert!(matches!(inspect_cuda(&cuda), CudaInstall::Incomplete(reason) if reason.contains("cublas")));
+    }
+
+    #[test]
+    fn finishing_an_incomplete_cuda_install_removes_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let cuda = cuda_folder(dir.path(), &["cublas"]);
+        assert!(finish_cuda_install(&cuda, "cuda_128").is_err());
+        assert!(!cuda.exists());
+    }
+
+    #[test]
+    fn finished_cuda_install_records_its_folder_and_is_complete() {
+        let dir = tempfile::tempdir().unwrap();
+        let cuda = cuda_folder(dir.path(), &[]);
+        finish_cuda_install(&cuda, "cuda_128").unwrap();
+        assert_eq!(fs::read_to_string(cuda.join(CUDA_MARKER_FILE)).unwrap(), "cuda_128");
+        assert_eq!(inspect_cuda(&cuda), CudaInstall::Complete);
+    }
+
+    #[test]
+    fn partial_marker_makes_a_full_cuda_folder_incomplete() {
+        let dir = tempfile::tempdir().unwrap();
+        let cuda = cuda_folder(dir.path(), &[]);
+        fs::write(cuda.join(CUDA_MARKER_FILE), CUDA_PARTIAL).unwrap();
+        assert!(matches!(inspect_cuda(&cuda), CudaInstall::Incomplete(_)));
+    }
+
+    #[test]
+    fn full_cuda_folder_without_marker_is_complete_and_left_untouched() {
+        let dir = tempfile::tempdir().unwrap();
+        let cuda = cuda_folder(dir.path(), &[]);
+        assert_eq!(inspect_cuda(&cuda), CudaInstall::Complete);
+        assert!(!cuda.join(CUDA_MARKER_FILE).exists());
+    }
+
+    #[test]
+    fn tool_versions_are_parsed_from_version_output() {
+        assert_eq!(parse_tool_version("Python 3.11.9\n"), Some((3, 11, 9)));
+        assert_eq!(parse_tool_version("git version 2.43.0.windows.1"), Some((2, 43, 0)));
+        assert_eq!(parse_tool_version("Python 3.13"), Some((3, 13, 0)));
+        assert_eq!(parse_tool_version("command not found"), None);
+    }
+
+    #[test]
+    fn system_python_must_be_within_the_supported_range() {
+        assert!(system_tool_rejection("python", (3, 10, 0)).is_none());
+        assert!(system_tool_rejection("python", (3, 12, 7)).is_none());
+        assert_eq!(system_tool_rejection("python", (3, 13, 0)).unwrap(), "Python 3.13.0 is outside the supported 3.10-3.12");
+        assert!(system_tool_rejection("python", (3, 9, 18)).is_some());
+    }
+
+    #[test]
+    fn system_git_must_be_recent_enough() {
+        assert!(system_tool_rejection("git", (2, 30, 0)).is_none());
+        assert_eq!(system_tool_rejection("git", (2, 25, 1)).unwrap(), "git 2.25.1 is older than 2.30");
+    }
+
+    #[test]
+    fn reports_from_parallel_tasks_arrive_in_order() {
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = seen.clone();
+        let reporter = Arc::new(SetupReporter::new(move |e: SetupEvent| sink.lock().unwrap().push((e.seq, e.done))));
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                let reporter = reporter.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..8 {
+                        reporter.report(&format!("task{}", i), SetupPhase::Extracting, true);
+                    }
+                })
+            })
+            .collect();
+        threads.into_iter().for_each(|t| t.join().unwrap());
+
+        let seen = seen.lock().unwrap();
+        let expected: Vec<(u64, usize)> = (1..=64).map(|n| (n, n as usize)).collect();
+        assert_eq!(*seen, expected);
+    }
+
+    #[test]
+    fn a_callback_that_reports_gets_its_event_after_returning() {
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let this: Arc<std::sync::OnceLock<std::sync::Weak<SetupReporter>>> = Arc::default();
+        let (sink, handle) = (seen.clone(), this.clone());
+        let reporter = Arc::new(SetupReporter::new(move |e: SetupEvent| {
+            sink.lock().unwrap().push((e.seq, e.phase));
+            if e.phase == SetupPhase::Planned {
+                handle.get().and_then(|r| r.upgrade()).unwrap().report("python", SetupPhase::Downloading, false);
+                sink.lock().unwrap().push((0, SetupPhase::Planned));
+            }
+        }));
+        this.set(Arc::downgrade(&reporter)).unwrap();
+
+        reporter.plan(2);
+        reporter.report("setup", SetupPhase::Completed, false);
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![(1, SetupPhase::Planned), (0, SetupPhase::Planned), (2, SetupPhase::Downloading), (3, SetupPhase::Completed)]
+        );
+    }
+}
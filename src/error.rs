@@ -1,7 +1,11 @@
 //! Error handling for PortableSource
 
+use serde::Serialize;
 use thiserror::Error;
 
+/// Exit code when a bulk operation failed for some of its items but not all
+pub const PARTIAL_FAILURE_EXIT_CODE: i32 = 3;
+
 /// Main error type for PortableSource operations
 #[derive(Error, Debug)]
 pub enum PortableSourceError {
@@ -32,6 +36,9 @@ pub enum PortableSourceError {
     #[error("Repository error: {message}")]
     Repository { message: String },
     
+    #[error("Repository not found: {name}")]
+    RepositoryNotFound { name: String },
+    
     #[error("Environment error: {message}")]
     Environment { message: String },
     
@@ -43,6 +50,25 @@ pub enum PortableSourceError {
     
     #[error("Missing dependency: {dependency}")]
     MissingDependency { dependency: String },
+    
+    #[error("Network error: {message}")]
+    Network { message: String },
+    
+    #[error("Compiler not found: {message}")]
+    CompilerMissing { message: String },
+    
+    #[error("CUDA out of memory: {message}")]
+    CudaOutOfMemory { message: String },
+    
+    #[error("Disk full: {message}")]
+    DiskFull { message: String },
+    
+    #[error("Permission denied: {message}")]
+    PermissionDenied { message: String },
+    
+    /// Failures of several items of one bulk operation (update --all, prefetch, model downloads)
+    #[error("{0}")]
+    Multi(MultiError),
 }
 
 /// Result type alias for PortableSource operations
@@ -73,6 +99,13 @@ impl PortableSourceError {
         }
     }
     
+    /// Repository `name` is not installed, or its remote does not exist
+    pub fn repository_not_found(name: impl Into<String>) -> Self {
+        Self::RepositoryNotFound {
+            name: name.into(),
+        }
+    }
+    
     pub fn environment(message: impl Into<String>) -> Self {
         Self::Environment {
             message: message.into(),
@@ -96,4 +129,337 @@ impl PortableSourceError {
             dependency: dependency.into(),
         }
     }
-}
\ No newline at end of file
+    
+    pub fn network(message: impl Into<String>) -> Self {
+        Self::Network {
+            message: message.into(),
+        }
+    }
+    
+    pub fn compiler_missing(message: impl Into<String>) -> Self {
+        Self::CompilerMissing {
+            message: message.into(),
+        }
+    }
+    
+    pub fn cuda_out_of_memory(message: impl Into<String>) -> Self {
+        Self::CudaOutOfMemory {
+            message: message.into(),
+        }
+    }
+    
+    pub fn disk_full(message: impl Into<String>) -> Self {
+        Self::DiskFull {
+            message: message.into(),
+        }
+    }
+    
+    pub fn permission_denied(message: impl Into<String>) -> Self {
+        Self::PermissionDenied {
+            message: message.into(),
+        }
+    }
+    
+    /// Classify a failed child command by its stderr output.
+    /// Falls back to a plain `Command` error when nothing recognizable is found.
+    pub fn from_command_output(message: impl Into<String>, stderr: &str) -> Self {
+        let message = message.into();
+        let haystack = stderr.to_lowercase();
+        let matches = |patterns: &[&str]| patterns.iter().any(|p| haystack.contains(p));
+        
+        // Order matters: more specific failures are checked before generic ones
+        if matches(DISK_FULL_PATTERNS) {
+            Self::disk_full(message)
+        } else if matches(CUDA_OOM_PATTERNS) {
+            Self::cuda_out_of_memory(message)
+        } else if matches(COMPILER_MISSING_PATTERNS) {
+            Self::compiler_missing(message)
+        } else if matches(PERMISSION_DENIED_PATTERNS) {
+            Self::permission_denied(message)
+        } else if matches(NETWORK_PATTERNS) {
+            Self::network(message)
+        } else {
+            Self::command(message)
+        }
+    }
+    
+    /// Short stable name of the error kind, e.g. `network` or `disk_full` (operation history)
+    pub fn category(&self) -> &'static str {
+        match self {
+            Self::Io(_) => "io",
+            Self::Reqwest(_) | Self::Network { .. } => "network",
+            Self::Json(_) => "json",
+            Self::Registry(_) => "registry",
+            Self::Url(_) => "url",
+            Self::Config { .. } => "config",
+            Self::GpuDetection { .. } => "gpu_detection",
+            Self::Installation { .. } => "installation",
+            Self::Repository { .. } => "repository",
+            Self::RepositoryNotFound { .. } => "repository_not_found",
+            Self::Environment { .. } => "environment",
+            Self::Command { .. } => "command",
+            Self::InvalidPath { .. } => "invalid_path",
+            Self::MissingDependency { .. } => "missing_dependency",
+            Self::CompilerMissing { .. } => "compiler_missing",
+            Self::CudaOutOfMemory { .. } => "cuda_out_of_memory",
+            Self::DiskFull { .. } => "disk_full",
+            Self::PermissionDenied { .. } => "permission_denied",
+            Self::Multi(_) => "multiple",
+        }
+    }
+    
+    /// Process exit code: [`PARTIAL_FAILURE_EXIT_CODE`] when a bulk operation got some items done
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Multi(multi) if multi.failures.len() < multi.total => PARTIAL_FAILURE_EXIT_CODE,
+            _ => 1,
+        }
+    }
+    
+    /// Machine-readable form for `--error-format json`
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            Self::Multi(multi) => serde_json::json!({
+                "category": self.category(),
+                "message": self.to_string(),
+                "operation": multi.operation,
+                "total": multi.total,
+                "failures": multi.failures,
+            }),
+            _ => serde_json::json!({
+                "category": self.category(),
+                "message": self.to_string(),
+                "hint": self.remediation_hint(),
+            }),
+        }
+    }
+    
+    /// Suggested next step for the user, if the error kind has a known fix.
+    pub fn remediation_hint(&self) -> Option<String> {
+        match self {
+            Self::Network { .. } | Self::Reqwest(_) => Some(
+                "Check your internet connection, proxy settings (HTTP_PROXY/HTTPS_PROXY) and retry the command".to_string()
+            ),
+            Self::CompilerMissing { .. } => {
+                #[cfg(windows)]
+                { Some("A C/C++ compiler is required to build this package. Run 'portablesource install-msvc' and retry".to_string()) }
+                #[cfg(unix)]
+                { Some("A C/C++ compiler is required to build this package. Install build tools (e.g. 'sudo apt install build-essential') and retry".to_string()) }
+            }
+            Self::CudaOutOfMemory { .. } => Some(
+                "The GPU ran out of memory. Close other GPU applications or use a smaller model/batch size".to_string()
+            ),
+            Self::DiskFull { .. } => Some(
+                "Not enough disk space. Free up space on the install drive (pip/uv caches in 'tmp' can be removed) and retry".to_string()
+            ),
+            Self::PermissionDenied { .. } => Some(
+                "Permission denied. Make sure the install directory is writable and no other process is using these files".to_string()
+            ),
+            _ => None,
+        }
+    }
+}
+
+/// Failure of one item of a bulk operation
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ItemError {
+    /// Repository, file or package the operation failed for
+    pub item: String,
+    /// See [`PortableSourceError::category`]
+    pub category: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+}
+
+/// Per-item failures of a bulk operation; every item is attempted before it is reported
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct MultiError {
+    /// What was done to each item, e.g. "update"
+    pub operation: String,
+    /// Items attempted, failed or not
+    pub total: usize,
+    pub failures: Vec<ItemError>,
+}
+
+impl MultiError {
+    pub fn new(operation: impl Into<String>, total: usize) -> Self {
+        Self { operation: operation.into(), total, failures: Vec::new() }
+    }
+    
+    pub fn push(&mut self, item: impl Into<String>, error: &PortableSourceError) {
+        self.failures.push(ItemError {
+            item: item.into(),
+            category: error.category().to_string(),
+            message: error.to_string(),
+            hint: error.remediation_hint(),
+        });
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.failures.is_empty()
+    }
+    
+    /// Ok when nothing failed
+    pub fn into_result(self) -> Result<()> {
+        if self.is_empty() { Ok(()) } else { Err(PortableSourceError::Multi(self)) }
+    }
+    
+    /// Table for the terminal, one row per failed item, then the distinct hints
+    pub fn render(&self) -> String {
+        let item_width = self.failures.iter().map(|f| f.item.len()).max().unwrap_or(0).max(4);
+        let category_width = self.failures.iter().map(|f| f.category.len()).max().unwrap_or(0).max(8);
+        let mut out = format!("  {:<item_width$}  {:<category_width$}  ERROR\n", "ITEM", "CATEGORY");
+        for failure in &self.failures {
+            let message = failure.message.lines().next().unwrap_or_default();
+            out.push_str(&format!("  {:<item_width$}  {:<category_width$}  {}\n", failure.item, failure.category, message));
+        }
+        let mut hints: Vec<&str> = Vec::new();
+        for hint in self.failures.iter().filter_map(|f| f.hint.as_deref()) {
+            if !hints.contains(&hint) {
+                hints.push(hint);
+                out.push_str(&format!("  hint: {}\n", hint));
+            }
+        }
+        out
+    }
+}
+
+impl std::fmt::Display for MultiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} failed for {} of {}: ", self.operation, self.failures.len(), self.total)?;
+        let items: Vec<String> = self.failures.iter().map(|e| format!("{} ({})", e.item, e.message)).collect();
+        f.write_str(&items.join("; "))
+    }
+}
+
+/// How the final error of a command is printed (`--error-format`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ErrorFormat {
+    #[default]
+    Text,
+    /// One JSON object on stderr
+    Json,
+}
+
+impl std::str::FromStr for ErrorFormat {
+    type Err = PortableSourceError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "text" => Ok(ErrorFormat::Text),
+            "json" => Ok(ErrorFormat::Json),
+            other => Err(PortableSourceError::config(format!("Unknown error format '{}' (expected text or json)", other))),
+        }
+    }
+}
+
+const DISK_FULL_PATTERNS: &[&str] = &[
+    "no space left on device",
+    "disk quota exceeded",
+    "not enough space on the disk",
+    "errno 28",
+];
+
+const CUDA_OOM_PATTERNS: &[&str] = &[
+    "cuda out of memory",
+    "outofmemoryerror",
+    "cuda error: out of memory",
+    "cudnn_status_alloc_failed",
+];
+
+const COMPILER_MISSING_PATTERNS: &[&str] = &[
+    "microsoft visual c++ 14.0",
+    "vcvarsall.bat",
+    "'cl.exe' failed",
+    "gcc: command not found",
+    "cc: not found",
+    "could not find a c compiler",
+    "unable to find a compiler",
+];
+
+const PERMISSION_DENIED_PATTERNS: &[&str] = &[
+    "permission denied",
+    "access is denied",
+    "errno 13",
+    "operation not permitted",
+];
+
+const NETWORK_PATTERNS: &[&str] = &[
+    "could not resolve host",
+    "temporary failure in name resolution",
+    "connection refused",
+    "connection reset",
+    "connection timed out",
+    "read timed out",
+    "network is unreachable",
+    "failed to connect",
+    "max retries exceeded",
+    "failed to establish a new connection",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `update` of 3 repositories where comfyui and facefusion failed
+    fn two_of_three_failed() -> MultiError {
+        let mut multi = MultiError::new("update", 3);
+        multi.push("comfyui", &PortableSourceError::network("Could not reach github.com"));
+        multi.push("facefusion", &PortableSourceError::disk_full("pip install failed"));
+        multi
+    }
+
+    #[test]
+    fn partial_failures_exit_distinctly() {
+        let err = two_of_three_failed().into_result().unwrap_err();
+        assert_eq!(err.exit_code(), PARTIAL_FAILURE_EXIT_CODE);
+        assert_eq!(err.category(), "multiple");
+        assert!(err.to_string().starts_with("update failed for 2 of 3: comfyui (Network error: Could not reach github.com)"));
+    }
+
+    #[test]
+    fn failures_render_one_row_and_hint_per_item() {
+        let table = two_of_three_failed().render();
+        assert!(table.contains("  facefusion  disk_full  Disk full: pip install failed"), "{}", table);
+        assert_eq!(table.matches("hint:").count(), 2);
+    }
+
+    #[test]
+    fn failures_are_listed_in_json() {
+        let json = two_of_three_failed().into_result().unwrap_err().to_json();
+        assert_eq!(json["failures"][1]["item"], "facefusion");
+        assert_eq!(json["total"], 3);
+    }
+
+    #[test]
+    fn all_items_failing_is_a_plain_failure() {
+        let all = MultiError { total: 2, ..two_of_three_failed() };
+        assert_eq!(PortableSourceError::Multi(all).exit_code(), 1);
+    }
+
+    #[test]
+    fn no_failures_is_success() {
+        assert!(MultiError::new("prefetch", 2).into_result().is_ok());
+    }
+
+    #[test]
+    fn error_format_is_parsed_case_insensitively() {
+        assert_eq!("JSON".parse::<ErrorFormat>().unwrap(), ErrorFormat::Json);
+    }
+
+    #[test]
+    fn failed_commands_are_classified_by_their_stderr() {
+        let classify = |stderr: &str| PortableSourceError::from_command_output("pip install failed", stderr).category();
+        assert_eq!(classify("OSError: [Errno 28] No space left on device"), "disk_full");
+        assert_eq!(classify("torch.OutOfMemoryError: CUDA out of memory. Tried to allocate 2.00 GiB"), "cuda_out_of_memory");
+        assert_eq!(classify("error: Microsoft Visual C++ 14.0 or greater is required."), "compiler_missing");
+        assert_eq!(classify("PermissionError: [Errno 13] Permission denied: 'site-packages'"), "permission_denied");
+        assert_eq!(classify("Max retries exceeded with url: /simple/torch/ (Caused by NewConnectionError)"), "network");
+        assert_eq!(classify("ERROR: No matching distribution found for torch==9.9"), "command");
+        // A full disk also fails with permission-like messages; the more specific kind wins
+        assert_eq!(classify("Permission denied\nNo space left on device"), "disk_full");
+        let err = PortableSourceError::from_command_output("git clone failed", "fatal: Could not resolve host: github.com");
+        assert!(err.to_string().contains("git clone failed"), "{}", err);
+    }
+}
@@ -0,0 +1,777 @@
+//! Parallel tar.zst unpacking
+//!
+//! A zstd frame decodes as one sequential stream, but archives packed with `pzstd` (or any
+//! tool writing several frames) are a sequence of independent frames. Their boundaries are
+//! found from the frame and block headers alone, and a pool of decoders sized to the CPU
+//! count unpacks frames ahead of the tar reader, which takes them in order. Each decoder
+//! holds at most one decoded frame, and only frames recording a size below
+//! `PARALLEL_FRAME_MAX` are decoded this way; single-frame archives stream through one
+//! decoder.
+//!
+//! Writing tens of thousands of files one after another, each paying for create/close (and
+//! an antivirus scan on Windows), is the other cost: small files go to a pool of writers,
+//! with at most `POOLED_BYTES_MAX` of file data waiting at any time, while large files,
+//! links and directories stream to disk in place. The frame header is read first: its
+//! window size sets the decoder's memory limit (archives packed with `--long` need more
+//! than the 128 MB default) and the input buffer, and the recorded content sizes, when
+//! present, make the free-space estimate exact.
+//!
+//! Archives are told apart by their magic bytes (then by extension): tool links serve
+//! tar.zst, legacy `.7z` archives from older links or user overrides go to an installed 7-Zip.
+
+use crate::disk_space::SpaceCheck;
+use crate::{PortableSourceError, Result};
+use std::cell::Cell;
+use std::fs::{self, File};
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::sync::{Arc, Condvar, Mutex};
+
+const ZSTD_MAGIC: u32 = 0xFD2F_B528;
+/// Skippable frames: magic 0x184D2A50-0x184D2A5F, then a 4-byte length
+const SKIPPABLE_MAGIC_MASK: u32 = 0xFFFF_FFF0;
+const SKIPPABLE_MAGIC: u32 = 0x184D_2A50;
+const SEVEN_ZIP_MAGIC: &[u8] = b"7z\xBC\xAF\x27\x1C";
+/// Files up to this size are buffered and written by the pool; larger ones stream to disk
+const POOLED_FILE_MAX: u64 = 16 * 1024 * 1024;
+/// File data waiting for the writer pool, at most
+const POOLED_BYTES_MAX: u64 = 64 * 1024 * 1024;
+/// Writers beyond this stop helping: the disk, not the CPU, is then the limit
+const MAX_WRITERS: usize = 8;
+const MAX_DECODERS: usize = 8;
+/// Frames decoded in parallel must record a decompressed size up to this
+const PARALLEL_FRAME_MAX: u64 = 16 * 1024 * 1024;
+
+/// What the first zstd frame header says about the archive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameInfo {
+    pub window_size: u64,
+    /// Decompressed size of the frame, if the compressor recorded it
+    pub content_size: Option<u64>,
+}
+
+impl FrameInfo {
+    /// `windowLog` the decoder must accept for this frame
+    pub fn window_log(&self) -> u32 {
+        64 - self.window_size.max(1).saturating_sub(1).leading_zeros()
+    }
+}
+
+/// Parse a zstd frame header (RFC 8878, section 3.1.1.1)
+pub fn parse_frame_header(bytes: &[u8]) -> Option<FrameInfo> {
+    if bytes.len() < 6 || u32::from_le_bytes(bytes[..4].try_into().ok()?) != ZSTD_MAGIC {
+        return None;
+    }
+    let descriptor = bytes[4];
+    let fcs_flag = descriptor >> 6;
+    let single_segment = descriptor & 0x20 != 0;
+    let dict_id_size = [0, 1, 2, 4][(descriptor & 0x03) as usize];
+
+    let mut pos = 5;
+    let mut window_size = None;
+    if !single_segment {
+        let wd = bytes[pos];
+        let base = 1u64 << (10 + (wd >> 3));
+        window_size = Some(base + (base / 8) * (wd & 0x07) as u64);
+        pos += 1;
+    }
+    pos += dict_id_size;
+
+    let fcs_size = match fcs_flag {
+        0 if single_segment => 1,
+        0 => 0,
+        1 => 2,
+        2 => 4,
+        _ => 8,
+    };
+    let content_size = if fcs_size == 0 {
+        None
+    } else {
+        let field = bytes.get(pos..pos + fcs_size)?;
+        let mut value = [0u8; 8];
+        value[..fcs_size].copy_from_slice(field);
+        let value = u64::from_le_bytes(value);
+        Some(if fcs_size == 2 { value + 256 } else { value })
+    };
+    Some(FrameInfo { window_size: window_size.or(content_size)?, content_size })
+}
+
+/// One zstd frame of an archive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameSpan {
+    pub offset: u64,
+    pub compressed_size: u64,
+    pub content_size: Option<u64>,
+}
+
+fn read_at(file: &mut File, offset: u64, buf: &mut [u8]) -> Option<usize> {
+    file.seek(SeekFrom::Start(offset)).ok()?;
+    let mut len = 0;
+    while len < buf.len() {
+        match file.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(_) => return None,
+        }
+    }
+    Some(len)
+}
+
+/// Frames of a zstd file, found by walking frame and block headers without decoding;
+/// skippable frames are left out. None when the file is not a sequence of zstd frames
+pub fn frame_spans(archive_path: &Path) -> Option<Vec<FrameSpan>> {
+    let mut file = File::open(archive_path).ok()?;
+    let len = file.metadata().ok()?.len();
+    let mut spans = Vec::new();
+    let mut offset = 0;
+    while offset < len {
+        let mut header = [0u8; 18];
+        let read = read_at(&mut file, offset, &mut header)?;
+        let magic = u32::from_le_bytes(header.get(..4)?.try_into().ok()?);
+        if magic & SKIPPABLE_MAGIC_MASK == SKIPPABLE_MAGIC {
+            offset += 8 + u64::from(u32::from_le_bytes(header.get(4..8)?.try_into().ok()?));
+            continue;
+        }
+        let info = parse_frame_header(&header[..read])?;
+        let descriptor = header[4];
+        let single_segment = descriptor & 0x20 != 0;
+        let fcs_size = match descriptor >> 6 {
+            0 if single_segment => 1,
+            0 => 0,
+            1 => 2,
+            2 => 4,
+            _ => 8,
+        };
+        let header_len = 5 + u64::from(!single_segment) + [0, 1, 2, 4][(descriptor & 0x03) as usize] + fcs_size;
+        let mut pos = offset + header_len;
+        loop {
+            let mut block = [0u8; 3];
+            if read_at(&mut file, pos, &mut block)? < 3 {
+                return None;
+            }
+            let block = u32::from(block[0]) | u32::from(block[1]) << 8 | u32::from(block[2]) << 16;
+            let payload = match (block >> 1) & 0x03 {
+                // RLE blocks store the repeated byte once
+                1 => 1,
+                3 => return None,
+                _ => u64::from(block >> 3),
+            };
+            pos += 3 + payload;
+            if block & 1 == 1 {
+                break;
+            }
+        }
+        if descriptor & 0x04 != 0 {
+            pos += 4;
+        }
+        if pos > len {
+            return None;
+        }
+        spans.push(FrameSpan { offset, compressed_size: pos - offset, content_size: info.content_size });
+        offset = pos;
+    }
+    Some(spans)
+}
+
+/// Decompressed size of all frames, when each records it
+fn total_content_size(spans: &[FrameSpan]) -> Option<u64> {
+    spans.iter().map(|s| s.content_size).sum()
+}
+
+/// Frames can be handed to parallel decoders: more than one, each of a known, small size
+fn decodes_in_parallel(spans: &[FrameSpan]) -> bool {
+    spans.len() > 1 && spans.iter().all(|s| s.content_size.is_some_and(|size| size <= PARALLEL_FRAME_MAX))
+}
+
+fn decode_frame(file: &mut File, span: &FrameSpan, window_log: u32) -> std::io::Result<Vec<u8>> {
+    let mut compressed = vec![0u8; span.compressed_size as usize];
+    file.seek(SeekFrom::Start(span.offset))?;
+    file.read_exact(&mut compressed)?;
+    let mut decoder = zstd::stream::Decoder::new(&compressed[..])?;
+    decoder.window_log_max(window_log)?;
+    let mut data = Vec::with_capacity(span.content_size.unwrap_or(0) as usize);
+    decoder.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// The decompressed archive, from frames decoded ahead by a pool of threads. Decoder `i`
+/// takes frames `i`, `i + n`, `i + 2n`... and hands each over once the reader asks for it
+struct ParallelFrames {
+    queues: Vec<Receiver<std::io::Result<Vec<u8>>>>,
+    spans: Vec<FrameSpan>,
+    next: usize,
+    chunk: Vec<u8>,
+    pos: usize,
+    compressed_read: Rc<Cell<u64>>,
+}
+
+impl ParallelFrames {
+    fn start(archive_path: &Path, spans: Vec<FrameSpan>, decoders: usize, compressed_read: Rc<Cell<u64>>) -> Self {
+        let queues = (0..decoders)
+            .map(|first| {
+                // Rendezvous: a decoder keeps at most the one frame it waits to hand over
+                let (sender, receiver) = sync_channel(0);
+                let (path, spans) = (archive_path.to_path_buf(), spans.clone());
+                std::thread::spawn(move || {
+                    let mut file = match File::open(&path) {
+                        Ok(file) => file,
+                        Err(e) => return drop(sender.send(Err(e))),
+                    };
+                    for span in spans.iter().skip(first).step_by(decoders) {
+                        // The window of a frame never exceeds its recorded size, far below 2^27
+                        let frame = decode_frame(&mut file, span, 27);
+                        let failed = frame.is_err();
+                        if sender.send(frame).is_err() || failed {
+                            return;
+                        }
+                    }
+                });
+                receiver
+            })
+            .collect();
+        Self { queues, spans, next: 0, chunk: Vec::new(), pos: 0, compressed_read }
+    }
+}
+
+impl Read for ParallelFrames {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            if self.next == self.spans.len() {
+                return Ok(0);
+            }
+            let queue = &self.queues[self.next % self.queues.len()];
+            self.chunk = queue.recv().map_err(|_| std::io::Error::other("zstd decoder thread stopped"))??;
+            self.pos = 0;
+            self.compressed_read.set(self.compressed_read.get() + self.spans[self.next].compressed_size);
+            self.next += 1;
+        }
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+pub fn read_frame_info(archive_path: &Path) -> Option<FrameInfo> {
+    let mut header = [0u8; 18];
+    let mut file = File::open(archive_path).ok()?;
+    let mut len = 0;
+    while len < header.len() {
+        match file.read(&mut header[len..]) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => len += n,
+        }
+    }
+    parse_frame_header(&header[..len])
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    TarZst,
+    /// Legacy tool archives, unpacked by 7-Zip
+    SevenZip,
+}
+
+impl ArchiveFormat {
+    /// Format of an archive by its first bytes, else by its extension (downloads are saved
+    /// under fixed names like `CUDA_128.tar.zst` whatever the link serves)
+    pub fn detect(archive_path: &Path) -> Option<Self> {
+        let mut magic = [0u8; 6];
+        if File::open(archive_path).and_then(|mut f| f.read_exact(&mut magic)).is_ok() {
+            if magic.starts_with(&ZSTD_MAGIC.to_le_bytes()) {
+                return Some(ArchiveFormat::TarZst);
+            }
+            if magic == SEVEN_ZIP_MAGIC {
+                return Some(ArchiveFormat::SevenZip);
+            }
+        }
+        let name = archive_path.file_name()?.to_string_lossy().to_lowercase();
+        if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            Some(ArchiveFormat::TarZst)
+        } else if name.ends_with(".7z") {
+            Some(ArchiveFormat::SevenZip)
+        } else {
+            None
+        }
+    }
+}
+
+/// Unpack an archive into `extract_to` by its format; `progress` gets the percent done
+pub fn unpack(archive_path: &Path, extract_to: &Path, space: &mut SpaceCheck, progress: &mut dyn FnMut(u64)) -> Result<()> {
+    match ArchiveFormat::detect(archive_path) {
+        Some(ArchiveFormat::TarZst) => unpack_tar_zstd(archive_path, extract_to, space, progress),
+        Some(ArchiveFormat::SevenZip) => unpack_7z(archive_path, extract_to, progress),
+        None => Err(PortableSourceError::environment(format!(
+            "{} is neither a tar.zst nor a 7z archive",
+            archive_path.display()
+        ))),
+    }
+}
+
+/// 7-Zip on PATH, else in its default install folder
+fn seven_zip() -> Option<PathBuf> {
+    ["7z", "7za", "7zr"].iter().find_map(|name| which::which(name).ok()).or_else(|| {
+        ["ProgramFiles", "ProgramW6432"]
+            .iter()
+            .filter_map(std::env::var_os)
+            .map(|dir| PathBuf::from(dir).join("7-Zip").join("7z.exe"))
+            .find(|exe| exe.is_file())
+    })
+}
+
+/// Last percentage in a chunk of 7-Zip `-bsp1` output (" 42% 17 - bin\\git.exe")
+fn seven_zip_percent(chunk: &str) -> Option<u64> {
+    chunk
+        .split(|c: char| c.is_whitespace() || c == '\u{8}')
+        .filter_map(|token| token.strip_suffix('%')?.parse().ok())
+        .next_back()
+}
+
+fn unpack_7z(archive_path: &Path, extract_to: &Path, progress: &mut dyn FnMut(u64)) -> Result<()> {
+    let exe = seven_zip().ok_or_else(|| {
+        PortableSourceError::environment(format!(
+            "{} is a legacy 7z archive and 7-Zip was not found; install 7-Zip or switch the tool links to .tar.zst",
+            archive_path.display()
+        ))
+    })?;
+    // Progress and errors both on stdout: one pipe cannot fill up while the other is read
+    let mut child = std::process::Command::new(&exe)
+        .arg("x")
+        .arg("-y")
+        .args(["-bsp1", "-bso0", "-bse1"])
+        .arg(format!("-o{}", extract_to.display()))
+        .arg(archive_path)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .map_err(|e| PortableSourceError::environment(format!("Failed to run {}: {}", exe.display(), e)))?;
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let mut text = String::new();
+    let mut buf = [0u8; 4096];
+    while let Ok(n) = stdout.read(&mut buf) {
+        if n == 0 {
+            break;
+        }
+        let chunk = String::from_utf8_lossy(&buf[..n]);
+        if let Some(percent) = seven_zip_percent(&chunk) {
+            progress(percent);
+        }
+        text.push_str(&chunk);
+    }
+    let status = child.wait()?;
+    // 1 is "warnings only"
+    if matches!(status.code(), Some(0) | Some(1)) {
+        return Ok(());
+    }
+    Err(PortableSourceError::from_command_output(
+        format!("7-Zip failed to extract {} (exit code {:?})", archive_path.display(), status.code()),
+        &text,
+    ))
+}
+
+/// Decoder and writer threads and read buffer for one archive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExtractTuning {
+    pub decoders: usize,
+    pub writers: usize,
+    pub input_buffer: usize,
+}
+
+impl ExtractTuning {
+    pub fn for_archive(cpus: usize, frame: Option<FrameInfo>) -> Self {
+        // Larger windows mean longer matches spread further apart; feed the decoder more per call
+        let input_buffer = match frame.map(|f| f.window_size).unwrap_or(0) {
+            w if w >= 64 * 1024 * 1024 => 4 * 1024 * 1024,
+            w if w >= 8 * 1024 * 1024 => 1024 * 1024,
+            _ => 256 * 1024,
+        };
+        Self {
+            decoders: cpus.saturating_sub(1).clamp(1, MAX_DECODERS),
+            writers: cpus.saturating_sub(1).clamp(1, MAX_WRITERS),
+            input_buffer,
+        }
+    }
+}
+
+/// Counts compressed bytes read, to extrapolate the unpacked size
+struct Counting<R> {
+    inner: R,
+    read: Rc<Cell<u64>>,
+}
+
+impl<R: Read> Read for Counting<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read.set(self.read.get() + n as u64);
+        Ok(n)
+    }
+}
+
+/// Destination of an entry inside `root`; None for absolute paths and `..` escapes
+fn safe_destination(root: &Path, entry_path: &Path) -> Option<PathBuf> {
+    let mut dest = root.to_path_buf();
+    for component in entry_path.components() {
+        match component {
+            Component::Normal(part) => dest.push(part),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    (dest != root).then_some(dest)
+}
+
+struct PooledFile {
+    dest: PathBuf,
+    data: Vec<u8>,
+    mode: Option<u32>,
+}
+
+fn write_file(file: &PooledFile) -> std::io::Result<()> {
+    fs::write(&file.dest, &file.data)?;
+    set_mode(&file.dest, file.mode)
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: Option<u32>) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    match mode {
+        Some(mode) => fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o7777)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: Option<u32>) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Bytes of file data handed to the writer pool and not written yet
+#[derive(Default)]
+struct PoolBudget {
+    queued: Mutex<u64>,
+    freed: Condvar,
+}
+
+impl PoolBudget {
+    /// Wait until `size` more bytes fit; a file alone larger than the budget waits for an
+    /// empty queue
+    fn take(&self, size: u64) {
+        let mut queued = self.queued.lock().unwrap_or_else(|p| p.into_inner());
+        while *queued > 0 && *queued + size > POOLED_BYTES_MAX {
+            queued = self.freed.wait(queued).unwrap_or_else(|p| p.into_inner());
+        }
+        *queued += size;
+    }
+
+    fn give_back(&self, size: u64) {
+        let mut queued = self.queued.lock().unwrap_or_else(|p| p.into_inner());
+        *queued = queued.saturating_sub(size);
+        self.freed.notify_all();
+    }
+}
+
+fn writer(queue: Arc<Mutex<Receiver<PooledFile>>>, budget: Arc<PoolBudget>, failure: Arc<Mutex<Option<std::io::Error>>>) {
+    loop {
+        let next = queue.lock().map(|q| q.recv());
+        let Ok(Ok(file)) = next else { return };
+        let result = write_file(&file);
+        budget.give_back(file.data.len() as u64);
+        if let Err(e) = result {
+            let mut failure = failure.lock().unwrap_or_else(|p| p.into_inner());
+            failure.get_or_insert(std::io::Error::new(e.kind(), format!("{}: {}", file.dest.display(), e)));
+        }
+    }
+}
+
+fn extract_error(e: impl std::fmt::Display) -> PortableSourceError {
+    PortableSourceError::environment(format!("Failed to extract tar archive: {}", e))
+}
+
+/// Unpack a tar.zst archive into `extract_to`, checking free space as it goes; `progress`
+/// gets the percent of decompressed bytes written (estimated from the compressed bytes
+/// read when the frame does not record its size)
+pub fn unpack_tar_zstd(archive_path: &Path, extract_to: &Path, space: &mut SpaceCheck, progress: &mut dyn FnMut(u64)) -> Result<()> {
+    let frame = read_frame_info(archive_path);
+    let spans = frame_spans(archive_path).unwrap_or_default();
+    let content_total = total_content_size(&spans).or_else(|| frame.and_then(|f| f.content_size).filter(|_| spans.len() == 1));
+    let cpus = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let tuning = ExtractTuning::for_archive(cpus, frame);
+    let parallel = tuning.decoders > 1 && decodes_in_parallel(&spans);
+    tracing::debug!("Extracting {:?}: {:?}, {} frame(s), parallel: {}, {:?}", archive_path, frame, spans.len(), parallel, tuning);
+
+    let file = File::open(archive_path).map_err(|e| PortableSourceError::environment(format!("Failed to open archive: {}", e)))?;
+    let compressed_total = file.metadata().map(|m| m.len()).unwrap_or(0);
+    let compressed_read = Rc::new(Cell::new(0u64));
+    let decoder: Box<dyn Read> = if parallel {
+        Box::new(ParallelFrames::start(archive_path, spans, tuning.decoders, compressed_read.clone()))
+    } else {
+        let input = BufReader::with_capacity(tuning.input_buffer, Counting { inner: file, read: compressed_read.clone() });
+        let mut decoder = zstd::stream::Decoder::with_buffer(input)
+            .map_err(|e| PortableSourceError::environment(format!("Failed to create zstd decoder: {}", e)))?;
+        if let Some(frame) = frame {
+            decoder
+                .window_log_max(frame.window_log().max(27))
+                .map_err(|e| PortableSourceError::environment(format!("Archive window too large for the zstd decoder: {}", e)))?;
+        }
+        Box::new(decoder)
+    };
+
+    let (sender, receiver) = sync_channel::<PooledFile>(tuning.writers * 4);
+    let queue = Arc::new(Mutex::new(receiver));
+    let budget = Arc::new(PoolBudget::default());
+    let failure: Arc<Mutex<Option<std::io::Error>>> = Arc::new(Mutex::new(None));
+    let writers: Vec<_> = (0..tuning.writers)
+        .map(|_| {
+            let (queue, budget, failure) = (queue.clone(), budget.clone(), failure.clone());
+            std::thread::spawn(move || writer(queue, budget, failure))
+        })
+        .collect();
+
+    let mut hard_links = Vec::new();
+    let result = (|| -> Result<()> {
+        let mut archive = tar::Archive::new(decoder);
+        let mut written = 0u64;
+        let mut reported = 0u64;
+        for entry in archive.entries().map_err(extract_error)? {
+            if failure.lock().is_ok_and(|f| f.is_some()) {
+                break;
+            }
+            let mut entry = entry.map_err(extract_error)?;
+            let size = entry.size();
+            written += size;
+            let remaining = match content_total {
+                Some(total) => Some(total.saturating_sub(written)),
+                None => {
+                    let read = compressed_read.get();
+                    (read > 0 && compressed_total > read).then(|| (written as f64 * compressed_total as f64 / read as f64) as u64 - written)
+                }
+            };
+            space.check(remaining)?;
+            let percent = match remaining {
+                Some(remaining) if written + remaining > 0 => written * 100 / (written + remaining),
+                _ => 0,
+            };
+            if percent > reported {
+                reported = percent;
+                progress(percent);
+            }
+
+            let path = entry.path().map_err(extract_error)?.into_owned();
+            let Some(dest) = safe_destination(extract_to, &path) else {
+                tracing::warn!("Skipping archive entry outside the target: {:?}", path);
+                continue;
+            };
+            let kind = entry.header().entry_type();
+            if kind.is_hard_link() {
+                // The link target may still be queued for a writer
+                let target = entry.link_name().map_err(extract_error)?.and_then(|l| safe_destination(extract_to, &l));
+                if let Some(target) = target {
+                    hard_links.push((target, dest));
+                }
+                continue;
+            }
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent).map_err(extract_error)?;
+            }
+            if kind.is_file() && size <= POOLED_FILE_MAX {
+                budget.take(size);
+                let mut data = Vec::with_capacity(size as usize);
+                entry.read_to_end(&mut data).map_err(extract_error)?;
+                let mode = entry.header().mode().ok();
+                if sender.send(PooledFile { dest, data, mode }).is_err() {
+                    break;
+                }
+            } else {
+                entry.unpack_in(extract_to).map_err(|e| match space.check_now(Some(size)) {
+                    Err(full) => full,
+                    Ok(()) => extract_error(e),
+                })?;
+            }
+        }
+        Ok(())
+    })();
+
+    drop(sender);
+    for handle in writers {
+        let _ = handle.join();
+    }
+    result?;
+    if let Some(e) = failure.lock().unwrap_or_else(|p| p.into_inner()).take() {
+        space.check_now(None)?;
+        return Err(extract_error(e));
+    }
+    for (target, dest) in hard_links {
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent).map_err(extract_error)?;
+        }
+        let _ = fs::remove_file(&dest);
+        // Filesystems without hard links (FAT/exFAT USB drives) get a copy
+        if fs::hard_link(&target, &dest).is_err() {
+            fs::copy(&target, &dest).map_err(extract_error)?;
+        }
+    }
+    progress(100);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_header_gives_the_content_size() {
+        // Single segment, 2-byte content size: 256 + 0x0100
+        let info = parse_frame_header(&[0x28, 0xB5, 0x2F, 0xFD, 0x60, 0x00, 0x01, 0, 0]).unwrap();
+        assert_eq!(info, FrameInfo { window_size: 512, content_size: Some(512) });
+    }
+
+    #[test]
+    fn long_window_frames_size_the_decoders() {
+        // Window descriptor 0x88: 2^(10+17) = 128 MiB, no content size
+        let long = parse_frame_header(&[0x28, 0xB5, 0x2F, 0xFD, 0x00, 0x88]).unwrap();
+        assert_eq!((long.window_size, long.content_size, long.window_log()), (128 << 20, None, 27));
+        assert_eq!(ExtractTuning::for_archive(16, Some(long)), ExtractTuning { decoders: 8, writers: 8, input_buffer: 4 << 20 });
+    }
+
+    #[test]
+    fn other_archives_have_no_frame_header() {
+        assert!(parse_frame_header(b"PK\x03\x04zip!").is_none());
+    }
+
+    /// tool.tar.zst with 50 files `tool/bin/fN.txt` holding `file N`
+    fn tool_archive(dir: &Path) -> PathBuf {
+        let archive_path = dir.join("tool.tar.zst");
+        let mut builder = tar::Builder::new(zstd::stream::Encoder::new(File::create(&archive_path).unwrap(), 3).unwrap().auto_finish());
+        for i in 0..50 {
+            let data = format!("file {}", i);
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o755);
+            builder.append_data(&mut header, format!("tool/bin/f{}.txt", i), data.as_bytes()).unwrap();
+        }
+        builder.into_inner().unwrap();
+        archive_path
+    }
+
+    #[test]
+    fn unpack_writes_every_file_and_reports_rising_progress() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = tool_archive(dir.path());
+        assert!(read_frame_info(&archive_path).is_some());
+        let target = dir.path().join("out");
+        fs::create_dir_all(&target).unwrap();
+        let mut reported = Vec::new();
+        unpack(&archive_path, &target, &mut SpaceCheck::new(&target).with_min_free(0), &mut |p| reported.push(p)).unwrap();
+        assert!(reported.windows(2).all(|w| w[0] < w[1]) && reported.last() == Some(&100));
+        assert_eq!(fs::read_dir(target.join("tool/bin")).unwrap().count(), 50);
+        assert_eq!(fs::read_to_string(target.join("tool/bin/f49.txt")).unwrap(), "file 49");
+    }
+
+    #[test]
+    fn entries_outside_the_target_are_refused() {
+        assert_eq!(safe_destination(Path::new("out"), Path::new("../evil")), None);
+    }
+
+    #[test]
+    fn downloads_are_recognized_by_their_magic_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let unnamed = dir.path().join("CUDA_128.7z");
+        fs::copy(tool_archive(dir.path()), &unnamed).unwrap();
+        assert_eq!(ArchiveFormat::detect(&unnamed), Some(ArchiveFormat::TarZst));
+        assert_eq!(ArchiveFormat::detect(Path::new("git-2.45.7z")), Some(ArchiveFormat::SevenZip));
+    }
+
+    #[test]
+    fn seven_zip_progress_is_read_from_its_last_percentage() {
+        assert_eq!(seven_zip_percent(" 12% 3 - a\u{8}\u{8} 57% 9 - bin\\git.exe"), Some(57));
+        assert_eq!(seven_zip_percent("Everything is Ok"), None);
+    }
+
+    /// tar of `count` files named `tool/fN.bin`, each `size` bytes of N
+    fn tar_of(count: usize, size: usize) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for i in 0..count {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(size as u64);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, format!("tool/f{}.bin", i), &vec![i as u8; size][..]).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn multi_frame_archives_are_split_at_frame_boundaries() {
+        let dir = tempfile::tempdir().unwrap();
+        let tar = tar_of(40, 30_000);
+        // pzstd-style: independent frames recording their size, with a skippable frame between
+        let mut packed = Vec::new();
+        for (i, chunk) in tar.chunks(256 * 1024).enumerate() {
+            if i == 1 {
+                packed.extend_from_slice(&(SKIPPABLE_MAGIC | 0x0E).to_le_bytes());
+                packed.extend_from_slice(&4u32.to_le_bytes());
+                packed.extend_from_slice(b"meta");
+            }
+            packed.extend(zstd::bulk::compress(chunk, 3).unwrap());
+        }
+        let archive_path = dir.path().join("tool.tar.zst");
+        fs::write(&archive_path, &packed).unwrap();
+
+        let spans = frame_spans(&archive_path).unwrap();
+        assert_eq!(spans.len(), tar.len().div_ceil(256 * 1024));
+        assert_eq!(total_content_size(&spans), Some(tar.len() as u64));
+        assert!(decodes_in_parallel(&spans));
+        assert_eq!(spans.iter().map(|s| s.compressed_size).sum::<u64>() + 12, packed.len() as u64);
+
+        let target = dir.path().join("out");
+        fs::create_dir_all(&target).unwrap();
+        unpack(&archive_path, &target, &mut SpaceCheck::new(&target).with_min_free(0), &mut |_| {}).unwrap();
+        assert_eq!(fs::read_dir(target.join("tool")).unwrap().count(), 40);
+        assert_eq!(fs::read(target.join("tool/f39.bin")).unwrap(), vec![39u8; 30_000]);
+    }
+
+    #[test]
+    fn parallel_frames_read_back_the_original_stream_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let data: Vec<u8> = (0..200_000u32).flat_map(|n| n.to_le_bytes()).collect();
+        let packed: Vec<u8> = data.chunks(100_000).flat_map(|c| zstd::bulk::compress(c, 1).unwrap()).collect();
+        let archive_path = dir.path().join("data.zst");
+        fs::write(&archive_path, packed).unwrap();
+
+        let spans = frame_spans(&archive_path).unwrap();
+        let read = Rc::new(Cell::new(0));
+        let mut decoded = Vec::new();
+        ParallelFrames::start(&archive_path, spans, 3, read.clone()).read_to_end(&mut decoded).unwrap();
+        assert!(decoded == data);
+        assert_eq!(read.get(), fs::metadata(&archive_path).unwrap().len());
+    }
+
+    #[test]
+    fn single_frame_and_unsized_archives_stream_through_one_decoder() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = dir.path().join("one.tar.zst");
+        fs::write(&archive_path, zstd::stream::encode_all(&tar_of(3, 10)[..], 3).unwrap()).unwrap();
+        let spans = frame_spans(&archive_path).unwrap();
+        assert_eq!(spans.len(), 1);
+        assert!(!decodes_in_parallel(&spans));
+
+        fs::write(&archive_path, b"not zstd at all").unwrap();
+        assert_eq!(frame_spans(&archive_path), None);
+    }
+
+    #[test]
+    fn the_writer_pool_budget_waits_for_written_bytes() {
+        let budget = Arc::new(PoolBudget::default());
+        budget.take(POOLED_BYTES_MAX - 10);
+        let waiting = {
+            let budget = budget.clone();
+            std::thread::spawn(move || budget.take(20))
+        };
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert!(!waiting.is_finished());
+        budget.give_back(POOLED_BYTES_MAX - 10);
+        waiting.join().unwrap();
+        assert_eq!(*budget.queued.lock().unwrap(), 20);
+    }
+}
@@ -1,6 +1,7 @@
 //! GPU detection and management
 
 use crate::{Result, PortableSourceError};
+use std::path::PathBuf;
 use std::process::Command;
 #[cfg(windows)]
 use serde::Deserialize;
@@ -23,6 +24,153 @@ pub struct GpuInfo {
     pub driver_version: Option<String>,
 }
 
+/// Current VRAM usage of one NVIDIA GPU
+#[derive(Debug, Clone, PartialEq)]
+pub struct GpuMemoryUsage {
+    pub index: u32,
+    pub used_mb: u64,
+    pub total_mb: u64,
+    /// GPU utilization in percent, None when the driver does not report it
+    pub utilization_pct: Option<u32>,
+}
+
+impl GpuMemoryUsage {
+    pub fn free_mb(&self) -> u64 {
+        self.total_mb.saturating_sub(self.used_mb)
+    }
+}
+
+/// Properties of one NVIDIA GPU reported by the driver
+#[derive(Debug, Clone, PartialEq)]
+pub struct NvidiaGpuDetails {
+    pub index: u32,
+    pub name: String,
+    pub memory_total_mb: u64,
+    pub driver_version: String,
+    /// None when the driver is too old to report it
+    pub compute_capability: Option<ComputeCapability>,
+}
+
+/// NVIDIA GPU as listed by `nvidia-smi -L`, with its MIG instances when MIG mode is on
+#[derive(Debug, Clone, PartialEq)]
+pub struct GpuDevice {
+    pub index: u32,
+    pub name: String,
+    pub uuid: String,
+    pub mig: Vec<MigDevice>,
+}
+
+/// MIG instance (a slice of an A100/H100) that CUDA_VISIBLE_DEVICES can select by UUID
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigDevice {
+    pub index: u32,
+    /// Instance profile, e.g. `1g.10gb`
+    pub profile: String,
+    pub uuid: String,
+}
+
+/// CUDA compute capability of an NVIDIA GPU, e.g. 8.6 (Ampere) or 12.0 (Blackwell GeForce)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ComputeCapability {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl ComputeCapability {
+    /// Stable torch wheels ship no kernels from here on; nightly cu128 builds do
+    pub const NIGHTLY_TORCH: ComputeCapability = ComputeCapability { major: 10, minor: 0 };
+}
+
+impl std::str::FromStr for ComputeCapability {
+    type Err = PortableSourceError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (major, minor) = s.trim().split_once('.').unwrap_or((s.trim(), "0"));
+        match (major.parse(), minor.parse()) {
+            (Ok(major), Ok(minor)) => Ok(Self { major, minor }),
+            _ => Err(PortableSourceError::gpu_detection(format!("Invalid compute capability '{}' (expected e.g. 8.6)", s.trim()))),
+        }
+    }
+}
+
+impl std::fmt::Display for ComputeCapability {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// Compute backend that drives package selection and subprocess environment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Cuda,
+    /// AMD ROCm / HIP
+    Rocm,
+    /// Intel oneAPI (XPU)
+    Xpu,
+    /// Apple Metal Performance Shaders
+    Mps,
+    Cpu,
+}
+
+impl Backend {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Backend::Cuda => "cuda",
+            Backend::Rocm => "rocm",
+            Backend::Xpu => "xpu",
+            Backend::Mps => "mps",
+            Backend::Cpu => "cpu",
+        }
+    }
+
+    /// Pick the backend for a detected GPU; vendor runtimes must be installed for ROCm/oneAPI
+    pub fn from_gpu(gpu: Option<&GpuInfo>) -> Self {
+        if cfg!(all(target_os = "macos", target_arch = "aarch64")) {
+            return Backend::Mps;
+        }
+        let Some(gpu) = gpu else { return Backend::Cpu };
+        let name = gpu.name.to_uppercase();
+        if gpu.gpu_type == GpuType::Nvidia || name.contains("NVIDIA") || name.contains("GEFORCE") || name.contains("RTX") {
+            return Backend::Cuda;
+        }
+        match gpu.gpu_type {
+            GpuType::Amd if rocm_root().is_some() => Backend::Rocm,
+            GpuType::Intel if oneapi_root().is_some() => Backend::Xpu,
+            _ => Backend::Cpu,
+        }
+    }
+
+    /// Detect the backend of the best GPU in this machine
+    pub fn detect() -> Self {
+        Self::from_gpu(GpuDetector::new().get_best_gpu().ok().flatten().as_ref())
+    }
+}
+
+impl std::fmt::Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// ROCm install root: ROCM_PATH / HIP_PATH or the default /opt/rocm
+pub fn rocm_root() -> Option<PathBuf> {
+    ["ROCM_PATH", "HIP_PATH"]
+        .iter()
+        .filter_map(|var| std::env::var_os(var).map(PathBuf::from))
+        .chain(cfg!(unix).then(|| PathBuf::from("/opt/rocm")))
+        .find(|p| p.exists())
+}
+
+/// Intel oneAPI install root: ONEAPI_ROOT or the installer default
+pub fn oneapi_root() -> Option<PathBuf> {
+    let default = if cfg!(windows) { r"C:\Program Files (x86)\Intel\oneAPI" } else { "/opt/intel/oneapi" };
+    std::env::var_os("ONEAPI_ROOT")
+        .map(PathBuf::from)
+        .into_iter()
+        .chain(std::iter::once(PathBuf::from(default)))
+        .find(|p| p.exists())
+}
+
 pub struct GpuDetector;
 
 impl GpuDetector {
@@ -33,7 +181,7 @@ impl GpuDetector {
     /// Detect NVIDIA GPU using nvidia-smi
     pub fn detect_nvidia_gpu(&self) -> Result<Option<GpuInfo>> {
         let mut cmd = Command::new("nvidia-smi");
-        cmd.args(&["--query-gpu=name,memory.total,driver_version", "--format=csv,noheader,nounits"]);
+        cmd.args(["--query-gpu=name,memory.total,driver_version", "--format=csv,noheader,nounits"]);
 
         #[cfg(target_os = "windows")]
         {
@@ -53,7 +201,7 @@ impl GpuDetector {
                 }
             }
             _ => {
-                log::debug!("nvidia-smi not available or failed");
+                tracing::debug!("nvidia-smi not available or failed");
                 Ok(None)
             }
         }
@@ -184,6 +332,7 @@ impl GpuDetector {
     }
 
     
+    #[cfg_attr(not(windows), allow(dead_code))]
     fn determine_gpu_type(&self, name: &str) -> GpuType {
         let name_upper = name.to_uppercase();
         
@@ -220,10 +369,92 @@ impl GpuDetector {
                 if let Some(glx) = self.detect_gpu_linux_glxinfo() { gpus.push(glx); }
             }
             for gpu in &gpus { if gpu.gpu_type == GpuType::Nvidia { return Ok(Some(gpu.clone())); } }
-            return Ok(gpus.into_iter().next());
+            Ok(gpus.into_iter().next())
         }
     }
     
+    /// Query VRAM usage and utilization of all NVIDIA GPUs (empty if nvidia-smi is unavailable)
+    pub fn query_gpu_memory(&self) -> Vec<GpuMemoryUsage> {
+        let mut cmd = Command::new("nvidia-smi");
+        cmd.args(["--query-gpu=index,memory.used,memory.total,utilization.gpu", "--format=csv,noheader,nounits"]);
+
+        #[cfg(target_os = "windows")]
+        {
+            use std::os::windows::process::CommandExt;
+            cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
+        }
+
+        match cmd.output() {
+            Ok(output) if output.status.success() => parse_memory_query(&String::from_utf8_lossy(&output.stdout)),
+            _ => {
+                tracing::debug!("nvidia-smi memory query not available or failed");
+                Vec::new()
+            }
+        }
+    }
+
+    /// Highest compute capability among NVIDIA GPUs, as reported by the driver (NVML) through
+    /// nvidia-smi; None if it is unavailable or too old to know the `compute_cap` field
+    pub fn query_compute_capability(&self) -> Option<ComputeCapability> {
+        let mut cmd = Command::new("nvidia-smi");
+        cmd.args(["--query-gpu=compute_cap", "--format=csv,noheader"]);
+
+        #[cfg(target_os = "windows")]
+        {
+            use std::os::windows::process::CommandExt;
+            cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
+        }
+
+        match cmd.output() {
+            Ok(output) if output.status.success() => parse_compute_capabilities(&String::from_utf8_lossy(&output.stdout)),
+            _ => {
+                tracing::debug!("nvidia-smi compute capability query not available or failed");
+                None
+            }
+        }
+    }
+
+    /// Name, VRAM, driver and compute capability of every NVIDIA GPU (empty if nvidia-smi
+    /// is unavailable)
+    pub fn query_nvidia_details(&self) -> Vec<NvidiaGpuDetails> {
+        let mut cmd = Command::new("nvidia-smi");
+        cmd.args(["--query-gpu=index,name,memory.total,driver_version,compute_cap", "--format=csv,noheader,nounits"]);
+
+        #[cfg(target_os = "windows")]
+        {
+            use std::os::windows::process::CommandExt;
+            cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
+        }
+
+        match cmd.output() {
+            Ok(output) if output.status.success() => parse_details_query(&String::from_utf8_lossy(&output.stdout)),
+            _ => {
+                tracing::debug!("nvidia-smi details query not available or failed");
+                Vec::new()
+            }
+        }
+    }
+
+    /// NVIDIA GPUs and their MIG instances (empty if nvidia-smi is unavailable)
+    pub fn list_devices(&self) -> Vec<GpuDevice> {
+        let mut cmd = Command::new("nvidia-smi");
+        cmd.arg("-L");
+
+        #[cfg(target_os = "windows")]
+        {
+            use std::os::windows::process::CommandExt;
+            cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
+        }
+
+        match cmd.output() {
+            Ok(output) if output.status.success() => parse_device_list(&String::from_utf8_lossy(&output.stdout)),
+            _ => {
+                tracing::debug!("nvidia-smi device listing not available or failed");
+                Vec::new()
+            }
+        }
+    }
+
     /// Check if NVIDIA GPU is available
     pub fn has_nvidia_gpu(&self) -> bool {
         self.detect_nvidia_gpu().unwrap_or(None).is_some()
@@ -233,8 +464,202 @@ impl GpuDetector {
 
 // removed raw COM helpers; using wmi crate instead
 
+/// Parse `nvidia-smi --query-gpu=index,memory.used,memory.total` csv output; bad lines are skipped
+fn parse_memory_query(stdout: &str) -> Vec<GpuMemoryUsage> {
+    stdout
+        .lines()
+        .filter_map(|line| {
+            let parts: Vec<&str> = line.split(',').map(|s| s.trim()).collect();
+            let (index, used, total, utilization) = match parts.as_slice() {
+                [index, used, total] => (index, used, total, None),
+                [index, used, total, utilization] => (index, used, total, utilization.parse().ok()),
+                _ => return None,
+            };
+            Some(GpuMemoryUsage {
+                index: index.parse().ok()?,
+                used_mb: used.parse().ok()?,
+                total_mb: total.parse().ok()?,
+                utilization_pct: utilization,
+            })
+        })
+        .collect()
+}
+
+/// Highest capability in `nvidia-smi --query-gpu=compute_cap` output; bad lines are skipped
+fn parse_compute_capabilities(stdout: &str) -> Option<ComputeCapability> {
+    stdout.lines().filter_map(|line| line.parse().ok()).max()
+}
+
+/// Parse `nvidia-smi --query-gpu=index,name,memory.total,driver_version,compute_cap` csv
+/// output; bad lines are skipped
+fn parse_details_query(stdout: &str) -> Vec<NvidiaGpuDetails> {
+    stdout
+        .lines()
+        .filter_map(|line| {
+            let parts: Vec<&str> = line.split(',').map(str::trim).collect();
+            let [index, name, memory, driver, cc] = parts.as_slice() else { return None };
+            Some(NvidiaGpuDetails {
+                index: index.parse().ok()?,
+                name: name.to_string(),
+                memory_total_mb: memory.parse().ok()?,
+                driver_version: driver.to_string(),
+                compute_capability: cc.parse().ok(),
+            })
+        })
+        .collect()
+}
+
+/// Parse `nvidia-smi -L`: `GPU 0: <name> (UUID: GPU-...)` lines, each followed by indented
+/// `MIG 1g.10gb Device 0: (UUID: MIG-...)` lines of its instances; other lines are skipped
+fn parse_device_list(stdout: &str) -> Vec<GpuDevice> {
+    fn uuid(line: &str) -> Option<String> {
+        let start = line.find("(UUID: ")? + "(UUID: ".len();
+        let end = line[start..].find(')')? + start;
+        Some(line[start..end].trim().to_string())
+    }
+
+    let mut devices: Vec<GpuDevice> = Vec::new();
+    for line in stdout.lines() {
+        let line = line.trim();
+        if let Some(rest) = line.strip_prefix("GPU ") {
+            let Some((index, rest)) = rest.split_once(':') else { continue };
+            let (Ok(index), Some(uuid)) = (index.trim().parse(), uuid(rest)) else { continue };
+            let name = rest.split(" (UUID:").next().unwrap_or_default().trim().to_string();
+            devices.push(GpuDevice { index, name, uuid, mig: Vec::new() });
+        } else if let Some(rest) = line.strip_prefix("MIG ") {
+            let (Some(gpu), Some(uuid)) = (devices.last_mut(), uuid(rest)) else { continue };
+            let Some((head, _)) = rest.split_once(':') else { continue };
+            let mut words = head.split_whitespace();
+            let profile = words.next().unwrap_or_default().to_string();
+            let Some(Ok(index)) = words.last().map(str::parse) else { continue };
+            gpu.mig.push(MigDevice { index, profile, uuid });
+        }
+    }
+    devices
+}
+
+/// Value of CUDA_VISIBLE_DEVICES for a `--gpu` selector: a MIG or GPU UUID, a GPU index
+/// (`0`) or a MIG instance of a GPU (`0:1`). Always a UUID: nvidia-smi numbers GPUs by PCI
+/// bus while CUDA defaults to fastest-first, so an index can name a different card
+pub fn resolve_visible_device(devices: &[GpuDevice], selector: &str) -> Result<String> {
+    let selector = selector.trim();
+    let mig = devices.iter().flat_map(|gpu| gpu.mig.iter().map(move |mig| (gpu, mig)));
+    let found = if let Some((gpu, instance)) = selector.split_once(':').filter(|_| !selector.contains('-')) {
+        mig.clone()
+            .find(|(g, m)| gpu.parse() == Ok(g.index) && instance.parse() == Ok(m.index))
+            .map(|(_, m)| m.uuid.clone())
+    } else if let Ok(index) = selector.parse::<u32>() {
+        devices.iter().find(|gpu| gpu.index == index).map(|gpu| gpu.uuid.clone())
+    } else {
+        mig.clone()
+            .map(|(_, m)| &m.uuid)
+            .chain(devices.iter().map(|gpu| &gpu.uuid))
+            .find(|uuid| uuid.eq_ignore_ascii_case(selector))
+            .cloned()
+    };
+    found.ok_or_else(|| {
+        PortableSourceError::gpu_detection(format!(
+            "No GPU or MIG instance '{}' on this machine; list them with 'portablesource check-gpu --all'",
+            selector
+        ))
+    })
+}
+
+/// nvidia-smi index of the GPU a GPU or MIG UUID belongs to
+pub fn device_index(devices: &[GpuDevice], uuid: &str) -> Option<u32> {
+    devices
+        .iter()
+        .find(|gpu| gpu.uuid.eq_ignore_ascii_case(uuid) || gpu.mig.iter().any(|m| m.uuid.eq_ignore_ascii_case(uuid)))
+        .map(|gpu| gpu.index)
+}
+
 impl Default for GpuDetector {
     fn default() -> Self {
         Self::new()
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nvidia_gpu_maps_to_cuda() {
+        let gpu = GpuInfo { name: "NVIDIA GeForce RTX 4090".into(), gpu_type: GpuType::Nvidia, memory_mb: 24564, driver_version: None };
+        let expected = if cfg!(all(target_os = "macos", target_arch = "aarch64")) { Backend::Mps } else { Backend::Cuda };
+        assert_eq!(Backend::from_gpu(Some(&gpu)), expected);
+    }
+
+    #[test]
+    fn parses_memory_query() {
+        let out = "0, 20480, 24576, 97\n1, 512, 8192, [N/A]\n[N/A], x, y, z\n";
+        let gpus = parse_memory_query(out);
+        assert_eq!(gpus.len(), 2);
+        assert_eq!(gpus[0].free_mb(), 4096);
+        assert_eq!(gpus[0].utilization_pct, Some(97));
+        assert_eq!(gpus[1], GpuMemoryUsage { index: 1, used_mb: 512, total_mb: 8192, utilization_pct: None });
+    }
+
+    #[test]
+    fn highest_compute_capability_decides() {
+        let cc = parse_compute_capabilities("8.6\n12.0\n[N/A]\n").unwrap();
+        assert_eq!(cc.to_string(), "12.0");
+        assert!(cc >= ComputeCapability::NIGHTLY_TORCH);
+        assert!("9.0".parse::<ComputeCapability>().unwrap() < ComputeCapability::NIGHTLY_TORCH);
+        assert_eq!("10".parse::<ComputeCapability>().unwrap(), ComputeCapability::NIGHTLY_TORCH);
+        assert!("sm_120".parse::<ComputeCapability>().is_err());
+    }
+
+    #[test]
+    fn parses_details_query() {
+        let gpus = parse_details_query("0, NVIDIA GeForce RTX 4090, 24564, 550.54.15, 8.9\n1, Tesla K80, 11441, 470.223.02, [N/A]\nbad\n");
+        assert_eq!(gpus.len(), 2);
+        assert_eq!(gpus[0].name, "NVIDIA GeForce RTX 4090");
+        assert_eq!(gpus[0].compute_capability.map(|c| c.to_string()).as_deref(), Some("8.9"));
+        assert_eq!((gpus[1].memory_total_mb, gpus[1].compute_capability), (11441, None));
+    }
+
+    const MIG_LIST: &str = "\
+GPU 0: NVIDIA A100-SXM4-80GB (UUID: GPU-5d5ba0d6-d33d-2b2c-524d-9e3d8d2b8a77)
+  MIG 1g.10gb     Device  0: (UUID: MIG-c6d4f1ef-42e4-5de3-91c7-45d71c87eb3f)
+  MIG 3g.40gb     Device  1: (UUID: MIG-0a1b2c3d-4e5f-5a6b-8c7d-9e0f1a2b3c4d)
+GPU 1: NVIDIA A100-SXM4-80GB (UUID: GPU-11111111-2222-3333-4444-555555555555)
+";
+
+    #[test]
+    fn lists_gpus_with_their_mig_instances() {
+        let devices = parse_device_list(MIG_LIST);
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].name, "NVIDIA A100-SXM4-80GB");
+        assert_eq!(devices[0].mig[1], MigDevice { index: 1, profile: "3g.40gb".into(), uuid: "MIG-0a1b2c3d-4e5f-5a6b-8c7d-9e0f1a2b3c4d".into() });
+        assert!(devices[1].mig.is_empty());
+    }
+
+    #[test]
+    fn mig_selectors_resolve_to_the_instance_uuid() {
+        let devices = parse_device_list(MIG_LIST);
+        assert_eq!(resolve_visible_device(&devices, "0:1").unwrap(), "MIG-0a1b2c3d-4e5f-5a6b-8c7d-9e0f1a2b3c4d");
+        assert_eq!(resolve_visible_device(&devices, "mig-c6d4f1ef-42e4-5de3-91c7-45d71c87eb3f").unwrap(), "MIG-c6d4f1ef-42e4-5de3-91c7-45d71c87eb3f");
+    }
+
+    #[test]
+    fn gpu_selectors_resolve_to_the_gpu_uuid() {
+        let devices = parse_device_list(MIG_LIST);
+        assert_eq!(resolve_visible_device(&devices, "1").unwrap(), "GPU-11111111-2222-3333-4444-555555555555");
+        assert_eq!(resolve_visible_device(&devices, "GPU-11111111-2222-3333-4444-555555555555").unwrap(), "GPU-11111111-2222-3333-4444-555555555555");
+    }
+
+    #[test]
+    fn unknown_selectors_are_refused() {
+        let devices = parse_device_list(MIG_LIST);
+        assert!(resolve_visible_device(&devices, "1:0").is_err());
+        assert!(resolve_visible_device(&devices, "MIG-unknown").is_err());
+    }
+
+    #[test]
+    fn device_index_finds_the_gpu_owning_a_uuid() {
+        let devices = parse_device_list(MIG_LIST);
+        assert_eq!(device_index(&devices, "MIG-0a1b2c3d-4e5f-5a6b-8c7d-9e0f1a2b3c4d"), Some(0));
+        assert_eq!(device_index(&devices, "gpu-11111111-2222-3333-4444-555555555555"), Some(1));
+        assert_eq!(device_index(&devices, "MIG-unknown"), None);
+    }
+}
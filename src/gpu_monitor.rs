@@ -0,0 +1,288 @@
+//! GPU sampling while CUDA-heavy checks run
+//!
+//! A smoke test or benchmark that fails because another program holds the GPU looks like
+//! a broken install. [`GpuMonitor`] samples utilization and VRAM through nvidia-smi in a
+//! background thread while the check runs; when it fails on a busy GPU the error names
+//! the culprit ("GPU busy: 96% utilization, 7200 MB held by chrome.exe"). The check's own
+//! processes are left out: their VRAM does not count, and utilization only counts while
+//! none of them is on the GPU. `test-repo` and `benchmark` both run [`cuda_check`] under
+//! the monitor.
+
+use crate::gpu::{GpuDetector, GpuMemoryUsage};
+use crate::{PortableSourceError, Result};
+use std::path::Path;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+pub const SAMPLE_INTERVAL: Duration = Duration::from_millis(500);
+/// Utilization or VRAM share (percent) from which the GPU counts as busy
+const BUSY_PCT: u64 = 90;
+
+/// Process holding GPU memory, as listed by nvidia-smi
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GpuProcess {
+    pub pid: u32,
+    pub name: String,
+    /// 0 when the driver does not report per-process memory (Windows WDDM)
+    pub used_mb: u64,
+}
+
+fn parse_process_query(stdout: &str) -> Vec<GpuProcess> {
+    stdout
+        .lines()
+        .filter_map(|line| {
+            let parts: Vec<&str> = line.split(',').map(|s| s.trim()).collect();
+            let [pid, name, used] = parts.as_slice() else { return None };
+            let name = Path::new(name).file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_else(|| name.to_string());
+            Some(GpuProcess { pid: pid.parse().ok()?, name, used_mb: used.parse().unwrap_or(0) })
+        })
+        .collect()
+}
+
+/// `pid` is this program, the monitored process or (on Linux) one of its descendants
+fn is_own_process(pid: u32, watched: u32) -> bool {
+    if pid == std::process::id() || (watched != 0 && pid == watched) {
+        return true;
+    }
+    #[cfg(target_os = "linux")]
+    {
+        let mut current = pid;
+        // Field 4 of /proc/<pid>/stat is the parent; the name before it may hold spaces
+        for _ in 0..16 {
+            let Ok(stat) = std::fs::read_to_string(format!("/proc/{}/stat", current)) else { break };
+            let Some(parent) = stat.rsplit_once(')').and_then(|(_, rest)| rest.split_whitespace().nth(1)?.parse::<u32>().ok()) else { break };
+            if watched != 0 && parent == watched {
+                return true;
+            }
+            if parent <= 1 {
+                break;
+            }
+            current = parent;
+        }
+    }
+    false
+}
+
+/// Compute processes on NVIDIA GPUs (empty if nvidia-smi is unavailable)
+pub fn query_gpu_processes() -> Vec<GpuProcess> {
+    let mut cmd = Command::new("nvidia-smi");
+    cmd.args(["--query-compute-apps=pid,process_name,used_memory", "--format=csv,noheader,nounits"]);
+
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::process::CommandExt;
+        cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
+    }
+
+    match cmd.output() {
+        Ok(output) if output.status.success() => parse_process_query(&String::from_utf8_lossy(&output.stdout)),
+        _ => Vec::new(),
+    }
+}
+
+/// Peaks seen while monitoring
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GpuReport {
+    pub samples: usize,
+    pub peak_utilization_pct: Option<u32>,
+    pub peak_used_mb: u64,
+    pub total_mb: u64,
+    /// VRAM in use minus what the monitored processes hold
+    pub peak_foreign_mb: u64,
+    /// Utilization seen while none of the monitored processes was on the GPU
+    pub peak_foreign_utilization_pct: Option<u32>,
+    /// Largest process other than the monitored one, at its peak
+    pub top_process: Option<GpuProcess>,
+}
+
+impl GpuReport {
+    fn add(&mut self, gpus: &[GpuMemoryUsage], processes: Vec<GpuProcess>, own_pid: u32) {
+        if gpus.is_empty() {
+            return;
+        }
+        self.samples += 1;
+        let (own, others): (Vec<GpuProcess>, Vec<GpuProcess>) = processes.into_iter().partition(|p| is_own_process(p.pid, own_pid));
+        let own_mb: u64 = own.iter().map(|p| p.used_mb).sum();
+        for gpu in gpus {
+            self.peak_used_mb = self.peak_used_mb.max(gpu.used_mb);
+            self.total_mb = self.total_mb.max(gpu.total_mb);
+            self.peak_foreign_mb = self.peak_foreign_mb.max(gpu.used_mb.saturating_sub(own_mb));
+            if let Some(pct) = gpu.utilization_pct {
+                self.peak_utilization_pct = Some(self.peak_utilization_pct.unwrap_or(0).max(pct));
+                if own.is_empty() {
+                    self.peak_foreign_utilization_pct = Some(self.peak_foreign_utilization_pct.unwrap_or(0).max(pct));
+                }
+            }
+        }
+        let other = others.into_iter().max_by_key(|p| p.used_mb);
+        if let Some(other) = other {
+            if self.top_process.as_ref().is_none_or(|top| other.used_mb > top.used_mb) {
+                self.top_process = Some(other);
+            }
+        }
+    }
+
+    /// Busy with something else than the monitored processes
+    pub fn busy(&self) -> bool {
+        let util = self.peak_foreign_utilization_pct.unwrap_or(0) as u64 >= BUSY_PCT;
+        let vram = self.total_mb > 0 && self.peak_foreign_mb * 100 >= self.total_mb * BUSY_PCT;
+        util || vram
+    }
+
+    /// "GPU busy: ..." when the GPU was busy, for error context
+    pub fn busy_context(&self) -> Option<String> {
+        if !self.busy() {
+            return None;
+        }
+        let mut context = format!(
+            "GPU busy: {}% utilization, {}/{} MB VRAM used by other programs",
+            self.peak_foreign_utilization_pct.unwrap_or(0),
+            self.peak_foreign_mb,
+            self.total_mb
+        );
+        if let Some(p) = &self.top_process {
+            if p.used_mb > 0 {
+                context.push_str(&format!(", {} MB held by {} (pid {})", p.used_mb, p.name, p.pid));
+            } else {
+                context.push_str(&format!(", also used by {} (pid {})", p.name, p.pid));
+            }
+        }
+        Some(context)
+    }
+
+    /// Append the busy context to a failure; the error kind (and its hint) is kept
+    pub fn annotate(&self, err: PortableSourceError) -> PortableSourceError {
+        let Some(context) = self.busy_context() else { return err };
+        match err {
+            PortableSourceError::CudaOutOfMemory { message } => PortableSourceError::cuda_out_of_memory(format!("{}\n{}", message, context)),
+            PortableSourceError::Command { message } => PortableSourceError::command(format!("{}\n{}", message, context)),
+            other => PortableSourceError::command(format!("{}\n{}", other, context)),
+        }
+    }
+}
+
+/// Background nvidia-smi sampler
+pub struct GpuMonitor {
+    stop: Arc<AtomicBool>,
+    own_pid: Arc<AtomicU32>,
+    handle: JoinHandle<GpuReport>,
+}
+
+impl GpuMonitor {
+    pub fn start(interval: Duration) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let own_pid = Arc::new(AtomicU32::new(0));
+        let (stop_flag, pid) = (stop.clone(), own_pid.clone());
+        let handle = std::thread::spawn(move || {
+            let detector = GpuDetector::new();
+            let mut report = GpuReport::default();
+            while !stop_flag.load(Ordering::Relaxed) {
+                report.add(&detector.query_gpu_memory(), query_gpu_processes(), pid.load(Ordering::Relaxed));
+                std::thread::sleep(interval);
+            }
+            report
+        });
+        Self { stop, own_pid, handle }
+    }
+
+    /// The monitored process; it is not reported as the one keeping the GPU busy
+    pub fn watch_pid(&self, pid: u32) {
+        self.own_pid.store(pid, Ordering::Relaxed);
+    }
+
+    pub fn finish(self) -> GpuReport {
+        self.stop.store(true, Ordering::Relaxed);
+        self.handle.join().unwrap_or_default()
+    }
+}
+
+/// CUDA check run in the repository environment; argv[1] is the number of timed matmuls
+const CUDA_CHECK_SCRIPT: &str = r#"
+import sys, time
+try:
+    import torch
+except ImportError:
+    print("torch is not installed, nothing to check on the GPU")
+    sys.exit(0)
+print(f"torch {torch.__version__}, CUDA available: {torch.cuda.is_available()}")
+if not torch.cuda.is_available():
+    sys.exit(0)
+print(f"device: {torch.cuda.get_device_name(0)}")
+n, iters = 4096, int(sys.argv[1])
+a = torch.randn(n, n, device="cuda", dtype=torch.float16)
+b = torch.randn(n, n, device="cuda", dtype=torch.float16)
+(a @ b).sum().item()
+torch.cuda.synchronize()
+start = time.perf_counter()
+for _ in range(iters):
+    c = a @ b
+torch.cuda.synchronize()
+elapsed = time.perf_counter() - start
+print(f"fp16 matmul {n}x{n}: {2 * n ** 3 * iters / elapsed / 1e12:.1f} TFLOPS ({iters} runs)")
+"#;
+
+/// Run the CUDA check for a repository under the GPU monitor; returns its output
+pub fn cuda_check(install_path: &Path, repo: &str, iterations: u32) -> Result<(String, GpuReport)> {
+    let python = crate::shared_env::repo_python(install_path, repo)?;
+    if !python.exists() {
+        return Err(PortableSourceError::environment(format!("Environment of '{}' not found at {}", repo, python.display())));
+    }
+    let monitor = GpuMonitor::start(SAMPLE_INTERVAL);
+    let child = Command::new(&python)
+        .args(["-c", CUDA_CHECK_SCRIPT, &iterations.max(1).to_string()])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn();
+    let output = child.and_then(|child| {
+        monitor.watch_pid(child.id());
+        child.wait_with_output()
+    });
+    let report = monitor.finish();
+    let output = output.map_err(|e| PortableSourceError::command(format!("Failed to start {}: {}", python.display(), e)))?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let tail: Vec<&str> = stderr.lines().rev().take(5).collect::<Vec<_>>().into_iter().rev().collect();
+        let err = PortableSourceError::from_command_output(format!("CUDA check of '{}' failed:\n{}", repo, tail.join("\n")), &stderr);
+        return Err(report.annotate(err));
+    }
+    Ok((stdout, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn busy_gpu_names_the_largest_other_process() {
+        let processes = parse_process_query("4242, /usr/lib/chromium/chrome, 22500\n777, python, 900\n99, Xorg, [N/A]\n");
+        assert_eq!(processes[0].name, "chrome");
+        assert_eq!(processes[2].used_mb, 0);
+
+        let mut report = GpuReport::default();
+        let gpu = GpuMemoryUsage { index: 0, used_mb: 23400, total_mb: 24576, utilization_pct: Some(96) };
+        report.add(&[gpu], processes, 777);
+        assert!(report.busy());
+        let err = report.annotate(PortableSourceError::cuda_out_of_memory("CUDA check of 'comfyui' failed"));
+        assert!(matches!(err, PortableSourceError::CudaOutOfMemory { .. }));
+        assert!(err.to_string().ends_with("GPU busy: 0% utilization, 22500/24576 MB VRAM used by other programs, 22500 MB held by chrome (pid 4242)"));
+    }
+
+    #[test]
+    fn the_checks_own_load_does_not_make_the_gpu_busy() {
+        let mut report = GpuReport::default();
+        let gpu = GpuMemoryUsage { index: 0, used_mb: 23000, total_mb: 24576, utilization_pct: Some(100) };
+        report.add(std::slice::from_ref(&gpu), vec![GpuProcess { pid: 777, name: "python".into(), used_mb: 22500 }], 777);
+        assert!(!report.busy(), "{:?}", report);
+        assert_eq!((report.peak_used_mb, report.peak_foreign_mb), (23000, 500));
+
+        // Before the check reached the GPU, its load was someone else's
+        let gpu = GpuMemoryUsage { utilization_pct: Some(97), used_mb: 1200, ..gpu };
+        report.add(&[gpu], vec![GpuProcess { pid: 4242, name: "game".into(), used_mb: 0 }], 777);
+        assert!(report.busy_context().unwrap().starts_with("GPU busy: 97% utilization"));
+    }
+}
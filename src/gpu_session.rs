@@ -0,0 +1,245 @@
+//! GPU access of the current session
+//!
+//! Remote Desktop sessions and session 0 (services, scheduled tasks, SSH on Windows) can
+//! often not create CUDA contexts: nvidia-smi lists the GPU, but every repository then dies
+//! with a CUDA initialization error. `check-env` creates one CUDA context through the driver
+//! API from the base Python (ctypes, no torch needed) and explains what the session can do
+//! instead; run-repo runs the same probe in such sessions and offers to start on the CPU.
+
+use crate::system::{CommandExecutor, CommandRequest};
+use std::path::Path;
+
+/// CUDA_VISIBLE_DEVICES value that hides every GPU, so repositories fall back to the CPU
+pub const HIDE_GPUS: &str = "-1";
+
+/// Creates and destroys one CUDA context; prints `ok`, `noload <error>` or `<call> <code>`
+const PROBE_SCRIPT: &str = r#"import ctypes, sys
+try:
+    cuda = ctypes.WinDLL("nvcuda.dll") if sys.platform == "win32" else ctypes.CDLL("libcuda.so.1")
+except OSError as e:
+    print("noload", e)
+    sys.exit(0)
+dev, ctx = ctypes.c_int(), ctypes.c_void_p()
+for name, call in (("cuInit", lambda: cuda.cuInit(0)), ("cuDeviceGet", lambda: cuda.cuDeviceGet(ctypes.byref(dev), 0)), ("cuCtxCreate", lambda: cuda.cuCtxCreate_v2(ctypes.byref(ctx), 0, dev))):
+    code = call()
+    if code:
+        print(name, code)
+        sys.exit(0)
+cuda.cuCtxDestroy_v2(ctx)
+print("ok")
+"#;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionKind {
+    /// Local desktop session
+    Console,
+    /// Windows Remote Desktop (SESSIONNAME RDP-Tcp#N)
+    RemoteDesktop,
+    /// Windows session without a desktop: service, scheduled task, SSH
+    Service,
+    /// Unix session without a display or over SSH; CUDA usually works there
+    Headless,
+}
+
+impl SessionKind {
+    /// Session type from environment variables (`var` returns a variable's value)
+    pub fn detect(var: impl Fn(&str) -> Option<String>) -> Self {
+        if cfg!(windows) {
+            match var("SESSIONNAME") {
+                Some(name) if name.eq_ignore_ascii_case("console") => SessionKind::Console,
+                Some(name) if name.to_uppercase().starts_with("RDP-") => SessionKind::RemoteDesktop,
+                Some(_) => SessionKind::Console,
+                None => SessionKind::Service,
+            }
+        } else if var("SSH_CONNECTION").is_some() || (var("DISPLAY").is_none() && var("WAYLAND_DISPLAY").is_none()) {
+            SessionKind::Headless
+        } else {
+            SessionKind::Console
+        }
+    }
+
+    pub fn current() -> Self {
+        Self::detect(|name| std::env::var(name).ok().filter(|v| !v.is_empty()))
+    }
+
+    /// Sessions where Windows may keep the GPU from CUDA
+    pub fn may_lack_gpu(&self) -> bool {
+        matches!(self, SessionKind::RemoteDesktop | SessionKind::Service)
+    }
+
+    pub fn describe(&self) -> &'static str {
+        match self {
+            SessionKind::Console => "console",
+            SessionKind::RemoteDesktop => "Remote Desktop",
+            SessionKind::Service => "no desktop (service, scheduled task or SSH)",
+            SessionKind::Headless => "headless",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CudaProbe {
+    /// A CUDA context was created
+    Ok,
+    /// The CUDA driver library could not be loaded
+    NoDriver(String),
+    /// A driver call failed: its name and CUresult code
+    Failed { call: String, code: i32 },
+    /// The probe itself could not run (no Python)
+    NotRun(String),
+}
+
+impl CudaProbe {
+    pub fn is_ok(&self) -> bool {
+        matches!(self, CudaProbe::Ok)
+    }
+
+    /// One-line status for check-env
+    pub fn summary(&self) -> String {
+        match self {
+            CudaProbe::Ok => "OK (context created)".to_string(),
+            CudaProbe::NoDriver(detail) => format!("CUDA driver library not loadable: {}", detail),
+            CudaProbe::Failed { call, code } => format!("{} failed with {} ({})", call, code, error_name(*code)),
+            CudaProbe::NotRun(reason) => format!("not checked: {}", reason),
+        }
+    }
+}
+
+/// Names of the CUresult codes a blocked session produces
+fn error_name(code: i32) -> &'static str {
+    match code {
+        2 => "CUDA_ERROR_OUT_OF_MEMORY",
+        3 => "CUDA_ERROR_NOT_INITIALIZED",
+        34 => "CUDA_ERROR_STUB_LIBRARY",
+        46 => "CUDA_ERROR_DEVICE_UNAVAILABLE",
+        100 => "CUDA_ERROR_NO_DEVICE",
+        101 => "CUDA_ERROR_INVALID_DEVICE",
+        803 => "CUDA_ERROR_SYSTEM_DRIVER_MISMATCH",
+        999 => "CUDA_ERROR_UNKNOWN",
+        _ => "see the CUDA driver API error codes",
+    }
+}
+
+/// Parse the probe script's output
+pub fn parse_probe(stdout: &str) -> CudaProbe {
+    let line = stdout.lines().map(str::trim).rfind(|l| !l.is_empty()).unwrap_or("");
+    if line == "ok" {
+        return CudaProbe::Ok;
+    }
+    match line.split_once(' ') {
+        Some(("noload", detail)) => CudaProbe::NoDriver(detail.to_string()),
+        Some((call, code)) => match code.parse() {
+            Ok(code) => CudaProbe::Failed { call: call.to_string(), code },
+            Err(_) => CudaProbe::NotRun(format!("unexpected probe output '{}'", line)),
+        },
+        None => CudaProbe::NotRun(format!("unexpected probe output '{}'", line)),
+    }
+}
+
+/// Create one CUDA context with `python`
+pub fn probe_cuda(executor: &dyn CommandExecutor, python: Option<&Path>) -> CudaProbe {
+    let Some(python) = python else {
+        return CudaProbe::NotRun("no Python found; run setup-env".to_string());
+    };
+    let request = CommandRequest {
+        program: python.to_string_lossy().to_string(),
+        args: vec!["-c".to_string(), PROBE_SCRIPT.to_string()],
+        ..Default::default()
+    };
+    match executor.execute(&request) {
+        Ok(out) if out.success() => parse_probe(&out.stdout),
+        Ok(out) => CudaProbe::NotRun(out.stderr.lines().last().unwrap_or("probe failed").trim().to_string()),
+        Err(e) => CudaProbe::NotRun(e.to_string()),
+    }
+}
+
+/// What to do when `probe` failed in `session`
+pub fn advice(session: SessionKind, probe: &CudaProbe) -> Vec<String> {
+    if matches!(probe, CudaProbe::Ok | CudaProbe::NotRun(_)) {
+        return Vec::new();
+    }
+    let mut lines = Vec::new();
+    match session {
+        SessionKind::RemoteDesktop => lines.push(
+            "Remote Desktop sessions may not reach the GPU: start repositories from the console session (sign in locally, \
+             or move this session to the console with 'tscon %SESSIONNAME% /dest:console' from an elevated prompt), \
+             or use a remote tool that shares the console session (Parsec, VNC, Sunshine)"
+                .to_string(),
+        ),
+        SessionKind::Service => lines.push(
+            "Services, scheduled tasks and SSH sessions run without a desktop and often cannot use the GPU: \
+             start repositories from a signed-in desktop session (scheduled task: 'Run only when user is logged on')"
+                .to_string(),
+        ),
+        SessionKind::Console | SessionKind::Headless => match probe {
+            CudaProbe::NoDriver(_) => lines.push("Install or repair the NVIDIA driver; the CUDA driver library is part of it".to_string()),
+            _ if cfg!(windows) => lines.push("Update or reinstall the NVIDIA driver, then reboot".to_string()),
+            _ => lines.push(
+                "Check that this user can open /dev/nvidia* (video group) and that containers were started with GPU access (docker --gpus all)"
+                    .to_string(),
+            ),
+        },
+    }
+    lines.push(format!("Repositories can still run on the CPU (slowly): run-repo offers it, or set CUDA_VISIBLE_DEVICES={}", HIDE_GPUS));
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockExecutor;
+
+    fn failed_init() -> CudaProbe {
+        let executor = MockExecutor::new();
+        executor.succeed_with("cuCtxCreate", "cuInit 100\n");
+        probe_cuda(&executor, Some(Path::new("python")))
+    }
+
+    fn env(vars: &'static [(&'static str, &'static str)]) -> impl Fn(&str) -> Option<String> {
+        move |name: &str| vars.iter().find(|(k, _)| *k == name).map(|(_, v)| v.to_string())
+    }
+
+    #[test]
+    fn failed_call_is_reported_with_its_cuda_error_name() {
+        let probe = failed_init();
+        assert_eq!(probe, CudaProbe::Failed { call: "cuInit".into(), code: 100 });
+        assert!(probe.summary().contains("CUDA_ERROR_NO_DEVICE"));
+    }
+
+    #[test]
+    fn probe_output_is_parsed() {
+        assert_eq!(parse_probe("ok\n"), CudaProbe::Ok);
+        assert_eq!(parse_probe("noload libcuda.so.1: cannot open"), CudaProbe::NoDriver("libcuda.so.1: cannot open".into()));
+    }
+
+    #[test]
+    fn probe_needs_a_python() {
+        assert_eq!(probe_cuda(&MockExecutor::new(), None), CudaProbe::NotRun("no Python found; run setup-env".into()));
+    }
+
+    #[test]
+    fn failed_context_in_remote_session_points_to_console_and_cpu() {
+        let lines = advice(SessionKind::RemoteDesktop, &failed_init());
+        assert!(lines[0].contains("console session"));
+        assert!(lines[1].contains("CUDA_VISIBLE_DEVICES=-1"));
+    }
+
+    #[test]
+    fn working_context_needs_no_advice() {
+        assert!(advice(SessionKind::Console, &CudaProbe::Ok).is_empty());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn rdp_and_service_sessions_are_detected() {
+        assert_eq!(SessionKind::detect(env(&[("SESSIONNAME", "RDP-Tcp#3")])), SessionKind::RemoteDesktop);
+        assert_eq!(SessionKind::detect(env(&[])), SessionKind::Service);
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn display_and_ssh_sessions_are_detected() {
+        assert_eq!(SessionKind::detect(env(&[("DISPLAY", ":0")])), SessionKind::Console);
+        assert_eq!(SessionKind::detect(env(&[("DISPLAY", ":0"), ("SSH_CONNECTION", "1 2 3 4")])), SessionKind::Headless);
+    }
+}
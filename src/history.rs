@@ -0,0 +1,323 @@
+//! Local history of repository operations and the usage report built from it
+//!
+//! Every install, update and delete of a repository appends one JSON line to
+//! `logs/history.jsonl` under the install path: when, which repository, how long it took
+//! and, for failures, the error category. Nothing is sent anywhere; `report-usage`
+//! summarizes the file for administrators of shared installs.
+
+use crate::{PortableSourceError, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+pub const HISTORY_FILE: &str = "history.jsonl";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryOperation {
+    Install,
+    Update,
+    Delete,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// Unix time the operation finished
+    pub at: u64,
+    pub operation: HistoryOperation,
+    pub repo: String,
+    pub duration_ms: u64,
+    /// Error category of a failed operation (see [`PortableSourceError::category`])
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+pub fn history_path(install_path: &Path) -> PathBuf {
+    install_path.join("logs").join(HISTORY_FILE)
+}
+
+pub fn append(install_path: &Path, entry: &HistoryEntry) -> Result<()> {
+    let path = history_path(install_path);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = fs::OpenOptions::new().create(true).append(true).open(&path)?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)?;
+    Ok(())
+}
+
+/// Append the outcome of an operation that started at `started`; failures are only logged
+pub fn record<T>(install_path: &Path, operation: HistoryOperation, repo: &str, started: Instant, result: &Result<T>) {
+    let entry = HistoryEntry {
+        at: crate::utils::unix_timestamp(),
+        operation,
+        repo: repo.to_string(),
+        duration_ms: started.elapsed().as_millis() as u64,
+        error: result.as_ref().err().map(|e| e.category().to_string()),
+    };
+    if let Err(e) = append(install_path, &entry) {
+        warn!("Could not write operation history: {}", e);
+    }
+}
+
+/// All entries, oldest first; lines that do not parse are skipped
+pub fn load(install_path: &Path) -> Result<Vec<HistoryEntry>> {
+    let path = history_path(install_path);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    Ok(fs::read_to_string(path)?.lines().filter_map(|line| serde_json::from_str(line).ok()).collect())
+}
+
+/// `30d`, `12h`, `2w` or a plain number of days
+pub fn parse_since(text: &str) -> Result<Duration> {
+    let text = text.trim();
+    let (number, unit) = match text.char_indices().last() {
+        Some((idx, c)) if c.is_ascii_alphabetic() => (&text[..idx], c.to_ascii_lowercase()),
+        _ => (text, 'd'),
+    };
+    let seconds = match unit {
+        'h' => 3_600,
+        'd' => 86_400,
+        'w' => 7 * 86_400,
+        _ => 0,
+    };
+    match number.parse::<u64>().ok().and_then(|n| n.checked_mul(seconds)) {
+        Some(secs) if seconds > 0 => Ok(Duration::from_secs(secs)),
+        _ => Err(PortableSourceError::config(format!("Invalid period '{}' (expected e.g. 30d, 12h or 2w)", text))),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReportFormat {
+    #[default]
+    Text,
+    Csv,
+    Json,
+}
+
+impl std::str::FromStr for ReportFormat {
+    type Err = PortableSourceError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "text" => Ok(ReportFormat::Text),
+            "csv" => Ok(ReportFormat::Csv),
+            "json" => Ok(ReportFormat::Json),
+            other => Err(PortableSourceError::config(format!("Unknown report format '{}' (expected text, csv or json)", other))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct RepoUsage {
+    pub repo: String,
+    pub installs: usize,
+    pub updates: usize,
+    pub deletes: usize,
+    pub failures: usize,
+    /// Mean duration of successful installs
+    pub avg_install_secs: Option<f64>,
+    /// Mean gap between successful updates; needs two of them in the period
+    pub avg_days_between_updates: Option<f64>,
+    /// Failures per error category
+    pub failure_categories: BTreeMap<String, usize>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct UsageReport {
+    /// Unix time the period starts
+    pub since: u64,
+    pub operations: usize,
+    pub repos: Vec<RepoUsage>,
+    /// Failures per error category over all repositories
+    pub failure_categories: BTreeMap<String, usize>,
+}
+
+/// Summarize the entries finished at or after `since`, one row per repository sorted by name
+pub fn report(entries: &[HistoryEntry], since: u64) -> UsageReport {
+    let mut usage = UsageReport { since, ..Default::default() };
+    let mut repos: BTreeMap<&str, (RepoUsage, Vec<u64>, Vec<u64>)> = BTreeMap::new();
+    for entry in entries.iter().filter(|e| e.at >= since) {
+        usage.operations += 1;
+        let (repo, install_ms, update_times) = repos.entry(&entry.repo).or_default();
+        repo.repo = entry.repo.clone();
+        match entry.operation {
+            HistoryOperation::Install => repo.installs += 1,
+            HistoryOperation::Update => repo.updates += 1,
+            HistoryOperation::Delete => repo.deletes += 1,
+        }
+        match &entry.error {
+            Some(category) => {
+                repo.failures += 1;
+                *repo.failure_categories.entry(category.clone()).or_default() += 1;
+                *usage.failure_categories.entry(category.clone()).or_default() += 1;
+            }
+            None if entry.operation == HistoryOperation::Install => install_ms.push(entry.duration_ms),
+            None if entry.operation == HistoryOperation::Update => update_times.push(entry.at),
+            None => {}
+        }
+    }
+    for (_, (mut repo, install_ms, mut update_times)) in repos {
+        if !install_ms.is_empty() {
+            repo.avg_install_secs = Some(install_ms.iter().sum::<u64>() as f64 / install_ms.len() as f64 / 1000.0);
+        }
+        update_times.sort_unstable();
+        if let (Some(first), Some(last)) = (update_times.first(), update_times.last()) {
+            if update_times.len() > 1 {
+                repo.avg_days_between_updates = Some((last - first) as f64 / 86_400.0 / (update_times.len() - 1) as f64);
+            }
+        }
+        usage.repos.push(repo);
+    }
+    usage
+}
+
+fn optional(value: Option<f64>) -> String {
+    value.map(|v| format!("{:.1}", v)).unwrap_or_default()
+}
+
+fn categories(map: &BTreeMap<String, usize>) -> String {
+    map.iter().map(|(category, n)| format!("{}:{}", category, n)).collect::<Vec<_>>().join(";")
+}
+
+/// CSV field, quoted when it holds a separator, a quote or a line break (RFC 4180)
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// One row per repository; failure categories as `category:count` pairs separated by `;`
+pub fn to_csv(report: &UsageReport) -> String {
+    let mut out = String::from("repo,installs,updates,deletes,failures,avg_install_secs,avg_days_between_updates,failure_categories\n");
+    for repo in &report.repos {
+        let _ = writeln!(
+            out,
+            "{},{},{},{},{},{},{},{}",
+            csv_field(&repo.repo),
+            repo.installs,
+            repo.updates,
+            repo.deletes,
+            repo.failures,
+            optional(repo.avg_install_secs),
+            optional(repo.avg_days_between_updates),
+            csv_field(&categories(&repo.failure_categories))
+        );
+    }
+    out
+}
+
+pub fn to_text(report: &UsageReport, period: &str) -> String {
+    let mut out = format!("Usage over the last {} ({} operations)\n", period, report.operations);
+    if report.repos.is_empty() {
+        out.push_str("No repository was installed, updated or deleted in this period\n");
+        return out;
+    }
+    let _ = writeln!(out, "  {:<28} {:>8} {:>8} {:>8} {:>9} {:>13} {:>14}", "REPOSITORY", "INSTALLS", "UPDATES", "DELETES", "FAILURES", "AVG INSTALL", "DAYS/UPDATE");
+    for repo in &report.repos {
+        let avg_install = repo.avg_install_secs.map(|s| format!("{:.0}s", s)).unwrap_or_else(|| "-".into());
+        let per_update = repo.avg_days_between_updates.map(|d| format!("{:.1}", d)).unwrap_or_else(|| "-".into());
+        let _ = writeln!(
+            out,
+            "  {:<28} {:>8} {:>8} {:>8} {:>9} {:>13} {:>14}",
+            repo.repo, repo.installs, repo.updates, repo.deletes, repo.failures, avg_install, per_update
+        );
+    }
+    if !report.failure_categories.is_empty() {
+        out.push_str("Failures by category:\n");
+        for (category, n) in &report.failure_categories {
+            let _ = writeln!(out, "  {:<20} {}", category, n);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn periods_are_parsed_in_days_and_weeks() {
+        assert_eq!(parse_since("30d").unwrap(), Duration::from_secs(30 * 86_400));
+        assert_eq!(parse_since("2w").unwrap(), Duration::from_secs(14 * 86_400));
+        assert_eq!(parse_since("7").unwrap(), Duration::from_secs(7 * 86_400));
+        assert!(parse_since("soon").is_err());
+    }
+
+    const DAY: u64 = 86_400;
+
+    /// Report since day 5 of a history with installs and updates of comfyui and forge and a
+    /// line that is not JSON
+    fn usage_since_day_5() -> UsageReport {
+        let dir = tempfile::tempdir().unwrap();
+        let entry = |at, operation, repo: &str, duration_ms, error: Option<&str>| HistoryEntry {
+            at,
+            operation,
+            repo: repo.into(),
+            duration_ms,
+            error: error.map(str::to_string),
+        };
+        for e in [
+            entry(DAY, HistoryOperation::Install, "forge", 10_000, None),
+            entry(10 * DAY, HistoryOperation::Install, "comfyui", 100_000, None),
+            entry(11 * DAY, HistoryOperation::Install, "comfyui", 200_000, None),
+            entry(12 * DAY, HistoryOperation::Install, "comfyui", 5_000, Some("network")),
+            entry(13 * DAY, HistoryOperation::Update, "comfyui", 1_000, None),
+            entry(17 * DAY, HistoryOperation::Update, "comfyui", 1_000, None),
+            entry(18 * DAY, HistoryOperation::Update, "forge", 1_000, Some("disk_full")),
+        ] {
+            append(dir.path(), &e).unwrap();
+        }
+        fs::write(history_path(dir.path()), fs::read_to_string(history_path(dir.path())).unwrap() + "not json\n").unwrap();
+        report(&load(dir.path()).unwrap(), 5 * DAY)
+    }
+
+    #[test]
+    fn report_counts_operations_within_the_period() {
+        let usage = usage_since_day_5();
+        assert_eq!(usage.operations, 6);
+        let comfy = &usage.repos[0];
+        assert_eq!((comfy.installs, comfy.updates, comfy.failures), (3, 2, 1));
+    }
+
+    #[test]
+    fn report_averages_install_time_and_update_interval() {
+        let comfy = &usage_since_day_5().repos[0];
+        assert_eq!(comfy.avg_install_secs, Some(150.0));
+        assert_eq!(comfy.avg_days_between_updates, Some(4.0));
+    }
+
+    #[test]
+    fn report_counts_failures_by_category() {
+        let usage = usage_since_day_5();
+        assert_eq!(usage.failure_categories, BTreeMap::from([("disk_full".to_string(), 1), ("network".to_string(), 1)]));
+    }
+
+    #[test]
+    fn csv_has_one_row_per_repo() {
+        let csv = to_csv(&usage_since_day_5());
+        assert_eq!(csv.lines().nth(1), Some("comfyui,3,2,0,1,150.0,4.0,network:1"));
+        assert_eq!(csv.lines().nth(2), Some("forge,0,1,0,1,,,disk_full:1"));
+    }
+
+    #[test]
+    fn csv_fields_with_commas_or_quotes_are_quoted() {
+        assert_eq!(csv_field("my,repo \"v2\""), "\"my,repo \"\"v2\"\"\"");
+    }
+
+    #[test]
+    fn periods_that_overflow_are_rejected() {
+        assert_eq!(parse_since("2w").unwrap(), Duration::from_secs(14 * 86_400));
+        assert_eq!(parse_since("30").unwrap(), Duration::from_secs(30 * 86_400));
+        assert!(parse_since("99999999999999999w").is_err());
+        assert!(parse_since("5y").is_err());
+    }
+}
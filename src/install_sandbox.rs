@@ -0,0 +1,222 @@
+//! Opt-in sandbox for code a repository runs while it is installed
+//!
+//! Building an sdist runs its setup.py, and installing a repository as a package runs its
+//! build hooks: arbitrary code with the user's rights. With `install_sandbox` set to
+//! `prefer` or `require`, pip/uv/python invocations of the installer run under bubblewrap
+//! (or firejail) on Linux. Everything outside the installation and the temp folder is
+//! read-only, so a hook can build into the environment but not touch the user's files.
+//! uv and pip keep their caches in `cache/` of the installation for the same reason.
+//! Network access stays, since pip downloads inside the same process.
+//!
+//! `prefer` installs without the sandbox and says so when none is available; `require`
+//! refuses to install. There is no sandbox on Windows, so `config install-sandbox` is not
+//! offered there and a policy copied in from another machine finds none.
+
+use crate::system::CommandRequest;
+use crate::{PortableSourceError, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Cache of `tool` (uv, pip) for sandboxed runs; the user's own caches are read-only there
+pub fn sandbox_cache_dir(install_path: &Path, tool: &str) -> PathBuf {
+    install_path.join("cache").join(tool)
+}
+
+/// `install_sandbox` config setting
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SandboxPolicy {
+    #[default]
+    Off,
+    /// Sandbox when a sandbox tool works here, otherwise install unsandboxed with a warning
+    Prefer,
+    /// Refuse to run install hooks without a sandbox
+    Require,
+}
+
+impl SandboxPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SandboxPolicy::Off => "off",
+            SandboxPolicy::Prefer => "prefer",
+            SandboxPolicy::Require => "require",
+        }
+    }
+}
+
+impl std::fmt::Display for SandboxPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for SandboxPolicy {
+    type Err = PortableSourceError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "off" => Ok(SandboxPolicy::Off),
+            "prefer" => Ok(SandboxPolicy::Prefer),
+            "require" => Ok(SandboxPolicy::Require),
+            other => Err(PortableSourceError::config(format!(
+                "Unknown sandbox policy '{}' (expected off, prefer or require)",
+                other
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Sandbox {
+    Bubblewrap(PathBuf),
+    Firejail(PathBuf),
+}
+
+impl Sandbox {
+    /// The first sandbox tool that works here; Err says why there is none
+    #[cfg(unix)]
+    pub fn detect() -> std::result::Result<Self, String> {
+        use std::process::{Command, Stdio};
+        let works = |program: &std::path::Path, args: &[&str]| {
+            Command::new(program)
+                .args(args)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .is_ok_and(|s| s.success())
+        };
+        if let Ok(bwrap) = which::which("bwrap") {
+            if works(&bwrap, &["--ro-bind", "/", "/", "--dev", "/dev", "--", "true"]) {
+                return Ok(Sandbox::Bubblewrap(bwrap));
+            }
+        }
+        if let Ok(firejail) = which::which("firejail") {
+            // Same mounts as `wrap`; firejail builds that refuse them do not count
+            let temp = format!("--read-write={}", std::env::temp_dir().display());
+            if works(&firejail, &["--quiet", "--noprofile", "--read-only=/", &temp, "--", "true"]) {
+                return Ok(Sandbox::Firejail(firejail));
+            }
+        }
+        Err("neither bubblewrap (bwrap) nor firejail is installed and working (unprivileged user namespaces disabled?)".to_string())
+    }
+
+    #[cfg(windows)]
+    pub fn detect() -> std::result::Result<Self, String> {
+        Err("install sandboxing is not available on Windows".to_string())
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Sandbox::Bubblewrap(_) => "bubblewrap",
+            Sandbox::Firejail(_) => "firejail",
+        }
+    }
+
+    /// `request` run inside the sandbox, with only `install_path` and the temp folder
+    /// writable; uv and pip cache inside the installation
+    pub fn wrap(&self, mut request: CommandRequest, install_path: &Path) -> CommandRequest {
+        let writable = [install_path.to_path_buf(), std::env::temp_dir()];
+        for (var, tool) in [("UV_CACHE_DIR", "uv"), ("PIP_CACHE_DIR", "pip")] {
+            let dir = sandbox_cache_dir(install_path, tool);
+            request.envs.entry(var.to_string()).or_insert_with(|| dir.to_string_lossy().to_string());
+        }
+        let mut args: Vec<String> = Vec::new();
+        let program = match self {
+            Sandbox::Bubblewrap(bwrap) => {
+                args.extend(["--ro-bind", "/", "/", "--dev", "/dev", "--proc", "/proc"].map(String::from));
+                for dir in &writable {
+                    let dir = dir.to_string_lossy().to_string();
+                    args.extend(["--bind-try".to_string(), dir.clone(), dir]);
+                }
+                args.extend(["--unshare-pid", "--die-with-parent", "--new-session"].map(String::from));
+                bwrap
+            }
+            Sandbox::Firejail(firejail) => {
+                args.extend(["--quiet", "--noprofile", "--read-only=/", "--nonewprivs", "--caps.drop=all"].map(String::from));
+                args.extend(writable.iter().map(|dir| format!("--read-write={}", dir.display())));
+                firejail
+            }
+        };
+        args.push("--".to_string());
+        args.push(request.program);
+        args.extend(request.args);
+        CommandRequest { program: program.to_string_lossy().to_string(), args, ..request }
+    }
+}
+
+/// The sandbox `policy` asks for: None when off, or when `prefer` finds none (the warning is
+/// printed here); Err with the reason when `require` finds none
+pub fn resolve(
+    policy: SandboxPolicy,
+    detect: impl FnOnce() -> std::result::Result<Sandbox, String>,
+) -> std::result::Result<Option<Sandbox>, String> {
+    if policy == SandboxPolicy::Off {
+        return Ok(None);
+    }
+    match (detect(), policy) {
+        (Ok(sandbox), _) => {
+            tracing::info!("Install hooks run under {}", sandbox.name());
+            Ok(Some(sandbox))
+        }
+        (Err(reason), SandboxPolicy::Require) if cfg!(windows) => Err(format!(
+            "install_sandbox is 'require' but {}; set it to \"off\" in portablesource_config.json",
+            reason
+        )),
+        (Err(reason), SandboxPolicy::Require) => Err(format!(
+            "install_sandbox is 'require' but {}; install bubblewrap or run 'portablesource config install-sandbox prefer'",
+            reason
+        )),
+        (Err(reason), _) => {
+            crate::output::warn(&format!("Install hooks run WITHOUT a sandbox: {}", reason));
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pip_install() -> CommandRequest {
+        CommandRequest::from_args(&["/opt/ps/envs/comfyui/bin/python".into(), "-m".into(), "pip".into(), "install".into(), ".".into()])
+            .unwrap()
+            .cwd(Some(Path::new("/opt/ps/repos/comfyui")))
+    }
+
+    #[test]
+    fn bubblewrap_keeps_only_the_installation_writable() {
+        let request = pip_install();
+        let wrapped = Sandbox::Bubblewrap("/usr/bin/bwrap".into()).wrap(request.clone(), Path::new("/opt/ps"));
+        assert_eq!(wrapped.program, "/usr/bin/bwrap");
+        assert_eq!(wrapped.args[..3], ["--ro-bind", "/", "/"]);
+        let line = wrapped.command_line();
+        assert!(line.contains("--bind-try /opt/ps /opt/ps"), "{}", line);
+        assert!(line.ends_with("-- /opt/ps/envs/comfyui/bin/python -m pip install ."), "{}", line);
+        assert_eq!(wrapped.cwd, request.cwd);
+    }
+
+    #[test]
+    fn firejail_makes_the_whole_file_system_read_only() {
+        let wrapped = Sandbox::Firejail("/usr/bin/firejail".into()).wrap(pip_install(), Path::new("/opt/ps"));
+        assert!(wrapped.args.contains(&"--read-only=/".to_string()));
+        assert!(!wrapped.args.contains(&"--read-only=~".to_string()));
+        assert!(wrapped.args.contains(&"--read-write=/opt/ps".to_string()));
+    }
+
+    #[test]
+    fn sandboxed_uv_and_pip_cache_inside_the_installation() {
+        let mut request = pip_install();
+        request.envs.insert("PIP_CACHE_DIR".into(), "/opt/ps/custom".into());
+        let wrapped = Sandbox::Bubblewrap("/usr/bin/bwrap".into()).wrap(request, Path::new("/opt/ps"));
+        assert_eq!(wrapped.envs["UV_CACHE_DIR"], sandbox_cache_dir(Path::new("/opt/ps"), "uv").to_string_lossy());
+        assert_eq!(wrapped.envs["PIP_CACHE_DIR"], "/opt/ps/custom");
+    }
+
+    #[test]
+    fn policy_decides_what_happens_without_a_sandbox() {
+        assert_eq!("Require".parse::<SandboxPolicy>().unwrap(), SandboxPolicy::Require);
+        assert_eq!(resolve(SandboxPolicy::Off, || panic!("not detected when off")).unwrap(), None);
+        assert_eq!(resolve(SandboxPolicy::Prefer, || Err("no bwrap".into())).unwrap(), None);
+        assert!(resolve(SandboxPolicy::Require, || Err("no bwrap".into())).is_err());
+    }
+}
@@ -1,14 +1,13 @@
 // src/installer/command_runner.rs
 
 use crate::{Result, PortableSourceError};
+use crate::build_slots::{self, BuildSlot};
 use crate::envs_manager::PortableEnvironmentManager;
-use log::{info, debug};
-use std::io::{BufRead, BufReader};
+use crate::install_sandbox::{self, Sandbox};
+use crate::system::{CommandOutput, CommandRequest, Services};
+use tracing::{info, debug};
 use std::path::Path;
-use std::process::{Command, Stdio};
-
-#[cfg(windows)]
-use std::os::windows::process::CommandExt;
+use std::sync::OnceLock;
 
 // Enum для типизации команд. Он может остаться здесь.
 #[derive(Clone, Copy, Debug)]
@@ -24,59 +23,96 @@ pub enum CommandType {
 /// Он держит ссылку на EnvironmentManager, чтобы правильно настраивать окружение.
 pub struct CommandRunner<'a> {
     env_manager: &'a PortableEnvironmentManager,
+    /// Sandbox for install hooks, decided on the first pip/uv/python command
+    sandbox: OnceLock<std::result::Result<Option<Sandbox>, String>>,
 }
 
 impl<'a> CommandRunner<'a> {
     pub fn new(env_manager: &'a PortableEnvironmentManager) -> Self {
-        Self { env_manager }
+        Self { env_manager, sandbox: OnceLock::new() }
     }
 
     /// Публичный метод для запуска команды с выводом в лог.
     /// Это замена `run_tool_with_env`.
     pub fn run(&self, args: &[String], label: Option<&str>, cwd: Option<&Path>) -> Result<()> {
-        if args.is_empty() { return Ok(()); }
-        
-        let mut cmd = self.create_command(args, cwd);
-        cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
-        
+        let Some(request) = self.create_request(args, cwd) else { return Ok(()); };
         let command_type = self.determine_command_type(args);
-        
-        self.run_with_progress(cmd, label, command_type)
+        let request = self.sandboxed(request, command_type)?;
+        let _slot = self.build_slot(args, label, cwd)?;
+        self.run_with_progress(&request.log_lines(), label, command_type)
     }
 
     /// Публичный метод для "тихого" запуска.
     /// Это замена `run_tool_with_env_silent`.
     pub fn run_silent(&self, args: &[String], label: Option<&str>, cwd: Option<&Path>) -> Result<()> {
-        if args.is_empty() { return Ok(()); }
+        let Some(request) = self.create_request(args, cwd) else { return Ok(()); };
+        let request = self.sandboxed(request, self.determine_command_type(args))?;
+        let _slot = self.build_slot(args, label, cwd)?;
         if let Some(l) = label { info!("{}...", l); }
 
-        let mut cmd = self.create_command(args, cwd);
-        cmd.stdout(Stdio::null()).stderr(Stdio::null());
-        
-        let status = cmd.status().map_err(|e| PortableSourceError::command(e.to_string()))?;
-        if !status.success() {
-            return Err(PortableSourceError::command(format!("Silent command failed with status: {}", status)));
+        let output = self.env_manager.services().executor.execute(&request)?;
+        if !output.success() {
+            debug!("[stderr] {}", output.stderr.trim_end());
+            return Err(PortableSourceError::from_command_output(
+                format!("Silent command failed with status: {}", Self::status_text(&output)),
+                &output.stderr,
+            ));
         }
         Ok(())
     }
 
+    /// Провайдеры сети/процессов/времени, общие для всех компонентов установщика
+    pub fn services(&self) -> &Services {
+        self.env_manager.services()
+    }
+
+    /// Запуск с захватом stdout (например, `pip show`); ошибка только если процесс не стартовал
+    pub fn run_capture(&self, args: &[String], cwd: Option<&Path>) -> Result<CommandOutput> {
+        let request = self.create_request(args, cwd)
+            .ok_or_else(|| PortableSourceError::command("Empty command"))?;
+        self.env_manager.services().executor.execute(&request)
+    }
+
     // --- Приватные хелперы (логика из твоих старых функций) ---
 
-    /// Создает объект `Command` с настроенным окружением.
-    fn create_command(&self, args: &[String], cwd: Option<&Path>) -> Command {
-        let mut cmd = Command::new(&args[0]);
-        cmd.args(&args[1..]);
-        if let Some(dir) = cwd {
-            cmd.current_dir(dir);
+    /// Создает запрос с настроенным окружением.
+    fn create_request(&self, args: &[String], cwd: Option<&Path>) -> Option<CommandRequest> {
+        let request = CommandRequest::from_args(args)?;
+        // Package installs can fill the disk minutes in; stop them early with a clear error
+        let watch = matches!(self.determine_command_type(args), CommandType::Pip | CommandType::Uv)
+            .then(|| self.env_manager.install_path());
+        Some(request.cwd(cwd).envs(self.env_manager.setup_environment_for_subprocess()).watch_disk(watch))
+    }
+
+    /// Run pip/uv/python commands, which may execute a repository's build hooks, in the
+    /// sandbox `install_sandbox` asks for; only the installation stays writable
+    fn sandboxed(&self, request: CommandRequest, command_type: CommandType) -> Result<CommandRequest> {
+        if !matches!(command_type, CommandType::Pip | CommandType::Uv | CommandType::Python) {
+            return Ok(request);
+        }
+        let policy = self.env_manager.config_manager().get_config().install_sandbox;
+        match self.sandbox.get_or_init(|| install_sandbox::resolve(policy, Sandbox::detect)) {
+            Ok(Some(sandbox)) => Ok(sandbox.wrap(request, self.env_manager.install_path())),
+            Ok(None) => Ok(request),
+            Err(reason) => Err(PortableSourceError::environment(reason.clone())),
+        }
+    }
+
+    /// Machine-wide slot for pip/uv commands that may compile native code; held until the
+    /// command finishes
+    fn build_slot(&self, args: &[String], label: Option<&str>, cwd: Option<&Path>) -> Result<Option<BuildSlot>> {
+        if !matches!(self.determine_command_type(args), CommandType::Pip | CommandType::Uv) || !build_slots::is_native_build(args, cwd) {
+            return Ok(None);
+        }
+        let max = self.env_manager.config_manager().get_config().max_native_builds;
+        build_slots::acquire(&build_slots::slots_dir(), max, label.unwrap_or("Native build"))
+    }
+
+    fn status_text(output: &CommandOutput) -> String {
+        match output.code {
+            Some(code) => format!("exit code: {}", code),
+            None => "terminated by signal".to_string(),
         }
-        let envs = self.env_manager.setup_environment_for_subprocess();
-        cmd.envs(envs);
-        
-        // Hide console window on Windows
-        #[cfg(windows)]
-        cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
-        
-        cmd
     }
     
     /// Определяет тип команды по аргументам.
@@ -115,13 +151,9 @@ impl<'a> CommandRunner<'a> {
 
     /// Основная логика выполнения команды с захватом stdout/stderr.
     /// Это замена `run_with_progress_typed`.
-    fn run_with_progress(&self, mut cmd: Command, label: Option<&str>, command_type: CommandType) -> Result<()> {
-        // Твоя логика выполнения...
-        // ... (скопировано 1-в-1 из run_with_progress_typed)
+    fn run_with_progress(&self, request: &CommandRequest, label: Option<&str>, command_type: CommandType) -> Result<()> {
         if let Some(l) = label { info!("{}...", l); }
-        let mut child = cmd.spawn().map_err(|e| PortableSourceError::command(e.to_string()))?;
-        
-        let mut stderr_lines = Vec::new();
+        let output = self.env_manager.services().executor.execute(request)?;
         
         let error_prefix = match command_type {
             CommandType::Git => "Git command failed",
@@ -131,29 +163,16 @@ impl<'a> CommandRunner<'a> {
             CommandType::Other => "Command failed",
         };
         
-        if let Some(out) = child.stdout.take() {
-            let reader = BufReader::new(out);
-            for line in reader.lines().flatten() { debug!("[stdout] {}", line); }
-        }
-        
-        if let Some(err) = child.stderr.take() {
-            let reader = BufReader::new(err);
-            for line in reader.lines().flatten() {
-                debug!("[stderr] {}", line);
-                stderr_lines.push(line);
-            }
-        }
-        
-        let status = child.wait().map_err(|e| PortableSourceError::command(e.to_string()))?;
-        if !status.success() {
-            let error_msg = if !stderr_lines.is_empty() {
-                format!("Command failed with status: {}\nOutput:\n{}", status, stderr_lines.join("\n"))
+        if !output.success() {
+            let stderr = output.stderr.trim_end();
+            let error_msg = if !stderr.is_empty() {
+                format!("Command failed with status: {}\nOutput:\n{}", Self::status_text(&output), stderr)
             } else {
-                format!("Command failed with status: {}", status)
+                format!("Command failed with status: {}", Self::status_text(&output))
             };
             debug!("{}: {}", error_prefix, error_msg);
-            return Err(PortableSourceError::command(error_msg));
+            return Err(PortableSourceError::from_command_output(error_msg, stderr));
         }
         Ok(())
     }
-}
\ No newline at end of file
+}
@@ -1,10 +1,13 @@
 //! Dependency installer module for managing Python environments and package installations.
 
-use crate::installer::{PipManager, ServerClient};
+use crate::installer::{plan_review, PipManager, ServerClient};
+use crate::output;
+use crate::shared_env::{self, EnvTarget, Ledger};
+use crate::warm_start;
 
 use crate::PortableSourceError;
 use crate::Result;
-use log::{info, warn};
+use tracing::{info, warn};
 use std::path::{Path, PathBuf};
 use std::fs;
 use serde_json::Value as JsonValue;
@@ -13,6 +16,8 @@ pub struct DependencyInstaller<'a> {
     pip_manager: &'a PipManager<'a>,
     server_client: &'a ServerClient,
     install_path: PathBuf,
+    review_plan: bool,
+    warm_start: bool,
 }
 
 impl<'a> DependencyInstaller<'a> {
@@ -25,21 +30,65 @@ impl<'a> DependencyInstaller<'a> {
             pip_manager,
             server_client,
             install_path,
+            review_plan: false,
+            warm_start: false,
         }
     }
 
+    /// Show server plans as a diff against the repository and ask before running undeclared steps
+    pub fn with_plan_review(mut self, review: bool) -> Self {
+        self.review_plan = review;
+        self
+    }
+
+    /// Start a new venv from a clone of the most similar installed environment (see `warm_start`)
+    pub fn with_warm_start(mut self, warm_start: bool) -> Self {
+        self.warm_start = warm_start;
+        self
+    }
+
     /// Main entry point for installing dependencies for a repository
+    #[tracing::instrument(name = "dependencies", skip_all)]
     pub async fn install_dependencies(&self, repo_path: &Path) -> Result<()> {
         info!("Installing dependencies for: {:?}", repo_path);
         let repo_name = repo_path.file_name().and_then(|s| s.to_str()).unwrap_or("").to_lowercase();
 
-        // Ensure project environment exists (Windows: copy portable python; Linux: create venv)
-        self.create_venv_environment(&repo_name)?;
+        let Some(target) = EnvTarget::load(repo_path)? else {
+            // Ensure project environment exists (Windows: copy portable python; Linux: create venv)
+            self.create_venv_environment(&repo_name)?;
+            if self.warm_start {
+                self.warm_start_venv(&repo_name, repo_path)?;
+            }
+            return self.install_requirements(repo_path, &repo_name).await;
+        };
+
+        // Shared environment: nothing is created, and what the install changes is tracked
+        let prefix = target.prefix(&self.install_path)?;
+        output::warn(&format!(
+            "Installing '{}' into the shared {} without a venv: packages of other repositories there may be replaced",
+            repo_name, target
+        ));
+        let before = shared_env::packages(&prefix);
+        let result = self.install_requirements(repo_path, &repo_name).await;
+        let mut ledger = Ledger::load(&self.install_path, &target)?;
+        let conflicts = ledger.record(&repo_name, &before, &shared_env::packages(&prefix));
+        ledger.save(&self.install_path, &target)?;
+        for conflict in &conflicts {
+            output::warn(&format!("Shared environment conflict: {}", conflict));
+        }
+        result
+    }
 
-        // Try server installation plan first
-        if let Some(plan) = self.server_client.get_installation_plan(&repo_name)? {
+    /// Install from the server plan, a lock file, pyproject.toml or requirements files into
+    /// the environment already in place
+    async fn install_requirements(&self, repo_path: &Path, repo_name: &str) -> Result<()> {
+        // Try server installation plan first (instances use their upstream's plan)
+        let upstream = crate::repo_metadata::upstream_name(repo_path);
+        if let Some(plan) = self.server_client.get_installation_plan(&upstream)? {
             info!("Using server installation plan");
-            if self.execute_server_installation_plan(&repo_name, &plan, Some(repo_path))? {
+            if self.review_plan && !plan_review::confirm(&plan_review::review_plan(&plan, repo_path), repo_name) {
+                output::info("Server plan not run; installing from the repository's own files");
+            } else if self.execute_server_installation_plan(repo_name, &plan, Some(repo_path))? {
                 return Ok(());
             } else {
                 warn!("Server installation failed, falling back to local requirements.txt");
@@ -48,17 +97,31 @@ impl<'a> DependencyInstaller<'a> {
             info!("No server installation plan, using local files");
         }
 
+        // Poetry/pdm projects: install the versions pinned in their lock file
+        match crate::installer::lockfile::export_locked(repo_path) {
+            Ok(Some((tool, requirements_path))) => {
+                info!("Installing {} locked dependencies from {:?}", tool, requirements_path);
+                self.pip_manager.install_requirements_with_uv_or_pip(repo_name, &requirements_path, Some(repo_path))?;
+                if crate::installer::lockfile::is_package(repo_path, tool) {
+                    self.pip_manager.install_repo_without_deps(repo_name, repo_path)?;
+                }
+                return Ok(());
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Failed to export locked dependencies, falling back to pyproject.toml: {}", e),
+        }
+
         // Check for pyproject.toml first
         let pyproject_path = repo_path.join("pyproject.toml");
         if pyproject_path.exists() {
             info!("Found pyproject.toml, extracting dependencies");
             if let Ok(requirements_path) = self.pip_manager.extract_dependencies_from_pyproject(&pyproject_path, repo_path) {
                 info!("Installing from extracted pyproject.toml dependencies: {:?}", requirements_path);
-                self.pip_manager.install_requirements_with_uv_or_pip(&repo_name, &requirements_path, Some(repo_path))?;
+                self.pip_manager.install_requirements_with_uv_or_pip(repo_name, &requirements_path, Some(repo_path))?;
                 
                 // Install the repository itself as a package
                 info!("Installing repository as package with uv pip install .");
-                self.pip_manager.install_repo_as_package(&repo_name, repo_path)?;
+                self.pip_manager.install_repo_as_package(repo_name, repo_path)?;
                 
                 return Ok(());
             } else {
@@ -69,7 +132,7 @@ impl<'a> DependencyInstaller<'a> {
         // Fallback to requirements.txt variants using smart search
         if let Some(requirements_file) = self.pip_manager.find_requirements_files(repo_path) {
             info!("Installing from {:?}", requirements_file);
-            self.pip_manager.install_requirements_with_uv_or_pip(&repo_name, &requirements_file, Some(repo_path))?;
+            self.pip_manager.install_requirements_with_uv_or_pip(repo_name, &requirements_file, Some(repo_path))?;
         } else {
             info!("No requirements.txt or pyproject.toml found");
         }
@@ -78,6 +141,7 @@ impl<'a> DependencyInstaller<'a> {
 
     /// Create virtual environment for the repository
     fn create_venv_environment(&self, repo_name: &str) -> Result<()> {
+        let _span = tracing::info_span!("venv_create", repo = %repo_name).entered();
         let install_path = self.install_path.clone();
         let envs_path = install_path.join("envs");
         let venv_path = envs_path.join(repo_name);
@@ -88,17 +152,27 @@ impl<'a> DependencyInstaller<'a> {
         }
 
         if cfg!(windows) {
-            // Windows: копируем портативный Python в envs/{repo}
-            let ps_env_python = install_path.join("ps_env").join("python");
+            // Windows: копируем портативный Python (или системный, выбранный через --use-system-tools) в envs/{repo}
+            let system_python = self.pip_manager.config_manager().system_tool("python").and_then(Path::parent).map(Path::to_path_buf);
+            let ps_env_python = system_python.unwrap_or_else(|| install_path.join("ps_env").join("python"));
             if !ps_env_python.exists() { 
                 return Err(PortableSourceError::installation(format!("Portable Python not found at: {:?}", ps_env_python))); 
             }
-            info!("Creating environment by copying portable Python: {:?} -> {:?}", ps_env_python, venv_path);
-            self.copy_dir_recursive(&ps_env_python, &venv_path)?;
+            let is_system = self.pip_manager.config_manager().system_tool("python").is_some();
+            info!("Creating environment by copying base Python: {:?} -> {:?}", ps_env_python, venv_path);
+            if is_system {
+                // Only the interpreter: packages the user installed into the system Python stay out
+                copy_python_skeleton(&ps_env_python, &venv_path)?;
+            } else {
+                self.copy_dir_recursive(&ps_env_python, &venv_path)?;
+            }
             let python_exe = venv_path.join("python.exe");
             if !python_exe.exists() { 
                 return Err(PortableSourceError::installation(format!("Python executable not found in {:?}", venv_path))); 
             }
+            if is_system {
+                ensure_pip(&python_exe);
+            }
         } else {
             // Linux: в DESK режиме используем python из micromamba-базы, в CLOUD — системный python3
             fs::create_dir_all(&envs_path)?;
@@ -133,34 +207,43 @@ impl<'a> DependencyInstaller<'a> {
             }
             
             // Ensure pip is present in the new venv
-            let venv_py = venv_path.join("bin").join("python");
-            let pip_ok = {
-                let mut cmd = std::process::Command::new(&venv_py);
-                cmd.args(["-m", "pip", "--version"]);
-                
-                // Hide console window on Windows
-                #[cfg(windows)]
-                {
-                    use std::os::windows::process::CommandExt;
-                    cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
-                }
-                
-                cmd.status()
-                    .map(|s| s.success())
-                    .unwrap_or(false)
-            };
-            if !pip_ok {
-                let mut cmd = std::process::Command::new(&venv_py);
-                cmd.args(["-m", "ensurepip", "-U"]);
-                
-                // Hide console window on Windows
-                #[cfg(windows)]
-                {
-                    use std::os::windows::process::CommandExt;
-                    cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
-                }
-                
-                let _ = cmd.status();
+            ensure_pip(&venv_path.join("bin").join("python"));
+        }
+        Ok(())
+    }
+
+    /// Clone the packages of the installed environment closest to the repository's
+    /// requirements into its fresh venv. A failed clone is not fatal: the venv is created
+    /// again and everything is installed from scratch
+    fn warm_start_venv(&self, repo_name: &str, repo_path: &Path) -> Result<()> {
+        let Some(requirements) = self.pip_manager.find_requirements_files(repo_path) else {
+            return Ok(());
+        };
+        let required = warm_start::requirement_names(&fs::read_to_string(&requirements)?);
+        let envs = self.install_path.join("envs");
+        let venv = envs.join(repo_name);
+        let Some(donor) = warm_start::find_donor(&envs, repo_name, warm_start::python_version(&venv), &required) else {
+            info!("No installed environment is close enough to warm-start '{}'", repo_name);
+            return Ok(());
+        };
+        output::step(&format!("Warm start from {}", donor.describe()));
+        let cloned = warm_start::clone_env(&donor.path, &venv).and_then(|report| {
+            if !report.local_packages.is_empty() {
+                self.pip_manager.uninstall_packages(repo_name, &report.local_packages)?;
+            }
+            if !report.reinstall.is_empty() {
+                self.pip_manager.reinstall_without_deps(repo_name, &report.reinstall, Some(repo_path))?;
+            }
+            Ok(report)
+        });
+        match cloned {
+            Ok(report) => output::info(&format!(
+                "Cloned {} files ({} hard-linked); only the difference is installed",
+                report.files, report.linked
+            )),
+            Err(e) => {
+                output::warn(&format!("Warm start failed ({}); installing into a clean environment", e));
+                self.create_venv_environment(repo_name)?;
             }
         }
         Ok(())
@@ -168,6 +251,7 @@ impl<'a> DependencyInstaller<'a> {
 
     /// Execute server installation plan
     fn execute_server_installation_plan(&self, repo_name: &str, plan: &JsonValue, repo_path: Option<&Path>) -> Result<bool> {
+        let _span = tracing::info_span!("requirements_install", source = "server").entered();
         self.pip_manager.execute_server_installation_plan(repo_name, plan, repo_path)
     }
 
@@ -187,4 +271,69 @@ impl<'a> DependencyInstaller<'a> {
         }
         Ok(())
     }
+}
+
+/// Copy a Python install without the packages in `Lib/site-packages` (the folder itself is
+/// kept, empty)
+fn copy_python_skeleton(from: &Path, to: &Path) -> Result<()> {
+    fn copy(from: &Path, to: &Path, site_packages: &Path) -> Result<()> {
+        fs::create_dir_all(to)?;
+        if from == site_packages {
+            return Ok(());
+        }
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            let dst = to.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                copy(&entry.path(), &dst, site_packages)?;
+            } else {
+                fs::copy(entry.path(), &dst)?;
+            }
+        }
+        Ok(())
+    }
+    copy(from, to, &from.join("Lib").join("site-packages"))
+}
+
+/// Install pip with `ensurepip` when `python -m pip` does not work
+fn ensure_pip(python: &Path) {
+    let run = |args: &[&str]| {
+        let mut cmd = std::process::Command::new(python);
+        cmd.args(args);
+
+        // Hide console window on Windows
+        #[cfg(windows)]
+        {
+            use std::os::windows::process::CommandExt;
+            cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
+        }
+
+        cmd.status().map(|s| s.success()).unwrap_or(false)
+    };
+    if !run(&["-m", "pip", "--version"]) {
+        run(&["-m", "ensurepip", "-U"]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_python_skeleton_leaves_installed_packages_behind() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("Python311");
+        fs::create_dir_all(base.join("Lib/site-packages/torch")).unwrap();
+        fs::create_dir_all(base.join("Lib/json")).unwrap();
+        fs::write(base.join("python.exe"), "exe").unwrap();
+        fs::write(base.join("Lib/json/__init__.py"), "").unwrap();
+        fs::write(base.join("Lib/site-packages/torch/__init__.py"), "").unwrap();
+
+        let venv = dir.path().join("envs/app");
+        copy_python_skeleton(&base, &venv).unwrap();
+        assert!(venv.join("python.exe").is_file());
+        assert!(venv.join("Lib/json/__init__.py").is_file());
+        assert!(venv.join("Lib/site-packages").is_dir());
+        assert_eq!(fs::read_dir(venv.join("Lib/site-packages")).unwrap().count(), 0);
+    }
 }
\ No newline at end of file
@@ -2,11 +2,65 @@
 
 use crate::installer::command_runer::CommandRunner;
 use crate::envs_manager::PortableEnvironmentManager;
+use crate::output;
+use crate::system::CommandRequest;
 use crate::PortableSourceError;
 use crate::Result;
 use std::fs;
 use std::path::Path;
-use log::{info, warn};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Upper bound for the `git ls-remote` check done before cloning
+pub const REMOTE_CHECK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// First git release with `git worktree add --relative-paths`
+const RELATIVE_WORKTREES_SINCE: (u32, u32, u32) = (2, 48, 0);
+
+/// Why a remote failed the pre-clone check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemoteFailure {
+    NotFound,
+    /// Credentials missing or rejected (hosts like GitHub also answer this for missing repos)
+    Auth,
+    Network,
+    Other,
+}
+
+/// Classify `git ls-remote` stderr
+pub fn classify_remote_failure(stderr: &str) -> RemoteFailure {
+    let text = stderr.to_lowercase();
+    let any = |patterns: &[&str]| patterns.iter().any(|p| text.contains(p));
+    if any(&["repository not found", "' not found", "does not appear to be a git repository", "error: 404", "does not exist"]) {
+        RemoteFailure::NotFound
+    } else if any(&[
+        "authentication failed",
+        "could not read username",
+        "could not read password",
+        "terminal prompts disabled",
+        "permission denied",
+        "invalid username or password",
+        "host key verification failed",
+        "error: 401",
+        "error: 403",
+    ]) {
+        RemoteFailure::Auth
+    } else if any(&[
+        "could not resolve",
+        "failed to connect",
+        "timed out",
+        "connection refused",
+        "network is unreachable",
+        "operation too slow",
+        "ssl",
+        "tls",
+        "unable to access",
+    ]) {
+        RemoteFailure::Network
+    } else {
+        RemoteFailure::Other
+    }
+}
 
 /// Repository information struct for git operations
 pub struct RepositoryInfo {
@@ -18,11 +72,18 @@ pub struct RepositoryInfo {
 pub struct GitManager<'a> {
     command_runner: &'a CommandRunner<'a>,
     env_manager: &'a PortableEnvironmentManager,
+    branch: Option<String>,
 }
 
 impl<'a> GitManager<'a> {
     pub fn new(command_runner: &'a CommandRunner, env_manager: &'a PortableEnvironmentManager) -> Self {
-        Self { command_runner, env_manager }
+        Self { command_runner, env_manager, branch: None }
+    }
+
+    /// Clone, check out and update this branch instead of the remote default
+    pub fn with_branch(mut self, branch: Option<String>) -> Self {
+        self.branch = branch;
+        self
     }
 
     fn get_git_executable(&self) -> String {
@@ -30,6 +91,50 @@ impl<'a> GitManager<'a> {
         "git".into()
     }
 
+    /// Check that `repo_url` exists and can be read with the current credentials, without
+    /// prompting for any; nothing is written to disk
+    pub fn check_remote(&self, repo_url: &str) -> Result<()> {
+        let args = [
+            self.get_git_executable(),
+            "-c".into(),
+            "http.lowSpeedLimit=1000".into(),
+            "-c".into(),
+            "http.lowSpeedTime=20".into(),
+            "ls-remote".into(),
+            repo_url.to_string(),
+            "HEAD".into(),
+        ];
+        let mut envs = self.env_manager.setup_environment_for_subprocess();
+        // Fail instead of waiting for a password or a credential manager window
+        envs.insert("GIT_TERMINAL_PROMPT".into(), "0".into());
+        envs.insert("GCM_INTERACTIVE".into(), "never".into());
+        envs.entry("GIT_SSH_COMMAND".into()).or_insert_with(|| "ssh -o BatchMode=yes -o ConnectTimeout=15".into());
+        let request = CommandRequest::from_args(&args)
+            .map(|r| r.envs(envs).timeout(REMOTE_CHECK_TIMEOUT))
+            .ok_or_else(|| PortableSourceError::command("Empty command"))?;
+
+        let output = self.command_runner.services().executor.execute(&request).map_err(|e| match e {
+            PortableSourceError::Io(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                PortableSourceError::network(format!("{} did not answer within {} s", repo_url, REMOTE_CHECK_TIMEOUT.as_secs()))
+            }
+            e => e,
+        })?;
+        if output.success() {
+            return Ok(());
+        }
+
+        let detail = output.stderr.lines().map(str::trim).rfind(|l| !l.is_empty()).unwrap_or("no error output").to_string();
+        Err(match classify_remote_failure(&output.stderr) {
+            RemoteFailure::NotFound => PortableSourceError::repository_not_found(format!("{} ({})", repo_url, detail)),
+            RemoteFailure::Auth => PortableSourceError::permission_denied(format!(
+                "{} needs credentials: the repository is private or does not exist. Configure git credentials or an SSH key and retry ({})",
+                repo_url, detail
+            )),
+            RemoteFailure::Network => PortableSourceError::network(format!("Cannot reach {}: {}", repo_url, detail)),
+            RemoteFailure::Other => PortableSourceError::repository(format!("git ls-remote {} failed: {}", repo_url, detail)),
+        })
+    }
+
     /// Clone or update repository using RepositoryInfo struct (main interface)
     pub async fn clone_or_update_repository(&self, repo_info: &RepositoryInfo, repo_path: &Path) -> Result<()> {
         let repo_url = repo_info.url.as_ref().ok_or_else(|| PortableSourceError::repository("Missing repository URL"))?;
@@ -37,6 +142,7 @@ impl<'a> GitManager<'a> {
     }
 
     /// Clone or update repository from URL (helper method)
+    #[tracing::instrument(name = "clone", skip_all, fields(url = %repo_url))]
     pub async fn clone_or_update_repository_from_url(&self, repo_url: &str, repo_path: &Path) -> Result<()> {
         let git_exe = self.get_git_executable();
         if repo_path.exists() {
@@ -64,7 +170,7 @@ impl<'a> GitManager<'a> {
         let parent = repo_path.parent().ok_or_else(|| PortableSourceError::repository("Invalid repo path"))?;
         fs::create_dir_all(parent)?;
         let mut args = vec![git_exe.clone(), "clone".to_string()];
-        if let Some(branch) = None::<String> { 
+        if let Some(branch) = self.branch.clone() {
             args.push("-b".to_string());
             args.push(branch);
         }
@@ -74,17 +180,65 @@ impl<'a> GitManager<'a> {
         match self.command_runner.run(&args, Some("Cloning repository"), Some(parent)) {
             Ok(_) => {
                 info!("Repository cloned successfully to: {:?}", repo_path);
-                println!("[PortableSource] Repository cloned successfully");
+                output::step("Repository cloned successfully");
                 Ok(())
             }
             Err(e) => {
                 eprintln!("Failed to clone repository from {}: {}", repo_url, e);
-                println!("[PortableSource] Failed to clone repository: {}", e);
+                output::error(&format!("Failed to clone repository: {}", e));
                 Err(e)
             }
         }
     }
 
+    /// Check out the branch into `repo_path` as a worktree of the clone at `base_path`, so both
+    /// share one object store; an existing worktree is updated instead. Links between the two
+    /// are relative where git supports it, so moving the install does not break them
+    #[tracing::instrument(name = "worktree", skip_all, fields(base = %base_path.display()))]
+    pub fn add_worktree(&self, base_path: &Path, repo_path: &Path) -> Result<()> {
+        let git_exe = self.get_git_executable();
+        if repo_path.join(".git").is_file() {
+            self.repair_worktree(base_path, repo_path)?;
+            return self.update_repository_with_fixes(&git_exe, repo_path);
+        }
+        if repo_path.exists() {
+            return Err(PortableSourceError::repository(format!("Directory exists but is not a git worktree: {:?}", repo_path)));
+        }
+        let branch = self.branch.clone().ok_or_else(|| PortableSourceError::repository("A worktree needs a branch"))?;
+
+        let fetch = vec![git_exe.clone(), "fetch".into(), "origin".into(), branch.clone()];
+        self.command_runner.run(&fetch, Some(&format!("Fetching branch {}", branch)), Some(base_path))?;
+        // The local branch tracks the remote one so `git pull` in the worktree keeps working
+        let mut args = vec![git_exe.clone(), "worktree".into(), "add".into(), "--track".into()];
+        if self.git_version(&git_exe).is_some_and(|v| v >= RELATIVE_WORKTREES_SINCE) {
+            args.push("--relative-paths".into());
+        }
+        args.extend(["-B".into(), branch.clone(), repo_path.to_string_lossy().to_string(), format!("origin/{}", branch)]);
+        self.command_runner.run(&args, Some("Adding worktree"), Some(base_path))?;
+        info!("Worktree of {:?} on branch {} created at {:?}", base_path, branch, repo_path);
+        output::step(&format!("Branch {} checked out as a worktree", branch));
+        Ok(())
+    }
+
+    /// Point the clone at `base_path` and its worktree at `repo_path` at each other again after
+    /// the install was moved, restored from a backup or imported
+    pub fn repair_worktree(&self, base_path: &Path, repo_path: &Path) -> Result<()> {
+        let args = vec![self.get_git_executable(), "worktree".into(), "repair".into(), repo_path.to_string_lossy().to_string()];
+        self.command_runner.run_silent(&args, Some("Repairing worktree links"), Some(base_path))
+    }
+
+    fn git_version(&self, git_exe: &str) -> Option<(u32, u32, u32)> {
+        let output = self.command_runner.run_capture(&[git_exe.to_string(), "--version".into()], None).ok()?;
+        crate::envs_manager::parse_tool_version(&output.stdout)
+    }
+
+    /// Forget worktrees of the clone at `base_path` whose folders were deleted
+    pub fn prune_worktrees(&self, base_path: &Path) -> Result<()> {
+        let args = vec![self.get_git_executable(), "worktree".into(), "prune".into()];
+        self.command_runner.run(&args, Some("Pruning worktrees"), Some(base_path))?;
+        Ok(())
+    }
+
     fn update_repository_with_fixes(&self, git_exe: &str, repo_path: &Path) -> Result<()> {
         let max_attempts = 3;
         for attempt in 0..max_attempts {
@@ -103,9 +257,8 @@ impl<'a> GitManager<'a> {
                         return Err(PortableSourceError::repository("Repository corrupted (exit code 128) - removed for re-cloning"));
                     }
                     
-                    if attempt < max_attempts - 1 {
-                        if self.fix_git_issues(git_exe, repo_path).is_ok() { continue; }
-                    }
+                    if attempt < max_attempts - 1
+                        && self.fix_git_issues(git_exe, repo_path).is_ok() { continue; }
                     if attempt == max_attempts - 1 { return Err(PortableSourceError::repository("Failed to update repository")); }
                 }
             }
@@ -115,9 +268,10 @@ impl<'a> GitManager<'a> {
 
     fn fix_git_issues(&self, git_exe: &str, repo_path: &Path) -> Result<()> {
         // Try a sequence of common fixes
+        let reset_to = format!("origin/{}", self.branch.as_deref().unwrap_or("main"));
         let fixes: Vec<Vec<&str>> = vec![
             vec!["fetch", "origin"],
-            vec!["reset", "--hard", "origin/main"],
+            vec!["reset", "--hard", &reset_to],
         ];
         for fix_args in fixes {
             let mut args = vec![git_exe.to_string()];
@@ -135,7 +289,11 @@ impl<'a> GitManager<'a> {
                 warn!("Failed to fetch from remote: {}", e);
             }
         }
-        {
+        if let Some(branch) = &self.branch {
+            let target = format!("origin/{}", branch);
+            let args = vec![git_exe.clone(), "reset".to_string(), "--hard".to_string(), target.clone()];
+            self.command_runner.run(&args, Some(&format!("Reset to {}", target)), Some(repo_path))?;
+        } else {
             let args = vec![git_exe.clone(), "reset".to_string(), "--hard".to_string(), "origin/main".to_string()];
             if self.command_runner.run(&args, Some("Reset to origin/main"), Some(repo_path)).is_err() {
                 let args = vec![git_exe.clone(), "reset".to_string(), "--hard".to_string(), "origin/master".to_string()];
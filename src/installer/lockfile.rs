@@ -0,0 +1,422 @@
+//! Locked dependencies of poetry and pdm projects
+//!
+//! Poetry and pdm keep their dependencies under `[tool.poetry]` / `[tool.pdm]` and pin the
+//! resolved versions in `poetry.lock` / `pdm.lock`, none of which `[project.dependencies]`
+//! parsing sees. This module does what `poetry export` / `pdm export` would without
+//! needing either tool in the environment: the runtime packages of the lock file (dev and
+//! other optional groups left out) become `name==version ; marker` lines in
+//! `requirements_lock.txt`, which then goes through the regular requirements install.
+//!
+//! Packages that only an extra needs (poetry `[tool.poetry.extras]`, pdm
+//! `[project.optional-dependencies]`) are exported only for the extras chosen with
+//! `install-repo --extras`, which are remembered for updates. The export and the extras live
+//! in the repository's state folder, outside the checkout.
+
+use crate::{PortableSourceError, Result};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::Value as TomlValue;
+
+pub const EXPORT_FILE: &str = "requirements_lock.txt";
+/// Extras chosen at install time, one per line (in the repository's state folder)
+pub const EXTRAS_FILE: &str = "extras";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockTool {
+    Poetry,
+    Pdm,
+}
+
+impl LockTool {
+    pub fn lock_file(&self) -> &'static str {
+        match self {
+            LockTool::Poetry => "poetry.lock",
+            LockTool::Pdm => "pdm.lock",
+        }
+    }
+}
+
+impl std::fmt::Display for LockTool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            LockTool::Poetry => "poetry",
+            LockTool::Pdm => "pdm",
+        })
+    }
+}
+
+/// PEP 503 normalized name (lock files and requirement strings differ in case and separators)
+pub(crate) fn normalize(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    for c in name.trim().chars() {
+        let c = if c == '_' || c == '.' { '-' } else { c.to_ascii_lowercase() };
+        if !(c == '-' && out.ends_with('-')) {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Name part of a PEP 508 requirement string
+pub(crate) fn requirement_name(req: &str) -> String {
+    let end = req.find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))).unwrap_or(req.len());
+    normalize(&req[..end])
+}
+
+fn read_toml(path: &Path) -> Result<TomlValue> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| PortableSourceError::repository(format!("Failed to read {:?}: {}", path, e)))?;
+    content.parse().map_err(|e| PortableSourceError::repository(format!("Failed to parse {:?}: {}", path, e)))
+}
+
+/// Lock tool of a repository: a `[tool.poetry]` / `[tool.pdm]` section together with its lock file
+pub fn detect(repo_path: &Path) -> Option<LockTool> {
+    let pyproject = read_toml(&repo_path.join("pyproject.toml")).ok();
+    let tool = pyproject.as_ref().and_then(|p| p.get("tool"));
+    [LockTool::Poetry, LockTool::Pdm]
+        .into_iter()
+        .find(|t| tool.and_then(|tool| tool.get(t.to_string())).is_some() && repo_path.join(t.lock_file()).exists())
+}
+
+/// Whether the project itself is an installable package (poetry `package-mode`, pdm `distribution`)
+pub fn is_package(repo_path: &Path, tool: LockTool) -> bool {
+    let Ok(pyproject) = read_toml(&repo_path.join("pyproject.toml")) else { return true };
+    let section = pyproject.get("tool").and_then(|t| t.get(tool.to_string()));
+    let key = match tool {
+        LockTool::Poetry => "package-mode",
+        LockTool::Pdm => "distribution",
+    };
+    section.and_then(|s| s.get(key)).and_then(|v| v.as_bool()).unwrap_or(true)
+}
+
+/// Extras remembered for the repository
+pub fn requested_extras(repo_path: &Path) -> Vec<String> {
+    crate::repo_state::read(repo_path, EXTRAS_FILE)
+        .unwrap_or_default()
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Remember the extras to install for the repository; an empty list forgets them
+pub fn save_extras(repo_path: &Path, extras: &[String]) -> Result<()> {
+    if extras.is_empty() {
+        let _ = fs::remove_file(crate::repo_state::path(repo_path, EXTRAS_FILE));
+        return Ok(());
+    }
+    crate::repo_state::write(repo_path, EXTRAS_FILE, extras.join("\n") + "\n")?;
+    Ok(())
+}
+
+/// Package names each extra pulls in
+fn declared_extras(pyproject: &TomlValue, tool: LockTool) -> HashMap<String, Vec<String>> {
+    let table = match tool {
+        LockTool::Poetry => pyproject.get("tool").and_then(|t| t.get("poetry")).and_then(|p| p.get("extras")),
+        LockTool::Pdm => pyproject.get("project").and_then(|p| p.get("optional-dependencies")),
+    };
+    table
+        .and_then(|t| t.as_table())
+        .into_iter()
+        .flatten()
+        .map(|(extra, packages)| {
+            let names = packages.as_array().into_iter().flatten().filter_map(|p| p.as_str()).map(requirement_name).collect();
+            (normalize(extra), names)
+        })
+        .collect()
+}
+
+/// Roots of the requested extras; an extra the project does not declare is an error
+fn extra_roots(pyproject: &TomlValue, tool: LockTool, extras: &[String]) -> Result<Vec<String>> {
+    let declared = declared_extras(pyproject, tool);
+    let mut roots = Vec::new();
+    for extra in extras {
+        let Some(names) = declared.get(&normalize(extra)) else {
+            let mut known: Vec<&str> = declared.keys().map(String::as_str).collect();
+            known.sort();
+            return Err(PortableSourceError::config(format!(
+                "Unknown extra '{}' (known: {})",
+                extra,
+                if known.is_empty() { "none".to_string() } else { known.join(", ") }
+            )));
+        };
+        roots.extend(names.iter().cloned());
+    }
+    Ok(roots)
+}
+
+/// Runtime dependencies declared in pyproject.toml (the roots of the lock graph); poetry
+/// dependencies marked `optional` belong to extras and are left out
+fn root_dependencies(pyproject: &TomlValue, tool: LockTool) -> Vec<String> {
+    let mut roots: Vec<String> = pyproject
+        .get("project")
+        .and_then(|p| p.get("dependencies"))
+        .and_then(|d| d.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|d| d.as_str())
+        .map(requirement_name)
+        .collect();
+    if tool == LockTool::Poetry {
+        let deps = pyproject.get("tool").and_then(|t| t.get("poetry")).and_then(|p| p.get("dependencies")).and_then(|d| d.as_table());
+        let optional = |spec: &TomlValue| spec.get("optional").and_then(|o| o.as_bool()).unwrap_or(false);
+        roots.extend(deps.into_iter().flatten().filter(|(k, spec)| *k != "python" && !optional(spec)).map(|(k, _)| normalize(k)));
+    }
+    roots
+}
+
+/// Dependency names of a lock entry: a table in poetry.lock, a list of requirements in pdm.lock
+fn package_dependencies(package: &TomlValue) -> Vec<String> {
+    match package.get("dependencies") {
+        Some(TomlValue::Table(table)) => table.keys().map(|k| normalize(k)).collect(),
+        Some(TomlValue::Array(list)) => list.iter().filter_map(|d| d.as_str()).map(requirement_name).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Group membership recorded in the lock, if any (poetry < 1.5 `category`, poetry 2 / pdm
+/// `groups`); pdm records extras as groups too
+fn in_runtime_group(package: &TomlValue, extras: &[String]) -> Option<bool> {
+    if let Some(category) = package.get("category").and_then(|c| c.as_str()) {
+        return Some(category == "main");
+    }
+    let groups = package.get("groups")?.as_array()?;
+    Some(groups.iter().filter_map(|g| g.as_str()).any(|g| g == "main" || g == "default" || extras.contains(&normalize(g))))
+}
+
+/// `roots` and everything they depend on, as far as the lock knows the packages
+fn closure(roots: Vec<String>, by_name: &HashMap<String, &TomlValue>) -> HashSet<String> {
+    let mut seen = HashSet::new();
+    let mut stack = roots;
+    while let Some(name) = stack.pop() {
+        if let Some(package) = by_name.get(&name) {
+            if seen.insert(name) {
+                stack.extend(package_dependencies(package));
+            }
+        }
+    }
+    seen
+}
+
+/// Requirement line for a lock entry; None for local path dependencies
+fn requirement_line(package: &TomlValue, name: &str, version: &str) -> Option<String> {
+    let source = package.get("source");
+    let field = |value: Option<&TomlValue>, key: &str| value.and_then(|v| v.get(key)).and_then(|v| v.as_str()).map(str::to_string);
+    let spec = match field(source, "type").as_deref() {
+        Some("git") => format!(
+            "{} @ git+{}@{}",
+            name,
+            field(source, "url")?,
+            field(source, "resolved_reference").or_else(|| field(source, "reference"))?
+        ),
+        Some("url") => format!("{} @ {}", name, field(source, "url")?),
+        Some("directory") | Some("file") => return None,
+        _ => {
+            if let Some(git) = field(Some(package), "git") {
+                format!("{} @ git+{}@{}", name, git, field(Some(package), "revision")?)
+            } else if let Some(url) = field(Some(package), "url") {
+                format!("{} @ {}", name, url)
+            } else if package.get("path").is_some() {
+                return None;
+            } else {
+                format!("{}=={}", name, version)
+            }
+        }
+    };
+    let marker = field(Some(package), "markers").or_else(|| field(Some(package), "marker"));
+    Some(match marker {
+        Some(marker) if !marker.is_empty() => format!("{} ; {}", spec, marker),
+        _ => spec,
+    })
+}
+
+/// Pinned runtime requirements of a poetry or pdm project, with those of `extras`
+pub fn locked_requirements(repo_path: &Path, tool: LockTool, extras: &[String]) -> Result<Vec<String>> {
+    let pyproject = read_toml(&repo_path.join("pyproject.toml"))?;
+    let lock = read_toml(&repo_path.join(tool.lock_file()))?;
+    let packages: Vec<&TomlValue> = lock.get("package").and_then(|p| p.as_array()).into_iter().flatten().collect();
+    if packages.is_empty() {
+        return Err(PortableSourceError::repository(format!("No packages in {}", tool.lock_file())));
+    }
+    let by_name: HashMap<String, &TomlValue> = packages
+        .iter()
+        .filter_map(|p| Some((normalize(p.get("name")?.as_str()?), *p)))
+        .collect();
+
+    let extra_names: Vec<String> = extras.iter().map(|e| normalize(e)).collect();
+    let for_extras = closure(extra_roots(&pyproject, tool, extras)?, &by_name);
+
+    // Locks with group information are filtered by it, dropping poetry's `optional` packages
+    // no chosen extra needs; otherwise (poetry 1.5-1.8) the runtime set is what the declared
+    // dependencies and the chosen extras pull in
+    let runtime: HashSet<String> = if packages.iter().all(|p| in_runtime_group(p, &extra_names).is_some()) {
+        let optional = |p: &TomlValue| p.get("optional").and_then(|o| o.as_bool()).unwrap_or(false);
+        by_name
+            .iter()
+            .filter(|(n, p)| in_runtime_group(p, &extra_names) == Some(true) && (!optional(p) || for_extras.contains(*n)))
+            .map(|(n, _)| n.clone())
+            .collect()
+    } else {
+        let mut seen = closure(root_dependencies(&pyproject, tool), &by_name);
+        seen.extend(for_extras);
+        seen
+    };
+
+    let mut lines: Vec<String> = packages
+        .iter()
+        .filter_map(|p| {
+            let name = p.get("name")?.as_str()?;
+            if !runtime.contains(&normalize(name)) {
+                return None;
+            }
+            requirement_line(p, name, p.get("version")?.as_str()?)
+        })
+        .collect();
+    lines.sort();
+    lines.dedup();
+    Ok(lines)
+}
+
+/// Write the locked requirements of a repository, with its remembered extras, to
+/// [`EXPORT_FILE`] in its state folder; None if it has no poetry/pdm lock
+pub fn export_locked(repo_path: &Path) -> Result<Option<(LockTool, PathBuf)>> {
+    let Some(tool) = detect(repo_path) else { return Ok(None) };
+    let lines = locked_requirements(repo_path, tool, &requested_extras(repo_path))?;
+    let mut content = format!("# Exported from {} by portablesource\n", tool.lock_file());
+    for line in &lines {
+        content.push_str(line);
+        content.push('\n');
+    }
+    let path = crate::repo_state::write(repo_path, EXPORT_FILE, content)
+        .map_err(|e| PortableSourceError::repository(format!("Failed to write {}: {}", EXPORT_FILE, e)))?;
+    Ok(Some((tool, path)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn poetry_lock_without_groups_follows_runtime_graph() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = &dir.path().join("repos").join("app");
+        fs::create_dir_all(repo).unwrap();
+        fs::write(
+            repo.join("pyproject.toml"),
+            "[tool.poetry]\nname = \"app\"\npackage-mode = false\n\n[tool.poetry.dependencies]\npython = \"^3.10\"\nGradio = \"^4.0\"\nmy_lib = { git = \"https://github.com/a/my-lib\" }\n\n[tool.poetry.group.dev.dependencies]\npytest = \"*\"\n",
+        )
+        .unwrap();
+        fs::write(
+            repo.join("poetry.lock"),
+            r#"
+[[package]]
+name = "gradio"
+version = "4.44.1"
+[package.dependencies]
+numpy = ">=1.0"
+
+[[package]]
+name = "numpy"
+version = "1.26.4"
+
+[[package]]
+name = "colorama"
+version = "0.4.6"
+markers = "sys_platform == \"win32\""
+
+[[package]]
+name = "my-lib"
+version = "0.1.0"
+[package.source]
+type = "git"
+url = "https://github.com/a/my-lib"
+reference = "HEAD"
+resolved_reference = "abc123"
+
+[[package]]
+name = "pytest"
+version = "8.3.3"
+[package.dependencies]
+colorama = { version = "*", markers = "sys_platform == \"win32\"" }
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(detect(repo), Some(LockTool::Poetry));
+        assert!(!is_package(repo, LockTool::Poetry));
+        let (_, path) = export_locked(repo).unwrap().unwrap();
+        assert_eq!(path, dir.path().join("state").join("app").join(EXPORT_FILE));
+        assert!(!repo.join(EXPORT_FILE).exists());
+        let exported = fs::read_to_string(path).unwrap();
+        let lines: Vec<&str> = exported.lines().skip(1).collect();
+        assert_eq!(lines, ["gradio==4.44.1", "my-lib @ git+https://github.com/a/my-lib@abc123", "numpy==1.26.4"]);
+
+        // pdm records groups, so dev packages are dropped by group
+        fs::remove_file(repo.join("poetry.lock")).unwrap();
+        fs::write(repo.join("pyproject.toml"), "[project]\nname = \"app\"\ndependencies = [\"torch>=2\"]\n\n[tool.pdm]\ndistribution = true\n").unwrap();
+        fs::write(
+            repo.join("pdm.lock"),
+            "[[package]]\nname = \"torch\"\nversion = \"2.4.1\"\ngroups = [\"default\"]\n\n[[package]]\nname = \"ruff\"\nversion = \"0.6.0\"\ngroups = [\"lint\"]\n",
+        )
+        .unwrap();
+        assert_eq!(locked_requirements(repo, LockTool::Pdm, &[]).unwrap(), ["torch==2.4.1"]);
+    }
+
+    const POETRY_WITH_EXTRAS: &str = "[tool.poetry]\nname = \"app\"\n\n[tool.poetry.dependencies]\npython = \"^3.10\"\ntorch = \"^2.4\"\nxformers = { version = \"*\", optional = true }\n\n[tool.poetry.extras]\nfast = [\"xformers\"]\n";
+
+    #[test]
+    fn poetry_extras_are_only_exported_when_chosen() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = dir.path();
+        fs::write(repo.join("pyproject.toml"), POETRY_WITH_EXTRAS).unwrap();
+        fs::write(
+            repo.join("poetry.lock"),
+            "[[package]]\nname = \"torch\"\nversion = \"2.4.1\"\n\n[[package]]\nname = \"xformers\"\nversion = \"0.0.28\"\noptional = true\n[package.dependencies]\nnumpy = \"*\"\n\n[[package]]\nname = \"numpy\"\nversion = \"1.26.4\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(locked_requirements(repo, LockTool::Poetry, &[]).unwrap(), ["torch==2.4.1"]);
+        assert_eq!(
+            locked_requirements(repo, LockTool::Poetry, &["fast".into()]).unwrap(),
+            ["numpy==1.26.4", "torch==2.4.1", "xformers==0.0.28"]
+        );
+        let err = locked_requirements(repo, LockTool::Poetry, &["cuda".into()]).unwrap_err();
+        assert!(err.to_string().contains("Unknown extra 'cuda' (known: fast)"), "{}", err);
+    }
+
+    #[test]
+    fn optional_packages_of_a_grouped_poetry_lock_need_their_extra() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = dir.path();
+        fs::write(repo.join("pyproject.toml"), POETRY_WITH_EXTRAS).unwrap();
+        fs::write(
+            repo.join("poetry.lock"),
+            "[[package]]\nname = \"torch\"\nversion = \"2.4.1\"\ngroups = [\"main\"]\n\n[[package]]\nname = \"xformers\"\nversion = \"0.0.28\"\noptional = true\ngroups = [\"main\"]\n",
+        )
+        .unwrap();
+
+        assert_eq!(locked_requirements(repo, LockTool::Poetry, &[]).unwrap(), ["torch==2.4.1"]);
+        assert_eq!(locked_requirements(repo, LockTool::Poetry, &["FAST".into()]).unwrap(), ["torch==2.4.1", "xformers==0.0.28"]);
+    }
+
+    #[test]
+    fn pdm_extras_are_lock_groups() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = dir.path();
+        fs::write(
+            repo.join("pyproject.toml"),
+            "[project]\nname = \"app\"\ndependencies = [\"torch>=2\"]\n\n[project.optional-dependencies]\nui = [\"gradio>=4\"]\n\n[tool.pdm]\n",
+        )
+        .unwrap();
+        fs::write(
+            repo.join("pdm.lock"),
+            "[[package]]\nname = \"torch\"\nversion = \"2.4.1\"\ngroups = [\"default\"]\n\n[[package]]\nname = \"gradio\"\nversion = \"4.44.1\"\ngroups = [\"ui\"]\n",
+        )
+        .unwrap();
+
+        assert_eq!(locked_requirements(repo, LockTool::Pdm, &[]).unwrap(), ["torch==2.4.1"]);
+        assert_eq!(locked_requirements(repo, LockTool::Pdm, &["ui".into()]).unwrap(), ["gradio==4.44.1", "torch==2.4.1"]);
+    }
+}
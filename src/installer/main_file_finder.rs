@@ -1,9 +1,111 @@
 //! Main file finder for detecting the main executable file in repositories.
 
 use crate::installer::server_client::ServerClient;
+use crate::repo_metadata::RepoMetadata;
 use std::path::Path;
 use std::fs;
 use url::Url;
+use walkdir::WalkDir;
+
+/// Names that usually are the entry point, best first
+const COMMON_ENTRY_NAMES: &[&str] = &[
+    "run.py", "app.py", "webui.py", "main.py", "start.py",
+    "launch.py", "gui.py", "interface.py", "server.py", "demo.py",
+];
+/// Directories never scanned for entry points
+const SKIP_DIRS: &[&str] = &[
+    "venv", "env", "node_modules", "site-packages", "__pycache__", "build", "dist",
+    "test", "tests", "docs", "doc", "scripts", "tools", "benchmarks",
+];
+/// Scan depth below the repo root (app.py inside a subpackage is depth 2)
+const MAX_SCAN_DEPTH: usize = 3;
+/// Only the head of large files is inspected
+const MAX_SCAN_BYTES: usize = 256 * 1024;
+/// Top score needed to skip the confirmation prompt
+const CONFIDENT_SCORE: i32 = 60;
+/// Lead over the runner-up needed to skip the confirmation prompt
+const CONFIDENT_MARGIN: i32 = 20;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct EntryPointCandidate {
+    /// Path relative to the repo root, with `/` separators
+    pub path: String,
+    pub score: i32,
+    /// Human-readable signals that contributed to the score
+    pub reasons: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct EntryPointDetection {
+    /// Best candidate first
+    pub candidates: Vec<EntryPointCandidate>,
+    /// Top candidate is clearly ahead; no need to ask the user
+    pub confident: bool,
+}
+
+/// Score one python file from its location and contents (AST-light: plain text signals)
+fn score_candidate(rel_path: &str, depth: usize, content: &str) -> EntryPointCandidate {
+    let file_name = rel_path.rsplit('/').next().unwrap_or(rel_path).to_lowercase();
+    let mut score = 0;
+    let mut reasons = Vec::new();
+    let mut add = |points: i32, reason: &str| {
+        score += points;
+        reasons.push(reason.to_string());
+    };
+
+    if let Some(pos) = COMMON_ENTRY_NAMES.iter().position(|n| *n == file_name) {
+        add(30 - pos as i32 * 2, "common entry name");
+    } else if ["main", "run", "start", "app", "launch"].iter().any(|k| file_name.contains(k)) {
+        add(10, "entry-like name");
+    }
+    if content.contains("if __name__ == \"__main__\"") || content.contains("if __name__ == '__main__'") {
+        add(40, "__main__ block");
+    }
+    if content.contains("import gradio") || content.contains("from gradio") {
+        add(10, "imports gradio");
+        if content.contains("gr.Blocks(") || content.contains("gr.Interface(") || content.contains("gr.ChatInterface(") {
+            add(15, "gradio app");
+        }
+        if content.contains(".launch(") {
+            add(15, "launches gradio");
+        }
+    }
+    if content.contains("FastAPI(") {
+        add(15, "fastapi app");
+    }
+    if content.contains("uvicorn.run(") {
+        add(20, "runs uvicorn");
+    }
+    if content.contains("argparse.ArgumentParser(") {
+        add(5, "parses arguments");
+    }
+    // Relative imports break `python path/file.py`
+    if content.lines().any(|l| l.trim_start().starts_with("from .")) {
+        add(-15, "relative imports");
+    }
+    if depth > 1 {
+        score -= 10 * (depth as i32 - 1);
+    }
+
+    EntryPointCandidate { path: rel_path.to_string(), score, reasons }
+}
+
+/// A common entry name at the repo root. It wins over any file in a subpackage: a root
+/// `main.py` usually imports and starts the app that scores higher on its own
+fn is_root_entry(rel_path: &str) -> bool {
+    !rel_path.contains('/') && COMMON_ENTRY_NAMES.contains(&rel_path.to_lowercase().as_str())
+}
+
+fn is_skipped_file(name: &str) -> bool {
+    let lower = name.to_lowercase();
+    !lower.ends_with(".py")
+        || lower.starts_with("test_")
+        || lower.ends_with("_test.py")
+        || lower == "setup.py"
+        || lower == "conftest.py"
+        || lower.starts_with("__")
+        || lower.contains("install")
+}
 
 #[derive(Clone, Debug, Default)]
 pub struct MainFileFinder {
@@ -17,6 +119,13 @@ impl MainFileFinder {
 
     /// Find the main file for a repository using multiple strategies
     pub fn find_main_file(&self, repo_name: &str, repo_path: &Path, repo_url: Option<&str>) -> Option<String> {
+        // 0) Entry point chosen by the user during install
+        if let Ok(Some(meta)) = RepoMetadata::load(repo_path) {
+            if let Some(entry) = meta.entry_point.filter(|e| self.validate_main_file(repo_path, e)) {
+                return Some(entry);
+            }
+        }
+
         // 1) Try server first
         if let Ok(Some(info)) = self.server_client.get_repository_info(repo_name) {
             if let Some(main_file) = info.main_file {
@@ -26,59 +135,16 @@ impl MainFileFinder {
             }
         }
         
-        // 2) Try common names
-        let common_names = [
-            "run.py", "app.py", "webui.py", "main.py", "start.py", 
-            "launch.py", "gui.py", "interface.py", "server.py"
-        ];
-        
-        for file_name in common_names {
-            if self.validate_main_file(repo_path, file_name) {
-                return Some(file_name.to_string());
-            }
-        }
-        
-        // 3) Heuristic: any single non-test python file
-        let mut candidates: Vec<String> = Vec::new();
-        if let Ok(entries) = fs::read_dir(repo_path) {
-            for entry in entries.flatten() {
-                if let Ok(file_type) = entry.file_type() {
-                    if file_type.is_file() {
-                        let name = entry.file_name().to_string_lossy().to_string();
-                        
-                        if name.to_lowercase().ends_with(".py") 
-                            && !name.contains("test_") 
-                            && name != "setup.py" 
-                            && !name.contains("__") 
-                            && !name.contains("install") {
-                            candidates.push(name);
-                        }
-                    }
-                }
-            }
-        }
-        
-        // If only one candidate, use it
-        if candidates.len() == 1 {
-            return candidates.into_iter().next();
-        }
-        
-        // Look for priority keywords in candidates
-        for candidate in &candidates {
-            let lower_candidate = candidate.to_lowercase();
-            if lower_candidate.contains("main") 
-                || lower_candidate.contains("run") 
-                || lower_candidate.contains("start") 
-                || lower_candidate.contains("app") {
-                return Some(candidate.clone());
-            }
+        // 2) Rank python files by name, `__main__` blocks and app objects
+        if let Some(best) = self.detect_entry_point(repo_path).candidates.into_iter().next() {
+            return Some(best.path);
         }
         
         // 4) Last resort: use repo_url name
         if let Some(url) = repo_url {
             if let Ok(parsed_url) = Url::parse(url) {
                 if let Some(name) = parsed_url.path_segments()
-                    .and_then(|s| s.last())
+                    .and_then(|mut s| s.next_back())
                     .map(|s| s.trim_end_matches(".git")) {
                     let candidate = format!("{}.py", name);
                     if self.validate_main_file(repo_path, &candidate) {
@@ -91,8 +157,110 @@ impl MainFileFinder {
         None
     }
 
+    /// Rank python files in the repository as entry point candidates
+    pub fn detect_entry_point(&self, repo_path: &Path) -> EntryPointDetection {
+        let mut candidates: Vec<EntryPointCandidate> = Vec::new();
+        // Root files without any signal; used only if it is the single python file
+        let mut unscored_root: Vec<EntryPointCandidate> = Vec::new();
+        let walker = WalkDir::new(repo_path)
+            .max_depth(MAX_SCAN_DEPTH)
+            .into_iter()
+            .filter_entry(|e| {
+                if e.depth() == 0 || !e.file_type().is_dir() { return true; }
+                let name = e.file_name().to_string_lossy().to_lowercase();
+                !name.starts_with('.') && !SKIP_DIRS.contains(&name.as_str())
+            });
+
+        for entry in walker.flatten() {
+            if !entry.file_type().is_file() || is_skipped_file(&entry.file_name().to_string_lossy()) {
+                continue;
+            }
+            let Ok(rel) = entry.path().strip_prefix(repo_path) else { continue };
+            let rel = rel.to_string_lossy().replace('\\', "/");
+            let Ok(bytes) = fs::read(entry.path()) else { continue };
+            let content = String::from_utf8_lossy(&bytes[..bytes.len().min(MAX_SCAN_BYTES)]);
+            let candidate = score_candidate(&rel, entry.depth(), &content);
+            if candidate.score > 0 {
+                candidates.push(candidate);
+            } else if entry.depth() == 1 {
+                unscored_root.push(candidate);
+            }
+        }
+        if candidates.is_empty() && unscored_root.len() == 1 {
+            candidates = unscored_root;
+        }
+
+        // Root entry names first; stable order for equal scores: shallower path, then name
+        candidates.sort_by(|a, b| {
+            is_root_entry(&b.path).cmp(&is_root_entry(&a.path))
+                .then(b.score.cmp(&a.score))
+                .then(a.path.matches('/').count().cmp(&b.path.matches('/').count()))
+                .then(a.path.cmp(&b.path))
+        });
+        let confident = match candidates.as_slice() {
+            [] => false,
+            [only] => only.score >= CONFIDENT_SCORE / 2,
+            [first, second, ..] => first.score >= CONFIDENT_SCORE && first.score - second.score >= CONFIDENT_MARGIN,
+        };
+        EntryPointDetection { candidates, confident }
+    }
+
     /// Validate that a main file exists in the repository
     fn validate_main_file(&self, repo_path: &Path, main_file: &str) -> bool {
         repo_path.join(main_file).exists()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(root: &Path, rel: &str, content: &str) {
+        let path = root.join(rel);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn finds_gradio_app_in_subpackage() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "utils.py", "def helper():\n    pass\n");
+        write(dir.path(), "tests/test_app.py", "if __name__ == \"__main__\":\n    pass\n");
+        write(
+            dir.path(),
+            "webapp/app.py",
+            "import gradio as gr\nwith gr.Blocks() as demo:\n    pass\nif __name__ == \"__main__\":\n    demo.launch()\n",
+        );
+
+        let detection = MainFileFinder::default().detect_entry_point(dir.path());
+        assert_eq!(detection.candidates[0].path, "webapp/app.py");
+        assert!(detection.confident);
+    }
+
+    #[test]
+    fn root_entry_name_wins_over_a_subpackage_app() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "main.py", "from webapp.app import demo\nif __name__ == \"__main__\":\n    demo.launch()\n");
+        write(
+            dir.path(),
+            "webapp/app.py",
+            "import gradio as gr\nwith gr.Blocks() as demo:\n    pass\nif __name__ == \"__main__\":\n    demo.launch()\n",
+        );
+
+        let detection = MainFileFinder::default().detect_entry_point(dir.path());
+        assert_eq!(detection.candidates[0].path, "main.py");
+        assert_eq!(detection.candidates[1].path, "webapp/app.py");
+    }
+
+    #[test]
+    fn ambiguous_candidates_are_not_confident() {
+        let dir = tempfile::tempdir().unwrap();
+        let body = "if __name__ == '__main__':\n    pass\n";
+        write(dir.path(), "train.py", body);
+        write(dir.path(), "infer.py", body);
+
+        let detection = MainFileFinder::default().detect_entry_point(dir.path());
+        assert_eq!(detection.candidates.len(), 2);
+        assert!(!detection.confident);
+    }
+}
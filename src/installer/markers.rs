@@ -0,0 +1,363 @@
+//! PEP 508 environment markers of requirements lines
+//!
+//! `torch==2.1.0; sys_platform == "win32"` must only reach the install plan on Windows:
+//! dedicated steps (torch, onnxruntime, insightface, triton) are chosen by package name, so
+//! a line meant for another platform would otherwise pull its step in everywhere. Markers
+//! are evaluated against the interpreter of the target venv; a marker that cannot be
+//! evaluated keeps its line and is left to pip.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::Command;
+
+/// Asks the interpreter for every marker variable PEP 508 defines
+const PROBE_SCRIPT: &str = "\
+import json, os, platform, sys
+impl = sys.implementation
+v = impl.version
+iv = '{0.major}.{0.minor}.{0.micro}'.format(v)
+if v.releaselevel != 'final':
+    iv += v.releaselevel[0] + str(v.serial)
+print(json.dumps({
+    'os_name': os.name,
+    'sys_platform': sys.platform,
+    'platform_machine': platform.machine(),
+    'platform_python_implementation': platform.python_implementation(),
+    'platform_release': platform.release(),
+    'platform_system': platform.system(),
+    'platform_version': platform.version(),
+    'python_version': '.'.join(platform.python_version_tuple()[:2]),
+    'python_full_version': platform.python_version(),
+    'implementation_name': impl.name,
+    'implementation_version': iv,
+}))";
+
+/// Values of the marker variables for one environment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MarkerEnvironment {
+    values: BTreeMap<String, String>,
+}
+
+impl MarkerEnvironment {
+    /// Variables reported by `python`; None when it cannot be run
+    pub fn probe(python: &Path) -> Option<Self> {
+        let mut cmd = Command::new(python);
+        cmd.args(["-c", PROBE_SCRIPT]);
+
+        #[cfg(target_os = "windows")]
+        {
+            use std::os::windows::process::CommandExt;
+            cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
+        }
+
+        let output = cmd.output().ok().filter(|o| o.status.success())?;
+        let values = serde_json::from_slice(&output.stdout).ok()?;
+        Some(Self { values })
+    }
+
+    /// Platform variables of this build of portablesource, for when the venv cannot be asked;
+    /// Python version markers then stay unevaluated
+    pub fn host() -> Self {
+        let (os_name, sys_platform, system) = match std::env::consts::OS {
+            "windows" => ("nt", "win32", "Windows"),
+            "macos" => ("posix", "darwin", "Darwin"),
+            _ => ("posix", "linux", "Linux"),
+        };
+        let machine = match (std::env::consts::OS, std::env::consts::ARCH) {
+            ("windows", "x86_64") => "AMD64",
+            ("windows", "aarch64") => "ARM64",
+            ("macos", "aarch64") => "arm64",
+            (_, arch) => arch,
+        };
+        let values = [
+            ("os_name", os_name),
+            ("sys_platform", sys_platform),
+            ("platform_system", system),
+            ("platform_machine", machine),
+            ("implementation_name", "cpython"),
+            ("platform_python_implementation", "CPython"),
+        ];
+        Self { values: values.into_iter().map(|(k, v)| (k.to_string(), v.to_string())).collect() }
+    }
+
+    pub fn with(mut self, variable: &str, value: &str) -> Self {
+        self.values.insert(variable.to_string(), value.to_string());
+        self
+    }
+
+    /// Whether a requirements line applies here; lines without a marker and lines whose
+    /// marker cannot be evaluated do
+    pub fn applies(&self, line: &str) -> bool {
+        requirement_marker(line).is_none_or(|marker| self.evaluate(marker).unwrap_or(true))
+    }
+
+    /// Value of a marker expression; None when it does not parse or uses a variable this
+    /// environment does not know
+    pub fn evaluate(&self, marker: &str) -> Option<bool> {
+        let tokens = tokenize(marker)?;
+        let mut parser = Parser { tokens: &tokens, pos: 0, env: self };
+        let value = parser.or_expr()?;
+        (parser.pos == tokens.len()).then_some(value)
+    }
+
+    fn variable(&self, name: &str) -> Option<&str> {
+        // `extra` only has a value while pip resolves a package's own dependencies
+        if name == "extra" {
+            return Some("");
+        }
+        // Pre-PEP 508 spellings: os.name, sys.platform, platform.machine, ...
+        let name = name.replace('.', "_");
+        let name = if name == "python_implementation" { "platform_python_implementation" } else { &name };
+        self.values.get(name).map(String::as_str)
+    }
+}
+
+/// Marker part of a requirements line (after `;`); a URL requirement needs whitespace before it
+pub fn requirement_marker(line: &str) -> Option<&str> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') || line.starts_with('-') {
+        return None;
+    }
+    let line = match line.find(" #").or_else(|| line.find("\t#")) {
+        Some(idx) => &line[..idx],
+        None => line,
+    };
+    let idx = if line.contains("://") { line.find(" ;").or_else(|| line.find("\t;"))? + 1 } else { line.find(';')? };
+    Some(line[idx + 1..].trim()).filter(|m| !m.is_empty())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Str(String),
+    Var(String),
+    Op(&'static str),
+    And,
+    Or,
+    Open,
+    Close,
+}
+
+fn tokenize(marker: &str) -> Option<Vec<Token>> {
+    const OPS: [&str; 8] = ["===", "==", "!=", "<=", ">=", "~=", "<", ">"];
+    let mut tokens = Vec::new();
+    let mut rest = marker.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if c == '(' || c == ')' {
+            tokens.push(if c == '(' { Token::Open } else { Token::Close });
+            rest = &rest[1..];
+        } else if c == '"' || c == '\'' {
+            let end = rest[1..].find(c)? + 1;
+            tokens.push(Token::Str(rest[1..end].to_string()));
+            rest = &rest[end + 1..];
+        } else if let Some(op) = OPS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.')).unwrap_or(rest.len());
+            let word = &rest[..end];
+            rest = &rest[end..];
+            tokens.push(match word {
+                "and" => Token::And,
+                "or" => Token::Or,
+                "in" => Token::Op("in"),
+                "not" => {
+                    let after = rest.trim_start();
+                    let is_in = after.strip_prefix("in").is_some_and(|r| !r.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_'));
+                    if !is_in {
+                        return None;
+                    }
+                    rest = &after[2..];
+                    Token::Op("not in")
+                }
+                _ => Token::Var(word.to_string()),
+            });
+        } else {
+            return None;
+        }
+        rest = rest.trim_start();
+    }
+    Some(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    env: &'a MarkerEnvironment,
+}
+
+impl<'a> Parser<'a> {
+    fn next(&mut self) -> Option<&'a Token> {
+        let token = self.tokens.get(self.pos);
+        self.pos += 1;
+        token
+    }
+
+    fn peek_is(&self, token: &Token) -> bool {
+        self.tokens.get(self.pos) == Some(token)
+    }
+
+    // Every operand is parsed even when the result is already known, so that a syntax error
+    // anywhere makes the whole marker unevaluated
+    fn or_expr(&mut self) -> Option<bool> {
+        let mut value = self.and_expr()?;
+        while self.peek_is(&Token::Or) {
+            self.pos += 1;
+            value |= self.and_expr()?;
+        }
+        Some(value)
+    }
+
+    fn and_expr(&mut self) -> Option<bool> {
+        let mut value = self.atom()?;
+        while self.peek_is(&Token::And) {
+            self.pos += 1;
+            value &= self.atom()?;
+        }
+        Some(value)
+    }
+
+    fn atom(&mut self) -> Option<bool> {
+        if self.peek_is(&Token::Open) {
+            self.pos += 1;
+            let value = self.or_expr()?;
+            return (self.next()? == &Token::Close).then_some(value);
+        }
+        let left = self.operand()?;
+        let Token::Op(op) = self.next()?.clone() else { return None };
+        let right = self.operand()?;
+        compare(&left, op, &right)
+    }
+
+    fn operand(&mut self) -> Option<String> {
+        match self.next()? {
+            Token::Str(s) => Some(s.clone()),
+            Token::Var(name) => self.env.variable(name).map(str::to_string),
+            _ => None,
+        }
+    }
+}
+
+/// Release segments of a version (`3.10.2rc1` -> [3, 10, 2]); None when it does not start
+/// with a number
+fn release(version: &str) -> Option<Vec<u64>> {
+    let version = version.trim();
+    let version = version.strip_suffix(".*").unwrap_or(version);
+    let segments: Vec<u64> = version
+        .split('.')
+        .map(|part| part.chars().take_while(|c| c.is_ascii_digit()).collect::<String>())
+        .map_while(|digits| digits.parse().ok())
+        .collect();
+    (!segments.is_empty()).then_some(segments)
+}
+
+fn compare_releases(a: &[u64], b: &[u64]) -> std::cmp::Ordering {
+    let len = a.len().max(b.len());
+    let pad = |v: &[u64]| v.iter().copied().chain(std::iter::repeat(0)).take(len).collect::<Vec<_>>();
+    pad(a).cmp(&pad(b))
+}
+
+/// PEP 508 comparison: version semantics when both sides are versions, strings otherwise
+fn compare(left: &str, op: &str, right: &str) -> Option<bool> {
+    use std::cmp::Ordering::*;
+    match op {
+        "in" => return Some(right.contains(left)),
+        "not in" => return Some(!right.contains(left)),
+        "===" => return Some(left == right),
+        _ => {}
+    }
+    let (Some(a), Some(b)) = (release(left), release(right)) else {
+        return match op {
+            "==" => Some(left == right),
+            "!=" => Some(left != right),
+            _ => None,
+        };
+    };
+    // `== "3.1.*"` matches every 3.1 release
+    if let Some(prefix) = right.trim().strip_suffix(".*") {
+        let prefix = release(prefix)?;
+        let matches = a.len() >= prefix.len() && a[..prefix.len()] == prefix[..];
+        return match op {
+            "==" => Some(matches),
+            "!=" => Some(!matches),
+            _ => None,
+        };
+    }
+    let ordering = compare_releases(&a, &b);
+    Some(match op {
+        "==" => ordering == Equal,
+        "!=" => ordering != Equal,
+        "<" => ordering == Less,
+        "<=" => ordering != Greater,
+        ">" => ordering == Greater,
+        ">=" => ordering != Less,
+        // ~=3.8 means >=3.8, ==3.*
+        "~=" => {
+            if b.len() < 2 {
+                return None;
+            }
+            ordering != Less && a.len() >= b.len() - 1 && a[..b.len() - 1] == b[..b.len() - 1]
+        }
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn windows() -> MarkerEnvironment {
+        MarkerEnvironment::host()
+            .with("sys_platform", "win32")
+            .with("platform_system", "Windows")
+            .with("os_name", "nt")
+            .with("platform_machine", "AMD64")
+            .with("python_version", "3.11")
+            .with("python_full_version", "3.11.9")
+    }
+
+    fn linux() -> MarkerEnvironment {
+        windows().with("sys_platform", "linux").with("platform_system", "Linux").with("os_name", "posix")
+    }
+
+    #[test]
+    fn platform_markers_follow_the_target_not_the_host() {
+        let line = "torch==2.1.0; sys_platform == \"win32\"";
+        assert!(windows().applies(line));
+        assert!(!linux().applies(line));
+    }
+
+    #[test]
+    fn requirements_without_marker_always_apply() {
+        assert!(windows().applies("numpy<2"));
+    }
+
+    #[test]
+    fn python_versions_compare_as_versions() {
+        assert!(linux().applies("triton ; platform_system != 'Windows' and python_version >= \"3.8\""));
+        assert!(!linux().applies("triton ; platform_system != 'Windows' and python_version < \"3.10\""));
+        assert!(windows().applies("dataclasses; python_full_version == '3.11.*'"));
+    }
+
+    #[test]
+    fn or_groups_and_membership_tests_are_evaluated() {
+        assert!(!linux().applies("pywin32 ; os.name == 'nt' or (python_version ~= '3.11' and 'AMD' not in platform_machine)"));
+        assert_eq!(linux().evaluate("python_version <= '3.11' or sys_platform in 'win32 cygwin'"), Some(true));
+    }
+
+    #[test]
+    fn extras_are_never_requested() {
+        assert!(!windows().applies("tomli ; python_version > '3.10' and extra == 'dev'"));
+    }
+
+    #[test]
+    fn url_requirements_keep_their_marker_and_fragment() {
+        assert!(!linux().applies("https://x/pkg-1.0-cp311-cp311-win_amd64.whl ; sys_platform == 'win32'  # windows only"));
+        assert_eq!(requirement_marker("pkg @ https://x/y.whl#sha256=a;b"), None);
+    }
+
+    #[test]
+    fn unparseable_markers_and_unknown_variables_are_left_to_pip() {
+        assert!(linux().applies("pkg ; sys_platform === "));
+        assert!(MarkerEnvironment::host().applies("pkg ; python_version < '3.9'"));
+    }
+}
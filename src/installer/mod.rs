@@ -1,15 +1,21 @@
 pub mod command_runer;
 pub mod git_manager;
+pub mod lockfile;
 pub mod pip_manager;
+pub mod plan_review;
 pub mod dependency_installer;
 pub mod script_generator;
 pub mod server_client;
 pub mod main_file_finder;
+pub mod markers;
+pub mod step_failure;
+pub mod wheel_compat;
 
 pub use command_runer::CommandRunner;
 pub use git_manager::{GitManager, RepositoryInfo};
-pub use pip_manager::PipManager;
+pub use pip_manager::{PipManager, RequirementsAnalyzer, PackageInfo, PackageType, InstallationPlan, NumpyDecision, RequirementsIndexes, IndexConflict, ENGINE_MARKER_FILE};
 pub use dependency_installer::DependencyInstaller;
-pub use script_generator::{ScriptGenerator, RepositoryInfo as ScriptRepositoryInfo};
+pub use script_generator::{ScriptGenerator, ScriptContext, LaunchTarget, RepositoryInfo as ScriptRepositoryInfo, render_script};
 pub use server_client::{ServerClient, RepositoryInfo as ServerRepositoryInfo};
-pub use main_file_finder::MainFileFinder;
\ No newline at end of file
+pub use main_file_finder::MainFileFinder;
+pub use step_failure::{OnStepError, SkippedStep};
\ No newline at end of file
@@ -1,18 +1,28 @@
 //! Pip manager for handling Python package installations with pip/uv support.
 
 use crate::installer::command_runer::CommandRunner;
-use crate::config::ConfigManager;
+use crate::installer::markers::{requirement_marker, MarkerEnvironment};
+use crate::installer::step_failure::{self, OnStepError, SkippedStep, StepDecision};
+use crate::installer::wheel_compat::{self, TargetPython, WheelResolution};
+use crate::config::{ConfigManager, InstallEngine};
+use crate::gpu::ComputeCapability;
+use crate::output;
+use crate::prompt;
+use crate::repo_state;
 use crate::PortableSourceError;
 use crate::Result;
-use log::{info, debug};
+use tracing::{info, debug, warn};
+use std::cell::RefCell;
+use std::collections::HashSet;
 use std::path::{Path, PathBuf};
 use std::fs;
 use std::io::Write;
 use serde_json::Value as JsonValue;
 use toml::Value as TomlValue;
 
+/// Packages that get a dedicated install step instead of the plain requirements pass
 #[derive(Clone, Debug, PartialEq, Eq)]
-enum PackageType {
+pub enum PackageType {
     Regular,
     Torch,
     Onnxruntime,
@@ -20,84 +30,533 @@ enum PackageType {
     Triton,
 }
 
+/// Exact project names (PEP 503 normalized) of each dedicated install step
+const TORCH_PACKAGES: &[&str] = &["torch", "torchvision", "torchaudio", "torchtext", "torchdata"];
+const ONNXRUNTIME_PACKAGES: &[&str] = &[
+    "onnxruntime", "onnxruntime-gpu", "onnxruntime-directml", "onnxruntime-openvino", "onnxruntime-silicon", "onnxruntime-qnn",
+];
+const INSIGHTFACE_PACKAGES: &[&str] = &["insightface"];
+const TRITON_PACKAGES: &[&str] = &["triton", "triton-windows"];
+
+impl PackageType {
+    /// Install step of a normalized project name; lookalikes such as `torchmetrics`,
+    /// `pytorch-lightning` or `onnxruntime-extensions` are regular packages
+    pub fn of(name: &str) -> Self {
+        if TORCH_PACKAGES.contains(&name) {
+            PackageType::Torch
+        } else if ONNXRUNTIME_PACKAGES.contains(&name) {
+            PackageType::Onnxruntime
+        } else if INSIGHTFACE_PACKAGES.contains(&name) {
+            PackageType::Insightface
+        } else if TRITON_PACKAGES.contains(&name) {
+            PackageType::Triton
+        } else {
+            PackageType::Regular
+        }
+    }
+}
+
+/// PEP 503 normalized name: lowercase with runs of `-`, `_` and `.` as one `-`
+pub fn normalize_package_name(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    for c in name.chars() {
+        if matches!(c, '-' | '_' | '.') {
+            if !out.ends_with('-') {
+                out.push('-');
+            }
+        } else {
+            out.push(c.to_ascii_lowercase());
+        }
+    }
+    out
+}
+
+/// A requirements line without its comment; `#` only starts one at the line start or after
+/// whitespace, so `#egg=` fragments of URLs stay
+fn strip_requirement_comment(line: &str) -> &str {
+    let line = line.trim();
+    if line.starts_with('#') {
+        return "";
+    }
+    match line.find(" #").or_else(|| line.find("\t#")) {
+        Some(idx) => line[..idx].trim(),
+        None => line,
+    }
+}
+
+/// Normalized project name of a requirements line: PEP 508 `name[extras] spec ; markers`,
+/// `name @ url`, or a bare URL naming a wheel or `#egg=`. None for comments, pip options
+/// and lines without a recognizable name.
+pub fn requirement_name(line: &str) -> Option<String> {
+    let line = strip_requirement_comment(line);
+    if line.is_empty() || line.starts_with('-') {
+        return None;
+    }
+    let spec = line.split(';').next().unwrap_or("").trim();
+    let end = spec.find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))).unwrap_or(spec.len());
+    let (name, rest) = spec.split_at(end);
+    let rest = rest.trim_start();
+    let named = name.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && (rest.is_empty() || rest.starts_with(|c: char| "[(=<>!~@".contains(c)));
+    if named {
+        return Some(normalize_package_name(name));
+    }
+
+    // Bare URL or path
+    if let Some((_, egg)) = spec.split_once("#egg=") {
+        let egg = egg.split('&').next().unwrap_or("");
+        return (!egg.is_empty()).then(|| normalize_package_name(egg));
+    }
+    let file = spec.rsplit(['/', '\\']).next()?;
+    let wheel_name = file.strip_suffix(".whl")?.split('-').next()?;
+    (!wheel_name.is_empty()).then(|| normalize_package_name(wheel_name))
+}
+
+/// Requirements lines for packages that get a dedicated install step (torch, onnxruntime,
+/// insightface, triton) and are left out of the plain requirements pass
+pub fn is_installed_separately(line: &str) -> bool {
+    requirement_name(line).is_some_and(|name| PackageType::of(&name) != PackageType::Regular)
+}
+
+/// One parsed requirement line
 #[derive(Clone, Debug)]
-struct PackageInfo {
-    name: String,
-    version: Option<String>,
-    package_type: PackageType,
+pub struct PackageInfo {
+    /// Lowercased name without extras
+    pub name: String,
+    pub version: Option<String>,
+    pub package_type: PackageType,
 }
 
-impl ToString for PackageInfo {
-    fn to_string(&self) -> String {
+impl std::fmt::Display for PackageInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if let Some(v) = &self.version {
-            format!("{}=={}", self.name, v)
+            write!(f, "{}=={}", self.name, v)
         } else {
-            self.name.clone()
+            write!(f, "{}", self.name)
+        }
+    }
+}
+
+/// Class of install operation; uv failures are remembered per class in `auto` mode
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum InstallStep {
+    Requirements,
+    Regular,
+    Torch,
+    Onnx,
+    Triton,
+    Insightface,
+    RepoPackage,
+}
+
+impl std::fmt::Display for InstallStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            InstallStep::Requirements => "requirements",
+            InstallStep::Regular => "regular",
+            InstallStep::Torch => "torch",
+            InstallStep::Onnx => "onnx",
+            InstallStep::Triton => "triton",
+            InstallStep::Insightface => "insightface",
+            InstallStep::RepoPackage => "repo-package",
+        };
+        f.write_str(name)
+    }
+}
+
+impl InstallStep {
+    /// Steps a repository usually runs without, only slower or with fewer features
+    fn is_optional(self) -> bool {
+        matches!(self, InstallStep::Triton | InstallStep::Insightface)
+    }
+
+    /// What does not work when the step is skipped
+    fn consequence(self) -> &'static str {
+        match self {
+            InstallStep::Requirements => "packages from the requirements file are missing; the repository will likely not start",
+            InstallStep::Regular => "packages of this step are missing; the repository may not start",
+            InstallStep::Torch => "PyTorch is missing or without CUDA; models run on the CPU or not at all",
+            InstallStep::Onnx => "ONNX Runtime is missing; models loaded through it will not work",
+            InstallStep::Triton => "Triton kernels and torch.compile are unavailable; some features are slower or disabled",
+            InstallStep::Insightface => "face detection and face swapping features will not work",
+            InstallStep::RepoPackage => "the repository itself is not installed as a package; imports of it may fail",
         }
     }
 }
 
+/// Flags understood by `uv pip install` but rejected by pip (flag, takes value)
+const UV_ONLY_FLAGS: &[(&str, bool)] = &[
+    ("--index-strategy", true),
+    ("--resolution", true),
+];
+
+/// Per-repo engine override, in the repository's state folder (see [`crate::repo_state`])
+pub const ENGINE_MARKER_FILE: &str = ".portablesource_engine";
+/// Which engine performed each install step, for debugging
+pub const ENGINE_LOG_FILE: &str = ".portablesource_install.log";
+
+/// Constraints applied to every install step of a repo once the plan needs numpy 1.x
+pub const CONSTRAINTS_FILE: &str = ".portablesource_constraints.txt";
+/// What packages built against the numpy 1.x ABI get pinned to
+pub const NUMPY1_SPEC: &str = "numpy<2";
+/// Version unversioned tensorflow requirements are installed as
+const TENSORFLOW_DEFAULT_VERSION: &str = "2.15.0";
+
+/// Prebuilt insightface for Windows (no compiler needed); checked against the venv before use
+#[cfg(windows)]
+const INSIGHTFACE_WINDOWS_WHEEL: &str = "https://huggingface.co/hanamizuki-ai/pypi-wheels/resolve/main/insightface/insightface-0.7.3-cp311-cp311-win_amd64.whl";
+
+/// Packages that break on numpy 2.x below the given version (None: every version)
+const NUMPY1_ONLY: &[(&str, Option<&str>)] = &[
+    ("insightface", None),
+    ("onnxruntime", Some("1.19")),
+    ("onnxruntime-gpu", Some("1.19")),
+    ("onnxruntime-directml", Some("1.19")),
+    ("torch", Some("2.3")),
+    ("torchvision", Some("0.18")),
+    ("torchaudio", Some("2.3")),
+    ("tensorflow", Some("2.18")),
+    ("tensorflow-cpu", Some("2.18")),
+];
+
+/// How the plan handles numpy, which pip would otherwise resolve to 2.x
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum NumpyDecision {
+    /// Every package supports numpy 2
+    #[default]
+    Unpinned,
+    /// The requirements pin numpy themselves; left as is
+    Explicit(String),
+    /// numpy<2 because of these requirements
+    Pinned(Vec<String>),
+}
+
+impl NumpyDecision {
+    pub fn for_packages(packages: &[PackageInfo]) -> Self {
+        if let Some(numpy) = packages.iter().find(|p| p.name == "numpy" && p.version.is_some()) {
+            return NumpyDecision::Explicit(numpy.to_string());
+        }
+        let reasons: Vec<String> = packages
+            .iter()
+            .filter_map(|p| {
+                let (_, below) = NUMPY1_ONLY.iter().find(|(name, _)| *name == p.name)?;
+                let version = p.version.as_deref()
+                    .or_else(|| p.name.starts_with("tensorflow").then_some(TENSORFLOW_DEFAULT_VERSION));
+                match (below, version) {
+                    (None, _) => Some(p.name.clone()),
+                    (Some(below), Some(version)) if version_lt(version, below) => Some(format!("{}=={}", p.name, version)),
+                    _ => None,
+                }
+            })
+            .collect();
+        if reasons.is_empty() { NumpyDecision::Unpinned } else { NumpyDecision::Pinned(reasons) }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            NumpyDecision::Unpinned => "not pinned, all packages support numpy 2".to_string(),
+            NumpyDecision::Explicit(spec) => format!("{} requested by the repository, left as is", spec),
+            NumpyDecision::Pinned(reasons) => format!("pinned to {} (needed by {})", NUMPY1_SPEC, reasons.join(", ")),
+        }
+    }
+}
+
+/// Compare dotted numeric versions; suffixes like `+cu118` or `rc1` are ignored
+fn version_lt(a: &str, b: &str) -> bool {
+    let parse = |v: &str| -> Vec<u64> {
+        v.split('.')
+            .map(|part| part.chars().take_while(|c| c.is_ascii_digit()).collect::<String>())
+            .map_while(|digits| digits.parse().ok())
+            .collect()
+    };
+    parse(a) < parse(b)
+}
+
+/// Requirements grouped by install step
 #[derive(Clone, Debug, Default)]
-struct InstallationPlan {
-    torch_packages: Vec<PackageInfo>,
-    onnx_packages: Vec<PackageInfo>,
-    insightface_packages: Vec<PackageInfo>,
-    triton_packages: Vec<PackageInfo>,
-    regular_packages: Vec<PackageInfo>,
-    torch_index_url: Option<String>,
-    onnx_package_name: Option<String>,
+pub struct InstallationPlan {
+    pub torch_packages: Vec<PackageInfo>,
+    pub onnx_packages: Vec<PackageInfo>,
+    pub insightface_packages: Vec<PackageInfo>,
+    pub triton_packages: Vec<PackageInfo>,
+    pub regular_packages: Vec<PackageInfo>,
+    pub torch_index_url: Option<String>,
+    pub onnx_package_name: Option<String>,
+    pub numpy: NumpyDecision,
+    /// Set when `torch_index_url` is a nightly channel
+    pub nightly: Option<NightlyNote>,
+    /// Index options of the requirements file that the install steps use
+    pub indexes: RequirementsIndexes,
+    /// Index options of the requirements file that were dropped, and why
+    pub index_conflicts: Vec<IndexConflict>,
+}
+
+/// Index and find-links options of a requirements file, which `parse_requirement_line` skips
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RequirementsIndexes {
+    /// `--index-url` / `-i`: replaces PyPI
+    pub index_url: Option<String>,
+    /// `--extra-index-url`
+    pub extra_index_urls: Vec<String>,
+    /// `--find-links` / `-f`: wheel folders or pages listing wheels
+    pub find_links: Vec<String>,
+}
+
+/// Option name and value of an index line: `--opt URL`, `--opt=URL` or `-fURL`
+fn index_option(line: &str) -> Option<(&'static str, String)> {
+    let line = strip_requirement_comment(line);
+    const OPTIONS: &[(&str, &str)] = &[
+        ("--index-url", "--index-url"),
+        ("-i", "--index-url"),
+        ("--extra-index-url", "--extra-index-url"),
+        ("--find-links", "--find-links"),
+        ("-f", "--find-links"),
+    ];
+    OPTIONS.iter().find_map(|(flag, option)| {
+        let value = line.strip_prefix(flag)?;
+        let value = value.strip_prefix('=').unwrap_or(value).trim();
+        // `-index...` after `-i` is another option, not a value
+        (!value.is_empty() && !value.starts_with('-')).then(|| (*option, value.to_string()))
+    })
+}
+
+impl RequirementsIndexes {
+    pub fn from_lines<'l>(lines: impl IntoIterator<Item = &'l str>) -> Self {
+        let mut indexes = Self::default();
+        for (option, url) in lines.into_iter().filter_map(index_option) {
+            let list = match option {
+                "--index-url" if indexes.index_url.is_none() => {
+                    indexes.index_url = Some(url);
+                    continue;
+                }
+                // pip honours one index url; later ones are kept as extra indexes
+                "--index-url" | "--extra-index-url" => &mut indexes.extra_index_urls,
+                _ => &mut indexes.find_links,
+            };
+            if !list.contains(&url) {
+                list.push(url);
+            }
+        }
+        indexes
+    }
+
+    /// Whether a requirements line is an index option captured here
+    pub fn is_index_line(line: &str) -> bool {
+        index_option(line).is_some()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index_url.is_none() && self.extra_index_urls.is_empty() && self.find_links.is_empty()
+    }
+
+    /// Install arguments for the options
+    pub fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(url) = &self.index_url {
+            args.extend(["--index-url".to_string(), url.clone()]);
+        }
+        for url in &self.extra_index_urls {
+            args.extend(["--extra-index-url".to_string(), url.clone()]);
+        }
+        for url in &self.find_links {
+            args.extend(["--find-links".to_string(), url.clone()]);
+        }
+        args
+    }
+}
+
+/// Index option of a requirements file that the plan does not use
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndexConflict {
+    pub option: &'static str,
+    pub url: String,
+    pub reason: String,
+}
+
+impl std::fmt::Display for IndexConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} ignored: {}", self.option, self.url, self.reason)
+    }
+}
+
+/// PyTorch wheel channels (`whl/cu121`, `whl/torch_stable.html`, ...)
+fn is_pytorch_index(url: &str) -> bool {
+    url.contains("download.pytorch.org/whl")
+}
+
+impl InstallationPlan {
+    /// Merge the index options of a requirements file with portablesource's own choices.
+    ///
+    /// Precedence: torch packages come only from `torch_index_url`, picked for the GPU and
+    /// CUDA version, so a PyTorch channel in the file that differs from it is a conflict and
+    /// is dropped from every step (it would otherwise pull a mismatched torch in as a
+    /// dependency). Every other option applies to the requirements pass and the regular,
+    /// ONNX and Triton steps, after portablesource's own arguments.
+    pub fn merge_indexes(&mut self, indexes: RequirementsIndexes) {
+        let ours = self.torch_index_url.as_deref().map(|u| u.trim_end_matches('/').to_string());
+        let mut keep = |option: &'static str, url: &str| {
+            if !is_pytorch_index(url) || ours.as_deref() == Some(url.trim_end_matches('/')) {
+                return true;
+            }
+            self.index_conflicts.push(IndexConflict {
+                option,
+                url: url.to_string(),
+                reason: format!("torch is installed from {} for this GPU", ours.as_deref().unwrap_or("the default index")),
+            });
+            false
+        };
+        let index_url = indexes.index_url.filter(|url| keep("--index-url", url));
+        let extra_index_urls = indexes.extra_index_urls.into_iter().filter(|url| keep("--extra-index-url", url)).collect();
+        let find_links = indexes.find_links.into_iter().filter(|url| keep("--find-links", url)).collect();
+        self.indexes = RequirementsIndexes { index_url, extra_index_urls, find_links };
+    }
+
+    /// Tell the user which index options of the requirements file are used or dropped
+    pub fn report_indexes(&self) {
+        if !self.indexes.is_empty() {
+            info!(args = ?self.indexes.args(), "requirements index options");
+            output::info(&format!("Using index options from the requirements: {}", self.indexes.args().join(" ")));
+        }
+        for conflict in &self.index_conflicts {
+            output::warn(&conflict.to_string());
+        }
+    }
+}
+
+const NIGHTLY_CU128_INDEX: &str = "https://download.pytorch.org/whl/nightly/cu128";
+
+/// Why a nightly torch channel was chosen
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NightlyNote {
+    pub index_url: String,
+    /// None when the capability is unknown and the GPU name decided
+    pub compute_capability: Option<ComputeCapability>,
+    pub reason: String,
+}
+
+impl NightlyNote {
+    /// Tell the user, and the log as structured fields, why nightly builds are installed
+    pub fn announce(&self) {
+        output::info(&format!("Using nightly torch builds from {}: {}", self.index_url, self.reason));
+        info!(
+            index_url = %self.index_url,
+            compute_capability = ?self.compute_capability.map(|cc| cc.to_string()),
+            reason = %self.reason,
+            "nightly torch channel selected"
+        );
+    }
+}
+
+/// Nightly channel for GPUs whose compute capability stable torch wheels do not support
+pub fn nightly_channel(config_manager: &ConfigManager) -> Option<NightlyNote> {
+    if !config_manager.has_cuda() {
+        return None;
+    }
+    nightly_channel_for(config_manager, config_manager.compute_capability(), &config_manager.get_gpu_name())
+}
+
+/// Nightly channel for the CUDA GPU `gpu_name` with `capability` (and where it came from)
+pub fn nightly_channel_for(config_manager: &ConfigManager, capability: Option<(ComputeCapability, &str)>, gpu_name: &str) -> Option<NightlyNote> {
+    let min = ComputeCapability::NIGHTLY_TORCH;
+    match capability {
+        Some((cc, source)) if cc >= min => Some(NightlyNote {
+            index_url: NIGHTLY_CU128_INDEX.into(),
+            compute_capability: Some(cc),
+            reason: format!("compute capability {} ({}) is {} or newer, which stable wheels have no kernels for", cc, source, min),
+        }),
+        Some(_) => None,
+        // Unknown capability (no nvidia-smi): fall back on the GPU name
+        None if config_manager.detect_gpu_generation(gpu_name) == crate::config::GpuGeneration::Blackwell => {
+            warn!("Compute capability unknown; set compute_capability in the config to override the name-based guess");
+            Some(NightlyNote {
+                index_url: NIGHTLY_CU128_INDEX.into(),
+                compute_capability: None,
+                reason: format!("compute capability unknown and '{}' looks like a Blackwell GPU", gpu_name),
+            })
+        }
+        None => None,
+    }
+}
+
+/// Packages the torch reinstall replaces together
+const TORCH_BUILD_PACKAGES: [&str; 3] = ["torch", "torchvision", "torchaudio"];
+
+/// Whether the local build tag of an installed torch package (`2.7.0+cu128`) is the one
+/// `index_url` serves (`.../whl/cu128`)
+pub fn torch_build_matches(version: &str, index_url: &str) -> bool {
+    version
+        .split_once('+')
+        .is_some_and(|(_, tag)| index_url.trim_end_matches('/').rsplit('/').next() == Some(tag))
+}
+
+/// Torch index for the detected GPU and CUDA version
+pub fn torch_index_url(config_manager: &ConfigManager) -> String {
+    if config_manager.cpu_only() {
+        return "https://download.pytorch.org/whl/cpu".into();
+    }
+    if let Some(note) = nightly_channel(config_manager) {
+        return note.index_url;
+    }
+
+    #[cfg(unix)]
+    {
+        if let Some(cv) = crate::utils::detect_cuda_version_from_system() {
+            return match cv {
+                crate::config::CudaVersionLinux::Cuda128 => "https://download.pytorch.org/whl/cu128".into(),
+                crate::config::CudaVersionLinux::Cuda126 => "https://download.pytorch.org/whl/cu126".into(),
+                crate::config::CudaVersionLinux::Cuda124 => "https://download.pytorch.org/whl/cu124".into(),
+                crate::config::CudaVersionLinux::Cuda121 => "https://download.pytorch.org/whl/cu121".into(),
+                crate::config::CudaVersionLinux::Cuda118 => "https://download.pytorch.org/whl/cu118".into(),
+            };
+        }
+    }
+
+    #[cfg(windows)]
+    {
+        if config_manager.has_cuda() {
+            if let Some(cuda_version) = config_manager.get_cuda_version() {
+                return match cuda_version {
+                    crate::config::CudaVersion::Cuda128 => "https://download.pytorch.org/whl/cu128".into(),
+                    crate::config::CudaVersion::Cuda124 => "https://download.pytorch.org/whl/cu124".into(),
+                    crate::config::CudaVersion::Cuda118 => "https://download.pytorch.org/whl/cu118".into(),
+                };
+            }
+        }
+    }
+
+    "https://download.pytorch.org/whl/cpu".into()
 }
 
-struct RequirementsAnalyzer<'a> {
+pub struct RequirementsAnalyzer<'a> {
     config_manager: &'a ConfigManager,
 }
 
 impl<'a> RequirementsAnalyzer<'a> {
-    fn new(config_manager: &'a ConfigManager) -> Self {
+    pub fn new(config_manager: &'a ConfigManager) -> Self {
         Self { config_manager }
     }
 
-    fn parse_requirement_line(&self, line_in: &str) -> Option<PackageInfo> {
-        let line = line_in.split('#').next().unwrap_or("").trim().to_string();
-        if line.is_empty() || line.starts_with('-') || line.contains("--index-url") || line.contains("--extra-index-url") {
-            return None;
-        }
-        
-        // Basic parse: name[extras]==version
-        let (name_part, version) = if let Some(idx) = line.find(|c: char| "=><!~".contains(c)) {
-            let (n, v) = line.split_at(idx);
-            (n.trim().to_string(), Some(v.trim_matches(|c| c == '=' || c == '>' || c == '<' || c == '!' || c == '~').to_string()))
-        } else {
-            (line.clone(), None)
-        };
-        
-        let name = if let Some(start) = name_part.find('[') {
-            name_part[..start].to_string()
-        } else {
-            name_part
-        };
-        
-        let lname = name.to_lowercase();
-        let package_type = if ["torch", "torchvision", "torchaudio", "torchtext", "torchdata"].contains(&lname.as_str()) {
-            PackageType::Torch
-        } else if lname.starts_with("onnxruntime") {
-            PackageType::Onnxruntime
-        } else if lname.starts_with("insightface") {
-            PackageType::Insightface
-        } else if lname.starts_with("triton") {
-            PackageType::Triton
+    /// Parse `name[extras]<op>version ; markers`; None for comments, options and blank lines.
+    /// Requirements given as URLs (`name @ url`, wheel links) have no version.
+    pub fn parse_requirement_line(&self, line_in: &str) -> Option<PackageInfo> {
+        let name = requirement_name(line_in)?;
+        let spec = strip_requirement_comment(line_in).split(';').next().unwrap_or("").trim();
+        let version = if spec.contains("://") || spec.contains('@') {
+            None
         } else {
-            PackageType::Regular
+            spec.find(|c: char| "=><!~".contains(c)).map(|idx| {
+                spec[idx..].trim_matches(|c: char| "=><!~()".contains(c) || c.is_whitespace()).to_string()
+            })
         };
         
         Some(PackageInfo {
-            name: lname,
+            package_type: PackageType::of(&name),
+            name,
             version,
-            package_type,
         })
     }
 
-    fn create_installation_plan(&self, packages: &Vec<PackageInfo>) -> InstallationPlan {
+    pub fn create_installation_plan(&self, packages: &[PackageInfo]) -> InstallationPlan {
         let mut plan = InstallationPlan::default();
         for p in packages {
             match p.package_type {
@@ -110,49 +569,15 @@ impl<'a> RequirementsAnalyzer<'a> {
         }
         // torch index url
         plan.torch_index_url = Some(self.get_torch_index_url());
+        plan.nightly = nightly_channel(self.config_manager);
         // onnx package name by GPU vendor
         plan.onnx_package_name = Some(self.get_onnx_package_name());
+        plan.numpy = NumpyDecision::for_packages(packages);
         plan
     }
 
     fn get_torch_index_url(&self) -> String {
-        if self.config_manager.has_cuda() {
-            let gpu_name = self.config_manager.get_gpu_name();
-            let gpu_generation = self.config_manager.detect_current_gpu_generation();
-            let name_up = gpu_name.to_uppercase();
-            let is_blackwell = name_up.contains("RTX 50") || format!("{:?}", gpu_generation).to_lowercase().contains("blackwell");
-            if is_blackwell {
-                return "https://download.pytorch.org/whl/nightly/cu128".into();
-            }
-        }
-        
-        #[cfg(unix)]
-        {
-            if let Some(cv) = crate::utils::detect_cuda_version_from_system() {
-                return match cv {
-                    crate::config::CudaVersionLinux::Cuda128 => "https://download.pytorch.org/whl/nightly/cu128".into(),
-                    crate::config::CudaVersionLinux::Cuda126 => "https://download.pytorch.org/whl/cu126".into(),
-                    crate::config::CudaVersionLinux::Cuda124 => "https://download.pytorch.org/whl/cu124".into(),
-                    crate::config::CudaVersionLinux::Cuda121 => "https://download.pytorch.org/whl/cu121".into(),
-                    crate::config::CudaVersionLinux::Cuda118 => "https://download.pytorch.org/whl/cu118".into(),
-                };
-            }
-        }
-        
-        #[cfg(windows)]
-        {
-            if self.config_manager.has_cuda() {
-                if let Some(cuda_version) = self.config_manager.get_cuda_version() {
-                    return match cuda_version {
-                        crate::config::CudaVersion::Cuda128 => "https://download.pytorch.org/whl/nightly/cu128".into(),
-                        crate::config::CudaVersion::Cuda124 => "https://download.pytorch.org/whl/cu124".into(),
-                        crate::config::CudaVersion::Cuda118 => "https://download.pytorch.org/whl/cu118".into(),
-                    };
-                }
-            }
-        }
-        
-        "https://download.pytorch.org/whl/cpu".into()
+        torch_index_url(self.config_manager)
     }
 
     fn get_onnx_package_name(&self) -> String {
@@ -173,6 +598,11 @@ impl<'a> RequirementsAnalyzer<'a> {
 pub struct PipManager<'a> {
     command_runner: &'a CommandRunner<'a>,
     config_manager: &'a ConfigManager,
+    uv_failed_steps: RefCell<HashSet<InstallStep>>,
+    /// Constraints file passed to every install step once written
+    constraints: RefCell<Option<PathBuf>>,
+    on_error: OnStepError,
+    skipped: RefCell<Vec<SkippedStep>>,
 }
 
 impl<'a> PipManager<'a> {
@@ -183,11 +613,164 @@ impl<'a> PipManager<'a> {
         Self {
             command_runner,
             config_manager,
+            uv_failed_steps: RefCell::new(HashSet::new()),
+            constraints: RefCell::new(None),
+            on_error: OnStepError::Abort,
+            skipped: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// What to do when an install step fails; `Ask` must only be set when someone can answer
+    pub fn with_on_error(mut self, policy: OnStepError) -> Self {
+        self.on_error = policy;
+        self
+    }
+
+    /// Steps skipped after failing, in install order
+    pub fn skipped_steps(&self) -> Vec<SkippedStep> {
+        self.skipped.borrow().clone()
+    }
+
+    pub fn config_manager(&self) -> &ConfigManager {
+        self.config_manager
+    }
+
+    /// Resolve install engine: per-repo marker first, then global setting
+    pub fn resolve_engine(&self, repo_name: &str) -> InstallEngine {
+        let repo_path = self.config_manager.get_config().install_path.join("repos").join(repo_name);
+        if let Some(content) = repo_state::read(&repo_path, ENGINE_MARKER_FILE) {
+            match content.parse() {
+                Ok(engine) => return engine,
+                Err(e) => warn!("Ignoring the install engine marker of {}: {}", repo_name, e),
+            }
+        }
+        self.config_manager.get_install_engine()
+    }
+
+    /// Report the plan's numpy decision; a pin constrains this and all later install steps
+    fn apply_numpy_decision(&self, repo_name: &str, decision: &NumpyDecision) -> Result<()> {
+        info!("numpy for {}: {}", repo_name, decision.describe());
+        output::info(&format!("numpy: {}", decision.describe()));
+        if !matches!(decision, NumpyDecision::Pinned(_)) || self.constraints.borrow().is_some() {
+            return Ok(());
+        }
+        let repo_dir = self.config_manager.get_config().install_path.join("repos").join(repo_name);
+        if !repo_dir.exists() {
+            return Ok(());
+        }
+        let path = repo_state::write(&repo_dir, CONSTRAINTS_FILE, format!("{}\n", NUMPY1_SPEC))?;
+        *self.constraints.borrow_mut() = Some(path);
+        Ok(())
+    }
+
+    /// Build `<engine> install <args>` command, dropping uv-only flags for pip
+    fn build_install_command(&self, repo_name: &str, use_uv: bool, args: &[String]) -> Vec<String> {
+        let mut constraints = self.constraints.borrow().as_ref()
+            .map(|path| vec!["-c".to_string(), path.to_string_lossy().to_string()])
+            .unwrap_or_default();
+        // Wheels warmed by `prefetch` are preferred over the network
+        if let Some(cache) = crate::prefetch::find_links(&self.config_manager.get_config().install_path) {
+            constraints.extend(["--find-links".to_string(), cache.to_string_lossy().to_string()]);
+        }
+        if use_uv {
+            let mut cmd = self.get_uv_executable(repo_name);
+            cmd.extend(["pip".into(), "install".into()]);
+            // Outside a venv uv has to be told which interpreter to install into
+            if let Some(python) = self.shared_env_python(repo_name) {
+                cmd.extend(["--python".into(), python.to_string_lossy().to_string()]);
+            }
+            cmd.extend(constraints);
+            cmd.extend(args.iter().cloned());
+            return cmd;
+        }
+        let mut cmd = self.get_pip_executable(repo_name);
+        cmd.push("install".into());
+        cmd.extend(constraints);
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            if let Some((_, takes_value)) = UV_ONLY_FLAGS.iter().find(|(flag, _)| arg == flag) {
+                if *takes_value { iter.next(); }
+                continue;
+            }
+            cmd.push(arg.clone());
+        }
+        cmd
+    }
+
+    /// Run one install step with the configured engine.
+    /// In `auto` mode a uv failure falls back to pip and disables uv for this step class.
+    fn run_install_step(&self, repo_name: &str, step: InstallStep, args: &[String], label: &str, repo_path: Option<&Path>, silent: bool) -> Result<()> {
+        let engine = self.resolve_engine(repo_name);
+        let use_uv = match engine {
+            InstallEngine::Pip => false,
+            InstallEngine::Uv => {
+                if !self.install_uv_in_venv(repo_name).unwrap_or(false) {
+                    return Err(PortableSourceError::missing_dependency("uv (install engine is set to 'uv')"));
+                }
+                true
+            }
+            InstallEngine::Auto => {
+                !self.uv_failed_steps.borrow().contains(&step) && self.install_uv_in_venv(repo_name).unwrap_or(false)
+            }
+        };
+
+        if use_uv {
+            let cmd = self.build_install_command(repo_name, true, args);
+            let result = self.run_engine_command(&cmd, &format!("{} (uv)", label), repo_path, silent);
+            self.record_step(repo_name, step, "uv", &result);
+            match result {
+                Ok(()) => return Ok(()),
+                Err(e @ PortableSourceError::DiskFull { .. }) => return Err(e),
+                Err(e) if engine == InstallEngine::Auto => {
+                    warn!("uv failed on {} step, falling back to pip: {}", step, e);
+                    self.uv_failed_steps.borrow_mut().insert(step);
+                }
+                Err(e) => return Err(e),
+            }
         }
+
+        let cmd = self.build_install_command(repo_name, false, args);
+        let result = self.run_engine_command(&cmd, &format!("{} (pip)", label), repo_path, silent);
+        self.record_step(repo_name, step, "pip", &result);
+        result
+    }
+
+    fn run_engine_command(&self, cmd: &[String], label: &str, repo_path: Option<&Path>, silent: bool) -> Result<()> {
+        if silent {
+            self.command_runner.run_silent(cmd, Some(label), repo_path)
+        } else {
+            self.command_runner.run(cmd, Some(label), repo_path)
+        }
+    }
+
+    /// Append engine/step outcome to the repo install log
+    fn record_step(&self, repo_name: &str, step: InstallStep, engine: &str, result: &Result<()>) {
+        let status = if result.is_ok() { "ok" } else { "failed" };
+        debug!("Install step '{}' via {}: {}", step, engine, status);
+        let repo_dir = self.config_manager.get_config().install_path.join("repos").join(repo_name);
+        if !repo_dir.exists() {
+            return;
+        }
+        let line = format!("{} {} {} {}\n", self.command_runner.services().clock.unix_timestamp(), step, engine, status);
+        let log = repo_state::path(&repo_dir, ENGINE_LOG_FILE);
+        let opened = log.parent().map_or(Ok(()), fs::create_dir_all).and_then(|_| fs::OpenOptions::new().create(true).append(true).open(&log));
+        if let Ok(mut file) = opened {
+            let _ = file.write_all(line.as_bytes());
+        }
+    }
+
+    /// Shared environment the repository installs into instead of its venv (`--no-venv`)
+    fn shared_env_python(&self, repo_name: &str) -> Option<PathBuf> {
+        let install_path = &self.config_manager.get_config().install_path;
+        let target = crate::shared_env::EnvTarget::load(&install_path.join("repos").join(repo_name)).ok()??;
+        target.python(install_path).ok()
     }
 
     /// Get python executable path in virtual environment
     pub fn get_python_in_env(&self, repo_name: &str) -> PathBuf {
+        if let Some(python) = self.shared_env_python(repo_name) {
+            return python;
+        }
         let cfg = self.config_manager.get_config();
         let venv_path = cfg.install_path.join("envs").join(repo_name);
         if cfg!(windows) {
@@ -225,7 +808,7 @@ impl<'a> PipManager<'a> {
         let uv_cmd = self.get_uv_executable(repo_name);
         // Try uv --version
         if self.command_runner.run_silent(
-            &vec![uv_cmd[0].clone(), uv_cmd[1].clone(), uv_cmd[2].clone(), "--version".into()], 
+            &[uv_cmd[0].clone(), uv_cmd[1].clone(), uv_cmd[2].clone(), "--version".into()], 
             None, 
             None
         ).is_ok() {
@@ -243,7 +826,7 @@ impl<'a> PipManager<'a> {
         
         // Verify installation
         let uv_works = self.command_runner.run_silent(
-            &vec![uv_cmd[0].clone(), uv_cmd[1].clone(), uv_cmd[2].clone(), "--version".into()], 
+            &[uv_cmd[0].clone(), uv_cmd[1].clone(), uv_cmd[2].clone(), "--version".into()], 
             None, 
             None
         ).is_ok();
@@ -437,12 +1020,11 @@ impl<'a> PipManager<'a> {
 
     /// Install requirements from requirements.txt file using uv or pip
     pub fn install_requirements_with_uv_or_pip(&self, repo_name: &str, requirements: &Path, repo_path: Option<&Path>) -> Result<()> {
+        let _span = tracing::info_span!("requirements_install", file = %requirements.display()).entered();
         if !requirements.exists() {
             return Err(PortableSourceError::repository(format!("Requirements file not found: {:?}", requirements)));
         }
 
-        let uv_available = self.install_uv_in_venv(repo_name).unwrap_or(false);
-        
         // Handle case when requirements is in different directory than repo_path
         let tmp = if let Some(repo) = repo_path {
             if requirements.starts_with(repo) {
@@ -457,28 +1039,28 @@ impl<'a> PipManager<'a> {
             requirements.to_path_buf()
         };
 
+        let analyzer = RequirementsAnalyzer::new(self.config_manager);
+        let content = std::fs::read_to_string(&tmp)?;
+        let content = self.lines_for_target(repo_name, content.lines()).join("\n");
+        let packages: Vec<PackageInfo> = content
+            .lines()
+            .filter_map(|line| analyzer.parse_requirement_line(line))
+            .collect();
+        let mut plan = analyzer.create_installation_plan(&packages);
+        plan.merge_indexes(RequirementsIndexes::from_lines(content.lines()));
+        plan.report_indexes();
+        self.apply_numpy_decision(repo_name, &plan.numpy)?;
+        let index_args = plan.indexes.args();
+
         // Filter out packages that we install separately from requirements
         let filtered_req = if repo_path.is_some() {
             let filtered_path = tmp.parent().unwrap().join("requirements_filtered.txt");
-            let content = std::fs::read_to_string(&tmp)?;
+            let target = if content.contains(".whl") { self.wheel_target(repo_name) } else { None };
             let filtered_content = content
                 .lines()
-                .filter(|line| {
-                    let line_lower = line.trim().to_lowercase();
-                    // Skip empty lines and comments
-                    if line_lower.is_empty() || line_lower.starts_with('#') {
-                        return true;
-                    }
-                    // Filter out packages we install separately
-                    !line_lower.starts_with("insightface") && 
-                    !line_lower.contains("insightface") &&
-                    !line_lower.starts_with("onnxruntime") && 
-                    !line_lower.contains("onnxruntime") &&
-                    !line_lower.starts_with("torch") && 
-                    !line_lower.contains("torch") &&
-                    !line_lower.starts_with("triton") && 
-                    !line_lower.contains("triton")
-                })
+                // Filter out packages we install separately; index options are passed as arguments
+                .filter(|line| !is_installed_separately(line) && !RequirementsIndexes::is_index_line(line))
+                .map(|line| if line.contains(".whl") { self.compatible_wheel_spec(line.trim(), target.as_ref()) } else { line.to_string() })
                 .collect::<Vec<_>>()
                 .join("\n");
             std::fs::write(&filtered_path, filtered_content)?;
@@ -487,15 +1069,18 @@ impl<'a> PipManager<'a> {
             tmp.clone()
         };
 
-        if uv_available {
-            let mut uv_cmd = self.get_uv_executable(repo_name);
-            uv_cmd.extend(["pip".into(), "install".into(), "-r".into(), filtered_req.to_string_lossy().to_string()]);
-            self.command_runner.run(&uv_cmd, Some("Installing requirements (uv)"), repo_path)?;
-        } else {
-            let mut pip_cmd = self.get_pip_executable(repo_name);
-            pip_cmd.extend(["install".into(), "-r".into(), filtered_req.to_string_lossy().to_string()]);
-            self.command_runner.run(&pip_cmd, Some("Installing requirements (pip)"), repo_path)?;
-        }
+        let mut requirements_args = index_args.clone();
+        requirements_args.extend(["-r".into(), filtered_req.to_string_lossy().to_string()]);
+        self.with_failure_policy(InstallStep::Requirements, || {
+            self.run_install_step(
+                repo_name,
+                InstallStep::Requirements,
+                &requirements_args,
+                "Installing requirements",
+                repo_path,
+                false,
+            )
+        })?;
 
         // Clean up temporary files if created
         if repo_path.is_some() {
@@ -509,123 +1094,73 @@ impl<'a> PipManager<'a> {
 
         // Install ONNX with GPU detection after base requirements
         let onnx_spec = self.get_onnx_package_spec();
-        let mut onnx_cmd = if uv_available {
-            let mut cmd = self.get_uv_executable(repo_name);
-            cmd.extend(["pip".into(), "install".into()]);
-            cmd
-        } else {
-            let mut cmd = self.get_pip_executable(repo_name);
-            cmd.push("install".into());
-            cmd
-        };
+        let mut onnx_args: Vec<String> = Vec::new();
         
         // Check if we need --pre flag for nightly builds (Blackwell GPUs)
         if self.needs_onnx_nightly() {
-            onnx_cmd.push("--pre".into());
+            onnx_args.push("--pre".into());
         }
         
-        onnx_cmd.extend(["--index-strategy".into(), "unsafe-best-match".into()]);
-        onnx_cmd.push(onnx_spec);
+        onnx_args.extend(["--index-strategy".into(), "unsafe-best-match".into()]);
+        onnx_args.extend(index_args.iter().cloned());
+        onnx_args.push(onnx_spec);
         
-        if let Err(_) = self.command_runner.run(&onnx_cmd, Some("Installing ONNX with GPU support"), repo_path) {
+        if self.run_install_step(repo_name, InstallStep::Onnx, &onnx_args, "Installing ONNX with GPU support", repo_path, false).is_err() {
             // Fallback without --pre if it fails
             if self.needs_onnx_nightly() {
-                let mut fallback_cmd = if uv_available {
-                    let mut cmd = self.get_uv_executable(repo_name);
-                    cmd.extend(["pip".into(), "install".into()]);
-                    cmd
-                } else {
-                    let mut cmd = self.get_pip_executable(repo_name);
-                    cmd.push("install".into());
-                    cmd
-                };
-                fallback_cmd.extend(["--index-strategy".into(), "unsafe-best-match".into()]);
-                fallback_cmd.push(self.get_onnx_package_spec());
-                let _ = self.command_runner.run(&fallback_cmd, Some("Installing ONNX (fallback)"), repo_path);
+                let mut fallback_args = vec!["--index-strategy".into(), "unsafe-best-match".into()];
+                fallback_args.extend(index_args.iter().cloned());
+                fallback_args.push(self.get_onnx_package_spec());
+                let _ = self.run_install_step(repo_name, InstallStep::Onnx, &fallback_args, "Installing ONNX (fallback)", repo_path, false);
             }
         }
 
         // Check if torch is installed and reinstall with CUDA index if needed
-        let mut check_cmd = self.get_pip_executable(repo_name);
-        check_cmd.extend(["show".into(), "torch".into()]);
-        
-        let cfg = self.config_manager.get_config();
-        let venv_path = cfg.install_path.join("envs").join(repo_name);
-        
-        if let Ok(output) = std::process::Command::new(&check_cmd[0])
-            .args(&check_cmd[1..])
-            .env("VIRTUAL_ENV", venv_path)
-            .output() {
-            if output.status.success() {
-                let mut reinstall_cmd = if uv_available {
-                    let mut cmd = self.get_uv_executable(repo_name);
-                    cmd.extend(["pip".into(), "install".into()]);
-                    cmd
-                } else {
-                    let mut cmd = self.get_pip_executable(repo_name);
-                    cmd.push("install".into());
-                    cmd
-                };
-                
-                reinstall_cmd.extend([
+        if self.installed_version(repo_name, "torch").is_some() {
+            let index_url = self.get_default_torch_index_url();
+            let all_match = TORCH_BUILD_PACKAGES
+                .iter()
+                .all(|package| self.installed_version(repo_name, package).is_some_and(|v| torch_build_matches(&v, &index_url)));
+            if all_match {
+                // e.g. kept from the environment a warm start cloned
+                info!("torch, torchvision and torchaudio already match {}", index_url);
+            } else {
+                if let Some(note) = nightly_channel(self.config_manager) {
+                    note.announce();
+                }
+                let reinstall_args = vec![
                     "--force-reinstall".into(), 
                     "--index-url".into(), 
-                    self.get_default_torch_index_url(),
+                    index_url,
                     "torch".into(), 
                     "torchvision".into(), 
                     "torchaudio".into()
-                ]);
+                ];
                 
-                if let Err(_) = self.command_runner.run_silent(&reinstall_cmd, Some("Reinstalling torch with CUDA"), repo_path) {
-                    // Fallback to pip if uv fails
-                    if uv_available {
-                        let mut pip_cmd = self.get_pip_executable(repo_name);
-                        pip_cmd.extend([
-                            "install".into(), 
-                            "--force-reinstall".into(), 
-                            "--index-url".into(), 
-                            self.get_default_torch_index_url(),
-                            "torch".into(), 
-                            "torchvision".into(), 
-                            "torchaudio".into()
-                        ]);
-                        let _ = self.command_runner.run_silent(&pip_cmd, Some("Reinstalling torch with CUDA (pip)"), repo_path);
-                    }
-                }
+                // uv -> pip fallback is handled by run_install_step in auto mode
+                let _ = self.run_install_step(repo_name, InstallStep::Torch, &reinstall_args, "Reinstalling torch with CUDA", repo_path, true);
             }
         }
 
-        // Install Triton with platform-specific package names
-        let mut triton_cmd = if uv_available {
-            let mut cmd = self.get_uv_executable(repo_name);
-            cmd.extend(["pip".into(), "install".into()]);
-            cmd
-        } else {
-            let mut cmd = self.get_pip_executable(repo_name);
-            cmd.push("install".into());
-            cmd
-        };
-        
-        // Use platform-specific triton package names
-        #[cfg(windows)]
-        triton_cmd.push("triton-windows".into());
-        #[cfg(not(windows))]
-        triton_cmd.push("triton".into());
-        
-        let _ = self.command_runner.run(&triton_cmd, Some("Installing Triton"), repo_path);
+        // Install Triton with platform-specific package names; it only speeds up GPU kernels
+        if !self.config_manager.cpu_only() {
+            let mut triton_args = index_args;
+            #[cfg(windows)]
+            triton_args.push("triton-windows".to_string());
+            #[cfg(not(windows))]
+            triton_args.push("triton".to_string());
+            
+            let _ = self.run_install_step(repo_name, InstallStep::Triton, &triton_args, "Installing Triton", repo_path, false);
+        }
 
         // Check if InsightFace was in the original requirements
-        let needs_insightface = std::fs::read_to_string(&tmp)?
+        let needs_insightface = content
             .lines()
-            .any(|line| {
-                let line_lower = line.trim().to_lowercase();
-                line_lower.starts_with("insightface") || 
-                line_lower.contains("insightface")
-            });
+            .any(|line| requirement_name(line).is_some_and(|name| PackageType::of(&name) == PackageType::Insightface));
 
         // Install InsightFace only if it was requested in requirements
         if needs_insightface {
-            self.handle_insightface_package(repo_name, repo_path)?;
+            self.with_failure_policy(InstallStep::Insightface, || self.handle_insightface_package(repo_name, repo_path))?;
         }
 
         Ok(())
@@ -633,17 +1168,89 @@ impl<'a> PipManager<'a> {
 
     /// Install repository as package using uv or pip
     pub fn install_repo_as_package(&self, repo_name: &str, repo_path: &Path) -> Result<()> {
-        let uv_available = self.install_uv_in_venv(repo_name).unwrap_or(false);
-        
-        if uv_available {
-            let mut uv_cmd = self.get_uv_executable(repo_name);
-            uv_cmd.extend(["pip".into(), "install".into(), ".".into()]);
-            self.command_runner.run_silent(&uv_cmd, Some("Installing repository as package (uv)"), Some(repo_path))
-        } else {
-            let mut pip_cmd = self.get_pip_executable(repo_name);
-            pip_cmd.extend(["install".into(), ".".into()]);
-            self.command_runner.run_silent(&pip_cmd, Some("Installing repository as package (pip)"), Some(repo_path))
+        self.with_failure_policy(InstallStep::RepoPackage, || {
+            self.run_install_step(repo_name, InstallStep::RepoPackage, &[".".into()], "Installing repository as package", Some(repo_path), true)
+        })
+    }
+
+    /// Install the repository package alone; its dependencies come from a lock file export
+    pub fn install_repo_without_deps(&self, repo_name: &str, repo_path: &Path) -> Result<()> {
+        self.with_failure_policy(InstallStep::RepoPackage, || {
+            self.run_install_step(repo_name, InstallStep::RepoPackage, &[".".into(), "--no-deps".into()], "Installing repository as package", Some(repo_path), true)
+        })
+    }
+
+    /// Version of `package` installed in the repository's environment, local build tag
+    /// included (`2.5.1+cu124`)
+    fn installed_version(&self, repo_name: &str, package: &str) -> Option<String> {
+        let mut cmd = self.get_pip_executable(repo_name);
+        cmd.extend(["show".into(), package.into()]);
+        let output = self.command_runner.run_capture(&cmd, None).ok().filter(|o| o.success())?;
+        output.stdout.lines().find_map(|line| line.strip_prefix("Version:")).map(|v| v.trim().to_string())
+    }
+
+    /// Remove `packages` from the repository's environment
+    pub fn uninstall_packages(&self, repo_name: &str, packages: &[String]) -> Result<()> {
+        let mut cmd = self.get_pip_executable(repo_name);
+        cmd.extend(["uninstall".into(), "-y".into()]);
+        cmd.extend(packages.iter().cloned());
+        self.command_runner.run_silent(&cmd, Some("Removing packages"), None)
+    }
+
+    /// Reinstall `specs` (`name==version`) as they are, without touching their dependencies
+    pub fn reinstall_without_deps(&self, repo_name: &str, specs: &[String], repo_path: Option<&Path>) -> Result<()> {
+        let mut args = vec!["--force-reinstall".to_string(), "--no-deps".to_string()];
+        args.extend(specs.iter().cloned());
+        self.run_install_step(repo_name, InstallStep::Regular, &args, "Reinstalling packages", repo_path, true)
+    }
+
+    /// Reinstall the backend-dependent packages (torch stack, ONNX Runtime variant) in the
+    /// build this manager's config selects; versions are kept. A failed install leaves the
+    /// previous build in place. Returns the packages that were swapped
+    pub fn switch_backend_packages(&self, repo_name: &str, repo_path: &Path) -> Result<Vec<String>> {
+        let mut swapped = Vec::new();
+        let torch: Vec<(String, String)> = TORCH_BUILD_PACKAGES
+            .into_iter()
+            .filter_map(|p| self.installed_version(repo_name, p).map(|v| (p.to_string(), v.split('+').next().unwrap_or("").to_string())))
+            .collect();
+        if !torch.is_empty() {
+            if let Some(note) = nightly_channel(self.config_manager) {
+                note.announce();
+            }
+            let names: Vec<String> = torch.iter().map(|(name, _)| name.clone()).collect();
+            // One pip call: the old build is only replaced once the new one is downloaded
+            let mut args = vec!["--force-reinstall".to_string(), "--index-url".to_string(), self.get_default_torch_index_url()];
+            args.extend(torch.iter().map(|(name, version)| format!("{}=={}", name, version)));
+            self.run_install_step(repo_name, InstallStep::Torch, &args, "Installing torch for the new backend", Some(repo_path), false)?;
+            swapped.extend(names);
         }
+
+        let spec = self.get_onnx_package_spec();
+        let target = spec.split(['>', '=', '<']).next().unwrap_or("onnxruntime").to_string();
+        let onnx: Vec<(String, String)> = ["onnxruntime", "onnxruntime-gpu", "onnxruntime-directml"]
+            .into_iter()
+            .filter_map(|p| self.installed_version(repo_name, p).map(|v| (p.to_string(), v)))
+            .collect();
+        if !onnx.is_empty() && onnx.iter().map(|(name, _)| name.as_str()).ne([target.as_str()]) {
+            // The variants share the onnxruntime module, so the old one goes first and comes
+            // back when the new one cannot be installed
+            let names: Vec<String> = onnx.iter().map(|(name, _)| name.clone()).collect();
+            self.uninstall_packages(repo_name, &names)?;
+            let mut args: Vec<String> = Vec::new();
+            if self.needs_onnx_nightly() {
+                args.push("--pre".into());
+            }
+            args.push(spec);
+            if let Err(e) = self.run_install_step(repo_name, InstallStep::Onnx, &args, "Installing ONNX Runtime for the new backend", Some(repo_path), false) {
+                let previous: Vec<String> = onnx.iter().map(|(name, version)| format!("{}=={}", name, version)).collect();
+                if let Err(restore) = self.run_install_step(repo_name, InstallStep::Onnx, &previous, "Restoring the previous ONNX Runtime", Some(repo_path), false) {
+                    warn!("Could not restore {}: {}", previous.join(", "), restore);
+                }
+                return Err(e);
+            }
+            swapped.push(target);
+        }
+        Ok(swapped)
     }
 
     /// Apply ONNX GPU detection to package name
@@ -662,42 +1269,17 @@ impl<'a> PipManager<'a> {
 
     /// Check if ONNX nightly build is needed for GPU compatibility
     pub fn needs_onnx_nightly(&self) -> bool {
-        // Blackwell GPUs need nightly builds
-        if self.config_manager.has_cuda() {
-            let gpu_generation = self.config_manager.detect_current_gpu_generation();
-            let gpu_name = self.config_manager.get_gpu_name();
-            let gpu_gen = format!("{:?}", gpu_generation).to_lowercase();
-            let name_up = gpu_name.to_uppercase();
-            let is_nvidia = name_up.contains("NVIDIA") || name_up.contains("RTX") || name_up.contains("GEFORCE");
-            if is_nvidia && gpu_gen.contains("blackwell") {
-                return true;
-            }
-        }
-        
-        // Linux: system CUDA 12.8
-        #[cfg(unix)]
-        {
-            if let Some(cv) = crate::utils::detect_cuda_version_from_system() {
-                if matches!(cv, crate::config::CudaVersionLinux::Cuda128) {
-                    return true;
-                }
-            }
-        }
-        
-        false
+        nightly_channel(self.config_manager).is_some()
     }
 
     /// Get ONNX package specification with GPU generation consideration
     pub fn get_onnx_package_spec(&self) -> String {
         if self.config_manager.has_cuda() {
-            let gpu_generation = self.config_manager.detect_current_gpu_generation();
             let gpu_name = self.config_manager.get_gpu_name();
-            let gpu_gen = format!("{:?}", gpu_generation).to_lowercase();
             let name_up = gpu_name.to_uppercase();
             let is_nvidia = name_up.contains("NVIDIA") || name_up.contains("RTX") || name_up.contains("GEFORCE");
-            let is_blackwell = gpu_gen.contains("blackwell");
             
-            if is_nvidia && is_blackwell {
+            if is_nvidia && self.needs_onnx_nightly() {
                 return "onnxruntime-gpu>=1.20".into();
             }
             if is_nvidia {
@@ -712,45 +1294,7 @@ impl<'a> PipManager<'a> {
 
     /// Get default torch index URL based on GPU and CUDA configuration
     pub fn get_default_torch_index_url(&self) -> String {
-        if self.config_manager.has_cuda() {
-            let gpu_name = self.config_manager.get_gpu_name();
-            let gpu_generation = self.config_manager.detect_current_gpu_generation();
-            let _name_up = gpu_name.to_uppercase();
-            let gen_str = format!("{:?}", gpu_generation).to_lowercase();
-            let is_blackwell = gen_str.contains("blackwell");
-            
-            if is_blackwell {
-                return "https://download.pytorch.org/whl/nightly/cu128".to_string();
-            }
-        }
-        
-        #[cfg(unix)]
-        {
-            if let Some(cv) = crate::utils::detect_cuda_version_from_system() {
-                return match cv {
-                    crate::config::CudaVersionLinux::Cuda128 => "https://download.pytorch.org/whl/nightly/cu128".to_string(),
-                    crate::config::CudaVersionLinux::Cuda126 => "https://download.pytorch.org/whl/cu126".to_string(),
-                    crate::config::CudaVersionLinux::Cuda124 => "https://download.pytorch.org/whl/cu124".to_string(),
-                    crate::config::CudaVersionLinux::Cuda121 => "https://download.pytorch.org/whl/cu121".to_string(),
-                    crate::config::CudaVersionLinux::Cuda118 => "https://download.pytorch.org/whl/cu118".to_string(),
-                };
-            }
-        }
-        
-        #[cfg(windows)]
-        {
-            if self.config_manager.has_cuda() {
-                if let Some(cuda_version) = self.config_manager.get_cuda_version() {
-                    return match cuda_version {
-                        crate::config::CudaVersion::Cuda128 => "https://download.pytorch.org/whl/nightly/cu128".to_string(),
-                        crate::config::CudaVersion::Cuda124 => "https://download.pytorch.org/whl/cu124".to_string(),
-                        crate::config::CudaVersion::Cuda118 => "https://download.pytorch.org/whl/cu118".to_string(),
-                    };
-                }
-            }
-        }
-        
-        "https://download.pytorch.org/whl/cpu".to_string()
+        torch_index_url(self.config_manager)
     }
     
     /// Get optional torch index URL
@@ -799,37 +1343,29 @@ impl<'a> PipManager<'a> {
 
     /// Handle pip_install step with comprehensive package analysis and separation
     fn handle_pip_install_step(&self, repo_name: &str, step: &JsonValue, repo_path: Option<&Path>) -> Result<()> {
-        let uv_available = self.install_uv_in_venv(repo_name).unwrap_or(false);
-        
         // Create analyzer for intelligent package processing
         let analyzer = RequirementsAnalyzer::new(self.config_manager);
         
         // Parse packages into PackageInfo structs with proper version handling
         let mut packages = Vec::new();
-        if let Some(pkgs) = step.get("packages").and_then(|p| p.as_array()) {
-            for p in pkgs {
-                if let Some(s) = p.as_str() {
-                    if let Some(pkg_info) = analyzer.parse_requirement_line(s) {
-                        packages.push(pkg_info);
-                    }
-                }
+        let lines = step.get("packages").and_then(|p| p.as_array()).into_iter().flatten().filter_map(|p| p.as_str());
+        let lines = self.lines_for_target(repo_name, lines);
+        for s in &lines {
+            if let Some(pkg_info) = analyzer.parse_requirement_line(s) {
+                packages.push(pkg_info);
             }
         }
         
         // Create installation plan with intelligent package separation
-        let plan = analyzer.create_installation_plan(&packages);
+        let mut plan = analyzer.create_installation_plan(&packages);
+        plan.merge_indexes(RequirementsIndexes::from_lines(lines.iter().copied()));
+        plan.report_indexes();
+        self.apply_numpy_decision(repo_name, &plan.numpy)?;
+        let index_args = plan.indexes.args();
         
         // Install regular packages first (no special index needed)
         if !plan.regular_packages.is_empty() {
-            let mut cmd = if uv_available {
-                let mut c = self.get_uv_executable(repo_name);
-                c.extend(["pip".into(), "install".into()]);
-                c
-            } else {
-                let mut c = self.get_pip_executable(repo_name);
-                c.push("install".into());
-                c
-            };
+            let mut args: Vec<String> = Vec::new();
             
             // Check if we need --pre flag for any onnx packages that got classified as regular
             let needs_pre = self.needs_onnx_nightly() && 
@@ -837,12 +1373,13 @@ impl<'a> PipManager<'a> {
                     pkg.name.starts_with("onnxruntime") && pkg.name.contains("gpu")
                 });
             if needs_pre {
-                cmd.push("--pre".into());
+                args.push("--pre".into());
             }
             
             // Add dependency resolution strategy flags for better conflict handling
-            cmd.extend(["--resolution".into(), "highest".into()]);
-            cmd.extend(["--index-strategy".into(), "unsafe-best-match".into()]);
+            args.extend(["--resolution".into(), "highest".into()]);
+            args.extend(["--index-strategy".into(), "unsafe-best-match".into()]);
+            args.extend(index_args.iter().cloned());
             
             // Add package specs with proper version handling
             for pkg in &plan.regular_packages {
@@ -852,15 +1389,15 @@ impl<'a> PipManager<'a> {
                     {
                         // On Windows, use regular tensorflow (CUDA libraries come separately)
                         // Use compatible version that works with typing-extensions>=4.8.0
-                        "tensorflow==2.15.0".to_string()
+                        format!("tensorflow=={}", TENSORFLOW_DEFAULT_VERSION)
                     }
                     #[cfg(not(windows))]
                     {
                         // On Linux, can use tensorflow with CUDA extensions
                         if self.config_manager.has_cuda() {
-                            "tensorflow==2.15.0".to_string()
+                            format!("tensorflow=={}", TENSORFLOW_DEFAULT_VERSION)
                         } else {
-                            "tensorflow-cpu==2.15.0".to_string()
+                            format!("tensorflow-cpu=={}", TENSORFLOW_DEFAULT_VERSION)
                         }
                     }
                 } else if pkg.name == "typing-extensions" && pkg.version.is_some() {
@@ -871,32 +1408,28 @@ impl<'a> PipManager<'a> {
                 } else {
                     pkg.to_string()
                 };
-                cmd.push(pkg_spec);
+                args.push(pkg_spec);
             }
             
-            self.command_runner.run(&cmd, Some("Installing regular packages"), repo_path)?;
+            self.with_failure_policy(InstallStep::Regular, || {
+                self.run_install_step(repo_name, InstallStep::Regular, &args, "Installing regular packages", repo_path, false)
+            })?;
         }
         
         // Install torch packages with appropriate index URL
         if !plan.torch_packages.is_empty() {
-            let mut cmd = if uv_available {
-                let mut c = self.get_uv_executable(repo_name);
-                c.extend(["pip".into(), "install".into()]);
-                c
-            } else {
-                let mut c = self.get_pip_executable(repo_name);
-                c.push("install".into());
-                c
-            };
+            let mut args: Vec<String> = Vec::new();
             
             // Use torch index URL from plan or step or default
-            let torch_index = plan.torch_index_url.as_ref()
-                .map(|s| s.as_str())
+            let torch_index = plan.torch_index_url.as_deref()
                 .or_else(|| step.get("torch_index_url").and_then(|s| s.as_str()))
                 .map(|s| s.to_string())
                 .unwrap_or_else(|| self.get_default_torch_index_url());
             
-            cmd.extend(["--index-url".into(), torch_index]);
+            if let Some(note) = &plan.nightly {
+                note.announce();
+            }
+            args.extend(["--index-url".into(), torch_index]);
             
             // Complete torch package trio - ensure torch, torchvision, torchaudio are all present
             let torch_names: std::collections::HashSet<String> = plan.torch_packages.iter().map(|p| p.name.clone()).collect();
@@ -922,126 +1455,143 @@ impl<'a> PipManager<'a> {
             
             // Add torch package specs with versions
             for pkg in &final_packages {
-                cmd.push(pkg.to_string());
+                args.push(pkg.to_string());
             }
             
-            self.command_runner.run(&cmd, Some("Installing torch packages"), repo_path)?;
+            self.with_failure_policy(InstallStep::Torch, || {
+                self.run_install_step(repo_name, InstallStep::Torch, &args, "Installing torch packages", repo_path, false)
+            })?;
         }
         
         // Install onnx packages with GPU detection and version handling
         if !plan.onnx_packages.is_empty() {
-            let mut cmd = if uv_available {
-                let mut c = self.get_uv_executable(repo_name);
-                c.extend(["pip".into(), "install".into()]);
-                c
-            } else {
-                let mut c = self.get_pip_executable(repo_name);
-                c.push("install".into());
-                c
-            };
+            let mut args: Vec<String> = Vec::new();
             
             // Check if we need --pre flag for nightly builds
             if self.needs_onnx_nightly() {
-                cmd.push("--pre".into());
+                args.push("--pre".into());
             }
             
+            args.extend(index_args.iter().cloned());
             // Apply GPU detection to onnx packages and add to command
             for pkg in &plan.onnx_packages {
                 let onnx_spec = self.apply_onnx_gpu_detection(&pkg.to_string());
-                cmd.push(onnx_spec);
+                args.push(onnx_spec);
             }
             
-            self.command_runner.run(&cmd, Some("Installing ONNX packages"), repo_path)?;
+            self.with_failure_policy(InstallStep::Onnx, || {
+                self.run_install_step(repo_name, InstallStep::Onnx, &args, "Installing ONNX packages", repo_path, false)
+            })?;
         }
         
         // Handle special packages with custom installation logic
         if !plan.insightface_packages.is_empty() {
-            self.handle_insightface_package(repo_name, repo_path)?;
+            self.with_failure_policy(InstallStep::Insightface, || self.handle_insightface_package(repo_name, repo_path))?;
         }
         
         // Handle triton packages with platform-specific logic
         if !plan.triton_packages.is_empty() {
-            let mut cmd = if uv_available {
-                let mut c = self.get_uv_executable(repo_name);
-                c.extend(["pip".into(), "install".into()]);
-                c
-            } else {
-                let mut c = self.get_pip_executable(repo_name);
-                c.push("install".into());
-                c
-            };
-            
             // Use platform-specific triton package names
+            let mut args = index_args;
             #[cfg(windows)]
-            cmd.push("triton-windows".into());
+            args.push("triton-windows".to_string());
             #[cfg(not(windows))]
-            cmd.push("triton".into());
+            args.push("triton".to_string());
             
-            self.command_runner.run(&cmd, Some("Installing Triton packages"), repo_path)?;
+            self.with_failure_policy(InstallStep::Triton, || {
+                self.run_install_step(repo_name, InstallStep::Triton, &args, "Installing Triton packages", repo_path, false)
+            })?;
         }
         
         Ok(())
     }
 
-    /// Handle insightface package installation with Windows wheel support
-    pub fn handle_insightface_package(&self, repo_name: &str, repo_path: Option<&Path>) -> Result<()> {
-        #[cfg(windows)]
-        {
-            let uv_available = self.install_uv_in_venv(repo_name).unwrap_or(false);
-            
-            // Use precompiled wheel for Windows
-            let wheel = "https://huggingface.co/hanamizuki-ai/pypi-wheels/resolve/main/insightface/insightface-0.7.3-cp311-cp311-win_amd64.whl";
-            if uv_available {
-                let mut uv_cmd = self.get_uv_executable(repo_name);
-                uv_cmd.extend([
-                    "pip".into(), 
-                    "install".into(), 
-                    "--force-reinstall".into(),
-                    "-U".into(),
-                    wheel.into(),
-                    "numpy==1.26.4".into()
-                ]);
-                self.command_runner.run(&uv_cmd, Some("Installing insightface + numpy (uv)"), repo_path)
-            } else {
-                let mut pip_cmd = self.get_pip_executable(repo_name);
-                pip_cmd.extend([
-                    "install".into(), 
-                    "--force-reinstall".into(),
-                    "-U".into(),
-                    wheel.into(),
-                    "numpy==1.26.4".into()
-                ]);
-                self.command_runner.run(&pip_cmd, Some("Installing insightface + numpy (pip)"), repo_path)
+    /// Run a step; when it fails, retry, skip or abort it as `--on-error` says
+    fn with_failure_policy(&self, step: InstallStep, run: impl FnMut() -> Result<()>) -> Result<()> {
+        let skipped = step_failure::run_step(self.on_error, step.is_optional(), run, |error| {
+            output::warn(&format!("Step '{}' failed: {}", step, error));
+            println!("If skipped: {}", step.consequence());
+            match prompt::choose("Retry, skip or abort?", &["retry", "skip", "abort"], 2) {
+                0 => StepDecision::Retry,
+                1 => StepDecision::Skip,
+                _ => StepDecision::Abort,
             }
+        })?;
+        if let Some(error) = skipped {
+            output::warn(&format!("Skipped step '{}': {}", step, step.consequence()));
+            self.skipped.borrow_mut().push(SkippedStep {
+                step: step.to_string(),
+                consequence: step.consequence().to_string(),
+                error: error.to_string(),
+                skipped_at: self.command_runner.services().clock.unix_timestamp(),
+            });
         }
-        
-        #[cfg(not(windows))]
-        {
-            let uv_available = self.install_uv_in_venv(repo_name).unwrap_or(false);
-            
-            if uv_available {
-                let mut uv_cmd = self.get_uv_executable(repo_name);
-                uv_cmd.extend([
-                    "pip".into(), 
-                    "install".into(), 
-                    "--force-reinstall".into(),
-                    "-U".into(), 
-                    "insightface".into(), 
-                    "numpy==1.26.4".into()
-                ]);
-                self.command_runner.run(&uv_cmd, Some("Installing insightface + numpy (uv)"), repo_path)
-            } else {
-                let mut pip_cmd = self.get_pip_executable(repo_name);
-                pip_cmd.extend([
-                    "install".into(), 
-                    "--force-reinstall".into(),
-                    "-U".into(), 
-                    "insightface".into(), 
-                    "numpy==1.26.4".into()
-                ]);
-                self.command_runner.run(&pip_cmd, Some("Installing insightface + numpy (pip)"), repo_path)
+        Ok(())
+    }
+
+    /// Requirement lines whose environment marker holds for the repository venv (markers
+    /// are evaluated on this platform when the venv cannot be asked)
+    fn lines_for_target<'l>(&self, repo_name: &str, lines: impl IntoIterator<Item = &'l str>) -> Vec<&'l str> {
+        let lines: Vec<&str> = lines.into_iter().collect();
+        if !lines.iter().any(|line| requirement_marker(line).is_some()) {
+            return lines;
+        }
+        let env = MarkerEnvironment::probe(&self.get_python_in_env(repo_name)).unwrap_or_else(|| {
+            debug!("Could not query the Python of {}; evaluating markers for this platform", repo_name);
+            MarkerEnvironment::host()
+        });
+        lines
+            .into_iter()
+            .filter(|line| {
+                let applies = env.applies(line);
+                if !applies {
+                    info!("Skipping '{}': marker does not match this environment", line.trim());
+                }
+                applies
+            })
+            .collect()
+    }
+
+    /// Interpreter of the repository venv, for wheel tag checks
+    pub fn wheel_target(&self, repo_name: &str) -> Option<TargetPython> {
+        let target = TargetPython::probe(&self.get_python_in_env(repo_name));
+        if target.is_none() {
+            debug!("Could not query the Python of {}; wheel URLs are used unchecked", repo_name);
+        }
+        target
+    }
+
+    /// `spec` itself, or a replacement when it pins a wheel built for another Python/platform
+    pub fn compatible_wheel_spec(&self, spec: &str, target: Option<&TargetPython>) -> String {
+        let Some(target) = target else { return spec.to_string() };
+        match wheel_compat::resolve_wheel(spec, target, wheel_compat::url_exists) {
+            WheelResolution::Compatible => spec.to_string(),
+            WheelResolution::Alternative { spec, reason } => {
+                output::warn(&format!("{}; using {}", reason, spec));
+                spec
+            }
+            WheelResolution::SourceBuild { spec, reason } => {
+                output::warn(&format!("{}; no matching wheel found, installing {} from the index (may build from source)", reason, spec));
+                spec
             }
         }
     }
 
+    /// Handle insightface package installation with Windows wheel support
+    pub fn handle_insightface_package(&self, repo_name: &str, repo_path: Option<&Path>) -> Result<()> {
+        // Use precompiled wheel for Windows
+        #[cfg(windows)]
+        let package = self.compatible_wheel_spec(INSIGHTFACE_WINDOWS_WHEEL, self.wheel_target(repo_name).as_ref());
+        #[cfg(not(windows))]
+        let package = "insightface".to_string();
+        
+        let args = vec![
+            "--force-reinstall".into(),
+            "-U".into(),
+            package,
+            NUMPY1_SPEC.into()
+        ];
+        self.run_install_step(repo_name, InstallStep::Insightface, &args, "Installing insightface + numpy", repo_path, false)
+    }
+
 }
@@ -0,0 +1,266 @@
+//! Review of server-provided installation plans
+//!
+//! A server plan can install any package from any index. With `--review-plan` the fetched
+//! plan is compared with what the repository itself declares (requirements files,
+//! `[project.dependencies]`, poetry/pdm locks) and shown as a diff before anything runs:
+//! `=` the repository asks for the same thing, `~` a different version of a package the
+//! repository uses, `+` something the repository does not mention. Anything marked
+//! `~` or `+` (other versions, packages, direct URLs, foreign indexes) needs confirmation;
+//! declining installs from the repository's own files instead.
+
+use crate::installer::lockfile::{self, requirement_name};
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path};
+
+/// Index used for torch when the plan does not name one; plans pointing there need no review
+const PYTORCH_INDEX_HOST: &str = "download.pytorch.org";
+
+/// Requirements the repository declares, by normalized name, plus the indexes it uses
+#[derive(Debug, Default)]
+pub struct LocalRequirements {
+    pub specs: HashMap<String, Vec<(String, String)>>,
+    pub indexes: Vec<String>,
+}
+
+impl LocalRequirements {
+    fn add(&mut self, spec: &str, source: &str) {
+        let spec = spec.split(" #").next().unwrap_or("").trim();
+        if spec.is_empty() || spec.starts_with('#') {
+            return;
+        }
+        for flag in ["--index-url", "--extra-index-url", "-i"] {
+            if let Some(url) = spec.strip_prefix(flag) {
+                self.indexes.push(url.trim_start_matches('=').trim().to_string());
+                return;
+            }
+        }
+        if spec.starts_with('-') {
+            return;
+        }
+        let name = requirement_name(spec);
+        if !name.is_empty() {
+            self.specs.entry(name).or_default().push((canonical(spec), source.to_string()));
+        }
+    }
+
+    /// Everything the repository declares about its dependencies
+    pub fn collect(repo_path: &Path) -> Self {
+        let mut local = Self::default();
+        let mut files: Vec<_> = [repo_path.to_path_buf(), repo_path.join("requirements")]
+            .iter()
+            .flat_map(|dir| fs::read_dir(dir).into_iter().flatten().flatten())
+            .map(|e| e.path())
+            .filter(|p| p.extension().is_some_and(|e| e == "txt"))
+            .filter(|p| p.file_name().is_some_and(|n| n.to_string_lossy().starts_with("requirements")))
+            // Written by the installer itself, possibly from an earlier server plan
+            .filter(|p| !p.ends_with("requirements_pyp.txt") && !p.ends_with(lockfile::EXPORT_FILE))
+            .collect();
+        files.sort();
+        for file in files {
+            let source = file.strip_prefix(repo_path).unwrap_or(&file).display().to_string();
+            for line in fs::read_to_string(&file).unwrap_or_default().lines() {
+                local.add(line, &source);
+            }
+        }
+
+        let pyproject = fs::read_to_string(repo_path.join("pyproject.toml")).ok().and_then(|c| c.parse::<toml::Value>().ok());
+        let deps = pyproject.as_ref().and_then(|p| p.get("project")).and_then(|p| p.get("dependencies")).and_then(|d| d.as_array());
+        for dep in deps.into_iter().flatten().filter_map(|d| d.as_str()) {
+            local.add(dep, "pyproject.toml");
+        }
+        if let Some(tool) = lockfile::detect(repo_path) {
+            for line in lockfile::locked_requirements(repo_path, tool, &lockfile::requested_extras(repo_path)).unwrap_or_default() {
+                local.add(&line, tool.lock_file());
+            }
+        }
+        local
+    }
+}
+
+/// Requirement with whitespace removed and the name normalized, for comparison
+fn canonical(spec: &str) -> String {
+    let spec: String = spec.chars().filter(|c| !c.is_whitespace()).collect();
+    let name = requirement_name(&spec);
+    let name_len = spec.find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))).unwrap_or(spec.len());
+    format!("{}{}", name, &spec[name_len..])
+}
+
+/// Installs from a URL or VCS instead of an index
+fn is_direct_reference(spec: &str) -> bool {
+    spec.contains('@') || spec.contains("://")
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin {
+    /// Declared identically by the repository (file named)
+    Repository(String),
+    /// The repository uses the package with another specifier
+    VersionDiffers(String),
+    NotInRepository,
+    /// Step type the installer does not execute
+    Ignored,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReviewItem {
+    pub step: usize,
+    pub step_type: String,
+    pub spec: String,
+    pub origin: Origin,
+}
+
+impl ReviewItem {
+    /// Installing this follows from the repository itself
+    pub fn derivable(&self) -> bool {
+        matches!(self.origin, Origin::Repository(_) | Origin::Ignored)
+    }
+
+    pub fn render(&self) -> String {
+        match &self.origin {
+            Origin::Repository(source) => format!("  = {} (as in {})", self.spec, source),
+            Origin::VersionDiffers(local) if !is_direct_reference(&self.spec) => format!("  ~ {} (repository: {})", self.spec, local),
+            Origin::VersionDiffers(local) => format!("  + {} (repository: {})", self.spec, local),
+            Origin::NotInRepository => format!("  + {} (not in repository)", self.spec),
+            Origin::Ignored => format!("    {} (ignored)", self.spec),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PlanReview {
+    pub items: Vec<ReviewItem>,
+}
+
+impl PlanReview {
+    pub fn needs_confirmation(&self) -> bool {
+        self.items.iter().any(|i| !i.derivable())
+    }
+
+    /// Diff lines grouped by plan step
+    pub fn render(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut current = None;
+        for item in &self.items {
+            if current != Some(item.step) {
+                current = Some(item.step);
+                lines.push(format!("Step {}: {}", item.step + 1, item.step_type));
+            }
+            lines.push(item.render());
+        }
+        lines
+    }
+}
+
+fn review_package(local: &LocalRequirements, spec: &str) -> Origin {
+    let Some(declared) = local.specs.get(&requirement_name(spec)) else { return Origin::NotInRepository };
+    let wanted = canonical(spec);
+    match declared.iter().find(|(s, _)| *s == wanted) {
+        Some((_, source)) => Origin::Repository(source.clone()),
+        None => Origin::VersionDiffers(declared.iter().map(|(s, _)| s.as_str()).collect::<Vec<_>>().join(", ")),
+    }
+}
+
+fn review_index(local: &LocalRequirements, url: &str) -> Origin {
+    let host = url::Url::parse(url).ok().and_then(|u| u.host_str().map(str::to_string));
+    if host.as_deref() == Some(PYTORCH_INDEX_HOST) {
+        Origin::Repository("default torch index".into())
+    } else if local.indexes.iter().any(|i| i.trim_end_matches('/') == url.trim_end_matches('/')) {
+        Origin::Repository("requirements".into())
+    } else {
+        Origin::NotInRepository
+    }
+}
+
+/// Compare every step of a server plan with what the repository declares
+pub fn review_plan(plan: &JsonValue, repo_path: &Path) -> PlanReview {
+    let local = LocalRequirements::collect(repo_path);
+    let mut items = Vec::new();
+    let steps = plan.get("steps").and_then(|s| s.as_array()).cloned().unwrap_or_default();
+    for (index, step) in steps.iter().enumerate() {
+        let step_type = step.get("type").and_then(|s| s.as_str()).unwrap_or("").to_string();
+        let mut push = |spec: String, origin: Origin| items.push(ReviewItem { step: index, step_type: step_type.clone(), spec, origin });
+        match step_type.as_str() {
+            "requirements" => {
+                let Some(path) = step.get("path").and_then(|s| s.as_str()) else { continue };
+                let inside = Path::new(path).components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+                let origin = if inside && repo_path.join(path).is_file() { Origin::Repository(path.to_string()) } else { Origin::NotInRepository };
+                push(format!("-r {}", path), origin);
+            }
+            "pip_install" | "regular" | "regular_only" => {
+                for spec in step.get("packages").and_then(|p| p.as_array()).into_iter().flatten().filter_map(|p| p.as_str()) {
+                    push(spec.to_string(), review_package(&local, spec));
+                }
+                if let Some(url) = step.get("torch_index_url").and_then(|s| s.as_str()) {
+                    push(format!("--index-url {}", url), review_index(&local, url));
+                }
+            }
+            other => push(format!("<{}>", other), Origin::Ignored),
+        }
+    }
+    PlanReview { items }
+}
+
+/// Show the review and ask before running steps that do not come from the repository
+pub fn confirm(review: &PlanReview, repo_name: &str) -> bool {
+    use std::io::{self, IsTerminal};
+    crate::output::step(&format!("Server installation plan for '{}':", repo_name));
+    for line in review.render() {
+        println!("{}", line);
+    }
+    if !review.needs_confirmation() {
+        crate::output::success("Every step of the plan follows from the repository's own files");
+        return true;
+    }
+    if !io::stdin().is_terminal() {
+        crate::output::warn("The plan installs packages or versions the repository does not declare; not confirmed in a non-interactive session");
+        return false;
+    }
+    crate::prompt::confirm("The plan installs items marked '~' or '+' that the repository does not declare. Run it?")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plan_is_diffed_against_repository_requirements() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = dir.path();
+        fs::write(repo.join("requirements.txt"), "Pillow >= 10.0\nnumpy==1.26.4  # pinned\n--extra-index-url https://pypi.example.org/simple\n").unwrap();
+        fs::write(repo.join("pyproject.toml"), "[project]\nname = \"app\"\ndependencies = [\"gradio==4.44.1\"]\n").unwrap();
+
+        let plan = serde_json::json!({ "steps": [
+            { "type": "requirements", "path": "requirements.txt" },
+            { "type": "pip_install", "packages": ["pillow>=10.0", "numpy==2.1.0", "gradio==4.44.1", "cryptominer==1.0", "numpy @ https://evil.example/numpy.whl"],
+              "torch_index_url": "https://download.pytorch.org/whl/cu124" },
+            { "type": "requirements", "path": "../outside.txt" },
+            { "type": "shell" },
+        ]});
+        let review = review_plan(&plan, repo);
+        let origins: Vec<_> = review.items.iter().map(|i| (i.spec.as_str(), i.derivable())).collect();
+        assert_eq!(
+            origins,
+            [
+                ("-r requirements.txt", true),
+                ("pillow>=10.0", true),
+                ("numpy==2.1.0", false),
+                ("gradio==4.44.1", true),
+                ("cryptominer==1.0", false),
+                ("numpy @ https://evil.example/numpy.whl", false),
+                ("--index-url https://download.pytorch.org/whl/cu124", true),
+                ("-r ../outside.txt", false),
+                ("<shell>", true),
+            ]
+        );
+        assert!(review.needs_confirmation());
+        let version_only = review_plan(&serde_json::json!({ "steps": [{ "type": "pip_install", "packages": ["numpy==2.1.0"] }] }), repo);
+        assert!(version_only.needs_confirmation());
+        let lines = review.render();
+        assert_eq!(lines[0], "Step 1: requirements");
+        assert_eq!(lines[4], "  ~ numpy==2.1.0 (repository: numpy==1.26.4)");
+        assert_eq!(lines[5], "  = gradio==4.44.1 (as in pyproject.toml)");
+        assert_eq!(lines[6], "  + cryptominer==1.0 (not in repository)");
+    }
+}
@@ -2,11 +2,17 @@
 
 use crate::installer::{PipManager, MainFileFinder};
 use crate::config::ConfigManager;
+use crate::performance::{Hardware, Tuning};
+use crate::repo_state;
+use crate::run_queue::RepoRunSettings;
 use crate::Result;
-use log::{info, warn};
-use std::path::{Path, PathBuf};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
 use std::fs;
-use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Hash of the start script portablesource last wrote, to tell user edits from stale templates
+pub const SCRIPT_HASH_FILE: &str = ".portablesource_script_hash";
 
 #[derive(Debug, Clone)]
 pub struct RepositoryInfo {
@@ -15,6 +21,414 @@ pub struct RepositoryInfo {
     pub program_args: Option<String>,
 }
 
+/// What the start script launches
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LaunchTarget {
+    /// `python <main_file> <args>`
+    MainFile(String),
+    /// `python -m <module> <args>` (pyproject.toml scripts)
+    Module(String),
+    /// Plain interpreter, no entry point found
+    Interpreter,
+}
+
+/// CUDA locations exported by Unix scripts (Windows scripts use the portable ps_env\CUDA layout)
+#[derive(Debug, Clone, Default)]
+pub struct CudaPaths {
+    pub base: PathBuf,
+    pub bin: PathBuf,
+    pub lib: PathBuf,
+    pub lib64: PathBuf,
+}
+
+/// Folders a start script puts in front of PATH
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathDir {
+    /// CUDA toolkit: [`WINDOWS_CUDA_DIRS`] on Windows, `bin` on Linux
+    Cuda,
+    /// DLL folders of the native library components (Windows)
+    Components,
+    /// Repository venv: its root on Windows, `bin` through `activate` on Linux
+    Venv,
+    /// `Scripts` of the repository venv (Windows)
+    VenvScripts,
+    /// Portable git, or the system git chosen with `--use-system-tools` (Windows)
+    Git,
+    /// Portable ffmpeg (Windows)
+    Ffmpeg,
+    /// `bin` of the shared environment used instead of the venv (`--no-venv`, Linux)
+    SharedEnv,
+    /// `bin` of the micromamba base in DESK mode (Linux)
+    MambaBase,
+}
+
+/// What the Windows start script prepends to PATH, in that order: the last is searched first
+pub const WINDOWS_PATH_ORDER: [PathDir; 6] =
+    [PathDir::Cuda, PathDir::Components, PathDir::Venv, PathDir::VenvScripts, PathDir::Git, PathDir::Ffmpeg];
+
+/// What the Linux start script prepends to PATH, in that order
+pub const UNIX_PATH_ORDER: [PathDir; 4] = [PathDir::MambaBase, PathDir::Venv, PathDir::SharedEnv, PathDir::Cuda];
+
+/// Batch variable and CUDA toolkit subfolder of each CUDA folder the Windows start script
+/// prepends, in that order
+pub const WINDOWS_CUDA_DIRS: [(&str, &str); 7] = [
+    ("cuda_bin", "bin"),
+    ("cuda_lib", "lib"),
+    ("cuda_lib_64", "lib\\x64"),
+    ("cuda_nvml_bin", "nvml\\bin"),
+    ("cuda_nvml_lib", "nvml\\lib"),
+    ("cuda_nvvm_bin", "nvvm\\bin"),
+    ("cuda_nvvm_lib", "nvvm\\lib"),
+];
+
+/// Everything a start script depends on. Rendering from it has no side effects.
+#[derive(Debug, Clone)]
+pub struct ScriptContext {
+    /// Lowercased repository name (script and venv name)
+    pub repo_name: String,
+    /// Repository folder name as on disk
+    pub repo_dir_name: String,
+    pub install_path: PathBuf,
+    pub repo_path: PathBuf,
+    pub target: LaunchTarget,
+    pub program_args: String,
+    pub cuda: Option<CudaPaths>,
+    /// Windows: mount install dir as X: (long/space/non-ASCII paths)
+    pub virtual_drive: bool,
+    /// Linux portable mode: resolve paths relative to the script location
+    pub portable: bool,
+    /// Performance profile variables, if the repo has a profile
+    pub tuning: Option<Tuning>,
+    /// Variables set unconditionally, e.g. a port assigned to avoid a clash
+    pub launch_env: Vec<(&'static str, String)>,
+    /// Windows: folder of the system git recorded by `--use-system-tools`
+    pub system_git: Option<PathBuf>,
+    /// Windows: PATH entries of the repository's native library components
+    pub components: Vec<String>,
+    /// Linux: prefix of the shared environment used instead of the venv (`--no-venv`)
+    pub shared_prefix: Option<PathBuf>,
+}
+
+impl ScriptContext {
+    /// File name of the start script for the current platform
+    pub fn script_file_name(&self) -> String {
+        script_file_name(&self.repo_name)
+    }
+}
+
+/// File name of the start script of the repository folder `repo_dir_name` for the current
+/// platform; the name is lowercased like the script's repository name
+pub fn script_file_name(repo_dir_name: &str) -> String {
+    let ext = if cfg!(windows) { "bat" } else { "sh" };
+    format!("start_{}.{}", repo_dir_name.to_lowercase(), ext)
+}
+
+/// Render start script for the current platform
+pub fn render_script(ctx: &ScriptContext) -> String {
+    if cfg!(windows) {
+        render_windows_script(ctx)
+    } else {
+        render_unix_script(ctx)
+    }
+}
+
+/// Render Windows batch script
+pub fn render_windows_script(ctx: &ScriptContext) -> String {
+    let repo_name = &ctx.repo_name;
+
+    let mut path_section = String::new();
+    for dir in WINDOWS_PATH_ORDER {
+        match dir {
+            PathDir::Cuda if ctx.cuda.is_some() => {
+                for (var, sub) in WINDOWS_CUDA_DIRS {
+                    path_section.push_str(&format!("set {}=%env_path%\\CUDA\\{}\n", var, sub));
+                }
+                path_section.push('\n');
+                for (var, _) in WINDOWS_CUDA_DIRS {
+                    path_section.push_str(&format!("set PATH=%{}%;%PATH%\n", var));
+                }
+            }
+            PathDir::Cuda => path_section.push_str("REM No CUDA paths configured\n"),
+            PathDir::Components if !ctx.components.is_empty() => {
+                path_section.push_str("REM === COMPONENTS ===\n");
+                for path in &ctx.components {
+                    path_section.push_str(&format!("set PATH={};%PATH%\n", path));
+                }
+            }
+            PathDir::Components => {}
+            PathDir::Venv => path_section.push_str("\nset PATH=%python_path%;%PATH%\n"),
+            PathDir::VenvScripts => path_section.push_str("set PATH=%python_path%\\Scripts;%PATH%\n"),
+            PathDir::Git => path_section.push_str("set PATH=%git_path%;%PATH%\n"),
+            PathDir::Ffmpeg => path_section.push_str("set PATH=%ffmpeg_path%;%PATH%\n"),
+            PathDir::SharedEnv | PathDir::MambaBase => {}
+        }
+    }
+
+    let base_content = if ctx.virtual_drive {
+        // Use virtual drive for complex paths
+        "@echo off\n".to_string() + &format!(
+            "echo Launch {}...\n\nREM Check if X: drive exists and unmount it\nif exist X:\\ (\n    echo Unmounting existing X: drive...\n    subst X: /D >nul 2>&1\n)\n\nset \"ROOT_PATH=%~dp0\\..\\..\\\"\nsubst X: \"%ROOT_PATH%\"\nX:\n\nset base_path=X:\nset env_path=%base_path%\\ps_env\nset envs_path=%base_path%\\envs\nset repos_path=%base_path%\\repos\nset ffmpeg_path=%env_path%\\ffmpeg\nset git_path=%env_path%\\git\\bin\nset python_path=%envs_path%\\{}\nset python_exe=%python_path%\\python.exe\nset repo_path=%repos_path%\\{}\n\nset tmp_path=%base_path%\\tmp\nif defined PORTABLESOURCE_TMP set \"tmp_path=%PORTABLESOURCE_TMP%\"\nset USERPROFILE=%tmp_path%\nset TEMP=%tmp_path%\\Temp\nset TMP=%tmp_path%\\Temp\nset APPDATA=%tmp_path%\\AppData\\Roaming\nset LOCALAPPDATA=%tmp_path%\\AppData\\Local\nset HF_HOME=%repo_path%\\huggingface_home\nset XDG_CACHE_HOME=%tmp_path%\nset HF_DATASETS_CACHE=%HF_HOME%\\datasets\n\nset PYTHONIOENCODING=utf-8\nset PYTHONUNBUFFERED=1\nset PYTHONDONTWRITEBYTECODE=1\n\nREM === CUDA PATHS ===\n{}\ncd /d \"%repo_path%\"\n",
+            repo_name,
+            repo_name,
+            repo_name,
+            path_section,
+        )
+    } else {
+        // Use direct paths for simple paths
+        let install_path_str = ctx.install_path.to_string_lossy().replace('\\', "\\\\");
+        "@echo off\n".to_string() + &format!(
+            "echo Launch {}...\n\nset base_path={}\nset env_path=%base_path%\\ps_env\nset envs_path=%base_path%\\envs\nset repos_path=%base_path%\\repos\nset ffmpeg_path=%env_path%\\ffmpeg\nset git_path=%env_path%\\git\\bin\nset python_path=%envs_path%\\{}\nset python_exe=%python_path%\\python.exe\nset repo_path=%repos_path%\\{}\n\nset tmp_path=%base_path%\\tmp\nif defined PORTABLESOURCE_TMP set \"tmp_path=%PORTABLESOURCE_TMP%\"\nset USERPROFILE=%tmp_path%\nset TEMP=%tmp_path%\\Temp\nset TMP=%tmp_path%\\Temp\nset APPDATA=%tmp_path%\\AppData\\Roaming\nset LOCALAPPDATA=%tmp_path%\\AppData\\Local\nset HF_HOME=%repo_path%\\huggingface_home\nset XDG_CACHE_HOME=%tmp_path%\nset HF_DATASETS_CACHE=%HF_HOME%\\datasets\n\nset PYTHONIOENCODING=utf-8\nset PYTHONUNBUFFERED=1\nset PYTHONDONTWRITEBYTECODE=1\n\nREM === CUDA PATHS ===\n{}\ncd /d \"%repo_path%\"\n",
+            repo_name,
+            install_path_str,
+            repo_name,
+            repo_name,
+            path_section,
+        )
+    };
+    // System git chosen with --use-system-tools lives outside ps_env
+    let base_content = match &ctx.system_git {
+        Some(dir) => base_content.replace("set git_path=%env_path%\\git\\bin\n", &format!("set git_path={}\n", dir.display())),
+        None => base_content,
+    };
+
+    let run_line = match &ctx.target {
+        LaunchTarget::MainFile(main_file) => format!("\"%python_exe%\" {} {}\n", main_file, ctx.program_args),
+        LaunchTarget::Module(module) => format!("\"%python_exe%\" -m {} {}\n", module, ctx.program_args),
+        LaunchTarget::Interpreter => "\"%python_exe%\"\n".to_string(),
+    };
+    let cleanup = if ctx.virtual_drive { "echo Cleaning up...\nsubst X: /D\n\n" } else { "" };
+
+    let mut tuning_section = String::new();
+    if let Some(tuning) = &ctx.tuning {
+        tuning_section.push_str(&format!("REM === PERFORMANCE PROFILE: {} ===\n", tuning.profile));
+        for (name, value) in &tuning.env {
+            tuning_section.push_str(&format!("if not defined {0} set \"{0}={1}\"\n", name, value));
+        }
+        tuning_section.push('\n');
+    }
+    if !ctx.launch_env.is_empty() {
+        tuning_section.push_str("REM === ASSIGNED RESOURCES ===\n");
+        for (name, value) in &ctx.launch_env {
+            tuning_section.push_str(&format!("set \"{}={}\"\n", name, value));
+        }
+        tuning_section.push('\n');
+    }
+
+    base_content
+        + &tuning_section
+        + &run_line
+        + "set EXIT_CODE=%ERRORLEVEL%\n\n"
+        + cleanup
+        + "if %EXIT_CODE% neq 0 (\n    echo.\n    echo Program finished with error (code: %EXIT_CODE%)\n) else (\n    echo.\n    echo Program finished successfully\n)\n\npause\n"
+}
+
+/// Render Unix shell script
+pub fn render_unix_script(ctx: &ScriptContext) -> String {
+    let mut path_section = String::new();
+    for dir in UNIX_PATH_ORDER {
+        match dir {
+            PathDir::MambaBase => path_section.push_str("# prepend micromamba base bin to PATH (no activation) in DESK mode\nif [[ \"$MODE\" == \"desk\" ]]; then\n  export PATH=\"$BASE_PREFIX/bin:$PATH\"\nfi\n\n"),
+            PathDir::Venv => path_section.push_str("# activate project venv if present (be tolerant to unset vars)\nif [[ -f \"$VENV/bin/activate\" ]]; then\n  set +u\n  source \"$VENV/bin/activate\" || true\n  set -u\nfi\n\n"),
+            PathDir::SharedEnv => {
+                if let Some(prefix) = &ctx.shared_prefix {
+                    path_section.push_str(&format!(
+                        "# Shared environment (installed with --no-venv)\nexport PATH=\"{0}/bin:$PATH\"\nPYEXE=\"{0}/bin/python\"\n",
+                        prefix.display()
+                    ));
+                }
+            }
+            PathDir::Cuda => {
+                if let Some(cuda) = &ctx.cuda {
+                    let base = cuda.base.to_string_lossy();
+                    path_section.push_str(&format!("export CUDA_PATH=\"{}\"\n", base));
+                    path_section.push_str(&format!("export CUDA_HOME=\"{}\"\n", base));
+                    path_section.push_str(&format!("export CUDA_ROOT=\"{}\"\n", base));
+                    path_section.push_str(&format!("export PATH=\"{}:$PATH\"\n", cuda.bin.to_string_lossy()));
+                    // Use default expansion for unset variable due to 'set -u'
+                    path_section.push_str(&format!(
+                        "export LD_LIBRARY_PATH=\"{}:{}:${{LD_LIBRARY_PATH:-}}\"\n",
+                        cuda.lib.to_string_lossy(),
+                        cuda.lib64.to_string_lossy()
+                    ));
+                }
+            }
+            PathDir::Components | PathDir::VenvScripts | PathDir::Git | PathDir::Ffmpeg => {}
+        }
+    }
+
+    // Portable mode: resolve paths relative to the script so the install dir can move (USB drives),
+    // and keep HOME/XDG state inside install dir
+    let (install_decl, repo_decl) = if ctx.portable {
+        (
+            "$(cd \"$(dirname \"${BASH_SOURCE[0]}\")/../..\" && pwd)".to_string(),
+            format!("$INSTALL/repos/{}", ctx.repo_dir_name),
+        )
+    } else {
+        (ctx.install_path.to_string_lossy().to_string(), ctx.repo_path.to_string_lossy().to_string())
+    };
+    let mut portable_exports = String::new();
+    if ctx.portable {
+        // PORTABLESOURCE_TMP: per-user folder when run-repo launches from a read-only install
+        portable_exports.push_str("TMP_ROOT=\"${PORTABLESOURCE_TMP:-$INSTALL/tmp}\"\n");
+        for (name, value) in portable_home_exports() {
+            portable_exports.push_str(&format!("export {}=\"{}\"\nmkdir -p \"${}\"\n", name, value, name));
+        }
+    }
+
+    // Generate base script content without execution command
+    let base_content = format!("#!/usr/bin/env bash\nset -Eeuo pipefail\n\nINSTALL=\"{}\"\nENV_PATH=\"$INSTALL/ps_env\"\nBASE_PREFIX=\"$ENV_PATH/mamba_env\"\nREPO_PATH=\"{}\"\nVENV=\"$INSTALL/envs/{}\"\nPYEXE=\"$VENV/bin/python\"\n\n# Detect mode: allow override via PORTABLESOURCE_MODE\nMODE=\"${{PORTABLESOURCE_MODE:-}}\"\nif [[ -z \"$MODE\" ]]; then\n  if command -v git >/dev/null 2>&1 && command -v python3 >/dev/null 2>&1 && command -v ffmpeg >/dev/null 2>&1; then\n    MODE=cloud\n  else\n    MODE=desk\n  fi\nfi\n\n{}{}\ncd \"$REPO_PATH\"\n",
+        install_decl,
+        repo_decl,
+        ctx.repo_name,
+        path_section,
+        portable_exports,
+    );
+
+    let mut tuning_exports = String::new();
+    if let Some(tuning) = &ctx.tuning {
+        tuning_exports.push_str(&format!("# Performance profile: {}\n", tuning.profile));
+        for (name, value) in &tuning.env {
+            tuning_exports.push_str(&format!("export {0}=\"${{{0}:-{1}}}\"\n", name, value));
+        }
+        tuning_exports.push('\n');
+    }
+    if !ctx.launch_env.is_empty() {
+        tuning_exports.push_str("# Assigned resources\n");
+        for (name, value) in &ctx.launch_env {
+            tuning_exports.push_str(&format!("export {}=\"{}\"\n", name, value));
+        }
+        tuning_exports.push('\n');
+    }
+
+    let program_args = &ctx.program_args;
+    let run = match &ctx.target {
+        LaunchTarget::MainFile(main_file) => format!(
+            "if [[ -x \"$PYEXE\" ]]; then\n  exec \"$PYEXE\" \"{}\" {}\nelse\n  exec python3 \"{}\" {}\nfi\n",
+            main_file,
+            program_args,
+            main_file,
+            program_args,
+        ),
+        LaunchTarget::Module(module_path) => format!(
+            "if [[ -x \"$PYEXE\" ]]; then\n  exec \"$PYEXE\" -m {} {}\nelse\n  exec python3 -m {} {}\nfi\n",
+            module_path,
+            program_args,
+            module_path,
+            program_args,
+        ),
+        LaunchTarget::Interpreter => "if [[ -x \"$PYEXE\" ]]; then\n  exec \"$PYEXE\"\nelse\n  exec python3\nfi\n".to_string(),
+    };
+    base_content + &tuning_exports + &run
+}
+
+/// HOME/XDG overrides written into portable Unix scripts (relative to $TMP_ROOT)
+fn portable_home_exports() -> Vec<(&'static str, &'static str)> {
+    vec![
+        ("HOME", "$TMP_ROOT/home"),
+        ("XDG_CACHE_HOME", "$TMP_ROOT/cache"),
+        ("XDG_CONFIG_HOME", "$TMP_ROOT/config"),
+        ("XDG_DATA_HOME", "$TMP_ROOT/data"),
+        ("TMPDIR", "$TMP_ROOT/tmp"),
+    ]
+}
+
+fn script_hash(content: &[u8]) -> String {
+    Sha256::digest(content).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Whether `content` is the start script portablesource last wrote for `repo_path`
+pub fn is_generated(repo_path: &Path, content: &[u8]) -> bool {
+    repo_state::read(repo_path, SCRIPT_HASH_FILE).is_some_and(|hash| hash.trim() == script_hash(content))
+}
+
+/// Remember `content` as the start script portablesource wrote, after it changed the script
+/// itself (a relocating restore)
+pub fn record_script_hash(repo_path: &Path, content: &[u8]) -> Result<()> {
+    repo_state::write(repo_path, SCRIPT_HASH_FILE, script_hash(content)).map(drop)
+}
+
+/// Write a generated start script and remember its hash
+pub fn write_script_file(repo_path: &Path, file_name: &str, content: &str) -> Result<()> {
+    crate::atomic_write::write_executable(repo_path.join(file_name), content)?;
+    record_script_hash(repo_path, content.as_bytes())
+}
+
+/// What `regenerate_script` did with one start script
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegenOutcome {
+    Unchanged,
+    Created,
+    Updated,
+    /// Edited by the user: left alone, the new script written next to it
+    KeptEdited(PathBuf),
+    /// Edited by the user and replaced (`--force`); the edited copy was kept
+    Replaced(PathBuf),
+}
+
+/// Write `content` as the start script unless the user edited the current one. A script is
+/// edited when it differs from the hash recorded at generation; scripts from releases that
+/// recorded no hash are treated as edited when they differ, so nothing is lost.
+pub fn regenerate_script(repo_path: &Path, file_name: &str, content: &str, force: bool, dry_run: bool) -> Result<RegenOutcome> {
+    let path = repo_path.join(file_name);
+    let current = match fs::read(&path) {
+        Ok(current) => current,
+        Err(_) => {
+            if !dry_run {
+                write_script_file(repo_path, file_name, content)?;
+            }
+            return Ok(RegenOutcome::Created);
+        }
+    };
+    if current == content.as_bytes() {
+        if !dry_run {
+            repo_state::write(repo_path, SCRIPT_HASH_FILE, script_hash(&current))?;
+        }
+        return Ok(RegenOutcome::Unchanged);
+    }
+    let recorded = repo_state::read(repo_path, SCRIPT_HASH_FILE);
+    if recorded.as_deref().map(str::trim) == Some(script_hash(&current).as_str()) {
+        if !dry_run {
+            write_script_file(repo_path, file_name, content)?;
+        }
+        return Ok(RegenOutcome::Updated);
+    }
+    if force {
+        let backup = repo_path.join(format!("{}.bak", file_name));
+        if !dry_run {
+            fs::write(&backup, &current)?;
+            write_script_file(repo_path, file_name, content)?;
+        }
+        return Ok(RegenOutcome::Replaced(backup));
+    }
+    let new = repo_path.join(format!("{}.new", file_name));
+    if !dry_run {
+        crate::atomic_write::write_executable(&new, content)?;
+    }
+    Ok(RegenOutcome::KeptEdited(new))
+}
+
+/// Check if virtual drive is needed based on path characteristics
+pub fn needs_virtual_drive(base_path: &Path) -> bool {
+    let path_str = base_path.to_string_lossy();
+    
+    // Check path length > 150 characters
+    if path_str.len() > 150 {
+        return true;
+    }
+    
+    // Check for spaces in path
+    if path_str.contains(' ') {
+        return true;
+    }
+    
+    // Check for non-ASCII characters (includes Russian text)
+    if !path_str.is_ascii() {
+        return true;
+    }
+    
+    false
+}
+
 pub struct ScriptGenerator<'a> {
     pip_manager: &'a PipManager<'a>,
     config_manager: &'a ConfigManager,
@@ -39,254 +453,259 @@ impl<'a> ScriptGenerator<'a> {
 
     /// Generate startup script for the repository (platform-specific)
     pub fn generate_startup_script(&self, repo_path: &Path, repo_info: &RepositoryInfo) -> Result<bool> {
-        if cfg!(windows) {
-            self.generate_startup_script_windows(repo_path, repo_info)
-        } else {
-            self.generate_startup_script_unix(repo_path, repo_info)
-        }
+        let ctx = self.build_context(repo_path, repo_info)?;
+        self.write_script(&ctx)?;
+        Ok(true)
+    }
+
+    /// Render script from context and write it into the repository folder
+    pub fn write_script(&self, ctx: &ScriptContext) -> Result<String> {
+        let content = render_script(ctx);
+        write_script_file(&ctx.repo_path, &ctx.script_file_name(), &content)?;
+        Ok(content)
     }
 
-    /// Generate Windows batch script
-    fn generate_startup_script_windows(&self, repo_path: &Path, repo_info: &RepositoryInfo) -> Result<bool> {
-        let repo_name = repo_path.file_name().and_then(|s| s.to_str()).unwrap_or("").to_lowercase();
+    /// Collect main file, CUDA and path settings for the repository script
+    pub fn build_context(&self, repo_path: &Path, repo_info: &RepositoryInfo) -> Result<ScriptContext> {
+        let repo_dir_name = repo_path.file_name().and_then(|s| s.to_str()).unwrap_or("").to_string();
+        let repo_name = repo_dir_name.to_lowercase();
         
         let mut main_file = repo_info.main_file.clone();
         if main_file.is_none() { 
-            main_file = self.main_file_finder.find_main_file(&repo_name, repo_path, repo_info.url.as_deref()); 
+            let upstream = crate::repo_metadata::upstream_name(repo_path);
+            main_file = self.main_file_finder.find_main_file(&upstream, repo_path, repo_info.url.as_deref()); 
         }
         
         // Check for pyproject.toml scripts if main_file is not found
-        let pyproject_path = repo_path.join("pyproject.toml");
-        let (has_pyproject_scripts, script_module) = if main_file.is_none() && pyproject_path.exists() {
+        let target = if let Some(main_file) = main_file {
+            LaunchTarget::MainFile(main_file)
+        } else if repo_path.join("pyproject.toml").exists() {
             info!("Main file not found, checking pyproject.toml for scripts");
-            self.check_scripts_in_pyproject(repo_path)?
-        } else {
-            (false, None)
-        };
-
-        let bat_file = repo_path.join(format!("start_{}.bat", repo_name));
-        let program_args = repo_info.program_args.clone().unwrap_or_default();
-
-        // CUDA PATH section if configured
-        let cuda_section = if self.config_manager.has_cuda() {
-            format!(
-                "set cuda_bin=%env_path%\\CUDA\\bin\nset cuda_lib=%env_path%\\CUDA\\lib\nset cuda_lib_64=%env_path%\\CUDA\\lib\\x64\nset cuda_nvml_bin=%env_path%\\CUDA\\nvml\\bin\nset cuda_nvml_lib=%env_path%\\CUDA\\nvml\\lib\nset cuda_nvvm_bin=%env_path%\\CUDA\\nvvm\\bin\nset cuda_nvvm_lib=%env_path%\\CUDA\\nvvm\\lib\n\nset PATH=%cuda_bin%;%PATH%\nset PATH=%cuda_lib%;%PATH%\nset PATH=%cuda_lib_64%;%PATH%\nset PATH=%cuda_nvml_bin%;%PATH%\nset PATH=%cuda_nvml_lib%;%PATH%\nset PATH=%cuda_nvvm_bin%;%PATH%\nset PATH=%cuda_nvvm_lib%;%PATH%\n"
-            )
-        } else { 
-            "REM No CUDA paths configured".into() 
-        };
-        
-        // Generate base script content without execution command
-        let use_virtual_drive = self.needs_virtual_drive(&self.install_path);
-        
-        let base_content = if use_virtual_drive {
-            // Use virtual drive for complex paths
-            format!("@echo off\n") + &format!(
-                "echo Launch {}...\n\nREM Check if X: drive exists and unmount it\nif exist X:\\ (\n    echo Unmounting existing X: drive...\n    subst X: /D >nul 2>&1\n)\n\nset \"ROOT_PATH=%~dp0\\..\\..\\\"\nsubst X: \"%ROOT_PATH%\"\nX:\n\nset base_path=X:\nset env_path=%base_path%\\ps_env\nset envs_path=%base_path%\\envs\nset repos_path=%base_path%\\repos\nset ffmpeg_path=%env_path%\\ffmpeg\nset git_path=%env_path%\\git\\bin\nset python_path=%envs_path%\\{}\nset python_exe=%python_path%\\python.exe\nset repo_path=%repos_path%\\{}\n\nset tmp_path=%base_path%\\tmp\nset USERPROFILE=%tmp_path%\nset TEMP=%tmp_path%\\Temp\nset TMP=%tmp_path%\\Temp\nset APPDATA=%tmp_path%\\AppData\\Roaming\nset LOCALAPPDATA=%tmp_path%\\AppData\\Local\nset HF_HOME=%repo_path%\\huggingface_home\nset XDG_CACHE_HOME=%tmp_path%\nset HF_DATASETS_CACHE=%HF_HOME%\\datasets\n\nset PYTHONIOENCODING=utf-8\nset PYTHONUNBUFFERED=1\nset PYTHONDONTWRITEBYTECODE=1\n\nREM === CUDA PATHS ===\n{}\nset PATH=%python_path%;%PATH%\nset PATH=%python_path%\\Scripts;%PATH%\nset PATH=%git_path%;%PATH%\nset PATH=%ffmpeg_path%;%PATH%\n\ncd /d \"%repo_path%\"\n",
-                repo_name,
-                repo_name,
-                repo_name,
-                cuda_section,
-            )
-        } else {
-            // Use direct paths for simple paths
-            let install_path_str = self.install_path.to_string_lossy().replace('\\', "\\\\");
-            format!("@echo off\n") + &format!(
-                "echo Launch {}...\n\nset base_path={}\nset env_path=%base_path%\\ps_env\nset envs_path=%base_path%\\envs\nset repos_path=%base_path%\\repos\nset ffmpeg_path=%env_path%\\ffmpeg\nset git_path=%env_path%\\git\\bin\nset python_path=%envs_path%\\{}\nset python_exe=%python_path%\\python.exe\nset repo_path=%repos_path%\\{}\n\nset tmp_path=%base_path%\\tmp\nset USERPROFILE=%tmp_path%\nset TEMP=%tmp_path%\\Temp\nset TMP=%tmp_path%\\Temp\nset APPDATA=%tmp_path%\\AppData\\Roaming\nset LOCALAPPDATA=%tmp_path%\\AppData\\Local\nset HF_HOME=%repo_path%\\huggingface_home\nset XDG_CACHE_HOME=%tmp_path%\nset HF_DATASETS_CACHE=%HF_HOME%\\datasets\n\nset PYTHONIOENCODING=utf-8\nset PYTHONUNBUFFERED=1\nset PYTHONDONTWRITEBYTECODE=1\n\nREM === CUDA PATHS ===\n{}\nset PATH=%python_path%;%PATH%\nset PATH=%python_path%\\Scripts;%PATH%\nset PATH=%git_path%;%PATH%\nset PATH=%ffmpeg_path%;%PATH%\n\ncd /d \"%repo_path%\"\n",
-                repo_name,
-                install_path_str,
-                repo_name,
-                repo_name,
-                cuda_section,
-            )
-        };
-        
-        // Determine execution command based on available options
-        let content = if let Some(main_file_path) = main_file {
-            // Case 1: main_file found - use it
-            if use_virtual_drive {
-                base_content + &format!(
-                    "\"%python_exe%\" {} {}\nset EXIT_CODE=%ERRORLEVEL%\n\necho Cleaning up...\nsubst X: /D\n\nif %EXIT_CODE% neq 0 (\n    echo.\n    echo Program finished with error (code: %EXIT_CODE%)\n) else (\n    echo.\n    echo Program finished successfully\n)\n\npause\n",
-                    main_file_path,
-                    program_args,
-                )
-            } else {
-                base_content + &format!(
-                    "\"%python_exe%\" {} {}\nset EXIT_CODE=%ERRORLEVEL%\n\nif %EXIT_CODE% neq 0 (\n    echo.\n    echo Program finished with error (code: %EXIT_CODE%)\n) else (\n    echo.\n    echo Program finished successfully\n)\n\npause\n",
-                    main_file_path,
-                    program_args,
-                )
-            }
-        } else if has_pyproject_scripts {
-            // Case 2: no main_file but pyproject.toml has scripts
-            if let Some(module_path) = script_module {
-                info!("No main file found, using pyproject.toml script: {}", module_path);
-                if use_virtual_drive {
-                    base_content + &format!(
-                        "\"%python_exe%\" -m {} {}\nset EXIT_CODE=%ERRORLEVEL%\n\necho Cleaning up...\nsubst X: /D\n\nif %EXIT_CODE% neq 0 (\n    echo.\n    echo Program finished with error (code: %EXIT_CODE%)\n) else (\n    echo.\n    echo Program finished successfully\n)\n\npause\n",
-                        module_path,
-                        program_args,
-                    )
-                } else {
-                    base_content + &format!(
-                        "\"%python_exe%\" -m {} {}\nset EXIT_CODE=%ERRORLEVEL%\n\nif %EXIT_CODE% neq 0 (\n    echo.\n    echo Program finished with error (code: %EXIT_CODE%)\n) else (\n    echo.\n    echo Program finished successfully\n)\n\npause\n",
-                        module_path,
-                        program_args,
-                    )
+            match self.check_scripts_in_pyproject(repo_path)? {
+                (true, Some(module_path)) => {
+                    info!("Using pyproject.toml script module: {}", module_path);
+                    LaunchTarget::Module(module_path)
                 }
-            } else {
-                // Fallback case - should not happen but handle gracefully
-                warn!("No main file or valid pyproject script found, generating interactive shell");
-                if use_virtual_drive {
-                    base_content + &format!(
-                        "\"%python_exe%\"\nset EXIT_CODE=%ERRORLEVEL%\n\necho Cleaning up...\nsubst X: /D\n\nif %EXIT_CODE% neq 0 (\n    echo.\n    echo Program finished with error (code: %EXIT_CODE%)\n) else (\n    echo.\n    echo Program finished successfully\n)\n\npause\n"
-                    )
-                } else {
-                    base_content + &format!(
-                        "\"%python_exe%\"\nset EXIT_CODE=%ERRORLEVEL%\n\nif %EXIT_CODE% neq 0 (\n    echo.\n    echo Program finished with error (code: %EXIT_CODE%)\n) else (\n    echo.\n    echo Program finished successfully\n)\n\npause\n"
-                    )
+                _ => {
+                    warn!("pyproject.toml found but no suitable scripts detected");
+                    LaunchTarget::Interpreter
                 }
             }
         } else {
-            // Case 3: no main_file and no pyproject.toml - just python shell
-            warn!("No main file or pyproject.toml scripts found, generating interactive Python shell");
-            if use_virtual_drive {
-                base_content + &format!(
-                    "\"%python_exe%\"\nset EXIT_CODE=%ERRORLEVEL%\n\necho Cleaning up...\nsubst X: /D\n\nif %EXIT_CODE% neq 0 (\n    echo.\n    echo Program finished with error (code: %EXIT_CODE%)\n) else (\n    echo.\n    echo Program finished successfully\n)\n\npause\n"
-                )
-            } else {
-                base_content + &format!(
-                    "\"%python_exe%\"\nset EXIT_CODE=%ERRORLEVEL%\n\nif %EXIT_CODE% neq 0 (\n    echo.\n    echo Program finished with error (code: %EXIT_CODE%)\n) else (\n    echo.\n    echo Program finished successfully\n)\n\npause\n"
-                )
-            }
+            warn!("No main file or pyproject.toml scripts found, generating basic python launcher");
+            LaunchTarget::Interpreter
         };
-        
-        let mut f = fs::File::create(&bat_file)?;
-        f.write_all(content.as_bytes())?;
 
-        Ok(true)
-    }
-
-    /// Generate Unix shell script
-    #[cfg(unix)]
-    fn generate_startup_script_unix(&self, repo_path: &Path, repo_info: &RepositoryInfo) -> Result<bool> {
-        use std::os::unix::fs::PermissionsExt;
-        
-        let repo_name = repo_path.file_name().and_then(|s| s.to_str()).unwrap_or("").to_lowercase();
-        let mut main_file = repo_info.main_file.clone();
-        if main_file.is_none() { 
-            main_file = self.main_file_finder.find_main_file(&repo_name, repo_path, repo_info.url.as_deref()); 
-        }
-        
-        // Check for pyproject.toml scripts if main_file is not found
-        let pyproject_path = repo_path.join("pyproject.toml");
-        let (has_pyproject_scripts, script_module) = if main_file.is_none() && pyproject_path.exists() {
-            info!("Main file not found, checking pyproject.toml for scripts");
-            self.check_scripts_in_pyproject(repo_path)?
+        let cuda = if self.config_manager.has_cuda() {
+            Some(CudaPaths {
+                base: self.config_manager.get_cuda_base_path().unwrap_or_default(),
+                bin: self.config_manager.get_cuda_bin().unwrap_or_default(),
+                lib: self.config_manager.get_cuda_lib().unwrap_or_default(),
+                lib64: self.config_manager.get_cuda_lib_64().unwrap_or_default(),
+            })
         } else {
-            (false, None)
+            None
         };
 
-        let install_path = &self.install_path;
-        let sh_file = repo_path.join(format!("start_{}.sh", repo_name));
-        let program_args = repo_info.program_args.clone().unwrap_or_default();
-        
-        // CUDA PATH exports if configured (optional)
-        let mut cuda_exports = String::new();
-        if self.config_manager.has_cuda() {
-            let base_path = self.config_manager.get_cuda_base_path().unwrap_or_default();
-            let bin_path = self.config_manager.get_cuda_bin().unwrap_or_default();
-            let lib_path = self.config_manager.get_cuda_lib().unwrap_or_default();
-            let lib64_path = self.config_manager.get_cuda_lib_64().unwrap_or_default();
-            let base = base_path.to_string_lossy();
-            let bin = bin_path.to_string_lossy();
-            let lib = lib_path.to_string_lossy();
-            let lib64 = lib64_path.to_string_lossy();
-            cuda_exports.push_str(&format!("export CUDA_PATH=\"{}\"\n", base));
-            cuda_exports.push_str(&format!("export CUDA_HOME=\"{}\"\n", base));
-            cuda_exports.push_str(&format!("export CUDA_ROOT=\"{}\"\n", base));
-            cuda_exports.push_str(&format!("export PATH=\"{}:$PATH\"\n", bin));
-            // Use default expansion for unset variable due to 'set -u'
-            cuda_exports.push_str(&format!("export LD_LIBRARY_PATH=\"{}:{}:${{LD_LIBRARY_PATH:-}}\"\n", lib, lib64));
-        }
+        #[cfg(unix)]
+        let portable = crate::utils::linux_portable_root().is_some();
+        #[cfg(not(unix))]
+        let portable = false;
 
-        // Generate base script content without execution command
-        let base_content = format!("#!/usr/bin/env bash\nset -Eeuo pipefail\n\nINSTALL=\"{}\"\nENV_PATH=\"$INSTALL/ps_env\"\nBASE_PREFIX=\"$ENV_PATH/mamba_env\"\nREPO_PATH=\"{}\"\nVENV=\"$INSTALL/envs/{}\"\nPYEXE=\"$VENV/bin/python\"\n\n# Detect mode: allow override via PORTABLESOURCE_MODE\nMODE=\"${{PORTABLESOURCE_MODE:-}}\"\nif [[ -z \"$MODE\" ]]; then\n  if command -v git >/dev/null 2>&1 && command -v python3 >/dev/null 2>&1 && command -v ffmpeg >/dev/null 2>&1; then\n    MODE=cloud\n  else\n    MODE=desk\n  fi\nfi\n\n# prepend micromamba base bin to PATH (no activation) in DESK mode\nif [[ \"$MODE\" == \"desk\" ]]; then\n  export PATH=\"$BASE_PREFIX/bin:$PATH\"\nfi\n\n# activate project venv if present (be tolerant to unset vars)\nif [[ -f \"$VENV/bin/activate\" ]]; then\n  set +u\n  source \"$VENV/bin/activate\" || true\n  set -u\nfi\n\n{}\ncd \"$REPO_PATH\"\n",
-            install_path.to_string_lossy(),
-            repo_path.to_string_lossy(),
-            repo_name,
-            cuda_exports,
-        );
-        
-        // Determine execution command based on available options
-        let content = if let Some(main_file) = main_file {
-            // Use main_file if available
-            base_content + &format!(
-                "if [[ -x \"$PYEXE\" ]]; then\n  exec \"$PYEXE\" \"{}\" {}\nelse\n  exec python3 \"{}\" {}\nfi\n",
-                main_file,
-                program_args,
-                main_file,
-                program_args,
-            )
-        } else if has_pyproject_scripts {
-            if let Some(module_path) = script_module {
-                info!("Using pyproject.toml script module: {}", module_path);
-                base_content + &format!(
-                    "if [[ -x \"$PYEXE\" ]]; then\n  exec \"$PYEXE\" -m {} {}\nelse\n  exec python3 -m {} {}\nfi\n",
-                    module_path,
-                    program_args,
-                    module_path,
-                    program_args,
-                )
-            } else {
-                warn!("pyproject.toml found but no suitable scripts detected");
-                base_content + "if [[ -x \"$PYEXE\" ]]; then\n  exec \"$PYEXE\"\nelse\n  exec python3\nfi\n"
-            }
-        } else {
-            // No main_file and no pyproject.toml - just run python
-            warn!("No main file or pyproject.toml scripts found, generating basic python launcher");
-            base_content + "if [[ -x \"$PYEXE\" ]]; then\n  exec \"$PYEXE\"\nelse\n  exec python3\nfi\n"
-        };
-
-        let mut f = fs::File::create(&sh_file)?;
-        f.write_all(content.as_bytes())?;
-        let mut perms = fs::metadata(&sh_file)?.permissions();
-        perms.set_mode(0o755);
-        fs::set_permissions(&sh_file, perms)?;
+        let settings = RepoRunSettings::load(repo_path)?;
+        let tuning = settings
+            .performance
+            .map(|profile| Tuning::new(profile, &Hardware::detect(self.config_manager)));
 
-        Ok(true)
-    }
+        let mut program_args = repo_info.program_args.clone().unwrap_or_default();
+        let upstream = crate::repo_metadata::upstream_name(repo_path);
+        let (extra_args, launch_env) = crate::resources::launch_settings(&upstream, &settings.resources, &program_args);
+        let components = settings
+            .components
+            .iter()
+            .filter_map(|name| match crate::components::find(name) {
+                Some(component) => Some(component.script_path()),
+                None => {
+                    warn!("Unknown component '{}' in the run settings of {:?}", name, repo_path);
+                    None
+                }
+            })
+            .collect();
+        let shared_prefix = crate::shared_env::EnvTarget::load(repo_path)?
+            .and_then(|target| target.prefix(&self.install_path).ok());
+        if !extra_args.is_empty() {
+            program_args = [program_args.as_str(), &extra_args.join(" ")].join(" ").trim().to_string();
+        }
 
-    /// Generate Unix shell script (no-op for non-Unix platforms)
-    #[cfg(not(unix))]
-    fn generate_startup_script_unix(&self, _repo_path: &Path, _repo_info: &RepositoryInfo) -> Result<bool> {
-        Ok(true) // No-op on non-Unix platforms
+        Ok(ScriptContext {
+            repo_name,
+            repo_dir_name,
+            install_path: self.install_path.clone(),
+            repo_path: repo_path.to_path_buf(),
+            target,
+            program_args,
+            cuda,
+            virtual_drive: needs_virtual_drive(&self.install_path),
+            portable,
+            tuning,
+            launch_env,
+            system_git: self.config_manager.system_tool("git").and_then(Path::parent).map(Path::to_path_buf),
+            components,
+            shared_prefix,
+        })
     }
 
     /// Check for pyproject.toml scripts
     fn check_scripts_in_pyproject(&self, repo_path: &Path) -> Result<(bool, Option<String>)> {
         self.pip_manager.check_scripts_in_pyproject(repo_path)
     }
-    
-    /// Check if virtual drive is needed based on path characteristics
-    fn needs_virtual_drive(&self, base_path: &Path) -> bool {
-        let path_str = base_path.to_string_lossy();
-        
-        // Check path length > 150 characters
-        if path_str.len() > 150 {
-            return true;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Compare against tests/golden/<name>; run with UPDATE_GOLDEN=1 to rewrite the files
+    fn assert_golden(name: &str, actual: &str) {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden").join(name);
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, actual).unwrap();
+            return;
         }
-        
-        // Check for spaces in path
-        if path_str.contains(' ') {
-            return true;
+        let expected = fs::read_to_string(&path)
+            .unwrap_or_else(|_| panic!("missing golden file {:?} (run with UPDATE_GOLDEN=1)", path));
+        assert_eq!(actual, expected, "script differs from golden file {:?}", path);
+    }
+
+    fn unix_context(target: LaunchTarget) -> ScriptContext {
+        ScriptContext {
+            repo_name: "comfyui".into(),
+            repo_dir_name: "ComfyUI".into(),
+            install_path: PathBuf::from("/opt/portablesource"),
+            repo_path: PathBuf::from("/opt/portablesource/repos/ComfyUI"),
+            target,
+            program_args: "--listen".into(),
+            cuda: None,
+            virtual_drive: false,
+            portable: false,
+            tuning: None,
+            launch_env: Vec::new(),
+            system_git: None,
+            components: Vec::new(),
+            shared_prefix: None,
         }
-        
-        // Check for non-ASCII characters (includes Russian text)
-        if !path_str.is_ascii() {
-            return true;
+    }
+
+    fn windows_context(target: LaunchTarget, install_path: &str) -> ScriptContext {
+        ScriptContext {
+            repo_name: "facefusion".into(),
+            repo_dir_name: "facefusion".into(),
+            install_path: PathBuf::from(install_path),
+            repo_path: PathBuf::from(format!("{}\\repos\\facefusion", install_path)),
+            target,
+            program_args: "run".into(),
+            cuda: None,
+            virtual_drive: needs_virtual_drive(Path::new(install_path)),
+            portable: false,
+            tuning: None,
+            launch_env: Vec::new(),
+            system_git: None,
+            components: Vec::new(),
+            shared_prefix: None,
         }
-        
-        false
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn regeneration_keeps_scripts_the_user_edited() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = dir.path();
+        assert_eq!(regenerate_script(repo, "start_x.sh", "v1\n", false, false).unwrap(), RegenOutcome::Created);
+        assert_eq!(regenerate_script(repo, "start_x.sh", "v1\n", false, false).unwrap(), RegenOutcome::Unchanged);
+        assert_eq!(regenerate_script(repo, "start_x.sh", "v2\n", false, true).unwrap(), RegenOutcome::Updated);
+        assert_eq!(fs::read_to_string(repo.join("start_x.sh")).unwrap(), "v1\n");
+        assert_eq!(regenerate_script(repo, "start_x.sh", "v2\n", false, false).unwrap(), RegenOutcome::Updated);
+
+        fs::write(repo.join("start_x.sh"), "v2\nexport MINE=1\n").unwrap();
+        assert_eq!(regenerate_script(repo, "start_x.sh", "v3\n", false, false).unwrap(), RegenOutcome::KeptEdited(repo.join("start_x.sh.new")));
+        assert_eq!(fs::read_to_string(repo.join("start_x.sh")).unwrap(), "v2\nexport MINE=1\n");
+        assert_eq!(regenerate_script(repo, "start_x.sh", "v3\n", true, false).unwrap(), RegenOutcome::Replaced(repo.join("start_x.sh.bak")));
+        assert_eq!(fs::read_to_string(repo.join("start_x.sh.bak")).unwrap(), "v2\nexport MINE=1\n");
+        assert_eq!(fs::read_to_string(repo.join("start_x.sh")).unwrap(), "v3\n");
+    }
+
+    fn linux_cuda() -> CudaPaths {
+        CudaPaths {
+            base: PathBuf::from("/opt/portablesource/ps_env/mamba_env"),
+            bin: PathBuf::from("/opt/portablesource/ps_env/mamba_env/bin"),
+            lib: PathBuf::from("/opt/portablesource/ps_env/mamba_env/lib"),
+            lib64: PathBuf::from("/opt/portablesource/ps_env/mamba_env/lib64"),
+        }
+    }
+
+    #[test]
+    fn unix_main_file_with_cuda() {
+        let mut ctx = unix_context(LaunchTarget::MainFile("main.py".into()));
+        ctx.cuda = Some(linux_cuda());
+        assert_golden("unix_main_file_cuda.sh", &render_unix_script(&ctx));
+    }
+
+    #[test]
+    fn unix_module_portable() {
+        let mut ctx = unix_context(LaunchTarget::Module("comfy.cli".into()));
+        ctx.portable = true;
+        assert_golden("unix_module_portable.sh", &render_unix_script(&ctx));
+    }
+
+    #[test]
+    fn unix_interpreter() {
+        let ctx = unix_context(LaunchTarget::Interpreter);
+        assert_golden("unix_interpreter.sh", &render_unix_script(&ctx));
+    }
+
+    #[test]
+    fn unix_with_performance_profile() {
+        let mut ctx = unix_context(LaunchTarget::MainFile("main.py".into()));
+        ctx.tuning = Some(Tuning {
+            profile: crate::performance::PerformanceProfile::LowVram,
+            env: vec![
+                ("PYTORCH_CUDA_ALLOC_CONF", "expandable_segments:True,garbage_collection_threshold:0.6".into()),
+                ("OMP_NUM_THREADS", "8".into()),
+            ],
+        });
+        assert_golden("unix_main_file_tuned.sh", &render_unix_script(&ctx));
+    }
+
+    #[test]
+    fn windows_direct_path_with_cuda() {
+        let mut ctx = windows_context(LaunchTarget::MainFile("facefusion.py".into()), "C:\\portablesource");
+        ctx.cuda = Some(CudaPaths::default());
+        assert!(!ctx.virtual_drive);
+        assert_golden("windows_direct_cuda.bat", &render_windows_script(&ctx));
+    }
+
+    #[test]
+    fn windows_virtual_drive_module() {
+        let ctx = windows_context(LaunchTarget::Module("facefusion.cli".into()), "D:\\My Programs\\portablesource");
+        assert!(ctx.virtual_drive);
+        assert_golden("windows_virtual_drive_module.bat", &render_windows_script(&ctx));
+    }
+
+    #[test]
+    fn windows_system_git_replaces_portable_git_path() {
+        let mut ctx = windows_context(LaunchTarget::MainFile("facefusion.py".into()), "C:\\portablesource");
+        ctx.system_git = Some(PathBuf::from("C:\\Program Files\\Git\\cmd"));
+        let script = render_windows_script(&ctx);
+        assert!(script.contains("set git_path=C:\\Program Files\\Git\\cmd\n"));
+        assert!(!script.contains("%env_path%\\git\\bin"));
+    }
+
+    #[test]
+    fn windows_components_go_on_path_after_cuda() {
+        let mut ctx = windows_context(LaunchTarget::MainFile("facefusion.py".into()), "C:\\portablesource");
+        ctx.components = vec![crate::components::find("cudnn").unwrap().script_path()];
+        let script = render_windows_script(&ctx);
+        assert!(script.contains("REM No CUDA paths configured\nREM === COMPONENTS ===\nset PATH=%env_path%\\components\\cudnn\\bin;%PATH%\n"));
+    }
+}
@@ -1,7 +1,7 @@
 //! Server client for communicating with PortableSource API server.
 
 use crate::Result;
-use log::warn;
+use tracing::warn;
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
 
@@ -10,6 +10,9 @@ pub struct RepositoryInfo {
     pub url: Option<String>,
     pub main_file: Option<String>, 
     pub program_args: Option<String>,
+    /// SPDX license id, if the server knows it
+    #[serde(default)]
+    pub license: Option<String>,
 }
 
 #[derive(Clone, Debug)]
@@ -80,8 +83,11 @@ impl ServerClient {
                                 let program_args = repo.get("programArgs")
                                     .and_then(|s| s.as_str())
                                     .map(|s| s.to_string());
+                                let license = repo.get("license")
+                                    .and_then(|s| s.as_str())
+                                    .map(|s| s.to_string());
                                 
-                                return Ok(Some(RepositoryInfo { url, main_file, program_args }));
+                                return Ok(Some(RepositoryInfo { url, main_file, program_args, license }));
                             }
                         } else {
                             // Legacy format
@@ -94,14 +100,15 @@ impl ServerClient {
                             let program_args = v.get("program_args")
                                 .and_then(|s| s.as_str())
                                 .map(|s| s.to_string());
+                            let license = v.get("license")
+                                .and_then(|s| s.as_str())
+                                .map(|s| s.to_string());
                             
                             if url.is_some() || main_file.is_some() {
-                                return Ok(Some(RepositoryInfo { url, main_file, program_args }));
+                                return Ok(Some(RepositoryInfo { url, main_file, program_args, license }));
                             }
                         }
                         Ok(None)
-                    } else if r.status().as_u16() == 404 {
-                        Ok(None)
                     } else {
                         Ok(None)
                     }
@@ -0,0 +1,174 @@
+//! What happens when one install step fails
+//!
+//! A failed step (Triton, InsightFace, a torch reinstall...) used to abort the whole
+//! install. With `--on-error ask` the user picks retry, skip or abort for that step after
+//! seeing what skipping costs; scripts pick a policy up front. Skipped steps are recorded in
+//! the repository metadata so `info-repo` can explain a missing feature later.
+
+use crate::{PortableSourceError, Result};
+use serde::{Deserialize, Serialize};
+
+/// Policy for a failed install step (`--on-error`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnStepError {
+    /// Ask retry/skip/abort on the terminal; abort when nobody can answer
+    #[default]
+    Ask,
+    Abort,
+    /// Skip steps the repository can run without (Triton, InsightFace), abort on others
+    SkipOptional,
+}
+
+impl OnStepError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OnStepError::Ask => "ask",
+            OnStepError::Abort => "abort",
+            OnStepError::SkipOptional => "skip-optional",
+        }
+    }
+
+    /// The policy that applies when prompts are `interactive`
+    pub fn resolve(self, interactive: bool) -> Self {
+        match self {
+            OnStepError::Ask if !interactive => OnStepError::Abort,
+            policy => policy,
+        }
+    }
+}
+
+impl std::fmt::Display for OnStepError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for OnStepError {
+    type Err = PortableSourceError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "ask" => Ok(OnStepError::Ask),
+            "abort" => Ok(OnStepError::Abort),
+            "skip-optional" => Ok(OnStepError::SkipOptional),
+            other => Err(PortableSourceError::config(format!(
+                "Unknown error policy '{}' (expected ask, abort or skip-optional)",
+                other
+            ))),
+        }
+    }
+}
+
+/// What to do with a step that just failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepDecision {
+    Retry,
+    Skip,
+    Abort,
+}
+
+impl StepDecision {
+    /// Decision taken without asking; None when the user has to be asked
+    pub fn from_policy(policy: OnStepError, optional: bool) -> Option<Self> {
+        match policy {
+            OnStepError::Ask => None,
+            OnStepError::SkipOptional if optional => Some(StepDecision::Skip),
+            OnStepError::SkipOptional | OnStepError::Abort => Some(StepDecision::Abort),
+        }
+    }
+}
+
+/// Run a step until it succeeds, retrying while `ask` says so; the policy decides without
+/// asking when it can. A full disk is never skipped: every later step would fail the same way.
+/// Returns the error of a skipped step
+pub fn run_step(
+    policy: OnStepError,
+    optional: bool,
+    mut run: impl FnMut() -> Result<()>,
+    mut ask: impl FnMut(&PortableSourceError) -> StepDecision,
+) -> Result<Option<PortableSourceError>> {
+    loop {
+        let error = match run() {
+            Ok(()) => return Ok(None),
+            Err(e @ PortableSourceError::DiskFull { .. }) => return Err(e),
+            Err(e) => e,
+        };
+        match StepDecision::from_policy(policy, optional).unwrap_or_else(|| ask(&error)) {
+            StepDecision::Retry => continue,
+            StepDecision::Skip => return Ok(Some(error)),
+            StepDecision::Abort => return Err(error),
+        }
+    }
+}
+
+/// Install step left out after it failed
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct SkippedStep {
+    /// Step name, e.g. "triton"
+    pub step: String,
+    /// What does not work without it
+    pub consequence: String,
+    pub error: String,
+    /// Unix time of the install that skipped it
+    pub skipped_at: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policies_decide_without_asking_except_ask() {
+        assert_eq!("Skip-Optional".parse::<OnStepError>().unwrap(), OnStepError::SkipOptional);
+        assert!("skip".parse::<OnStepError>().is_err());
+        assert_eq!(OnStepError::Ask.resolve(false), OnStepError::Abort);
+        assert_eq!(OnStepError::Ask.resolve(true), OnStepError::Ask);
+
+        assert_eq!(StepDecision::from_policy(OnStepError::Ask, true), None);
+        assert_eq!(StepDecision::from_policy(OnStepError::SkipOptional, true), Some(StepDecision::Skip));
+        assert_eq!(StepDecision::from_policy(OnStepError::SkipOptional, false), Some(StepDecision::Abort));
+        assert_eq!(StepDecision::from_policy(OnStepError::Abort, true), Some(StepDecision::Abort));
+    }
+
+    /// Step that fails `failures` times before it succeeds
+    fn flaky(failures: usize) -> impl FnMut() -> Result<()> {
+        let mut runs = 0;
+        move || {
+            runs += 1;
+            if runs > failures { Ok(()) } else { Err(PortableSourceError::command("pip install failed")) }
+        }
+    }
+
+    #[test]
+    fn asked_retry_runs_the_step_again() {
+        let mut asked = 0;
+        let outcome = run_step(OnStepError::Ask, false, flaky(2), |_| {
+            asked += 1;
+            StepDecision::Retry
+        });
+        assert!(outcome.unwrap().is_none());
+        assert_eq!(asked, 2);
+    }
+
+    #[test]
+    fn skipped_step_returns_its_error() {
+        let skipped = run_step(OnStepError::Ask, false, flaky(1), |_| StepDecision::Skip).unwrap();
+        assert_eq!(skipped.unwrap().to_string(), PortableSourceError::command("pip install failed").to_string());
+
+        let skipped = run_step(OnStepError::SkipOptional, true, flaky(1), |_| unreachable!()).unwrap();
+        assert!(skipped.is_some());
+    }
+
+    #[test]
+    fn abort_returns_the_error_without_asking() {
+        assert!(run_step(OnStepError::SkipOptional, false, flaky(1), |_| unreachable!()).is_err());
+        assert!(run_step(OnStepError::Ask, true, flaky(1), |_| StepDecision::Abort).is_err());
+    }
+
+    #[test]
+    fn full_disk_is_never_skipped() {
+        let full = || Err(PortableSourceError::disk_full("No space left on device"));
+        let outcome = run_step(OnStepError::SkipOptional, true, full, |_| StepDecision::Skip);
+        assert!(matches!(outcome, Err(PortableSourceError::DiskFull { .. })));
+    }
+}
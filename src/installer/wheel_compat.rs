@@ -0,0 +1,231 @@
+//! Compatibility of pinned wheel URLs with the target environment
+//!
+//! Some packages are installed from a wheel URL instead of an index (the Windows
+//! insightface build, wheels pinned in requirements files). A wheel built for another
+//! Python or platform fails to install with an unhelpful resolver error, or worse, not at
+//! all when pip silently skips it. Before such a URL is used its file name tags are checked
+//! against the venv's interpreter; an incompatible wheel is swapped for the same file built
+//! for the right tags when the host has it, and otherwise for a source build of the pinned
+//! version, with a message saying which.
+
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+
+const URL_CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Tags from a wheel file name: `{name}-{version}(-{build})?-{python}-{abi}-{platform}.whl`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WheelTags {
+    pub file_name: String,
+    pub name: String,
+    pub version: String,
+    pub python: Vec<String>,
+    pub abi: Vec<String>,
+    pub platform: Vec<String>,
+}
+
+impl WheelTags {
+    pub fn from_file_name(file_name: &str) -> Option<Self> {
+        let stem = file_name.strip_suffix(".whl")?;
+        let parts: Vec<&str> = stem.split('-').collect();
+        if parts.len() != 5 && parts.len() != 6 {
+            return None;
+        }
+        let n = parts.len();
+        let split = |s: &str| s.split('.').map(str::to_lowercase).collect();
+        Some(Self {
+            file_name: file_name.to_string(),
+            name: parts[0].to_string(),
+            version: parts[1].to_string(),
+            python: split(parts[n - 3]),
+            abi: split(parts[n - 2]),
+            platform: split(parts[n - 1]),
+        })
+    }
+
+    pub fn from_url(url: &str) -> Option<Self> {
+        let path = url.split(['?', '#']).next()?;
+        let file_name = path.rsplit('/').next()?;
+        Self::from_file_name(&file_name.replace("%2B", "+").replace("%2b", "+"))
+    }
+}
+
+/// Interpreter of the target venv
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetPython {
+    pub major: u32,
+    pub minor: u32,
+    /// `sysconfig.get_platform()` with `-` and `.` as `_` (win_amd64, linux_x86_64)
+    pub platform: String,
+}
+
+impl TargetPython {
+    pub fn probe(python: &Path) -> Option<Self> {
+        let mut cmd = Command::new(python);
+        cmd.args(["-c", "import sys, sysconfig; print(sys.version_info[0], sys.version_info[1], sysconfig.get_platform())"]);
+
+        #[cfg(target_os = "windows")]
+        {
+            use std::os::windows::process::CommandExt;
+            cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
+        }
+
+        let output = cmd.output().ok().filter(|o| o.status.success())?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let mut fields = stdout.split_whitespace();
+        Some(Self {
+            major: fields.next()?.parse().ok()?,
+            minor: fields.next()?.parse().ok()?,
+            platform: fields.next()?.replace(['-', '.'], "_").to_lowercase(),
+        })
+    }
+
+    pub fn python_tag(&self) -> String {
+        format!("cp{}{}", self.major, self.minor)
+    }
+
+    fn arch(&self) -> &str {
+        let arch = self.platform.rsplit('_').next().unwrap_or("");
+        if self.platform.ends_with("x86_64") { "x86_64" } else { arch }
+    }
+
+    fn accepts_python(&self, wheel: &WheelTags) -> bool {
+        let abi3 = wheel.abi.iter().any(|a| a == "abi3");
+        wheel.python.iter().any(|tag| {
+            if tag == "py3" || *tag == format!("py{}", self.major) || *tag == format!("py{}{}", self.major, self.minor) {
+                return true;
+            }
+            let Some(version) = tag.strip_prefix(&format!("cp{}", self.major)) else { return false };
+            match version.parse::<u32>() {
+                Ok(minor) => minor == self.minor || (abi3 && minor <= self.minor),
+                Err(_) => version.is_empty(),
+            }
+        })
+    }
+
+    fn accepts_platform(&self, wheel: &WheelTags) -> bool {
+        wheel.platform.iter().any(|plat| {
+            if plat == "any" || *plat == self.platform {
+                return true;
+            }
+            if self.platform.starts_with("linux") {
+                return (plat.starts_with("manylinux") || plat.starts_with("linux")) && plat.ends_with(self.arch());
+            }
+            if self.platform.starts_with("macosx") {
+                return plat.starts_with("macosx") && (plat.ends_with(self.arch()) || plat.ends_with("universal2"));
+            }
+            false
+        })
+    }
+
+    /// Why `wheel` cannot be installed here, if it cannot
+    pub fn incompatibility(&self, wheel: &WheelTags) -> Option<String> {
+        if !self.accepts_python(wheel) {
+            return Some(format!(
+                "{} is built for Python {}, the environment has {}.{}",
+                wheel.file_name,
+                wheel.python.join("."),
+                self.major,
+                self.minor
+            ));
+        }
+        if !self.accepts_platform(wheel) {
+            return Some(format!("{} is built for {}, the environment is {}", wheel.file_name, wheel.platform.join("."), self.platform));
+        }
+        None
+    }
+}
+
+/// What to install instead of a pinned wheel spec
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WheelResolution {
+    Compatible,
+    /// The same release built for this environment, from the same location
+    Alternative { spec: String, reason: String },
+    /// No matching wheel: the pinned version from the index, built from source if needed
+    SourceBuild { spec: String, reason: String },
+}
+
+/// `url` and the rest of a requirement that pins a wheel by URL (`url` or `name @ url ; marker`)
+fn split_wheel_spec(spec: &str) -> Option<(&str, &str)> {
+    let (requirement, marker) = match spec.find(';') {
+        Some(i) => (&spec[..i], &spec[i..]),
+        None => (spec, ""),
+    };
+    let url = requirement.rsplit('@').next().unwrap_or(requirement).trim();
+    let url = if url.contains("://") { url } else { requirement.trim() };
+    (url.contains("://") && url.split(['?', '#']).next()?.ends_with(".whl")).then_some((url, marker))
+}
+
+/// Wheel URL of the same release with the python/abi tags of `target`
+fn alternative_url(url: &str, wheel: &WheelTags, target: &TargetPython) -> Option<String> {
+    let old_python = wheel.python.join(".");
+    if !old_python.starts_with("cp") || wheel.abi.iter().any(|a| a == "abi3" || a == "none") {
+        return None;
+    }
+    let tag = target.python_tag();
+    let old = format!("-{}-{}-", old_python, wheel.abi.join("."));
+    let file_name = wheel.file_name.replace(&old, &format!("-{}-{}-", tag, tag));
+    (file_name != wheel.file_name).then(|| url.replace(&wheel.file_name, &file_name))
+}
+
+/// Check a requirement that pins a wheel URL; `exists` tells whether an alternative URL is served
+pub fn resolve_wheel(spec: &str, target: &TargetPython, exists: impl Fn(&str) -> bool) -> WheelResolution {
+    let Some((url, marker)) = split_wheel_spec(spec) else { return WheelResolution::Compatible };
+    let Some(wheel) = WheelTags::from_url(url) else { return WheelResolution::Compatible };
+    let Some(reason) = target.incompatibility(&wheel) else { return WheelResolution::Compatible };
+
+    if let Some(alternative) = alternative_url(url, &wheel, target) {
+        let alt_wheel = WheelTags::from_url(&alternative);
+        if alt_wheel.is_some_and(|w| target.incompatibility(&w).is_none()) && exists(&alternative) {
+            return WheelResolution::Alternative { spec: spec.replace(url, &alternative), reason };
+        }
+    }
+    let marker = if marker.is_empty() { String::new() } else { format!(" {}", marker.trim()) };
+    let spec = format!("{}=={}{}", wheel.name.replace('_', "-"), wheel.version, marker);
+    WheelResolution::SourceBuild { spec, reason }
+}
+
+/// Whether `url` answers a HEAD request with success (redirects followed)
+pub fn url_exists(url: &str) -> bool {
+    crate::system::block_on(async {
+        crate::system::http_client()
+            .head(url)
+            .timeout(URL_CHECK_TIMEOUT)
+            .send()
+            .await
+            .is_ok_and(|r| r.status().is_success())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn incompatible_wheels_fall_back_to_other_tags_or_source() {
+        let url = "https://huggingface.co/x/wheels/resolve/main/insightface/insightface-0.7.3-cp311-cp311-win_amd64.whl";
+        let py312 = TargetPython { major: 3, minor: 12, platform: "win_amd64".into() };
+        let py311 = TargetPython { minor: 11, ..py312.clone() };
+        let linux = TargetPython { major: 3, minor: 11, platform: "linux_x86_64".into() };
+
+        assert_eq!(resolve_wheel(url, &py311, |_| false), WheelResolution::Compatible);
+        assert_eq!(resolve_wheel("numpy==1.26.4", &py312, |_| false), WheelResolution::Compatible);
+
+        let alt = resolve_wheel(url, &py312, |u| u.contains("cp312-cp312"));
+        let WheelResolution::Alternative { spec, reason } = alt else { panic!("{:?}", alt) };
+        assert!(spec.ends_with("insightface-0.7.3-cp312-cp312-win_amd64.whl"));
+        assert_eq!(reason, "insightface-0.7.3-cp311-cp311-win_amd64.whl is built for Python cp311, the environment has 3.12");
+
+        let pinned = format!("insightface @ {} ; sys_platform == \"win32\"", url);
+        let source = resolve_wheel(&pinned, &py312, |_| false);
+        assert!(matches!(&source, WheelResolution::SourceBuild { spec, .. } if spec == "insightface==0.7.3 ; sys_platform == \"win32\""));
+        assert!(matches!(resolve_wheel(url, &linux, |_| true), WheelResolution::SourceBuild { reason, .. } if reason.contains("the environment is linux_x86_64")));
+
+        let manylinux = WheelTags::from_url("https://x/pkg-1.0-1-cp38-abi3-manylinux_2_17_x86_64.manylinux2014_x86_64.whl?download=1").unwrap();
+        assert_eq!(manylinux.platform.len(), 2);
+        assert!(linux.incompatibility(&manylinux).is_none());
+        assert!(py312.incompatibility(&WheelTags::from_file_name("tool-2.0-py2.py3-none-any.whl").unwrap()).is_none());
+    }
+}
@@ -0,0 +1,338 @@
+//! Machine descriptor for fleet management
+//!
+//! `inventory --json` describes a workstation in one document meant to be collected
+//! centrally across many machines: host, OS build, CPU, RAM, GPUs (VRAM, driver, compute
+//! capability), installed repositories with their checked-out commit and size on disk, and
+//! the versions of the tools portablesource runs. `schema` is bumped when a field changes
+//! meaning; new fields are only added.
+
+use crate::config::{ConfigManager, VERSION};
+use crate::envs_manager::{parse_nvcc_release, parse_tool_version, PortableEnvironmentManager};
+use crate::gpu::GpuDetector;
+use crate::planned_actions::dir_size;
+use crate::repo_index::RepoIndex;
+use crate::repo_metadata::RepoMetadata;
+use crate::Result;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Inventory {
+    pub schema: u32,
+    /// Unix time of collection
+    pub collected_at: u64,
+    pub portablesource_version: &'static str,
+    pub install_path: PathBuf,
+    pub host: HostInfo,
+    pub gpus: Vec<GpuEntry>,
+    /// Highest CUDA version the NVIDIA driver supports
+    pub driver_cuda: Option<String>,
+    pub repos: Vec<RepoEntry>,
+    /// Tool name to its version; None when the tool is missing or does not answer
+    pub tools: BTreeMap<String, Option<String>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HostInfo {
+    pub hostname: Option<String>,
+    /// OS name, build and architecture, as in `version`
+    pub os: String,
+    pub cpu: Option<String>,
+    pub cpu_threads: usize,
+    pub memory_mb: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GpuEntry {
+    pub name: String,
+    pub vendor: String,
+    pub memory_mb: u64,
+    pub driver_version: Option<String>,
+    pub compute_capability: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RepoEntry {
+    pub name: String,
+    /// Upstream name of a `--as` instance
+    pub upstream: Option<String>,
+    pub url: Option<String>,
+    pub branch: Option<String>,
+    /// Checked-out commit
+    pub commit: Option<String>,
+    pub size_bytes: u64,
+    pub env_size_bytes: u64,
+}
+
+impl Inventory {
+    pub fn collect(install_path: &Path, config_manager: &ConfigManager) -> Result<Self> {
+        let collected_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        Ok(Self {
+            schema: SCHEMA_VERSION,
+            collected_at,
+            portablesource_version: VERSION,
+            install_path: install_path.to_path_buf(),
+            host: HostInfo::detect(),
+            gpus: detect_gpus(),
+            driver_cuda: crate::build_info::run(&["nvidia-smi"]).and_then(|out| crate::build_info::parse_driver_cuda(&out)),
+            repos: collect_repos(install_path)?,
+            tools: tool_versions(install_path, config_manager),
+        })
+    }
+
+    /// Summary for the terminal
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let host = &self.host;
+        out.push_str(&format!("Host: {}\n", host.hostname.as_deref().unwrap_or("unknown")));
+        out.push_str(&format!("OS: {}\n", host.os));
+        out.push_str(&format!("CPU: {} ({} threads)\n", host.cpu.as_deref().unwrap_or("unknown"), host.cpu_threads));
+        if let Some(mb) = host.memory_mb {
+            out.push_str(&format!("RAM: {} MB\n", mb));
+        }
+        for gpu in &self.gpus {
+            let cc = gpu.compute_capability.as_deref().map(|c| format!(", compute {}", c)).unwrap_or_default();
+            let driver = gpu.driver_version.as_deref().map(|d| format!(", driver {}", d)).unwrap_or_default();
+            out.push_str(&format!("GPU: {} ({} MB{}{})\n", gpu.name, gpu.memory_mb, driver, cc));
+        }
+        for (tool, version) in &self.tools {
+            out.push_str(&format!("{}: {}\n", tool, version.as_deref().unwrap_or("not found")));
+        }
+        out.push_str(&format!("Repositories ({}):\n", self.repos.len()));
+        for repo in &self.repos {
+            let commit = repo.commit.as_deref().map(|c| &c[..c.len().min(12)]).unwrap_or("-");
+            let size = (repo.size_bytes + repo.env_size_bytes) / (1024 * 1024);
+            out.push_str(&format!("  {} @ {} ({} MB with environment)\n", repo.name, commit, size));
+        }
+        out
+    }
+}
+
+impl HostInfo {
+    pub fn detect() -> Self {
+        let (cpu, memory_mb) = cpu_and_memory();
+        Self {
+            hostname: hostname(),
+            os: crate::build_info::os_version(),
+            cpu,
+            cpu_threads: std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            memory_mb,
+        }
+    }
+}
+
+fn hostname() -> Option<String> {
+    let name = if cfg!(windows) {
+        std::env::var("COMPUTERNAME").ok()
+    } else {
+        fs::read_to_string("/proc/sys/kernel/hostname").ok().or_else(|| crate::build_info::run(&["hostname"]))
+    };
+    name.map(|n| n.trim().to_string()).filter(|n| !n.is_empty())
+}
+
+/// `model name` and `MemTotal` of /proc/cpuinfo and /proc/meminfo
+fn parse_proc_value<'a>(content: &'a str, key: &str) -> Option<&'a str> {
+    content
+        .lines()
+        .find_map(|line| line.split_once(':').filter(|(k, _)| k.trim() == key).map(|(_, v)| v.trim()))
+}
+
+#[cfg(target_os = "linux")]
+fn cpu_and_memory() -> (Option<String>, Option<u64>) {
+    let cpuinfo = fs::read_to_string("/proc/cpuinfo").unwrap_or_default();
+    let meminfo = fs::read_to_string("/proc/meminfo").unwrap_or_default();
+    let cpu = parse_proc_value(&cpuinfo, "model name").map(str::to_string);
+    let memory_kb = parse_proc_value(&meminfo, "MemTotal").and_then(|v| v.trim_end_matches("kB").trim().parse::<u64>().ok());
+    (cpu, memory_kb.map(|kb| kb / 1024))
+}
+
+#[cfg(target_os = "macos")]
+fn cpu_and_memory() -> (Option<String>, Option<u64>) {
+    let cpu = crate::build_info::run(&["sysctl", "-n", "machdep.cpu.brand_string"]).map(|c| c.trim().to_string());
+    let memory = crate::build_info::run(&["sysctl", "-n", "hw.memsize"]).and_then(|m| m.trim().parse::<u64>().ok());
+    (cpu, memory.map(|bytes| bytes / (1024 * 1024)))
+}
+
+#[cfg(windows)]
+fn cpu_and_memory() -> (Option<String>, Option<u64>) {
+    use serde::Deserialize;
+    use wmi::{COMLibrary, WMIConnection};
+
+    #[derive(Deserialize)]
+    #[allow(non_snake_case)]
+    struct Win32Processor {
+        #[serde(rename = "Name")] Name: Option<String>,
+    }
+    #[derive(Deserialize)]
+    #[allow(non_snake_case)]
+    struct Win32ComputerSystem {
+        #[serde(rename = "TotalPhysicalMemory")] TotalPhysicalMemory: Option<u64>,
+    }
+
+    let Some(wmi_con) = COMLibrary::new().ok().and_then(|com| WMIConnection::new(com.into()).ok()) else {
+        return (std::env::var("PROCESSOR_IDENTIFIER").ok(), None);
+    };
+    let cpu = wmi_con
+        .query::<Win32Processor>()
+        .ok()
+        .and_then(|r| r.into_iter().find_map(|p| p.Name))
+        .map(|n| n.trim().to_string());
+    let memory = wmi_con
+        .query::<Win32ComputerSystem>()
+        .ok()
+        .and_then(|r| r.into_iter().find_map(|s| s.TotalPhysicalMemory));
+    (cpu, memory.map(|bytes| bytes / (1024 * 1024)))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn cpu_and_memory() -> (Option<String>, Option<u64>) {
+    (None, None)
+}
+
+/// NVIDIA GPUs from the driver; other vendors from the generic detection
+fn detect_gpus() -> Vec<GpuEntry> {
+    let detector = GpuDetector::new();
+    let nvidia = detector.query_nvidia_details();
+    if !nvidia.is_empty() {
+        return nvidia
+            .into_iter()
+            .map(|gpu| GpuEntry {
+                name: gpu.name,
+                vendor: "nvidia".into(),
+                memory_mb: gpu.memory_total_mb,
+                driver_version: Some(gpu.driver_version),
+                compute_capability: gpu.compute_capability.map(|cc| cc.to_string()),
+            })
+            .collect();
+    }
+    detector
+        .get_best_gpu()
+        .ok()
+        .flatten()
+        .map(|gpu| GpuEntry {
+            vendor: format!("{:?}", gpu.gpu_type).to_lowercase(),
+            name: gpu.name,
+            memory_mb: gpu.memory_mb as u64,
+            driver_version: gpu.driver_version,
+            compute_capability: None,
+        })
+        .into_iter()
+        .collect()
+}
+
+fn collect_repos(install_path: &Path) -> Result<Vec<RepoEntry>> {
+    let index = RepoIndex::load_or_refresh(install_path)?;
+    Ok(index
+        .repos
+        .into_iter()
+        .map(|(name, entry)| {
+            let repo_path = install_path.join("repos").join(&name);
+            let metadata = RepoMetadata::load(&repo_path).ok().flatten();
+            let url = fs::read_to_string(repo_path.join("link.txt"))
+                .ok()
+                .map(|l| l.trim().to_string())
+                .filter(|l| !l.is_empty())
+                .or_else(|| metadata.as_ref().and_then(|m| m.provenance.as_ref()?.url.clone()));
+            let (branch, commit) = git_head(&repo_path);
+            RepoEntry {
+                upstream: entry.upstream,
+                url,
+                branch,
+                commit,
+                size_bytes: dir_size(&repo_path),
+                env_size_bytes: crate::shared_env::repo_env_prefix(install_path, &name).map_or(0, |env| dir_size(&env)),
+                name,
+            }
+        })
+        .collect())
+}
+
+/// Branch and commit checked out in `repo_path`, read from `.git` without running git;
+/// a worktree's `.git` file points to its git dir, whose refs live in the common dir
+pub fn git_head(repo_path: &Path) -> (Option<String>, Option<String>) {
+    let mut git_dir = repo_path.join(".git");
+    if git_dir.is_file() {
+        let Some(target) = fs::read_to_string(&git_dir).ok().and_then(|t| t.trim().strip_prefix("gitdir:").map(|p| p.trim().to_string())) else {
+            return (None, None);
+        };
+        git_dir = repo_path.join(target);
+    }
+    let Ok(head) = fs::read_to_string(git_dir.join("HEAD")) else { return (None, None) };
+    let head = head.trim();
+    let Some(reference) = head.strip_prefix("ref:").map(str::trim) else {
+        return (None, Some(head.to_string()));
+    };
+    let branch = reference.strip_prefix("refs/heads/").map(str::to_string);
+    let common = fs::read_to_string(git_dir.join("commondir")).map(|c| git_dir.join(c.trim())).unwrap_or_else(|_| git_dir.clone());
+    let commit = [&git_dir, &common].into_iter().find_map(|dir| {
+        fs::read_to_string(dir.join(reference)).ok().map(|c| c.trim().to_string()).or_else(|| {
+            fs::read_to_string(dir.join("packed-refs"))
+                .ok()?
+                .lines()
+                .find_map(|line| line.strip_suffix(reference).map(|sha| sha.trim().to_string()))
+        })
+    });
+    (branch, commit.filter(|c| !c.is_empty()))
+}
+
+/// Versions of python, git, ffmpeg and the portable nvcc this install uses
+fn tool_versions(install_path: &Path, config_manager: &ConfigManager) -> BTreeMap<String, Option<String>> {
+    let env_manager = PortableEnvironmentManager::with_config(install_path.to_path_buf(), config_manager.clone());
+    let version = |exe: Option<PathBuf>, flag: &str| {
+        let exe = exe?;
+        let out = crate::build_info::run(&[&exe.to_string_lossy(), flag])?;
+        parse_tool_version(&out).map(|(a, b, c)| format!("{}.{}.{}", a, b, c))
+    };
+    let mut tools = BTreeMap::new();
+    tools.insert("python".to_string(), version(env_manager.get_python_executable(), "--version"));
+    tools.insert("git".to_string(), version(env_manager.get_git_executable(), "--version"));
+    tools.insert("ffmpeg".to_string(), version(env_manager.get_ffmpeg_executable(), "-version"));
+    let nvcc = install_path.join("ps_env").join("CUDA").join("bin").join(if cfg!(windows) { "nvcc.exe" } else { "nvcc" });
+    let nvcc_version = nvcc
+        .exists()
+        .then(|| crate::build_info::run(&[&nvcc.to_string_lossy(), "--version"]))
+        .flatten()
+        .and_then(|out| parse_nvcc_release(&out));
+    tools.insert("nvcc".to_string(), nvcc_version);
+    tools
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn head_is_read_from_refs_packed_refs_and_worktrees() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = dir.path().join("ComfyUI");
+        let git = repo.join(".git");
+        fs::create_dir_all(git.join("refs/heads")).unwrap();
+        fs::write(git.join("HEAD"), "ref: refs/heads/master\n").unwrap();
+        fs::write(git.join("refs/heads/master"), "0123abcd\n").unwrap();
+        assert_eq!(git_head(&repo), (Some("master".into()), Some("0123abcd".into())));
+
+        fs::write(git.join("HEAD"), "ref: refs/heads/dev\n").unwrap();
+        fs::write(git.join("packed-refs"), "# pack-refs with: peeled\nfeedbeef refs/heads/dev\n").unwrap();
+        assert_eq!(git_head(&repo), (Some("dev".into()), Some("feedbeef".into())));
+
+        let worktree = dir.path().join("ComfyUI-video");
+        let wt_git = git.join("worktrees").join("ComfyUI-video");
+        fs::create_dir_all(&wt_git).unwrap();
+        fs::create_dir_all(&worktree).unwrap();
+        fs::write(worktree.join(".git"), format!("gitdir: {}\n", wt_git.display())).unwrap();
+        fs::write(wt_git.join("HEAD"), "ref: refs/heads/master\n").unwrap();
+        fs::write(wt_git.join("commondir"), "../..\n").unwrap();
+        assert_eq!(git_head(&worktree), (Some("master".into()), Some("0123abcd".into())));
+
+        fs::write(git.join("HEAD"), "cafef00d\n").unwrap();
+        assert_eq!(git_head(&repo), (None, Some("cafef00d".into())));
+        assert_eq!(parse_proc_value("model name\t: AMD Ryzen 9 7950X\n", "model name"), Some("AMD Ryzen 9 7950X"));
+    }
+}
@@ -0,0 +1,134 @@
+//! Copy-ready launch command of an installed repository
+//!
+//! After install and in `info-repo` the exact command that starts a repository (its start
+//! script, which carries the performance profile and resource settings) is printed on a line
+//! of its own, quoted for the platform shell. With `--copy` it also goes to the clipboard
+//! through the platform tool: `clip` on Windows, `pbcopy` on macOS, `wl-copy`, `xclip` or
+//! `xsel` elsewhere.
+
+use crate::installer::script_generator::script_file_name;
+use crate::{PortableSourceError, Result};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Start script written by the script generator for the repository folder `repo`
+pub fn start_script(install_path: &Path, repo: &str) -> PathBuf {
+    install_path.join("repos").join(repo).join(script_file_name(repo))
+}
+
+/// `path` as one word of a POSIX shell command line
+pub fn quote_unix(path: &str) -> String {
+    let plain = !path.is_empty() && path.chars().all(|c| c.is_ascii_alphanumeric() || "/._-+=:,@%".contains(c));
+    if plain {
+        path.to_string()
+    } else {
+        format!("'{}'", path.replace('\'', r"'\''"))
+    }
+}
+
+/// `path` as one word of a cmd.exe command line. cmd expands `%VAR%` even inside quotes, so
+/// a `%` leaves the quotes and is escaped with `^`
+pub fn quote_windows(path: &str) -> String {
+    if path.chars().any(|c| c.is_whitespace() || "&()[]{}^=;!'+,`~%".contains(c)) {
+        format!("\"{}\"", path.replace('%', "\"^%\""))
+    } else {
+        path.to_string()
+    }
+}
+
+/// Command line that starts `repo`
+pub fn command_line(install_path: &Path, repo: &str) -> Result<String> {
+    let script = start_script(install_path, repo);
+    if !script.exists() {
+        return Err(PortableSourceError::repository(format!(
+            "No start script for '{}'; run update-repo {} to generate it", repo, repo
+        )));
+    }
+    let script = script.to_string_lossy();
+    Ok(if cfg!(windows) { quote_windows(&script) } else { quote_unix(&script) })
+}
+
+/// Clipboard tools to try, first match wins
+fn clipboard_tools() -> Vec<(&'static str, &'static [&'static str])> {
+    if cfg!(windows) {
+        vec![("clip", &[])]
+    } else if cfg!(target_os = "macos") {
+        vec![("pbcopy", &[])]
+    } else {
+        let mut tools: Vec<(&'static str, &'static [&'static str])> = Vec::new();
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            tools.push(("wl-copy", &[]));
+        }
+        tools.push(("xclip", &["-selection", "clipboard"]));
+        tools.push(("xsel", &["--clipboard", "--input"]));
+        tools
+    }
+}
+
+/// Bytes to pipe into `tool`: `clip` reads the console code page unless the input is UTF-16
+/// with a byte order mark, which keeps non-ASCII install paths intact
+fn clipboard_input(tool: &str, text: &str) -> Vec<u8> {
+    if tool == "clip" {
+        [0xFF, 0xFE].into_iter().chain(text.encode_utf16().flat_map(u16::to_le_bytes)).collect()
+    } else {
+        text.as_bytes().to_vec()
+    }
+}
+
+pub fn copy_to_clipboard(text: &str) -> Result<()> {
+    for (tool, args) in clipboard_tools() {
+        let Ok(mut child) = Command::new(tool).args(args).stdin(Stdio::piped()).stdout(Stdio::null()).stderr(Stdio::null()).spawn() else {
+            continue;
+        };
+        // stdin is closed before waiting, so the tool sees the end of its input
+        let written = child.stdin.take().map_or(Ok(()), |mut stdin| stdin.write_all(&clipboard_input(tool, text)));
+        let exited = child.wait();
+        if written.is_ok() && exited.is_ok_and(|status| status.success()) {
+            return Ok(());
+        }
+    }
+    Err(PortableSourceError::environment("No clipboard available (install wl-clipboard, xclip or xsel)"))
+}
+
+/// Print how to start `repo` and, with `copy`, put the command on the clipboard
+pub fn show(install_path: &Path, repo: &str, copy: bool) -> Result<()> {
+    let command = command_line(install_path, repo)?;
+    crate::output::info(&format!("To start {}, run:", repo));
+    println!("  {}", command);
+    if copy {
+        match copy_to_clipboard(&command) {
+            Ok(()) => crate::output::success("Launch command copied to the clipboard"),
+            Err(e) => crate::output::warn(&format!("Could not copy the launch command: {}", e)),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_are_quoted_for_the_platform_shell() {
+        assert_eq!(quote_unix("/opt/ps/repos/ComfyUI/start_comfyui.sh"), "/opt/ps/repos/ComfyUI/start_comfyui.sh");
+        assert_eq!(quote_unix("/home/me/My AI/repos/x/start_x.sh"), "'/home/me/My AI/repos/x/start_x.sh'");
+        assert_eq!(quote_unix("/tmp/it's/start.sh"), r"'/tmp/it'\''s/start.sh'");
+        assert_eq!(quote_windows(r"C:\ps\repos\x\start_x.bat"), r"C:\ps\repos\x\start_x.bat");
+        assert_eq!(quote_windows(r"D:\AI Tools\repos\x\start_x.bat"), r#""D:\AI Tools\repos\x\start_x.bat""#);
+        assert_eq!(quote_windows(r"D:\100%AI%\start_x.bat"), r#""D:\100"^%"AI"^%"\start_x.bat""#);
+
+        let dir = tempfile::tempdir().unwrap();
+        assert!(command_line(dir.path(), "ComfyUI").is_err());
+        let script = start_script(dir.path(), "ComfyUI");
+        std::fs::create_dir_all(script.parent().unwrap()).unwrap();
+        std::fs::write(&script, "").unwrap();
+        assert!(command_line(dir.path(), "ComfyUI").unwrap().contains("start_comfyui."));
+    }
+
+    #[test]
+    fn clip_gets_utf16_with_a_byte_order_mark() {
+        assert_eq!(clipboard_input("clip", "D:\\ИИ"), vec![0xFF, 0xFE, b'D', 0, b':', 0, b'\\', 0, 0x18, 0x04, 0x18, 0x04]);
+        assert_eq!(clipboard_input("xclip", "/ИИ"), "/ИИ".as_bytes());
+    }
+}
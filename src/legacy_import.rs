@@ -0,0 +1,276 @@
+//! Import of installs made by the Python version of portablesource
+//!
+//! The Python version used the same `repos/<name>` checkouts and a config file without
+//! `schema_version`, but kept no per-repository metadata and, in its conda-based releases,
+//! put environments under `miniconda/envs/<name>` instead of `envs/<name>`. A repository
+//! folder without `.portablesource_meta.json` is treated as legacy. Importing records
+//! metadata from the git remote, moves checkouts and environments into the install path
+//! when the legacy install lives elsewhere (copying when it is on another drive), rewrites
+//! the old paths inside moved environments, migrates the config and regenerates start
+//! scripts. What cannot be carried over (conda environments, missing environments,
+//! name clashes) is reported instead of guessed.
+
+use crate::config_migration;
+use crate::planned_actions::ActionPlan;
+use crate::repo_metadata::{RepoMetadata, Provenance, METADATA_FILE};
+use crate::utils::unix_timestamp;
+use crate::Result;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const CONFIG_FILE: &str = "portablesource_config.json";
+
+/// Where conda-based releases created repository environments, relative to the install root
+const CONDA_ENV_DIRS: &[&str] = &["miniconda/envs", "conda/envs"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LegacyEnv {
+    /// `envs/<name>`: a venv or portable Python copy, used as it is
+    Reusable(PathBuf),
+    /// Conda environment; the new layout cannot run it
+    Conda(PathBuf),
+    Missing,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LegacyRepo {
+    pub name: String,
+    pub path: PathBuf,
+    /// `origin` remote of the checkout
+    pub url: Option<String>,
+    pub env: LegacyEnv,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LegacyInstall {
+    pub root: PathBuf,
+    /// Config file without a schema version
+    pub config: Option<PathBuf>,
+    pub repos: Vec<LegacyRepo>,
+}
+
+impl LegacyInstall {
+    pub fn is_empty(&self) -> bool {
+        self.config.is_none() && self.repos.is_empty()
+    }
+}
+
+/// URL of the `origin` remote from `.git/config`, without running git
+pub fn origin_url(repo_path: &Path) -> Option<String> {
+    let config = fs::read_to_string(repo_path.join(".git").join("config")).ok()?;
+    let mut in_origin = false;
+    for line in config.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_origin = line == "[remote \"origin\"]";
+        } else if in_origin {
+            if let Some((key, value)) = line.split_once('=') {
+                if key.trim() == "url" {
+                    return Some(value.trim().to_string());
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Environment of a legacy repository; environment folders may differ in case from the repo
+fn find_env(root: &Path, name: &str) -> LegacyEnv {
+    let lookup = |dir: PathBuf| {
+        fs::read_dir(&dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .find(|e| e.file_name().to_string_lossy().eq_ignore_ascii_case(name) && e.path().is_dir())
+            .map(|e| e.path())
+    };
+    if let Some(env) = lookup(root.join("envs")) {
+        return LegacyEnv::Reusable(env);
+    }
+    match CONDA_ENV_DIRS.iter().find_map(|dir| lookup(root.join(dir))) {
+        Some(env) => LegacyEnv::Conda(env),
+        None => LegacyEnv::Missing,
+    }
+}
+
+/// Legacy config and repositories under `root`, sorted by name
+pub fn detect(root: &Path) -> Result<LegacyInstall> {
+    let mut legacy = LegacyInstall { root: root.to_path_buf(), ..Default::default() };
+    let config = root.join(CONFIG_FILE);
+    if config.exists() {
+        let value: serde_json::Value = serde_json::from_str(&fs::read_to_string(&config)?)?;
+        if config_migration::schema_version(&value) < config_migration::CURRENT_SCHEMA_VERSION {
+            legacy.config = Some(config);
+        }
+    }
+    for entry in fs::read_dir(root.join("repos")).into_iter().flatten().flatten() {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !path.is_dir() || name.starts_with('.') || path.join(METADATA_FILE).exists() {
+            continue;
+        }
+        legacy.repos.push(LegacyRepo { url: origin_url(&path), env: find_env(root, &name), name, path });
+    }
+    legacy.repos.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(legacy)
+}
+
+/// Folder a legacy repository ends up in
+pub fn target_repo_path(install_path: &Path, repo: &LegacyRepo) -> PathBuf {
+    install_path.join("repos").join(&repo.name)
+}
+
+/// Folder the environment of a legacy repository ends up in
+pub fn target_env_path(install_path: &Path, repo: &LegacyRepo) -> PathBuf {
+    install_path.join("envs").join(repo.name.to_lowercase())
+}
+
+/// Point a moved environment at its new location: activate scripts, shebangs, `pyvenv.cfg`
+/// and, on Unix, interpreter links that named the old environment or the old install.
+/// Returns how many files and links were rewritten
+pub fn relocate_env(env: &Path, old_env: &Path, old_root: &Path, install_path: &Path) -> Result<usize> {
+    let mut rewritten = 0;
+    // The folder name may change case, so the environment itself goes first
+    for (old, new) in [(old_env, env), (old_root, install_path)] {
+        rewritten += crate::backup::rewrite_tree(env, &old.to_string_lossy(), &new.to_string_lossy(), true)?;
+    }
+    Ok(rewritten)
+}
+
+/// What importing changes; notes list what has to be done by hand
+pub fn plan(legacy: &LegacyInstall, install_path: &Path) -> ActionPlan {
+    let mut plan = ActionPlan::default();
+    let in_place = legacy.root == install_path;
+    if let Some(config) = &legacy.config {
+        if in_place {
+            plan.modify(config.clone(), &format!("migrated to schema {}", config_migration::CURRENT_SCHEMA_VERSION));
+        } else {
+            plan.keep(config.clone(), "settings of the old install are not merged");
+            plan.notes.push(format!("Settings in {} were not merged; the current config is kept", config.display()));
+        }
+    }
+
+    for repo in &legacy.repos {
+        let target = target_repo_path(install_path, repo);
+        if !in_place {
+            if target.exists() {
+                plan.keep(repo.path.clone(), "a repository with this name is already installed");
+                plan.notes.push(format!("{}: already installed here; the old copy was left in {}", repo.name, repo.path.display()));
+                continue;
+            }
+            plan.move_to(repo.path.clone(), target.clone(), "repository checkout");
+        }
+        match &repo.env {
+            LegacyEnv::Reusable(env) if !in_place => {
+                plan.move_to(env.clone(), target_env_path(install_path, repo), "repository environment");
+            }
+            LegacyEnv::Reusable(_) => {}
+            LegacyEnv::Conda(env) => {
+                plan.keep(env.clone(), "conda environment, not usable by this version");
+                plan.notes.push(format!("{}: its conda environment cannot be reused; create a new one with 'portablesource update-repo {}'", repo.name, repo.name));
+            }
+            LegacyEnv::Missing => {
+                plan.notes.push(format!("{}: no environment found; create one with 'portablesource update-repo {}'", repo.name, repo.name));
+            }
+        }
+        plan.modify(target.join(METADATA_FILE), "metadata recorded");
+        if repo.url.is_none() {
+            plan.notes.push(format!("{}: no git remote found, so updates will not work until it is reinstalled", repo.name));
+        }
+    }
+    plan
+}
+
+/// Metadata of an imported repository: the git remote as provenance
+pub fn metadata_for(repo: &LegacyRepo) -> RepoMetadata {
+    RepoMetadata {
+        name: repo.name.clone(),
+        provenance: Some(Provenance {
+            source: "git".to_string(),
+            url: repo.url.clone(),
+            fetched_at: unix_timestamp(),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Old install with ComfyUI (git remote, venv), facefusion (conda env), a folder without
+    /// either and an already imported forge
+    fn legacy_install(old: &Path) -> LegacyInstall {
+        for repo in ["ComfyUI", "facefusion", "notes-only"] {
+            fs::create_dir_all(old.join("repos").join(repo)).unwrap();
+        }
+        fs::create_dir_all(old.join("repos/ComfyUI/.git")).unwrap();
+        fs::write(
+            old.join("repos/ComfyUI/.git/config"),
+            "[core]\n\tbare = false\n[remote \"origin\"]\n\turl = https://github.com/comfyanonymous/ComfyUI\n\tfetch = +refs/heads/*:refs/remotes/origin/*\n",
+        )
+        .unwrap();
+        fs::create_dir_all(old.join("envs/comfyui")).unwrap();
+        fs::create_dir_all(old.join("miniconda/envs/facefusion")).unwrap();
+        fs::write(old.join(CONFIG_FILE), r#"{"install_path": "C:\\old"}"#).unwrap();
+        // Already imported repositories carry metadata
+        fs::create_dir_all(old.join("repos/forge")).unwrap();
+        fs::write(old.join("repos/forge").join(METADATA_FILE), "{}").unwrap();
+        detect(old).unwrap()
+    }
+
+    #[test]
+    fn repos_without_metadata_are_detected_with_the_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let legacy = legacy_install(dir.path());
+        assert_eq!(legacy.config, Some(dir.path().join(CONFIG_FILE)));
+        let names: Vec<_> = legacy.repos.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, ["ComfyUI", "facefusion", "notes-only"]);
+        assert_eq!(legacy.repos[0].url.as_deref(), Some("https://github.com/comfyanonymous/ComfyUI"));
+    }
+
+    #[test]
+    fn environments_are_classified_by_where_they_live() {
+        let dir = tempfile::tempdir().unwrap();
+        let legacy = legacy_install(dir.path());
+        assert_eq!(legacy.repos[0].env, LegacyEnv::Reusable(dir.path().join("envs/comfyui")));
+        assert_eq!(legacy.repos[1].env, LegacyEnv::Conda(dir.path().join("miniconda/envs/facefusion")));
+        assert_eq!(legacy.repos[2].env, LegacyEnv::Missing);
+    }
+
+    #[test]
+    fn plan_moves_reusable_environments_and_notes_the_rest() {
+        let dir = tempfile::tempdir().unwrap();
+        let (old, install) = (dir.path().join("old"), dir.path().join("new"));
+        let plan = plan(&legacy_install(&old), &install);
+        let move_env = crate::planned_actions::PlanAction::MoveTo(install.join("envs/comfyui"));
+        assert!(plan.items.iter().any(|i| i.path == old.join("envs/comfyui") && i.action == move_env));
+        assert_eq!(plan.notes.len(), 5);
+        assert!(plan.notes[1].starts_with("facefusion: its conda environment cannot be reused"));
+        assert!(plan.notes[4].starts_with("notes-only: no git remote found"));
+    }
+
+    #[test]
+    fn moved_environments_name_their_new_location() {
+        let dir = tempfile::tempdir().unwrap();
+        let (old, install) = (dir.path().join("old"), dir.path().join("new"));
+        let old_env = old.join("envs/ComfyUI");
+        let env = install.join("envs/comfyui");
+        fs::create_dir_all(env.join("bin")).unwrap();
+        fs::write(env.join("pyvenv.cfg"), format!("home = {}\n", old.join("ps_env/python").display())).unwrap();
+        fs::write(env.join("bin/activate"), format!("VIRTUAL_ENV=\"{}\"\n", old_env.display())).unwrap();
+
+        assert_eq!(relocate_env(&env, &old_env, &old, &install).unwrap(), 2);
+        assert_eq!(fs::read_to_string(env.join("pyvenv.cfg")).unwrap(), format!("home = {}\n", install.join("ps_env/python").display()));
+        assert_eq!(fs::read_to_string(env.join("bin/activate")).unwrap(), format!("VIRTUAL_ENV=\"{}\"\n", env.display()));
+    }
+
+    #[test]
+    fn an_unsaved_config_is_still_legacy_after_loading() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join(CONFIG_FILE);
+        fs::write(&config, r#"{"version": "0.9", "install_path": ""}"#).unwrap();
+        crate::config::ConfigManager::new(Some(config.clone())).unwrap();
+        assert_eq!(detect(dir.path()).unwrap().config, Some(config));
+    }
+}
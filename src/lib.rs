@@ -2,14 +2,101 @@
 //! 
 //! This is a Rust implementation of the PortableSource CLI tool,
 //! originally written in Python.
+//!
+//! Applications embedding PortableSource should use [`api`], the only part of the crate
+//! covered by semver, and depend on the crate with `default-features = false`. The other
+//! modules belong to the CLI binary: they are public only with the `cli` feature (on by
+//! default, required by the binary) and change without notice.
+#![cfg_attr(not(feature = "cli"), allow(dead_code, unused_imports))]
 
-pub mod cli;
-pub mod config;
-pub mod gpu;
-pub mod utils;
-pub mod envs_manager;
-pub mod installer;
-pub mod repository_installer;
-pub mod error;
+/// Declares modules of the CLI: public with the `cli` feature, private without it
+macro_rules! cli_modules {
+    ($($(#[$attr:meta])* $name:ident;)*) => {
+        $(
+            $(#[$attr])*
+            #[cfg(feature = "cli")]
+            pub mod $name;
+            $(#[$attr])*
+            #[cfg(not(feature = "cli"))]
+            mod $name;
+        )*
+    };
+}
 
-pub use error::{Result, PortableSourceError};
\ No newline at end of file
+pub mod api;
+
+mod build_info;
+mod download_state;
+mod extraction;
+mod net_isolation;
+mod path_rewrite;
+mod resources;
+mod warm_start;
+
+cli_modules! {
+    atomic_write;
+    backup;
+    ci_manifest;
+    cli;
+    components;
+    config;
+    config_migration;
+    gpu;
+    gpu_monitor;
+    gpu_session;
+    launch_command;
+    legacy_import;
+    log_levels;
+    utils;
+    video_check;
+    envs_manager;
+    env_preview;
+    bootstrap;
+    build_slots;
+    cache_gc;
+    disk_space;
+    download_plan;
+    history;
+    installer;
+    install_sandbox;
+    inventory;
+    repository_installer;
+    scheduler;
+    repo_metadata;
+    repo_index;
+    repo_state;
+    run_queue;
+    run_stats;
+    native_deps;
+    models;
+    oom_advice;
+    output;
+    performance;
+    planned_actions;
+    plugins;
+    prefetch;
+    progress;
+    prompt;
+    read_only;
+    shared_env;
+    shared_models;
+    system;
+    timings;
+    tool_lookup;
+    tool_slots;
+    venv_repair;
+    workspace;
+    #[cfg(unix)]
+    env_pack;
+    #[cfg(unix)]
+    uninstall;
+    error;
+}
+
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
+#[cfg(feature = "cli")]
+pub use error::{Result, PortableSourceError};
+#[cfg(not(feature = "cli"))]
+use error::{Result, PortableSourceError};
@@ -0,0 +1,107 @@
+//! Per-subsystem log levels
+//!
+//! `--log installer=debug,gpu=warn` (or `log_levels` in the config file) raises or lowers
+//! logging for parts of the tool without touching the rest. Subsystem names expand to the
+//! modules behind them, third-party crates included: `download` covers reqwest/hyper, `gpu`
+//! covers wmi. A full module path (`portablesource_rs::installer::pip_manager`) is accepted
+//! as well. A bare level (`--log warn`) sets the default level.
+
+use crate::{PortableSourceError, Result};
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::EnvFilter;
+
+/// Subsystem name and the log targets it expands to
+pub const SUBSYSTEMS: &[(&str, &[&str])] = &[
+    ("installer", &["portablesource_rs::installer", "portablesource_rs::repository_installer"]),
+    ("env", &["portablesource_rs::envs_manager", "portablesource_rs::bootstrap", "portablesource_rs::env_pack"]),
+    ("gpu", &["portablesource_rs::gpu", "portablesource_rs::run_queue", "wmi"]),
+    ("download", &["portablesource_rs::download_state", "reqwest", "hyper", "hyper_util", "h2", "rustls"]),
+    ("config", &["portablesource_rs::config", "portablesource_rs::config_migration"]),
+    ("run", &["portablesource_rs::utils", "portablesource_rs::run_stats", "portablesource_rs::net_isolation"]),
+    ("scheduler", &["portablesource_rs::scheduler"]),
+];
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LogSpec {
+    /// Level for everything not named in `targets`
+    pub default: Option<LevelFilter>,
+    pub targets: Vec<(String, LevelFilter)>,
+}
+
+impl LogSpec {
+    /// Parse `name=level[,name=level...]`; later entries win
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut out = LogSpec::default();
+        for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let Some((name, level)) = part.split_once('=') else {
+                out.default = Some(parse_level(part)?);
+                continue;
+            };
+            let level = parse_level(level)?;
+            let name = name.trim();
+            if name.contains("::") {
+                out.targets.push((name.to_string(), level));
+                continue;
+            }
+            let (_, targets) = SUBSYSTEMS
+                .iter()
+                .find(|(known, _)| known.eq_ignore_ascii_case(name))
+                .ok_or_else(|| PortableSourceError::config(format!(
+                    "Unknown log subsystem '{}' (expected one of: {}, or a module path)",
+                    name,
+                    SUBSYSTEMS.iter().map(|(n, _)| *n).collect::<Vec<_>>().join(", ")
+                )))?;
+            out.targets.extend(targets.iter().map(|t| (t.to_string(), level)));
+        }
+        Ok(out)
+    }
+
+    /// Layer `other` on top of this spec
+    pub fn merge(mut self, other: LogSpec) -> Self {
+        if other.default.is_some() {
+            self.default = other.default;
+        }
+        self.targets.extend(other.targets);
+        self
+    }
+
+    /// Add the directives to `filter`; a directive replaces an earlier one for the same target
+    pub fn apply(&self, mut filter: EnvFilter) -> EnvFilter {
+        if let Some(level) = self.default {
+            filter = filter.add_directive(level.into());
+        }
+        for (target, level) in &self.targets {
+            match format!("{}={}", target, level).parse() {
+                Ok(directive) => filter = filter.add_directive(directive),
+                Err(e) => tracing::warn!("Ignoring log directive for {}: {}", target, e),
+            }
+        }
+        filter
+    }
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter> {
+    level.trim().parse::<LevelFilter>().map_err(|_| PortableSourceError::config(format!(
+        "Unknown log level '{}' (expected off, error, warn, info, debug or trace)", level.trim()
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subsystems_expand_and_later_entries_win() {
+        let spec = LogSpec::parse("installer=debug, gpu=warn").unwrap();
+        assert_eq!(spec.default, None);
+        assert!(spec.targets.contains(&("portablesource_rs::installer".to_string(), LevelFilter::DEBUG)));
+        assert!(spec.targets.contains(&("wmi".to_string(), LevelFilter::WARN)));
+
+        let merged = spec.merge(LogSpec::parse("info,portablesource_rs::installer::pip_manager=trace").unwrap());
+        assert_eq!(merged.default, Some(LevelFilter::INFO));
+        assert_eq!(merged.targets.last().unwrap(), &("portablesource_rs::installer::pip_manager".to_string(), LevelFilter::TRACE));
+
+        assert!(LogSpec::parse("pip=debug").is_err());
+        assert!(LogSpec::parse("installer=loud").is_err());
+    }
+}
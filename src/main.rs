@@ -1,16 +1,50 @@
 use portablesource_rs::{
-    cli::{Cli, Commands},
+    atomic_write,
+    cli::{BackupAction, CacheAction, Cli, Commands, ConfigAction, EnvAction, ScheduleAction},
     config::ConfigManager,
-    gpu::GpuDetector,
+    download_plan::DownloadPlan,
+    ci_manifest,
+    config_migration,
+    output,
+    progress,
+    read_only,
+    gpu::{self, GpuDetector, GpuInfo},
+    gpu_session::{self, SessionKind},
+    history::{self, ReportFormat},
+    inventory::Inventory,
+    launch_command,
+    native_deps,
+    legacy_import,
+    log_levels::LogSpec,
+    performance::{Hardware, PerformanceProfile, Tuning},
+    planned_actions::{ActionPlan, PlanAction},
+    oom_advice,
+    repo_metadata::{self, Backend, RepoMetadata},
+    run_stats,
+    shared_env::{self, EnvTarget},
+    system::SystemExecutor,
+    video_check,
+    timings::{self, TimingLayer},
     utils,
-    envs_manager::PortableEnvironmentManager,
+    installer::OnStepError,
     repository_installer::RepositoryInstaller,
-    PortableSourceError,
+    scheduler::{self, ScheduleFrequency},
+    run_queue::{self, GpuQueueOverride, RepoRunSettings},
+    venv_repair::{self, Repair},
+    workspace,
     Result,
 };
-use log::{info, error, warn, LevelFilter};
-use std::path::PathBuf;
+use portablesource_rs::envs_manager::{self, PortableEnvironmentManager, ToolUpgrade};
+use portablesource_rs::error::{ErrorFormat, MultiError};
+#[cfg(unix)]
+use portablesource_rs::install_sandbox::{Sandbox, SandboxPolicy};
+use portablesource_rs::PortableSourceError;
+use tracing::{info, warn, level_filters::LevelFilter};
+use tracing_subscriber::{filter::filter_fn, fmt::format::FmtSpan, prelude::*, EnvFilter};
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
 use std::sync::OnceLock;
+use std::time::Duration;
 // use std::io; // not used
 
 // Глобальная переменная для хранения install_path в текущей сессии
@@ -20,29 +54,85 @@ static SESSION_INSTALL_PATH: OnceLock<PathBuf> = OnceLock::new();
 async fn main() {
     // Parse command line arguments
     let cli = Cli::parse_args();
+    output::init(cli.no_color || cli.plain);
 
     // Initialize logging with default INFO (DEBUG if --debug)
-    let mut builder = env_logger::Builder::from_default_env();
-    if cli.debug { builder.filter_level(LevelFilter::Debug); } else { builder.filter_level(LevelFilter::Info); }
-    let _ = builder.try_init();
+    // quickstart shows its own stages instead of the install log
+    let condensed = cli.quiet || matches!(cli.command, Some(Commands::Quickstart { .. }));
+    let level = if cli.debug { LevelFilter::DEBUG } else if condensed { LevelFilter::WARN } else { LevelFilter::INFO };
+    let filter = EnvFilter::builder().with_default_directive(level.into()).from_env_lossy();
+    // Per-subsystem levels: config file first, --log on top
+    let config_path = cli
+        .install_path
+        .clone()
+        .or_else(utils::install_path_from_env)
+        .or_else(workspace::from_current_dir)
+        .map(|p| p.join("portablesource_config.json"));
+    let config_path = config_path.unwrap_or_else(ConfigManager::default_config_path);
+    let config_levels = ConfigManager::log_levels_from_file(&config_path);
+    let mut log_spec = LogSpec::default();
+    if let Some(spec) = config_levels {
+        match LogSpec::parse(&spec) {
+            Ok(spec) => log_spec = spec,
+            Err(e) => output::warn(&format!("Ignoring log_levels from config: {}", e)),
+        }
+    }
+    if let Some(spec) = cli.log.clone() {
+        log_spec = log_spec.merge(spec);
+    }
+    let span_events = if cli.trace { FmtSpan::CLOSE } else { FmtSpan::NONE };
+    let fmt_layer = tracing_subscriber::fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_ansi(!cli.no_color && std::env::var_os("NO_COLOR").is_none() && std::io::stderr().is_terminal())
+        .with_span_events(span_events)
+        .with_filter(log_spec.apply(filter));
+    // Timings are collected whatever the log level
+    let timing_layer = TimingLayer.with_filter(filter_fn(|meta| meta.is_span()));
+    let _ = tracing_subscriber::registry().with(fmt_layer).with(timing_layer).try_init();
+    progress::init(cli.quiet, cli.plain, cli.progress_interval.map(Duration::from_secs));
+    portablesource_rs::prompt::init(cli.plain);
     
     // Run the application
+    let error_format = cli.error_format;
     if let Err(e) = run(cli).await {
-        error!("Application error: {}", e);
-        std::process::exit(1);
+        match (&e, error_format) {
+            (_, ErrorFormat::Json) => eprintln!("{}", e.to_json()),
+            (PortableSourceError::Multi(multi), ErrorFormat::Text) => {
+                output::error(&format!("{} failed for {} of {}", multi.operation, multi.failures.len(), multi.total));
+                eprint!("{}", multi.render());
+            }
+            (_, ErrorFormat::Text) => {
+                output::error(&e.to_string());
+                if let Some(hint) = e.remediation_hint() {
+                    output::hint(&hint);
+                }
+            }
+        }
+        std::process::exit(e.exit_code());
     }
 }
 
 async fn run(cli: Cli) -> Result<()> {
     // Fast-path: commands that don't require config or install_path
     match cli.command.as_ref() {
-        Some(Commands::CheckGpu) => {
-            return check_gpu();
+        Some(Commands::CheckGpu { all }) => {
+            return check_gpu(*all);
+        }
+        Some(Commands::Version { json }) => {
+            return utils::show_version(*json);
         }
-        Some(Commands::Version) => {
-            utils::show_version();
+        Some(Commands::Examples) => {
+            print!("{}", portablesource_rs::cli::EXAMPLES);
             return Ok(());
         }
+        Some(Commands::Init { path }) => {
+            return init_workspace(path.as_deref());
+        }
+        // Before ConfigManager::new, which would fail on a config it cannot read
+        Some(Commands::Config { action: ConfigAction::Migrate { file, dry_run } }) => {
+            let install_path = cli.install_path.clone().or_else(utils::install_path_from_env).or_else(workspace::from_current_dir);
+            return migrate_config(file.as_deref(), *dry_run, install_path.as_deref());
+        }
         _ => {}
     }
 
@@ -52,16 +142,16 @@ async fn run(cli: Cli) -> Result<()> {
     // Handle install path from CLI, registry, config, or default
     // Skip interactive prompt for commands that don't need install_path
     #[cfg(windows)]
-    let needs_install_path = matches!(cli.command, Some(Commands::SetupEnv) | Some(Commands::InstallRepo { .. }) | Some(Commands::UpdateRepo { .. }) | Some(Commands::DeleteRepo { .. }) | Some(Commands::ListRepos) | Some(Commands::CheckEnv));
+    let needs_install_path = matches!(cli.command, Some(Commands::SetupEnv { .. }) | Some(Commands::InstallRepo { .. }) | Some(Commands::UpdateRepo { .. }) | Some(Commands::DeleteRepo { .. }) | Some(Commands::ListRepos) | Some(Commands::CheckEnv));
     #[cfg(unix)]
-    let needs_install_path = matches!(cli.command, Some(Commands::SetupEnv) | Some(Commands::InstallRepo { .. }) | Some(Commands::UpdateRepo { .. }) | Some(Commands::DeleteRepo { .. }) | Some(Commands::ListRepos) | Some(Commands::ChangePath) | Some(Commands::CheckEnv) | Some(Commands::Uninstall));
+    let needs_install_path = matches!(cli.command, Some(Commands::SetupEnv { .. }) | Some(Commands::InstallRepo { .. }) | Some(Commands::UpdateRepo { .. }) | Some(Commands::DeleteRepo { .. }) | Some(Commands::ListRepos) | Some(Commands::ChangePath) | Some(Commands::CheckEnv) | Some(Commands::Uninstall { .. }) | Some(Commands::ExportEnv { .. }) | Some(Commands::ImportEnv { .. }));
     #[cfg(all(not(windows), not(unix)))]
-    let needs_install_path = matches!(cli.command, Some(Commands::SetupEnv) | Some(Commands::InstallRepo { .. }) | Some(Commands::UpdateRepo { .. }) | Some(Commands::DeleteRepo { .. }) | Some(Commands::ListRepos) | Some(Commands::CheckEnv));
+    let needs_install_path = matches!(cli.command, Some(Commands::SetupEnv { .. }) | Some(Commands::InstallRepo { .. }) | Some(Commands::UpdateRepo { .. }) | Some(Commands::DeleteRepo { .. }) | Some(Commands::ListRepos) | Some(Commands::CheckEnv));
 
     let install_path = if let Some(cached_path) = SESSION_INSTALL_PATH.get() {
         // Используем сохраненный путь из текущей сессии
         cached_path.clone()
-    } else if let Some(path) = cli.install_path {
+    } else if let Some(path) = cli.install_path.clone().or_else(utils::install_path_from_env) {
         let validated_path = utils::validate_and_create_path(&path)?;
         config_manager.set_install_path(validated_path.clone())?;
         
@@ -82,6 +172,22 @@ async fn run(cli: Cli) -> Result<()> {
         }
         // Для Windows больше не используем реестр - только портативный режим
         
+        validated_path
+    } else if let Some(workspace) = workspace::from_current_dir() {
+        // Project-local workspace: not recorded as the machine-wide install path
+        info!("Using project workspace {:?}", workspace);
+        let _ = SESSION_INSTALL_PATH.set(workspace.clone());
+        workspace
+    } else if matches!(cli.command, Some(Commands::Quickstart { .. })) {
+        // quickstart never asks: ./portablesource, reused when it already exists
+        let validated_path = utils::validate_and_create_path(&std::env::current_dir()?.join("portablesource"))?;
+        #[cfg(windows)]
+        utils::copy_executable_to_install_path(&validated_path)?;
+        #[cfg(unix)]
+        {
+            let _ = utils::save_install_path_to_registry(&validated_path);
+        }
+        let _ = SESSION_INSTALL_PATH.set(validated_path.clone());
         validated_path
     } else {
         // Портативная логика только для Windows
@@ -92,6 +198,11 @@ async fn run(cli: Cli) -> Result<()> {
                 .parent()
                 .ok_or_else(|| PortableSourceError::installation("Cannot determine current directory".to_string()))?
                 .to_path_buf();
+            // С read-only шары путь установки не выводим и exe не копируем;
+            // установка в режиме read-only и не должна быть доступна для записи
+            if !read_only::is_active(&current_dir, cli.read_only) && !utils::is_dir_writable(&current_dir) {
+                return Err(utils::read_only_location_error(&current_dir));
+            }
             
             // Проверяем, находимся ли мы уже в установленной директории
             if !utils::is_first_installation(&current_dir) {
@@ -135,7 +246,13 @@ async fn run(cli: Cli) -> Result<()> {
         // Для Linux оставляем старую логику
         #[cfg(unix)]
         {
-            if !needs_install_path {
+            if let Some(portable_root) = utils::linux_portable_root() {
+                // Портативный режим: всё хранится рядом с бинарником, как на Windows
+                info!("Linux portable mode: using binary directory {:?}", portable_root);
+                let validated_path = utils::validate_and_create_path(&portable_root)?;
+                let _ = SESSION_INSTALL_PATH.set(validated_path.clone());
+                validated_path
+            } else if !needs_install_path {
                 // Use existing config or silent defaults without prompting
                 if let Some(path) = utils::load_install_path_from_registry()? {
                     utils::validate_and_create_path(&path)?
@@ -152,7 +269,7 @@ async fn run(cli: Cli) -> Result<()> {
                 validated_path
             } else if !config_manager.get_config().install_path.as_os_str().is_empty() {
                 let existing = config_manager.get_config().install_path.clone();
-                if matches!(cli.command, Some(Commands::SetupEnv)) {
+                if matches!(cli.command, Some(Commands::SetupEnv { .. })) {
                     println!("\nCurrent installation path: {}", existing.display());
                     let chosen = utils::prompt_install_path_linux(&existing)?;
                     let _ = utils::save_install_path_to_registry(&chosen);
@@ -164,7 +281,7 @@ async fn run(cli: Cli) -> Result<()> {
                     validated_path
                 }
             } else {
-                if matches!(cli.command, Some(Commands::SetupEnv)) {
+                if matches!(cli.command, Some(Commands::SetupEnv { .. })) {
                     let default_path = utils::default_install_path_linux();
                     let chosen = utils::prompt_install_path_linux(&default_path)?;
                     let _ = utils::save_install_path_to_registry(&chosen);
@@ -184,6 +301,24 @@ async fn run(cli: Cli) -> Result<()> {
     config_manager.set_config_path_to_install_dir();
     // Конфигурация больше не сохраняется на диск - только сессионные настройки
     info!("Using install path: {:?}", install_path);
+    let read_only = read_only::is_active(&install_path, cli.read_only);
+    if read_only && !cli.command.as_ref().is_none_or(Commands::allowed_read_only) {
+        return Err(read_only::refused(&install_path));
+    }
+    config_manager.set_read_only(read_only);
+    let stale_temps = if read_only { Vec::new() } else { atomic_write::recover_stale_temps(&install_path) };
+    for stale in stale_temps {
+        if stale.target_missing {
+            warn!("Interrupted write of {:?} discarded; the file was never completed (regenerate it, e.g. with render-script)", stale.target);
+        } else {
+            warn!("Interrupted write of {:?} discarded; the previous version is kept", stale.target);
+        }
+    }
+    if !read_only {
+        for repaired in portablesource_rs::tool_slots::repair(&install_path.join("ps_env"), Duration::from_secs(24 * 3600)) {
+            info!("Portable tools: {}", repaired);
+        }
+    }
     #[cfg(not(windows))]
     {
         // На Linux работаем как менеджер репозиториев без постоянного конфига
@@ -195,7 +330,7 @@ async fn run(cli: Cli) -> Result<()> {
 
     // Linux: выбор режима CLOUD/DESK и базовая подготовка — только когда действительно готовим базу
     #[cfg(unix)]
-    if matches!(cli.command, Some(Commands::SetupEnv)) {
+    if matches!(cli.command, Some(Commands::SetupEnv { .. })) {
         use portablesource_rs::utils::{detect_linux_mode, LinuxMode, detect_cuda_version_from_system, setup_micromamba_base_env};
         match detect_linux_mode() {
                         LinuxMode::Cloud => {
@@ -224,29 +359,31 @@ async fn run(cli: Cli) -> Result<()> {
                     Some(_) => None,
                     None => {
                         if config_manager.has_cuda() {
-                            if let Some(cuda_version) = config_manager.get_cuda_version() {
-                                Some(match cuda_version {
-                                    portablesource_rs::config::CudaVersion::Cuda128 => portablesource_rs::config::CudaVersionLinux::Cuda128,
-                                    portablesource_rs::config::CudaVersion::Cuda124 => portablesource_rs::config::CudaVersionLinux::Cuda124,
-                                    portablesource_rs::config::CudaVersion::Cuda118 => portablesource_rs::config::CudaVersionLinux::Cuda118,
-                                })
-                            } else {
-                                None
-                            }
+                            config_manager.get_cuda_version().map(|cuda_version| match cuda_version {
+                                portablesource_rs::config::CudaVersion::Cuda128 => portablesource_rs::config::CudaVersionLinux::Cuda128,
+                                portablesource_rs::config::CudaVersion::Cuda124 => portablesource_rs::config::CudaVersionLinux::Cuda124,
+                                portablesource_rs::config::CudaVersion::Cuda118 => portablesource_rs::config::CudaVersionLinux::Cuda118,
+                            })
                         } else {
                             None
                         }
                     }
                 };
-                setup_micromamba_base_env(&install_path, cv)?;
+                setup_micromamba_base_env(&install_path, cv).await?;
             }
         }
     }
     
     // Handle commands
-    match cli.command.as_ref() {
-        Some(Commands::SetupEnv) => {
-            setup_environment(&install_path, &mut config_manager).await
+    let result = match cli.command.as_ref() {
+        Some(Commands::SetupEnv { use_system_tools, yes }) => {
+            setup_environment(&install_path, &mut config_manager, *use_system_tools, *yes).await
+        }
+        Some(Commands::UpgradeTool { tool, rollback }) => {
+            upgrade_tool(tool, *rollback, &install_path, &config_manager).await
+        }
+        Some(Commands::Quickstart { repo, accept_license, no_run, on_error, args }) => {
+            quickstart(repo, args, *accept_license, *no_run, *on_error, &install_path, &mut config_manager).await
         }
         #[cfg(unix)]
         Some(Commands::SetupReg) => {
@@ -261,37 +398,160 @@ async fn run(cli: Cli) -> Result<()> {
             Ok(())
         }
         #[cfg(unix)]
-        Some(Commands::Uninstall) => {
-            utils::uninstall_portablesource(&install_path).await
+        Some(Commands::Uninstall { keep_repos, tools_only, repo, with_models, dry_run }) => {
+            use portablesource_rs::uninstall::UninstallScope;
+            let scope = match (repo, *keep_repos, *tools_only) {
+                (Some(name), _, _) => UninstallScope::Repo { name: name.clone(), with_models: *with_models },
+                (None, true, _) => UninstallScope::KeepRepos { with_models: *with_models },
+                (None, _, true) => UninstallScope::ToolsOnly,
+                _ => UninstallScope::Everything { with_models: *with_models },
+            };
+            utils::uninstall_portablesource(&install_path, &scope, *dry_run).await
         }
         #[cfg(unix)]
         Some(Commands::ChangePath) => {
             change_installation_path(&mut config_manager).await
         }
-        Some(Commands::InstallRepo { repo }) => {
-            install_repository(repo, &install_path, &config_manager).await
+        Some(Commands::InstallRepo { repo, accept_license, engine, instance, branch, worktree_of, no_venv, conda_env, profile, review_plan, on_error, clean_env, extras, copy }) => {
+            let env_target = EnvTarget::from_flags(*no_venv, conda_env.clone())?;
+            let installer = RepositoryInstaller::new(install_path.to_path_buf(), config_manager.clone())
+                .with_install_engine(*engine)
+                .with_license_acceptance(*accept_license)
+                .with_instance_name(instance.clone())
+                .with_branch(branch.clone())
+                .with_worktree_of(worktree_of.clone())
+                .with_env_target(env_target)
+                .with_performance_profile(*profile)
+                .with_plan_review(*review_plan)
+                .with_on_error(*on_error)
+                .with_clean_env(*clean_env)
+                .with_extras(extras.clone());
+            install_repository(repo, installer, *copy, &install_path).await
         }
-        Some(Commands::UpdateRepo { repo }) => {
-            update_repository(repo.clone(), &install_path, &config_manager).await
+        Some(Commands::UpdateRepo { repo, all, engine, review_plan, on_error, dry_run }) => {
+            let mut installer = RepositoryInstaller::new(install_path.to_path_buf(), config_manager.clone())
+                .with_install_engine(*engine)
+                .with_plan_review(*review_plan)
+                .with_on_error(*on_error);
+            if *all {
+                installer.update_all(*dry_run).await
+            } else {
+                update_repository(repo.clone(), installer, *dry_run).await
+            }
+        }
+        Some(Commands::Prefetch { targets }) => {
+            prefetch(targets, &install_path, &config_manager).await
+        }
+        Some(Commands::DownloadModels { repo, manifest, jobs }) => {
+            download_models(repo, manifest.as_deref(), *jobs as usize, &install_path).await
         }
-        Some(Commands::DeleteRepo { repo }) => {
-            delete_repository(repo, &install_path, &config_manager)
+        Some(Commands::ShareModels { repos }) => {
+            share_models(repos, &install_path)
+        }
+        Some(Commands::Plugins) => {
+            list_plugins(&install_path)
+        }
+        Some(Commands::DeleteRepo { repo, dry_run }) => {
+            delete_repository(repo, *dry_run, &install_path, &config_manager)
         }
         Some(Commands::ListRepos) => {
             list_repositories(&install_path, &config_manager)
         }
-        Some(Commands::RunRepo { repo, args }) => {
-            utils::run_repository(repo, &install_path, args).await
+        Some(Commands::RunRepo { repo, args, wait_gpu, no_wait_gpu, min_free_vram, gpu_timeout, no_network, gpu, capture_log }) => {
+            let flags = GpuQueueOverride {
+                enabled: if *wait_gpu { Some(true) } else if *no_wait_gpu { Some(false) } else { None },
+                min_free_vram_mb: *min_free_vram,
+                timeout_secs: *gpu_timeout,
+            };
+            let launch = RunLaunch { no_network: *no_network, gpu: gpu.as_deref(), read_only, capture_log: *capture_log };
+            run_repository(repo, args, &flags, &launch, &install_path, &config_manager).await
+        }
+        Some(Commands::ReportUsage { since, format, output }) => {
+            report_usage(since, *format, output.as_deref(), &install_path)
+        }
+        Some(Commands::Stats { repo, enable, disable, clear }) => {
+            let collect = if *enable { Some(true) } else if *disable { Some(false) } else { None };
+            show_stats(repo.as_deref(), collect, *clear, &install_path, &mut config_manager)
+        }
+        Some(Commands::GpuQueue { repo, enable, disable, min_free_vram, timeout, reset }) => {
+            let changes = GpuQueueOverride {
+                enabled: if *enable { Some(true) } else if *disable { Some(false) } else { None },
+                min_free_vram_mb: *min_free_vram,
+                timeout_secs: *timeout,
+            };
+            configure_gpu_queue(repo.as_deref(), &changes, *reset, &install_path, &mut config_manager)
+        }
+        Some(Commands::ImportLegacy { from, dry_run }) => {
+            import_legacy(from.as_deref(), *dry_run, &install_path, &config_manager)
+        }
+        Some(Commands::RebuildIndex) => {
+            rebuild_index(&install_path, &config_manager)
+        }
+        Some(Commands::InfoRepo { repo, json, copy }) => {
+            info_repository(repo, *json, *copy, &install_path, &config_manager)
+        }
+        Some(Commands::TuneRepo { repo, profile, reset }) => {
+            tune_repository(repo, *profile, *reset, &install_path, &config_manager)
+        }
+        Some(Commands::SwitchBackend { repo, backend }) => {
+            switch_backend(repo, *backend, &install_path, &config_manager)
+        }
+        Some(Commands::Cache { action: CacheAction::Gc { dry_run, min_age_days } }) => {
+            collect_cache_garbage(*dry_run, *min_age_days, &install_path)
+        }
+        Some(Commands::Env { action: EnvAction::Show { repo } }) => {
+            show_launch_env(repo, &install_path, &config_manager)
+        }
+        Some(Commands::Components { repo, add, remove }) => {
+            repo_components(repo.as_deref(), add, remove, &install_path, &config_manager).await
+        }
+        Some(Commands::VerifyRepo { repo, verbose }) => {
+            verify_repository(repo, *verbose, &install_path)
+        }
+        Some(Commands::Which { repo, tool, all }) => {
+            which_tool(repo, tool, *all, &install_path, &config_manager)
+        }
+        Some(Commands::RenderScript { repo, dry_run }) => {
+            render_script(repo, *dry_run, &install_path, &config_manager)
+        }
+        Some(Commands::RegenScripts { repo, force, dry_run }) => {
+            regen_scripts(repo.as_deref(), *force, *dry_run, &install_path, &config_manager)
+        }
+        #[cfg(unix)]
+        Some(Commands::ExportEnv { repo, output, relocatable }) => {
+            export_env(repo, output.as_deref(), *relocatable, &install_path)
+        }
+        #[cfg(unix)]
+        Some(Commands::ImportEnv { archive, name }) => {
+            import_env(archive, name.as_deref(), &install_path)
+        }
+        Some(Commands::CiManifest { repo, output }) => {
+            ci_manifest(repo, output.as_deref(), &install_path)
+        }
+        Some(Commands::Inventory { json }) => {
+            show_inventory(*json, &install_path, &config_manager)
+        }
+        Some(Commands::Bootstrap { output, repos, release }) => {
+            bootstrap(output, repos, release.clone(), &install_path, &config_manager)
         }
         Some(Commands::SystemInfo) => {
             show_system_info(&mut config_manager).await
         }
+        Some(Commands::TestRepo { repo }) => {
+            repair_venv_if_broken(repo, &install_path, &config_manager).await.and_then(|_| test_repository(repo, 1, &install_path))
+        }
+        Some(Commands::Benchmark { repo, iterations }) => {
+            repair_venv_if_broken(repo, &install_path, &config_manager).await.and_then(|_| test_repository(repo, *iterations, &install_path))
+        }
         Some(Commands::CheckEnv) => {
             check_environment(&install_path, &config_manager).await
         }
+        Some(Commands::Doctor { timings }) => {
+            doctor(*timings, read_only, &install_path, &config_manager).await
+        }
         #[cfg(windows)]
         Some(Commands::InstallMsvc) => {
-            utils::install_msvc_build_tools()
+            utils::install_msvc_build_tools().await
         }
         #[cfg(windows)]
         Some(Commands::CheckMsvc) => {
@@ -299,57 +559,132 @@ async fn run(cli: Cli) -> Result<()> {
             println!("MSVC Build Tools: {}", if installed { "Installed" } else { "Not installed" });
             Ok(())
         }
-        Some(Commands::CheckGpu) => {
-            check_gpu()
+        Some(Commands::Backup { action }) => {
+            match action {
+                BackupAction::Create { target, with_envs, exclude, no_default_excludes } => {
+                    let options = portablesource_rs::backup::BackupOptions {
+                        with_envs: *with_envs,
+                        excludes: exclude.clone(),
+                        no_default_excludes: *no_default_excludes,
+                    };
+                    backup_create(target, &options, &install_path)
+                }
+                BackupAction::Restore { archive, force } => backup_restore(archive, *force, &install_path),
+            }
         }
-        Some(Commands::Version) => {
-            utils::show_version();
-            Ok(())
+        Some(Commands::Schedule { action }) => {
+            match action {
+                ScheduleAction::Enable { weekly, daily } => {
+                    let (frequency, commands) = match (weekly, daily) {
+                        (Some(w), _) => (ScheduleFrequency::Weekly, w),
+                        (None, Some(d)) => (ScheduleFrequency::Daily, d),
+                        (None, None) => unreachable!("clap requires --weekly or --daily"),
+                    };
+                    scheduler::enable_schedule(&install_path, frequency, commands)
+                }
+                ScheduleAction::Disable => scheduler::disable_schedule(&install_path),
+                ScheduleAction::Status => scheduler::show_schedule_status(&install_path),
+                ScheduleAction::Run => scheduler::run_scheduled_maintenance(&install_path),
+            }
+        }
+        Some(Commands::CheckGpu { all }) => {
+            check_gpu(*all)
+        }
+        Some(Commands::Config { action: ConfigAction::ReadOnly { message, off } }) => {
+            set_read_only(*off, message.as_deref(), &install_path)
+        }
+        Some(Commands::Config { action: ConfigAction::BuildSlots { max } }) => {
+            configure_build_slots(*max, &mut config_manager)
+        }
+        #[cfg(unix)]
+        Some(Commands::Config { action: ConfigAction::InstallSandbox { policy } }) => {
+            configure_install_sandbox(*policy, &mut config_manager)
+        }
+        Some(Commands::Config { action: ConfigAction::Migrate { .. } }) | Some(Commands::Examples) | Some(Commands::Init { .. }) | Some(Commands::Version { .. }) => {
+            unreachable!("handled before config loading")
         }
         None => {
             // No command provided, show system info by default
             show_system_info(&mut config_manager).await
         }
+    };
+
+    if !matches!(cli.command, Some(Commands::Doctor { .. })) {
+        let command = std::env::args().skip(1).collect::<Vec<_>>().join(" ");
+        if let Err(e) = timings::save(&read_only::writable_root(&install_path, read_only), &command) {
+            warn!("Could not save operation timings: {}", e);
+        }
+    }
+    result
+}
+
+/// Show what setup-env is about to download and ask before starting; false when declined
+async fn review_downloads(mut plan: DownloadPlan, yes: bool) -> bool {
+    if plan.is_empty() {
+        return true;
     }
+    plan.measure_sizes().await;
+    output::info("setup-env will download:");
+    println!("{}", plan.render());
+    yes || !std::io::stdin().is_terminal() || portablesource_rs::prompt::confirm("Start the downloads?")
 }
 
-async fn setup_environment(install_path: &PathBuf, config_manager: &mut ConfigManager) -> Result<()> {
+async fn setup_environment(install_path: &Path, config_manager: &mut ConfigManager, use_system_tools: bool, yes: bool) -> Result<()> {
     // Create directory structure
     utils::create_directory_structure(install_path)?;
     
     // Windows: ставим портативные инструменты (tar zstd архивы)
     #[cfg(windows)]
     {
+        // System python/git are chosen anew on every setup; without the flag the portable ones return
+        let system_tools = if use_system_tools {
+            PortableEnvironmentManager::with_config(install_path.to_path_buf(), config_manager.clone()).detect_system_tools()
+        } else {
+            Default::default()
+        };
+        if config_manager.get_config().system_tools != system_tools {
+            config_manager.get_config_mut().system_tools = system_tools;
+            config_manager.save_config()?;
+        }
         // Initialize environment manager
-        let env_manager = PortableEnvironmentManager::new(install_path.clone());
+        let env_manager = PortableEnvironmentManager::with_config(install_path.to_path_buf(), config_manager.clone());
+        if !review_downloads(env_manager.planned_downloads(), yes).await {
+            println!("Cancelled.");
+            return Ok(());
+        }
         // Setup environment via portable archives
         env_manager.setup_environment().await?;
     }
 
+    #[cfg(unix)]
+    if use_system_tools {
+        output::info("--use-system-tools applies to Windows; Linux chooses system tools through the CLOUD/DESK mode");
+    }
+
     // Linux/macOS: используем системный tar, готовим базу через micromamba
     #[cfg(unix)]
     {
-        use portablesource_rs::utils::{detect_cuda_version_from_system, setup_micromamba_base_env};
+        use portablesource_rs::utils::{detect_cuda_version_from_system, micromamba_download_plan, setup_micromamba_base_env};
         // Если системная CUDA есть — не ставим CUDA в базу
         let cv = match detect_cuda_version_from_system() {
             Some(_) => None,
             None => {
                 if config_manager.has_cuda() {
-                    if let Some(cuda_version) = config_manager.get_cuda_version() {
-                        Some(match cuda_version {
-                            portablesource_rs::config::CudaVersion::Cuda128 => portablesource_rs::config::CudaVersionLinux::Cuda128,
-                            portablesource_rs::config::CudaVersion::Cuda124 => portablesource_rs::config::CudaVersionLinux::Cuda124,
-                            portablesource_rs::config::CudaVersion::Cuda118 => portablesource_rs::config::CudaVersionLinux::Cuda118,
-                        })
-                    } else {
-                        None
-                    }
+                    config_manager.get_cuda_version().map(|cuda_version| match cuda_version {
+                        portablesource_rs::config::CudaVersion::Cuda128 => portablesource_rs::config::CudaVersionLinux::Cuda128,
+                        portablesource_rs::config::CudaVersion::Cuda124 => portablesource_rs::config::CudaVersionLinux::Cuda124,
+                        portablesource_rs::config::CudaVersion::Cuda118 => portablesource_rs::config::CudaVersionLinux::Cuda118,
+                    })
                 } else {
                     None
                 }
             }
         };
-        setup_micromamba_base_env(install_path, cv)?;
+        if !review_downloads(micromamba_download_plan(install_path, cv.as_ref()), yes).await {
+            println!("Cancelled.");
+            return Ok(());
+        }
+        setup_micromamba_base_env(install_path, cv).await?;
     }
     
     // GPU detection is now handled dynamically by ConfigManager
@@ -373,6 +708,91 @@ async fn setup_environment(install_path: &PathBuf, config_manager: &mut ConfigMa
     Ok(())
 }
 
+/// How long quickstart waits for GPU detection before installing CPU packages
+const QUICKSTART_GPU_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// NVIDIA GPU found within `timeout`; detection that hangs (a stuck driver) counts as none
+fn detect_cuda_gpu_within(timeout: Duration) -> Option<GpuInfo> {
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let _ = tx.send(GpuDetector::new().detect_nvidia_gpu().ok().flatten());
+    });
+    rx.recv_timeout(timeout).ok().flatten()
+}
+
+fn init_workspace(project: Option<&Path>) -> Result<()> {
+    let project = match project {
+        Some(path) => utils::validate_and_create_path(path)?,
+        None => std::env::current_dir()?,
+    };
+    let (path, created) = workspace::init(&project)?;
+    if created {
+        output::success(&format!("Created workspace {}", path.display()));
+    } else {
+        output::info(&format!("Workspace {} already exists", path.display()));
+    }
+    println!("Commands run in {} or below now use it. Next: portablesource setup-env", project.display());
+    Ok(())
+}
+
+async fn upgrade_tool(tool: &str, rollback: bool, install_path: &Path, config_manager: &ConfigManager) -> Result<()> {
+    let env_manager = PortableEnvironmentManager::with_config(install_path.to_path_buf(), config_manager.clone());
+    let outcome = if rollback { env_manager.rollback_tool(tool)? } else { env_manager.upgrade_tool(tool).await? };
+    match outcome {
+        ToolUpgrade::UpToDate(slot) => output::success(&format!("{} is up to date ({})", tool, slot)),
+        ToolUpgrade::Upgraded { from, to } => output::success(&format!("{} switched from {} to {}", tool, from, to)),
+    }
+    Ok(())
+}
+
+async fn quickstart(repo: &str, args: &[String], accept_license: bool, no_run: bool, on_error: OnStepError, install_path: &Path, config_manager: &mut ConfigManager) -> Result<()> {
+    let started = std::time::Instant::now();
+    let stages = if no_run { 3 } else { 4 };
+    let stage = |n: usize, what: &str| output::step(&format!("[{}/{}] {}", n, stages, what));
+
+    stage(1, "Detecting GPU");
+    match detect_cuda_gpu_within(QUICKSTART_GPU_TIMEOUT) {
+        Some(gpu) => output::info(&format!("Using {}", gpu.name)),
+        None => {
+            config_manager.set_cpu_only();
+            output::info(&format!("No CUDA GPU found within {}s; installing CPU packages", QUICKSTART_GPU_TIMEOUT.as_secs()));
+        }
+    }
+
+    stage(2, &format!("Preparing tools in {}", install_path.display()));
+    if config_manager.get_config().environment_setup_completed {
+        output::info("Tools already set up");
+    } else {
+        setup_environment(install_path, config_manager, false, true).await?;
+    }
+
+    let mut installer = RepositoryInstaller::new(install_path.to_path_buf(), config_manager.clone())
+        .with_license_acceptance(accept_license)
+        .with_prompts(false)
+        .with_on_error(on_error);
+    let name = if !installer.is_repository_url(repo) && launch_command::start_script(install_path, repo).exists() {
+        stage(3, &format!("{} is already installed", repo));
+        repo.to_string()
+    } else {
+        stage(3, &format!("Installing {}", repo));
+        if on_error == OnStepError::SkipOptional {
+            output::info("Optional steps that fail (Triton, InsightFace) are skipped; see info-repo afterwards");
+        }
+        installer.install_repository(repo).await?;
+        installer.installed_name().unwrap_or(repo).to_string()
+    };
+
+    let elapsed = started.elapsed().as_secs();
+    output::success(&format!("{} is ready ({}m{:02}s)", name, elapsed / 60, elapsed % 60));
+    if no_run {
+        return launch_command::show(install_path, &name, false);
+    }
+    stage(4, &format!("Launching {}", name));
+    let flags = GpuQueueOverride { enabled: Some(false), ..Default::default() };
+    let launch = RunLaunch { no_network: false, gpu: None, read_only: false, capture_log: false };
+    run_repository(&name, args, &flags, &launch, install_path, config_manager).await
+}
+
 #[cfg(unix)]
 async fn change_installation_path(config_manager: &mut ConfigManager) -> Result<()> {
     println!("Enter new installation path:");
@@ -392,15 +812,28 @@ async fn change_installation_path(config_manager: &mut ConfigManager) -> Result<
     Ok(())
 }
 
-async fn install_repository(repo: &str, install_path: &PathBuf, config_manager: &ConfigManager) -> Result<()> {
-    let mut installer = RepositoryInstaller::new(install_path.clone(), config_manager.clone());
-    installer.install_repository(repo).await
+async fn install_repository(repo: &str, mut installer: RepositoryInstaller, copy: bool, install_path: &Path) -> Result<()> {
+    installer.install_repository(repo).await?;
+    let name = installer.installed_name().unwrap_or(repo);
+    match native_deps::scan_repo(install_path, name) {
+        Ok(report) if !report.mismatches.is_empty() => {
+            for mismatch in &report.mismatches {
+                output::warn(&mismatch.describe());
+            }
+            output::hint(&format!("Details: portablesource verify-repo {}", name));
+        }
+        Ok(_) => {}
+        Err(e) => tracing::debug!("Native library check skipped: {}", e),
+    }
+    if let Err(e) = launch_command::show(install_path, name, copy) {
+        output::warn(&e.to_string());
+    }
+    Ok(())
 }
 
-async fn update_repository(repo: Option<String>, install_path: &PathBuf, config_manager: &ConfigManager) -> Result<()> {
-    let mut installer = RepositoryInstaller::new(install_path.clone(), config_manager.clone());
+async fn update_repository(repo: Option<String>, mut installer: RepositoryInstaller, dry_run: bool) -> Result<()> {
     if let Some(name) = repo {
-        return installer.update_repository(&name).await;
+        return update_one(&mut installer, &name, dry_run).await;
     }
 
     // Simple TUI: показать список и выбрать номер
@@ -428,16 +861,894 @@ async fn update_repository(repo: Option<String>, install_path: &PathBuf, config_
     }
 
     let selected = &names[choice - 1];
-    installer.update_repository(selected).await
+    update_one(&mut installer, selected, dry_run).await
+}
+
+/// Update one repository, or with `dry_run` only show what the update would change
+async fn update_one(installer: &mut RepositoryInstaller, name: &str, dry_run: bool) -> Result<()> {
+    if dry_run {
+        installer.plan_update(name)?.print_dry_run();
+        return Ok(());
+    }
+    installer.update_repository(name).await
+}
+
+async fn prefetch(targets: &[String], install_path: &Path, config_manager: &ConfigManager) -> Result<()> {
+    let repos = portablesource_rs::prefetch::expand_targets(targets)?;
+    if repos.is_empty() {
+        println!("Nothing to prefetch");
+        return Ok(());
+    }
+    let installer = RepositoryInstaller::new(install_path.to_path_buf(), config_manager.clone());
+    let mut failed = MultiError::new("prefetch", repos.len());
+    for (i, repo) in repos.iter().enumerate() {
+        output::step(&format!("Prefetching {} ({}/{})", repo, i + 1, repos.len()));
+        if let Err(e) = installer.prefetch_repository(repo).await {
+            output::error(&format!("Failed to prefetch '{}': {}", repo, e));
+            failed.push(repo, &e);
+        }
+    }
+    failed.into_result()?;
+    output::success(&format!(
+        "{} repositories prefetched into {}",
+        repos.len(),
+        portablesource_rs::prefetch::wheel_cache_dir(install_path).display()
+    ));
+    Ok(())
+}
+
+async fn download_models(repo: &str, manifest: Option<&Path>, jobs: usize, install_path: &Path) -> Result<()> {
+    use portablesource_rs::models::{self, ModelManifest, RepoLayout};
+    let repo_path = install_path.join("repos").join(repo);
+    if !repo_path.exists() {
+        return Err(PortableSourceError::repository_not_found(repo));
+    }
+    let manifest_path = manifest.map(Path::to_path_buf).unwrap_or_else(|| repo_path.join(models::MANIFEST_FILE));
+    let manifest = ModelManifest::load(&manifest_path)?;
+    let layout = RepoLayout::detect(&portablesource_rs::repo_metadata::upstream_name(&repo_path));
+    let files = models::plan(&manifest, &repo_path, layout)?;
+    output::step(&format!("Fetching {} model file(s) for {} ({} at a time)", files.len(), repo, jobs));
+    let report = models::download_all(&portablesource_rs::system::HttpDownloader, &files, jobs).await?;
+    output::success(&format!("{} file(s) downloaded, {} already in place", report.downloaded, report.skipped));
+    Ok(())
+}
+
+fn list_plugins(install_path: &Path) -> Result<()> {
+    use portablesource_rs::plugins;
+    let (found, warnings) = plugins::discover(install_path);
+    for warning in warnings {
+        output::warn(&warning);
+    }
+    if found.is_empty() {
+        output::info(&format!("No plugins in {}", plugins::plugins_dir(install_path).display()));
+        return Ok(());
+    }
+    for plugin in found {
+        let hooks: Vec<String> = plugin.manifest.hooks.iter().map(|h| h.to_string()).collect();
+        let repos = if plugin.manifest.repos.is_empty() { "all repositories".to_string() } else { plugin.manifest.repos.join(", ") };
+        println!("{:<20} {:<30} {}", plugin.manifest.name, hooks.join(", "), repos);
+    }
+    Ok(())
+}
+
+fn share_models(repos: &[String], install_path: &Path) -> Result<()> {
+    use portablesource_rs::models::RepoLayout;
+    use portablesource_rs::shared_models::{self, LinkOutcome};
+    let names = if repos.is_empty() {
+        portablesource_rs::repo_index::RepoIndex::load_or_refresh(install_path)?.repos.into_keys().collect()
+    } else {
+        repos.to_vec()
+    };
+    let mut conflicts = 0;
+    for name in &names {
+        let repo_path = install_path.join("repos").join(name);
+        if !repo_path.exists() {
+            return Err(PortableSourceError::repository_not_found(name));
+        }
+        let layout = RepoLayout::detect(&portablesource_rs::repo_metadata::upstream_name(&repo_path));
+        let links = shared_models::share_repo_models(install_path, &repo_path, layout)?;
+        if links.is_empty() {
+            output::info(&format!("{}: no known model folders to share", name));
+        }
+        for link in links {
+            let folder = link.link.strip_prefix(&repo_path).unwrap_or(&link.link).display().to_string();
+            match link.outcome {
+                LinkOutcome::Linked(kind) => output::success(&format!("{}: {} -> {} ({})", name, folder, link.target.display(), kind)),
+                LinkOutcome::AlreadyLinked => output::info(&format!("{}: {} already shared", name, folder)),
+                LinkOutcome::Conflicts(left) => {
+                    conflicts += 1;
+                    output::warn(&format!("{}: {} not linked, {} file(s) also exist in {}", name, folder, left.len(), link.target.display()));
+                    for file in left {
+                        output::hint(&format!("compare and remove one copy: {}", file.display()));
+                    }
+                }
+            }
+        }
+    }
+    if conflicts > 0 {
+        output::hint("Run share-models again once the duplicates are resolved");
+    }
+    Ok(())
+}
+
+fn backup_create(target: &Path, options: &portablesource_rs::backup::BackupOptions, install_path: &Path) -> Result<()> {
+    output::step(&format!("Backing up {:?} to {:?}", install_path, target));
+    let manifest = portablesource_rs::backup::create(install_path, target, options)?;
+    let size = std::fs::metadata(target).map(|m| m.len()).unwrap_or(0);
+    output::success(&format!(
+        "{} repositories and {} environments backed up ({:.1} MB)",
+        manifest.repos.len(),
+        manifest.envs.len(),
+        size as f64 / (1024.0 * 1024.0)
+    ));
+    if !options.no_default_excludes {
+        output::info("Models, outputs and caches were left out; use --no-default-excludes to include them");
+    }
+    Ok(())
+}
+
+fn backup_restore(archive: &Path, force: bool, install_path: &Path) -> Result<()> {
+    output::step(&format!("Restoring {:?} into {:?}", archive, install_path));
+    let report = portablesource_rs::backup::restore(install_path, archive, force)?;
+    if report.old_install_path != install_path.to_string_lossy() {
+        output::info(&format!("Paths rewritten from {}", report.old_install_path));
+    }
+    output::success(&format!("{} repositories and {} environments restored", report.repos.len(), report.envs.len()));
+    if !report.skipped.is_empty() {
+        output::warn(&format!("Already present, not restored: {}", report.skipped.join(", ")));
+        output::hint("Use --force to replace them");
+    }
+    if !report.foreign_envs.is_empty() {
+        output::warn(&format!("Environments from another platform were skipped: {}", report.foreign_envs.join(", ")));
+        output::hint("Run update-repo for each repository to rebuild its environment");
+    }
+    if report.config_restored {
+        output::info("Configuration restored (the previous one was kept as .bak)");
+    }
+    output::hint("Run setup-env to download the portable tools, which are not part of backups");
+    Ok(())
+}
+
+fn test_repository(repo: &str, iterations: u32, install_path: &Path) -> Result<()> {
+    output::step(&format!("Running CUDA check for '{}'", repo));
+    let (stdout, report) = portablesource_rs::gpu_monitor::cuda_check(install_path, repo, iterations)?;
+    for line in stdout.lines() {
+        output::info(line);
+    }
+    if let Some(context) = report.busy_context() {
+        output::warn(&format!("{}; results may be lower than usual", context));
+    }
+    output::success(&format!("'{}' passed the CUDA check", repo));
+    Ok(())
+}
+
+/// Launch flags of run-repo besides GPU queueing
+struct RunLaunch<'a> {
+    no_network: bool,
+    /// `--gpu` selector: GPU index, `gpu:mig` or UUID
+    gpu: Option<&'a str>,
+    read_only: bool,
+    /// `--capture-log`: keep the end of stderr even when it is a terminal
+    capture_log: bool,
+}
+
+async fn run_repository(repo: &str, args: &[String], flags: &GpuQueueOverride, launch: &RunLaunch<'_>, install_path: &Path, config_manager: &ConfigManager) -> Result<()> {
+    let read_only = launch.read_only;
+    let repo_path = install_path.join("repos").join(repo);
+    let (mut visible_device, gpu_index) = match launch.gpu {
+        Some(selector) => {
+            let (device, index) = visible_device(selector)?;
+            (Some(device), index)
+        }
+        None => (None, None),
+    };
+    if visible_device.is_none() && !check_session_gpu_access(repo, install_path, config_manager) {
+        visible_device = Some(gpu_session::HIDE_GPUS.to_string());
+    }
+    let queue = run_queue::effective_queue_config(&config_manager.get_config().gpu_queue, &repo_path, flags)?;
+    run_queue::wait_for_idle_gpu(repo, &queue, gpu_index)?;
+    if !read_only {
+        repair_venv_if_broken(repo, install_path, config_manager).await?;
+    } else if !venv_repair::dangling_links(&shared_env::repo_env_prefix(install_path, repo)?).is_empty() {
+        return Err(PortableSourceError::environment(format!(
+            "The Python environment of '{}' is broken and this installation is read-only; ask its administrator to run 'portablesource update-repo {}'",
+            repo, repo
+        )));
+    }
+    // A read-only install keeps no run statistics, and the repository's temp files and run
+    // log go to the user
+    let options = utils::RunOptions {
+        no_network: launch.no_network,
+        visible_device,
+        record_stats: config_manager.get_config().collect_run_stats && !read_only,
+        tmp_dir: read_only.then(|| read_only::user_dir(install_path).join("tmp")),
+        // Piping a terminal would cost the repository its progress bars and colors
+        capture_stderr: launch.capture_log || !std::io::stderr().is_terminal(),
+        log_dir: if read_only { read_only::user_dir(install_path).join("logs") } else { install_path.join("logs") },
+    };
+    let result = utils::run_repository(repo, install_path, args, &options).await;
+    if matches!(result, Err(PortableSourceError::CudaOutOfMemory { .. })) {
+        advise_after_oom(repo, args, read_only, install_path, config_manager)?;
+    }
+    result
+}
+
+/// In Remote Desktop and desktop-less Windows sessions, create a CUDA context before the
+/// launch and explain a failure; false when the user chose to run on the CPU instead
+fn check_session_gpu_access(repo: &str, install_path: &Path, config_manager: &ConfigManager) -> bool {
+    let session = SessionKind::current();
+    if !session.may_lack_gpu() || !GpuDetector::new().has_nvidia_gpu() {
+        return true;
+    }
+    let env_manager = PortableEnvironmentManager::with_config(install_path.to_path_buf(), config_manager.clone());
+    let probe = gpu_session::probe_cuda(&SystemExecutor, env_manager.get_python_executable().as_deref());
+    if probe.is_ok() || matches!(probe, gpu_session::CudaProbe::NotRun(_)) {
+        return true;
+    }
+    output::warn(&format!("This {} session cannot use the GPU: {}", session.describe(), probe.summary()));
+    for line in gpu_session::advice(session, &probe) {
+        output::hint(&line);
+    }
+    !(std::io::stdin().is_terminal() && portablesource_rs::prompt::confirm(&format!("Run '{}' on the CPU this time?", repo)))
+}
+
+/// Targeted advice after a run ran out of GPU memory; offers to switch the start script to
+/// the low-vram profile
+fn advise_after_oom(repo: &str, args: &[String], read_only: bool, install_path: &Path, config_manager: &ConfigManager) -> Result<()> {
+    let repo_path = install_path.join("repos").join(repo);
+    let profile = RepoRunSettings::load(&repo_path)?.performance;
+    output::warn(&format!("'{}' ran out of GPU memory", repo));
+    for line in oom_advice::advise(repo, &repo_metadata::upstream_name(&repo_path), args, profile) {
+        output::hint(&line);
+    }
+    if profile == Some(PerformanceProfile::LowVram) || read_only || !std::io::stdin().is_terminal() {
+        return Ok(());
+    }
+    if portablesource_rs::prompt::confirm(&format!("Switch '{}' to the low-vram profile now?", repo)) {
+        tune_repository(repo, Some(PerformanceProfile::LowVram), false, install_path, config_manager)?;
+    }
+    Ok(())
+}
+
+/// Fix a repository venv whose interpreter links dangle (base Python upgraded or moved):
+/// relink to the current base Python, or offer a rebuild when its version changed
+async fn repair_venv_if_broken(repo: &str, install_path: &Path, config_manager: &ConfigManager) -> Result<()> {
+    // A shared environment is not ours to relink or rebuild
+    if EnvTarget::load(&install_path.join("repos").join(repo))?.is_some() {
+        return Ok(());
+    }
+    let venv = shared_env::repo_env_prefix(install_path, repo)?;
+    let links = venv_repair::dangling_links(&venv);
+    if links.is_empty() {
+        return Ok(());
+    }
+    output::warn(&format!("The Python environment of '{}' links to an interpreter that no longer exists: {}", repo, links[0].display()));
+
+    let env_manager = PortableEnvironmentManager::with_config(install_path.to_path_buf(), config_manager.clone());
+    let base = env_manager
+        .get_python_executable()
+        .or_else(|| which::which("python3").ok())
+        .ok_or_else(|| PortableSourceError::environment("No base Python found to repair the environment; run setup-env"))?;
+    let version_output = std::process::Command::new(&base).arg("--version").output()?;
+    let version_text = format!("{}{}", String::from_utf8_lossy(&version_output.stdout), String::from_utf8_lossy(&version_output.stderr));
+    let (major, minor, _) = envs_manager::parse_tool_version(&version_text)
+        .ok_or_else(|| PortableSourceError::environment(format!("Cannot tell the version of {}", base.display())))?;
+
+    let venv_version = venv_repair::venv_python_version(&venv);
+    match venv_repair::plan_repair(venv_version, (major, minor)) {
+        Repair::Relink => {
+            venv_repair::relink(&venv, &base)?;
+            output::success(&format!("Environment of '{}' relinked to {}", repo, base.display()));
+        }
+        Repair::Rebuild => {
+            let was = venv_version.map(|(a, b)| format!("{}.{}", a, b)).unwrap_or_else(|| "an unknown version".into());
+            let question = format!("Python changed from {} to {}.{}. Rebuild the environment of '{}' from its requirements?", was, major, minor, repo);
+            if !std::io::stdin().is_terminal() || !portablesource_rs::prompt::confirm(&question) {
+                return Err(PortableSourceError::environment(format!(
+                    "The environment of '{}' was made for Python {} and must be rebuilt: portablesource update-repo {}",
+                    repo, was, repo
+                )));
+            }
+            RepositoryInstaller::new(install_path.to_path_buf(), config_manager.clone()).rebuild_environment(repo).await?;
+            output::success(&format!("Environment of '{}' rebuilt", repo));
+        }
+    }
+    Ok(())
+}
+
+fn configure_build_slots(max: Option<usize>, config_manager: &mut ConfigManager) -> Result<()> {
+    if let Some(max) = max {
+        config_manager.get_config_mut().max_native_builds = max;
+        config_manager.save_config()?;
+    }
+    match config_manager.get_config().max_native_builds {
+        0 => println!("Native builds at once: no limit"),
+        max => println!("Native builds at once: {}", max),
+    }
+    let dir = portablesource_rs::build_slots::slots_dir();
+    println!("Slots (shared by every installation on this machine): {}", dir.display());
+    Ok(())
+}
+
+#[cfg(unix)]
+fn configure_install_sandbox(policy: Option<SandboxPolicy>, config_manager: &mut ConfigManager) -> Result<()> {
+    if let Some(policy) = policy {
+        config_manager.get_config_mut().install_sandbox = policy;
+        config_manager.save_config()?;
+    }
+    let policy = config_manager.get_config().install_sandbox;
+    println!("Install sandbox: {}", policy);
+    if policy != SandboxPolicy::Off {
+        match Sandbox::detect() {
+            Ok(sandbox) => output::info(&format!("Install hooks run under {}", sandbox.name())),
+            Err(reason) if policy == SandboxPolicy::Require => output::warn(&format!("Installs will be refused: {}", reason)),
+            Err(reason) => output::warn(&format!("Installs will run without a sandbox: {}", reason)),
+        }
+    }
+    Ok(())
 }
 
-fn delete_repository(repo: &str, install_path: &PathBuf, config_manager: &ConfigManager) -> Result<()> {
-    let installer = RepositoryInstaller::new(install_path.clone(), config_manager.clone());
-    installer.delete_repository(repo)
+fn set_read_only(off: bool, message: Option<&str>, install_path: &Path) -> Result<()> {
+    // Users of a shared install must not lift the mode; only an account that can write to it
+    if read_only::marker_path(install_path).exists() && !utils::is_dir_writable(install_path) {
+        return Err(PortableSourceError::installation(format!(
+            "{} is read-only and this account cannot write to it; ask the administrator to change read-only mode",
+            install_path.display()
+        )));
+    }
+    if off {
+        if read_only::disable(install_path)? {
+            output::success(&format!("{} is writable again", install_path.display()));
+        } else {
+            output::info(&format!("{} was not read-only", install_path.display()));
+        }
+        return Ok(());
+    }
+    read_only::enable(install_path, message)?;
+    output::success(&format!("{} is now read-only: only run-repo and inspecting commands will work", install_path.display()));
+    output::hint("Also remove write permission for other users, e.g. 'chmod -R go-w' on Linux; the mode itself only stops portablesource");
+    Ok(())
+}
+
+fn report_usage(since: &str, format: ReportFormat, output_file: Option<&Path>, install_path: &Path) -> Result<()> {
+    let period = history::parse_since(since)?;
+    let start = utils::unix_timestamp().saturating_sub(period.as_secs());
+    let report = history::report(&history::load(install_path)?, start);
+    let text = match format {
+        ReportFormat::Text => history::to_text(&report, since),
+        ReportFormat::Csv => history::to_csv(&report),
+        ReportFormat::Json => serde_json::to_string_pretty(&report)? + "\n",
+    };
+    match output_file {
+        Some(path) => {
+            std::fs::write(path, text)?;
+            output::success(&format!("Usage report written to {}", path.display()));
+        }
+        None => print!("{}", text),
+    }
+    Ok(())
+}
+
+fn show_stats(repo: Option<&str>, collect: Option<bool>, clear: bool, install_path: &Path, config_manager: &mut ConfigManager) -> Result<()> {
+    if let Some(collect) = collect {
+        config_manager.get_config_mut().collect_run_stats = collect;
+        config_manager.save_config()?;
+    }
+    println!("Run statistics collection: {}", if config_manager.get_config().collect_run_stats { "enabled" } else { "disabled" });
+    let Some(repo) = repo else { return Ok(()) };
+
+    let repo_path = install_path.join("repos").join(repo);
+    if !repo_path.exists() {
+        return Err(PortableSourceError::repository(format!("Repository '{}' not installed", repo)));
+    }
+    let mut metadata = RepoMetadata::load(&repo_path)?.unwrap_or_else(|| RepoMetadata { name: repo.to_string(), ..Default::default() });
+    if clear {
+        metadata.runs.clear();
+        metadata.save(&repo_path)?;
+        output::success(&format!("Run statistics of '{}' cleared", repo));
+        return Ok(());
+    }
+    if metadata.runs.is_empty() {
+        println!("No runs recorded for '{}'", repo);
+        return Ok(());
+    }
+
+    let all: Vec<_> = metadata.runs.iter().collect();
+    let total = run_stats::summarize(&all);
+    let mb = |v: Option<u64>| v.map(|v| format!("{} MB", v)).unwrap_or_else(|| "-".to_string());
+    let pct = |v: Option<f32>| v.map(|v| format!("{:.0}%", v)).unwrap_or_else(|| "-".to_string());
+    println!("Repository: {}", repo);
+    println!("  runs:              {} ({} succeeded)", total.runs, total.succeeded);
+    println!("  average duration:  {}s", total.avg_duration_secs);
+    println!("  average GPU util:  {}", pct(total.avg_gpu_util_pct));
+    println!("  peak VRAM:         {}", mb(total.max_peak_vram_mb));
+    println!("  VRAM added by run: {}", mb(total.max_added_vram_mb));
+    if let Some(last) = metadata.runs.last() {
+        println!("  last exit code:    {}", last.exit_code.map(|c| c.to_string()).unwrap_or_else(|| "-".to_string()));
+    }
+
+    println!();
+    println!("{:<40} {:>5} {:>5} {:>9} {:>6} {:>11}", "Arguments", "Runs", "OK", "Avg time", "GPU", "Peak VRAM");
+    for (args, summary) in run_stats::summarize_by_args(&metadata.runs) {
+        let args = if args.is_empty() { "(none)".to_string() } else { args };
+        println!(
+            "{:<40} {:>5} {:>5} {:>8}s {:>6} {:>11}",
+            args, summary.runs, summary.succeeded, summary.avg_duration_secs, pct(summary.avg_gpu_util_pct), mb(summary.max_peak_vram_mb)
+        );
+    }
+    Ok(())
+}
+
+fn configure_gpu_queue(repo: Option<&str>, changes: &GpuQueueOverride, reset: bool, install_path: &Path, config_manager: &mut ConfigManager) -> Result<()> {
+    let global = config_manager.get_config().gpu_queue.clone();
+    let effective = match repo {
+        Some(repo) => {
+            let repo_path = install_path.join("repos").join(repo);
+            if !repo_path.exists() {
+                return Err(PortableSourceError::repository(format!("Repository '{}' not installed", repo)));
+            }
+            let mut settings = RepoRunSettings::load(&repo_path)?;
+            if reset {
+                settings.gpu_queue = GpuQueueOverride::default();
+            }
+            settings.gpu_queue.merge(changes);
+            if reset || !changes.is_empty() {
+                settings.save(&repo_path)?;
+            }
+            settings.gpu_queue.apply(&global)
+        }
+        None => {
+            if !changes.is_empty() {
+                config_manager.get_config_mut().gpu_queue = changes.apply(&global);
+                config_manager.save_config()?;
+            }
+            config_manager.get_config().gpu_queue.clone()
+        }
+    };
+
+    println!("GPU queue ({}):", repo.unwrap_or("global"));
+    println!("  enabled:       {}", if effective.enabled { "yes" } else { "no" });
+    println!("  min free VRAM: {} MB", effective.min_free_vram_mb);
+    let timeout = if effective.timeout_secs == 0 { "none".to_string() } else { format!("{}s", effective.timeout_secs) };
+    println!("  timeout:       {}", timeout);
+    Ok(())
+}
+
+fn info_repository(repo: &str, json: bool, copy: bool, install_path: &Path, config_manager: &ConfigManager) -> Result<()> {
+    let installer = RepositoryInstaller::new(install_path.to_path_buf(), config_manager.clone());
+    let Some(metadata) = installer.repository_metadata(repo)? else {
+        println!("No metadata recorded for '{}' (installed before license tracking)", repo);
+        return launch_command::show(install_path, repo, copy);
+    };
+    if json {
+        println!("{}", serde_json::to_string_pretty(&metadata)?);
+        return Ok(());
+    }
+
+    println!("Repository: {}", repo);
+    if let Some(upstream) = &metadata.upstream {
+        println!("Instance of: {}", upstream);
+    }
+    match &metadata.license {
+        Some(license) => {
+            println!("License: {}{}", license.display_name(), if license.is_permissive() { "" } else { " (non-permissive)" });
+            if let Some(url) = &license.url { println!("License URL: {}", url); }
+        }
+        None => println!("License: unknown"),
+    }
+    if metadata.license_accepted {
+        println!("License accepted: yes");
+    }
+    if let Some(entry) = &metadata.entry_point {
+        println!("Entry point: {}", entry);
+    }
+    if let Some(backend) = metadata.backend {
+        println!("Backend: {}", backend);
+    }
+    for skipped in &metadata.skipped_steps {
+        println!("Skipped install step: {} ({})", skipped.step, skipped.consequence);
+    }
+    if let Some(p) = &metadata.provenance {
+        println!("Source: {} [via {}]", p.url.as_deref().unwrap_or("-"), p.source);
+        if let Some(owner) = &p.owner { println!("Owner: {}", owner); }
+        if let Some(branch) = &p.default_branch { println!("Default branch: {}", branch); }
+        if let Some(desc) = &p.description { println!("Description: {}", desc); }
+        if p.archived { println!("Archived: yes"); }
+    }
+    launch_command::show(install_path, repo, copy)
+}
+
+#[cfg(unix)]
+fn export_env(repo: &str, output: Option<&Path>, relocatable: bool, install_path: &Path) -> Result<()> {
+    let archive = portablesource_rs::env_pack::export_env(install_path, repo, output, relocatable)?;
+    output::info(&format!("Environment for '{}' exported to {:?}", repo, archive));
+    if !relocatable {
+        output::info("Archive is bound to this install path; use --relocatable for other nodes");
+    }
+    Ok(())
 }
 
-fn list_repositories(install_path: &PathBuf, config_manager: &ConfigManager) -> Result<()> {
-    let installer = RepositoryInstaller::new(install_path.clone(), config_manager.clone());
+#[cfg(unix)]
+fn import_env(archive: &Path, name: Option<&str>, install_path: &Path) -> Result<()> {
+    let repo = portablesource_rs::env_pack::import_env(install_path, archive, name)?;
+    output::info(&format!("Environment imported as '{}'", repo));
+    Ok(())
+}
+
+fn bootstrap(output: &Path, repos: &[String], release: Option<String>, install_path: &Path, config_manager: &ConfigManager) -> Result<()> {
+    use portablesource_rs::bootstrap::{self, BootstrapPlan, BootstrapRepo};
+
+    let repos = if repos.is_empty() {
+        bootstrap::installed_repos(install_path)?
+    } else {
+        repos.iter().map(|r| BootstrapRepo::new(r)).collect()
+    };
+    let plan = BootstrapPlan {
+        install_path: install_path.to_path_buf(),
+        release,
+        gpu_queue: config_manager.get_config().gpu_queue.clone(),
+        repos,
+    };
+    bootstrap::write_script(&plan, output)?;
+    output::info(&format!("Bootstrap script written to {:?} ({} repositories)", output, plan.repos.len()));
+    Ok(())
+}
+
+fn tune_repository(repo: &str, profile: Option<PerformanceProfile>, reset: bool, install_path: &Path, config_manager: &ConfigManager) -> Result<()> {
+    let repo_path = install_path.join("repos").join(repo);
+    if !repo_path.exists() {
+        return Err(PortableSourceError::repository(format!("Repository '{}' not installed", repo)));
+    }
+    let mut settings = RepoRunSettings::load(&repo_path)?;
+    if reset || profile.is_some() {
+        settings.performance = profile;
+        settings.save(&repo_path)?;
+        let installer = RepositoryInstaller::new(install_path.to_path_buf(), config_manager.clone());
+        installer.render_startup_script(repo, false)?;
+        output::info(&format!("Start script for '{}' regenerated", repo));
+    }
+
+    let Some(profile) = settings.performance else {
+        println!("Performance profile ({}): none, the start script sets no tuning variables", repo);
+        return Ok(());
+    };
+    println!("Performance profile ({}): {}", repo, profile);
+    for (name, value) in Tuning::new(profile, &Hardware::detect(config_manager)).env {
+        println!("  {}={}", name, value);
+    }
+    Ok(())
+}
+
+fn switch_backend(repo: &str, backend: Backend, install_path: &Path, config_manager: &ConfigManager) -> Result<()> {
+    use portablesource_rs::installer::script_generator::RegenOutcome;
+
+    let installer = RepositoryInstaller::new(install_path.to_path_buf(), config_manager.clone());
+    output::step(&format!("Switching '{}' to the {} backend", repo, backend));
+    let (swapped, script) = installer.switch_backend(repo, backend)?;
+    if swapped.is_empty() {
+        output::info("No backend-dependent packages needed a different build");
+    } else {
+        output::info(&format!("Reinstalled: {}", swapped.join(", ")));
+    }
+    if let RegenOutcome::KeptEdited(new) = script {
+        output::warn(&format!("Start script was edited by you and kept; the {} version is in {}", backend, new.display()));
+    }
+    output::success(&format!("'{}' now runs on the {} backend", repo, backend));
+    Ok(())
+}
+
+fn collect_cache_garbage(dry_run: bool, min_age_days: u64, install_path: &Path) -> Result<()> {
+    use portablesource_rs::cache_gc;
+    let gc = cache_gc::plan(install_path, std::time::Duration::from_secs(min_age_days * 86_400))?;
+    let reclaimable = portablesource_rs::disk_space::format_size(gc.reclaimable);
+    if dry_run {
+        gc.actions.print_dry_run();
+        output::info(&format!("{} reclaimable", reclaimable));
+        return Ok(());
+    }
+    if gc.actions.is_empty() {
+        tracing::info!("Package cache: nothing to collect");
+        return Ok(());
+    }
+    gc.actions.execute()?;
+    let removed = gc.actions.items.iter().filter(|i| i.action == PlanAction::Delete).count();
+    output::info(&format!("Package cache: removed {} unused packages, {} freed", removed, reclaimable));
+    Ok(())
+}
+
+fn show_launch_env(repo: &str, install_path: &Path, config_manager: &ConfigManager) -> Result<()> {
+    let installer = RepositoryInstaller::new(install_path.to_path_buf(), config_manager.clone());
+    let ctx = installer.script_context(repo)?;
+    let script = ctx.repo_path.join(ctx.script_file_name());
+    if !script.exists() {
+        output::warn(&format!("{} does not exist yet; showing what a regenerated script would set", script.display()));
+    }
+    println!("Environment of run-repo {} ({})\n", repo, script.display());
+    print!("{}", portablesource_rs::env_preview::preview(&ctx).render());
+    Ok(())
+}
+
+fn verify_repository(repo: &str, verbose: bool, install_path: &Path) -> Result<()> {
+    let report = native_deps::scan_repo(install_path, repo)?;
+    if verbose {
+        for (package, libs) in &report.requirements {
+            let libs: Vec<String> = libs.iter().map(|l| format!("{} {}", l.display_name(), l.version)).collect();
+            println!("{}: {}", package, libs.join(", "));
+        }
+    }
+    if report.mismatches.is_empty() {
+        output::success(&format!("'{}': the CUDA libraries of {} native extensions are provided", repo, report.extensions));
+        return Ok(());
+    }
+    for mismatch in &report.mismatches {
+        output::error(&mismatch.describe());
+        output::info(&format!("  {}", mismatch.extension.display()));
+    }
+    output::hint("Install the package build that matches the provided CUDA (e.g. the cu12 wheel), or the nvidia-* wheel of the missing library");
+    Err(PortableSourceError::environment(format!("{} CUDA library mismatch(es) in '{}'", report.mismatches.len(), repo)))
+}
+
+fn which_tool(repo: &str, tool: &str, all: bool, install_path: &Path, config_manager: &ConfigManager) -> Result<()> {
+    let ctx = portablesource_rs::tool_lookup::SearchContext::for_repo(install_path, repo, config_manager)?;
+    let candidates = ctx.candidates(tool, std::env::var_os("PATH"));
+    let Some(first) = candidates.first() else {
+        return Err(PortableSourceError::environment(format!("No {} found for '{}'", tool, repo)));
+    };
+    if !all {
+        println!("{}", first.path.display());
+        return Ok(());
+    }
+    for (i, candidate) in candidates.iter().enumerate() {
+        let marker = if i == 0 { "*" } else { " " };
+        println!("{} {}  ({})", marker, candidate.path.display(), candidate.source);
+    }
+    Ok(())
+}
+
+async fn repo_components(repo: Option<&str>, add: &[String], remove: &[String], install_path: &Path, config_manager: &ConfigManager) -> Result<()> {
+    use portablesource_rs::components::{self, REGISTRY};
+    let cuda = config_manager.get_cuda_version();
+    let Some(repo) = repo else {
+        for component in REGISTRY {
+            let state = if component.is_installed(install_path, cuda.as_ref()) { "installed" } else { "" };
+            println!("{:<8} {:<10} {}", component.name, state, component.description);
+        }
+        return Ok(());
+    };
+    let repo_path = install_path.join("repos").join(repo);
+    if !repo_path.exists() {
+        return Err(PortableSourceError::repository(format!("Repository '{}' not installed", repo)));
+    }
+    let mut settings = RepoRunSettings::load(&repo_path)?;
+    if !add.is_empty() || !remove.is_empty() {
+        let (added, removed) = (components::resolve(add)?, components::resolve(remove)?);
+        settings.components.retain(|name| !removed.iter().any(|c| c.name.eq_ignore_ascii_case(name)));
+        for component in added {
+            if !settings.components.iter().any(|name| name.eq_ignore_ascii_case(component.name)) {
+                settings.components.push(component.name.to_string());
+            }
+        }
+        settings.save(&repo_path)?;
+        let installer = RepositoryInstaller::new(install_path.to_path_buf(), config_manager.clone());
+        installer.install_components(repo).await?;
+        installer.render_startup_script(repo, false)?;
+        output::info(&format!("Start script for '{}' regenerated", repo));
+        if !cfg!(windows) {
+            output::info("Components are only used by Windows start scripts; nothing was downloaded");
+        }
+    }
+
+    if settings.components.is_empty() {
+        println!("Components ({}): none", repo);
+        return Ok(());
+    }
+    println!("Components ({}):", repo);
+    for component in components::resolve(&settings.components)? {
+        let state = if component.is_installed(install_path, cuda.as_ref()) { "installed" } else { "not downloaded" };
+        println!("  {:<8} {}", component.name, state);
+    }
+    Ok(())
+}
+
+fn ci_manifest(repo: &str, output: Option<&Path>, install_path: &Path) -> Result<()> {
+    if !install_path.join("repos").join(repo).exists() {
+        return Err(PortableSourceError::repository(format!("Repository '{}' not installed", repo)));
+    }
+    let output = output.unwrap_or(Path::new("."));
+    let (lock_path, workflow_path, lock) = ci_manifest::write_manifest(install_path, repo, output)?;
+    for change in &lock.changes {
+        output::info(&change.to_string());
+    }
+    output::success(&format!("{} packages locked in {}", lock.requirements.len(), lock_path.display()));
+    output::success(&format!("Workflow written to {}", workflow_path.display()));
+    println!("Put {} in the project root and {} in .github/workflows/", ci_manifest::LOCK_FILE, ci_manifest::WORKFLOW_FILE);
+    Ok(())
+}
+
+fn show_inventory(json: bool, install_path: &Path, config_manager: &ConfigManager) -> Result<()> {
+    let inventory = Inventory::collect(install_path, config_manager)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&inventory)?);
+    } else {
+        print!("{}", inventory.render());
+    }
+    Ok(())
+}
+
+fn render_script(repo: &str, dry_run: bool, install_path: &Path, config_manager: &ConfigManager) -> Result<()> {
+    let installer = RepositoryInstaller::new(install_path.to_path_buf(), config_manager.clone());
+    let script = installer.render_startup_script(repo, dry_run)?;
+    if dry_run {
+        print!("{}", script);
+    } else {
+        output::info(&format!("Start script for '{}' regenerated", repo));
+    }
+    Ok(())
+}
+
+fn regen_scripts(repo: Option<&str>, force: bool, dry_run: bool, install_path: &Path, config_manager: &ConfigManager) -> Result<()> {
+    use portablesource_rs::installer::script_generator::RegenOutcome;
+
+    let installer = RepositoryInstaller::new(install_path.to_path_buf(), config_manager.clone());
+    let repos = match repo {
+        Some(name) => vec![name.to_string()],
+        None => installer.list_repository_names_raw()?,
+    };
+    if repos.is_empty() {
+        println!("No repositories installed");
+        return Ok(());
+    }
+    let (mut changed, mut kept, mut failed) = (0, 0, 0);
+    for name in &repos {
+        let status = match installer.regenerate_startup_script(name, force, dry_run) {
+            Ok(RegenOutcome::Unchanged) => "unchanged".to_string(),
+            Ok(RegenOutcome::Created) => {
+                changed += 1;
+                "created".to_string()
+            }
+            Ok(RegenOutcome::Updated) => {
+                changed += 1;
+                "updated".to_string()
+            }
+            Ok(RegenOutcome::KeptEdited(new)) => {
+                kept += 1;
+                format!("edited by you, kept; new version in {}", new.display())
+            }
+            Ok(RegenOutcome::Replaced(backup)) => {
+                changed += 1;
+                format!("edited by you, replaced; your version saved as {}", backup.display())
+            }
+            Err(e) => {
+                failed += 1;
+                format!("failed: {}", e)
+            }
+        };
+        println!("  {:<28} {}", name, status);
+    }
+
+    let verb = if dry_run { "would change" } else { "changed" };
+    output::info(&format!("{} of {} start scripts {}", changed, repos.len(), verb));
+    if kept > 0 {
+        output::hint("Merge your edits into the .new scripts, or run with --force to replace the edited ones");
+    }
+    if failed > 0 {
+        return Err(PortableSourceError::installation(format!("{} start scripts could not be regenerated", failed)));
+    }
+    Ok(())
+}
+
+fn delete_repository(repo: &str, dry_run: bool, install_path: &Path, config_manager: &ConfigManager) -> Result<()> {
+    let installer = RepositoryInstaller::new(install_path.to_path_buf(), config_manager.clone());
+    if dry_run {
+        installer.plan_delete(repo)?.print_dry_run();
+        return Ok(());
+    }
+    installer.delete_repository(repo)?;
+    // Packages only this repository used are garbage now
+    if let Err(e) = collect_cache_garbage(false, portablesource_rs::cache_gc::DEFAULT_MIN_AGE_DAYS, install_path) {
+        output::warn(&format!("Package cache cleanup failed: {}", e));
+    }
+    Ok(())
+}
+
+fn migrate_config(file: Option<&Path>, dry_run: bool, install_path: Option<&Path>) -> Result<()> {
+    let path = file
+        .map(Path::to_path_buf)
+        .or_else(|| install_path.map(|p| p.join("portablesource_config.json")))
+        .unwrap_or_else(ConfigManager::default_config_path);
+    if !path.exists() {
+        println!("No configuration file at {:?}", path);
+        return Ok(());
+    }
+
+    let (_, report) = config_migration::migrate_file(&path, dry_run)?;
+    if report.is_noop() {
+        output::info(&format!("{:?} is already at schema {}", path, report.to));
+        return Ok(());
+    }
+    let verb = if dry_run { "Would migrate" } else { "Migrated" };
+    output::info(&format!("{} {:?} from schema {} to {}:", verb, path, report.from, report.to));
+    for step in &report.steps {
+        println!("  - {}", step);
+    }
+    if dry_run {
+        let mut plan = ActionPlan::default();
+        plan.modify(path.clone(), &format!("rewritten at schema {}", report.to));
+        plan.notes.push(format!("The original would be kept at {}", config_migration::backup_path(&path, report.from).display()));
+        plan.print_dry_run();
+    }
+    if let Some(backup) = &report.backup {
+        output::info(&format!("Original kept at {:?}", backup));
+    }
+    Ok(())
+}
+
+fn import_legacy(from: Option<&Path>, dry_run: bool, install_path: &Path, config_manager: &ConfigManager) -> Result<()> {
+    // The same folder given another way is an import in place
+    let root = match from {
+        Some(from) if std::fs::canonicalize(from)? != std::fs::canonicalize(install_path)? => from.to_path_buf(),
+        _ => install_path.to_path_buf(),
+    };
+    let legacy = legacy_import::detect(&root)?;
+    if legacy.is_empty() {
+        output::info(&format!("Nothing to import: no legacy config or repositories without metadata in {}", root.display()));
+        return Ok(());
+    }
+    let mut plan = legacy_import::plan(&legacy, install_path);
+    if dry_run {
+        plan.print_dry_run();
+        return Ok(());
+    }
+    print!("{}", plan.render_items());
+    plan.execute()?;
+    if root != install_path {
+        for repo in &legacy.repos {
+            let legacy_import::LegacyEnv::Reusable(old_env) = &repo.env else { continue };
+            let env = legacy_import::target_env_path(install_path, repo);
+            if env.exists() {
+                let n = legacy_import::relocate_env(&env, old_env, &root, install_path)?;
+                tracing::debug!("Rewrote {} path(s) in the environment of {}", n, repo.name);
+            }
+        }
+    }
+    if let Some(config) = legacy.config.as_ref().filter(|_| root == install_path) {
+        config_migration::migrate_file(config, false)?;
+    }
+
+    let installer = RepositoryInstaller::new(install_path.to_path_buf(), config_manager.clone());
+    let mut imported = 0;
+    for repo in &legacy.repos {
+        let target = legacy_import::target_repo_path(install_path, repo);
+        if !target.exists() || target.join(portablesource_rs::repo_metadata::METADATA_FILE).exists() {
+            continue;
+        }
+        legacy_import::metadata_for(repo).save(&target)?;
+        imported += 1;
+        if let Err(e) = installer.render_startup_script(&repo.name, false) {
+            plan.notes.push(format!("{}: the start script could not be regenerated ({}); run 'portablesource render-script {}'", repo.name, e, repo.name));
+        }
+    }
+    installer.rebuild_index()?;
+
+    output::success(&format!("Imported {} repositories from {}", imported, root.display()));
+    if !plan.notes.is_empty() {
+        output::warn("Not migrated automatically:");
+        for note in &plan.notes {
+            println!("  - {}", note);
+        }
+    }
+    Ok(())
+}
+
+fn rebuild_index(install_path: &Path, config_manager: &ConfigManager) -> Result<()> {
+    let installer = RepositoryInstaller::new(install_path.to_path_buf(), config_manager.clone());
+    let count = installer.rebuild_index()?;
+    output::info(&format!("Repository index rebuilt ({} repositories)", count));
+    Ok(())
+}
+
+fn list_repositories(install_path: &Path, config_manager: &ConfigManager) -> Result<()> {
+    let installer = RepositoryInstaller::new(install_path.to_path_buf(), config_manager.clone());
     let repos = installer.list_repositories()?;
     
     if repos.is_empty() {
@@ -552,10 +1863,25 @@ fn ensure_config_initialized(config_manager: &mut ConfigManager) -> Result<()> {
     Ok(())
 }
 
-async fn check_environment(install_path: &PathBuf, _config_manager: &ConfigManager) -> Result<()> {
+async fn doctor(show_timings: bool, read_only: bool, install_path: &Path, config_manager: &ConfigManager) -> Result<()> {
+    check_environment(install_path, config_manager).await?;
+    if !show_timings {
+        println!("\nRun 'portablesource doctor --timings' to see where the last command spent its time.");
+        return Ok(());
+    }
+    println!();
+    match timings::load(&read_only::writable_root(install_path, read_only))? {
+        Some(report) => print!("{}", timings::render(&report)),
+        None => println!("No timings recorded yet; they are saved after install-repo, update-repo, setup-env and similar commands."),
+    }
+    Ok(())
+}
+
+async fn check_environment(install_path: &Path, config_manager: &ConfigManager) -> Result<()> {
     println!("=== Environment Status ===");
     
-    let env_manager = PortableEnvironmentManager::new(install_path.clone());
+    #[cfg(windows)]
+    let env_manager = PortableEnvironmentManager::new(install_path.to_path_buf());
     #[cfg(unix)]
     let status = {
         let base_bin = install_path.join("ps_env").join("mamba_env").join("bin");
@@ -593,15 +1919,80 @@ async fn check_environment(install_path: &PathBuf, _config_manager: &ConfigManag
             println!("{}: {}", tool, if available { "Available" } else { "Not found" });
         }
     }
+
+    // NVENC through the portable ffmpeg with the environment repositories run with
+    println!("\n=== Video Encoding (NVENC) ===");
+    let video_env = portablesource_rs::envs_manager::PortableEnvironmentManager::with_config(install_path.to_path_buf(), config_manager.clone());
+    let video = video_check::run_check(
+        &SystemExecutor,
+        video_env.get_ffmpeg_executable().as_deref(),
+        video_env.setup_environment_for_subprocess(),
+        GpuDetector::new().has_nvidia_gpu(),
+    );
+    if !video.encoders.is_empty() {
+        println!("encoders: {}", video.encoders.join(", "));
+    }
+    println!("nvenc: {}", video.summary());
+    if let Some(fix) = video.remediation() {
+        output::hint(&fix);
+    }
+
+    // nvidia-smi can see the GPU while this session cannot create CUDA contexts
+    println!("\n=== GPU Access ===");
+    let session = SessionKind::current();
+    println!("session: {}", session.describe());
+    if GpuDetector::new().has_nvidia_gpu() {
+        let probe = gpu_session::probe_cuda(&SystemExecutor, video_env.get_python_executable().as_deref());
+        println!("cuda context: {}", probe.summary());
+        for line in gpu_session::advice(session, &probe) {
+            output::hint(&line);
+        }
+    } else {
+        println!("cuda context: skipped (no NVIDIA GPU)");
+    }
     
     Ok(())
 }
 
 
 
-fn check_gpu() -> Result<()> {
+fn check_gpu(all: bool) -> Result<()> {
     let gpu_detector = GpuDetector::new();
-    let has_nvidia = gpu_detector.has_nvidia_gpu();
-    println!("{}", has_nvidia);
+    if !all {
+        println!("{}", gpu_detector.has_nvidia_gpu());
+        return Ok(());
+    }
+    let devices = gpu_detector.list_devices();
+    if devices.is_empty() {
+        output::info("No NVIDIA GPUs found (nvidia-smi unavailable or no devices)");
+        return Ok(());
+    }
+    for gpu in &devices {
+        println!("GPU {}: {} ({})", gpu.index, gpu.name, gpu.uuid);
+        for mig in &gpu.mig {
+            println!("  MIG {}:{} {:<8} {}", gpu.index, mig.index, mig.profile, mig.uuid);
+        }
+    }
+    if let Some(pipe) = std::env::var_os("CUDA_MPS_PIPE_DIRECTORY") {
+        output::info(&format!("MPS: launches share GPUs through the MPS daemon at {}", PathBuf::from(pipe).display()));
+    }
+    if devices.iter().any(|gpu| !gpu.mig.is_empty()) {
+        output::hint("Bind a launch to one instance: portablesource run-repo --gpu <gpu:mig or MIG UUID> <repo>");
+    }
     Ok(())
 }
+
+/// CUDA_VISIBLE_DEVICES for `run-repo --gpu`, checked against the devices nvidia-smi lists,
+/// and the nvidia-smi index of its GPU. Without nvidia-smi (some containers) a UUID is
+/// passed through unchecked
+fn visible_device(selector: &str) -> Result<(String, Option<u32>)> {
+    let devices = GpuDetector::new().list_devices();
+    if devices.is_empty() && (selector.starts_with("MIG-") || selector.starts_with("GPU-")) {
+        output::warn(&format!("Cannot list GPUs to check '{}'; passing it to CUDA as is", selector));
+        return Ok((selector.to_string(), None));
+    }
+    let device = gpu::resolve_visible_device(&devices, selector)?;
+    output::info(&format!("CUDA_VISIBLE_DEVICES={}", device));
+    let index = gpu::device_index(&devices, &device);
+    Ok((device, index))
+}
@@ -0,0 +1,295 @@
+//! Model downloads from manifests
+//!
+//! A manifest (`.portablesource_models.json` in the repository, or `--manifest FILE`) lists
+//! models, each made of one or more files (split safetensors shards and the like) with a
+//! sha256 per file. `download-models` places every file in the folder the repository
+//! expects for that kind of model (ComfyUI `models/loras`, webui `models/Lora`, facefusion
+//! `.assets/models`, ...), downloading several files at once. Files are written as `.part`
+//! and resumed on the next run; only a file whose checksum matches is moved into place.
+//!
+//! ```json
+//! { "models": [ { "name": "flux-dev", "kind": "checkpoint", "files": [
+//!     { "url": "https://host/flux-00001-of-00002.safetensors", "sha256": "..." },
+//!     { "url": "https://host/flux-00002-of-00002.safetensors", "sha256": "..." } ] } ] }
+//! ```
+
+use crate::system::Downloader;
+use crate::error::MultiError;
+use crate::{PortableSourceError, Result};
+use futures_util::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use tracing::{info, warn};
+
+pub const MANIFEST_FILE: &str = ".portablesource_models.json";
+pub const DEFAULT_JOBS: usize = 3;
+/// Attempts per file; each retry resumes the partial download
+const ATTEMPTS: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModelKind {
+    Checkpoint,
+    Lora,
+    Vae,
+    Controlnet,
+    Upscaler,
+    Embedding,
+    /// Face detection/swap models (insightface, inswapper, ...)
+    Face,
+    #[default]
+    Other,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelFile {
+    pub url: String,
+    pub sha256: String,
+    /// File name, optionally with subfolders, relative to the model folder (default: from the URL)
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelEntry {
+    pub name: String,
+    #[serde(default)]
+    pub kind: ModelKind,
+    /// Folder relative to the repository, overriding the placement rules
+    #[serde(default)]
+    pub target: Option<String>,
+    pub files: Vec<ModelFile>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ModelManifest {
+    pub models: Vec<ModelEntry>,
+}
+
+impl ModelManifest {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| PortableSourceError::config(format!("Cannot read model manifest {:?}: {}", path, e)))?;
+        serde_json::from_str(&content)
+            .map_err(|e| PortableSourceError::config(format!("Invalid model manifest {:?}: {}", path, e)))
+    }
+}
+
+/// Repository layouts with known model folders
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RepoLayout {
+    ComfyUi,
+    SdWebUi,
+    FaceFusion,
+    Generic,
+}
+
+impl RepoLayout {
+    /// Layout from the upstream repository name
+    pub fn detect(upstream: &str) -> Self {
+        let name = upstream.to_lowercase();
+        if name.contains("comfyui") {
+            RepoLayout::ComfyUi
+        } else if name.contains("stable-diffusion-webui") || name.contains("sd-webui") {
+            RepoLayout::SdWebUi
+        } else if name.contains("facefusion") {
+            RepoLayout::FaceFusion
+        } else {
+            RepoLayout::Generic
+        }
+    }
+
+    /// Folder, relative to the repository, where models of `kind` are looked up
+    pub fn folder(self, kind: ModelKind) -> &'static str {
+        use ModelKind::*;
+        match (self, kind) {
+            (RepoLayout::ComfyUi, Checkpoint) => "models/checkpoints",
+            (RepoLayout::ComfyUi, Lora) => "models/loras",
+            (RepoLayout::ComfyUi, Vae) => "models/vae",
+            (RepoLayout::ComfyUi, Controlnet) => "models/controlnet",
+            (RepoLayout::ComfyUi, Upscaler) => "models/upscale_models",
+            (RepoLayout::ComfyUi, Embedding) => "models/embeddings",
+            (RepoLayout::ComfyUi, Face) => "models/insightface",
+            (RepoLayout::SdWebUi, Checkpoint) => "models/Stable-diffusion",
+            (RepoLayout::SdWebUi, Lora) => "models/Lora",
+            (RepoLayout::SdWebUi, Vae) => "models/VAE",
+            (RepoLayout::SdWebUi, Controlnet) => "models/ControlNet",
+            (RepoLayout::SdWebUi, Upscaler) => "models/ESRGAN",
+            (RepoLayout::SdWebUi, Embedding) => "embeddings",
+            (RepoLayout::FaceFusion, _) => ".assets/models",
+            _ => "models",
+        }
+    }
+}
+
+/// One file to fetch, with its final location
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlannedFile {
+    pub model: String,
+    pub url: String,
+    pub sha256: String,
+    pub destination: PathBuf,
+}
+
+/// Relative path without `..`, root or drive components
+fn safe_relative(path: &str) -> Option<&Path> {
+    let p = Path::new(path);
+    p.components().all(|c| matches!(c, Component::Normal(_))).then_some(p)
+}
+
+/// Resolve every file of the manifest to its place in the repository
+pub fn plan(manifest: &ModelManifest, repo_path: &Path, layout: RepoLayout) -> Result<Vec<PlannedFile>> {
+    let mut files = Vec::new();
+    for model in &manifest.models {
+        let folder = model.target.as_deref().unwrap_or_else(|| layout.folder(model.kind));
+        let folder = safe_relative(folder)
+            .ok_or_else(|| PortableSourceError::config(format!("Model '{}': target {:?} must stay inside the repository", model.name, folder)))?;
+        for file in &model.files {
+            let name = match &file.path {
+                Some(path) => path.clone(),
+                None => file.url.split(['?', '#']).next().unwrap_or("").rsplit('/').next().unwrap_or("").to_string(),
+            };
+            let relative = safe_relative(&name)
+                .filter(|p| !p.as_os_str().is_empty())
+                .ok_or_else(|| PortableSourceError::config(format!("Model '{}': invalid file name {:?} for {}", model.name, name, file.url)))?;
+            files.push(PlannedFile {
+                model: model.name.clone(),
+                url: file.url.clone(),
+                sha256: file.sha256.to_lowercase(),
+                destination: repo_path.join(folder).join(relative),
+            });
+        }
+    }
+    Ok(files)
+}
+
+/// sha256 (hex) of a whole file
+pub fn file_sha256(path: &Path) -> Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1024 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+async fn sha256_blocking(path: &Path) -> Result<String> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || file_sha256(&path))
+        .await
+        .map_err(|e| PortableSourceError::environment(format!("Checksum task failed: {}", e)))?
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DownloadReport {
+    pub downloaded: usize,
+    /// Already in place with the right checksum
+    pub skipped: usize,
+}
+
+fn part_path(destination: &Path) -> PathBuf {
+    let mut name = destination.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    destination.with_file_name(name)
+}
+
+/// Download, verify and place one file; Ok(false) if it was already in place
+async fn fetch(downloader: &dyn Downloader, file: &PlannedFile) -> Result<bool> {
+    if file.destination.exists() && sha256_blocking(&file.destination).await? == file.sha256 {
+        return Ok(false);
+    }
+    if let Some(parent) = file.destination.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let part = part_path(&file.destination);
+    let mut attempt = 1;
+    while let Err(e) = downloader.download(&file.url, &part).await {
+        if attempt == ATTEMPTS {
+            return Err(e);
+        }
+        warn!("Download of {} failed ({}), resuming (attempt {}/{})", file.url, e, attempt + 1, ATTEMPTS);
+        attempt += 1;
+    }
+    let actual = sha256_blocking(&part).await?;
+    if actual != file.sha256 {
+        let _ = tokio::fs::remove_file(&part).await;
+        crate::download_state::DownloadState::remove(&part);
+        return Err(PortableSourceError::network(format!(
+            "Checksum mismatch for {}: expected {}, got {}",
+            file.url, file.sha256, actual
+        )));
+    }
+    tokio::fs::rename(&part, &file.destination).await?;
+    Ok(true)
+}
+
+/// Fetch all files, `jobs` at a time; every file is attempted even if others fail
+pub async fn download_all(downloader: &dyn Downloader, files: &[PlannedFile], jobs: usize) -> Result<DownloadReport> {
+    let results: Vec<(&PlannedFile, Result<bool>)> = stream::iter(files)
+        .map(|file| async move { (file, fetch(downloader, file).await) })
+        .buffer_unordered(jobs.max(1))
+        .collect()
+        .await;
+
+    let mut report = DownloadReport::default();
+    let mut failed = MultiError::new("model download", files.len());
+    for (file, result) in results {
+        match result {
+            Ok(true) => {
+                info!("{} placed at {:?}", file.model, file.destination);
+                report.downloaded += 1;
+            }
+            Ok(false) => report.skipped += 1,
+            Err(e) => failed.push(file.destination.display().to_string(), &e),
+        }
+    }
+    failed.into_result()?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockDownloader;
+
+    fn sha(data: &[u8]) -> String {
+        format!("{:x}", Sha256::digest(data))
+    }
+
+    #[tokio::test]
+    async fn shards_are_verified_and_placed_per_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest: ModelManifest = serde_json::from_value(serde_json::json!({"models": [
+            {"name": "flux", "kind": "checkpoint", "files": [
+                {"url": "https://host/flux-1.safetensors?download=true", "sha256": sha(b"one").to_uppercase()},
+                {"url": "https://host/flux-2.safetensors", "sha256": sha(b"two")}]},
+            {"name": "bad", "kind": "lora", "files": [{"url": "https://host/bad.safetensors", "sha256": sha(b"good")}]},
+        ]}))
+        .unwrap();
+        let files = plan(&manifest, dir.path(), RepoLayout::detect("ComfyUI")).unwrap();
+        assert_eq!(files[0].destination, dir.path().join("models/checkpoints/flux-1.safetensors"));
+        assert_eq!(files[2].destination, dir.path().join("models/loras/bad.safetensors"));
+
+        let downloader = MockDownloader::new()
+            .with_file("https://host/flux-1.safetensors?download=true", b"one".to_vec())
+            .with_file("https://host/flux-2.safetensors", b"two".to_vec())
+            .with_file("https://host/bad.safetensors", b"tampered".to_vec());
+        let err = download_all(&downloader, &files, 2).await.unwrap_err();
+        assert!(err.to_string().contains("Checksum mismatch"));
+        assert!(files[1].destination.exists());
+        assert!(!files[2].destination.exists() && !part_path(&files[2].destination).exists());
+
+        let report = download_all(&downloader, &files[..2], 2).await.unwrap();
+        assert_eq!(report, DownloadReport { downloaded: 0, skipped: 2 });
+
+        let escape = ModelManifest { models: vec![ModelEntry { target: Some("../x".into()), ..manifest.models[0].clone() }] };
+        assert!(plan(&escape, dir.path(), RepoLayout::Generic).is_err());
+    }
+}
@@ -0,0 +1,424 @@
+//! CUDA libraries required by the native extensions of an environment
+//!
+//! A wheel built against cuDNN 9 imports fine until the first convolution, then fails with
+//! a loader error that names neither the package nor the version. `verify-repo` reads the
+//! dynamic dependencies of every extension in site-packages (DT_NEEDED of ELF files, the
+//! import and delay-import tables of PE files), keeps the CUDA libraries among them and
+//! compares their major versions with the libraries the environment provides: files in
+//! site-packages (nvidia-* wheels, bundled `.libs` folders), the portable CUDA toolkit and
+//! native library components. Each extension is attributed to its package through the
+//! RECORD files of the installed wheels.
+
+use crate::{PortableSourceError, Result};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Library families checked; the driver (libcuda / nvcuda) comes with the NVIDIA driver
+const CUDA_LIBRARIES: &[&str] = &[
+    "cudart", "cudnn", "cublas", "cufft", "curand", "cusolver", "cusparse", "nvrtc", "nvjitlink", "nccl", "nvjpeg", "npp",
+];
+
+/// A CUDA library file name split into name and version: `libcudnn.so.8.9.2`, `cudnn64_9.dll`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CudaLib {
+    /// Lowercase name without `lib`/`64`, e.g. `cudnn`, `cudnn_ops`, `cublaslt`
+    pub name: String,
+    /// Version from the file name, e.g. "8.9.2" or "12"; empty when the name has none
+    pub version: String,
+}
+
+impl CudaLib {
+    pub fn parse(file_name: &str) -> Option<Self> {
+        let lower = file_name.to_lowercase();
+        let (name, version) = if let Some(stem) = lower.strip_suffix(".dll") {
+            // cudnn64_9, cudnn_ops64_9, nvrtc64_120_0, nvjitlink_120_0
+            let mut parts: Vec<&str> = stem.split('_').collect();
+            if parts.len() > 2 && parts.last() == Some(&"0") {
+                parts.pop();
+            }
+            let number = parts.pop().filter(|p| !p.is_empty() && p.chars().all(|c| c.is_ascii_digit()))?;
+            let name = parts.join("_");
+            let name = name.strip_suffix("64").unwrap_or(&name).to_string();
+            // nvrtc64_120_0: major 12, minor 0
+            let version = if number.len() == 3 { format!("{}.{}", &number[..2], &number[2..]) } else { number.to_string() };
+            (name, version)
+        } else {
+            let (base, version) = lower.split_once(".so")?;
+            let name = base.strip_prefix("lib")?.to_string();
+            (name, version.trim_start_matches('.').to_string())
+        };
+        CUDA_LIBRARIES.iter().any(|lib| name.starts_with(lib)).then_some(Self { name, version })
+    }
+
+    pub fn major(&self) -> &str {
+        self.version.split('.').next().unwrap_or("")
+    }
+
+    /// `libcudnn` on Linux, `cudnn` on Windows
+    pub fn display_name(&self) -> String {
+        if cfg!(windows) { self.name.clone() } else { format!("lib{}", self.name) }
+    }
+}
+
+/// A required library no provided file satisfies
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// Package owning the extension, or the extension path when no RECORD names it
+    pub package: String,
+    pub extension: PathBuf,
+    pub required: CudaLib,
+    /// Versions of the same library that are provided, newest first
+    pub provided: Vec<String>,
+}
+
+impl Mismatch {
+    /// "torchvision needs libcudnn 9, provisioned 8.9"
+    pub fn describe(&self) -> String {
+        let provided = match self.provided.first() {
+            Some(version) => format!("provisioned {}", version),
+            None => "not provisioned".to_string(),
+        };
+        format!("{} needs {} {}, {}", self.package, self.required.display_name(), self.required.major(), provided)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NativeReport {
+    /// Extensions whose dependencies were read
+    pub extensions: usize,
+    /// Required CUDA libraries per package
+    pub requirements: BTreeMap<String, BTreeSet<CudaLib>>,
+    pub mismatches: Vec<Mismatch>,
+}
+
+/// Read `len` bytes at `offset`; shorter at the end of the file
+fn read_at(file: &mut File, offset: u64, len: usize) -> Option<Vec<u8>> {
+    file.seek(SeekFrom::Start(offset)).ok()?;
+    let mut buf = Vec::with_capacity(len);
+    file.take(len as u64).read_to_end(&mut buf).ok()?;
+    Some(buf)
+}
+
+fn u16_at(b: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(b.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn u32_at(b: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(b.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn u64_at(b: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(b.get(at..at + 8)?.try_into().ok()?))
+}
+
+fn c_string(b: &[u8], at: usize) -> Option<String> {
+    let bytes = b.get(at..)?;
+    let end = bytes.iter().position(|&c| c == 0)?;
+    Some(String::from_utf8_lossy(&bytes[..end]).to_string())
+}
+
+/// DT_NEEDED entries of a 64-bit little-endian ELF file
+pub fn elf_needed(path: &Path) -> Option<Vec<String>> {
+    let mut file = File::open(path).ok()?;
+    let header = read_at(&mut file, 0, 64)?;
+    if header.get(..4)? != b"\x7fELF" || header[4] != 2 || header[5] != 1 {
+        return None;
+    }
+    let (phoff, phentsize, phnum) = (u64_at(&header, 0x20)?, u16_at(&header, 0x36)? as usize, u16_at(&header, 0x38)? as usize);
+    let phdrs = read_at(&mut file, phoff, phentsize * phnum)?;
+    // (vaddr, offset, filesz) of loaded segments, to map the string table address to the file
+    let mut loads = Vec::new();
+    let mut dynamic = None;
+    for i in 0..phnum {
+        let ph = phdrs.get(i * phentsize..(i + 1) * phentsize)?;
+        let (kind, offset, vaddr, filesz) = (u32_at(ph, 0)?, u64_at(ph, 8)?, u64_at(ph, 16)?, u64_at(ph, 32)?);
+        match kind {
+            1 => loads.push((vaddr, offset, filesz)),
+            2 => dynamic = Some((offset, filesz)),
+            _ => {}
+        }
+    }
+    let (offset, size) = dynamic?;
+    let entries = read_at(&mut file, offset, size as usize)?;
+    let (mut needed, mut strtab, mut strsz) = (Vec::new(), None, 0u64);
+    for entry in entries.chunks_exact(16) {
+        match (u64_at(entry, 0)?, u64_at(entry, 8)?) {
+            (0, _) => break,
+            (1, name) => needed.push(name),
+            (5, addr) => strtab = Some(addr),
+            (10, size) => strsz = size,
+            _ => {}
+        }
+    }
+    let strtab = strtab?;
+    let (vaddr, offset, _) = loads.iter().find(|(vaddr, _, filesz)| strtab >= *vaddr && strtab < vaddr + filesz)?;
+    let strings = read_at(&mut file, strtab - vaddr + offset, strsz as usize)?;
+    Some(needed.into_iter().filter_map(|at| c_string(&strings, at as usize)).collect())
+}
+
+/// DLL names of the import and delay-import tables of a PE file
+pub fn pe_imports(path: &Path) -> Option<Vec<String>> {
+    let mut file = File::open(path).ok()?;
+    let dos = read_at(&mut file, 0, 64)?;
+    if dos.get(..2)? != b"MZ" {
+        return None;
+    }
+    let pe = u32_at(&dos, 0x3c)? as u64;
+    let headers = read_at(&mut file, pe, 24 + 240)?;
+    if headers.get(..4)? != b"PE\0\0" {
+        return None;
+    }
+    let (sections, optional_size) = (u16_at(&headers, 6)? as usize, u16_at(&headers, 20)? as u64);
+    let directories = match u16_at(&headers, 24)? {
+        0x10b => 24 + 96,
+        0x20b => 24 + 112,
+        _ => return None,
+    };
+    let table = read_at(&mut file, pe + 24 + optional_size, sections * 40)?;
+    let rva_to_offset = |rva: u32| {
+        table.chunks_exact(40).find_map(|s| {
+            let (vsize, va, raw_size, raw_ptr) = (u32_at(s, 8)?, u32_at(s, 12)?, u32_at(s, 16)?, u32_at(s, 20)?);
+            (rva >= va && rva < va + vsize.max(raw_size)).then(|| (rva - va + raw_ptr) as u64)
+        })
+    };
+    let mut names = Vec::new();
+    // Import table (descriptor 20 bytes, name at +12) and delay-import table (32 bytes, name at +4)
+    for (index, size, name_at) in [(1, 20, 12), (13, 32, 4)] {
+        let rva = u32_at(&headers, directories + index * 8).unwrap_or(0);
+        let Some(offset) = (rva != 0).then(|| rva_to_offset(rva)).flatten() else { continue };
+        for i in 0.. {
+            let Some(descriptor) = read_at(&mut file, offset + i * size as u64, size) else { break };
+            let name_rva = u32_at(&descriptor, name_at).unwrap_or(0);
+            if name_rva == 0 {
+                break;
+            }
+            if let Some(name) = rva_to_offset(name_rva).and_then(|at| read_at(&mut file, at, 256)).and_then(|b| c_string(&b, 0)) {
+                names.push(name);
+            }
+        }
+    }
+    Some(names)
+}
+
+fn is_native_file(path: &Path) -> bool {
+    let name = path.file_name().map(|n| n.to_string_lossy().to_lowercase()).unwrap_or_default();
+    name.ends_with(".pyd") || name.ends_with(".dll") || name.ends_with(".so") || name.contains(".so.")
+}
+
+/// Path column of a RECORD line; RECORD is CSV, so paths with commas or quotes are quoted
+fn record_path(line: &str) -> Option<String> {
+    let Some(quoted) = line.strip_prefix('"') else { return line.split(',').next().map(str::to_string) };
+    let mut path = String::new();
+    let mut chars = quoted.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '"' && chars.next_if_eq(&'"').is_none() {
+            return Some(path);
+        }
+        path.push(c);
+    }
+    None
+}
+
+/// Package that installed each file, from `*.dist-info/RECORD`
+pub fn record_owners(site_packages: &Path) -> HashMap<PathBuf, String> {
+    let mut owners = HashMap::new();
+    let Ok(entries) = std::fs::read_dir(site_packages) else { return owners };
+    for entry in entries.flatten() {
+        let dir = entry.file_name().to_string_lossy().to_string();
+        let Some(stem) = dir.strip_suffix(".dist-info") else { continue };
+        let package = stem.split('-').next().unwrap_or(stem).to_string();
+        let Ok(record) = std::fs::read_to_string(entry.path().join("RECORD")) else { continue };
+        for line in record.lines() {
+            if let Some(file) = record_path(line).filter(|f| !f.is_empty() && !f.starts_with("..")) {
+                owners.insert(site_packages.join(file), package.clone());
+            }
+        }
+    }
+    owners
+}
+
+/// Newest first: "12.4" before "12"
+fn sort_versions(versions: &mut [String]) {
+    let key = |v: &String| v.split('.').map(|p| p.parse::<u64>().unwrap_or(0)).collect::<Vec<_>>();
+    versions.sort_by_key(|v| std::cmp::Reverse(key(v)));
+}
+
+/// Compare the CUDA libraries the extensions in `site_packages` load with the library
+/// files found in `site_packages` and `library_dirs`
+pub fn scan(site_packages: &[PathBuf], library_dirs: &[PathBuf]) -> Result<NativeReport> {
+    let mut provided: HashMap<String, Vec<String>> = HashMap::new();
+    let mut extensions = Vec::new();
+    for root in site_packages.iter().chain(library_dirs) {
+        for entry in WalkDir::new(root).into_iter().flatten().filter(|e| e.file_type().is_file()) {
+            if let Some(lib) = CudaLib::parse(&entry.file_name().to_string_lossy()) {
+                provided.entry(lib.name).or_default().push(lib.version);
+            }
+            if site_packages.contains(&root.to_path_buf()) && is_native_file(entry.path()) {
+                extensions.push(entry.path().to_path_buf());
+            }
+        }
+    }
+    let owners: HashMap<PathBuf, String> = site_packages.iter().flat_map(|dir| record_owners(dir)).collect();
+
+    let mut report = NativeReport { extensions: extensions.len(), ..Default::default() };
+    let mut reported = BTreeSet::new();
+    for extension in extensions {
+        let imports = elf_needed(&extension).or_else(|| pe_imports(&extension));
+        let package = owners.get(&extension).cloned().unwrap_or_else(|| extension.display().to_string());
+        for required in imports.unwrap_or_default().iter().filter_map(|name| CudaLib::parse(name)) {
+            report.requirements.entry(package.clone()).or_default().insert(required.clone());
+            let mut versions = provided.get(&required.name).cloned().unwrap_or_default();
+            let satisfied = versions.iter().any(|v| v.split('.').next() == Some(required.major()));
+            if required.version.is_empty() || satisfied || !reported.insert((package.clone(), required.clone())) {
+                continue;
+            }
+            sort_versions(&mut versions);
+            versions.dedup();
+            report.mismatches.push(Mismatch { package: package.clone(), extension: extension.clone(), required, provided: versions });
+        }
+    }
+    Ok(report)
+}
+
+/// Scan the environment of an installed repository (its venv or shared environment)
+pub fn scan_repo(install_path: &Path, repo: &str) -> Result<NativeReport> {
+    let repo_path = install_path.join("repos").join(repo);
+    if !repo_path.exists() {
+        return Err(PortableSourceError::repository(format!("Repository '{}' not installed", repo)));
+    }
+    let env = crate::shared_env::repo_env_prefix(install_path, repo)?;
+    let site_packages: Vec<PathBuf> = crate::cache_gc::site_packages_dirs(&env).into_iter().filter(|d| d.is_dir()).collect();
+    let ps_env = install_path.join("ps_env");
+    let library_dirs: Vec<PathBuf> = [ps_env.join("CUDA"), ps_env.join(crate::components::COMPONENTS_DIR)]
+        .into_iter()
+        .filter(|d| d.is_dir())
+        .collect();
+    scan(&site_packages, &library_dirs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(windows)]
+    const CUDNN: &str = "cudnn";
+    #[cfg(not(windows))]
+    const CUDNN: &str = "libcudnn";
+
+    /// 64-bit ELF file whose dynamic section needs `libraries`
+    fn elf_needing(libraries: &[&str]) -> Vec<u8> {
+        let strtab = 64 + 2 * 56;
+        let mut strings = vec![0u8];
+        let mut dynamic = Vec::new();
+        for library in libraries {
+            dynamic.push((1u64, strings.len() as u64));
+            strings.extend_from_slice(library.as_bytes());
+            strings.push(0);
+        }
+        dynamic.extend([(5, strtab as u64), (10, strings.len() as u64), (0, 0)]);
+        let dynamic_at = strtab + strings.len();
+        let len = dynamic_at + dynamic.len() * 16;
+
+        let mut elf = vec![0u8; 64];
+        elf[..6].copy_from_slice(b"\x7fELF\x02\x01");
+        elf[0x20..0x28].copy_from_slice(&64u64.to_le_bytes());
+        elf[0x36..0x38].copy_from_slice(&56u16.to_le_bytes());
+        elf[0x38..0x3a].copy_from_slice(&2u16.to_le_bytes());
+        // PT_LOAD mapping the whole file at address 0, PT_DYNAMIC
+        for (kind, offset, size) in [(1u32, 0, len), (2, dynamic_at, len - dynamic_at)] {
+            let mut header = [0u8; 56];
+            header[..4].copy_from_slice(&kind.to_le_bytes());
+            header[8..16].copy_from_slice(&(offset as u64).to_le_bytes());
+            header[16..24].copy_from_slice(&(offset as u64).to_le_bytes());
+            header[32..40].copy_from_slice(&(size as u64).to_le_bytes());
+            elf.extend(header);
+        }
+        elf.extend(strings);
+        for (tag, value) in dynamic {
+            elf.extend(tag.to_le_bytes());
+            elf.extend(value.to_le_bytes());
+        }
+        elf
+    }
+
+    #[test]
+    fn library_file_names_give_name_and_version() {
+        assert_eq!(CudaLib::parse("libcudnn.so.8.9.2"), Some(CudaLib { name: "cudnn".into(), version: "8.9.2".into() }));
+        assert_eq!(CudaLib::parse("cudnn_ops64_9.dll"), Some(CudaLib { name: "cudnn_ops".into(), version: "9".into() }));
+        assert_eq!(CudaLib::parse("nvrtc64_120_0.dll"), Some(CudaLib { name: "nvrtc".into(), version: "12.0".into() }));
+        assert_eq!(CudaLib::parse("cublasLt64_12.dll").unwrap().name, "cublaslt");
+        assert_eq!(CudaLib::parse("libcuda.so.1"), None);
+        assert_eq!(CudaLib::parse("libc.so.6"), None);
+    }
+
+    #[test]
+    fn mismatch_names_the_package_and_both_versions() {
+        let mismatch = Mismatch {
+            package: "torchvision".into(),
+            extension: PathBuf::from("_C.so"),
+            required: CudaLib::parse("libcudnn.so.9").unwrap(),
+            provided: vec!["8.9".into()],
+        };
+        assert_eq!(mismatch.describe(), format!("torchvision needs {} 9, provisioned 8.9", CUDNN));
+    }
+
+    #[test]
+    fn dynamic_dependencies_of_an_elf_file_are_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("_C.so");
+        std::fs::write(&path, elf_needing(&["libcudnn.so.9", "libc.so.6"])).unwrap();
+        assert_eq!(elf_needed(&path).unwrap(), ["libcudnn.so.9", "libc.so.6"]);
+        assert_eq!(pe_imports(&path), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn dynamic_dependencies_of_the_test_binary_are_read() {
+        let needed = elf_needed(&std::env::current_exe().unwrap()).unwrap();
+        assert!(needed.iter().any(|n| n.starts_with("libc.so")), "{:?}", needed);
+    }
+
+    #[test]
+    fn record_paths_are_read_as_csv() {
+        assert_eq!(record_path("torch/_C.so,sha256=abc,10").as_deref(), Some("torch/_C.so"));
+        assert_eq!(record_path("\"a,b/\"\"q\"\".so\",sha256=abc,10").as_deref(), Some("a,b/\"q\".so"));
+        assert_eq!(record_path("\"unterminated,10"), None);
+    }
+
+    #[test]
+    fn extension_needing_another_major_is_a_mismatch() {
+        let dir = tempfile::tempdir().unwrap();
+        let site = dir.path().join("site-packages");
+        let cuda = dir.path().join("CUDA/lib");
+        for folder in ["nvidia/cudnn/lib", "torchvision/ops,v2", "torchvision-0.20.1.dist-info"] {
+            std::fs::create_dir_all(site.join(folder)).unwrap();
+        }
+        std::fs::create_dir_all(&cuda).unwrap();
+        std::fs::write(site.join("nvidia/cudnn/lib/libcudnn.so.8.9.2"), "").unwrap();
+        std::fs::write(cuda.join("libcudart.so.12.4.127"), "").unwrap();
+        let extension = site.join("torchvision/ops,v2/_C.so");
+        std::fs::write(&extension, elf_needing(&["libcudnn.so.9", "libcudart.so.12", "libc.so.6"])).unwrap();
+        std::fs::write(
+            site.join("torchvision-0.20.1.dist-info/RECORD"),
+            "\"torchvision/ops,v2/_C.so\",sha256=abc,10\ntorchvision-0.20.1.dist-info/RECORD,,\n",
+        )
+        .unwrap();
+
+        let report = scan(&[site], &[dir.path().join("CUDA")]).unwrap();
+        assert_eq!(report.extensions, 2, "the extension and the cuDNN library");
+        let needed: Vec<&str> = report.requirements["torchvision"].iter().map(|lib| lib.name.as_str()).collect();
+        assert_eq!(needed, ["cudart", "cudnn"]);
+        assert_eq!(
+            report.mismatches,
+            [Mismatch {
+                package: "torchvision".into(),
+                extension,
+                required: CudaLib { name: "cudnn".into(), version: "9".into() },
+                provided: vec!["8.9.2".into()],
+            }]
+        );
+    }
+}
@@ -0,0 +1,184 @@
+//! Network isolation for `run-repo --no-network`
+//!
+//! Linux: the start script runs in a fresh network namespace (`unshare --net`, through a
+//! user namespace when not root) with only loopback up.
+//! Windows: outbound and inbound Windows Firewall block rules for the repo's python.exe,
+//! removed again when the launch ends. Needs an elevated console. `run_repo` defers Ctrl+C to
+//! the app, so the launcher outlives it and removes the rules; rules left behind by a launcher
+//! that was killed outright are replaced on the next launch.
+
+use tracing::debug;
+use std::path::Path;
+use std::process::Command;
+#[cfg(unix)]
+use std::process::Stdio;
+
+/// Isolation that could be set up for a launch; on Windows dropping it removes the firewall rules
+pub struct NetworkIsolation {
+    #[cfg(unix)]
+    unshare_args: Vec<&'static str>,
+    /// Held only so the rules are removed on drop
+    #[cfg(windows)]
+    #[allow(dead_code)]
+    rules: Vec<FirewallRule>,
+}
+
+impl NetworkIsolation {
+    /// Set up isolation for `repo`; Err explains why it cannot be enforced here
+    #[cfg(unix)]
+    pub fn prepare(_repo: &str, _install_path: &Path) -> std::result::Result<Self, String> {
+        if which::which("unshare").is_err() {
+            return Err("'unshare' (util-linux) is not installed".to_string());
+        }
+        let candidates: &[&[&'static str]] = if crate::utils::is_root() {
+            &[&["--net"]]
+        } else {
+            // Mapping to root keeps the capability needed to bring loopback up
+            &[&["--user", "--map-root-user", "--net"], &["--user", "--map-current-user", "--net"]]
+        };
+        for args in candidates {
+            let ok = Command::new("unshare")
+                .args(*args)
+                .arg("true")
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .map(|s| s.success())
+                .unwrap_or(false);
+            if ok {
+                debug!("Network namespace via unshare {}", args.join(" "));
+                return Ok(Self { unshare_args: args.to_vec() });
+            }
+        }
+        Err("network namespaces are not available (unprivileged user namespaces disabled?)".to_string())
+    }
+
+    #[cfg(windows)]
+    pub fn prepare(repo: &str, install_path: &Path) -> std::result::Result<Self, String> {
+        let python = install_path.join("envs").join(repo.to_lowercase()).join("python.exe");
+        if !python.exists() {
+            return Err(format!("python.exe of '{}' not found at {}", repo, python.display()));
+        }
+        let mut rules = Vec::new();
+        for direction in ["out", "in"] {
+            let rule = FirewallRule {
+                name: format!("PortableSource no-network {} ({})", repo, direction),
+                program: python.to_string_lossy().to_string(),
+                direction,
+            };
+            // Leftover of a launcher that did not get to clean up; netsh would add a duplicate
+            let _ = FirewallRule::delete(&rule.name);
+            if let Err(e) = rule.add() {
+                // Nothing to delete for this one; rules already added are removed when `rules` is dropped
+                std::mem::forget(rule);
+                return Err(format!("could not add Windows Firewall rule ({}); run from an elevated console", e));
+            }
+            rules.push(rule);
+        }
+        Ok(Self { rules })
+    }
+
+    /// Command that runs `program args...` under this isolation
+    #[cfg(unix)]
+    pub fn command(&self, program: &str, args: &[String]) -> Command {
+        let mut cmd = Command::new("unshare");
+        cmd.args(&self.unshare_args);
+        // Bring loopback up so the app can still talk to itself
+        cmd.args(["sh", "-c", "ip link set lo up 2>/dev/null || true; exec \"$@\"", "sh", program]);
+        cmd.args(args);
+        cmd
+    }
+
+    #[cfg(windows)]
+    pub fn command(&self, program: &str, args: &[String]) -> Command {
+        let mut cmd = Command::new(program);
+        cmd.args(args);
+        cmd
+    }
+
+    /// What the user should expect from this isolation
+    pub fn describe(&self) -> &'static str {
+        if cfg!(windows) {
+            "Windows Firewall blocks all traffic of the repo's python.exe"
+        } else {
+            "separate network namespace; its web UI is reachable only from inside the sandbox"
+        }
+    }
+}
+
+#[cfg(windows)]
+struct FirewallRule {
+    name: String,
+    program: String,
+    direction: &'static str,
+}
+
+#[cfg(windows)]
+impl FirewallRule {
+    fn netsh(args: &[String]) -> std::result::Result<(), String> {
+        use std::os::windows::process::CommandExt;
+        let output = Command::new("netsh")
+            .args(["advfirewall", "firewall"])
+            .args(args)
+            .creation_flags(0x08000000) // CREATE_NO_WINDOW
+            .output()
+            .map_err(|e| e.to_string())?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(String::from_utf8_lossy(&output.stdout).trim().to_string())
+        }
+    }
+
+    fn delete(name: &str) -> std::result::Result<(), String> {
+        Self::netsh(&["delete".into(), "rule".into(), format!("name={}", name)])
+    }
+
+    fn add(&self) -> std::result::Result<(), String> {
+        Self::netsh(&[
+            "add".into(),
+            "rule".into(),
+            format!("name={}", self.name),
+            format!("dir={}", self.direction),
+            "action=block".into(),
+            format!("program={}", self.program),
+            "enable=yes".into(),
+        ])
+    }
+}
+
+#[cfg(windows)]
+impl Drop for FirewallRule {
+    fn drop(&mut self) {
+        if let Err(e) = Self::delete(&self.name) {
+            tracing::warn!("Failed to remove firewall rule '{}': {}", self.name, e);
+        }
+    }
+}
+
+/// Launch without isolation only if the user agrees on a terminal
+pub fn confirm_unisolated_launch(repo: &str) -> crate::Result<()> {
+    use std::io::{self, IsTerminal};
+    if !io::stdin().is_terminal() {
+        return Err(crate::PortableSourceError::command(format!(
+            "Refusing to launch '{}' with network access; drop --no-network to run it anyway", repo
+        )));
+    }
+    if crate::prompt::confirm(&format!("Launch '{}' WITH network access anyway?", repo)) {
+        Ok(())
+    } else {
+        Err(crate::PortableSourceError::command(format!("Launch of '{}' cancelled", repo)))
+    }
+}
+
+/// `program args...`, wrapped in the isolation when there is one
+pub fn command(isolation: Option<&NetworkIsolation>, program: &str, args: &[String]) -> Command {
+    match isolation {
+        Some(iso) => iso.command(program, args),
+        None => {
+            let mut cmd = Command::new(program);
+            cmd.args(args);
+            cmd
+        }
+    }
+}
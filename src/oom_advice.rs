@@ -0,0 +1,74 @@
+//! Advice after a run ran out of GPU memory
+//!
+//! `run-repo` keeps the tail of the repository's stderr in `logs/last_run_<name>.log` when it
+//! captures stderr (`--capture-log`, or stderr redirected). When a failed run left a CUDA out-of-memory error there, the generic "use a smaller
+//! model" hint comes with advice for that repository: its own low-VRAM launch flags, and
+//! the `low-vram` performance profile when the start script does not use it yet.
+
+use crate::performance::PerformanceProfile;
+use std::path::{Path, PathBuf};
+
+/// Last part of the stderr of the most recent run of `repo`, in the logs folder
+pub fn run_log_path(log_dir: &Path, repo: &str) -> PathBuf {
+    log_dir.join(format!("last_run_{}.log", repo))
+}
+
+/// Bytes of stderr kept for the log
+pub const RUN_LOG_TAIL: usize = 64 * 1024;
+
+/// Low-VRAM launch flags of known repositories: upstream name, flags, what they do
+const LOW_VRAM_FLAGS: &[(&str, &str, &str)] = &[
+    ("comfyui", "--lowvram", "keeps model parts in system RAM until they are needed"),
+    ("stable-diffusion-webui", "--medvram", "keeps only the active part of the model on the GPU; --lowvram for 4 GB cards"),
+    ("stable-diffusion-webui-forge", "--always-offload-from-vram", "moves models off the GPU after use"),
+    ("fooocus", "--always-low-vram", "offloads models between steps"),
+    ("facefusion", "--video-memory-strategy strict", "frees models between frames"),
+];
+
+/// Low-VRAM flags of a repository and what they do
+pub fn low_vram_flags(upstream: &str) -> Option<(&'static str, &'static str)> {
+    LOW_VRAM_FLAGS
+        .iter()
+        .find(|(name, _, _)| name.eq_ignore_ascii_case(upstream))
+        .map(|(_, flags, what)| (*flags, *what))
+}
+
+/// Advice lines for `repo` (installed from `upstream`) after running out of GPU memory
+/// with `args` under `profile`
+pub fn advise(repo: &str, upstream: &str, args: &[String], profile: Option<PerformanceProfile>) -> Vec<String> {
+    let mut lines = Vec::new();
+    if let Some((flags, what)) = low_vram_flags(upstream) {
+        let first = flags.split_whitespace().next().unwrap_or(flags);
+        if !args.iter().any(|a| a == first) {
+            lines.push(format!("Start it with {} ({}): portablesource run-repo {} {}", flags, what, repo, flags));
+        }
+    }
+    lines.push("Lower the batch size, resolution or model size in its settings, and close other programs using the GPU".to_string());
+    if profile != Some(PerformanceProfile::LowVram) {
+        lines.push(format!(
+            "The low-vram profile makes PyTorch return fragmented memory sooner: portablesource tune-repo {} --profile low-vram",
+            repo
+        ));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn advice_names_repo_flags_not_yet_used() {
+        let advice = advise("comfyui-video", "comfyui", &[], None);
+        assert_eq!(advice.len(), 3);
+        assert!(advice[0].contains("portablesource run-repo comfyui-video --lowvram"));
+        assert!(advice[2].contains("tune-repo comfyui-video --profile low-vram"));
+
+        let advice = advise("comfyui", "comfyui", &["--lowvram".to_string()], Some(PerformanceProfile::LowVram));
+        assert_eq!(advice.len(), 1);
+
+        let advice = advise("facefusion", "facefusion", &["run".to_string()], Some(PerformanceProfile::LowVram));
+        assert!(advice[0].contains("--video-memory-strategy strict"));
+        assert_eq!(advise("mytool", "mytool", &[], Some(PerformanceProfile::Balanced)).len(), 2);
+    }
+}
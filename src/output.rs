@@ -0,0 +1,126 @@
+//! Themed status messages
+//!
+//! Everything the CLI tells the user (as opposed to log records) goes through the level
+//! functions here, so long install logs read the same everywhere: `[STEP]`, `[INFO]`,
+//! `[SUCCESS]`, `[WARNING]`, `[ERROR]` and `[HINT]` prefixes, colored on a terminal.
+//! Warnings, errors and hints go to stderr, the rest to stdout, so piping a command's
+//! output keeps the diagnostics visible. Colors are off with `--no-color`, with the
+//! `NO_COLOR` environment variable, or when the stream is not a terminal. Messages are
+//! never dropped by `--quiet`.
+
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    /// Start of a major phase (resolving, cloning, installing dependencies)
+    Step,
+    Info,
+    Success,
+    Warning,
+    Error,
+    /// What the user can do about the previous warning or error
+    Hint,
+}
+
+impl Level {
+    pub fn prefix(self) -> &'static str {
+        match self {
+            Level::Step => "[STEP]",
+            Level::Info => "[INFO]",
+            Level::Success => "[SUCCESS]",
+            Level::Warning => "[WARNING]",
+            Level::Error => "[ERROR]",
+            Level::Hint => "[HINT]",
+        }
+    }
+
+    /// Diagnostics go to stderr
+    pub fn is_diagnostic(self) -> bool {
+        matches!(self, Level::Warning | Level::Error | Level::Hint)
+    }
+
+    /// ANSI SGR parameters of the prefix
+    fn style(self) -> &'static str {
+        match self {
+            Level::Step => "1;36",
+            Level::Info => "34",
+            Level::Success => "1;32",
+            Level::Warning => "1;33",
+            Level::Error => "1;31",
+            Level::Hint => "35",
+        }
+    }
+}
+
+/// Colors allowed by the user; each stream still has to be a terminal
+static COLOR: OnceLock<bool> = OnceLock::new();
+
+fn color_allowed() -> bool {
+    *COLOR.get_or_init(|| std::env::var_os("NO_COLOR").is_none())
+}
+
+/// Decide once at startup whether to color output; later calls are ignored
+pub fn init(no_color: bool) {
+    let _ = COLOR.set(!no_color && std::env::var_os("NO_COLOR").is_none());
+}
+
+pub fn color_enabled() -> bool {
+    color_allowed() && std::io::stdout().is_terminal()
+}
+
+fn stderr_color_enabled() -> bool {
+    color_allowed() && std::io::stderr().is_terminal()
+}
+
+/// `[LEVEL] message`, with the prefix colored when `color` is set
+pub fn format_line(level: Level, msg: &str, color: bool) -> String {
+    if color {
+        format!("\x1b[{}m{}\x1b[0m {}", level.style(), level.prefix(), msg)
+    } else {
+        format!("{} {}", level.prefix(), msg)
+    }
+}
+
+pub fn message(level: Level, msg: &str) {
+    if level.is_diagnostic() {
+        crate::progress::eprint_line(&format_line(level, msg, stderr_color_enabled()));
+    } else {
+        crate::progress::print_line(&format_line(level, msg, color_enabled()));
+    }
+}
+
+pub fn step(msg: &str) {
+    message(Level::Step, msg);
+}
+
+pub fn info(msg: &str) {
+    message(Level::Info, msg);
+}
+
+pub fn success(msg: &str) {
+    message(Level::Success, msg);
+}
+
+pub fn warn(msg: &str) {
+    message(Level::Warning, msg);
+}
+
+pub fn error(msg: &str) {
+    message(Level::Error, msg);
+}
+
+pub fn hint(msg: &str) {
+    message(Level::Hint, msg);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefixes_are_colored_only_on_request() {
+        assert_eq!(format_line(Level::Warning, "disk almost full", false), "[WARNING] disk almost full");
+        assert_eq!(format_line(Level::Error, "failed", true), "\x1b[1;31m[ERROR]\x1b[0m failed");
+    }
+}
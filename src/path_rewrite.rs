@@ -0,0 +1,149 @@
+//! Rewriting an absolute path inside text files
+//!
+//! Installs that move (backup restore, import-env, warm-start clones) replace the old
+//! location in scripts and configs. Only whole path tokens are replaced: `/opt/ps` matches
+//! in `/opt/ps` and `/opt/ps/envs`, never in `/opt/ps2` or `/x/opt/ps`. Files are streamed
+//! line by line into a sibling file that is renamed over the original, so the other names of
+//! a hard-linked file keep the old content.
+
+use crate::Result;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+/// Bytes inspected to tell text from binary (same heuristic as git)
+const BINARY_SNIFF_LEN: usize = 8000;
+
+fn is_path_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | '/' | '\\' | '~' | '+' | '@')
+}
+
+/// `old` starts a path token at `start` of `text` and ends one at `end`
+fn is_token(text: &str, start: usize, end: usize) -> bool {
+    let before = &text[..start];
+    let starts = before.ends_with("file://") || !before.chars().next_back().is_some_and(is_path_char);
+    let rest = &text[end..];
+    let ends = rest.starts_with(['/', '\\']) || !rest.chars().next().is_some_and(is_path_char);
+    starts && ends
+}
+
+/// `text` with every whole-token `old` replaced by `new`; None when there is none
+pub fn replace_path(text: &str, old: &str, new: &str) -> Option<String> {
+    if old.is_empty() {
+        return None;
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut last = 0;
+    let mut changed = false;
+    for (start, _) in text.match_indices(old) {
+        if start < last || !is_token(text, start, start + old.len()) {
+            continue;
+        }
+        out.push_str(&text[last..start]);
+        out.push_str(new);
+        last = start + old.len();
+        changed = true;
+    }
+    changed.then(|| out + &text[last..])
+}
+
+/// Whether `text` names `old` as a whole path token
+pub fn mentions_path(text: &str, old: &str) -> bool {
+    !old.is_empty() && text.match_indices(old).any(|(start, _)| is_token(text, start, start + old.len()))
+}
+
+/// First bytes of `path` contain a NUL byte
+pub fn is_binary(path: &Path) -> Result<bool> {
+    let mut head = Vec::with_capacity(BINARY_SNIFF_LEN);
+    File::open(path)?.take(BINARY_SNIFF_LEN as u64).read_to_end(&mut head)?;
+    Ok(head.contains(&0))
+}
+
+/// Whether the file contains `needle`, read in chunks
+pub fn file_contains(path: &Path, needle: &[u8]) -> Result<bool> {
+    if needle.is_empty() {
+        return Ok(false);
+    }
+    let mut reader = BufReader::with_capacity(1 << 16, File::open(path)?);
+    let mut carry: Vec<u8> = Vec::new();
+    loop {
+        let chunk = reader.fill_buf()?;
+        if chunk.is_empty() {
+            return Ok(false);
+        }
+        carry.extend_from_slice(chunk);
+        let consumed = chunk.len();
+        reader.consume(consumed);
+        if carry.windows(needle.len()).any(|w| w == needle) {
+            return Ok(true);
+        }
+        // Keep the tail a match could start in
+        let keep = carry.len().min(needle.len() - 1);
+        carry.drain(..carry.len() - keep);
+    }
+}
+
+/// Replace `old` by `new` in a text file, line by line; binary files and files that are not
+/// UTF-8 are left alone. Returns whether the file changed
+pub fn rewrite_file(path: &Path, old: &str, new: &str) -> Result<bool> {
+    if is_binary(path)? {
+        return Ok(false);
+    }
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut out = tempfile::NamedTempFile::new_in(dir)?;
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut line = Vec::new();
+    let mut changed = false;
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        let Ok(text) = std::str::from_utf8(&line) else { return Ok(false) };
+        match replace_path(text, old, new) {
+            Some(replaced) => {
+                out.write_all(replaced.as_bytes())?;
+                changed = true;
+            }
+            None => out.write_all(&line)?,
+        }
+    }
+    if !changed {
+        return Ok(false);
+    }
+    out.as_file().set_permissions(fs::metadata(path)?.permissions())?;
+    out.persist(path).map_err(|e| e.error)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_whole_path_tokens_are_replaced() {
+        let text = "#!/opt/ps/envs/a/bin/python\nA=/opt/ps B=/opt/ps2 C=/x/opt/ps D=\"/opt/ps\" E=file:///opt/ps/repos\n";
+        let replaced = replace_path(text, "/opt/ps", "/srv/new").unwrap();
+        assert_eq!(
+            replaced,
+            "#!/srv/new/envs/a/bin/python\nA=/srv/new B=/opt/ps2 C=/x/opt/ps D=\"/srv/new\" E=file:///srv/new/repos\n"
+        );
+        assert_eq!(replace_path("/opt/ps.bak and /opt/psx", "/opt/ps", "/srv/new"), None);
+        assert!(mentions_path("cd C:\\ps\\repos", "C:\\ps") && !mentions_path("C:\\ps2", "C:\\ps"));
+    }
+
+    #[test]
+    fn rewriting_a_hard_linked_file_leaves_the_other_link_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let original = dir.path().join("activate");
+        let link = dir.path().join("activate-link");
+        fs::write(&original, "VIRTUAL_ENV=/opt/ps/envs/a\n").unwrap();
+        fs::hard_link(&original, &link).unwrap();
+
+        assert!(rewrite_file(&link, "/opt/ps", "/srv/new").unwrap());
+        assert_eq!(fs::read_to_string(&link).unwrap(), "VIRTUAL_ENV=/srv/new/envs/a\n");
+        assert_eq!(fs::read_to_string(&original).unwrap(), "VIRTUAL_ENV=/opt/ps/envs/a\n");
+        assert!(!rewrite_file(&link, "/opt/ps", "/srv/new").unwrap());
+        assert!(file_contains(&original, b"/opt/ps/envs").unwrap());
+    }
+}
@@ -0,0 +1,170 @@
+//! Performance profiles baked into start scripts
+//!
+//! A repository gets a profile at install (`install-repo --profile`) or later with
+//! `tune-repo`; it is kept in the repo run settings. The start script then exports
+//! allocator, CUDA module loading and threading variables picked from the profile, the
+//! GPU generation and its VRAM. Values already set in the user's environment win.
+
+use crate::config::{ConfigManager, GpuGeneration};
+use crate::{PortableSourceError, Result};
+use serde::{Deserialize, Serialize};
+
+/// Cards with at most this much VRAM get the fragmentation-friendly allocator under `balanced`
+const SMALL_VRAM_MB: u32 = 8192;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum PerformanceProfile {
+    /// Less fragmentation, lazy CUDA module loading, half the CPU threads
+    #[default]
+    Balanced,
+    /// Fit bigger models: aggressive allocator garbage collection
+    LowVram,
+    /// Fastest kernels: eager module loading, TF32 on Ampere and newer, all CPU threads
+    MaxSpeed,
+}
+
+impl PerformanceProfile {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PerformanceProfile::Balanced => "balanced",
+            PerformanceProfile::LowVram => "low-vram",
+            PerformanceProfile::MaxSpeed => "max-speed",
+        }
+    }
+}
+
+impl std::fmt::Display for PerformanceProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for PerformanceProfile {
+    type Err = PortableSourceError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().replace('_', "-").as_str() {
+            "balanced" => Ok(PerformanceProfile::Balanced),
+            "low-vram" => Ok(PerformanceProfile::LowVram),
+            "max-speed" => Ok(PerformanceProfile::MaxSpeed),
+            other => Err(PortableSourceError::config(format!(
+                "Unknown performance profile '{}' (expected balanced, low-vram or max-speed)", other
+            ))),
+        }
+    }
+}
+
+/// Hardware the tuning is derived from
+#[derive(Debug, Clone)]
+pub struct Hardware {
+    /// `Unknown` when there is no NVIDIA GPU; CUDA variables are skipped then
+    pub generation: GpuGeneration,
+    pub vram_mb: u32,
+    pub cpu_threads: usize,
+    /// Windows PyTorch builds do not support expandable segments
+    pub windows: bool,
+}
+
+impl Hardware {
+    pub fn detect(config_manager: &ConfigManager) -> Self {
+        let gpu = config_manager.detect_gpu();
+        Self {
+            generation: gpu.as_ref().map(|g| config_manager.detect_gpu_generation(&g.name)).unwrap_or(GpuGeneration::Unknown),
+            vram_mb: gpu.map(|g| g.memory_mb).unwrap_or(0),
+            cpu_threads: std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            windows: cfg!(windows),
+        }
+    }
+}
+
+/// Environment a profile resolves to on some hardware
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tuning {
+    pub profile: PerformanceProfile,
+    pub env: Vec<(&'static str, String)>,
+}
+
+impl Tuning {
+    pub fn new(profile: PerformanceProfile, hw: &Hardware) -> Self {
+        let mut env = Vec::new();
+
+        if hw.generation != GpuGeneration::Unknown {
+            let loading = if profile == PerformanceProfile::MaxSpeed { "EAGER" } else { "LAZY" };
+            env.push(("CUDA_MODULE_LOADING", loading.to_string()));
+
+            let small_vram = hw.vram_mb > 0 && hw.vram_mb <= SMALL_VRAM_MB;
+            let alloc: &[&str] = match profile {
+                PerformanceProfile::LowVram => low_vram_alloc(hw.windows),
+                PerformanceProfile::Balanced if small_vram => low_vram_alloc(hw.windows),
+                PerformanceProfile::Balanced if !hw.windows => &["expandable_segments:True"],
+                _ => &[],
+            };
+            if !alloc.is_empty() {
+                env.push(("PYTORCH_CUDA_ALLOC_CONF", alloc.join(",")));
+            }
+
+            let tensor_cores = matches!(hw.generation, GpuGeneration::Ampere | GpuGeneration::AdaLovelace | GpuGeneration::Blackwell);
+            if profile == PerformanceProfile::MaxSpeed && tensor_cores {
+                env.push(("TORCH_ALLOW_TF32_CUBLAS_OVERRIDE", "1".to_string()));
+            }
+        }
+
+        let threads = match profile {
+            PerformanceProfile::MaxSpeed => hw.cpu_threads,
+            _ => hw.cpu_threads / 2,
+        };
+        env.push(("OMP_NUM_THREADS", threads.max(1).to_string()));
+
+        Self { profile, env }
+    }
+}
+
+fn low_vram_alloc(windows: bool) -> &'static [&'static str] {
+    if windows {
+        &["garbage_collection_threshold:0.6", "max_split_size_mb:128"]
+    } else {
+        &["expandable_segments:True", "garbage_collection_threshold:0.6"]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hw(generation: GpuGeneration, vram_mb: u32, windows: bool) -> Hardware {
+        Hardware { generation, vram_mb, cpu_threads: 16, windows }
+    }
+
+    fn get<'a>(tuning: &'a Tuning, name: &str) -> Option<&'a str> {
+        tuning.env.iter().find(|(k, _)| *k == name).map(|(_, v)| v.as_str())
+    }
+
+    #[test]
+    fn profiles_follow_gpu_and_vram() {
+        let big = Tuning::new(PerformanceProfile::Balanced, &hw(GpuGeneration::AdaLovelace, 24_000, false));
+        assert_eq!(get(&big, "PYTORCH_CUDA_ALLOC_CONF"), Some("expandable_segments:True"));
+        assert_eq!(get(&big, "CUDA_MODULE_LOADING"), Some("LAZY"));
+        assert_eq!(get(&big, "OMP_NUM_THREADS"), Some("8"));
+
+        let small_win = Tuning::new(PerformanceProfile::Balanced, &hw(GpuGeneration::Turing, 6_000, true));
+        assert_eq!(get(&small_win, "PYTORCH_CUDA_ALLOC_CONF"), Some("garbage_collection_threshold:0.6,max_split_size_mb:128"));
+
+        let fast = Tuning::new(PerformanceProfile::MaxSpeed, &hw(GpuGeneration::Ampere, 12_000, false));
+        assert_eq!(get(&fast, "CUDA_MODULE_LOADING"), Some("EAGER"));
+        assert_eq!(get(&fast, "TORCH_ALLOW_TF32_CUBLAS_OVERRIDE"), Some("1"));
+        assert_eq!(get(&fast, "PYTORCH_CUDA_ALLOC_CONF"), None);
+        assert_eq!(get(&fast, "OMP_NUM_THREADS"), Some("16"));
+
+        let cpu_only = Tuning::new(PerformanceProfile::LowVram, &hw(GpuGeneration::Unknown, 0, false));
+        assert_eq!(cpu_only.env, vec![("OMP_NUM_THREADS", "8".to_string())]);
+    }
+
+    #[test]
+    fn profile_names_round_trip() {
+        for profile in [PerformanceProfile::Balanced, PerformanceProfile::LowVram, PerformanceProfile::MaxSpeed] {
+            assert_eq!(profile.as_str().parse::<PerformanceProfile>().unwrap(), profile);
+        }
+        assert_eq!("LOW_VRAM".parse::<PerformanceProfile>().unwrap(), PerformanceProfile::LowVram);
+    }
+}
@@ -0,0 +1,269 @@
+//! Planned file system changes of destructive commands
+//!
+//! delete-repo, update-repo, uninstall and config migrate describe what they are about to
+//! do as an [`ActionPlan`]: every path that will be deleted, moved, modified or kept, with
+//! its size and the reason. The plan is shown before confirmation and is all `--dry-run`
+//! prints.
+
+use crate::output;
+use crate::Result;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlanAction {
+    Delete,
+    /// Moved out of the way instead of deleted
+    MoveTo(PathBuf),
+    /// Rewritten in place
+    Modify,
+    Keep,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlanItem {
+    pub path: PathBuf,
+    pub action: PlanAction,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ActionPlan {
+    pub items: Vec<PlanItem>,
+    /// Consequences the user should know before confirming
+    pub notes: Vec<String>,
+}
+
+impl ActionPlan {
+    /// Delete `path`; paths that do not exist are not listed
+    pub fn delete(&mut self, path: PathBuf, reason: &str) {
+        if path.exists() || fs::symlink_metadata(&path).is_ok() {
+            self.push(path, PlanAction::Delete, reason);
+        }
+    }
+
+    pub fn move_to(&mut self, path: PathBuf, to: PathBuf, reason: &str) {
+        self.push(path, PlanAction::MoveTo(to), reason);
+    }
+
+    pub fn modify(&mut self, path: PathBuf, reason: &str) {
+        self.push(path, PlanAction::Modify, reason);
+    }
+
+    pub fn keep(&mut self, path: PathBuf, reason: &str) {
+        if path.exists() {
+            self.push(path, PlanAction::Keep, reason);
+        }
+    }
+
+    fn push(&mut self, path: PathBuf, action: PlanAction, reason: &str) {
+        self.items.push(PlanItem { path, action, reason: reason.to_string() });
+    }
+
+    /// Nothing would change
+    pub fn is_empty(&self) -> bool {
+        self.items.iter().all(|i| i.action == PlanAction::Keep)
+    }
+
+    /// One section per action, each path with its size and reason
+    pub fn render_items(&self) -> String {
+        let mut out = String::new();
+        let section = |out: &mut String, title: &str, items: Vec<&PlanItem>| {
+            if items.is_empty() {
+                return;
+            }
+            out.push_str(title);
+            out.push('\n');
+            for item in items {
+                let target = match &item.action {
+                    PlanAction::MoveTo(to) => format!(" -> {}", to.display()),
+                    _ => String::new(),
+                };
+                // Moved folders are not part of the size of the folder they leave
+                let moved: u64 = self
+                    .items
+                    .iter()
+                    .filter(|other| matches!(other.action, PlanAction::MoveTo(_)) && other.path != item.path && other.path.starts_with(&item.path))
+                    .map(|other| dir_size(&other.path))
+                    .sum();
+                out.push_str(&format!(
+                    "  {}{} [{}] ({})\n",
+                    item.path.display(),
+                    target,
+                    format_size(dir_size(&item.path).saturating_sub(moved)),
+                    item.reason
+                ));
+            }
+        };
+        section(&mut out, "Will delete:", self.items.iter().filter(|i| i.action == PlanAction::Delete).collect());
+        section(&mut out, "Will move:", self.items.iter().filter(|i| matches!(i.action, PlanAction::MoveTo(_))).collect());
+        section(&mut out, "Will modify:", self.items.iter().filter(|i| i.action == PlanAction::Modify).collect());
+        section(&mut out, "Will keep:", self.items.iter().filter(|i| i.action == PlanAction::Keep).collect());
+        out
+    }
+
+    pub fn render_notes(&self) -> String {
+        self.notes.iter().map(|note| format!("[NOTE] {}\n", note)).collect()
+    }
+
+    /// Human-readable listing shown before confirmation
+    pub fn render(&self) -> String {
+        self.render_items() + &self.render_notes()
+    }
+
+    /// What `--dry-run` prints: the plan, then that nothing was touched
+    pub fn print_dry_run(&self) {
+        if self.is_empty() {
+            output::info("Dry run: nothing would change");
+            return;
+        }
+        print!("{}", self.render());
+        output::info("Dry run: nothing was changed");
+    }
+
+    /// Delete and move as planned; modifications are up to the command, kept entries are
+    /// left alone
+    pub fn execute(&self) -> Result<()> {
+        for item in &self.items {
+            match &item.action {
+                PlanAction::Delete => {
+                    let is_link = fs::symlink_metadata(&item.path).is_ok_and(|m| m.file_type().is_symlink());
+                    if item.path.is_dir() && !is_link {
+                        fs::remove_dir_all(&item.path)?;
+                    } else if is_link {
+                        crate::shared_models::remove_link(&item.path)?;
+                    } else if item.path.exists() {
+                        fs::remove_file(&item.path)?;
+                    }
+                }
+                PlanAction::MoveTo(to) => move_path(&item.path, to)?,
+                PlanAction::Modify | PlanAction::Keep => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Move `from` to `to`; across drives, where a rename fails, copy and then delete
+pub fn move_path(from: &Path, to: &Path) -> Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            if let Err(e) = copy_tree(from, to) {
+                let _ = fs::remove_dir_all(to);
+                return Err(e);
+            }
+            if from.is_dir() && !fs::symlink_metadata(from)?.file_type().is_symlink() {
+                fs::remove_dir_all(from)?;
+            } else {
+                fs::remove_file(from)?;
+            }
+            Ok(())
+        }
+        result => Ok(result?),
+    }
+}
+
+/// Copy a file or directory tree; links are copied as links, not followed
+fn copy_tree(from: &Path, to: &Path) -> Result<()> {
+    let meta = fs::symlink_metadata(from)?;
+    if meta.file_type().is_symlink() {
+        let target = fs::read_link(from)?;
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&target, to)?;
+        #[cfg(windows)]
+        if from.is_dir() {
+            std::os::windows::fs::symlink_dir(&target, to)?;
+        } else {
+            std::os::windows::fs::symlink_file(&target, to)?;
+        }
+    } else if meta.is_dir() {
+        fs::create_dir_all(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_tree(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        fs::set_permissions(to, meta.permissions())?;
+    } else {
+        fs::copy(from, to)?;
+    }
+    Ok(())
+}
+
+/// Bytes of the files under `path`; a link counts as nothing, whatever it points to
+pub fn dir_size(path: &Path) -> u64 {
+    if fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink()) {
+        return 0;
+    }
+    WalkDir::new(path)
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.metadata().ok())
+        .filter(|m| m.is_file())
+        .map(|m| m.len())
+        .sum()
+}
+
+fn format_size(bytes: u64) -> String {
+    const MB: u64 = 1024 * 1024;
+    if bytes >= 1024 * MB {
+        format!("{:.1} GB", bytes as f64 / (1024 * MB) as f64)
+    } else {
+        format!("{:.1} MB", bytes as f64 / MB as f64)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plan_lists_sizes_and_execute_leaves_link_targets_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let shared = root.join("shared_models/checkpoints");
+        fs::create_dir_all(&shared).unwrap();
+        fs::write(shared.join("sd.safetensors"), vec![0u8; 3 * 1024 * 1024]).unwrap();
+        let repo = root.join("repos/comfyui");
+        fs::create_dir_all(repo.join("models")).unwrap();
+        fs::write(repo.join("main.py"), vec![0u8; 1024 * 1024]).unwrap();
+        std::os::unix::fs::symlink(&shared, repo.join("models/checkpoints")).unwrap();
+        let config = root.join("portablesource_config.json");
+        fs::write(&config, "{}").unwrap();
+
+        let mut plan = ActionPlan::default();
+        plan.delete(repo.join("models/checkpoints"), "link into shared_models");
+        plan.delete(repo.clone(), "repository source");
+        plan.delete(root.join("envs/comfyui"), "repository environment");
+        plan.modify(config.clone(), "rewritten");
+        assert_eq!(plan.items.len(), 3);
+
+        let text = plan.render();
+        assert!(text.contains(&format!("  {} [0.0 MB] (link into shared_models)\n", repo.join("models/checkpoints").display())));
+        assert!(text.contains(&format!("  {} [1.0 MB] (repository source)\n", repo.display())));
+        assert!(text.contains("Will modify:\n"));
+
+        plan.execute().unwrap();
+        assert!(!repo.exists());
+        assert!(shared.join("sd.safetensors").exists());
+        assert_eq!(fs::read_to_string(&config).unwrap(), "{}");
+    }
+
+    #[test]
+    fn copied_trees_keep_links_as_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let env = dir.path().join("old/envs/comfyui");
+        fs::create_dir_all(env.join("bin")).unwrap();
+        fs::write(env.join("pyvenv.cfg"), "home = /opt/python/bin\n").unwrap();
+        std::os::unix::fs::symlink("/opt/python/bin/python3", env.join("bin/python")).unwrap();
+
+        let copy = dir.path().join("new/envs/comfyui");
+        fs::create_dir_all(copy.parent().unwrap()).unwrap();
+        copy_tree(&env, &copy).unwrap();
+        assert_eq!(fs::read_link(copy.join("bin/python")).unwrap(), Path::new("/opt/python/bin/python3"));
+        assert_eq!(fs::read_to_string(copy.join("pyvenv.cfg")).unwrap(), "home = /opt/python/bin\n");
+    }
+}
@@ -0,0 +1,290 @@
+//! Installer plugins
+//!
+//! Niche repositories sometimes need steps no generic installer knows: fetching models from
+//! a custom host, accepting a license on a website, patching a config after install. A
+//! plugin is a folder under `<install>/plugins` with a `plugin.json` manifest naming a
+//! command, the hooks it handles and the repositories it applies to:
+//!
+//! ```json
+//! { "name": "kohya", "command": ["python", "plugin.py"],
+//!   "hooks": ["resolve", "install", "post-install"], "repos": ["kohya_ss", "sd-scripts*"] }
+//! ```
+//!
+//! For every hook the command runs once in the plugin folder (`python` is the portable
+//! Python) and gets one JSON request on stdin: `{"protocol": 1, "hook": ..., "repo": {...},
+//! "install_path": ...}`. It answers with one JSON object on the last line of stdout; its
+//! log goes to stderr. Common answer fields are `messages` (shown to the user) and `error`
+//! (aborts the install); `resolve` may answer `url`, `main_file`, `program_args` and
+//! `license` to describe a repository the server does not know, `install` may answer
+//! `packages` to install into the repository environment.
+
+use crate::{output, PortableSourceError, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+pub const PROTOCOL_VERSION: u32 = 1;
+pub const MANIFEST_FILE: &str = "plugin.json";
+pub const PLUGIN_TIMEOUT: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Hook {
+    /// Before cloning: describe the repository (URL, entry point, license)
+    Resolve,
+    /// After the dependencies are installed
+    Install,
+    /// After the start script is written
+    PostInstall,
+}
+
+impl std::fmt::Display for Hook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Hook::Resolve => "resolve",
+            Hook::Install => "install",
+            Hook::PostInstall => "post-install",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PluginManifest {
+    pub name: String,
+    /// Program and arguments; a leading `python` runs the portable Python
+    pub command: Vec<String>,
+    pub hooks: Vec<Hook>,
+    /// Repository names the plugin applies to (`*` suffix for prefixes); empty: every repository
+    #[serde(default)]
+    pub repos: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Plugin {
+    pub dir: PathBuf,
+    pub manifest: PluginManifest,
+}
+
+impl Plugin {
+    pub fn applies_to(&self, hook: Hook, repo: &str) -> bool {
+        let repo = repo.to_lowercase();
+        self.manifest.hooks.contains(&hook)
+            && (self.manifest.repos.is_empty()
+                || self.manifest.repos.iter().map(|p| p.to_lowercase()).any(|p| match p.strip_suffix('*') {
+                    Some(prefix) => repo.starts_with(prefix),
+                    None => repo == p,
+                }))
+    }
+}
+
+/// Repository a hook runs for
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct HookRepo {
+    pub name: String,
+    pub upstream: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    /// Python of the repository environment (install and post-install)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub env_python: Option<PathBuf>,
+}
+
+#[derive(Debug, Serialize)]
+struct HookRequest<'a> {
+    protocol: u32,
+    hook: Hook,
+    repo: &'a HookRepo,
+    install_path: &'a Path,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct HookResponse {
+    pub messages: Vec<String>,
+    pub error: Option<String>,
+    pub url: Option<String>,
+    pub main_file: Option<String>,
+    pub program_args: Option<String>,
+    pub license: Option<String>,
+    pub packages: Vec<String>,
+}
+
+/// Last stdout line holding a JSON object; earlier lines are the plugin's own output
+fn parse_response(stdout: &str) -> Option<HookResponse> {
+    stdout.lines().rev().map(str::trim).find(|l| l.starts_with('{')).and_then(|l| serde_json::from_str(l).ok())
+}
+
+pub fn plugins_dir(install_path: &Path) -> PathBuf {
+    install_path.join("plugins")
+}
+
+/// Plugins found under `<install>/plugins`, with a warning per unreadable manifest
+pub fn discover(install_path: &Path) -> (Vec<Plugin>, Vec<String>) {
+    let mut dirs: Vec<PathBuf> = std::fs::read_dir(plugins_dir(install_path))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.join(MANIFEST_FILE).is_file())
+        .collect();
+    dirs.sort();
+
+    let mut plugins = Vec::new();
+    let mut warnings = Vec::new();
+    for dir in dirs {
+        let manifest = std::fs::read_to_string(dir.join(MANIFEST_FILE))
+            .map_err(|e| e.to_string())
+            .and_then(|c| serde_json::from_str::<PluginManifest>(&c).map_err(|e| e.to_string()));
+        match manifest {
+            Ok(manifest) if manifest.command.is_empty() => warnings.push(format!("Plugin {:?} has an empty command", dir)),
+            Ok(manifest) => plugins.push(Plugin { dir, manifest }),
+            Err(e) => warnings.push(format!("Plugin manifest {:?} is invalid: {}", dir.join(MANIFEST_FILE), e)),
+        }
+    }
+    (plugins, warnings)
+}
+
+/// Discovered plugins and how to run them
+#[derive(Debug, Clone, Default)]
+pub struct PluginHost {
+    install_path: PathBuf,
+    python: Option<PathBuf>,
+    pub plugins: Vec<Plugin>,
+}
+
+impl PluginHost {
+    pub fn load(install_path: &Path, python: Option<PathBuf>) -> Self {
+        let (plugins, warnings) = discover(install_path);
+        for warning in warnings {
+            tracing::warn!("{}", warning);
+        }
+        Self { install_path: install_path.to_path_buf(), python, plugins }
+    }
+
+    fn command(&self, plugin: &Plugin) -> Command {
+        let program = &plugin.manifest.command[0];
+        let program = match (program.as_str(), &self.python) {
+            ("python" | "python3", Some(python)) => python.clone(),
+            _ if program.starts_with("./") || program.starts_with(".\\") => plugin.dir.join(program),
+            _ => PathBuf::from(program),
+        };
+        let mut cmd = Command::new(program);
+        cmd.args(&plugin.manifest.command[1..]).current_dir(&plugin.dir);
+
+        #[cfg(target_os = "windows")]
+        {
+            use std::os::windows::process::CommandExt;
+            cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
+        }
+        cmd
+    }
+
+    fn call(&self, plugin: &Plugin, hook: Hook, repo: &HookRepo) -> Result<HookResponse> {
+        let name = &plugin.manifest.name;
+        let request = serde_json::to_string(&HookRequest { protocol: PROTOCOL_VERSION, hook, repo, install_path: &self.install_path })?;
+        let mut child = self
+            .command(plugin)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| PortableSourceError::command(format!("Plugin '{}' failed to start: {}", name, e)))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            let _ = writeln!(stdin, "{}", request);
+        }
+        let mut stdout = child.stdout.take();
+        let mut stderr = child.stderr.take();
+        let out = std::thread::spawn(move || {
+            let mut s = String::new();
+            stdout.as_mut().map(|o| o.read_to_string(&mut s));
+            s
+        });
+        let err = std::thread::spawn(move || {
+            let mut s = String::new();
+            stderr.as_mut().map(|e| e.read_to_string(&mut s));
+            s
+        });
+
+        let started = Instant::now();
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if started.elapsed() > PLUGIN_TIMEOUT {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(PortableSourceError::command(format!("Plugin '{}' timed out in the {} hook", name, hook)));
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        };
+        let stdout = out.join().unwrap_or_default();
+        let stderr = err.join().unwrap_or_default();
+        for line in stderr.lines().filter(|l| !l.trim().is_empty()) {
+            tracing::info!("[plugin {}] {}", name, line);
+        }
+        if !status.success() {
+            let tail: Vec<&str> = stderr.lines().rev().take(5).collect::<Vec<_>>().into_iter().rev().collect();
+            return Err(PortableSourceError::command(format!("Plugin '{}' failed in the {} hook:\n{}", name, hook, tail.join("\n"))));
+        }
+        let response = parse_response(&stdout)
+            .ok_or_else(|| PortableSourceError::command(format!("Plugin '{}' gave no JSON answer to the {} hook", name, hook)))?;
+        for message in &response.messages {
+            output::info(&format!("[{}] {}", name, message));
+        }
+        if let Some(error) = &response.error {
+            return Err(PortableSourceError::installation(format!("Plugin '{}': {}", name, error)));
+        }
+        Ok(response)
+    }
+
+    /// Run a hook in every plugin that applies to the repository, in name order
+    pub fn run(&self, hook: Hook, repo: &HookRepo) -> Result<Vec<HookResponse>> {
+        let mut responses = Vec::new();
+        for plugin in self.plugins.iter().filter(|p| p.applies_to(hook, &repo.upstream) || p.applies_to(hook, &repo.name)) {
+            output::step(&format!("Running plugin '{}' ({})", plugin.manifest.name, hook));
+            responses.push(self.call(plugin, hook, repo)?);
+        }
+        Ok(responses)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plugins_are_discovered_and_answer_over_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let plugin = plugins_dir(root).join("kohya");
+        std::fs::create_dir_all(&plugin).unwrap();
+        std::fs::write(
+            plugin.join(MANIFEST_FILE),
+            r#"{"name": "kohya", "command": ["sh", "hook.sh"], "hooks": ["resolve", "install"], "repos": ["kohya*"]}"#,
+        )
+        .unwrap();
+        std::fs::write(
+            plugin.join("hook.sh"),
+            "read request\necho \"got $request\" >&2\necho 'progress...'\ncase \"$request\" in\n  *'\"hook\":\"resolve\"'*) echo '{\"url\": \"https://example.org/kohya_ss.git\", \"messages\": [\"resolved\"]}' ;;\n  *) echo '{\"error\": \"models host unreachable\"}' ;;\nesac\n",
+        )
+        .unwrap();
+        std::fs::create_dir_all(plugins_dir(root).join("broken")).unwrap();
+        std::fs::write(plugins_dir(root).join("broken").join(MANIFEST_FILE), "{").unwrap();
+
+        let (plugins, warnings) = discover(root);
+        assert_eq!((plugins.len(), warnings.len()), (1, 1));
+        assert!(plugins[0].applies_to(Hook::Install, "Kohya_SS") && !plugins[0].applies_to(Hook::PostInstall, "kohya_ss"));
+
+        let host = PluginHost { install_path: root.to_path_buf(), python: None, plugins };
+        let repo = HookRepo { name: "kohya_ss".into(), upstream: "kohya_ss".into(), ..Default::default() };
+        let resolved = host.run(Hook::Resolve, &repo).unwrap();
+        assert_eq!(resolved[0].url.as_deref(), Some("https://example.org/kohya_ss.git"));
+        let err = host.run(Hook::Install, &repo).unwrap_err();
+        assert_eq!(err.to_string(), "Installation error: Plugin 'kohya': models host unreachable");
+        assert!(host.run(Hook::Install, &HookRepo { name: "comfyui".into(), upstream: "comfyui".into(), ..Default::default() }).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,207 @@
+//! Dependency cache warming
+//!
+//! `prefetch <repo|recipe>...` resolves what installing a repository would pull (server
+//! install plan, else the repository's requirements) and runs `pip download` into the
+//! shared wheel cache `cache/wheels` without creating an environment. Installs pass the
+//! cache as `--find-links`, so a prefetched repository installs with little or no network.
+//!
+//! A recipe is a `.recipe` text file listing repositories (names or git URLs), one per line,
+//! with `#` comments, so a whole set can be prefetched overnight or before going offline.
+//! Other targets are always repositories, even when a file of that name exists.
+
+use crate::installer::{InstallationPlan, PackageInfo, RequirementsAnalyzer};
+use crate::Result;
+use serde_json::Value as JsonValue;
+use std::path::{Path, PathBuf};
+
+/// Shared wheel cache, relative to the install path
+pub fn wheel_cache_dir(install_path: &Path) -> PathBuf {
+    install_path.join("cache").join("wheels")
+}
+
+/// Sources of repositories that were prefetched but are not installed
+pub fn source_cache_dir(install_path: &Path) -> PathBuf {
+    install_path.join("cache").join("sources")
+}
+
+/// Cache directory to pass as `--find-links`, when something was prefetched
+pub fn find_links(install_path: &Path) -> Option<PathBuf> {
+    let dir = wheel_cache_dir(install_path);
+    std::fs::read_dir(&dir).ok()?.next()?.ok()?;
+    Some(dir)
+}
+
+/// Extension that marks a prefetch target as a recipe file
+pub const RECIPE_EXTENSION: &str = "recipe";
+
+/// Repositories listed in a recipe file
+pub fn read_recipe(path: &Path) -> Result<Vec<String>> {
+    Ok(std::fs::read_to_string(path)?
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or("").trim())
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Command line targets with recipe files replaced by the repositories they list
+pub fn expand_targets(targets: &[String]) -> Result<Vec<String>> {
+    let mut repos = Vec::new();
+    for target in targets {
+        let path = Path::new(target);
+        let is_recipe = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case(RECIPE_EXTENSION));
+        let listed = if is_recipe { read_recipe(path)? } else { vec![target.clone()] };
+        for repo in listed {
+            if !repos.contains(&repo) {
+                repos.push(repo);
+            }
+        }
+    }
+    Ok(repos)
+}
+
+/// One `pip download` call
+#[derive(Debug, Clone, PartialEq)]
+pub struct DownloadBatch {
+    pub label: String,
+    /// Arguments after `pip download -d <cache>`
+    pub args: Vec<String>,
+}
+
+/// Batches for requirement lines, split the way the installer splits them
+pub fn batches_for_packages(analyzer: &RequirementsAnalyzer, packages: &[PackageInfo]) -> Vec<DownloadBatch> {
+    let plan = analyzer.create_installation_plan(packages);
+    batches_for_plan(&plan)
+}
+
+fn batches_for_plan(plan: &InstallationPlan) -> Vec<DownloadBatch> {
+    let specs = |packages: &[PackageInfo]| packages.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+    let mut batches = Vec::new();
+    let mut regular = specs(&plan.regular_packages);
+    regular.extend(specs(&plan.insightface_packages));
+    regular.extend(specs(&plan.triton_packages));
+    if !regular.is_empty() {
+        batches.push(DownloadBatch { label: "packages".into(), args: regular });
+    }
+    if !plan.torch_packages.is_empty() {
+        let mut args = Vec::new();
+        if let Some(index) = &plan.torch_index_url {
+            args.extend(["--index-url".to_string(), index.clone()]);
+        }
+        args.extend(specs(&plan.torch_packages));
+        batches.push(DownloadBatch { label: "torch".into(), args });
+    }
+    if !plan.onnx_packages.is_empty() {
+        let onnx_name = plan.onnx_package_name.as_deref().unwrap_or("onnxruntime");
+        let args = plan
+            .onnx_packages
+            .iter()
+            .map(|p| if p.name == "onnxruntime" { p.to_string().replacen("onnxruntime", onnx_name, 1) } else { p.to_string() })
+            .collect();
+        batches.push(DownloadBatch { label: "onnx".into(), args });
+    }
+    batches
+}
+
+/// Packages of a requirements file
+pub fn read_requirements(analyzer: &RequirementsAnalyzer, path: &Path) -> Result<Vec<PackageInfo>> {
+    Ok(std::fs::read_to_string(path)?
+        .lines()
+        .filter_map(|line| analyzer.parse_requirement_line(line))
+        .collect())
+}
+
+/// Whether a server install plan reads requirement files from the repository
+pub fn plan_needs_source(plan: &JsonValue) -> bool {
+    plan.get("steps")
+        .and_then(|s| s.as_array())
+        .is_some_and(|steps| steps.iter().any(|s| s.get("type").and_then(|t| t.as_str()) == Some("requirements")))
+}
+
+/// Batches for a server install plan; `requirements` steps need the repository source
+pub fn batches_for_server_plan(analyzer: &RequirementsAnalyzer, plan: &JsonValue, repo_path: Option<&Path>) -> Result<Vec<DownloadBatch>> {
+    let mut packages = Vec::new();
+    for step in plan.get("steps").and_then(|s| s.as_array()).into_iter().flatten() {
+        match step.get("type").and_then(|t| t.as_str()).unwrap_or("") {
+            "requirements" => {
+                if let (Some(path), Some(repo)) = (step.get("path").and_then(|p| p.as_str()), repo_path) {
+                    packages.extend(read_requirements(analyzer, &repo.join(path))?);
+                }
+            }
+            "pip_install" | "regular" | "regular_only" => {
+                let lines = step.get("packages").and_then(|p| p.as_array()).into_iter().flatten();
+                packages.extend(lines.filter_map(|p| p.as_str()).filter_map(|s| analyzer.parse_requirement_line(s)));
+            }
+            _ => {}
+        }
+    }
+    Ok(batches_for_packages(analyzer, &packages))
+}
+
+/// `python -m pip download` command for one batch
+pub fn download_command(python: &Path, cache: &Path, batch: &DownloadBatch) -> Vec<String> {
+    let mut cmd = vec![
+        python.to_string_lossy().to_string(),
+        "-m".into(), "pip".into(), "download".into(),
+        "-d".into(), cache.to_string_lossy().to_string(),
+    ];
+    cmd.extend(batch.args.iter().cloned());
+    cmd
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigManager;
+
+    #[test]
+    fn recipes_expand_to_the_repositories_they_list() {
+        let dir = tempfile::tempdir().unwrap();
+        let recipe = dir.path().join("travel.recipe");
+        std::fs::write(&recipe, "# video\nfacefusion\n\nhttps://github.com/acme/demo.git  # extra\ncomfyui\n").unwrap();
+        let targets = expand_targets(&["comfyui".to_string(), recipe.to_string_lossy().to_string()]).unwrap();
+        assert_eq!(targets, ["comfyui", "facefusion", "https://github.com/acme/demo.git"]);
+    }
+
+    #[test]
+    fn other_files_are_repository_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let notes = dir.path().join("comfyui");
+        std::fs::write(&notes, "facefusion\n").unwrap();
+        let target = notes.to_string_lossy().to_string();
+        assert_eq!(expand_targets(std::slice::from_ref(&target)).unwrap(), [target]);
+    }
+
+    #[test]
+    fn missing_recipe_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("travel.recipe").to_string_lossy().to_string();
+        assert!(expand_targets(&[missing]).is_err());
+    }
+
+    #[test]
+    fn server_plans_split_into_batches_like_installs() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = ConfigManager::new(Some(dir.path().join("config.json"))).unwrap();
+        let analyzer = RequirementsAnalyzer::new(&config);
+        let plan = serde_json::json!({"steps": [
+            {"type": "pip_install", "packages": ["numpy==1.26.4", "torch==2.3.0", "onnxruntime"]},
+            {"type": "requirements", "path": "requirements.txt"},
+        ]});
+        assert!(plan_needs_source(&plan));
+        let batches = batches_for_server_plan(&analyzer, &plan, None).unwrap();
+        let labels: Vec<_> = batches.iter().map(|b| b.label.as_str()).collect();
+        assert_eq!(labels, ["packages", "torch", "onnx"]);
+        assert_eq!(batches[0].args, ["numpy==1.26.4"]);
+        assert_eq!(batches[1].args[0], "--index-url");
+    }
+
+    #[test]
+    fn wheel_cache_is_used_once_it_has_files() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(find_links(dir.path()).is_none());
+        std::fs::create_dir_all(wheel_cache_dir(dir.path())).unwrap();
+        std::fs::write(wheel_cache_dir(dir.path()).join("numpy-1.26.4.whl"), b"").unwrap();
+        assert_eq!(find_links(dir.path()), Some(wheel_cache_dir(dir.path())));
+    }
+}
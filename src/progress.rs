@@ -0,0 +1,252 @@
+//! Progress output for downloads, extraction and long-running tools
+//!
+//! On a terminal progress is drawn with indicatif bars. When stdout is piped (CI logs,
+//! GUI wrappers) bars would garble the output, so plain `[Progress]` lines with percent
+//! and speed are printed every `--progress-interval` seconds instead; `--plain` asks for
+//! the same lines on a terminal, for screen readers that announce every spinner frame.
+//! `--quiet` drops progress entirely; errors and warnings still go through the logger.
+
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use std::io::IsTerminal;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressMode {
+    /// Interactive terminal: indicatif bars
+    Bars,
+    /// Non-TTY stdout or `--plain`: periodic plain-text lines
+    Plain,
+    /// `--quiet`: no progress at all
+    Quiet,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    mode: ProgressMode,
+    interval: Duration,
+}
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+/// Pick the progress mode once at startup; later calls are ignored
+pub fn init(quiet: bool, plain: bool, interval: Option<Duration>) {
+    let mode = if quiet {
+        ProgressMode::Quiet
+    } else if std::io::stdout().is_terminal() && !plain {
+        ProgressMode::Bars
+    } else {
+        ProgressMode::Plain
+    };
+    let _ = SETTINGS.set(Settings { mode, interval: interval.unwrap_or(DEFAULT_INTERVAL) });
+}
+
+fn settings() -> Settings {
+    *SETTINGS.get_or_init(|| {
+        let mode = if std::io::stdout().is_terminal() { ProgressMode::Bars } else { ProgressMode::Plain };
+        Settings { mode, interval: DEFAULT_INTERVAL }
+    })
+}
+
+pub fn mode() -> ProgressMode {
+    settings().mode
+}
+
+/// All bars go through one MultiProgress so download and extract bars can be drawn at once
+fn multi() -> &'static MultiProgress {
+    static MULTI: OnceLock<MultiProgress> = OnceLock::new();
+    MULTI.get_or_init(MultiProgress::new)
+}
+
+/// Status line printed alongside progress: does not tear active bars, dropped with --quiet
+pub fn println(msg: &str) {
+    match mode() {
+        ProgressMode::Bars => multi().suspend(|| println!("{}", msg)),
+        ProgressMode::Plain => println!("{}", msg),
+        ProgressMode::Quiet => {}
+    }
+}
+
+/// Line shown even with --quiet (messages from `output`), without tearing active bars
+pub fn print_line(msg: &str) {
+    // May run before init(): do not fix the mode here
+    match SETTINGS.get().map(|s| s.mode) {
+        Some(ProgressMode::Bars) => multi().suspend(|| println!("{}", msg)),
+        _ => println!("{}", msg),
+    }
+}
+
+/// [`print_line`] on stderr
+pub fn eprint_line(msg: &str) {
+    match SETTINGS.get().map(|s| s.mode) {
+        Some(ProgressMode::Bars) => multi().suspend(|| eprintln!("{}", msg)),
+        _ => eprintln!("{}", msg),
+    }
+}
+
+struct PlainState {
+    prefix: String,
+    total: Option<u64>,
+    position: u64,
+    message: String,
+    last_print: Option<Instant>,
+    interval: Duration,
+}
+
+impl PlainState {
+    fn line(&self) -> String {
+        let mut line = format!("[Progress] {}", self.prefix);
+        if let Some(total) = self.total.filter(|t| *t > 0) {
+            line.push_str(&format!(": {}%", (self.position.min(total) * 100) / total));
+        }
+        if !self.message.is_empty() {
+            line.push_str(&format!(" {}", self.message));
+        }
+        line
+    }
+
+    fn maybe_print(&mut self) {
+        if self.last_print.is_none_or(|t| t.elapsed() >= self.interval) {
+            println!("{}", self.line());
+            self.last_print = Some(Instant::now());
+        }
+    }
+}
+
+enum Inner {
+    Bar(ProgressBar),
+    Plain(Mutex<PlainState>),
+    Hidden,
+}
+
+/// Progress indicator that renders according to the current [`ProgressMode`]
+pub struct Progress {
+    inner: Inner,
+}
+
+impl Progress {
+    fn new(prefix: &str, total: Option<u64>, bar: impl FnOnce() -> ProgressBar) -> Self {
+        let settings = settings();
+        let inner = match settings.mode {
+            ProgressMode::Bars => {
+                let pb = multi().add(bar());
+                pb.set_prefix(prefix.to_string());
+                Inner::Bar(pb)
+            }
+            ProgressMode::Plain => Inner::Plain(Mutex::new(PlainState {
+                prefix: prefix.to_string(),
+                total,
+                position: 0,
+                message: String::new(),
+                last_print: None,
+                interval: settings.interval,
+            })),
+            ProgressMode::Quiet => Inner::Hidden,
+        };
+        Self { inner }
+    }
+
+    /// Byte progress; a spinner when the size is unknown
+    pub fn download(total: Option<u64>, prefix: &str) -> Self {
+        match total {
+            Some(total) if total > 0 => Self::new(prefix, Some(total), || {
+                let pb = ProgressBar::new(total);
+                let style = ProgressStyle::with_template("{prefix:.bold} [{bar:40.cyan/blue}] {percent:>3}% {msg} ETA {eta}")
+                    .unwrap()
+                    .progress_chars("=>-");
+                pb.set_style(style);
+                pb
+            }),
+            _ => Self::spinner(prefix),
+        }
+    }
+
+    /// Percent progress (0..=100) of extraction
+    pub fn extract(prefix: &str) -> Self {
+        Self::new(prefix, Some(100), || {
+            let pb = ProgressBar::new(100);
+            let style = ProgressStyle::with_template("{prefix:.bold} [{bar:40.magenta/blue}] {pos:>3}% ETA {eta}")
+                .unwrap()
+                .progress_chars("=>-");
+            pb.set_style(style);
+            pb
+        })
+    }
+
+    /// Activity without a known end; the message carries the status
+    pub fn spinner(prefix: &str) -> Self {
+        Self::new(prefix, None, || {
+            let pb = ProgressBar::new_spinner();
+            pb.set_style(ProgressStyle::with_template("{prefix:.bold} {spinner} {msg}").unwrap());
+            pb.enable_steady_tick(Duration::from_millis(120));
+            pb
+        })
+    }
+
+    pub fn set_position(&self, position: u64) {
+        match &self.inner {
+            Inner::Bar(pb) => pb.set_position(position),
+            Inner::Plain(state) => {
+                let mut state = state.lock().unwrap();
+                state.position = position;
+                state.maybe_print();
+            }
+            Inner::Hidden => {}
+        }
+    }
+
+    pub fn set_message(&self, msg: impl Into<String>) {
+        match &self.inner {
+            Inner::Bar(pb) => pb.set_message(msg.into()),
+            Inner::Plain(state) => {
+                let mut state = state.lock().unwrap();
+                state.message = msg.into();
+                state.maybe_print();
+            }
+            Inner::Hidden => {}
+        }
+    }
+
+    pub fn finish_with_message(self, msg: &str) {
+        match self.inner {
+            Inner::Bar(pb) => pb.finish_with_message(msg.to_string()),
+            Inner::Plain(state) => {
+                let prefix = &state.lock().unwrap().prefix;
+                println!("[Progress] {}: {}", prefix, msg);
+            }
+            Inner::Hidden => {}
+        }
+    }
+
+    /// Stop drawing and leave the last state on screen (failed/interrupted work)
+    pub fn abandon(self) {
+        match self.inner {
+            Inner::Bar(pb) => pb.abandon(),
+            Inner::Plain(state) => println!("{} (stopped)", state.lock().unwrap().line()),
+            Inner::Hidden => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_line_shows_percent_and_message() {
+        let mut state = PlainState {
+            prefix: "Downloading python.tar.zst".to_string(),
+            total: Some(200),
+            position: 50,
+            message: "1.00 MB/2.00 MB @ 0.50 MB/s".to_string(),
+            last_print: None,
+            interval: DEFAULT_INTERVAL,
+        };
+        assert_eq!(state.line(), "[Progress] Downloading python.tar.zst: 25% 1.00 MB/2.00 MB @ 0.50 MB/s");
+        state.total = None;
+        state.message.clear();
+        assert_eq!(state.line(), "[Progress] Downloading python.tar.zst");
+    }
+}
@@ -0,0 +1,112 @@
+//! Questions asked on the terminal
+//!
+//! Yes/no questions take `y`/`yes` or the number of the answer. With `--plain` the answers
+//! are also listed as numbered lines, so a screen reader announces every choice instead of
+//! an inline `[y/N]` hint it may read as noise. Destructive questions want the word itself
+//! typed out, never a number. Questions with more answers take the answer, its first letter
+//! or its number.
+
+use std::io::{self, Write};
+use std::sync::OnceLock;
+
+static PLAIN: OnceLock<bool> = OnceLock::new();
+
+/// Decide once at startup whether prompts list numbered answers; later calls are ignored
+pub fn init(plain: bool) {
+    let _ = PLAIN.set(plain);
+}
+
+pub fn plain() -> bool {
+    *PLAIN.get().unwrap_or(&false)
+}
+
+/// One trimmed line from stdin (empty at end of input)
+pub fn read_answer() -> String {
+    io::stdout().flush().ok();
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).ok();
+    input.trim().to_string()
+}
+
+/// Whether `answer` means yes; `word` is the answer required in place of `y`/`yes`/`1`
+fn is_yes(answer: &str, word: Option<&str>) -> bool {
+    let answer = answer.to_lowercase();
+    match word {
+        Some(word) => answer == word,
+        None => matches!(answer.as_str(), "y" | "yes" | "1"),
+    }
+}
+
+/// Yes/no question, no by default
+pub fn confirm(question: &str) -> bool {
+    if plain() {
+        println!("{}", question);
+        println!("  1) yes");
+        println!("  2) no");
+        print!("Enter 1 or 2 (default 2): ");
+    } else {
+        print!("{} [y/N]: ", question);
+    }
+    is_yes(&read_answer(), None)
+}
+
+/// Destructive yes/no question: only the word itself, typed out, means yes
+pub fn confirm_typed(question: &str, word: &str) -> bool {
+    if plain() {
+        println!("{}", question);
+        print!("Type {} to continue, anything else cancels: ", word);
+    } else {
+        print!("{} ({}/no): ", question, word);
+    }
+    is_yes(&read_answer(), Some(word))
+}
+
+/// Index of the answer `input` names by number, word or first letter
+fn parse_choice(input: &str, answers: &[&str]) -> Option<usize> {
+    let input = input.to_lowercase();
+    if let Ok(n) = input.parse::<usize>() {
+        return (1..=answers.len()).contains(&n).then(|| n - 1);
+    }
+    answers
+        .iter()
+        .position(|a| *a == input)
+        .or_else(|| answers.iter().position(|a| input.len() == 1 && a.starts_with(input.as_str())))
+}
+
+/// Question with several answers; returns the index of the one chosen, `default` for an
+/// empty or unknown answer
+pub fn choose(question: &str, answers: &[&str], default: usize) -> usize {
+    if plain() {
+        println!("{}", question);
+        for (i, answer) in answers.iter().enumerate() {
+            println!("  {}) {}", i + 1, answer);
+        }
+        print!("Enter 1-{} (default {}): ", answers.len(), default + 1);
+    } else {
+        print!("{} ({}, default {}): ", question, answers.join("/"), answers[default]);
+    }
+    parse_choice(&read_answer(), answers).unwrap_or(default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbered_and_word_answers() {
+        assert!(is_yes("Y", None) && is_yes("yes", None) && is_yes("1", None));
+        assert!(!is_yes("", None) && !is_yes("2", None) && !is_yes("no", None));
+        assert!(is_yes("YES", Some("yes")));
+        assert!(!is_yes("y", Some("yes")) && !is_yes("1", Some("yes")));
+    }
+
+    #[test]
+    fn choices_by_number_word_or_letter() {
+        let answers = ["retry", "skip", "abort"];
+        assert_eq!(parse_choice("2", &answers), Some(1));
+        assert_eq!(parse_choice("Abort", &answers), Some(2));
+        assert_eq!(parse_choice("r", &answers), Some(0));
+        assert_eq!(parse_choice("4", &answers), None);
+        assert_eq!(parse_choice("", &answers), None);
+    }
+}
@@ -0,0 +1,100 @@
+//! Read-only mode for shared lab installs
+//!
+//! An administrator marks a shared install read-only with `config read-only` (a
+//! `.portablesource_readonly` marker in the install root, optionally holding a message for
+//! users); `--read-only` or `PORTABLESOURCE_READ_ONLY=1` turn the mode on for one invocation.
+//! Commands that would change the install then refuse, while launching and inspecting
+//! repositories keeps working. What run-repo writes itself (operation timings, temp files of
+//! the launched repository) goes to a per-user directory instead of the install.
+
+use crate::{PortableSourceError, Result};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const READ_ONLY_MARKER: &str = ".portablesource_readonly";
+pub const READ_ONLY_ENV: &str = "PORTABLESOURCE_READ_ONLY";
+
+pub fn marker_path(install_path: &Path) -> PathBuf {
+    install_path.join(READ_ONLY_MARKER)
+}
+
+/// Read-only through the marker, the environment variable or `--read-only`
+pub fn is_active(install_path: &Path, forced: bool) -> bool {
+    let from_env = std::env::var(READ_ONLY_ENV).is_ok_and(|v| matches!(v.trim(), "1" | "true" | "yes"));
+    forced || from_env || marker_path(install_path).exists()
+}
+
+/// Administrator's note kept in the marker, if any
+pub fn message(install_path: &Path) -> Option<String> {
+    let text = fs::read_to_string(marker_path(install_path)).ok()?;
+    let text = text.trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+pub fn enable(install_path: &Path, message: Option<&str>) -> Result<()> {
+    crate::atomic_write::write(marker_path(install_path), message.unwrap_or_default())
+}
+
+/// Returns false when the install was not read-only
+pub fn disable(install_path: &Path) -> Result<bool> {
+    let marker = marker_path(install_path);
+    if !marker.exists() {
+        return Ok(false);
+    }
+    fs::remove_file(marker)?;
+    Ok(true)
+}
+
+/// Error for a command that would change a read-only install
+pub fn refused(install_path: &Path) -> PortableSourceError {
+    let mut text = format!(
+        "The installation at {} is read-only; this command would change it. Launching (run-repo) and inspecting repositories still work",
+        install_path.display()
+    );
+    if let Some(note) = message(install_path) {
+        text.push_str(&format!(". Administrator's note: {}", note));
+    }
+    PortableSourceError::installation(text)
+}
+
+/// Writable folder of the current user for one install, e.g.
+/// `~/.local/share/portablesource/<install hash>`
+pub fn user_dir(install_path: &Path) -> PathBuf {
+    let hash = Sha256::digest(install_path.to_string_lossy().as_bytes());
+    let key: String = hash.iter().take(6).map(|b| format!("{:02x}", b)).collect();
+    dirs::data_local_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("portablesource")
+        .join(key)
+}
+
+/// Where files the run subsystem writes (logs, timings) go
+pub fn writable_root(install_path: &Path, read_only: bool) -> PathBuf {
+    if read_only {
+        user_dir(install_path)
+    } else {
+        install_path.to_path_buf()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn marker_turns_read_only_on_and_keeps_the_note() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(!is_active(dir.path(), false) || std::env::var_os(READ_ONLY_ENV).is_some());
+        assert!(is_active(dir.path(), true));
+
+        enable(dir.path(), Some("Ask lab staff to install new tools")).unwrap();
+        assert!(is_active(dir.path(), false));
+        assert!(refused(dir.path()).to_string().ends_with("Administrator's note: Ask lab staff to install new tools"));
+
+        assert_ne!(writable_root(dir.path(), true), dir.path());
+        assert_eq!(writable_root(dir.path(), false), dir.path());
+        assert!(disable(dir.path()).unwrap());
+        assert!(!disable(dir.path()).unwrap());
+    }
+}
@@ -0,0 +1,178 @@
+//! Cached listing of installed repositories
+//!
+//! Listing used to read `link.txt` of every folder on every call. The index keeps the
+//! source label per folder in `<install_path>/repos_index.json` and only re-reads
+//! folders whose mtime changed (or all of `repos/` when its own mtime changed).
+//! `rebuild-index` recreates it after manual changes that mtimes do not reveal.
+
+use crate::repo_metadata::RepoMetadata;
+use crate::Result;
+use tracing::debug;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+pub const INDEX_FILE: &str = "repos_index.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RepoSource {
+    Github,
+    Git,
+    Server,
+}
+
+impl RepoSource {
+    pub fn label(&self) -> &'static str {
+        match self {
+            RepoSource::Github => " [From github]",
+            RepoSource::Git => " [From git]",
+            RepoSource::Server => " [From server]",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RepoIndexEntry {
+    /// Folder mtime (ns since epoch) when the entry was read
+    pub mtime: u64,
+    pub source: RepoSource,
+    /// Upstream name of a `--as` instance
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream: Option<String>,
+}
+
+impl RepoIndexEntry {
+    fn read(repo_dir: &Path, mtime: u64) -> Self {
+        let source = match fs::read_to_string(repo_dir.join("link.txt")) {
+            Ok(link) if link.to_lowercase().contains("github.com") => RepoSource::Github,
+            Ok(_) => RepoSource::Git,
+            Err(_) => RepoSource::Server,
+        };
+        let upstream = RepoMetadata::load(repo_dir).ok().flatten().and_then(|m| m.upstream);
+        Self { mtime, source, upstream }
+    }
+
+    /// Source suffix plus " (instance of X)" for instances
+    pub fn label(&self) -> String {
+        match &self.upstream {
+            Some(upstream) => format!("{} (instance of {})", self.source.label(), upstream),
+            None => self.source.label().to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RepoIndex {
+    /// mtime of `repos/` itself; folders were added or removed when it changes
+    pub repos_mtime: Option<u64>,
+    pub repos: BTreeMap<String, RepoIndexEntry>,
+}
+
+fn mtime_nanos(path: &Path) -> Option<u64> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_nanos() as u64)
+}
+
+impl RepoIndex {
+    pub fn path(install_path: &Path) -> PathBuf {
+        install_path.join(INDEX_FILE)
+    }
+
+    fn load(install_path: &Path) -> Self {
+        fs::read_to_string(Self::path(install_path))
+            .ok()
+            .and_then(|c| serde_json::from_str(&c).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, install_path: &Path) -> Result<()> {
+        crate::atomic_write::write(Self::path(install_path), serde_json::to_string_pretty(self)?)
+    }
+
+    /// Load the index and re-read whatever changed since it was written
+    pub fn load_or_refresh(install_path: &Path) -> Result<Self> {
+        let mut index = Self::load(install_path);
+        if index.refresh(install_path)? {
+            // A read-only install dir should not break listing
+            if let Err(e) = index.save(install_path) {
+                debug!("Could not save repository index: {}", e);
+            }
+        }
+        Ok(index)
+    }
+
+    /// Drop the cache and read every folder again
+    pub fn rebuild(install_path: &Path) -> Result<Self> {
+        let mut index = Self::default();
+        index.refresh(install_path)?;
+        index.save(install_path)?;
+        Ok(index)
+    }
+
+    /// Bring entries up to date with `repos/`; true if anything changed
+    fn refresh(&mut self, install_path: &Path) -> Result<bool> {
+        let repos_dir = install_path.join("repos");
+        let repos_mtime = mtime_nanos(&repos_dir);
+        if repos_mtime.is_none() {
+            let changed = !self.repos.is_empty() || self.repos_mtime.is_some();
+            *self = Self::default();
+            return Ok(changed);
+        }
+        if repos_mtime == self.repos_mtime && self.folders_unchanged(&repos_dir) {
+            return Ok(false);
+        }
+
+        let mut repos = BTreeMap::new();
+        for entry in fs::read_dir(&repos_dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            let Some(name) = entry.file_name().to_str().map(|s| s.to_string()) else { continue };
+            let mtime = mtime_nanos(&entry.path()).unwrap_or(0);
+            let cached = self.repos.get(&name).filter(|e| e.mtime == mtime).cloned();
+            repos.insert(name, cached.unwrap_or_else(|| RepoIndexEntry::read(&entry.path(), mtime)));
+        }
+        let changed = repos != self.repos || repos_mtime != self.repos_mtime;
+        self.repos = repos;
+        self.repos_mtime = repos_mtime;
+        Ok(changed)
+    }
+
+    /// Folder mtimes still match (one stat per repo, no file reads)
+    fn folders_unchanged(&self, repos_dir: &Path) -> bool {
+        self.repos.iter().all(|(name, e)| mtime_nanos(&repos_dir.join(name)) == Some(e.mtime))
+    }
+
+    /// (folder name, "name [From ...]") sorted by name
+    pub fn labeled(&self) -> Vec<(String, String)> {
+        self.repos.iter().map(|(name, e)| (name.clone(), format!("{}{}", name, e.label()))).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_picks_up_added_and_removed_folders() {
+        let dir = tempfile::tempdir().unwrap();
+        let repos = dir.path().join("repos");
+        fs::create_dir_all(repos.join("alpha")).unwrap();
+        fs::write(repos.join("alpha").join("link.txt"), "https://github.com/acme/alpha").unwrap();
+
+        let index = RepoIndex::load_or_refresh(dir.path()).unwrap();
+        assert_eq!(index.labeled(), [("alpha".to_string(), "alpha [From github]".to_string())]);
+        assert!(RepoIndex::path(dir.path()).exists());
+
+        fs::create_dir_all(repos.join("beta")).unwrap();
+        fs::remove_dir_all(repos.join("alpha")).unwrap();
+        let index = RepoIndex::load_or_refresh(dir.path()).unwrap();
+        assert_eq!(index.labeled(), [("beta".to_string(), "beta [From server]".to_string())]);
+        assert_eq!(RepoIndex::rebuild(dir.path()).unwrap().repos, index.repos);
+    }
+}
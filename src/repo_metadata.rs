@@ -0,0 +1,300 @@
+//! Per-repository metadata store
+//!
+//! Metadata lives next to the other repo markers (`.portablesource_url`, `link.txt`)
+//! as `repos/<name>/.portablesource_meta.json`.
+
+use crate::{PortableSourceError, Result};
+use crate::installer::SkippedStep;
+use crate::run_stats::RunRecord;
+use crate::utils::unix_timestamp;
+use tracing::debug;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub const METADATA_FILE: &str = ".portablesource_meta.json";
+
+/// SPDX ids that do not require a confirmation before install
+const PERMISSIVE_LICENSES: &[&str] = &[
+    "MIT", "MIT-0", "Apache-2.0", "BSD-2-Clause", "BSD-3-Clause", "ISC", "Unlicense",
+    "0BSD", "Zlib", "CC0-1.0", "BSL-1.0", "Python-2.0",
+];
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct LicenseInfo {
+    /// SPDX identifier, e.g. "MIT" or "AGPL-3.0"
+    pub spdx_id: Option<String>,
+    pub name: Option<String>,
+    pub url: Option<String>,
+}
+
+impl LicenseInfo {
+    pub fn from_spdx(spdx_id: &str) -> Self {
+        Self {
+            spdx_id: Some(spdx_id.to_string()),
+            name: None,
+            url: None,
+        }
+    }
+
+    /// Known permissive license; unknown/missing licenses are not permissive
+    pub fn is_permissive(&self) -> bool {
+        self.spdx_id
+            .as_deref()
+            .map(|id| PERMISSIVE_LICENSES.iter().any(|p| p.eq_ignore_ascii_case(id)))
+            .unwrap_or(false)
+    }
+
+    pub fn display_name(&self) -> String {
+        match (&self.spdx_id, &self.name) {
+            (Some(id), Some(name)) if id != name => format!("{} ({})", id, name),
+            (Some(id), _) => id.clone(),
+            (None, Some(name)) => name.clone(),
+            (None, None) => "unknown".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct Provenance {
+    /// Where the metadata came from: "github", "server" or "git"
+    pub source: String,
+    pub url: Option<String>,
+    pub owner: Option<String>,
+    pub default_branch: Option<String>,
+    pub description: Option<String>,
+    pub archived: bool,
+    /// Unix time when provenance was fetched
+    pub fetched_at: u64,
+}
+
+/// Compute backend a repository was switched to with `switch-backend`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Cpu,
+    Cuda,
+}
+
+impl Backend {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Backend::Cpu => "cpu",
+            Backend::Cuda => "cuda",
+        }
+    }
+}
+
+impl std::fmt::Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for Backend {
+    type Err = PortableSourceError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "cpu" => Ok(Backend::Cpu),
+            "cuda" | "gpu" => Ok(Backend::Cuda),
+            other => Err(PortableSourceError::config(format!("Unknown backend '{}' (expected cpu or cuda)", other))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RepoMetadata {
+    pub name: String,
+    pub license: Option<LicenseInfo>,
+    pub provenance: Option<Provenance>,
+    /// License was non-permissive and the user explicitly accepted it
+    pub license_accepted: bool,
+    /// Entry point confirmed by the user when detection was ambiguous
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entry_point: Option<String>,
+    /// Upstream repository name when installed under a custom instance name (`--as`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream: Option<String>,
+    /// Branch checked out instead of the remote default (`--branch`); updates follow it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    /// Installed repository whose clone this checkout is a git worktree of (`--worktree-of`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub worktree_of: Option<String>,
+    /// Launch statistics, oldest first (see `run_stats`)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub runs: Vec<RunRecord>,
+    /// Install steps skipped after failing in the last install (`--on-error`)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub skipped_steps: Vec<SkippedStep>,
+    /// Backend chosen with `switch-backend`; None follows the detected GPU
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backend: Option<Backend>,
+}
+
+impl RepoMetadata {
+    pub fn path(repo_path: &Path) -> PathBuf {
+        repo_path.join(METADATA_FILE)
+    }
+
+    pub fn load(repo_path: &Path) -> Result<Option<Self>> {
+        let path = Self::path(repo_path);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = std::fs::read_to_string(&path)?;
+        Ok(Some(serde_json::from_str(&content)?))
+    }
+
+    pub fn save(&self, repo_path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        crate::atomic_write::write(Self::path(repo_path), json)
+    }
+
+    /// Remember the steps an install skipped, replacing those of earlier installs
+    pub fn record_skipped_steps(&mut self, repo_path: &Path, skipped: Vec<SkippedStep>) -> Result<()> {
+        if skipped == self.skipped_steps {
+            return Ok(());
+        }
+        self.skipped_steps = skipped;
+        self.save(repo_path)
+    }
+}
+
+/// Name used for server lookups: the recorded upstream of an instance, else the folder name
+pub fn upstream_name(repo_path: &Path) -> String {
+    RepoMetadata::load(repo_path)
+        .ok()
+        .flatten()
+        .and_then(|m| m.upstream)
+        .or_else(|| repo_path.file_name().map(|s| s.to_string_lossy().to_string()))
+        .unwrap_or_default()
+        .to_lowercase()
+}
+
+/// Installed repositories that are git worktrees of `base`, sorted by name
+pub fn worktrees_of(install_path: &Path, base: &str) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(install_path.join("repos"))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|e| {
+            RepoMetadata::load(&e.path())
+                .ok()
+                .flatten()
+                .and_then(|m| m.worktree_of)
+                .is_some_and(|of| of.eq_ignore_ascii_case(base))
+        })
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .collect();
+    names.sort();
+    names
+}
+
+/// Instance names become folder and venv names, so they must be a single plain path component
+pub fn validate_instance_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name != "."
+        && name != ".."
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid {
+        return Err(PortableSourceError::repository(format!(
+            "Invalid instance name '{}': use letters, digits, '-', '_' or '.'", name
+        )));
+    }
+    Ok(())
+}
+
+/// Extract (owner, repo) from a github.com URL
+pub fn parse_github_url(url: &str) -> Option<(String, String)> {
+    let rest = url
+        .trim()
+        .trim_end_matches('/')
+        .strip_prefix("https://github.com/")
+        .or_else(|| url.trim().strip_prefix("http://github.com/"))
+        .or_else(|| url.trim().strip_prefix("git@github.com:"))?;
+    let mut parts = rest.split('/');
+    let owner = parts.next()?.to_string();
+    let repo = parts.next()?.trim_end_matches(".git").to_string();
+    if owner.is_empty() || repo.is_empty() {
+        return None;
+    }
+    Some((owner, repo))
+}
+
+/// Fetch license and provenance from the GitHub API (best-effort, None on any failure)
+pub fn fetch_github_metadata(url: &str) -> Option<(Option<LicenseInfo>, Provenance)> {
+    let (owner, repo) = parse_github_url(url)?;
+    let api_url = format!("https://api.github.com/repos/{}/{}", owner, repo);
+    let repo_url = url.to_string();
+
+    crate::system::block_on(async move {
+        let resp = crate::system::http_client()
+            .get(&api_url)
+            .header("User-Agent", format!("portablesource/{}", crate::config::VERSION))
+            .header("Accept", "application/vnd.github+json")
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await
+            .ok()?;
+        if !resp.status().is_success() {
+            debug!("GitHub API returned {} for {}", resp.status(), api_url);
+            return None;
+        }
+        let v: serde_json::Value = resp.json().await.ok()?;
+        let str_field = |val: &serde_json::Value, key: &str| val.get(key).and_then(|s| s.as_str()).map(|s| s.to_string());
+
+        let license = v.get("license").filter(|l| !l.is_null()).map(|l| LicenseInfo {
+            spdx_id: str_field(l, "spdx_id").filter(|id| id != "NOASSERTION"),
+            name: str_field(l, "name"),
+            url: str_field(&v, "html_url").map(|u| format!("{}/blob/HEAD/LICENSE", u)),
+        });
+        let provenance = Provenance {
+            source: "github".to_string(),
+            url: Some(repo_url),
+            owner: v.get("owner").and_then(|o| str_field(o, "login")),
+            default_branch: str_field(&v, "default_branch"),
+            description: str_field(&v, "description"),
+            archived: v.get("archived").and_then(|a| a.as_bool()).unwrap_or(false),
+            fetched_at: unix_timestamp(),
+        };
+        Some((license, provenance))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn worktrees_are_found_by_their_base() {
+        let dir = tempfile::tempdir().unwrap();
+        let repos = dir.path().join("repos");
+        for (name, worktree_of) in [("comfyui", None), ("comfyui-dev", Some("comfyui")), ("comfyui-next", Some("ComfyUI")), ("other", Some("forge"))] {
+            let path = repos.join(name);
+            std::fs::create_dir_all(&path).unwrap();
+            let metadata = RepoMetadata {
+                name: name.to_string(),
+                worktree_of: worktree_of.map(str::to_string),
+                branch: worktree_of.map(|_| "dev".to_string()),
+                ..Default::default()
+            };
+            metadata.save(&path).unwrap();
+        }
+
+        assert_eq!(worktrees_of(dir.path(), "comfyui"), ["comfyui-dev", "comfyui-next"]);
+        assert!(worktrees_of(dir.path(), "comfyui-dev").is_empty());
+        let saved = RepoMetadata::load(&repos.join("comfyui-dev")).unwrap().unwrap();
+        assert_eq!(saved.branch.as_deref(), Some("dev"));
+        assert_eq!(saved.backend, None);
+
+        let switched = RepoMetadata { backend: Some("CPU".parse().unwrap()), ..saved };
+        switched.save(&repos.join("comfyui-dev")).unwrap();
+        let json = std::fs::read_to_string(RepoMetadata::path(&repos.join("comfyui-dev"))).unwrap();
+        assert!(json.contains("\"backend\": \"cpu\""), "{}", json);
+        assert!("rocm".parse::<Backend>().is_err());
+    }
+}
@@ -0,0 +1,65 @@
+//! Files the installer keeps about a repository, outside its checkout
+//!
+//! The install engine override, the numpy constraints, the engine log, the hash of the
+//! generated start script and the poetry/pdm export with its extras live in
+//! `<install>/state/<name>/`, so `git status`, `git clean -fdx` and the repository's own
+//! tooling never see them. Files that earlier releases left in `repos/<name>` are still read
+//! until the next write moves them over.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const STATE_DIR: &str = "state";
+
+/// State folder of the repository checked out at `<install>/repos/<name>`
+pub fn dir(repo_path: &Path) -> PathBuf {
+    let name = repo_path.file_name().unwrap_or_default();
+    let install_path = repo_path.parent().and_then(Path::parent).unwrap_or(Path::new("."));
+    install_path.join(STATE_DIR).join(name)
+}
+
+/// Where `file` of the repository is written
+pub fn path(repo_path: &Path, file: &str) -> PathBuf {
+    dir(repo_path).join(file)
+}
+
+/// Where `file` of the repository is read from: the state folder, else the checkout
+pub fn existing(repo_path: &Path, file: &str) -> PathBuf {
+    let current = path(repo_path, file);
+    let legacy = repo_path.join(file);
+    if !current.exists() && legacy.exists() { legacy } else { current }
+}
+
+pub fn read(repo_path: &Path, file: &str) -> Option<String> {
+    fs::read_to_string(existing(repo_path, file)).ok()
+}
+
+/// Write `file` into the state folder; a copy an earlier release left in the checkout goes
+pub fn write(repo_path: &Path, file: &str, content: impl AsRef<[u8]>) -> crate::Result<PathBuf> {
+    let path = path(repo_path, file);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    crate::atomic_write::write(&path, content)?;
+    let _ = fs::remove_file(repo_path.join(file));
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn state_lives_beside_repos_and_legacy_files_are_read_then_moved() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = dir.path().join("repos").join("comfyui");
+        fs::create_dir_all(&repo).unwrap();
+        assert_eq!(path(&repo, ".portablesource_engine"), dir.path().join("state/comfyui/.portablesource_engine"));
+
+        fs::write(repo.join(".portablesource_engine"), "pip").unwrap();
+        assert_eq!(read(&repo, ".portablesource_engine").as_deref(), Some("pip"));
+        write(&repo, ".portablesource_engine", "uv").unwrap();
+        assert!(!repo.join(".portablesource_engine").exists());
+        assert_eq!(read(&repo, ".portablesource_engine").as_deref(), Some("uv"));
+    }
+}
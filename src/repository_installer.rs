@@ -4,18 +4,36 @@
 //! using a modular architecture with specialized components for different tasks.
 
 use crate::{Result, PortableSourceError};
-use crate::config::{ConfigManager, SERVER_DOMAIN};
+use crate::error::MultiError;
+use crate::components;
+use crate::output;
+use crate::config::{ConfigManager, InstallEngine, SERVER_DOMAIN};
 use crate::envs_manager::PortableEnvironmentManager;
+use crate::history::{self, HistoryOperation};
+use crate::performance::PerformanceProfile;
+use crate::planned_actions::ActionPlan;
+use crate::plugins::{Hook, HookRepo, PluginHost};
+use crate::prefetch;
+use crate::repo_index::RepoIndex;
+use crate::repo_metadata::{self, Backend, LicenseInfo, Provenance, RepoMetadata};
+use crate::repo_state;
+use crate::resources;
+use crate::run_queue::RepoRunSettings;
+use crate::shared_models;
+use crate::shared_env::EnvTarget;
 use crate::installer::{
     CommandRunner, GitManager, PipManager, DependencyInstaller, 
-    ScriptGenerator, RepositoryInfo as GitRepositoryInfo,
-    ScriptRepositoryInfo, ServerClient, MainFileFinder
+    ScriptContext, ScriptGenerator, RepositoryInfo as GitRepositoryInfo, render_script,
+    ScriptRepositoryInfo, ServerClient, MainFileFinder, RequirementsAnalyzer, ENGINE_MARKER_FILE, OnStepError
 };
-use log::info;
+use crate::installer::lockfile;
+use crate::installer::script_generator::{self, RegenOutcome};
+use tracing::{info, warn};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::time::Instant;
 use url::Url;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -23,6 +41,8 @@ pub struct FallbackRepo {
     pub url: Option<String>,
     pub main_file: Option<String>,
     pub program_args: Option<String>,
+    #[serde(default)]
+    pub license: Option<String>,
 }
 
 /// Main repository installer using modular components
@@ -33,6 +53,20 @@ pub struct RepositoryInstaller {
     server_client: ServerClient,
     main_file_finder: MainFileFinder,
     fallback_repositories: HashMap<String, FallbackRepo>,
+    engine_override: Option<InstallEngine>,
+    accept_license: bool,
+    instance_name: Option<String>,
+    branch: Option<String>,
+    worktree_of: Option<String>,
+    env_target: Option<EnvTarget>,
+    performance_profile: Option<PerformanceProfile>,
+    review_plan: bool,
+    clean_env: bool,
+    extras: Vec<String>,
+    prompts: bool,
+    on_error: OnStepError,
+    plugins: PluginHost,
+    installed_name: Option<String>,
 }
 
 impl RepositoryInstaller {
@@ -41,6 +75,7 @@ impl RepositoryInstaller {
         let server_client = ServerClient::new(format!("https://{}", SERVER_DOMAIN));
         let main_file_finder = MainFileFinder::new(server_client.clone());
         let fallback_repositories = default_fallback_repositories();
+        let plugins = PluginHost::load(&install_path, env_manager.get_python_executable());
         
         // Anchor config to install dir
         config_manager.get_config_mut().install_path = install_path.clone();
@@ -53,160 +88,512 @@ impl RepositoryInstaller {
             server_client,
             main_file_finder,
             fallback_repositories,
+            engine_override: None,
+            accept_license: false,
+            instance_name: None,
+            branch: None,
+            worktree_of: None,
+            env_target: None,
+            performance_profile: None,
+            review_plan: false,
+            clean_env: false,
+            extras: Vec::new(),
+            prompts: true,
+            on_error: OnStepError::Ask,
+            plugins,
+            installed_name: None,
         }
     }
     
+    /// Replace network/process/clock providers (mocks in tests)
+    pub fn with_services(mut self, services: crate::system::Services) -> Self {
+        self.env_manager = self.env_manager.with_services(services);
+        self
+    }
+
+    /// Skip the confirmation prompt for non-permissive licenses
+    pub fn with_license_acceptance(mut self, accept: bool) -> Self {
+        self.accept_license = accept;
+        self
+    }
+    
+    /// Ask on the terminal (license, ambiguous entry point); without prompts the answers
+    /// used for a non-interactive stdin apply
+    pub fn with_prompts(mut self, prompts: bool) -> Self {
+        self.prompts = prompts;
+        self
+    }
+    
+    /// What to do when an install step fails (`--on-error`); `ask` aborts when prompts are
+    /// off or stdin is not a terminal
+    pub fn with_on_error(mut self, policy: OnStepError) -> Self {
+        self.on_error = policy;
+        self
+    }
+    
+    /// Use the given install engine for repositories handled by this installer
+    /// and remember it in the repository folder for later updates
+    pub fn with_install_engine(mut self, engine: Option<InstallEngine>) -> Self {
+        self.engine_override = engine;
+        self
+    }
+    
+    /// Install under a custom folder/venv name instead of the upstream name,
+    /// so several instances of one repository can coexist
+    pub fn with_instance_name(mut self, name: Option<String>) -> Self {
+        self.instance_name = name;
+        self
+    }
+    
+    /// Check out this branch instead of the remote default; updates follow it
+    pub fn with_branch(mut self, branch: Option<String>) -> Self {
+        self.branch = branch;
+        self
+    }
+
+    /// Add the checkout as a git worktree of this installed repository instead of cloning
+    pub fn with_worktree_of(mut self, base: Option<String>) -> Self {
+        self.worktree_of = base;
+        self
+    }
+
+    /// Install packages into a shared environment instead of a venv (`--no-venv`)
+    pub fn with_env_target(mut self, target: Option<EnvTarget>) -> Self {
+        self.env_target = target;
+        self
+    }
+    
+    /// Performance profile baked into the start script of newly installed repositories
+    pub fn with_performance_profile(mut self, profile: Option<PerformanceProfile>) -> Self {
+        self.performance_profile = profile;
+        self
+    }
+    
+    /// Review server installation plans against the repository before running them
+    pub fn with_plan_review(mut self, review: bool) -> Self {
+        self.review_plan = review;
+        self
+    }
+
+    /// Install new repositories into an empty venv instead of a clone of the most similar one
+    pub fn with_clean_env(mut self, clean: bool) -> Self {
+        self.clean_env = clean;
+        self
+    }
+    
+    /// Extras of a poetry or pdm project installed with it and on later updates
+    pub fn with_extras(mut self, extras: Vec<String>) -> Self {
+        self.extras = extras;
+        self
+    }
+
     /// Install a repository from URL or name
+    #[tracing::instrument(name = "install_repo", skip_all, fields(repo = %repo_url_or_name))]
     pub async fn install_repository(&mut self, repo_url_or_name: &str) -> Result<()> {
         info!("Installing repository: {}", repo_url_or_name);
-        println!("[PortableSource] Installing repository: {}", repo_url_or_name);
+        output::step(&format!("Installing repository: {}", repo_url_or_name));
         
-        if self.is_repository_url(repo_url_or_name) {
+        let started = Instant::now();
+        let result = if self.is_repository_url(repo_url_or_name) {
             self.install_from_url(repo_url_or_name).await
         } else {
             self.install_from_name(repo_url_or_name).await
+        };
+        let name = self.installed_name.clone().unwrap_or_else(|| repo_url_or_name.to_string());
+        history::record(&self.install_path, HistoryOperation::Install, &name, started, &result);
+        result
+    }
+    
+    /// Download what installing a repository would pull into the shared wheel cache,
+    /// without creating its environment. Returns the number of `pip download` batches run.
+    #[tracing::instrument(name = "prefetch", skip_all, fields(repo = %repo_url_or_name))]
+    pub async fn prefetch_repository(&self, repo_url_or_name: &str) -> Result<usize> {
+        let python = self.env_manager.get_python_executable()
+            .ok_or_else(|| PortableSourceError::environment("Portable Python not found; run setup-env first"))?;
+        let (upstream, url) = if self.is_repository_url(repo_url_or_name) {
+            let url = Url::parse(repo_url_or_name)
+                .map_err(|e| PortableSourceError::repository(format!("Invalid repository URL: {}", e)))?;
+            (self.extract_repo_name_from_url(&url)?, Some(repo_url_or_name.to_string()))
+        } else {
+            let repo_info = self.get_repository_info(repo_url_or_name)?
+                .ok_or_else(|| PortableSourceError::repository_not_found(repo_url_or_name))?;
+            (self.normalize_repo_name(repo_url_or_name, &repo_info)?, repo_info.url)
+        };
+
+        let command_runner = CommandRunner::new(&self.env_manager);
+        let pip_manager = PipManager::new(&command_runner, &self.config_manager);
+        let analyzer = RequirementsAnalyzer::new(&self.config_manager);
+        let plan = self.server_client.get_installation_plan(&upstream)?;
+
+        // Installed repositories are read in place, others are cloned into cache/sources
+        let source = match &plan {
+            Some(plan) if !prefetch::plan_needs_source(plan) => None,
+            _ => {
+                let installed = self.install_path.join("repos").join(&upstream);
+                if installed.exists() {
+                    Some(installed)
+                } else {
+                    let url = url.ok_or_else(|| PortableSourceError::repository(format!("No URL known for '{}'", upstream)))?;
+                    let path = prefetch::source_cache_dir(&self.install_path).join(&upstream);
+                    output::step(&format!("Fetching source of {} to read its requirements...", upstream));
+                    GitManager::new(&command_runner, &self.env_manager).clone_or_update_repository_from_url(&url, &path).await?;
+                    Some(path)
+                }
+            }
+        };
+
+        let batches = match (&plan, &source) {
+            (Some(plan), source) => prefetch::batches_for_server_plan(&analyzer, plan, source.as_deref())?,
+            (None, Some(source)) => {
+                let pyproject = source.join("pyproject.toml");
+                let requirements = if pyproject.exists() {
+                    pip_manager.extract_dependencies_from_pyproject(&pyproject, source).ok()
+                } else {
+                    None
+                };
+                match requirements.or_else(|| pip_manager.find_requirements_files(source)) {
+                    Some(file) => prefetch::batches_for_packages(&analyzer, &prefetch::read_requirements(&analyzer, &file)?),
+                    None => Vec::new(),
+                }
+            }
+            (None, None) => Vec::new(),
+        };
+        if batches.is_empty() {
+            output::info(&format!("{} has no Python dependencies to prefetch", upstream));
+            return Ok(0);
         }
+
+        let cache = prefetch::wheel_cache_dir(&self.install_path);
+        fs::create_dir_all(&cache)?;
+        for batch in &batches {
+            let cmd = prefetch::download_command(&python, &cache, batch);
+            command_runner.run(&cmd, Some(&format!("Downloading {} for {}", batch.label, upstream)), None)?;
+        }
+        Ok(batches.len())
+    }
+
+    /// Folder name chosen by the last `install_repository` call
+    pub fn installed_name(&self) -> Option<&str> {
+        self.installed_name.as_deref()
     }
     
     /// Update an existing repository
+    #[tracing::instrument(name = "update_repo", skip_all, fields(repo = %repo_name))]
     pub async fn update_repository(&mut self, repo_name: &str) -> Result<()> {
+        let started = Instant::now();
+        let result = self.update_checkout_and_environment(repo_name).await;
+        history::record(&self.install_path, HistoryOperation::Update, repo_name, started, &result);
+        result
+    }
+
+    /// Update every installed repository, or with `dry_run` show what each update would
+    /// change; every repository is attempted and the failures are reported together
+    pub async fn update_all(&mut self, dry_run: bool) -> Result<()> {
+        let names = self.list_repository_names_raw()?;
+        if names.is_empty() {
+            println!("No repositories installed");
+            return Ok(());
+        }
+
+        let mut failed = MultiError::new("update", names.len());
+        for (i, name) in names.iter().enumerate() {
+            output::step(&format!("Updating {} ({}/{})", name, i + 1, names.len()));
+            let result = if dry_run {
+                self.plan_update(name).map(|plan| plan.print_dry_run())
+            } else {
+                self.update_repository(name).await
+            };
+            if let Err(e) = result {
+                output::error(&format!("Failed to update '{}': {}", name, e));
+                failed.push(name, &e);
+            }
+        }
+        failed.into_result()?;
+        if !dry_run {
+            output::success(&format!("{} repositories updated", names.len()));
+        }
+        Ok(())
+    }
+
+    async fn update_checkout_and_environment(&mut self, repo_name: &str) -> Result<()> {
         info!("Updating repository: {}", repo_name);
 
         let repo_path = self.install_path.join("repos").join(repo_name);
 
         if !repo_path.exists() {
-            return Err(PortableSourceError::repository(
-                format!("Repository '{}' not found", repo_name)
-            ));
+            return Err(PortableSourceError::repository_not_found(repo_name));
         }
 
         // Create modular components for this operation
         let command_runner = CommandRunner::new(&self.env_manager);
-        let git_manager = GitManager::new(&command_runner, &self.env_manager);
+        let metadata = RepoMetadata::load(&repo_path)?.unwrap_or_default();
+        let git_manager = GitManager::new(&command_runner, &self.env_manager).with_branch(metadata.branch);
 
+        // A worktree's links to its base are absolute on older git; fix them after a move
+        if let Some(base) = &metadata.worktree_of {
+            git_manager.repair_worktree(&self.install_path.join("repos").join(base), &repo_path)?;
+        }
         // Use GitManager for update operations
         git_manager.update_repository(&repo_path)?;
+        self.write_engine_marker(&repo_path)?;
+        self.write_performance_profile(&repo_path)?;
+        self.install_components(repo_name).await?;
+
+        self.rebuild_environment(repo_name).await
+    }
+
+    /// Download the components the repository declares that are not unpacked yet; they are
+    /// only used by Windows start scripts, elsewhere nothing is downloaded
+    pub async fn install_components(&self, repo_name: &str) -> Result<usize> {
+        let settings = RepoRunSettings::load(&self.install_path.join("repos").join(repo_name))?;
+        let cuda = self.config_manager.get_cuda_version();
+        let mut installed = 0;
+        for component in components::resolve(&settings.components)? {
+            if !cfg!(windows) || component.is_installed(&self.install_path, cuda.as_ref()) {
+                continue;
+            }
+            output::step(&format!("Installing component {}", component.name));
+            self.env_manager.install_component(component).await?;
+            installed += 1;
+        }
+        Ok(installed)
+    }
+
+    /// What `update_repository` changes, without changing it
+    pub fn plan_update(&self, repo_name: &str) -> Result<ActionPlan> {
+        let repo_path = self.install_path.join("repos").join(repo_name);
+        if !repo_path.exists() {
+            return Err(PortableSourceError::repository_not_found(repo_name));
+        }
+        let mut plan = ActionPlan::default();
+        match RepoMetadata::load(&repo_path)?.and_then(|m| m.branch) {
+            Some(branch) => plan.modify(repo_path.clone(), &format!("git fetch, hard reset to origin/{} and pull", branch)),
+            None => plan.modify(repo_path.clone(), "git fetch, hard reset to origin/main (or origin/master) and pull"),
+        }
+        if let Some(engine) = self.engine_override {
+            plan.modify(repo_state::path(&repo_path, ENGINE_MARKER_FILE), &format!("install engine set to {}", engine));
+        }
+        if let Some(profile) = self.performance_profile {
+            plan.modify(RepoRunSettings::path(&repo_path), &format!("performance profile set to {:?}", profile));
+        }
+        plan.delete(self.install_path.join("envs").join(repo_name), "environment, recreated from the requirements");
+        plan.notes.push("Local changes to tracked files in the repository are discarded by the hard reset".into());
+        Ok(plan)
+    }
+
+    /// Recreate the Python environment of an installed repository from its requirements
+    /// (server plan, lock file export or requirements files), without touching the checkout
+    pub async fn rebuild_environment(&mut self, repo_name: &str) -> Result<()> {
+        let repo_path = self.install_path.join("repos").join(repo_name);
+        if !repo_path.exists() {
+            return Err(PortableSourceError::repository_not_found(repo_name));
+        }
 
         // Create components for dependency installation
-        let pip_manager = PipManager::new(&command_runner, &self.config_manager);
+        let command_runner = CommandRunner::new(&self.env_manager);
+        let config_manager = self.repo_config(&repo_path)?;
+        let pip_manager = PipManager::new(&command_runner, &config_manager).with_on_error(self.step_failure_policy());
         let dependency_installer = DependencyInstaller::new(
             &pip_manager,
             &self.server_client,
             self.install_path.clone(),
-        );
+        )
+        .with_plan_review(self.review_plan);
 
         // Reinstall dependencies using DependencyInstaller
         dependency_installer.install_dependencies(&repo_path).await?;
+        let mut metadata = RepoMetadata::load(&repo_path)?.unwrap_or_else(|| RepoMetadata { name: repo_name.to_string(), ..Default::default() });
+        metadata.record_skipped_steps(&repo_path, pip_manager.skipped_steps())?;
+        // The environment was recreated: plugin packages go in again
+        self.run_plugin_hook(Hook::Install, repo_name, None, &repo_path, &pip_manager)?;
 
         Ok(())
     }
     
+    /// Config for work on an installed repository: CPU packages and a script without the
+    /// CUDA section once it was switched to the CPU backend
+    fn repo_config(&self, repo_path: &Path) -> Result<ConfigManager> {
+        let mut config_manager = self.config_manager.clone();
+        if RepoMetadata::load(repo_path)?.and_then(|m| m.backend) == Some(Backend::Cpu) {
+            config_manager.set_cpu_only();
+        }
+        Ok(config_manager)
+    }
+
+    /// Swap the torch and ONNX Runtime builds of an installed repository to `backend`, record
+    /// it and re-render the start script. Returns the swapped packages and the script outcome
+    pub fn switch_backend(&self, repo_name: &str, backend: Backend) -> Result<(Vec<String>, RegenOutcome)> {
+        let repo_path = self.install_path.join("repos").join(repo_name);
+        if !repo_path.exists() {
+            return Err(PortableSourceError::repository_not_found(repo_name));
+        }
+        if EnvTarget::load(&repo_path)?.is_some() {
+            return Err(PortableSourceError::environment(format!(
+                "'{}' uses a shared environment; switching it would change every repository that shares it",
+                repo_name
+            )));
+        }
+        let mut config_manager = self.config_manager.clone();
+        match backend {
+            Backend::Cpu => config_manager.set_cpu_only(),
+            Backend::Cuda if !config_manager.has_cuda() => {
+                return Err(PortableSourceError::gpu_detection("No NVIDIA GPU detected; the CUDA backend needs one"));
+            }
+            Backend::Cuda => {}
+        }
+
+        let command_runner = CommandRunner::new(&self.env_manager);
+        let pip_manager = PipManager::new(&command_runner, &config_manager);
+        let swapped = pip_manager.switch_backend_packages(repo_name, &repo_path)?;
+
+        let mut metadata = RepoMetadata::load(&repo_path)?.unwrap_or_else(|| RepoMetadata { name: repo_name.to_string(), ..Default::default() });
+        metadata.backend = Some(backend);
+        metadata.save(&repo_path)?;
+        let script = self.regenerate_startup_script(repo_name, false, false)?;
+        Ok((swapped, script))
+    }
+
+    /// Render start script for an installed repository.
+    /// With `dry_run` the script is only returned, otherwise it is written to the repo folder.
+    pub fn render_startup_script(&self, repo_name: &str, dry_run: bool) -> Result<String> {
+        let ctx = self.script_context(repo_name)?;
+        let script = render_script(&ctx);
+        if !dry_run {
+            script_generator::write_script_file(&ctx.repo_path, &ctx.script_file_name(), &script)?;
+        }
+        Ok(script)
+    }
+
+    /// Re-render the start script with the current templates, keeping scripts the user edited
+    /// (see [`script_generator::regenerate_script`])
+    pub fn regenerate_startup_script(&self, repo_name: &str, force: bool, dry_run: bool) -> Result<RegenOutcome> {
+        let ctx = self.script_context(repo_name)?;
+        script_generator::regenerate_script(&ctx.repo_path, &ctx.script_file_name(), &render_script(&ctx), force, dry_run)
+    }
+
+    /// Everything the start script of an installed repository is rendered from
+    pub fn script_context(&self, repo_name: &str) -> Result<ScriptContext> {
+        let repo_path = self.install_path.join("repos").join(repo_name);
+        if !repo_path.exists() {
+            return Err(PortableSourceError::repository_not_found(repo_name));
+        }
+
+        // Repos installed from URL keep their source in link.txt; others come from server/fallback list
+        let link_file = repo_path.join("link.txt");
+        let script_repo_info = if link_file.exists() {
+            ScriptRepositoryInfo {
+                url: Some(fs::read_to_string(&link_file)?.trim().to_string()),
+                main_file: None,
+                program_args: None,
+            }
+        } else {
+            let info = self.get_repository_info(&repo_metadata::upstream_name(&repo_path))?;
+            ScriptRepositoryInfo {
+                url: info.as_ref().and_then(|i| i.url.clone()),
+                main_file: info.as_ref().and_then(|i| i.main_file.clone()),
+                program_args: info.as_ref().and_then(|i| i.program_args.clone()),
+            }
+        };
+
+        let command_runner = CommandRunner::new(&self.env_manager);
+        let config_manager = self.repo_config(&repo_path)?;
+        let pip_manager = PipManager::new(&command_runner, &config_manager);
+        let script_generator = ScriptGenerator::new(
+            &pip_manager,
+            &config_manager,
+            &self.main_file_finder,
+            self.install_path.clone(),
+        );
+        script_generator.build_context(&repo_path, &script_repo_info)
+    }
+    
     /// Delete a repository
     pub fn delete_repository(&self, repo_name: &str) -> Result<()> {
+        let started = Instant::now();
+        let result = self.delete_checkout_and_environment(repo_name);
+        history::record(&self.install_path, HistoryOperation::Delete, repo_name, started, &result);
+        result
+    }
+
+    fn delete_checkout_and_environment(&self, repo_name: &str) -> Result<()> {
         info!("Deleting repository: {}", repo_name);
+        let repo_path = self.install_path.join("repos").join(repo_name);
+        let worktree_of = RepoMetadata::load(&repo_path).ok().flatten().and_then(|m| m.worktree_of);
+
+        // The plan unlinks shared model folders first, so shared files are never deleted through them
+        self.plan_delete(repo_name)?
+            .execute()
+            .map_err(|e| PortableSourceError::repository(format!("Failed to delete repository '{}': {}", repo_name, e)))?;
+
+        // The base clone still lists the deleted worktree until it is pruned
+        if let Some(base) = worktree_of {
+            let base_path = self.install_path.join("repos").join(&base);
+            if base_path.exists() {
+                let command_runner = CommandRunner::new(&self.env_manager);
+                if let Err(e) = GitManager::new(&command_runner, &self.env_manager).prune_worktrees(&base_path) {
+                    warn!("Failed to prune worktrees of '{}': {}", base, e);
+                }
+            }
+        }
         
+        info!("Repository '{}' deleted successfully", repo_name);
+        Ok(())
+    }
+    
+    /// What `delete_repository` removes, without removing it
+    pub fn plan_delete(&self, repo_name: &str) -> Result<ActionPlan> {
         let repo_path = self.install_path.join("repos").join(repo_name);
         let env_path = self.install_path.join("envs").join(repo_name);
-        
         if !repo_path.exists() && !env_path.exists() {
-            return Err(PortableSourceError::repository(
-                format!("Repository '{}' not found", repo_name)
-            ));
+            return Err(PortableSourceError::repository_not_found(repo_name));
         }
-        
-        // Delete repo folder if present
-        if repo_path.exists() {
-            std::fs::remove_dir_all(&repo_path)
-                .map_err(|e| PortableSourceError::repository(
-                    format!("Failed to delete repository '{}': {}", repo_name, e)
-                ))?;
+        self.check_no_worktrees(repo_name)?;
+        let worktree_of = RepoMetadata::load(&repo_path).ok().flatten().and_then(|m| m.worktree_of);
+        let mut plan = ActionPlan::default();
+        if let Some(target) = EnvTarget::load(&repo_path).ok().flatten() {
+            plan.notes.push(format!("Packages installed into the {} stay; other repositories may use them", target));
         }
+        for link in shared_models::repo_model_links(&self.install_path, &repo_path) {
+            plan.delete(link, "link into shared_models; the shared files stay");
+        }
+        plan.delete(repo_state::dir(&repo_path), "install engine, constraints and logs");
+        plan.delete(repo_path, "repository source");
+        plan.delete(env_path, "repository environment");
+        if let Some(base) = worktree_of {
+            plan.modify(self.install_path.join("repos").join(&base).join(".git"), "worktree entry pruned");
+        }
+        Ok(plan)
+    }
 
-        // Delete corresponding env folder if present
-        if env_path.exists() {
-            std::fs::remove_dir_all(&env_path)
-                .map_err(|e| PortableSourceError::repository(
-                    format!("Failed to delete environment for '{}': {}", repo_name, e)
-                ))?;
+    /// Worktrees live off the object store of their base clone, so the base goes last
+    fn check_no_worktrees(&self, repo_name: &str) -> Result<()> {
+        let worktrees = repo_metadata::worktrees_of(&self.install_path, repo_name);
+        if worktrees.is_empty() {
+            return Ok(());
         }
-        
-        info!("Repository '{}' deleted successfully", repo_name);
-        Ok(())
+        Err(PortableSourceError::repository(format!(
+            "'{}' has worktrees installed ({}); delete them first", repo_name, worktrees.join(", ")
+        )))
     }
-    
+
     /// List installed repositories with source suffixes
     pub fn list_repositories(&self) -> Result<Vec<String>> {
-        let repos_path = self.install_path.join("repos");
-        
-        if !repos_path.exists() {
-            return Ok(Vec::new());
-        }
-        
-        let mut repositories = Vec::new();
-        
-        for entry in std::fs::read_dir(&repos_path)? {
-            let entry = entry?;
-            if entry.file_type()?.is_dir() {
-                if let Some(name) = entry.file_name().to_str() {
-                    let repo_dir = entry.path();
-                    let link_file = repo_dir.join("link.txt");
-                    let suffix = if link_file.exists() {
-                        let link = fs::read_to_string(&link_file).unwrap_or_default();
-                        let link_lower = link.to_lowercase();
-                        if link_lower.contains("github.com") { " [From github]" } else { " [From git]" }
-                    } else {
-                        " [From server]"
-                    };
-                    repositories.push(format!("{}{}", name, suffix));
-                }
-            }
-        }
-        
-        repositories.sort();
-        Ok(repositories)
+        Ok(self.list_repositories_labeled()?.into_iter().map(|(_, label)| label).collect())
     }
 
     /// List raw repository folder names (no suffixes)
     pub fn list_repository_names_raw(&self) -> Result<Vec<String>> {
-        let repos_path = self.install_path.join("repos");
-        if !repos_path.exists() { return Ok(Vec::new()); }
-        let mut repositories = Vec::new();
-        for entry in std::fs::read_dir(&repos_path)? {
-            let entry = entry?;
-            if entry.file_type()?.is_dir() {
-                if let Some(name) = entry.file_name().to_str() {
-                    repositories.push(name.to_string());
-                }
-            }
-        }
-        repositories.sort();
-        Ok(repositories)
+        Ok(RepoIndex::load_or_refresh(&self.install_path)?.repos.into_keys().collect())
     }
 
     /// List repositories with labels, preserving mapping to raw names, sorted by name
     pub fn list_repositories_labeled(&self) -> Result<Vec<(String, String)>> {
-        let repos_path = self.install_path.join("repos");
-        if !repos_path.exists() { return Ok(Vec::new()); }
-        let mut items: Vec<(String, String)> = Vec::new();
-        for entry in std::fs::read_dir(&repos_path)? {
-            let entry = entry?;
-            if entry.file_type()?.is_dir() {
-                if let Some(name) = entry.file_name().to_str() {
-                    let repo_dir = entry.path();
-                    let link_file = repo_dir.join("link.txt");
-                    let suffix = if link_file.exists() {
-                        let link = fs::read_to_string(&link_file).unwrap_or_default();
-                        let link_lower = link.to_lowercase();
-                        if link_lower.contains("github.com") { " [From github]" } else { " [From git]" }
-                    } else {
-                        " [From server]"
-                    };
-                    items.push((name.to_string(), format!("{}{}", name, suffix)));
-                }
-            }
-        }
-        items.sort_by(|a, b| a.0.cmp(&b.0));
-        Ok(items)
+        Ok(RepoIndex::load_or_refresh(&self.install_path)?.labeled())
+    }
+
+    /// Re-read every repository folder into the listing cache
+    pub fn rebuild_index(&self) -> Result<usize> {
+        Ok(RepoIndex::rebuild(&self.install_path)?.repos.len())
     }
     
     // Private helper methods
@@ -216,13 +603,26 @@ impl RepositoryInstaller {
         // Parse URL to get repository name
         let url = Url::parse(repo_url)
             .map_err(|e| PortableSourceError::repository(format!("Invalid repository URL: {}", e)))?;
-        let repo_name = self.extract_repo_name_from_url(&url)?;
+        let upstream = self.extract_repo_name_from_url(&url)?;
+
+        // Fail on a wrong URL, missing credentials or no network before touching the install dir
+        {
+            let command_runner = CommandRunner::new(&self.env_manager);
+            GitManager::new(&command_runner, &self.env_manager).check_remote(repo_url)?;
+        }
+
+        let repo_name = self.target_name(&upstream)?;
+        self.installed_name = Some(repo_name.clone());
         let repo_path = self.install_path.join("repos").join(&repo_name);
 
+        // Show license/provenance and confirm before fetching third-party code
+        let mut metadata = self.review_license(&repo_name, Some(repo_url), None)?;
+        metadata.upstream = (repo_name != upstream).then(|| upstream.clone());
+
         // Create modular components for this operation
         let command_runner = CommandRunner::new(&self.env_manager);
-        let git_manager = GitManager::new(&command_runner, &self.env_manager);
-        let pip_manager = PipManager::new(&command_runner, &self.config_manager);
+        let git_manager = GitManager::new(&command_runner, &self.env_manager).with_branch(self.branch.clone());
+        let pip_manager = PipManager::new(&command_runner, &self.config_manager).with_on_error(self.step_failure_policy());
         
         // Clone or update using GitManager
         let repo_info = GitRepositoryInfo { 
@@ -230,19 +630,30 @@ impl RepositoryInstaller {
             main_file: None, 
             program_args: None 
         };
-        git_manager.clone_or_update_repository(&repo_info, &repo_path).await?;
+        self.checkout(&git_manager, &repo_info, &upstream, &repo_path, &mut metadata).await?;
+        metadata.save(&repo_path)?;
 
         // Create URL marker and link.txt (source)
-        let _ = self.create_url_marker(&repo_path, &repo_name, repo_url);
+        let _ = self.create_url_marker(&repo_path, &upstream, repo_url);
         let _ = self.write_link_file(&repo_path, repo_url);
+        self.write_engine_marker(&repo_path)?;
+        self.write_env_target(&repo_path)?;
+        self.write_performance_profile(&repo_path)?;
+        lockfile::save_extras(&repo_path, &self.extras)?;
+        self.assign_resources(&repo_name, &upstream, &repo_path)?;
 
         // Install dependencies using DependencyInstaller
         let dependency_installer = DependencyInstaller::new(
             &pip_manager,
             &self.server_client,
             self.install_path.clone(),
-        );
+        )
+        .with_plan_review(self.review_plan)
+        .with_warm_start(!self.clean_env);
         dependency_installer.install_dependencies(&repo_path).await?;
+        metadata.record_skipped_steps(&repo_path, pip_manager.skipped_steps())?;
+        self.run_plugin_hook(Hook::Install, &repo_name, Some(repo_url), &repo_path, &pip_manager)?;
+        self.confirm_entry_point(&repo_name, &repo_path, &mut metadata)?;
 
         // Generate startup script using ScriptGenerator
         let script_generator = ScriptGenerator::new(
@@ -257,9 +668,10 @@ impl RepositoryInstaller {
             program_args: None,
         };
         script_generator.generate_startup_script(&repo_path, &script_repo_info)?;
+        self.run_plugin_hook(Hook::PostInstall, &repo_name, Some(repo_url), &repo_path, &pip_manager)?;
 
         // Send stats (non-fatal)
-        let _ = self.server_client.send_download_stats(&repo_name);
+        let _ = self.server_client.send_download_stats(&upstream);
 
         info!("Repository '{}' installed successfully", repo_name);
         Ok(())
@@ -267,20 +679,24 @@ impl RepositoryInstaller {
     
     async fn install_from_name(&mut self, repo_name: &str) -> Result<()> {
         info!("Installing from name: {}", repo_name);
-        println!("[PortableSource] Resolving repository '{}'", repo_name);
+        output::step(&format!("Resolving repository '{}'", repo_name));
         let repo_info = self.get_repository_info(repo_name)?
-            .ok_or_else(|| PortableSourceError::repository(format!("Repository '{}' not found", repo_name)))?;
+            .ok_or_else(|| PortableSourceError::repository_not_found(repo_name))?;
 
-        let name = self.normalize_repo_name(repo_name, &repo_info)?;
+        let upstream = self.normalize_repo_name(repo_name, &repo_info)?;
+        let name = self.target_name(&upstream)?;
+        self.installed_name = Some(name.clone());
         let repo_path = self.install_path.join("repos").join(&name);
 
-        println!("[PortableSource] Target path: {:?}", repo_path);
-        println!("[PortableSource] Cloning/Updating repository...");
+        output::step(&format!("Target path: {:?}", repo_path));
+        let mut metadata = self.review_license(&name, repo_info.url.as_deref(), repo_info.license.as_deref())?;
+        metadata.upstream = (name != upstream).then(|| upstream.clone());
+        output::step("Cloning/Updating repository...");
         
         // Create modular components for this operation
         let command_runner = CommandRunner::new(&self.env_manager);
-        let git_manager = GitManager::new(&command_runner, &self.env_manager);
-        let pip_manager = PipManager::new(&command_runner, &self.config_manager);
+        let git_manager = GitManager::new(&command_runner, &self.env_manager).with_branch(self.branch.clone());
+        let pip_manager = PipManager::new(&command_runner, &self.config_manager).with_on_error(self.step_failure_policy());
         
         // Convert to GitRepositoryInfo
         let git_repo_info = GitRepositoryInfo {
@@ -288,15 +704,28 @@ impl RepositoryInstaller {
             main_file: repo_info.main_file.clone(),
             program_args: repo_info.program_args.clone(),
         };
-        git_manager.clone_or_update_repository(&git_repo_info, &repo_path).await?;
+        self.checkout(&git_manager, &git_repo_info, &upstream, &repo_path, &mut metadata).await?;
+        metadata.save(&repo_path)?;
+        self.write_engine_marker(&repo_path)?;
+        self.write_env_target(&repo_path)?;
+        self.write_performance_profile(&repo_path)?;
+        lockfile::save_extras(&repo_path, &self.extras)?;
+        self.assign_resources(&name, &upstream, &repo_path)?;
 
-        println!("[PortableSource] Installing dependencies...");
+        output::step("Installing dependencies...");
         let dependency_installer = DependencyInstaller::new(
             &pip_manager,
             &self.server_client,
             self.install_path.clone(),
-        );
+        )
+        .with_plan_review(self.review_plan)
+        .with_warm_start(!self.clean_env);
         dependency_installer.install_dependencies(&repo_path).await?;
+        metadata.record_skipped_steps(&repo_path, pip_manager.skipped_steps())?;
+        self.run_plugin_hook(Hook::Install, &name, repo_info.url.as_deref(), &repo_path, &pip_manager)?;
+        if repo_info.main_file.is_none() {
+            self.confirm_entry_point(&name, &repo_path, &mut metadata)?;
+        }
 
         // Generate startup script using ScriptGenerator
         let script_generator = ScriptGenerator::new(
@@ -311,25 +740,27 @@ impl RepositoryInstaller {
             program_args: repo_info.program_args.clone(),
         };
         script_generator.generate_startup_script(&repo_path, &script_repo_info)?;
+        self.run_plugin_hook(Hook::PostInstall, &name, repo_info.url.as_deref(), &repo_path, &pip_manager)?;
 
-        let _ = self.server_client.send_download_stats(&name);
+        let _ = self.server_client.send_download_stats(&upstream);
         Ok(())
     }
     
-    fn is_repository_url(&self, input: &str) -> bool {
+    fn step_failure_policy(&self) -> OnStepError {
+        use std::io::{self, IsTerminal};
+        self.on_error.resolve(self.prompts && io::stdin().is_terminal())
+    }
+
+    pub fn is_repository_url(&self, input: &str) -> bool {
         input.starts_with("http://") || input.starts_with("https://") || input.starts_with("git@")
     }
     
     fn extract_repo_name_from_url(&self, url: &Url) -> Result<String> {
         let path = url.path();
-        let name = path.split('/').last().unwrap_or("unknown");
+        let name = path.split('/').next_back().unwrap_or("unknown");
         
         // Remove .git suffix if present
-        let name = if name.ends_with(".git") {
-            &name[..name.len() - 4]
-        } else {
-            name
-        };
+        let name = name.strip_suffix(".git").unwrap_or(name);
         
         if name.is_empty() {
             return Err(PortableSourceError::repository(
@@ -341,12 +772,24 @@ impl RepositoryInstaller {
     }
 
     fn get_repository_info(&self, repo_name: &str) -> Result<Option<FallbackRepo>> {
-        // Try server first
+        // Plugins describe repositories the server does not know (or override it)
+        let hook_repo = HookRepo { name: repo_name.to_string(), upstream: repo_name.to_string(), ..Default::default() };
+        if let Some(resolved) = self.plugins.run(Hook::Resolve, &hook_repo)?.into_iter().find(|r| r.url.is_some()) {
+            return Ok(Some(FallbackRepo {
+                url: resolved.url,
+                main_file: resolved.main_file,
+                program_args: resolved.program_args,
+                license: resolved.license,
+            }));
+        }
+
+        // Then the server
         if let Ok(Some(server_repo)) = self.server_client.get_repository_info(repo_name) {
             return Ok(Some(FallbackRepo {
                 url: server_repo.url,
                 main_file: server_repo.main_file,
                 program_args: server_repo.program_args,
+                license: server_repo.license,
             }));
         }
         
@@ -354,6 +797,74 @@ impl RepositoryInstaller {
         Ok(self.fallback_repositories.get(repo_name).cloned())
     }
 
+    /// Run an install or post-install hook of the plugins for a repository; `install` answers may add packages
+    fn run_plugin_hook(&self, hook: Hook, repo_name: &str, url: Option<&str>, repo_path: &Path, pip_manager: &PipManager) -> Result<()> {
+        let hook_repo = HookRepo {
+            name: repo_name.to_string(),
+            upstream: repo_metadata::upstream_name(repo_path),
+            url: url.map(str::to_string),
+            path: Some(repo_path.to_path_buf()),
+            env_python: Some(pip_manager.get_python_in_env(repo_name)),
+        };
+        let packages: Vec<String> = self.plugins.run(hook, &hook_repo)?.into_iter().flat_map(|r| r.packages).collect();
+        if packages.is_empty() {
+            return Ok(());
+        }
+        let requirements = repo_path.join("requirements_plugins.txt");
+        fs::write(&requirements, packages.join("\n"))?;
+        let result = pip_manager.install_requirements_with_uv_or_pip(repo_name, &requirements, Some(repo_path));
+        let _ = fs::remove_file(&requirements);
+        result
+    }
+
+    /// Clone the repository, or add it as a worktree of an installed clone (`--worktree-of`);
+    /// the branch and worktree base are recorded in `metadata` for updates and deletion
+    async fn checkout(&self, git_manager: &GitManager<'_>, repo_info: &GitRepositoryInfo, upstream: &str, repo_path: &Path, metadata: &mut RepoMetadata) -> Result<()> {
+        metadata.branch = self.branch.clone();
+        let Some(base) = &self.worktree_of else {
+            return git_manager.clone_or_update_repository(repo_info, repo_path).await;
+        };
+        let base_path = self.install_path.join("repos").join(base);
+        // A worktree has a `.git` file instead of a folder and cannot be a base itself
+        if !base_path.join(".git").is_dir() {
+            return Err(PortableSourceError::repository(format!(
+                "'{}' is not an installed clone; install it first or drop --worktree-of", base
+            )));
+        }
+        let base_upstream = repo_metadata::upstream_name(&base_path);
+        if !base_upstream.eq_ignore_ascii_case(upstream) {
+            return Err(PortableSourceError::repository(format!(
+                "'{}' is a clone of '{}', not of '{}'", base, base_upstream, upstream
+            )));
+        }
+        if repo_path == base_path {
+            return Err(PortableSourceError::repository("A worktree needs its own instance name (--as)"));
+        }
+        metadata.worktree_of = Some(base.clone());
+        git_manager.add_worktree(&base_path, repo_path)
+    }
+
+    /// Folder/venv name for an install: the instance name if one was given, else the upstream name
+    fn target_name(&self, upstream: &str) -> Result<String> {
+        let Some(instance) = &self.instance_name else {
+            return Ok(upstream.to_string());
+        };
+        repo_metadata::validate_instance_name(instance)?;
+        let repo_path = self.install_path.join("repos").join(instance);
+        if repo_path.exists() {
+            let existing = repo_metadata::upstream_name(&repo_path);
+            if !existing.eq_ignore_ascii_case(upstream) {
+                return Err(PortableSourceError::repository(format!(
+                    "'{}' is already installed from '{}'; choose another instance name", instance, existing
+                )));
+            }
+        }
+        if instance != upstream {
+            output::step(&format!("Installing '{}' as instance '{}'", upstream, instance));
+        }
+        Ok(instance.clone())
+    }
+
     fn normalize_repo_name(&self, input_name: &str, repo_info: &FallbackRepo) -> Result<String> {
         if let Some(ref url) = repo_info.url {
             if let Ok(parsed_url) = Url::parse(url) {
@@ -374,6 +885,172 @@ impl RepositoryInstaller {
         fs::write(&link_file, repo_url)?;
         Ok(())
     }
+
+    /// Show license and provenance; non-permissive licenses need explicit confirmation
+    fn review_license(&self, repo_name: &str, url: Option<&str>, server_license: Option<&str>) -> Result<RepoMetadata> {
+        let github = url.and_then(repo_metadata::fetch_github_metadata);
+        let (license, provenance) = match github {
+            Some((license, provenance)) => (license.or_else(|| server_license.map(LicenseInfo::from_spdx)), provenance),
+            None => (
+                server_license.map(LicenseInfo::from_spdx),
+                Provenance {
+                    source: if server_license.is_some() { "server" } else { "git" }.to_string(),
+                    url: url.map(|u| u.to_string()),
+                    fetched_at: crate::utils::unix_timestamp(),
+                    ..Default::default()
+                },
+            ),
+        };
+
+        let license_name = license.as_ref().map(|l| l.display_name()).unwrap_or_else(|| "unknown".to_string());
+        output::step(&format!("License: {}", license_name));
+        if let Some(url) = &provenance.url {
+            let owner = provenance.owner.as_deref().map(|o| format!(" (owner: {})", o)).unwrap_or_default();
+            output::step(&format!("Source: {}{} [via {}]", url, owner, provenance.source));
+        }
+        if provenance.archived {
+            output::warn("Upstream repository is archived and no longer maintained");
+        }
+
+        let permissive = license.as_ref().map(|l| l.is_permissive()).unwrap_or(false);
+        let mut accepted = false;
+        if !permissive {
+            if self.accept_license {
+                accepted = true;
+            } else {
+                use std::io::{self, IsTerminal};
+                let cannot_ask = if !self.prompts {
+                    Some("prompts are off")
+                } else if !io::stdin().is_terminal() {
+                    Some("stdin is not a terminal")
+                } else {
+                    None
+                };
+                if let Some(reason) = cannot_ask {
+                    let terms = license.as_ref().and_then(|l| l.url.as_deref()).or(provenance.url.as_deref());
+                    return Err(PortableSourceError::installation(format!(
+                        "License '{}' of '{}' is not a known permissive license and cannot be confirmed here ({}).{} \
+                         Pass --accept-license to install it anyway",
+                        license_name,
+                        repo_name,
+                        reason,
+                        terms.map(|url| format!(" Terms: {}.", url)).unwrap_or_default(),
+                    )));
+                }
+                let question = format!("License '{}' is not a known permissive license. Continue installing '{}'?", license_name, repo_name);
+                if !crate::prompt::confirm(&question) {
+                    return Err(PortableSourceError::installation(format!(
+                        "Installation of '{}' cancelled: license not accepted", repo_name
+                    )));
+                }
+                accepted = true;
+            }
+        }
+
+        Ok(RepoMetadata {
+            name: repo_name.to_string(),
+            license,
+            provenance: Some(provenance),
+            license_accepted: accepted,
+            entry_point: None,
+            upstream: None,
+            branch: None,
+            worktree_of: None,
+            runs: Vec::new(),
+            skipped_steps: Vec::new(),
+            backend: None,
+        })
+    }
+
+    /// Ask which file to launch when entry point detection is ambiguous; the answer is kept in metadata
+    fn confirm_entry_point(&self, repo_name: &str, repo_path: &Path, metadata: &mut RepoMetadata) -> Result<()> {
+        use std::io::{self, IsTerminal, Write};
+
+        let detection = self.main_file_finder.detect_entry_point(repo_path);
+        if detection.confident || detection.candidates.is_empty() {
+            return Ok(());
+        }
+        let candidates: Vec<_> = detection.candidates.iter().take(5).collect();
+        if !self.prompts || !io::stdin().is_terminal() {
+            info!("Entry point of '{}' is ambiguous, using {}", repo_name, candidates[0].path);
+            return Ok(());
+        }
+
+        output::step(&format!("Could not reliably detect the entry point of '{}'. Candidates:", repo_name));
+        for (i, c) in candidates.iter().enumerate() {
+            println!("  {}) {} ({})", i + 1, c.path, c.reasons.join(", "));
+        }
+        let chosen = loop {
+            print!("Select entry point [1-{}] or enter a path relative to the repo (default 1): ", candidates.len());
+            io::stdout().flush().ok();
+            let mut input = String::new();
+            io::stdin().read_line(&mut input).ok();
+            let input = input.trim();
+            if input.is_empty() {
+                break candidates[0].path.clone();
+            }
+            if let Ok(n) = input.parse::<usize>() {
+                if (1..=candidates.len()).contains(&n) {
+                    break candidates[n - 1].path.clone();
+                }
+            } else if repo_path.join(input).is_file() {
+                break input.replace('\\', "/");
+            }
+            output::warn(&format!("Invalid choice: {}", input));
+        };
+
+        output::step(&format!("Entry point: {}", chosen));
+        metadata.entry_point = Some(chosen);
+        metadata.save(repo_path)
+    }
+
+    /// Load stored metadata of an installed repository
+    pub fn repository_metadata(&self, repo_name: &str) -> Result<Option<RepoMetadata>> {
+        let repo_path = self.install_path.join("repos").join(repo_name);
+        if !repo_path.exists() {
+            return Err(PortableSourceError::repository_not_found(repo_name));
+        }
+        RepoMetadata::load(&repo_path)
+    }
+
+    fn write_performance_profile(&self, repo_path: &Path) -> Result<()> {
+        if let Some(profile) = self.performance_profile {
+            let mut settings = RepoRunSettings::load(repo_path)?;
+            settings.performance = Some(profile);
+            settings.save(repo_path)?;
+        }
+        Ok(())
+    }
+
+    /// Move the port/output folder off those of other installed repositories
+    fn assign_resources(&self, repo_name: &str, upstream: &str, repo_path: &Path) -> Result<()> {
+        let mut settings = RepoRunSettings::load(repo_path)?;
+        let (assigned, warnings) = resources::assign(&self.install_path, repo_name, upstream, &settings.resources)?;
+        for warning in &warnings {
+            output::warn(warning);
+        }
+        if assigned != settings.resources {
+            settings.resources = assigned;
+            settings.save(repo_path)?;
+        }
+        Ok(())
+    }
+
+    fn write_engine_marker(&self, repo_path: &Path) -> Result<()> {
+        if let Some(engine) = self.engine_override {
+            repo_state::write(repo_path, ENGINE_MARKER_FILE, engine.as_str())?;
+            info!("Install engine for {:?} set to {}", repo_path, engine);
+        }
+        Ok(())
+    }
+
+    fn write_env_target(&self, repo_path: &Path) -> Result<()> {
+        if let Some(target) = &self.env_target {
+            target.save(repo_path)?;
+            info!("Packages of {:?} go into the {}", repo_path, target);
+        }
+        Ok(())
+    }
 }
 
 fn default_fallback_repositories() -> HashMap<String, FallbackRepo> {
@@ -383,12 +1060,14 @@ fn default_fallback_repositories() -> HashMap<String, FallbackRepo> {
         url: Some("https://github.com/AUTOMATIC1111/stable-diffusion-webui.git".to_string()),
         main_file: Some("webui.py".to_string()),
         program_args: None,
+        license: Some("AGPL-3.0".to_string()),
     });
     
     repos.insert("comfyui".to_string(), FallbackRepo {
         url: Some("https://github.com/comfyanonymous/ComfyUI.git".to_string()),
         main_file: Some("main.py".to_string()),
         program_args: None,
+        license: Some("GPL-3.0".to_string()),
     });
     
     repos
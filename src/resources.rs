@@ -0,0 +1,217 @@
+//! Shared resources of installed repositories
+//!
+//! Two repositories that listen on the same default port (every gradio app wants 7860) or
+//! write to the same output folder cannot run side by side. At install time the ports and
+//! output folders of the other repositories are collected from their run settings; a
+//! clash gets the next free port or a distinct output folder, recorded in the new
+//! repository's run settings and passed by its start script (`--port`, `--output-directory`
+//! or `GRADIO_SERVER_PORT`, depending on what the application understands).
+
+use crate::run_queue::RepoRunSettings;
+use crate::Result;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Port and output folder assigned to a repository; unset fields mean the application default
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct RepoResources {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_dir: Option<PathBuf>,
+}
+
+impl RepoResources {
+    pub fn is_empty(&self) -> bool {
+        self.port.is_none() && self.output_dir.is_none()
+    }
+}
+
+/// How an application picks its port and output folder
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LaunchConvention {
+    pub default_port: u16,
+    /// Command-line flag for the port; None: `GRADIO_SERVER_PORT`
+    pub port_arg: Option<&'static str>,
+    /// Command-line flag for the output folder; None: outputs cannot be moved
+    pub output_arg: Option<&'static str>,
+    /// Output folder relative to the repository
+    pub default_output: &'static str,
+}
+
+/// Convention of a known application, from the upstream repository name
+pub fn convention(upstream: &str) -> Option<LaunchConvention> {
+    let name = upstream.to_lowercase();
+    if name.contains("comfyui") {
+        Some(LaunchConvention { default_port: 8188, port_arg: Some("--port"), output_arg: Some("--output-directory"), default_output: "output" })
+    } else if name.contains("stable-diffusion-webui") || name.contains("sd-webui") {
+        Some(LaunchConvention { default_port: 7860, port_arg: Some("--port"), output_arg: None, default_output: "outputs" })
+    } else if name.contains("fooocus") {
+        Some(LaunchConvention { default_port: 7865, port_arg: Some("--port"), output_arg: Some("--output-path"), default_output: "outputs" })
+    } else if name.contains("facefusion") {
+        Some(LaunchConvention { default_port: 7860, port_arg: None, output_arg: None, default_output: ".temp" })
+    } else {
+        None
+    }
+}
+
+/// Effective port and output folder of an installed repository
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Claim {
+    pub repo: String,
+    pub port: u16,
+    pub output_dir: PathBuf,
+}
+
+fn effective(repo_path: &Path, conv: &LaunchConvention, recorded: &RepoResources) -> (u16, PathBuf) {
+    (
+        recorded.port.unwrap_or(conv.default_port),
+        recorded.output_dir.clone().unwrap_or_else(|| repo_path.join(conv.default_output)),
+    )
+}
+
+/// Resources used by the installed repositories other than `except`
+pub fn claims(install_path: &Path, except: &str) -> Result<Vec<Claim>> {
+    let repos = install_path.join("repos");
+    let mut names: Vec<String> = std::fs::read_dir(&repos)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|e| e.path().is_dir())
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .filter(|name| !name.eq_ignore_ascii_case(except))
+        .collect();
+    names.sort();
+
+    let mut claims = Vec::new();
+    for repo in names {
+        let repo_path = repos.join(&repo);
+        let Some(conv) = convention(&crate::repo_metadata::upstream_name(&repo_path)) else { continue };
+        let (port, output_dir) = effective(&repo_path, &conv, &RepoRunSettings::load(&repo_path)?.resources);
+        claims.push(Claim { repo, port, output_dir });
+    }
+    Ok(claims)
+}
+
+/// Resources for a repository being installed, and a warning per reassignment
+pub fn assign(install_path: &Path, repo: &str, upstream: &str, current: &RepoResources) -> Result<(RepoResources, Vec<String>)> {
+    let Some(conv) = convention(upstream) else { return Ok((current.clone(), Vec::new())) };
+    let repo_path = install_path.join("repos").join(repo);
+    let claims = claims(install_path, repo)?;
+    let (wanted_port, wanted_output) = effective(&repo_path, &conv, current);
+    let mut assigned = current.clone();
+    let mut warnings = Vec::new();
+
+    if let Some(owner) = claims.iter().find(|c| c.port == wanted_port) {
+        let mut port = conv.default_port;
+        while claims.iter().any(|c| c.port == port) {
+            port += 1;
+        }
+        warnings.push(format!("Port {} is already used by '{}'; '{}' will use port {}", wanted_port, owner.repo, repo, port));
+        assigned.port = (port != conv.default_port).then_some(port);
+    }
+
+    if let Some(owner) = claims.iter().find(|c| c.output_dir == wanted_output) {
+        if conv.output_arg.is_some() {
+            let output = install_path.join("outputs").join(repo);
+            warnings.push(format!("Output folder {:?} is shared with '{}'; '{}' will write to {:?}", wanted_output, owner.repo, repo, output));
+            assigned.output_dir = Some(output);
+        } else {
+            warnings.push(format!("Output folder {:?} is shared with '{}' and cannot be changed for '{}'", wanted_output, owner.repo, repo));
+        }
+    }
+    Ok((assigned, warnings))
+}
+
+/// Start script arguments and environment variables applying `resources`
+pub fn launch_settings(upstream: &str, resources: &RepoResources, program_args: &str) -> (Vec<String>, Vec<(&'static str, String)>) {
+    let mut args = Vec::new();
+    let mut env = Vec::new();
+    let Some(conv) = convention(upstream) else { return (args, env) };
+    let has_arg = |flag: &str| program_args.split_whitespace().any(|a| a == flag || a.starts_with(&format!("{}=", flag)));
+
+    if let Some(port) = resources.port {
+        match conv.port_arg {
+            Some(flag) if !has_arg(flag) => args.extend([flag.to_string(), port.to_string()]),
+            Some(_) => {}
+            None => env.push(("GRADIO_SERVER_PORT", port.to_string())),
+        }
+    }
+    if let (Some(dir), Some(flag)) = (&resources.output_dir, conv.output_arg) {
+        if !has_arg(flag) {
+            args.extend([flag.to_string(), format!("\"{}\"", dir.display())]);
+        }
+    }
+    (args, env)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo_metadata::RepoMetadata;
+
+    /// Install with comfyui, a second instance comfy2 sharing its outputs and port 8189 by
+    /// hand, facefusion and webui; returns comfy2's settings
+    fn instances(root: &Path) -> RepoResources {
+        for repo in ["comfyui", "comfy2", "facefusion", "webui"] {
+            std::fs::create_dir_all(root.join("repos").join(repo)).unwrap();
+        }
+        RepoMetadata { name: "comfy2".into(), upstream: Some("comfyui".into()), ..Default::default() }
+            .save(&root.join("repos/comfy2"))
+            .unwrap();
+        RepoMetadata { name: "webui".into(), upstream: Some("stable-diffusion-webui".into()), ..Default::default() }
+            .save(&root.join("repos/webui"))
+            .unwrap();
+        let shared = RepoRunSettings {
+            resources: RepoResources { port: Some(8189), output_dir: Some(root.join("repos/comfyui/output")) },
+            ..Default::default()
+        };
+        shared.save(&root.join("repos/comfy2")).unwrap();
+        shared.resources
+    }
+
+    #[test]
+    fn another_instance_gets_the_next_free_port() {
+        let dir = tempfile::tempdir().unwrap();
+        instances(dir.path());
+        let (fresh, warnings) = assign(dir.path(), "comfy3", "comfyui", &RepoResources::default()).unwrap();
+        assert_eq!(fresh.port, Some(8190));
+        assert_eq!(fresh.output_dir, None);
+        assert_eq!(warnings.len(), 1);
+    }
+
+    #[test]
+    fn shared_output_folder_is_replaced_by_an_own_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = instances(dir.path());
+        let (fixed, warnings) = assign(dir.path(), "comfy2", "comfyui", &shared).unwrap();
+        assert_eq!(fixed.port, Some(8189));
+        assert_eq!(fixed.output_dir, Some(dir.path().join("outputs/comfy2")));
+        assert!(warnings[0].contains("shared with 'comfyui'"));
+    }
+
+    #[test]
+    fn repos_with_the_same_default_port_get_distinct_ones() {
+        // facefusion defaults to 7860 like webui
+        let dir = tempfile::tempdir().unwrap();
+        instances(dir.path());
+        let (ff, _) = assign(dir.path(), "facefusion", "facefusion", &RepoResources::default()).unwrap();
+        assert_eq!(ff.port, Some(7861));
+    }
+
+    #[test]
+    fn port_goes_through_the_environment_without_a_port_flag() {
+        let resources = RepoResources { port: Some(7861), output_dir: None };
+        let (args, env) = launch_settings("facefusion", &resources, "run");
+        assert!(args.is_empty());
+        assert_eq!(env, [("GRADIO_SERVER_PORT", "7861".to_string())]);
+    }
+
+    #[test]
+    fn port_given_on_the_command_line_is_kept() {
+        let resources = RepoResources { port: Some(8190), output_dir: None };
+        let (args, _) = launch_settings("comfyui", &resources, "--listen --port 9000");
+        assert!(args.is_empty());
+    }
+}
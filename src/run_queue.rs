@@ -0,0 +1,193 @@
+//! Idle-GPU queue for run-repo
+//!
+//! When a GPU-heavy repo is already running, a new launch can wait until VRAM frees up
+//! instead of failing with CUDA out of memory. Global defaults live in the config
+//! (`gpu_queue`), per-repo overrides in `repos/<name>/.portablesource_run.json`,
+//! and run-repo flags override both.
+
+use crate::config::GpuQueueConfig;
+use crate::gpu::{GpuDetector, GpuMemoryUsage};
+use crate::performance::PerformanceProfile;
+use crate::resources::RepoResources;
+use crate::output;
+use crate::{PortableSourceError, Result};
+use tracing::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+pub const RUN_SETTINGS_FILE: &str = ".portablesource_run.json";
+
+/// Partial queue settings; unset fields fall through to the next layer
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct GpuQueueOverride {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_free_vram_mb: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+}
+
+impl GpuQueueOverride {
+    pub fn is_empty(&self) -> bool {
+        self.enabled.is_none() && self.min_free_vram_mb.is_none() && self.timeout_secs.is_none()
+    }
+
+    /// Overlay set fields of `other` onto self
+    pub fn merge(&mut self, other: &GpuQueueOverride) {
+        if other.enabled.is_some() { self.enabled = other.enabled; }
+        if other.min_free_vram_mb.is_some() { self.min_free_vram_mb = other.min_free_vram_mb; }
+        if other.timeout_secs.is_some() { self.timeout_secs = other.timeout_secs; }
+    }
+
+    pub fn apply(&self, base: &GpuQueueConfig) -> GpuQueueConfig {
+        GpuQueueConfig {
+            enabled: self.enabled.unwrap_or(base.enabled),
+            min_free_vram_mb: self.min_free_vram_mb.unwrap_or(base.min_free_vram_mb),
+            timeout_secs: self.timeout_secs.unwrap_or(base.timeout_secs),
+            poll_interval_secs: base.poll_interval_secs,
+        }
+    }
+}
+
+/// Per-repo run settings stored next to the repo markers
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RepoRunSettings {
+    pub gpu_queue: GpuQueueOverride,
+    /// Tuning exported by the start script; none when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub performance: Option<PerformanceProfile>,
+    /// Port and output folder assigned at install time to avoid clashes
+    #[serde(skip_serializing_if = "RepoResources::is_empty")]
+    pub resources: RepoResources,
+    /// Native library components put on the PATH of the Windows start script
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub components: Vec<String>,
+}
+
+impl RepoRunSettings {
+    pub fn path(repo_path: &Path) -> PathBuf {
+        repo_path.join(RUN_SETTINGS_FILE)
+    }
+
+    pub fn load(repo_path: &Path) -> Result<Self> {
+        let path = Self::path(repo_path);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(serde_json::from_str(&std::fs::read_to_string(&path)?)?)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.gpu_queue.is_empty() && self.performance.is_none() && self.resources.is_empty() && self.components.is_empty()
+    }
+
+    pub fn save(&self, repo_path: &Path) -> Result<()> {
+        let path = Self::path(repo_path);
+        if self.is_empty() {
+            if path.exists() { std::fs::remove_file(&path)?; }
+            return Ok(());
+        }
+        crate::atomic_write::write(path, serde_json::to_string_pretty(self)?)
+    }
+}
+
+/// Global config, then the repo override, then command-line flags
+pub fn effective_queue_config(global: &GpuQueueConfig, repo_path: &Path, flags: &GpuQueueOverride) -> Result<GpuQueueConfig> {
+    let mut layered = RepoRunSettings::load(repo_path)?.gpu_queue;
+    layered.merge(flags);
+    Ok(layered.apply(global))
+}
+
+/// Keep only the GPU the launch is bound to, else those listed in a numeric
+/// CUDA_VISIBLE_DEVICES, if set
+fn visible_gpus(gpus: Vec<GpuMemoryUsage>, only_gpu: Option<u32>) -> Vec<GpuMemoryUsage> {
+    if let Some(index) = only_gpu {
+        return gpus.into_iter().filter(|g| g.index == index).collect();
+    }
+    let Ok(visible) = std::env::var("CUDA_VISIBLE_DEVICES") else { return gpus };
+    let indices: Option<Vec<u32>> = visible.split(',').map(|s| s.trim().parse().ok()).collect();
+    match indices {
+        Some(indices) => gpus.into_iter().filter(|g| indices.contains(&g.index)).collect(),
+        // UUID-style values: cannot map to nvidia-smi indices, consider all GPUs
+        None => gpus,
+    }
+}
+
+/// Block until some visible GPU has `min_free_vram_mb` free, or fail after the timeout.
+/// `only_gpu` is the nvidia-smi index of the GPU picked with `run-repo --gpu`
+pub fn wait_for_idle_gpu(repo: &str, cfg: &GpuQueueConfig, only_gpu: Option<u32>) -> Result<()> {
+    if !cfg.enabled {
+        return Ok(());
+    }
+    let detector = GpuDetector::new();
+    let started = Instant::now();
+    let mut queued = false;
+
+    loop {
+        let gpus = visible_gpus(detector.query_gpu_memory(), only_gpu);
+        let Some(best_free) = gpus.iter().map(|g| g.free_mb()).max() else {
+            warn!("nvidia-smi unavailable, launching '{}' without GPU queue", repo);
+            return Ok(());
+        };
+        debug!("GPU queue: {} MB free, need {} MB", best_free, cfg.min_free_vram_mb);
+
+        if best_free >= cfg.min_free_vram_mb {
+            if queued {
+                output::info(&format!(
+                    "{} MB VRAM free, launching '{}' (waited {}s)",
+                    best_free, repo, started.elapsed().as_secs()
+                ));
+            }
+            return Ok(());
+        }
+
+        if !queued {
+            let limit = if cfg.timeout_secs > 0 { format!(" (timeout {}s)", cfg.timeout_secs) } else { String::new() };
+            output::info(&format!(
+                "GPU busy: {} MB VRAM free, '{}' needs {} MB. Queued until VRAM frees up{}",
+                best_free, repo, cfg.min_free_vram_mb, limit
+            ));
+            queued = true;
+        }
+        if cfg.timeout_secs > 0 && started.elapsed() >= Duration::from_secs(cfg.timeout_secs) {
+            return Err(PortableSourceError::command(format!(
+                "Timed out after {}s waiting for {} MB of free VRAM to launch '{}'",
+                cfg.timeout_secs, cfg.min_free_vram_mb, repo
+            )));
+        }
+        std::thread::sleep(Duration::from_secs(cfg.poll_interval_secs.max(1)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_override_repo_settings_over_global() {
+        let dir = tempfile::tempdir().unwrap();
+        RepoRunSettings {
+            gpu_queue: GpuQueueOverride { enabled: Some(true), min_free_vram_mb: Some(8000), timeout_secs: None },
+            ..Default::default()
+        }
+        .save(dir.path())
+        .unwrap();
+
+        let flags = GpuQueueOverride { min_free_vram_mb: Some(12000), ..Default::default() };
+        let cfg = effective_queue_config(&GpuQueueConfig::default(), dir.path(), &flags).unwrap();
+        assert!(cfg.enabled);
+        assert_eq!(cfg.min_free_vram_mb, 12000);
+        assert_eq!(cfg.timeout_secs, GpuQueueConfig::default().timeout_secs);
+    }
+
+    #[test]
+    fn the_chosen_gpu_is_the_only_one_queued_on() {
+        let gpu = |index, used_mb| GpuMemoryUsage { index, used_mb, total_mb: 24576, utilization_pct: None };
+        let gpus = visible_gpus(vec![gpu(0, 24000), gpu(1, 100)], Some(0));
+        assert_eq!(gpus, vec![gpu(0, 24000)]);
+    }
+}
@@ -0,0 +1,221 @@
+//! Per-run statistics for run-repo
+//!
+//! When `collect_run_stats` is on, every launch samples nvidia-smi in the background and
+//! appends a [`RunRecord`] (times, exit code, average GPU utilization, VRAM) to the repo's
+//! metadata. `portablesource stats <repo>` summarizes them, grouped by launch arguments,
+//! so it is easy to see which model settings fit the GPU.
+
+use crate::gpu::GpuDetector;
+use crate::repo_metadata::RepoMetadata;
+use crate::utils::unix_timestamp;
+use tracing::warn;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Older runs are dropped beyond this many
+pub const MAX_RUN_RECORDS: usize = 200;
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RunRecord {
+    pub started_at: u64,
+    pub finished_at: u64,
+    /// None when the process was killed or could not be started
+    pub exit_code: Option<i32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    pub avg_gpu_util_pct: Option<f32>,
+    /// Highest VRAM use seen on any GPU during the run
+    pub peak_vram_mb: Option<u64>,
+    /// VRAM already in use when the run started
+    pub baseline_vram_mb: Option<u64>,
+}
+
+impl RunRecord {
+    pub fn duration_secs(&self) -> u64 {
+        self.finished_at.saturating_sub(self.started_at)
+    }
+
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+
+    /// VRAM the run added on top of what was in use before it
+    pub fn added_vram_mb(&self) -> Option<u64> {
+        Some(self.peak_vram_mb?.saturating_sub(self.baseline_vram_mb.unwrap_or(0)))
+    }
+}
+
+#[derive(Debug, Default)]
+struct Samples {
+    util_sum: f64,
+    util_count: u32,
+    peak_vram_mb: Option<u64>,
+    baseline_vram_mb: Option<u64>,
+}
+
+/// Background nvidia-smi sampler for one launch
+pub struct RunStatsRecorder {
+    repo_path: PathBuf,
+    args: Vec<String>,
+    started_at: u64,
+    stop: Arc<AtomicBool>,
+    sampler: JoinHandle<Samples>,
+}
+
+impl RunStatsRecorder {
+    pub fn start(repo_path: &Path, args: &[String]) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_flag = stop.clone();
+        let sampler = std::thread::spawn(move || {
+            let detector = GpuDetector::new();
+            let mut samples = Samples::default();
+            loop {
+                let gpus = detector.query_gpu_memory();
+                if gpus.is_empty() {
+                    // No NVIDIA GPU / nvidia-smi: record times and exit code only
+                    break;
+                }
+                let used = gpus.iter().map(|g| g.used_mb).max().unwrap_or(0);
+                samples.baseline_vram_mb.get_or_insert(used);
+                samples.peak_vram_mb = Some(samples.peak_vram_mb.unwrap_or(0).max(used));
+                if let Some(util) = gpus.iter().filter_map(|g| g.utilization_pct).max() {
+                    samples.util_sum += util as f64;
+                    samples.util_count += 1;
+                }
+
+                let next = Instant::now() + SAMPLE_INTERVAL;
+                while Instant::now() < next {
+                    if stop_flag.load(Ordering::SeqCst) {
+                        return samples;
+                    }
+                    std::thread::sleep(Duration::from_millis(200));
+                }
+            }
+            samples
+        });
+        Self { repo_path: repo_path.to_path_buf(), args: args.to_vec(), started_at: unix_timestamp(), stop, sampler }
+    }
+
+    /// Stop sampling and append the run to the repo metadata (failures only logged)
+    pub fn finish(self, exit_code: Option<i32>) {
+        self.stop.store(true, Ordering::SeqCst);
+        let samples = self.sampler.join().unwrap_or_default();
+        let record = RunRecord {
+            started_at: self.started_at,
+            finished_at: unix_timestamp(),
+            exit_code,
+            args: self.args,
+            avg_gpu_util_pct: (samples.util_count > 0).then(|| (samples.util_sum / samples.util_count as f64) as f32),
+            peak_vram_mb: samples.peak_vram_mb,
+            baseline_vram_mb: samples.baseline_vram_mb,
+        };
+        if let Err(e) = append_run(&self.repo_path, record) {
+            warn!("Failed to save run statistics: {}", e);
+        }
+    }
+}
+
+pub fn append_run(repo_path: &Path, record: RunRecord) -> crate::Result<()> {
+    let mut metadata = RepoMetadata::load(repo_path)?.unwrap_or_else(|| RepoMetadata {
+        name: repo_path.file_name().map(|s| s.to_string_lossy().to_string()).unwrap_or_default(),
+        ..Default::default()
+    });
+    metadata.runs.push(record);
+    if metadata.runs.len() > MAX_RUN_RECORDS {
+        let excess = metadata.runs.len() - MAX_RUN_RECORDS;
+        metadata.runs.drain(..excess);
+    }
+    metadata.save(repo_path)
+}
+
+/// Aggregates over a set of runs
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RunSummary {
+    pub runs: usize,
+    pub succeeded: usize,
+    pub avg_duration_secs: u64,
+    pub avg_gpu_util_pct: Option<f32>,
+    pub max_peak_vram_mb: Option<u64>,
+    pub max_added_vram_mb: Option<u64>,
+}
+
+pub fn summarize(records: &[&RunRecord]) -> RunSummary {
+    if records.is_empty() {
+        return RunSummary::default();
+    }
+    let utils: Vec<f32> = records.iter().filter_map(|r| r.avg_gpu_util_pct).collect();
+    RunSummary {
+        runs: records.len(),
+        succeeded: records.iter().filter(|r| r.success()).count(),
+        avg_duration_secs: records.iter().map(|r| r.duration_secs()).sum::<u64>() / records.len() as u64,
+        avg_gpu_util_pct: (!utils.is_empty()).then(|| utils.iter().sum::<f32>() / utils.len() as f32),
+        max_peak_vram_mb: records.iter().filter_map(|r| r.peak_vram_mb).max(),
+        max_added_vram_mb: records.iter().filter_map(|r| r.added_vram_mb()).max(),
+    }
+}
+
+/// Summaries per distinct argument list, most used first
+pub fn summarize_by_args(records: &[RunRecord]) -> Vec<(String, RunSummary)> {
+    let mut groups: Vec<(String, Vec<&RunRecord>)> = Vec::new();
+    for record in records {
+        let key = record.args.join(" ");
+        match groups.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, group)) => group.push(record),
+            None => groups.push((key, vec![record])),
+        }
+    }
+    let mut summaries: Vec<_> = groups.into_iter().map(|(k, g)| (k, summarize(&g))).collect();
+    summaries.sort_by_key(|(_, s)| std::cmp::Reverse(s.runs));
+    summaries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(args: &[&str], exit_code: i32, peak: u64, util: f32) -> RunRecord {
+        RunRecord {
+            started_at: 1_000,
+            finished_at: 1_060,
+            exit_code: Some(exit_code),
+            args: args.iter().map(|s| s.to_string()).collect(),
+            avg_gpu_util_pct: Some(util),
+            peak_vram_mb: Some(peak),
+            baseline_vram_mb: Some(1_000),
+        }
+    }
+
+    #[test]
+    fn summarizes_runs_per_argument_set() {
+        let records = vec![
+            run(&["--highvram"], 1, 24_000, 90.0),
+            run(&[], 0, 9_000, 60.0),
+            run(&["--highvram"], 0, 20_000, 80.0),
+        ];
+        let groups = summarize_by_args(&records);
+        assert_eq!(groups[0].0, "--highvram");
+        let high = &groups[0].1;
+        assert_eq!((high.runs, high.succeeded, high.avg_duration_secs), (2, 1, 60));
+        assert_eq!(high.max_peak_vram_mb, Some(24_000));
+        assert_eq!(high.max_added_vram_mb, Some(23_000));
+        assert_eq!(high.avg_gpu_util_pct, Some(85.0));
+        assert_eq!(groups[1].1.runs, 1);
+    }
+
+    #[test]
+    fn keeps_only_recent_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..MAX_RUN_RECORDS + 5 {
+            append_run(dir.path(), RunRecord { started_at: i as u64, ..Default::default() }).unwrap();
+        }
+        let runs = RepoMetadata::load(dir.path()).unwrap().unwrap().runs;
+        assert_eq!(runs.len(), MAX_RUN_RECORDS);
+        assert_eq!(runs[0].started_at, 5);
+    }
+}
@@ -0,0 +1,528 @@
+//! Scheduled maintenance tasks
+//!
+//! Registers a Scheduled Task (Windows) or a systemd user timer / cron entry (Linux)
+//! that runs `portablesource schedule run`. The run replays the configured
+//! PortableSource commands non-interactively and writes a report into the install dir.
+
+use crate::{Result, PortableSourceError};
+use crate::cli::{Cli, Commands};
+use crate::atomic_write;
+#[cfg(unix)]
+use crate::launch_command::quote_unix;
+use crate::output;
+use crate::utils::{execute_command, is_command_available, unix_timestamp};
+use clap::Parser;
+use tracing::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+const SCHEDULE_FILE: &str = "schedule.json";
+const REPORT_FILE: &str = "maintenance_report.txt";
+#[cfg(windows)]
+const TASK_NAME: &str = "PortableSource\\Maintenance";
+#[cfg(unix)]
+const UNIT_NAME: &str = "portablesource-maintenance";
+#[cfg(unix)]
+const CRON_MARKER: &str = "# portablesource-maintenance";
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ScheduleFrequency {
+    Daily,
+    Weekly,
+}
+
+impl ScheduleFrequency {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScheduleFrequency::Daily => "daily",
+            ScheduleFrequency::Weekly => "weekly",
+        }
+    }
+}
+
+/// How the schedule was registered with the OS
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ScheduleBackend {
+    TaskScheduler,
+    Systemd,
+    Cron,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MaintenanceSchedule {
+    pub frequency: ScheduleFrequency,
+    /// PortableSource command lines, e.g. "update-repo comfyui"
+    pub commands: Vec<String>,
+    pub backend: ScheduleBackend,
+}
+
+impl MaintenanceSchedule {
+    pub fn load(install_path: &Path) -> Result<Option<Self>> {
+        let path = install_path.join(SCHEDULE_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = std::fs::read_to_string(&path)?;
+        Ok(Some(serde_json::from_str(&content)?))
+    }
+
+    fn save(&self, install_path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        atomic_write::write(install_path.join(SCHEDULE_FILE), json)
+    }
+}
+
+/// Split "cmd a; cmd b" into separate command lines; a quoted `;` stays in its argument
+pub fn parse_command_list(spec: &str) -> Vec<String> {
+    let mut commands = Vec::new();
+    let mut current = String::new();
+    let mut quote = None;
+    let mut escaped = false;
+    for c in spec.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quote != Some('\'') => escaped = true,
+            '\'' | '"' if quote.is_none() => quote = Some(c),
+            _ if quote == Some(c) => quote = None,
+            ';' if quote.is_none() => {
+                commands.push(std::mem::take(&mut current));
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    commands.push(current);
+    commands.into_iter().map(|c| c.trim().to_string()).filter(|c| !c.is_empty()).collect()
+}
+
+/// Arguments of one command line, split like the platform shell would: POSIX quotes and
+/// backslashes, or on Windows the C runtime rules under which `C:\models` keeps its backslashes
+pub fn command_args(cmd_line: &str) -> Result<Vec<String>> {
+    #[cfg(windows)]
+    let args = split_windows(cmd_line);
+    #[cfg(not(windows))]
+    let args = shell_words::split(cmd_line).map_err(|e| e.to_string());
+    args.map_err(|e| PortableSourceError::config(format!("Cannot parse maintenance command '{}': {}", cmd_line, e)))
+}
+
+/// `cmd_line` split like `CommandLineToArgvW` does: backslashes are literal unless they precede
+/// a `"`, and `""` inside quotes is a literal quote
+#[cfg_attr(not(windows), allow(dead_code))]
+fn split_windows(cmd_line: &str) -> std::result::Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_arg = false;
+    let mut quoted = false;
+    let mut chars = cmd_line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                let mut backslashes = 1;
+                while chars.next_if_eq(&'\\').is_some() {
+                    backslashes += 1;
+                }
+                if chars.peek() == Some(&'"') {
+                    current.extend(std::iter::repeat_n('\\', backslashes / 2));
+                    if backslashes % 2 == 1 {
+                        current.push('"');
+                        chars.next();
+                    }
+                } else {
+                    current.extend(std::iter::repeat_n('\\', backslashes));
+                }
+                in_arg = true;
+            }
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                current.push('"');
+                chars.next();
+            }
+            '"' => {
+                quoted = !quoted;
+                in_arg = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            c => {
+                current.push(c);
+                in_arg = true;
+            }
+        }
+    }
+    if quoted {
+        return Err("missing closing quote".to_string());
+    }
+    if in_arg {
+        args.push(current);
+    }
+    Ok(args)
+}
+
+/// Reject a command line the scheduled run could never execute: unknown subcommands, bad
+/// arguments, or `schedule` itself
+pub fn validate_command(cmd_line: &str) -> Result<()> {
+    let args = command_args(cmd_line)?;
+    let cli = Cli::try_parse_from(std::iter::once("portablesource".to_string()).chain(args)).map_err(|e| {
+        let rendered = e.to_string();
+        let reason = rendered.lines().next().unwrap_or_default().trim_start_matches("error: ").to_string();
+        PortableSourceError::config(format!("Invalid maintenance command '{}': {}", cmd_line, reason))
+    })?;
+    match cli.command {
+        Some(Commands::Schedule { .. }) => Err(PortableSourceError::config(format!(
+            "Invalid maintenance command '{}': a scheduled run cannot change the schedule",
+            cmd_line
+        ))),
+        None => Err(PortableSourceError::config(format!("Invalid maintenance command '{}': no subcommand", cmd_line))),
+        Some(_) => Ok(()),
+    }
+}
+
+/// Register maintenance task with the OS scheduler
+pub fn enable_schedule(install_path: &Path, frequency: ScheduleFrequency, commands_spec: &str) -> Result<()> {
+    let commands = parse_command_list(commands_spec);
+    if commands.is_empty() {
+        return Err(PortableSourceError::config("No maintenance commands given"));
+    }
+    for cmd in &commands {
+        validate_command(cmd)?;
+    }
+
+    let exe = std::env::current_exe()?;
+    let backend = register_task(&exe, install_path, frequency)?;
+    let schedule = MaintenanceSchedule { frequency, commands, backend };
+    schedule.save(install_path)?;
+
+    output::info(&format!("Maintenance scheduled {} via {:?}", frequency.as_str(), backend));
+    for cmd in &schedule.commands {
+        println!("  - {}", cmd);
+    }
+    output::info(&format!("Report will be written to {}", install_path.join(REPORT_FILE).display()));
+    Ok(())
+}
+
+/// Remove maintenance task from the OS scheduler
+pub fn disable_schedule(install_path: &Path) -> Result<()> {
+    match MaintenanceSchedule::load(install_path)? {
+        Some(schedule) => {
+            unregister_task(schedule.backend)?;
+            std::fs::remove_file(install_path.join(SCHEDULE_FILE))?;
+            output::info("Scheduled maintenance disabled");
+        }
+        None => output::info("Scheduled maintenance is not enabled"),
+    }
+    Ok(())
+}
+
+/// Print schedule configuration and last report summary
+pub fn show_schedule_status(install_path: &Path) -> Result<()> {
+    let Some(schedule) = MaintenanceSchedule::load(install_path)? else {
+        println!("Scheduled maintenance: disabled");
+        return Ok(());
+    };
+    println!("Scheduled maintenance: enabled ({}, {:?})", schedule.frequency.as_str(), schedule.backend);
+    println!("Registered with OS: {}", if is_task_registered(schedule.backend) { "yes" } else { "NO (re-run 'schedule enable')" });
+    println!("Commands:");
+    for cmd in &schedule.commands {
+        println!("  - {}", cmd);
+    }
+    let report = install_path.join(REPORT_FILE);
+    if let Ok(content) = std::fs::read_to_string(&report) {
+        println!("\nLast report ({}):", report.display());
+        for line in content.lines().filter(|l| !l.starts_with("    ")) {
+            println!("  {}", line);
+        }
+    } else {
+        println!("No report yet");
+    }
+    Ok(())
+}
+
+/// Entry point for the scheduler: run configured commands and write a report. Fails when a
+/// command failed, so the OS scheduler records the run as failed too
+pub fn run_scheduled_maintenance(install_path: &Path) -> Result<()> {
+    let schedule = MaintenanceSchedule::load(install_path)?
+        .ok_or_else(|| PortableSourceError::config("Scheduled maintenance is not enabled"))?;
+    run_commands(&std::env::current_exe()?, install_path, &schedule.commands)
+}
+
+fn run_commands(exe: &Path, install_path: &Path, commands: &[String]) -> Result<()> {
+    let started = unix_timestamp();
+    let mut report = format!("Maintenance run started at {} (unix time)\n", started);
+    let mut failures = 0;
+    for cmd_line in commands {
+        info!("Scheduled maintenance: {}", cmd_line);
+        let args = match command_args(cmd_line) {
+            Ok(args) => args,
+            Err(e) => {
+                failures += 1;
+                report.push_str(&format!("[FAILED] {}\n    {}\n", cmd_line, e));
+                continue;
+            }
+        };
+        let output = Command::new(exe)
+            .arg("--install-path")
+            .arg(install_path)
+            .args(&args)
+            .stdin(Stdio::null())
+            .output();
+        match output {
+            Ok(out) => {
+                let status = if out.status.success() { "OK".to_string() } else { failures += 1; format!("FAILED ({})", out.status) };
+                report.push_str(&format!("[{}] {}\n", status, cmd_line));
+                let text = format!("{}{}", String::from_utf8_lossy(&out.stdout), String::from_utf8_lossy(&out.stderr));
+                for line in text.lines() {
+                    report.push_str(&format!("    {}\n", line));
+                }
+            }
+            Err(e) => {
+                failures += 1;
+                report.push_str(&format!("[FAILED] {}\n    could not start: {}\n", cmd_line, e));
+            }
+        }
+    }
+    report.push_str(&format!("Finished at {} (unix time): {} command(s), {} failed\n", unix_timestamp(), commands.len(), failures));
+
+    let report_path = install_path.join(REPORT_FILE);
+    std::fs::write(&report_path, &report)?;
+    output::info(&format!("Maintenance report written to {}", report_path.display()));
+    if failures > 0 {
+        warn!("{} scheduled maintenance command(s) failed", failures);
+        return Err(PortableSourceError::command(format!(
+            "{} of {} scheduled maintenance command(s) failed; see {}",
+            failures,
+            commands.len(),
+            report_path.display()
+        )));
+    }
+    Ok(())
+}
+
+// ===== Windows: Task Scheduler =====
+
+#[cfg(windows)]
+fn register_task(exe: &Path, install_path: &Path, frequency: ScheduleFrequency) -> Result<ScheduleBackend> {
+    let task_run = format!("\"{}\" --install-path \"{}\" schedule run", exe.display(), install_path.display());
+    let mut args = vec!["/Create", "/F", "/TN", TASK_NAME, "/TR", task_run.as_str(), "/ST", "03:00"];
+    match frequency {
+        ScheduleFrequency::Daily => args.extend(["/SC", "DAILY"]),
+        ScheduleFrequency::Weekly => args.extend(["/SC", "WEEKLY", "/D", "SUN"]),
+    }
+    execute_command("schtasks", &args, None)?;
+    Ok(ScheduleBackend::TaskScheduler)
+}
+
+#[cfg(windows)]
+fn unregister_task(_backend: ScheduleBackend) -> Result<()> {
+    if is_command_available("schtasks") {
+        execute_command("schtasks", &["/Delete", "/F", "/TN", TASK_NAME], None)?;
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn is_task_registered(_backend: ScheduleBackend) -> bool {
+    execute_command("schtasks", &["/Query", "/TN", TASK_NAME], None).is_ok()
+}
+
+// ===== Linux: systemd user timer, cron as fallback =====
+
+#[cfg(unix)]
+fn systemd_user_available() -> bool {
+    is_command_available("systemctl")
+        && execute_command("systemctl", &["--user", "show-environment"], None).is_ok()
+}
+
+#[cfg(unix)]
+fn systemd_unit_dir() -> Result<PathBuf> {
+    dirs::config_dir()
+        .map(|d| d.join("systemd").join("user"))
+        .ok_or_else(|| PortableSourceError::environment("Cannot determine systemd user unit directory"))
+}
+
+#[cfg(unix)]
+fn register_task(exe: &Path, install_path: &Path, frequency: ScheduleFrequency) -> Result<ScheduleBackend> {
+    if systemd_user_available() {
+        let unit_dir = systemd_unit_dir()?;
+        std::fs::create_dir_all(&unit_dir)?;
+        let service = format!(
+            "[Unit]\nDescription=PortableSource maintenance\n\n[Service]\nType=oneshot\nExecStart={} --install-path {} schedule run\n",
+            systemd_word(exe),
+            systemd_word(install_path)
+        );
+        let timer = format!(
+            "[Unit]\nDescription=PortableSource maintenance timer\n\n[Timer]\nOnCalendar={}\nPersistent=true\n\n[Install]\nWantedBy=timers.target\n",
+            frequency.as_str()
+        );
+        atomic_write::write(unit_dir.join(format!("{}.service", UNIT_NAME)), service)?;
+        atomic_write::write(unit_dir.join(format!("{}.timer", UNIT_NAME)), timer)?;
+        execute_command("systemctl", &["--user", "daemon-reload"], None)?;
+        execute_command("systemctl", &["--user", "enable", "--now", &format!("{}.timer", UNIT_NAME)], None)?;
+        return Ok(ScheduleBackend::Systemd);
+    }
+
+    if is_command_available("crontab") {
+        let when = match frequency {
+            ScheduleFrequency::Daily => "0 3 * * *",
+            ScheduleFrequency::Weekly => "0 3 * * 0",
+        };
+        let mut lines = current_crontab_without_entry();
+        lines.push(cron_entry(when, exe, install_path));
+        write_crontab(&lines)?;
+        return Ok(ScheduleBackend::Cron);
+    }
+
+    Err(PortableSourceError::missing_dependency("systemd user session or crontab"))
+}
+
+/// `path` as one word of a systemd `ExecStart=` line, where `%` starts a specifier and `$` a
+/// variable
+#[cfg(unix)]
+fn systemd_word(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    format!("\"{}\"", path.replace('\\', "\\\\").replace('"', "\\\"").replace('%', "%%").replace('$', "$$"))
+}
+
+/// Crontab line running the maintenance; cron ends the command at an unescaped `%`
+#[cfg(unix)]
+fn cron_entry(when: &str, exe: &Path, install_path: &Path) -> String {
+    let word = |path: &Path| quote_unix(&path.to_string_lossy()).replace('%', "\\%");
+    format!("{} {} --install-path {} schedule run >/dev/null 2>&1 {}", when, word(exe), word(install_path), CRON_MARKER)
+}
+
+#[cfg(unix)]
+fn current_crontab_without_entry() -> Vec<String> {
+    // `crontab -l` fails when the user has no crontab yet
+    execute_command("crontab", &["-l"], None)
+        .unwrap_or_default()
+        .lines()
+        .filter(|l| !l.contains(CRON_MARKER))
+        .map(|l| l.to_string())
+        .collect()
+}
+
+#[cfg(unix)]
+fn write_crontab(lines: &[String]) -> Result<()> {
+    let tmp = std::env::temp_dir().join(format!("portablesource_crontab_{}", std::process::id()));
+    std::fs::write(&tmp, format!("{}\n", lines.join("\n")))?;
+    let result = execute_command("crontab", &[&tmp.to_string_lossy()], None);
+    let _ = std::fs::remove_file(&tmp);
+    result.map(|_| ())
+}
+
+#[cfg(unix)]
+fn unregister_task(backend: ScheduleBackend) -> Result<()> {
+    match backend {
+        ScheduleBackend::Systemd => {
+            let _ = execute_command("systemctl", &["--user", "disable", "--now", &format!("{}.timer", UNIT_NAME)], None);
+            let unit_dir = systemd_unit_dir()?;
+            for ext in ["service", "timer"] {
+                let unit = unit_dir.join(format!("{}.{}", UNIT_NAME, ext));
+                if unit.exists() {
+                    std::fs::remove_file(unit)?;
+                }
+            }
+            let _ = execute_command("systemctl", &["--user", "daemon-reload"], None);
+        }
+        ScheduleBackend::Cron => {
+            if is_command_available("crontab") {
+                write_crontab(&current_crontab_without_entry())?;
+            }
+        }
+        ScheduleBackend::TaskScheduler => {}
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn is_task_registered(backend: ScheduleBackend) -> bool {
+    match backend {
+        ScheduleBackend::Systemd => {
+            execute_command("systemctl", &["--user", "is-enabled", &format!("{}.timer", UNIT_NAME)], None).is_ok()
+        }
+        ScheduleBackend::Cron => {
+            execute_command("crontab", &["-l"], None)
+                .map(|c| c.contains(CRON_MARKER))
+                .unwrap_or(false)
+        }
+        ScheduleBackend::TaskScheduler => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quoted_semicolons_and_spaces_stay_in_their_argument() {
+        let commands = parse_command_list("update-repo comfyui; run-repo comfyui --args \"a; b\" ;");
+        assert_eq!(commands, vec!["update-repo comfyui", "run-repo comfyui --args \"a; b\""]);
+        assert_eq!(command_args(&commands[1]).unwrap(), vec!["run-repo", "comfyui", "--args", "a; b"]);
+        #[cfg(unix)]
+        assert_eq!(command_args(r"clean 'C:\Program Files\x'").unwrap(), vec!["clean", r"C:\Program Files\x"]);
+        #[cfg(windows)]
+        assert_eq!(command_args(r"clean C:\models").unwrap(), vec!["clean", r"C:\models"]);
+    }
+
+    #[test]
+    fn windows_splitting_keeps_backslashes_of_paths() {
+        assert_eq!(
+            split_windows(r#"install-repo C:\models "C:\My Models\\" say\"hi\" a""b"#).unwrap(),
+            vec!["install-repo", r"C:\models", r"C:\My Models\", r#"say"hi""#, "ab"]
+        );
+        assert_eq!(split_windows(r#"x "a""b""#).unwrap(), vec!["x", r#"a"b"#]);
+        assert!(split_windows(r#"run-repo "comfyui"#).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn scheduler_entries_survive_quotes_and_percent_signs_in_paths() {
+        let exe = Path::new("/opt/it's 100%/portablesource");
+        let install = Path::new("/data/ps");
+        assert_eq!(
+            cron_entry("0 3 * * *", exe, install),
+            format!("0 3 * * * '/opt/it'\\''s 100\\%/portablesource' --install-path /data/ps schedule run >/dev/null 2>&1 {}", CRON_MARKER)
+        );
+        assert_eq!(systemd_word(exe), r#""/opt/it's 100%%/portablesource""#);
+        assert_eq!(systemd_word(Path::new(r#"/a "b" $HOME\c"#)), r#""/a \"b\" $$HOME\\c""#);
+    }
+
+    #[test]
+    fn unbalanced_quotes_are_rejected() {
+        assert!(command_args("run-repo \"comfyui").is_err());
+    }
+
+    #[test]
+    fn entries_must_be_portablesource_commands_other_than_schedule() {
+        validate_command("update-repo --all").unwrap();
+        validate_command("--quiet update-repo comfyui --dry-run").unwrap();
+        let unknown = validate_command("update --all").unwrap_err().to_string();
+        assert!(unknown.contains("'update --all'") && unknown.contains("unrecognized subcommand"), "{}", unknown);
+        assert!(validate_command("update-repo --no-such-flag").is_err());
+        assert!(validate_command("schedule run").unwrap_err().to_string().contains("cannot change the schedule"));
+        assert!(validate_command("--quiet").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_failed_command_fails_the_run_and_is_reported() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let exe = dir.path().join("portablesource");
+        std::fs::write(&exe, "#!/bin/sh\nshift 2\necho \"args: $*\"\n[ \"$1\" != fail ]\n").unwrap();
+        std::fs::set_permissions(&exe, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let commands = vec!["check 'two words'".to_string(), "fail now".to_string()];
+        let err = run_commands(&exe, dir.path(), &commands).unwrap_err();
+        assert!(err.to_string().contains("1 of 2"), "{}", err);
+        let report = std::fs::read_to_string(dir.path().join(REPORT_FILE)).unwrap();
+        assert!(report.contains("[OK] check 'two words'\n    args: check two words\n"), "{}", report);
+        assert!(report.contains("[FAILED (exit status: 1)] fail now"), "{}", report);
+    }
+}
@@ -0,0 +1,305 @@
+//! Installs into a shared Python environment instead of a per-repository venv
+//!
+//! Some managed cloud notebooks make venv creation slow or forbid it. In Linux CLOUD mode
+//! `install-repo --no-venv` installs a repository's packages into the micromamba base
+//! (`ps_env/mamba_env`) or, with `--conda-env NAME`, into an existing conda environment.
+//! The choice is kept in `repos/<name>/.portablesource_env_target` and read by the
+//! installer and the start script. Repositories sharing an environment can overwrite each
+//! other's packages, so a ledger per environment (`envs/.shared_<key>.json`) remembers which
+//! repository installed which version and every install warns about versions it changed.
+
+use crate::cache_gc::{self, Distribution};
+use crate::{PortableSourceError, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const ENV_TARGET_FILE: &str = ".portablesource_env_target";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EnvTarget {
+    /// `ps_env/mamba_env`
+    MambaBase,
+    /// Existing conda environment, by name
+    Conda(String),
+}
+
+impl std::fmt::Display for EnvTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EnvTarget::MambaBase => f.write_str("micromamba base environment"),
+            EnvTarget::Conda(name) => write!(f, "conda environment '{}'", name),
+        }
+    }
+}
+
+impl EnvTarget {
+    /// Target selected by `--no-venv` / `--conda-env`; only Linux CLOUD mode allows one
+    pub fn from_flags(no_venv: bool, conda_env: Option<String>) -> Result<Option<Self>> {
+        if !no_venv {
+            return Ok(None);
+        }
+        #[cfg(unix)]
+        let cloud = crate::utils::detect_linux_mode() == crate::utils::LinuxMode::Cloud;
+        #[cfg(not(unix))]
+        let cloud = false;
+        if !cloud {
+            return Err(PortableSourceError::config(
+                "--no-venv is only available in Linux CLOUD mode (set PORTABLESOURCE_MODE=cloud)",
+            ));
+        }
+        Ok(Some(conda_env.map_or(EnvTarget::MambaBase, EnvTarget::Conda)))
+    }
+
+    pub fn load(repo_path: &Path) -> Result<Option<Self>> {
+        let path = repo_path.join(ENV_TARGET_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&path)?;
+        match content.trim() {
+            "base" => Ok(Some(EnvTarget::MambaBase)),
+            other => match other.strip_prefix("conda:") {
+                Some(name) if !name.is_empty() => Ok(Some(EnvTarget::Conda(name.to_string()))),
+                _ => Err(PortableSourceError::config(format!("Invalid environment target '{}' in {:?}", other, path))),
+            },
+        }
+    }
+
+    pub fn save(&self, repo_path: &Path) -> Result<()> {
+        let content = match self {
+            EnvTarget::MambaBase => "base".to_string(),
+            EnvTarget::Conda(name) => format!("conda:{}", name),
+        };
+        crate::atomic_write::write(repo_path.join(ENV_TARGET_FILE), content)
+    }
+
+    /// Prefix of the environment; it has to exist, nothing is created
+    pub fn prefix(&self, install_path: &Path) -> Result<PathBuf> {
+        let found = match self {
+            EnvTarget::MambaBase => Some(install_path.join("ps_env").join("mamba_env")).filter(|p| p.is_dir()),
+            EnvTarget::Conda(name) => conda_env_dirs().into_iter().map(|dir| dir.join(name)).find(|p| p.join("bin").is_dir()),
+        };
+        found.ok_or_else(|| PortableSourceError::environment(format!("The {} does not exist; run setup-env or create it first", self)))
+    }
+
+    pub fn python(&self, install_path: &Path) -> Result<PathBuf> {
+        Ok(self.prefix(install_path)?.join("bin").join("python"))
+    }
+
+    /// File name part of the ledger
+    fn key(&self) -> String {
+        match self {
+            EnvTarget::MambaBase => "base".to_string(),
+            EnvTarget::Conda(name) => format!("conda-{}", name),
+        }
+    }
+}
+
+/// Python environment of an installed repository: the shared environment it targets, else
+/// its own venv `envs/<name>`
+pub fn repo_env_prefix(install_path: &Path, repo: &str) -> Result<PathBuf> {
+    match EnvTarget::load(&install_path.join("repos").join(repo))? {
+        Some(target) => target.prefix(install_path),
+        None => Ok(install_path.join("envs").join(repo.to_lowercase())),
+    }
+}
+
+/// Interpreter of [`repo_env_prefix`]
+pub fn repo_python(install_path: &Path, repo: &str) -> Result<PathBuf> {
+    let prefix = repo_env_prefix(install_path, repo)?;
+    Ok(if cfg!(windows) { prefix.join("python.exe") } else { prefix.join("bin").join("python") })
+}
+
+/// Folders holding named conda environments, most specific first
+fn conda_env_dirs() -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = std::env::var("CONDA_ENVS_PATH")
+        .map(|paths| std::env::split_paths(&paths).collect())
+        .unwrap_or_default();
+    for root in ["MAMBA_ROOT_PREFIX", "CONDA_ROOT"].iter().filter_map(std::env::var_os) {
+        dirs.push(PathBuf::from(root).join("envs"));
+    }
+    // An activated environment: its siblings are the other environments
+    if let Some(active) = std::env::var_os("CONDA_PREFIX").map(PathBuf::from) {
+        dirs.extend(active.parent().map(Path::to_path_buf));
+        dirs.push(active.join("envs"));
+    }
+    if let Some(home) = dirs::home_dir() {
+        dirs.push(home.join(".conda").join("envs"));
+    }
+    dirs.push(PathBuf::from("/opt/conda/envs"));
+    dirs
+}
+
+/// Installed packages of an environment, normalized name to version
+pub fn packages(prefix: &Path) -> BTreeMap<String, String> {
+    cache_gc::site_packages_dirs(prefix)
+        .into_iter()
+        .flat_map(|dir| fs::read_dir(dir).into_iter().flatten().flatten())
+        .filter_map(|e| cache_gc::parse_dist_info(&e.file_name().to_string_lossy()))
+        .collect()
+}
+
+/// Shared environments used by installed repositories, for cache GC
+pub fn shared_distributions(install_path: &Path) -> Vec<Distribution> {
+    let mut targets: Vec<EnvTarget> = fs::read_dir(install_path.join("repos"))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|e| EnvTarget::load(&e.path()).ok().flatten())
+        .collect();
+    targets.dedup();
+    targets
+        .iter()
+        .filter_map(|t| t.prefix(install_path).ok())
+        .flat_map(|prefix| packages(&prefix))
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    pub version: String,
+    /// Repository whose install put this version there
+    pub repo: String,
+}
+
+/// A repository install replaced a version another repository installed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub package: String,
+    pub before: String,
+    pub after: String,
+    pub owner: String,
+}
+
+impl std::fmt::Display for Conflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} -> {} (installed for {})", self.package, self.before, self.after, self.owner)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Ledger {
+    pub packages: BTreeMap<String, LedgerEntry>,
+}
+
+impl Ledger {
+    pub fn path(install_path: &Path, target: &EnvTarget) -> PathBuf {
+        install_path.join("envs").join(format!(".shared_{}.json", target.key()))
+    }
+
+    pub fn load(install_path: &Path, target: &EnvTarget) -> Result<Self> {
+        let path = Self::path(install_path, target);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, install_path: &Path, target: &EnvTarget) -> Result<()> {
+        let path = Self::path(install_path, target);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        crate::atomic_write::write(path, serde_json::to_string_pretty(self)?)
+    }
+
+    /// Take over what `repo`'s install added or changed; returns versions it replaced that
+    /// another repository had installed
+    pub fn record(&mut self, repo: &str, before: &BTreeMap<String, String>, after: &BTreeMap<String, String>) -> Vec<Conflict> {
+        let mut conflicts = Vec::new();
+        for (package, version) in after {
+            let previous = before.get(package);
+            if previous == Some(version) {
+                continue;
+            }
+            if let (Some(previous), Some(entry)) = (previous, self.packages.get(package)) {
+                if entry.repo != repo {
+                    conflicts.push(Conflict {
+                        package: package.clone(),
+                        before: previous.clone(),
+                        after: version.clone(),
+                        owner: entry.repo.clone(),
+                    });
+                }
+            }
+            self.packages.insert(package.clone(), LedgerEntry { version: version.clone(), repo: repo.to_string() });
+        }
+        self.packages.retain(|package, _| after.contains_key(package));
+        conflicts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn env_target_is_saved_per_repository() {
+        let dir = tempfile::tempdir().unwrap();
+        EnvTarget::Conda("py311".into()).save(dir.path()).unwrap();
+        assert_eq!(EnvTarget::load(dir.path()).unwrap(), Some(EnvTarget::Conda("py311".into())));
+    }
+
+    #[test]
+    fn shared_target_resolves_to_the_mamba_python() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = dir.path().join("repos/comfyui");
+        fs::create_dir_all(&repo).unwrap();
+        fs::create_dir_all(dir.path().join("ps_env/mamba_env")).unwrap();
+        EnvTarget::MambaBase.save(&repo).unwrap();
+        let python = dir.path().join("ps_env/mamba_env/bin/python");
+        assert_eq!(EnvTarget::MambaBase.python(dir.path()).unwrap(), python);
+        assert_eq!(repo_python(dir.path(), "comfyui").unwrap(), python);
+    }
+
+    #[test]
+    fn repository_without_a_target_uses_its_own_venv() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(repo_env_prefix(dir.path(), "Forge").unwrap(), dir.path().join("envs/forge"));
+    }
+
+    #[test]
+    fn packages_are_read_from_dist_info_folders() {
+        let dir = tempfile::tempdir().unwrap();
+        let site = dir.path().join("ps_env/mamba_env/lib/python3.11/site-packages");
+        fs::create_dir_all(site.join("torch-2.3.1.dist-info")).unwrap();
+        let snapshot = packages(&EnvTarget::MambaBase.prefix(dir.path()).unwrap());
+        assert_eq!(snapshot, BTreeMap::from([("torch".to_string(), "2.3.1".to_string())]));
+    }
+
+    fn set(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs.iter().map(|(n, v)| (n.to_string(), v.to_string())).collect()
+    }
+
+    /// Ledger after comfyui installed torch 2.4.0 and numpy 1.26.4
+    fn comfyui_ledger() -> Ledger {
+        let mut ledger = Ledger::default();
+        assert!(ledger.record("comfyui", &set(&[]), &set(&[("torch", "2.4.0"), ("numpy", "1.26.4")])).is_empty());
+        ledger
+    }
+
+    #[test]
+    fn reinstalling_the_same_repository_is_not_a_conflict() {
+        let mut ledger = comfyui_ledger();
+        assert!(ledger.record("comfyui", &set(&[("torch", "2.4.0")]), &set(&[("torch", "2.5.0"), ("numpy", "1.26.4")])).is_empty());
+    }
+
+    #[test]
+    fn ledger_reports_versions_replaced_for_another_repository() {
+        let mut ledger = comfyui_ledger();
+        let before = set(&[("torch", "2.4.0"), ("numpy", "1.26.4")]);
+        let conflicts = ledger.record("forge", &before, &set(&[("torch", "2.1.2"), ("numpy", "1.26.4"), ("xformers", "0.0.23")]));
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].to_string(), "torch 2.4.0 -> 2.1.2 (installed for comfyui)");
+    }
+
+    #[test]
+    fn ledger_credits_changed_packages_to_the_new_repository() {
+        let mut ledger = comfyui_ledger();
+        ledger.record("forge", &set(&[("torch", "2.4.0"), ("numpy", "1.26.4")]), &set(&[("torch", "2.1.2"), ("numpy", "1.26.4")]));
+        assert_eq!(ledger.packages["torch"].repo, "forge");
+        assert_eq!(ledger.packages["numpy"].repo, "comfyui");
+    }
+}
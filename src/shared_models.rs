@@ -0,0 +1,217 @@
+//! Model folders shared between repositories
+//!
+//! `share-models` replaces a repository's model folders (ComfyUI `models/checkpoints`,
+//! webui `models/Stable-diffusion`, ...) with links into `shared_models/<kind>`, so one
+//! copy of a checkpoint serves every repository. Files already in the repository folder
+//! are moved into the shared folder first. On Windows a directory symlink is used when
+//! developer mode allows it, otherwise a junction (no admin rights needed); on Unix a
+//! symlink. Every link is verified after creation, and delete-repo removes the links
+//! before the repository so shared files are never deleted through them.
+
+use crate::models::{ModelKind, RepoLayout};
+use crate::{PortableSourceError, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub fn shared_models_dir(install_path: &Path) -> PathBuf {
+    install_path.join("shared_models")
+}
+
+/// Folder under `shared_models` for a kind of model
+pub fn shared_folder(kind: ModelKind) -> &'static str {
+    match kind {
+        ModelKind::Checkpoint => "checkpoints",
+        ModelKind::Lora => "loras",
+        ModelKind::Vae => "vae",
+        ModelKind::Controlnet => "controlnet",
+        ModelKind::Upscaler => "upscale_models",
+        ModelKind::Embedding => "embeddings",
+        ModelKind::Face => "face",
+        ModelKind::Other => "other",
+    }
+}
+
+/// Kinds whose folder in `layout` holds only that kind and can be shared
+pub fn shared_kinds(layout: RepoLayout) -> &'static [ModelKind] {
+    use ModelKind::*;
+    match layout {
+        RepoLayout::ComfyUi => &[Checkpoint, Lora, Vae, Controlnet, Upscaler, Embedding, Face],
+        RepoLayout::SdWebUi => &[Checkpoint, Lora, Vae, Controlnet, Upscaler, Embedding],
+        RepoLayout::FaceFusion => &[Face],
+        RepoLayout::Generic => &[],
+    }
+}
+
+/// Whether `path` is a symlink or junction (not followed)
+pub fn is_link(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink())
+}
+
+#[cfg(unix)]
+pub fn create_link(link: &Path, target: &Path) -> Result<&'static str> {
+    std::os::unix::fs::symlink(target, link)?;
+    Ok("symlink")
+}
+
+#[cfg(windows)]
+pub fn create_link(link: &Path, target: &Path) -> Result<&'static str> {
+    // Directory symlinks need developer mode (or admin); junctions work for any user
+    if std::os::windows::fs::symlink_dir(target, link).is_ok() {
+        return Ok("symlink");
+    }
+    let status = std::process::Command::new("cmd")
+        .args(["/C", "mklink", "/J"])
+        .arg(link)
+        .arg(target)
+        .stdout(std::process::Stdio::null())
+        .status()?;
+    if !status.success() {
+        return Err(PortableSourceError::permission_denied(format!("Cannot create junction {:?} -> {:?}", link, target)));
+    }
+    Ok("junction")
+}
+
+/// Remove a link without touching what it points to
+pub fn remove_link(path: &Path) -> Result<()> {
+    // Junctions and directory symlinks are directories to Windows, plain files to Unix
+    if cfg!(windows) {
+        fs::remove_dir(path)?;
+    } else {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// The link resolves to the shared folder
+pub fn verify_link(link: &Path, target: &Path) -> bool {
+    is_link(link)
+        && matches!((fs::canonicalize(link), fs::canonicalize(target)), (Ok(a), Ok(b)) if a == b)
+}
+
+/// Move files of `from` into `to`; names that already exist in `to` stay behind
+fn merge_into(from: &Path, to: &Path) -> Result<Vec<PathBuf>> {
+    let mut left = Vec::new();
+    for entry in fs::read_dir(from)?.flatten() {
+        let dest = to.join(entry.file_name());
+        if dest.exists() {
+            left.push(entry.path());
+        } else {
+            fs::rename(entry.path(), dest)?;
+        }
+    }
+    Ok(left)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkOutcome {
+    /// Newly linked, with the link type
+    Linked(&'static str),
+    AlreadyLinked,
+    /// Not linked: these files exist in both the repository and the shared folder
+    Conflicts(Vec<PathBuf>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FolderLink {
+    pub link: PathBuf,
+    pub target: PathBuf,
+    pub outcome: LinkOutcome,
+}
+
+/// Link every shareable model folder of a repository into the shared folders
+pub fn share_repo_models(install_path: &Path, repo_path: &Path, layout: RepoLayout) -> Result<Vec<FolderLink>> {
+    let shared = shared_models_dir(install_path);
+    let mut results = Vec::new();
+    for &kind in shared_kinds(layout) {
+        let link = repo_path.join(layout.folder(kind));
+        let target = shared.join(shared_folder(kind));
+        fs::create_dir_all(&target)?;
+
+        let outcome = if verify_link(&link, &target) {
+            LinkOutcome::AlreadyLinked
+        } else {
+            if is_link(&link) {
+                // Points somewhere else (moved install dir): relink
+                remove_link(&link)?;
+            } else if link.is_dir() {
+                let left = merge_into(&link, &target)?;
+                if !left.is_empty() {
+                    results.push(FolderLink { link, target, outcome: LinkOutcome::Conflicts(left) });
+                    continue;
+                }
+                fs::remove_dir(&link)?;
+            }
+            if let Some(parent) = link.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let kind = create_link(&link, &target)?;
+            if !verify_link(&link, &target) {
+                return Err(PortableSourceError::environment(format!("Link {:?} does not resolve to {:?}", link, target)));
+            }
+            LinkOutcome::Linked(kind)
+        };
+        results.push(FolderLink { link, target, outcome });
+    }
+    Ok(results)
+}
+
+/// Remove the shared-folder links of a repository; returns how many were removed
+pub fn unlink_repo_models(install_path: &Path, repo_path: &Path) -> Result<usize> {
+    let links = repo_model_links(install_path, repo_path);
+    for link in &links {
+        remove_link(link)?;
+    }
+    Ok(links.len())
+}
+
+/// Model folders of a repository that are links into shared_models (or dangling links)
+pub fn repo_model_links(install_path: &Path, repo_path: &Path) -> Vec<PathBuf> {
+    let shared = fs::canonicalize(shared_models_dir(install_path)).ok();
+    let layouts = [RepoLayout::ComfyUi, RepoLayout::SdWebUi, RepoLayout::FaceFusion];
+    let mut folders: Vec<&str> = layouts.iter().flat_map(|l| shared_kinds(*l).iter().map(|k| l.folder(*k))).collect();
+    folders.sort();
+    folders.dedup();
+    folders
+        .into_iter()
+        .map(|folder| repo_path.join(folder))
+        .filter(|link| is_link(link))
+        // Dangling links are removed too; live ones only if they point into shared_models
+        .filter(|link| match (fs::canonicalize(link), &shared) {
+            (Ok(resolved), Some(shared)) => resolved.starts_with(shared),
+            (Err(_), _) => true,
+            _ => false,
+        })
+        .collect()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folders_are_merged_linked_and_unlinked() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let comfy = root.join("repos/comfyui");
+        let webui = root.join("repos/webui");
+        fs::create_dir_all(comfy.join("models/checkpoints")).unwrap();
+        fs::write(comfy.join("models/checkpoints/sd15.safetensors"), b"a").unwrap();
+        fs::create_dir_all(webui.join("models/Stable-diffusion")).unwrap();
+        fs::write(webui.join("models/Stable-diffusion/sd15.safetensors"), b"a").unwrap();
+        fs::write(webui.join("models/Stable-diffusion/xl.safetensors"), b"b").unwrap();
+
+        let linked = share_repo_models(root, &comfy, RepoLayout::ComfyUi).unwrap();
+        assert!(linked.iter().all(|l| matches!(l.outcome, LinkOutcome::Linked(_))));
+        assert!(root.join("shared_models/checkpoints/sd15.safetensors").exists());
+        assert!(share_repo_models(root, &comfy, RepoLayout::ComfyUi).unwrap().iter().all(|l| l.outcome == LinkOutcome::AlreadyLinked));
+
+        // Same file name in both places: the folder is left alone, xl moved
+        let webui_links = share_repo_models(root, &webui, RepoLayout::SdWebUi).unwrap();
+        assert!(matches!(&webui_links[0].outcome, LinkOutcome::Conflicts(left) if left.len() == 1));
+        assert!(root.join("shared_models/checkpoints/xl.safetensors").exists());
+
+        assert_eq!(unlink_repo_models(root, &comfy).unwrap(), 7);
+        fs::remove_dir_all(&comfy).unwrap();
+        assert!(root.join("shared_models/checkpoints/sd15.safetensors").exists());
+    }
+}
@@ -0,0 +1,425 @@
+//! Side-effect boundaries: downloads, external commands and time
+//!
+//! Installer components reach the network, processes and the clock only through
+//! these traits, so tests can swap in the mocks from [`crate::testing`].
+
+use crate::envs_manager::PortableEnvironmentManager;
+use crate::{PortableSourceError, Result};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use crate::disk_space::SpaceCheck;
+use std::process::{Command, Output, Stdio};
+use futures_util::future::BoxFuture;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Fetches a URL into a local file (resuming partial downloads where supported)
+pub trait Downloader: Send + Sync {
+    fn download<'a>(&'a self, url: &'a str, destination: &'a Path) -> BoxFuture<'a, Result<()>>;
+}
+
+/// External command to run; `envs` are added on top of the inherited environment
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CommandRequest {
+    pub program: String,
+    pub args: Vec<String>,
+    pub cwd: Option<PathBuf>,
+    pub envs: HashMap<String, String>,
+    /// Kill the process if the volume holding this path runs out of space
+    pub watch_disk: Option<PathBuf>,
+    /// Kill the process if it has not exited after this long
+    pub timeout: Option<Duration>,
+    /// Log each output line at debug level as it arrives, not once the process exits
+    pub log_lines: bool,
+}
+
+impl CommandRequest {
+    /// Build from `[program, args...]`; None for an empty slice
+    pub fn from_args(args: &[String]) -> Option<Self> {
+        let (program, rest) = args.split_first()?;
+        Some(Self {
+            program: program.clone(),
+            args: rest.to_vec(),
+            ..Default::default()
+        })
+    }
+
+    pub fn cwd(mut self, cwd: Option<&Path>) -> Self {
+        self.cwd = cwd.map(|p| p.to_path_buf());
+        self
+    }
+
+    pub fn envs(mut self, envs: HashMap<String, String>) -> Self {
+        self.envs = envs;
+        self
+    }
+
+    pub fn watch_disk(mut self, path: Option<&Path>) -> Self {
+        self.watch_disk = path.map(|p| p.to_path_buf());
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn log_lines(mut self) -> Self {
+        self.log_lines = true;
+        self
+    }
+
+    /// Temp folder the process will use (TMPDIR/TEMP/TMP from `envs`, else the system one)
+    fn temp_dir(&self) -> PathBuf {
+        ["TMPDIR", "TEMP", "TMP"]
+            .iter()
+            .find_map(|name| self.envs.get(*name))
+            .map(PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir)
+    }
+
+    /// Program and arguments joined by spaces (for logs and test matching)
+    pub fn command_line(&self) -> String {
+        std::iter::once(self.program.as_str())
+            .chain(self.args.iter().map(|s| s.as_str()))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CommandOutput {
+    /// Exit code; None if the process was killed by a signal
+    pub code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl CommandOutput {
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+}
+
+/// Runs a command to completion and captures its output.
+/// Err only when the process could not be started; a non-zero exit is a normal output.
+pub trait CommandExecutor: Send + Sync {
+    fn execute(&self, request: &CommandRequest) -> Result<CommandOutput>;
+}
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+
+    fn unix_timestamp(&self) -> u64 {
+        self.now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+    }
+}
+
+/// Async HTTP client shared by all downloads, so parallel downloads reuse one connection pool
+pub fn http_client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(30))
+            .timeout(Duration::from_secs(600))
+            .build()
+            .expect("HTTP client with static settings")
+    })
+}
+
+/// Wait for `future` from synchronous installer code. On a worker of the multi-threaded
+/// runtime the worker hands its other tasks off first, so they keep running; without one
+/// (or on a current-thread runtime) the future runs on a runtime of its own thread
+pub fn block_on<F>(future: F) -> F::Output
+where
+    F: std::future::Future + Send,
+    F::Output: Send,
+{
+    use tokio::runtime::{Handle, RuntimeFlavor};
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(|| handle.block_on(future))
+        }
+        _ => std::thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    tokio::runtime::Builder::new_current_thread()
+                        .enable_all()
+                        .build()
+                        .expect("tokio runtime for a blocking call")
+                        .block_on(future)
+                })
+                .join()
+                .expect("blocking call panicked")
+        }),
+    }
+}
+
+static CTRL_C_DEFERRED: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+/// While alive, Ctrl+C does not end this process. The child it waits for gets the interrupt
+/// anyway (same console, same foreground process group), so the wait returns once the child
+/// has exited and the launcher can still clean up and record the run.
+pub struct CtrlCDeferred(());
+
+/// Leave Ctrl+C to the child until the guard drops; with no guard alive it exits with 130
+pub fn defer_ctrl_c() -> CtrlCDeferred {
+    use std::sync::atomic::Ordering;
+    static HANDLER: std::sync::Once = std::sync::Once::new();
+    HANDLER.call_once(|| {
+        let (ready, registered) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let Ok(runtime) = tokio::runtime::Builder::new_current_thread().enable_all().build() else {
+                return;
+            };
+            // Created (and so registered) before `defer_ctrl_c` returns
+            #[cfg(unix)]
+            let signal = runtime.block_on(async {
+                tokio::signal::unix::signal(tokio::signal::unix::SignalKind::interrupt())
+            });
+            #[cfg(windows)]
+            let signal = runtime.block_on(async { tokio::signal::windows::ctrl_c() });
+            let _ = ready.send(());
+            let Ok(mut signal) = signal else { return };
+            while runtime.block_on(signal.recv()).is_some() {
+                if CTRL_C_DEFERRED.load(Ordering::SeqCst) == 0 {
+                    std::process::exit(130);
+                }
+                tracing::debug!("Ctrl+C left to the running child");
+            }
+        });
+        let _ = registered.recv();
+    });
+    CTRL_C_DEFERRED.fetch_add(1, Ordering::SeqCst);
+    CtrlCDeferred(())
+}
+
+impl Drop for CtrlCDeferred {
+    fn drop(&mut self) {
+        CTRL_C_DEFERRED.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+/// Size a successful HEAD response announces. Read from the Content-Length header:
+/// `Response::content_length` is the length of the (empty) HEAD body
+pub fn head_content_length(response: &reqwest::Response) -> Option<u64> {
+    if !response.status().is_success() {
+        return None;
+    }
+    response.headers().get(reqwest::header::CONTENT_LENGTH)?.to_str().ok()?.trim().parse().ok()
+}
+
+/// HTTP downloads with resume support
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HttpDownloader;
+
+impl Downloader for HttpDownloader {
+    fn download<'a>(&'a self, url: &'a str, destination: &'a Path) -> BoxFuture<'a, Result<()>> {
+        Box::pin(PortableEnvironmentManager::download_with_resume(url, destination))
+    }
+}
+
+/// Real processes via `std::process::Command`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemExecutor;
+
+impl CommandExecutor for SystemExecutor {
+    fn execute(&self, request: &CommandRequest) -> Result<CommandOutput> {
+        let mut cmd = Command::new(&request.program);
+        cmd.args(&request.args).envs(&request.envs);
+        if let Some(dir) = &request.cwd {
+            cmd.current_dir(dir);
+        }
+
+        // Hide console window on Windows
+        #[cfg(windows)]
+        {
+            use std::os::windows::process::CommandExt;
+            cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
+        }
+
+        let output = if request.watch_disk.is_some() || request.timeout.is_some() || request.log_lines {
+            output_supervised(cmd, request)
+        } else {
+            cmd.output()
+        }
+        .map_err(|e| {
+            if e.kind() == std::io::ErrorKind::TimedOut {
+                PortableSourceError::Io(e)
+            } else if e.kind() == std::io::ErrorKind::PermissionDenied {
+                PortableSourceError::permission_denied(format!("{}: {}", request.program, e))
+            } else if e.kind() == std::io::ErrorKind::StorageFull {
+                PortableSourceError::disk_full(e.to_string())
+            } else {
+                PortableSourceError::command(format!("Failed to start {}: {}", request.program, e))
+            }
+        })?;
+        Ok(CommandOutput {
+            code: output.status.code(),
+            stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        })
+    }
+}
+
+/// `Command::output`, but the process is killed, and waited for, when free space under
+/// `watch_disk` runs low or `timeout` passes (an error of kind `TimedOut`), and with
+/// `log_lines` its output is logged while it runs. With a disk to
+/// watch the process gets a temp folder of its own, removed with what it left there (pip
+/// build folders and the like) before returning; the shared temp folder is never touched.
+fn output_supervised(mut cmd: Command, request: &CommandRequest) -> std::io::Result<Output> {
+    use std::io::{BufRead, BufReader, Read};
+    let temp = match request.watch_disk {
+        Some(_) => {
+            let temp = tempfile::Builder::new().prefix("portablesource-cmd-").tempdir_in(request.temp_dir())?;
+            for name in ["TMPDIR", "TEMP", "TMP"] {
+                cmd.env(name, temp.path());
+            }
+            Some(temp)
+        }
+        None => None,
+    };
+
+    let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    let log_lines = request.log_lines;
+    let drain = |name: &'static str, pipe: Option<Box<dyn Read + Send>>| {
+        std::thread::spawn(move || {
+            let mut buf = Vec::new();
+            let Some(pipe) = pipe else { return buf };
+            let mut reader = BufReader::new(pipe);
+            loop {
+                let start = buf.len();
+                match reader.read_until(b'\n', &mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) if log_lines => {
+                        tracing::debug!("[{}] {}", name, String::from_utf8_lossy(&buf[start..]).trim_end());
+                    }
+                    Ok(_) => {}
+                }
+            }
+            buf
+        })
+    };
+    let stdout = drain("stdout", child.stdout.take().map(|p| Box::new(p) as Box<dyn Read + Send>));
+    let stderr = drain("stderr", child.stderr.take().map(|p| Box::new(p) as Box<dyn Read + Send>));
+
+    let mut space = request.watch_disk.as_deref().map(SpaceCheck::new);
+    let started = std::time::Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if let Some(Err(full)) = space.as_mut().map(|space| space.check(None)) {
+            let _ = child.kill();
+            let _ = child.wait();
+            tracing::warn!("{} stopped: {}; removing its temp folder {:?}", request.program, full, temp.as_ref().map(|t| t.path()));
+            return Err(std::io::Error::new(std::io::ErrorKind::StorageFull, full.to_string()));
+        }
+        if let Some(timeout) = request.timeout.filter(|timeout| started.elapsed() >= *timeout) {
+            let _ = child.kill();
+            let _ = child.wait();
+            let message = format!("{} did not finish within {} s", request.program, timeout.as_secs_f32());
+            return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, message));
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    };
+    Ok(Output { status, stdout: stdout.join().unwrap_or_default(), stderr: stderr.join().unwrap_or_default() })
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// Bundle of side-effect providers handed to installer components
+#[derive(Clone)]
+pub struct Services {
+    pub downloader: Arc<dyn Downloader>,
+    pub executor: Arc<dyn CommandExecutor>,
+    pub clock: Arc<dyn Clock>,
+}
+
+impl Services {
+    /// Real network, processes and time
+    pub fn system() -> Self {
+        Self {
+            downloader: Arc::new(HttpDownloader),
+            executor: Arc::new(SystemExecutor),
+            clock: Arc::new(SystemClock),
+        }
+    }
+}
+
+impl Default for Services {
+    fn default() -> Self {
+        Self::system()
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watched_command_gets_a_private_temp_folder_that_is_removed() {
+        let shared = tempfile::tempdir().unwrap();
+        std::fs::write(shared.path().join("other-program.tmp"), b"").unwrap();
+        let request = CommandRequest {
+            program: "sh".into(),
+            args: vec!["-c".into(), "touch \"$TMPDIR/build-leftover\"; echo \"$TMPDIR\"".into()],
+            envs: HashMap::from([("TMPDIR".to_string(), shared.path().to_string_lossy().to_string())]),
+            watch_disk: Some(shared.path().to_path_buf()),
+            ..Default::default()
+        };
+        let output = SystemExecutor.execute(&request).unwrap();
+        let private = PathBuf::from(output.stdout.trim());
+        assert_eq!(private.parent(), Some(shared.path()));
+        assert!(!private.exists());
+        assert!(shared.path().join("other-program.tmp").exists());
+    }
+
+    #[test]
+    fn block_on_works_outside_and_inside_a_runtime() {
+        assert_eq!(block_on(async { 1 }), 1);
+        let current = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        assert_eq!(current.block_on(async { block_on(async { 2 }) }), 2);
+        let multi = tokio::runtime::Builder::new_multi_thread().worker_threads(1).build().unwrap();
+        assert_eq!(multi.block_on(async { tokio::spawn(async { block_on(async { 3 }) }).await.unwrap() }), 3);
+    }
+
+    #[test]
+    fn output_logged_while_running_is_still_captured() {
+        let script = "echo one; echo two >&2; printf three";
+        let request = CommandRequest::from_args(&["sh".into(), "-c".into(), script.into()]).unwrap().log_lines();
+        let output = SystemExecutor.execute(&request).unwrap();
+        assert_eq!((output.stdout.as_str(), output.stderr.as_str()), ("one\nthree", "two\n"));
+    }
+
+    #[test]
+    fn a_command_past_its_timeout_is_killed() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("finished");
+        let script = format!("sleep 2; touch '{}'", marker.display());
+        let request = CommandRequest::from_args(&["sh".into(), "-c".into(), script]).unwrap().timeout(Duration::from_millis(200));
+        let started = std::time::Instant::now();
+        let err = SystemExecutor.execute(&request).unwrap_err();
+        assert!(matches!(&err, PortableSourceError::Io(e) if e.kind() == std::io::ErrorKind::TimedOut), "{}", err);
+        assert!(started.elapsed() < Duration::from_secs(2));
+        // A leaked `sh` would still touch the marker
+        std::thread::sleep(Duration::from_millis(2500));
+        assert!(!marker.exists());
+    }
+
+    #[test]
+    fn ctrl_c_while_deferred_leaves_the_process_running() {
+        let guard = defer_ctrl_c();
+        unsafe { libc::kill(libc::getpid(), libc::SIGINT) };
+        // Without the handler the default action would end the test binary here
+        std::thread::sleep(Duration::from_millis(300));
+        drop(guard);
+    }
+}
@@ -0,0 +1,163 @@
+//! In-memory mocks of the [`crate::system`] traits for tests
+//!
+//! ```
+//! use portablesource_rs::testing::MockExecutor;
+//! use portablesource_rs::system::{CommandExecutor, CommandRequest};
+//!
+//! let exec = MockExecutor::new();
+//! exec.fail_on("git pull", "fatal: not a git repository");
+//! let req = CommandRequest::from_args(&["git".into(), "pull".into()]).unwrap();
+//! assert!(!exec.execute(&req).unwrap().success());
+//! assert_eq!(exec.command_lines(), vec!["git pull"]);
+//! ```
+
+use crate::system::{Clock, CommandExecutor, CommandOutput, CommandRequest, Downloader, Services};
+use crate::{PortableSourceError, Result};
+use futures_util::future::BoxFuture;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Serves registered URLs from memory; unknown URLs fail like a 404
+#[derive(Default)]
+pub struct MockDownloader {
+    files: Mutex<HashMap<String, Vec<u8>>>,
+    requests: Mutex<Vec<String>>,
+}
+
+impl MockDownloader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_file(self, url: &str, content: impl Into<Vec<u8>>) -> Self {
+        self.files.lock().unwrap().insert(url.to_string(), content.into());
+        self
+    }
+
+    /// URLs requested so far, in order
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Downloader for MockDownloader {
+    fn download<'a>(&'a self, url: &'a str, destination: &'a Path) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.requests.lock().unwrap().push(url.to_string());
+            let content = self.files.lock().unwrap().get(url).cloned()
+                .ok_or_else(|| PortableSourceError::network(format!("404 Not Found: {}", url)))?;
+            if let Some(parent) = destination.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(destination, content)?;
+            Ok(())
+        })
+    }
+}
+
+/// Records every command and answers from canned responses.
+/// The first rule whose pattern is a substring of the command line wins; otherwise the command succeeds.
+#[derive(Default)]
+pub struct MockExecutor {
+    rules: Mutex<Vec<(String, CommandOutput)>>,
+    calls: Mutex<Vec<CommandRequest>>,
+}
+
+impl MockExecutor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn respond(&self, pattern: &str, output: CommandOutput) -> &Self {
+        self.rules.lock().unwrap().push((pattern.to_string(), output));
+        self
+    }
+
+    pub fn succeed_with(&self, pattern: &str, stdout: &str) -> &Self {
+        self.respond(pattern, CommandOutput { code: Some(0), stdout: stdout.to_string(), stderr: String::new() })
+    }
+
+    pub fn fail_on(&self, pattern: &str, stderr: &str) -> &Self {
+        self.respond(pattern, CommandOutput { code: Some(1), stdout: String::new(), stderr: stderr.to_string() })
+    }
+
+    pub fn calls(&self) -> Vec<CommandRequest> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    pub fn command_lines(&self) -> Vec<String> {
+        self.calls().iter().map(|c| c.command_line()).collect()
+    }
+}
+
+impl CommandExecutor for MockExecutor {
+    fn execute(&self, request: &CommandRequest) -> Result<CommandOutput> {
+        self.calls.lock().unwrap().push(request.clone());
+        let line = request.command_line();
+        let rules = self.rules.lock().unwrap();
+        Ok(rules
+            .iter()
+            .find(|(pattern, _)| line.contains(pattern.as_str()))
+            .map(|(_, output)| output.clone())
+            .unwrap_or(CommandOutput { code: Some(0), ..Default::default() }))
+    }
+}
+
+/// Manually advanced clock
+pub struct MockClock {
+    secs: AtomicU64,
+}
+
+impl MockClock {
+    pub fn at(unix_secs: u64) -> Self {
+        Self { secs: AtomicU64::new(unix_secs) }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.secs.fetch_add(by.as_secs(), Ordering::SeqCst);
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.secs.load(Ordering::SeqCst))
+    }
+}
+
+/// Mocks wired into a [`Services`] bundle, with handles kept for assertions
+pub struct MockServices {
+    pub downloader: Arc<MockDownloader>,
+    pub executor: Arc<MockExecutor>,
+    pub clock: Arc<MockClock>,
+}
+
+impl MockServices {
+    pub fn new() -> Self {
+        Self::with_downloader(MockDownloader::new())
+    }
+
+    pub fn with_downloader(downloader: MockDownloader) -> Self {
+        Self {
+            downloader: Arc::new(downloader),
+            executor: Arc::new(MockExecutor::new()),
+            clock: Arc::new(MockClock::at(1_700_000_000)),
+        }
+    }
+
+    pub fn services(&self) -> Services {
+        Services {
+            downloader: self.downloader.clone(),
+            executor: self.executor.clone(),
+            clock: self.clock.clone(),
+        }
+    }
+}
+
+impl Default for MockServices {
+    fn default() -> Self {
+        Self::new()
+    }
+}
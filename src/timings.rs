@@ -0,0 +1,186 @@
+//! Operation timings collected from tracing spans
+//!
+//! Major operations (download, extract, clone, venv creation, requirements install) run
+//! inside tracing spans. [`TimingLayer`] measures every closed span; at the end of a command
+//! the report is written to `logs/last_timings.json` under the install path, and
+//! `doctor --timings` prints it as a tree.
+
+use crate::{atomic_write, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::Subscriber;
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+pub const TIMINGS_FILE: &str = "last_timings.json";
+
+/// One finished span
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SpanTiming {
+    pub name: String,
+    /// Span fields, e.g. `repo=comfyui`
+    #[serde(default)]
+    pub detail: String,
+    /// Nesting level, 0 for top-level operations
+    pub depth: usize,
+    /// Milliseconds since the first span of the command started
+    pub start_ms: u64,
+    pub elapsed_ms: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TimingReport {
+    pub command: String,
+    pub spans: Vec<SpanTiming>,
+}
+
+struct Collector {
+    origin: Option<Instant>,
+    spans: Vec<SpanTiming>,
+}
+
+fn collector() -> &'static Mutex<Collector> {
+    static COLLECTOR: OnceLock<Mutex<Collector>> = OnceLock::new();
+    COLLECTOR.get_or_init(|| Mutex::new(Collector { origin: None, spans: Vec::new() }))
+}
+
+/// Span start time and fields, kept in the span's extensions until it closes
+struct Started {
+    at: Instant,
+    detail: String,
+}
+
+#[derive(Default)]
+struct DetailVisitor(String);
+
+impl Visit for DetailVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if !self.0.is_empty() {
+            self.0.push(' ');
+        }
+        let _ = write!(self.0, "{}={:?}", field.name(), value);
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if !self.0.is_empty() {
+            self.0.push(' ');
+        }
+        let _ = write!(self.0, "{}={}", field.name(), value);
+    }
+}
+
+/// Layer recording the wall time of every span
+pub struct TimingLayer;
+
+impl<S> Layer<S> for TimingLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+        let mut visitor = DetailVisitor::default();
+        attrs.record(&mut visitor);
+        let at = Instant::now();
+        collector().lock().unwrap().origin.get_or_insert(at);
+        span.extensions_mut().insert(Started { at, detail: visitor.0 });
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else { return };
+        let depth = span.scope().skip(1).count();
+        let extensions = span.extensions();
+        let Some(started) = extensions.get::<Started>() else { return };
+        let mut collector = collector().lock().unwrap();
+        let origin = *collector.origin.get_or_insert(started.at);
+        collector.spans.push(SpanTiming {
+            name: span.name().to_string(),
+            detail: started.detail.clone(),
+            depth,
+            start_ms: started.at.saturating_duration_since(origin).as_millis() as u64,
+            elapsed_ms: started.at.elapsed().as_millis() as u64,
+        });
+    }
+}
+
+pub fn timings_path(install_path: &Path) -> PathBuf {
+    install_path.join("logs").join(TIMINGS_FILE)
+}
+
+/// Write the spans collected so far; nothing is written when no span ran
+pub fn save(install_path: &Path, command: &str) -> Result<bool> {
+    let mut spans = std::mem::take(&mut collector().lock().unwrap().spans);
+    if spans.is_empty() {
+        return Ok(false);
+    }
+    spans.sort_by_key(|s| (s.start_ms, s.depth));
+    let report = TimingReport { command: command.to_string(), spans };
+    let path = timings_path(install_path);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    atomic_write::write(&path, serde_json::to_string_pretty(&report)?.as_bytes())?;
+    Ok(true)
+}
+
+pub fn load(install_path: &Path) -> Result<Option<TimingReport>> {
+    let path = timings_path(install_path);
+    if !path.exists() {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_str(&std::fs::read_to_string(path)?)?))
+}
+
+/// Indented tree, slowest operations easy to spot by their share of the total
+pub fn render(report: &TimingReport) -> String {
+    let total = report.spans.iter().filter(|s| s.depth == 0).map(|s| s.elapsed_ms).sum::<u64>().max(1);
+    let mut out = format!("Timings of '{}':\n", report.command);
+    for span in &report.spans {
+        let label = if span.detail.is_empty() { span.name.clone() } else { format!("{} ({})", span.name, span.detail) };
+        let _ = writeln!(
+            out,
+            "  {}{:<w$} {:>9} {:>4}%",
+            "  ".repeat(span.depth),
+            label,
+            format_ms(span.elapsed_ms),
+            span.elapsed_ms * 100 / total,
+            w = 60usize.saturating_sub(span.depth * 2),
+        );
+    }
+    out
+}
+
+fn format_ms(ms: u64) -> String {
+    if ms >= 60_000 {
+        format!("{}m{:02}s", ms / 60_000, (ms % 60_000) / 1000)
+    } else {
+        format!("{:.1}s", ms as f64 / 1000.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn nested_spans_are_timed_with_depth() {
+        let subscriber = tracing_subscriber::registry().with(TimingLayer);
+        tracing::subscriber::with_default(subscriber, || {
+            let _install = tracing::info_span!("install_repo", repo = "demo").entered();
+            let _clone = tracing::info_span!("clone").entered();
+        });
+        let dir = tempfile::tempdir().unwrap();
+        assert!(save(dir.path(), "install-repo demo").unwrap());
+
+        let report = load(dir.path()).unwrap().unwrap();
+        let names: Vec<_> = report.spans.iter().map(|s| (s.name.as_str(), s.depth)).collect();
+        assert_eq!(names, [("install_repo", 0), ("clone", 1)]);
+        assert_eq!(report.spans[0].detail, "repo=demo");
+        assert!(render(&report).contains("install_repo (repo=demo)"));
+    }
+}
@@ -0,0 +1,223 @@
+//! Which executable a repository gets for a tool (`portablesource which`)
+//!
+//! The start script decides: it runs the interpreter of the venv (or of the shared
+//! environment) directly and puts its own folders in front of PATH, in the order the script
+//! generator's `WINDOWS_PATH_ORDER` / `UNIX_PATH_ORDER` give. The folders are listed here in
+//! the order the script leaves them on PATH, followed by the inherited PATH, so every
+//! candidate is shown and the first one is what the repository runs.
+
+use crate::config::ConfigManager;
+use crate::installer::script_generator::{PathDir, UNIX_PATH_ORDER, WINDOWS_CUDA_DIRS, WINDOWS_PATH_ORDER};
+use crate::shared_env::EnvTarget;
+use crate::{PortableSourceError, Result};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    pub path: PathBuf,
+    /// Where the start script gets it from, e.g. "repository venv"
+    pub source: String,
+}
+
+/// What the start script of one repository adds to PATH
+#[derive(Debug, Clone, Default)]
+pub struct SearchContext {
+    pub install_path: PathBuf,
+    /// Venv folder name (the repository folder name)
+    pub venv_name: String,
+    /// CUDA toolkit folder, when CUDA is configured
+    pub cuda: Option<PathBuf>,
+    /// Folder of the system git chosen with `--use-system-tools` (Windows)
+    pub system_git: Option<PathBuf>,
+    /// DLL folders of the repository's native library components (Windows)
+    pub components: Vec<PathBuf>,
+    /// Shared environment used instead of a venv (`--no-venv`)
+    pub shared_prefix: Option<PathBuf>,
+    /// Linux DESK mode: the micromamba base is on PATH
+    pub desk_mode: bool,
+}
+
+impl SearchContext {
+    pub fn for_repo(install_path: &Path, repo: &str, config_manager: &ConfigManager) -> Result<Self> {
+        let repo_path = install_path.join("repos").join(repo);
+        if !repo_path.exists() {
+            return Err(PortableSourceError::repository(format!("Repository '{}' not installed", repo)));
+        }
+        let settings = crate::run_queue::RepoRunSettings::load(&repo_path)?;
+        Ok(Self {
+            install_path: install_path.to_path_buf(),
+            venv_name: repo.to_string(),
+            cuda: config_manager.get_cuda_base_path(),
+            system_git: config_manager.system_tool("git").and_then(Path::parent).map(Path::to_path_buf),
+            components: settings
+                .components
+                .iter()
+                .filter_map(|name| crate::components::find(name))
+                .map(|component| component.dll_path(install_path))
+                .collect(),
+            shared_prefix: EnvTarget::load(&repo_path)?.and_then(|target| target.prefix(install_path).ok()),
+            desk_mode: desk_mode(),
+        })
+    }
+
+    fn venv(&self) -> PathBuf {
+        self.install_path.join("envs").join(&self.venv_name)
+    }
+
+    /// Interpreter the start script launches the repository with
+    pub fn launch_python(&self) -> (PathBuf, &'static str) {
+        match &self.shared_prefix {
+            Some(prefix) if !cfg!(windows) => (prefix.join("bin").join("python"), "shared environment (--no-venv)"),
+            _ if cfg!(windows) => (self.venv().join("python.exe"), "repository venv"),
+            _ => (self.venv().join("bin").join("python"), "repository venv"),
+        }
+    }
+
+    /// Folders the start script puts in front of PATH, first searched first
+    pub fn script_dirs(&self) -> Vec<(PathBuf, String)> {
+        let order: &[PathDir] = if cfg!(windows) { &WINDOWS_PATH_ORDER } else { &UNIX_PATH_ORDER };
+        let ps_env = self.install_path.join("ps_env");
+        let mut dirs = Vec::new();
+        // The script prepends each folder, so the last one it adds is searched first
+        for dir in order.iter().rev() {
+            match dir {
+                PathDir::Cuda => {
+                    let Some(cuda) = &self.cuda else { continue };
+                    if cfg!(windows) {
+                        for (_, sub) in WINDOWS_CUDA_DIRS.iter().rev() {
+                            dirs.push((cuda.join(sub), "portable CUDA".to_string()));
+                        }
+                    } else {
+                        dirs.push((cuda.join("bin"), "portable CUDA".to_string()));
+                    }
+                }
+                PathDir::Components => {
+                    for dir in self.components.iter().rev() {
+                        dirs.push((dir.clone(), "native library component".to_string()));
+                    }
+                }
+                PathDir::Venv if cfg!(windows) => dirs.push((self.venv(), "repository venv".to_string())),
+                // The script only activates a venv that has an activate script
+                PathDir::Venv => {
+                    if self.venv().join("bin").join("activate").is_file() {
+                        dirs.push((self.venv().join("bin"), "repository venv".to_string()));
+                    }
+                }
+                PathDir::VenvScripts => dirs.push((self.venv().join("Scripts"), "repository venv".to_string())),
+                PathDir::Git => match &self.system_git {
+                    Some(dir) => dirs.push((dir.clone(), "system git (--use-system-tools)".to_string())),
+                    None => dirs.push((ps_env.join("git").join("bin"), "portable git".to_string())),
+                },
+                PathDir::Ffmpeg => dirs.push((ps_env.join("ffmpeg"), "portable ffmpeg".to_string())),
+                PathDir::SharedEnv => {
+                    if let Some(prefix) = &self.shared_prefix {
+                        dirs.push((prefix.join("bin"), "shared environment (--no-venv)".to_string()));
+                    }
+                }
+                PathDir::MambaBase => {
+                    if self.desk_mode {
+                        dirs.push((ps_env.join("mamba_env").join("bin"), "micromamba base (DESK mode)".to_string()));
+                    }
+                }
+            }
+        }
+        dirs
+    }
+
+    /// Every executable for `tool`, the one the repository uses first; `path` is the
+    /// PATH the start script inherits
+    pub fn candidates(&self, tool: &str, path: Option<OsString>) -> Vec<Candidate> {
+        let mut found: Vec<Candidate> = Vec::new();
+        let mut push = |path: PathBuf, source: String| {
+            if path.is_file() && !found.iter().any(|c| c.path == path) {
+                found.push(Candidate { path, source });
+            }
+        };
+        if tool == "python" {
+            let (python, source) = self.launch_python();
+            push(python, format!("{}, runs the repository", source));
+        }
+        let names = executable_names(tool);
+        let inherited = path.map(|p| std::env::split_paths(&p).collect::<Vec<_>>()).unwrap_or_default();
+        let dirs = self
+            .script_dirs()
+            .into_iter()
+            .chain(inherited.into_iter().map(|dir| (dir, "PATH".to_string())));
+        for (dir, source) in dirs {
+            for name in &names {
+                push(dir.join(name), source.clone());
+            }
+        }
+        found
+    }
+}
+
+fn executable_names(tool: &str) -> Vec<String> {
+    let names: &[&str] = match tool {
+        "python" if !cfg!(windows) => &["python", "python3"],
+        _ => &[tool],
+    };
+    names
+        .iter()
+        .map(|name| if cfg!(windows) { format!("{}.exe", name) } else { name.to_string() })
+        .collect()
+}
+
+/// Mode the Linux start script picks: `PORTABLESOURCE_MODE`, else CLOUD when git, python3
+/// and ffmpeg are all on PATH
+pub fn desk_mode() -> bool {
+    if cfg!(windows) {
+        return false;
+    }
+    match std::env::var("PORTABLESOURCE_MODE") {
+        Ok(mode) if !mode.is_empty() => mode == "desk",
+        _ => !["git", "python3", "ffmpeg"].iter().all(|tool| which::which(tool).is_ok()),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn script_folders_come_before_path_in_script_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let install = dir.path().join("install");
+        let venv_bin = install.join("envs/comfyui/bin");
+        let base_bin = install.join("ps_env/mamba_env/bin");
+        let cuda_bin = install.join("ps_env/CUDA/bin");
+        let system = dir.path().join("usr/bin");
+        for bin in [&venv_bin, &base_bin, &cuda_bin, &system] {
+            fs::create_dir_all(bin).unwrap();
+        }
+        fs::write(venv_bin.join("activate"), "").unwrap();
+        fs::write(venv_bin.join("python"), "").unwrap();
+        fs::write(base_bin.join("python3"), "").unwrap();
+        fs::write(base_bin.join("ffmpeg"), "").unwrap();
+        fs::write(cuda_bin.join("nvcc"), "").unwrap();
+        fs::write(system.join("ffmpeg"), "").unwrap();
+        fs::write(system.join("nvcc"), "").unwrap();
+
+        let ctx = SearchContext {
+            install_path: install.clone(),
+            venv_name: "comfyui".into(),
+            cuda: Some(install.join("ps_env/CUDA")),
+            desk_mode: true,
+            ..Default::default()
+        };
+        let path = Some(system.clone().into_os_string());
+        let python = ctx.candidates("python", path.clone());
+        assert_eq!(python[0].path, venv_bin.join("python"));
+        assert_eq!(python[0].source, "repository venv, runs the repository");
+        assert_eq!(python[1].path, base_bin.join("python3"));
+
+        let ffmpeg = ctx.candidates("ffmpeg", path.clone());
+        assert_eq!(ffmpeg.iter().map(|c| c.path.clone()).collect::<Vec<_>>(), [base_bin.join("ffmpeg"), system.join("ffmpeg")]);
+        assert_eq!(ctx.candidates("nvcc", path.clone())[0].path, cuda_bin.join("nvcc"));
+
+        let cloud = SearchContext { desk_mode: false, ..ctx };
+        assert_eq!(cloud.candidates("ffmpeg", path)[0].source, "PATH");
+    }
+}
@@ -0,0 +1,285 @@
+//! Side-by-side versions of portable tools
+//!
+//! `upgrade-tool` never changes a tool folder that repositories may be using. Each version
+//! lives in its own slot, `ps_env/<tool>@<version>` (`python@3.11.10`, `CUDA@12.8`), and
+//! `ps_env/<tool>` becomes a link to the current one, so every path recorded in start
+//! scripts and environments stays valid. A new version is unpacked and verified in its own
+//! slot, then the link is switched in one step and checked again; if that check fails the
+//! link goes back to the previous slot. The previous slot is kept for `--rollback`, older
+//! ones are removed. A tool folder from before slots is adopted on its first upgrade.
+//!
+//! Windows junctions hold an absolute target and cannot be replaced in one step. A switch
+//! there is journaled first, and [`repair`] (run at startup) finishes an interrupted switch,
+//! re-links junctions that still point into an install that was moved or restored elsewhere,
+//! and removes upgrade downloads a killed process left behind.
+
+use crate::shared_models::{create_link, is_link, remove_link};
+use crate::{PortableSourceError, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Folder name of a tool version: `python@3.11.10`
+pub fn slot_name(tool_dir: &str, version: &str) -> String {
+    format!("{}@{}", tool_dir, version)
+}
+
+/// Slot the `ps_env/<tool_dir>` link points to; None when it is missing or a plain folder
+pub fn current_slot(ps_env: &Path, tool_dir: &str) -> Option<String> {
+    let link = ps_env.join(tool_dir);
+    if !is_link(&link) {
+        return None;
+    }
+    let target = fs::read_link(&link).ok()?;
+    target.file_name().map(|n| n.to_string_lossy().to_string())
+}
+
+/// Numeric parts of the version in a slot name, for ordering: `python@3.11.10` > `python@3.11.9`;
+/// a slot without a version (`python@previous`) sorts last
+fn version_key(slot: &str) -> Vec<u64> {
+    let version = slot.rsplit_once('@').map(|(_, v)| v).unwrap_or("");
+    version.split(|c: char| !c.is_ascii_digit()).filter_map(|part| part.parse().ok()).collect()
+}
+
+/// Slots of a tool, newest version first
+pub fn slots(ps_env: &Path, tool_dir: &str) -> Vec<String> {
+    let prefix = format!("{}@", tool_dir);
+    let mut found: Vec<String> = fs::read_dir(ps_env)
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+                .map(|e| e.file_name().to_string_lossy().to_string())
+                .filter(|name| name.starts_with(&prefix))
+                .collect()
+        })
+        .unwrap_or_default();
+    found.sort_by(|a, b| version_key(b).cmp(&version_key(a)).then_with(|| b.cmp(a)));
+    found
+}
+
+/// The newest slot other than the current one, which `--rollback` returns to
+pub fn previous_slot(ps_env: &Path, tool_dir: &str) -> Option<String> {
+    let current = current_slot(ps_env, tool_dir);
+    slots(ps_env, tool_dir).into_iter().find(|s| Some(s) != current.as_ref())
+}
+
+/// Move a plain `ps_env/<tool_dir>` folder into the slot for `version` and link it back
+pub fn adopt(ps_env: &Path, tool_dir: &str, version: &str) -> Result<String> {
+    let dir = ps_env.join(tool_dir);
+    let slot = slot_name(tool_dir, version);
+    let slot_path = ps_env.join(&slot);
+    if slot_path.exists() {
+        fs::remove_dir_all(&slot_path)?;
+    }
+    fs::rename(&dir, &slot_path).map_err(|e| {
+        PortableSourceError::environment(format!(
+            "Cannot move {} to {} ({}); close running repositories and try again",
+            dir.display(),
+            slot_path.display(),
+            e
+        ))
+    })?;
+    if let Err(e) = link_to(ps_env, tool_dir, &slot) {
+        // Put the folder back where everything expects it
+        let _ = fs::rename(&slot_path, &dir);
+        return Err(e);
+    }
+    Ok(slot)
+}
+
+fn link_target(ps_env: &Path, slot: &str) -> PathBuf {
+    // Junctions need an absolute target; a relative symlink survives moving the install
+    if cfg!(windows) { ps_env.join(slot) } else { PathBuf::from(slot) }
+}
+
+fn link_to(ps_env: &Path, tool_dir: &str, slot: &str) -> Result<()> {
+    create_link(&ps_env.join(tool_dir), &link_target(ps_env, slot)).map(|_| ())
+}
+
+/// Point `ps_env/<tool_dir>` at `slot`. On Unix the new link replaces the old one with a
+/// single rename; Windows cannot rename over a junction, so the old link is removed first
+/// and restored if the new one cannot be created
+pub fn switch(ps_env: &Path, tool_dir: &str, slot: &str) -> Result<()> {
+    let link = ps_env.join(tool_dir);
+    if !ps_env.join(slot).is_dir() {
+        return Err(PortableSourceError::environment(format!("{} does not exist", ps_env.join(slot).display())));
+    }
+    if link.exists() && !is_link(&link) {
+        return Err(PortableSourceError::environment(format!("{} is a folder, not a link to a version", link.display())));
+    }
+    if cfg!(windows) {
+        let previous = current_slot(ps_env, tool_dir);
+        let journal = journal_path(ps_env, tool_dir);
+        crate::atomic_write::write(&journal, slot)?;
+        if is_link(&link) {
+            remove_link(&link)?;
+        }
+        if let Err(e) = link_to(ps_env, tool_dir, slot) {
+            if let Some(previous) = previous {
+                let _ = link_to(ps_env, tool_dir, &previous);
+            }
+            let _ = fs::remove_file(&journal);
+            return Err(e);
+        }
+        fs::remove_file(&journal)?;
+        return Ok(());
+    }
+    let staged = ps_env.join(format!(".{}.link", tool_dir));
+    if is_link(&staged) {
+        remove_link(&staged)?;
+    }
+    create_link(&staged, &link_target(ps_env, slot))?;
+    fs::rename(&staged, &link)?;
+    Ok(())
+}
+
+/// Names the slot a Windows switch is moving `ps_env/<tool_dir>` to, while the link is replaced
+fn journal_path(ps_env: &Path, tool_dir: &str) -> PathBuf {
+    ps_env.join(format!(".{}.switch", tool_dir))
+}
+
+/// Staging folder and archive of `upgrade-tool`
+pub fn upgrade_staging(ps_env: &Path, tool_dir: &str) -> (PathBuf, PathBuf) {
+    let name = tool_dir.to_lowercase();
+    (ps_env.join(format!("__{}_upgrade__", name)), ps_env.join(format!("{}_upgrade.tar.zst", name)))
+}
+
+/// Put tool links and upgrade leftovers in order; returns what was done. Upgrade downloads
+/// younger than `min_age` may belong to a running upgrade and are kept
+pub fn repair(ps_env: &Path, min_age: Duration) -> Vec<String> {
+    let mut done = Vec::new();
+    let now = SystemTime::now();
+    for entry in fs::read_dir(ps_env).into_iter().flatten().flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let path = entry.path();
+        if let Some(tool_dir) = name.strip_prefix('.').and_then(|n| n.strip_suffix(".switch")) {
+            // Interrupted Windows switch: the slot was verified before the journal was written
+            let slot = fs::read_to_string(&path).unwrap_or_default().trim().to_string();
+            let link = ps_env.join(tool_dir);
+            if !link.exists() && !is_link(&link) && !slot.is_empty() && ps_env.join(&slot).is_dir() && link_to(ps_env, tool_dir, &slot).is_ok() {
+                done.push(format!("finished switching {} to {}", tool_dir, slot));
+            }
+            let _ = fs::remove_file(&path);
+        } else if is_link(&path) && !path.exists() {
+            // Absolute junction into the old location of a moved install
+            let Some(slot) = fs::read_link(&path).ok().and_then(|t| t.file_name().map(|n| n.to_string_lossy().to_string())) else { continue };
+            if slot.starts_with(&format!("{}@", name)) && ps_env.join(&slot).is_dir() && remove_link(&path).is_ok() {
+                match link_to(ps_env, &name, &slot) {
+                    Ok(()) => done.push(format!("re-linked {} to {}", name, slot)),
+                    Err(e) => tracing::warn!("Cannot re-link {:?} to {}: {}", path, slot, e),
+                }
+            }
+        } else if (name.starts_with("__") && name.ends_with("_upgrade__")) || name.ends_with("_upgrade.tar.zst") {
+            let age = entry.metadata().and_then(|m| m.modified()).ok().and_then(|m| now.duration_since(m).ok());
+            if age.is_some_and(|age| age >= min_age) {
+                let removed = if path.is_dir() { fs::remove_dir_all(&path) } else { fs::remove_file(&path) };
+                if removed.is_ok() {
+                    done.push(format!("removed interrupted upgrade download {}", name));
+                }
+            }
+        }
+    }
+    done
+}
+
+/// Remove every slot except `keep`; slots still in use (Windows) are left for next time
+pub fn prune(ps_env: &Path, tool_dir: &str, keep: &[&str]) -> Vec<String> {
+    let mut removed = Vec::new();
+    for slot in slots(ps_env, tool_dir) {
+        if keep.contains(&slot.as_str()) {
+            continue;
+        }
+        match fs::remove_dir_all(ps_env.join(&slot)) {
+            Ok(()) => removed.push(slot),
+            Err(e) => tracing::warn!("Cannot remove old {}: {}", slot, e),
+        }
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exe(ps_env: &Path, dir: &str) -> String {
+        fs::read_to_string(ps_env.join(dir).join("python.exe")).unwrap()
+    }
+
+    fn slot_with(ps_env: &Path, slot: &str, content: &str) {
+        fs::create_dir_all(ps_env.join(slot)).unwrap();
+        fs::write(ps_env.join(slot).join("python.exe"), content).unwrap();
+    }
+
+    #[test]
+    fn plain_tool_folder_is_adopted_into_a_slot() {
+        let dir = tempfile::tempdir().unwrap();
+        let ps_env = dir.path();
+        fs::create_dir_all(ps_env.join("python")).unwrap();
+        fs::write(ps_env.join("python/python.exe"), "old").unwrap();
+
+        assert_eq!(current_slot(ps_env, "python"), None);
+        assert_eq!(adopt(ps_env, "python", "3.11.9").unwrap(), "python@3.11.9");
+        assert_eq!(current_slot(ps_env, "python").as_deref(), Some("python@3.11.9"));
+        assert_eq!(exe(ps_env, "python"), "old");
+    }
+
+    #[test]
+    fn switching_moves_the_link_and_rolls_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let ps_env = dir.path();
+        slot_with(ps_env, "python@3.11.9", "old");
+        link_to(ps_env, "python", "python@3.11.9").unwrap();
+        slot_with(ps_env, "python@3.11.10", "new");
+
+        switch(ps_env, "python", "python@3.11.10").unwrap();
+        assert_eq!(exe(ps_env, "python"), "new");
+        assert_eq!(previous_slot(ps_env, "python").as_deref(), Some("python@3.11.9"));
+        switch(ps_env, "python", "python@3.11.9").unwrap();
+        assert_eq!(exe(ps_env, "python"), "old");
+        assert!(switch(ps_env, "python", "python@3.12.0").is_err());
+        assert!(!journal_path(ps_env, "python").exists());
+    }
+
+    #[test]
+    fn slots_are_ordered_by_version_and_pruned() {
+        let dir = tempfile::tempdir().unwrap();
+        let ps_env = dir.path();
+        for slot in ["python@3.11.10", "python@previous", "python@3.11.9", "python@3.10.0"] {
+            fs::create_dir_all(ps_env.join(slot)).unwrap();
+        }
+        assert_eq!(slots(ps_env, "python"), ["python@3.11.10", "python@3.11.9", "python@3.10.0", "python@previous"]);
+        assert_eq!(prune(ps_env, "python", &["python@3.11.9", "python@3.11.10"]).len(), 2);
+        assert_eq!(slots(ps_env, "python").len(), 2);
+    }
+
+    #[test]
+    fn repair_finishes_an_interrupted_switch_and_removes_old_downloads() {
+        let dir = tempfile::tempdir().unwrap();
+        let ps_env = dir.path();
+        slot_with(ps_env, "python@3.11.10", "new");
+        fs::write(journal_path(ps_env, "python"), "python@3.11.10").unwrap();
+        let (staging, archive) = upgrade_staging(ps_env, "python");
+        fs::create_dir_all(&staging).unwrap();
+        fs::write(&archive, "partial").unwrap();
+
+        assert!(repair(ps_env, Duration::from_secs(3600)).iter().any(|d| d.contains("finished switching")));
+        assert_eq!(exe(ps_env, "python"), "new");
+        assert!(staging.exists() && archive.exists(), "a running upgrade may still use them");
+        repair(ps_env, Duration::ZERO);
+        assert!(!staging.exists() && !archive.exists());
+        assert!(!journal_path(ps_env, "python").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn repair_relinks_a_link_into_the_old_location() {
+        let dir = tempfile::tempdir().unwrap();
+        let ps_env = dir.path();
+        slot_with(ps_env, "python@3.11.9", "old");
+        create_link(&ps_env.join("python"), Path::new("/moved/away/ps_env/python@3.11.9")).unwrap();
+
+        assert_eq!(repair(ps_env, Duration::ZERO), ["re-linked python to python@3.11.9"]);
+        assert_eq!(exe(ps_env, "python"), "old");
+    }
+}
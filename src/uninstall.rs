@@ -0,0 +1,290 @@
+//! Selective uninstall
+//!
+//! `uninstall` builds an [`UninstallPlan`] first: every path that will be deleted or kept,
+//! with the reason, and what stops working afterwards. The plan is shown before the
+//! confirmation prompt and only then executed.
+//!
+//! - everything: the install directory, the config directory and the executable
+//! - `--keep-repos`: like everything, but `repos/` (sources, models, outputs) survives
+//! - `--tools-only`: only `ps_env` (portable Python, git, ffmpeg, CUDA) and the package cache
+//! - `--repo <name>`: one repository and its environment
+//!
+//! Model folders of repositories are moved to `models/<name>`, and `models/` is kept, unless
+//! `--with-models` is given.
+
+use crate::planned_actions::ActionPlan;
+pub use crate::planned_actions::{PlanAction, PlanItem};
+use crate::repo_metadata;
+use crate::Result;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Top-level folders of a repository that usually hold downloaded weights
+pub const MODEL_DIRS: &[&str] = &["models", "checkpoints", "weights", ".assets"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UninstallScope {
+    Everything { with_models: bool },
+    KeepRepos { with_models: bool },
+    ToolsOnly,
+    Repo { name: String, with_models: bool },
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UninstallPlan {
+    pub actions: ActionPlan,
+    /// Delete the portablesource binary itself once everything else is gone
+    pub remove_executable: bool,
+}
+
+impl UninstallPlan {
+    pub fn is_empty(&self) -> bool {
+        !self.remove_executable && self.actions.is_empty()
+    }
+
+    /// Everything in the install directory except `keep`, with a reason per entry
+    fn delete_install_dir_except(&mut self, install_path: &Path, keep: &[&str]) {
+        let Ok(entries) = fs::read_dir(install_path) else { return };
+        let mut names: Vec<String> = entries.flatten().map(|e| e.file_name().to_string_lossy().to_string()).collect();
+        names.sort();
+        for name in names {
+            let path = install_path.join(&name);
+            if keep.contains(&name.as_str()) {
+                continue;
+            }
+            let reason = match name.as_str() {
+                "ps_env" => "portable Python, git, ffmpeg and CUDA",
+                "envs" => "repository environments, built on ps_env's Python",
+                "cache" => "prefetched packages and sources",
+                "shared_models" => "models shared between repositories",
+                "logs" => "logs and timings",
+                "state" => "install engines, constraints and start script hashes of repositories",
+                _ => "PortableSource data",
+            };
+            self.actions.delete(path, reason);
+        }
+    }
+
+    /// Human-readable listing shown before confirmation
+    pub fn render(&self) -> String {
+        let mut out = self.actions.render_items();
+        if self.remove_executable {
+            out.push_str("The portablesource executable will be removed as well.\n");
+        }
+        out + &self.actions.render_notes()
+    }
+
+    /// Delete and move as planned; kept entries are left alone
+    pub fn execute(&self) -> Result<()> {
+        self.actions.execute()
+    }
+}
+
+/// Work out what a scope removes; nothing is touched here
+pub fn plan(install_path: &Path, config_dir: Option<&Path>, scope: &UninstallScope) -> Result<UninstallPlan> {
+    let mut plan = UninstallPlan::default();
+    match scope {
+        UninstallScope::Everything { with_models } => {
+            let repos = repo_names(install_path);
+            let keeps_models = !with_models
+                && (install_path.join(MODELS_HOME).is_dir() || repos.iter().any(|name| !model_dirs(&install_path.join("repos").join(name)).is_empty()));
+            if keeps_models {
+                for name in &repos {
+                    move_models_home(&mut plan, install_path, name);
+                }
+                plan.delete_install_dir_except(install_path, &[MODELS_HOME]);
+                plan.keep_models_home(install_path);
+            } else {
+                plan.actions.delete(install_path.to_path_buf(), "install directory with tools, repositories and environments");
+            }
+            if let Some(dir) = config_dir {
+                plan.actions.delete(dir.to_path_buf(), "configuration");
+            }
+            plan.remove_executable = true;
+        }
+        UninstallScope::KeepRepos { with_models } => {
+            let mut keep = vec!["repos", "shared_models"];
+            if !with_models {
+                keep.push(MODELS_HOME);
+            }
+            plan.delete_install_dir_except(install_path, &keep);
+            plan.actions.keep(install_path.join("repos"), "repository sources, models and outputs");
+            plan.actions.keep(crate::shared_models::shared_models_dir(install_path), "models shared between repositories");
+            if !with_models {
+                plan.keep_models_home(install_path);
+            }
+            if let Some(dir) = config_dir {
+                plan.actions.delete(dir.to_path_buf(), "configuration");
+            }
+            plan.remove_executable = true;
+            plan.actions.notes.push(
+                "Environments are removed with the tools they are built on; after reinstalling PortableSource, run update-repo to recreate them".into(),
+            );
+        }
+        UninstallScope::ToolsOnly => {
+            plan.actions.delete(install_path.join("ps_env"), "portable Python, git, ffmpeg and CUDA");
+            plan.actions.delete(install_path.join("cache"), "prefetched packages and sources");
+            plan.actions.keep(install_path.join("repos"), "repository sources");
+            plan.actions.keep(install_path.join("envs"), "repository environments");
+            if install_path.join("envs").exists() {
+                plan.actions.notes.push("Environments use ps_env's Python; run setup-env again before starting repositories".into());
+            }
+        }
+        UninstallScope::Repo { name, with_models } => plan_repo(&mut plan, install_path, name, *with_models)?,
+    }
+    Ok(plan)
+}
+
+/// Folder the model folders of removed repositories are kept in, `models/<name>`
+const MODELS_HOME: &str = "models";
+
+fn repo_names(install_path: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(install_path.join("repos"))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|e| e.path().is_dir())
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .collect();
+    names.sort();
+    names
+}
+
+fn model_dirs(repo_path: &Path) -> Vec<PathBuf> {
+    MODEL_DIRS.iter().map(|d| repo_path.join(d)).filter(|p| p.is_dir()).collect()
+}
+
+/// Plan moving the model folders of `name` to `models/<name>`; false when it has none
+fn move_models_home(plan: &mut UninstallPlan, install_path: &Path, name: &str) -> bool {
+    let dirs = model_dirs(&install_path.join("repos").join(name));
+    let models_home = install_path.join(MODELS_HOME).join(name);
+    for dir in &dirs {
+        let to = models_home.join(dir.file_name().unwrap_or_default());
+        plan.actions.move_to(dir.clone(), to, "model files");
+    }
+    !dirs.is_empty()
+}
+
+impl UninstallPlan {
+    fn keep_models_home(&mut self, install_path: &Path) {
+        let models = install_path.join(MODELS_HOME);
+        self.actions.keep(models.clone(), "model folders of repositories");
+        self.actions.notes.push(format!("Models are kept in {}; use --with-models to delete them", models.display()));
+    }
+}
+
+/// `repos/<name>` for a plain repository name; anything resolving elsewhere (`..`, empty,
+/// a link out of repos/) is refused
+fn repo_dir(install_path: &Path, name: &str) -> Result<PathBuf> {
+    repo_metadata::validate_instance_name(name)?;
+    let repos = install_path.join("repos");
+    let repo_path = repos.join(name);
+    if let (Ok(repos), Ok(resolved)) = (fs::canonicalize(&repos), fs::canonicalize(&repo_path)) {
+        if resolved.parent() != Some(repos.as_path()) {
+            return Err(crate::PortableSourceError::repository(format!(
+                "'{}' does not resolve to a folder in {}", name, repos.display()
+            )));
+        }
+    }
+    Ok(repo_path)
+}
+
+fn plan_repo(plan: &mut UninstallPlan, install_path: &Path, name: &str, with_models: bool) -> Result<()> {
+    let repos = install_path.join("repos");
+    let repo_path = repo_dir(install_path, name)?;
+    let env_path = install_path.join("envs").join(name);
+    if !repo_path.exists() && !env_path.exists() {
+        return Err(crate::PortableSourceError::repository_not_found(name));
+    }
+
+    if with_models {
+        plan.actions.delete(repo_path.clone(), "repository source and models");
+    } else {
+        let moved = move_models_home(plan, install_path, name);
+        plan.actions.delete(repo_path.clone(), "repository source");
+        if moved {
+            let models_home = install_path.join(MODELS_HOME).join(name);
+            plan.actions.notes.push(format!("Models are kept in {}; use --with-models to delete them", models_home.display()));
+        }
+    }
+    plan.actions.delete(env_path, "repository environment");
+
+    // Instances are independent copies: removing one never touches another
+    let upstream = repo_metadata::upstream_name(&repo_path);
+    let mut siblings: Vec<String> = fs::read_dir(&repos)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .filter(|other| other != name && repo_metadata::upstream_name(&repos.join(other)).eq_ignore_ascii_case(&upstream))
+        .collect();
+    siblings.sort();
+    if !siblings.is_empty() {
+        plan.actions.notes.push(format!("Other instances of {} are not affected: {}", upstream, siblings.join(", ")));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repo_uninstall_moves_models_and_tools_only_keeps_repos() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for sub in ["ps_env/python", "envs/comfyui", "repos/comfyui/models/checkpoints", "repos/comfyui/app", "repos/comfy2"] {
+            fs::create_dir_all(root.join(sub)).unwrap();
+        }
+        fs::write(root.join("repos/comfyui/models/checkpoints/sd.safetensors"), b"weights").unwrap();
+        let instance = repo_metadata::RepoMetadata { name: "comfy2".into(), upstream: Some("comfyui".into()), ..Default::default() };
+        instance.save(&root.join("repos/comfy2")).unwrap();
+
+        let repo = plan(root, None, &UninstallScope::Repo { name: "comfyui".into(), with_models: false }).unwrap();
+        let models = root.join("models/comfyui/models");
+        assert!(repo.actions.items.iter().any(|i| i.action == PlanAction::MoveTo(models.clone())));
+        assert!(repo.render().contains("Will move:"));
+        assert!(repo.actions.notes.iter().any(|n| n.contains("not affected: comfy2")));
+        repo.execute().unwrap();
+        assert!(models.join("checkpoints/sd.safetensors").exists());
+        assert!(!root.join("repos/comfyui").exists() && !root.join("envs/comfyui").exists());
+
+        let tools = plan(root, None, &UninstallScope::ToolsOnly).unwrap();
+        let deleted: Vec<_> = tools.actions.items.iter().filter(|i| i.action == PlanAction::Delete).map(|i| i.path.clone()).collect();
+        assert_eq!(deleted, [root.join("ps_env")]);
+        assert!(!tools.remove_executable);
+    }
+
+    #[test]
+    fn repo_names_outside_repos_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("repos/comfyui")).unwrap();
+        for name in ["..", "", ".", "../repos", "comfyui/.."] {
+            let scope = UninstallScope::Repo { name: name.into(), with_models: false };
+            assert!(plan(dir.path(), None, &scope).is_err(), "{:?} was accepted", name);
+        }
+    }
+
+    #[test]
+    fn everything_and_keep_repos_keep_model_folders_without_with_models() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for sub in ["ps_env/python", "models/old/checkpoints", "repos/comfyui/models", "repos/comfyui/app"] {
+            fs::create_dir_all(root.join(sub)).unwrap();
+        }
+
+        let everything = plan(root, None, &UninstallScope::Everything { with_models: false }).unwrap();
+        assert!(!everything.actions.items.iter().any(|i| i.path == root && i.action == PlanAction::Delete));
+        assert!(everything.actions.items.iter().any(|i| i.action == PlanAction::MoveTo(root.join("models/comfyui/models"))));
+        everything.execute().unwrap();
+        assert!(root.join("models/old/checkpoints").is_dir() && root.join("models/comfyui/models").is_dir());
+        assert!(!root.join("ps_env").exists() && !root.join("repos").exists());
+
+        let keep_repos = plan(root, None, &UninstallScope::KeepRepos { with_models: false }).unwrap();
+        assert!(!keep_repos.actions.items.iter().any(|i| i.path == root.join("models") && i.action == PlanAction::Delete));
+        let with_models = plan(root, None, &UninstallScope::KeepRepos { with_models: true }).unwrap();
+        assert!(with_models.actions.items.iter().any(|i| i.path == root.join("models") && i.action == PlanAction::Delete));
+        let all = plan(root, None, &UninstallScope::Everything { with_models: true }).unwrap();
+        assert!(all.actions.items.iter().any(|i| i.path == root && i.action == PlanAction::Delete));
+    }
+}
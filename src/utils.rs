@@ -1,17 +1,20 @@
 //! Utility functions for PortableSource
 
 use crate::{Result, PortableSourceError};
+use crate::output;
 use crate::config::ConfigManager;
 use crate::envs_manager::PortableEnvironmentManager;
 use crate::repository_installer::RepositoryInstaller;
 use crate::gpu::{GpuDetector, GpuType};
+use crate::net_isolation::{self, NetworkIsolation};
+use crate::oom_advice;
+use crate::run_stats::RunStatsRecorder;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::fs;
-use std::time::Duration;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
 
-#[cfg(unix)]
-use indicatif::{ProgressBar, ProgressStyle};
 #[cfg(unix)]
 use libc;
 
@@ -32,14 +35,49 @@ pub fn save_install_path_to_registry(install_path: &Path) -> Result<()> {
         .map_err(|e| PortableSourceError::Registry(format!("Failed to create registry key: {}", e)))?;
     key.set_value(INSTALL_PATH_VALUE, &install_path.to_string_lossy().to_string())
         .map_err(|e| PortableSourceError::Registry(format!("Failed to set registry value: {}", e)))?;
-    log::info!("Installation path saved to registry: {:?}", install_path);
+    tracing::info!("Installation path saved to registry: {:?}", install_path);
     Ok(())
 }
 
+/// Marker file next to the binary that enables fully portable mode on Linux
 #[cfg(unix)]
-pub fn save_install_path_to_registry(install_path: &Path) -> Result<()> {
-    // Save install path to ~/.portablesource
-    let config_file = if is_root() {
+pub const PORTABLE_MARKER_FILE: &str = "portablesource.portable";
+
+/// Linux portable mode: all state lives next to the binary (like on Windows).
+/// Returns the binary directory when the marker file is present.
+#[cfg(unix)]
+pub fn linux_portable_root() -> Option<PathBuf> {
+    let exe = std::env::current_exe().ok()?;
+    let dir = exe.parent()?.to_path_buf();
+    if dir.join(PORTABLE_MARKER_FILE).is_file() {
+        if !is_dir_writable(&dir) {
+            tracing::warn!("Portable marker found but {:?} is read-only; ignoring portable mode", dir);
+            return None;
+        }
+        Some(dir)
+    } else {
+        None
+    }
+}
+
+/// HOME/XDG/TMPDIR overrides pointing into `install_path/tmp` for portable mode
+#[cfg(unix)]
+pub fn portable_home_env_vars(install_path: &Path) -> Vec<(String, String)> {
+    let tmp = install_path.join("tmp");
+    let var = |name: &str, path: PathBuf| (name.to_string(), path.to_string_lossy().to_string());
+    vec![
+        var("HOME", tmp.join("home")),
+        var("XDG_CACHE_HOME", tmp.join("cache")),
+        var("XDG_CONFIG_HOME", tmp.join("config")),
+        var("XDG_DATA_HOME", tmp.join("data")),
+        var("TMPDIR", tmp.join("tmp")),
+    ]
+}
+
+/// Location of the per-user file holding the install path (~/.portablesource)
+#[cfg(unix)]
+fn install_path_file() -> PathBuf {
+    if is_root() {
         PathBuf::from("/root/.portablesource")
     } else {
         if let Ok(username) = std::env::var("USER") {
@@ -49,11 +87,22 @@ pub fn save_install_path_to_registry(install_path: &Path) -> Result<()> {
         } else {
             PathBuf::from("./.portablesource")
         }
-    };
+    }
+}
+
+#[cfg(unix)]
+pub fn save_install_path_to_registry(install_path: &Path) -> Result<()> {
+    // Portable mode: install path is always the binary directory, nothing to persist
+    if linux_portable_root().is_some() {
+        tracing::debug!("Portable mode: not saving install path outside install dir");
+        return Ok(());
+    }
+    // Save install path to ~/.portablesource
+    let config_file = install_path_file();
     
     std::fs::write(&config_file, install_path.to_string_lossy().as_bytes())
         .map_err(|e| PortableSourceError::Registry(format!("Failed to write {}: {}", config_file.display(), e)))?;
-    log::info!("Installation path saved to {}", config_file.display());
+    tracing::info!("Installation path saved to {}", config_file.display());
     Ok(())
 }
 
@@ -66,11 +115,11 @@ pub fn delete_install_path_from_registry() -> Result<()> {
         Ok(key) => {
             key.delete_value(INSTALL_PATH_VALUE)
                 .map_err(|e| PortableSourceError::Registry(format!("Failed to delete registry value: {}", e)))?;
-            log::info!("Installation path deleted from registry");
+            tracing::info!("Installation path deleted from registry");
             Ok(())
         }
         Err(_) => {
-            log::warn!("Registry key not found");
+            tracing::warn!("Registry key not found");
             Ok(()) // Not an error if key doesn't exist
         }
     }
@@ -78,18 +127,12 @@ pub fn delete_install_path_from_registry() -> Result<()> {
 
 #[cfg(unix)]
 pub fn delete_install_path_from_registry() -> Result<()> {
+    if linux_portable_root().is_some() {
+        tracing::debug!("Portable mode: no install path stored outside install dir");
+        return Ok(());
+    }
     // Remove ~/.portablesource file
-    let config_file = if is_root() {
-        PathBuf::from("/root/.portablesource")
-    } else {
-        if let Ok(username) = std::env::var("USER") {
-            PathBuf::from(format!("/home/{}/.portablesource", username))
-        } else if let Some(home) = dirs::home_dir() {
-            home.join(".portablesource")
-        } else {
-            PathBuf::from("./.portablesource")
-        }
-    };
+    let config_file = install_path_file();
     
     if config_file.exists() { 
         let _ = std::fs::remove_file(&config_file); 
@@ -100,7 +143,7 @@ pub fn delete_install_path_from_registry() -> Result<()> {
     if is_root() && etc_file.exists() { 
         let _ = std::fs::remove_file(&etc_file); 
     }
-    log::info!("Installation path deleted (user and legacy locations cleaned where possible)");
+    tracing::info!("Installation path deleted (user and legacy locations cleaned where possible)");
     Ok(())
 }
 
@@ -114,7 +157,7 @@ pub fn load_install_path_from_registry() -> Result<Option<PathBuf>> {
             match key.get_value::<String, _>(INSTALL_PATH_VALUE) {
                 Ok(path_str) => {
                     let path = PathBuf::from(path_str);
-                    log::debug!("Loaded installation path from registry: {:?}", path);
+                    tracing::debug!("Loaded installation path from registry: {:?}", path);
                     Ok(Some(path))
                 }
                 Err(_) => Ok(None),
@@ -126,18 +169,11 @@ pub fn load_install_path_from_registry() -> Result<Option<PathBuf>> {
 
 #[cfg(unix)]
 pub fn load_install_path_from_registry() -> Result<Option<PathBuf>> {
+    if let Some(root) = linux_portable_root() {
+        return Ok(Some(root));
+    }
     // Load install path from ~/.portablesource
-    let config_file = if is_root() {
-        PathBuf::from("/root/.portablesource")
-    } else {
-        if let Ok(username) = std::env::var("USER") {
-            PathBuf::from(format!("/home/{}/.portablesource", username))
-        } else if let Some(home) = dirs::home_dir() {
-            home.join(".portablesource")
-        } else {
-            PathBuf::from("./.portablesource")
-        }
-    };
+    let config_file = install_path_file();
     
     if config_file.exists() {
         let content = std::fs::read_to_string(&config_file)
@@ -207,12 +243,44 @@ pub fn create_directory_structure(install_path: &Path) -> Result<()> {
             .map_err(|e| PortableSourceError::installation(
                 format!("Failed to create directory {:?}: {}", dir, e)
             ))?;
-        log::debug!("Created directory: {:?}", dir);
+        tracing::debug!("Created directory: {:?}", dir);
     }
     
     Ok(())
 }
 
+/// Install path used when `--install-path` is not given (deployments from read-only shares)
+pub const INSTALL_PATH_ENV: &str = "PORTABLESOURCE_INSTALL_PATH";
+
+pub fn install_path_from_env() -> Option<PathBuf> {
+    std::env::var_os(INSTALL_PATH_ENV).filter(|v| !v.is_empty()).map(PathBuf::from)
+}
+
+/// Whether files can be created in `dir`; false on read-only network shares and media.
+/// Probed once per existing directory, then answered from a cache
+pub fn is_dir_writable(dir: &Path) -> bool {
+    static PROBED: OnceLock<Mutex<HashMap<PathBuf, bool>>> = OnceLock::new();
+    if !dir.is_dir() {
+        return false;
+    }
+    let probed = PROBED.get_or_init(Mutex::default);
+    if let Some(&writable) = probed.lock().unwrap_or_else(|e| e.into_inner()).get(dir) {
+        return writable;
+    }
+    let writable = tempfile::Builder::new().prefix(".ps_write_test").tempfile_in(dir).is_ok();
+    probed.lock().unwrap_or_else(|e| e.into_inner()).insert(dir.to_path_buf(), writable);
+    writable
+}
+
+/// The install path cannot be inferred from a read-only executable location
+pub fn read_only_location_error(exe_dir: &Path) -> PortableSourceError {
+    PortableSourceError::installation(format!(
+        "PortableSource is running from a read-only location ({}). Choose a writable install directory with --install-path <DIR> or the {} environment variable",
+        exe_dir.display(),
+        INSTALL_PATH_ENV
+    ))
+}
+
 /// Определяет, является ли это первой установкой (отсутствуют директории среды)
 pub fn is_first_installation(install_path: &Path) -> bool {
     let ps_env = install_path.join("ps_env");
@@ -237,9 +305,15 @@ pub fn copy_executable_to_install_path(install_path: &Path) -> Result<()> {
     
     let target_exe = install_path.join(exe_name);
     
+    // Запуск с read-only шары: exe остаётся там, копию не делаем
+    if current_exe.parent().is_some_and(|dir| !is_dir_writable(dir)) {
+        tracing::info!("Executable runs from a read-only location {:?}; not copying it to {:?}", current_exe, install_path);
+        return Ok(());
+    }
+    
     // Не копируем, если уже находимся в целевой директории
     if current_exe == target_exe {
-        log::info!("Executable already in target location: {:?}", target_exe);
+        tracing::info!("Executable already in target location: {:?}", target_exe);
         return Ok(());
     }
     
@@ -255,7 +329,7 @@ pub fn copy_executable_to_install_path(install_path: &Path) -> Result<()> {
             format!("Failed to copy executable from {:?} to {:?}: {}", current_exe, target_exe, e)
         ))?;
     
-    log::info!("Executable copied to: {:?}", target_exe);
+    tracing::info!("Executable copied to: {:?}", target_exe);
     Ok(())
 }
 
@@ -269,6 +343,9 @@ pub fn is_root() -> bool {
 
 #[cfg(unix)]
 pub fn default_install_path_linux() -> PathBuf {
+    if let Some(root) = linux_portable_root() {
+        return root;
+    }
     if is_root() {
         PathBuf::from("/root/portablesource")
     } else {
@@ -302,19 +379,23 @@ pub fn prompt_install_path_linux(default: &Path) -> Result<PathBuf> {
     validate_and_create_path(&chosen)
 }
 
-/// Simple HTTP(S) download helper
-pub fn download_file(url: &str, destination: &Path) -> Result<()> {
-    use reqwest::blocking::Client;
-    use std::io::copy;
-    if let Some(parent) = destination.parent() { std::fs::create_dir_all(parent)?; }
-    let client = Client::builder().timeout(Duration::from_secs(600)).build()?;
-    let mut resp = client.get(url).send()
+/// Simple HTTP(S) download helper on the shared async client
+pub async fn download_file(url: &str, destination: &Path) -> Result<()> {
+    use tokio::io::AsyncWriteExt;
+    if let Some(parent) = destination.parent() { tokio::fs::create_dir_all(parent).await?; }
+    let mut resp = crate::system::http_client().get(url).send().await
         .map_err(|e| PortableSourceError::environment(format!("Failed to GET {}: {}", url, e)))?;
     if !resp.status().is_success() {
         return Err(PortableSourceError::environment(format!("Download failed: HTTP {}", resp.status())));
     }
-    let mut file = std::fs::File::create(destination)?;
-    copy(&mut resp, &mut file)?;
+    let mut file = tokio::fs::File::create(destination).await?;
+    while let Some(chunk) = resp.chunk().await? {
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+    // Closed before returning, so a downloaded installer can be started right away on Windows
+    file.sync_all().await?;
+    drop(file);
     Ok(())
 }
 
@@ -355,7 +436,7 @@ pub fn detect_cuda_version_from_system() -> Option<crate::config::CudaVersionLin
     let stdout = String::from_utf8_lossy(&out.stdout).to_lowercase();
     for line in stdout.lines() {
         if line.contains("cuda compilation tools") && line.contains("release") {
-            let ver = line.split_whitespace().filter(|s| s.chars().next().map(|c| c.is_ascii_digit()).unwrap_or(false)).next().unwrap_or("");
+            let ver = line.split_whitespace().find(|s| s.chars().next().map(|c| c.is_ascii_digit()).unwrap_or(false)).unwrap_or("");
             if ver.starts_with("12.8") { return Some(crate::config::CudaVersionLinux::Cuda128); }
             if ver.starts_with("12.6") { return Some(crate::config::CudaVersionLinux::Cuda126); }
             if ver.starts_with("12.4") { return Some(crate::config::CudaVersionLinux::Cuda124); }
@@ -377,25 +458,55 @@ fn cuda_version_to_runtime_spec(v: &crate::config::CudaVersionLinux) -> &'static
     }
 }
 
+// Correct latest asset URL + fallback pinned version
+#[cfg(unix)]
+const MICROMAMBA_URL_LATEST: &str = "https://github.com/mamba-org/micromamba-releases/releases/latest/download/micromamba-linux-64";
 #[cfg(unix)]
-pub fn setup_micromamba_base_env(install_path: &Path, cuda_version: Option<crate::config::CudaVersionLinux>) -> Result<()> {
+const MICROMAMBA_URL_FALLBACK: &str = "https://github.com/mamba-org/micromamba-releases/releases/download/2.3.1-0/micromamba-linux-64";
+
+/// What `setup_micromamba_base_env` would download; the conda packages are resolved by
+/// micromamba itself, so only their names are known up front
+#[cfg(unix)]
+pub fn micromamba_download_plan(install_path: &Path, cuda_version: Option<&crate::config::CudaVersionLinux>) -> crate::download_plan::DownloadPlan {
+    use crate::download_plan::{DownloadPlan, PlannedDownload};
+    let mamba_bin = install_path.join("ps_env").join("micromamba-linux-64");
+    let mut plan = DownloadPlan::default();
+    if !mamba_bin.exists() {
+        plan.items.push(PlannedDownload::new("micromamba", MICROMAMBA_URL_LATEST, mamba_bin));
+    }
+    let packages = match cuda_version {
+        Some(v) => format!("python 3.11, git, ffmpeg, cuda-toolkit {}, cudnn", cuda_version_to_runtime_spec(v)),
+        None => "python 3.11, git, ffmpeg".to_string(),
+    };
+    plan.notes.push(format!("Conda packages ({}) from conda-forge/nvidia into ps_env/mamba_env; size shown by micromamba", packages));
+    plan
+}
+
+#[cfg(unix)]
+pub async fn setup_micromamba_base_env(install_path: &Path, cuda_version: Option<crate::config::CudaVersionLinux>) -> Result<()> {
     use std::os::unix::fs::PermissionsExt;
     // Ensure directory layout
     create_directory_structure(install_path)?;
     let mamba_bin = install_path.join("ps_env").join("micromamba-linux-64");
-    // Correct latest asset URL + fallback pinned version
-    let mamba_url_latest = "https://github.com/mamba-org/micromamba-releases/releases/latest/download/micromamba-linux-64";
-    let mamba_url_fallback = "https://github.com/mamba-org/micromamba-releases/releases/download/2.3.1-0/micromamba-linux-64";
     if !mamba_bin.exists() {
-        if let Err(e) = download_file(mamba_url_latest, &mamba_bin) {
-            log::warn!("micromamba latest download failed: {} — trying fallback", e);
-            download_file(mamba_url_fallback, &mamba_bin)?;
+        if let Err(e) = download_file(MICROMAMBA_URL_LATEST, &mamba_bin).await {
+            tracing::warn!("micromamba latest download failed: {} — trying fallback", e);
+            download_file(MICROMAMBA_URL_FALLBACK, &mamba_bin).await?;
         }
         let mut perms = std::fs::metadata(&mamba_bin)?.permissions();
         perms.set_mode(0o755);
         std::fs::set_permissions(&mamba_bin, perms)?;
     }
 
+    // micromamba create runs for minutes: keep it off the async workers
+    let install_path = install_path.to_path_buf();
+    tokio::task::spawn_blocking(move || create_micromamba_base_env(&install_path, &mamba_bin, cuda_version))
+        .await
+        .map_err(|e| PortableSourceError::environment(format!("micromamba task failed: {}", e)))?
+}
+
+#[cfg(unix)]
+fn create_micromamba_base_env(install_path: &Path, mamba_bin: &Path, cuda_version: Option<crate::config::CudaVersionLinux>) -> Result<()> {
     let base_prefix = install_path.join("ps_env").join("mamba_env");
     let root_prefix = install_path.join("ps_env");
     let mut args: Vec<String> = vec![
@@ -414,7 +525,7 @@ pub fn setup_micromamba_base_env(install_path: &Path, cuda_version: Option<crate
         attempted_cuda = true;
     }
     // auto-accept ToS/licenses
-    let mut child = std::process::Command::new(&mamba_bin)
+    let mut child = std::process::Command::new(mamba_bin)
         .env("MAMBA_ALWAYS_YES", "true")
         .env("MAMBA_NO_RC", "true")
         .env("MAMBA_ROOT_PREFIX", &root_prefix)
@@ -425,22 +536,20 @@ pub fn setup_micromamba_base_env(install_path: &Path, cuda_version: Option<crate
         .spawn()
         .map_err(|e| PortableSourceError::environment(format!("Failed to run micromamba: {}", e)))?;
 
-    let pb = ProgressBar::new_spinner();
-    pb.set_style(ProgressStyle::with_template("{spinner} {msg}").unwrap());
-    pb.enable_steady_tick(Duration::from_millis(120));
+    let pb = crate::progress::Progress::spinner("micromamba");
     if let Some(out) = child.stdout.take() {
         use std::io::{BufRead, BufReader};
         let reader = BufReader::new(out);
-        for line in reader.lines().flatten() {
+        for line in reader.lines().map_while(std::result::Result::ok) {
             let l = line.trim();
             if !l.is_empty() {
-                pb.set_message(l.to_string());
+                pb.set_message(l);
             }
         }
     }
     let status = child.wait().map_err(|e| PortableSourceError::environment(format!("micromamba wait failed: {}", e)))?;
     if status.success() {
-        pb.finish_with_message("micromamba: done");
+        pb.finish_with_message("done");
         // Verify env created
         let py = base_prefix.join("bin").join("python");
         if !py.exists() {
@@ -450,7 +559,7 @@ pub fn setup_micromamba_base_env(install_path: &Path, cuda_version: Option<crate
             )));
         }
     } else {
-        pb.finish_with_message("micromamba: failed");
+        pb.finish_with_message("failed");
         return Err(PortableSourceError::environment("micromamba create failed"));
     }
     // Verify CUDA runtime presence on DESK: libcudart.so* must exist if we attempted CUDA
@@ -573,11 +682,8 @@ fn check_msvc_registry() -> bool {
     false
 }
 
-/// Download and run MSVC Build Tools installer (blocking)
-pub fn install_msvc_build_tools() -> Result<()> {
-    use reqwest::blocking::Client;
-    use std::io::copy;
-    
+/// Download and run MSVC Build Tools installer
+pub async fn install_msvc_build_tools() -> Result<()> {
     // Prefer winget if available (often faster and more reliable)
     // Helper: choose one SDK depending on OS build (Win11 vs Win10)
     #[cfg(windows)]
@@ -608,7 +714,7 @@ pub fn install_msvc_build_tools() -> Result<()> {
             " --add Microsoft.VisualStudio.Component.VC.Redist.14.Latest"
         ).to_string();
 
-        log::info!("Trying to install MSVC Build Tools via winget...");
+        tracing::info!("Trying to install MSVC Build Tools via winget...");
         let mut cmd = Command::new("winget");
         cmd.arg("install")
             .arg("Microsoft.VisualStudio.2022.BuildTools")
@@ -624,14 +730,14 @@ pub fn install_msvc_build_tools() -> Result<()> {
         let status = cmd.status();
         match status {
             Ok(st) if st.success() => {
-                log::info!("winget installation completed successfully");
+                tracing::info!("winget installation completed successfully");
                 return Ok(());
             }
             Ok(st) => {
-                log::warn!("winget returned non-zero exit code: {:?}. Falling back to direct bootstrapper.", st.code());
+                tracing::warn!("winget returned non-zero exit code: {:?}. Falling back to direct bootstrapper.", st.code());
             }
             Err(e) => {
-                log::warn!("winget not usable: {}. Falling back to direct bootstrapper.", e);
+                tracing::warn!("winget not usable: {}. Falling back to direct bootstrapper.", e);
             }
         }
     }
@@ -652,8 +758,8 @@ pub fn install_msvc_build_tools() -> Result<()> {
         detect_windows_sdk_component()
     );
 
-    log::info!("Starting MSVC Build Tools installation...");
-    log::info!("Download URL: {}", url);
+    tracing::info!("Starting MSVC Build Tools installation...");
+    tracing::info!("Download URL: {}", url);
 
     // Prepare temp dir and file
     let temp_dir = std::env::temp_dir().join("portablesource");
@@ -663,42 +769,27 @@ pub fn install_msvc_build_tools() -> Result<()> {
     if installer_path.exists() { let _ = std::fs::remove_file(&installer_path); }
 
     // Download file
-    log::info!("Downloading installer to {:?}...", installer_path);
-    let client = Client::builder()
-        .timeout(Duration::from_secs(600))
-        .build()?;
-    let mut resp = client.get(&url).send()?;
-    if !resp.status().is_success() {
-        return Err(PortableSourceError::installation(format!(
-            "Failed to download installer: HTTP {}",
-            resp.status()
-        )));
-    }
-    let mut file = std::fs::File::create(&installer_path)?;
-    copy(&mut resp, &mut file)?;
-    // Ensure data is fully flushed and file handle is closed before executing (Windows lock avoidance)
-    let _ = file.sync_all();
-    drop(file);
+    tracing::info!("Downloading installer to {:?}...", installer_path);
+    download_file(&url, &installer_path)
+        .await
+        .map_err(|e| PortableSourceError::installation(format!("Failed to download installer: {}", e)))?;
 
     // Run installer
-    log::info!("Running installer (this may take a while)...");
-    let status = {
-        let mut cmd = Command::new(&installer_path);
-        cmd.args(args.split_whitespace());
-        #[cfg(windows)]
-        {
-            use std::os::windows::process::CommandExt;
-            cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
-        }
-        cmd.status()
-            .map_err(|e| PortableSourceError::command(format!("Failed to start installer: {}", e)))?
-    };
+    tracing::info!("Running installer (this may take a while)...");
+    let mut cmd = Command::new(&installer_path);
+    cmd.args(args.split_whitespace());
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
+    }
+    let status = run_installer(cmd).await?;
 
     // Cleanup best-effort
     let _ = std::fs::remove_file(&installer_path);
 
     if status.success() {
-        log::info!("[OK] MSVC Build Tools installed successfully");
+        tracing::info!("[OK] MSVC Build Tools installed successfully");
         Ok(())
     } else {
         Err(PortableSourceError::installation(format!(
@@ -708,11 +799,16 @@ pub fn install_msvc_build_tools() -> Result<()> {
     }
 }
 
-/// Install MSVC Build Tools using a provided install path for temp storage
-pub fn install_msvc_build_tools_with_path(install_path: &Path) -> Result<()> {
-    use reqwest::blocking::Client;
-    use std::io::copy;
+/// Wait for an installer off the async workers; it runs for minutes
+async fn run_installer(mut cmd: Command) -> Result<std::process::ExitStatus> {
+    tokio::task::spawn_blocking(move || cmd.status())
+        .await
+        .map_err(|e| PortableSourceError::command(format!("Installer task failed: {}", e)))?
+        .map_err(|e| PortableSourceError::command(format!("Failed to start installer: {}", e)))
+}
 
+/// Install MSVC Build Tools using a provided install path for temp storage
+pub async fn install_msvc_build_tools_with_path(install_path: &Path) -> Result<()> {
     let (url, args) = ConfigManager::new(None)
         .map(|cm| cm.msvc_bt_config())
         .unwrap_or_else(|_| (
@@ -720,35 +816,27 @@ pub fn install_msvc_build_tools_with_path(install_path: &Path) -> Result<()> {
             " --quiet --wait --norestart --nocache --add Microsoft.VisualStudio.Workload.NativeDesktop --add Microsoft.VisualStudio.Component.VC.CMake.Project --add Microsoft.VisualStudio.Component.VC.Llvm.Clang".to_string()
         ));
 
-    log::info!("Starting MSVC Build Tools installation...");
-    log::info!("Download URL: {}", url);
+    tracing::info!("Starting MSVC Build Tools installation...");
+    tracing::info!("Download URL: {}", url);
 
     let temp_dir = install_path.join("tmp");
     fs::create_dir_all(&temp_dir)?;
     let installer_path = temp_dir.join("vs_buildtools.exe");
 
-    log::info!("Downloading installer to {:?}...", installer_path);
-    let client = Client::builder().timeout(Duration::from_secs(600)).build()?;
-    let mut resp = client.get(&url).send()?;
-    if !resp.status().is_success() {
-        return Err(PortableSourceError::installation(format!(
-            "Failed to download installer: HTTP {}",
-            resp.status()
-        )));
-    }
-    let mut file = std::fs::File::create(&installer_path)?;
-    copy(&mut resp, &mut file)?;
+    tracing::info!("Downloading installer to {:?}...", installer_path);
+    download_file(&url, &installer_path)
+        .await
+        .map_err(|e| PortableSourceError::installation(format!("Failed to download installer: {}", e)))?;
 
-    log::info!("Running installer (this may take a while)...");
-    let status = Command::new(&installer_path)
-        .args(args.split_whitespace())
-        .status()
-        .map_err(|e| PortableSourceError::command(format!("Failed to start installer: {}", e)))?;
+    tracing::info!("Running installer (this may take a while)...");
+    let mut cmd = Command::new(&installer_path);
+    cmd.args(args.split_whitespace());
+    let status = run_installer(cmd).await?;
 
     let _ = std::fs::remove_file(&installer_path);
 
     if status.success() {
-        log::info!("[OK] MSVC Build Tools installed successfully");
+        tracing::info!("[OK] MSVC Build Tools installed successfully");
         Ok(())
     } else {
         Err(PortableSourceError::installation(format!(
@@ -759,8 +847,14 @@ pub fn install_msvc_build_tools_with_path(install_path: &Path) -> Result<()> {
 }
 
 /// Show version information
-pub fn show_version() {
-    println!("PortableSource version: {}", crate::config::VERSION);
+pub fn show_version(json: bool) -> Result<()> {
+    let report = crate::build_info::VersionReport::collect();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{}", report.render());
+    }
+    Ok(())
 }
 
 #[cfg(unix)]
@@ -769,14 +863,14 @@ pub fn prepare_linux_system() -> Result<()> {
     let is_root = unsafe { libc::geteuid() } == 0;
     let use_sudo = !is_root && which::which("sudo").is_ok();
     if !is_root && !use_sudo {
-        log::warn!("Not running as root and sudo not available. Skipping package installation. Some steps may fail.");
+        tracing::warn!("Not running as root and sudo not available. Skipping package installation. Some steps may fail.");
         return Ok(());
     }
 
     // 1) Определяем пакетный менеджер
     let pm = linux_detect_package_manager();
     if matches!(pm, LinuxPackageManager::Unknown) {
-        log::warn!("Unsupported package manager. Skipping package installation.");
+        tracing::warn!("Unsupported package manager. Skipping package installation.");
         return Ok(());
     }
 
@@ -886,7 +980,7 @@ fn linux_check_requirements(pm: LinuxPackageManager) -> Vec<(String, String)> {
 }
 
 #[cfg(unix)]
-fn install_linux_packages(pm: LinuxPackageManager, missing: &Vec<(String, String)>, use_sudo: bool) -> Result<()> {
+fn install_linux_packages(pm: LinuxPackageManager, missing: &[(String, String)], use_sudo: bool) -> Result<()> {
     use std::process::Command;
     if missing.is_empty() { return Ok(()); }
     let pkgs: Vec<String> = missing.iter().map(|(_, p)| p.clone()).collect();
@@ -896,33 +990,33 @@ fn install_linux_packages(pm: LinuxPackageManager, missing: &Vec<(String, String
         LinuxPackageManager::Apt => {
             // apt-get update
             if let Some(s) = sudo("apt-get") {
-                let _ = Command::new(s).args(&sudo_args).arg("apt-get").arg("update").status();
+                let _ = Command::new(s).args(sudo_args).arg("apt-get").arg("update").status();
             } else {
                 let _ = Command::new("apt-get").arg("update").status();
             }
             // apt-get install -y pkgs
-            let mut cmd = if let Some(s) = sudo("apt-get") { let mut c = Command::new(s); c.args(&sudo_args).arg("apt-get"); c } else { Command::new("apt-get") };
+            let mut cmd = if let Some(s) = sudo("apt-get") { let mut c = Command::new(s); c.args(sudo_args).arg("apt-get"); c } else { Command::new("apt-get") };
             cmd.arg("install").arg("-y");
             for p in &pkgs { cmd.arg(p); }
             let st = cmd.status().map_err(|e| PortableSourceError::environment(format!("apt-get failed: {}", e)))?;
             if !st.success() { return Err(PortableSourceError::environment("apt-get install failed")); }
         }
         LinuxPackageManager::Dnf => {
-            let mut cmd = if let Some(s) = sudo("dnf") { let mut c = Command::new(s); c.args(&sudo_args).arg("dnf"); c } else { Command::new("dnf") };
+            let mut cmd = if let Some(s) = sudo("dnf") { let mut c = Command::new(s); c.args(sudo_args).arg("dnf"); c } else { Command::new("dnf") };
             cmd.arg("install").arg("-y");
             for p in &pkgs { cmd.arg(p); }
             let st = cmd.status().map_err(|e| PortableSourceError::environment(format!("dnf failed: {}", e)))?;
             if !st.success() { return Err(PortableSourceError::environment("dnf install failed")); }
         }
         LinuxPackageManager::Yum => {
-            let mut cmd = if let Some(s) = sudo("yum") { let mut c = Command::new(s); c.args(&sudo_args).arg("yum"); c } else { Command::new("yum") };
+            let mut cmd = if let Some(s) = sudo("yum") { let mut c = Command::new(s); c.args(sudo_args).arg("yum"); c } else { Command::new("yum") };
             cmd.arg("install").arg("-y");
             for p in &pkgs { cmd.arg(p); }
             let st = cmd.status().map_err(|e| PortableSourceError::environment(format!("yum failed: {}", e)))?;
             if !st.success() { return Err(PortableSourceError::environment("yum install failed")); }
         }
         LinuxPackageManager::Pacman => {
-            let mut cmd = if let Some(s) = sudo("pacman") { let mut c = Command::new(s); c.args(&sudo_args).arg("pacman"); c } else { Command::new("pacman") };
+            let mut cmd = if let Some(s) = sudo("pacman") { let mut c = Command::new(s); c.args(sudo_args).arg("pacman"); c } else { Command::new("pacman") };
             cmd.arg("-Sy").arg("--noconfirm");
             for p in &pkgs { cmd.arg(p); }
             let st = cmd.status().map_err(|e| PortableSourceError::environment(format!("pacman failed: {}", e)))?;
@@ -1003,6 +1097,14 @@ pub fn execute_command(command: &str, args: &[&str], working_dir: Option<&Path>)
     Ok(String::from_utf8_lossy(&output.stdout).to_string())
 }
 
+/// Seconds since the Unix epoch (0 if the clock is before it)
+pub fn unix_timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
 /// Format file size in human-readable format
 pub fn format_file_size(bytes: u64) -> String {
     const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB"];
@@ -1054,37 +1156,37 @@ pub fn show_system_info_detailed(install_path: &Path, config_manager: &ConfigMan
     let slash = if cfg!(windows) { "\\" } else { "/" };
     let os_name = if cfg!(windows) { "Windows" } else { "Linux/macOS" };
 
-    log::info!("PortableSource - System Information:");
-    log::info!("  - Installation path: {}", install_path.display());
-    log::info!("  - Operating system: {}", os_name);
+    tracing::info!("PortableSource - System Information:");
+    tracing::info!("  - Installation path: {}", install_path.display());
+    tracing::info!("  - Operating system: {}", os_name);
 
     // Directory structure
-    log::info!("  - Directory structure:");
-    log::info!("    * {}{}ps_env", install_path.display(), slash);
-    log::info!("    * {}{}repos", install_path.display(), slash);
-    log::info!("    * {}{}envs", install_path.display(), slash);
+    tracing::info!("  - Directory structure:");
+    tracing::info!("    * {}{}ps_env", install_path.display(), slash);
+    tracing::info!("    * {}{}repos", install_path.display(), slash);
+    tracing::info!("    * {}{}envs", install_path.display(), slash);
 
     // GPU information
     if config_manager.has_cuda() {
-        log::info!("  - GPU: {}", config_manager.get_gpu_name());
-        log::info!("  - GPU type: {:?}", config_manager.detect_current_gpu_generation());
+        tracing::info!("  - GPU: {}", config_manager.get_gpu_name());
+        tracing::info!("  - GPU type: {:?}", config_manager.detect_current_gpu_generation());
         if let Some(cuda) = config_manager.get_cuda_version() {
-            log::info!("  - CUDA version: {:?}", cuda);
+            tracing::info!("  - CUDA version: {:?}", cuda);
         }
     } else {
-        log::info!("  - GPU: Not configured");
+        tracing::info!("  - GPU: Not configured");
     }
 
     // Portable environment
     if let Some(mgr) = env_manager {
         let available = mgr.check_environment_status()?;
-        log::info!("  - Portable Environment: {}", if available { "Available" } else { "Not available" });
+        tracing::info!("  - Portable Environment: {}", if available { "Available" } else { "Not available" });
         let base_created = mgr.get_python_executable().is_some();
-        log::info!("  - Base environment (ps_env): {}", if base_created { "Created" } else { "Not created" });
+        tracing::info!("  - Base environment (ps_env): {}", if base_created { "Created" } else { "Not created" });
     }
 
     let msvc_status = if check_msvc_build_tools_installed() { "Installed" } else { "Not installed" };
-    log::info!("  - MSVC Build Tools: {}", msvc_status);
+    tracing::info!("  - MSVC Build Tools: {}", msvc_status);
     Ok(())
 }
 
@@ -1117,24 +1219,17 @@ pub fn change_installation_path_interactive(config_manager: &mut ConfigManager)
     let new_path = if input.is_empty() { default_path } else { validate_and_get_path(input)? };
 
     println!("\nNew installation path: {}", new_path.display());
-    if new_path.exists() && fs::read_dir(&new_path).map(|mut it| it.next().is_some()).unwrap_or(false) {
-        loop {
-            print!("Continue? (y/n): ");
-            io::stdout().flush().ok();
-            let mut confirm = String::new();
-            io::stdin().read_line(&mut confirm).ok();
-            let c = confirm.trim().to_lowercase();
-            if c == "y" || c == "yes" { break; }
-            if c == "n" || c == "no" { println!("Path change cancelled."); return Ok(()); }
-            println!("Please enter 'y' or 'n'");
-        }
+    let not_empty = new_path.exists() && fs::read_dir(&new_path).map(|mut it| it.next().is_some()).unwrap_or(false);
+    if not_empty && !crate::prompt::confirm("The folder is not empty. Continue?") {
+        println!("Path change cancelled.");
+        return Ok(());
     }
 
     save_install_path_to_registry(&new_path)?;
     config_manager.set_install_path(new_path.clone())?;
-    log::info!("[OK] Installation path successfully changed");
-    log::info!("New path: {:?}", new_path);
-    log::info!("Restart PortableSource to apply changes");
+    tracing::info!("[OK] Installation path successfully changed");
+    tracing::info!("New path: {:?}", new_path);
+    tracing::info!("Restart PortableSource to apply changes");
     Ok(())
 }
 
@@ -1146,6 +1241,12 @@ pub struct PortableSourceApp {
     pub repository_installer: Option<RepositoryInstaller>,
 }
 
+impl Default for PortableSourceApp {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl PortableSourceApp {
     pub fn new() -> Self {
         Self { install_path: None, config_manager: None, environment_manager: None, repository_installer: None }
@@ -1190,7 +1291,7 @@ impl PortableSourceApp {
         {
             let chosen = prompt_install_path_linux(&default_path)?;
             save_install_path_to_registry(&chosen)?;
-            return Ok(chosen);
+            Ok(chosen)
         }
         
         #[cfg(windows)]
@@ -1209,17 +1310,9 @@ impl PortableSourceApp {
             let chosen = if input.is_empty() { default_path } else { validate_and_get_path(input)? };
             println!("\nChosen installation path: {}", chosen.display());
 
-            if chosen.exists() && fs::read_dir(&chosen).map(|mut it| it.next().is_some()).unwrap_or(false) {
-                loop {
-                    print!("Continue? (y/n): ");
-                    io::stdout().flush().ok();
-                    let mut confirm = String::new();
-                    io::stdin().read_line(&mut confirm).ok();
-                    let c = confirm.trim().to_lowercase();
-                    if c == "y" || c == "yes" { break; }
-                    if c == "n" || c == "no" { return Err(PortableSourceError::installation("Installation cancelled")); }
-                    println!("Please enter 'y' or 'n'");
-                }
+            let not_empty = chosen.exists() && fs::read_dir(&chosen).map(|mut it| it.next().is_some()).unwrap_or(false);
+            if not_empty && !crate::prompt::confirm("The folder is not empty. Continue?") {
+                return Err(PortableSourceError::installation("Installation cancelled"));
             }
 
             save_install_path_to_registry(&chosen)?;
@@ -1266,9 +1359,9 @@ impl PortableSourceApp {
         let env_ref = self.environment_manager.as_ref();
         show_system_info_detailed(install_path, cfg, env_ref)?;
         if let Ok(repos) = self.list_installed_repositories() {
-            log::info!("  - Installed repositories: {}", repos.len());
+            tracing::info!("  - Installed repositories: {}", repos.len());
             for repo in repos {
-                log::info!("    * {}", repo);
+                tracing::info!("    * {}", repo);
             }
         }
         Ok(())
@@ -1276,53 +1369,44 @@ impl PortableSourceApp {
 }
 
 #[cfg(unix)]
-pub async fn uninstall_portablesource(install_path: &PathBuf) -> Result<()> {
-    use std::io::{self, Write};
+pub async fn uninstall_portablesource(install_path: &Path, scope: &crate::uninstall::UninstallScope, dry_run: bool) -> Result<()> {
     use std::fs;
     
-    println!("[WARNING] This will completely remove PortableSource and all installed repositories!");
-    println!("Installation path: {}", install_path.display());
-    print!("Are you sure you want to continue? (yes/no): ");
-    io::stdout().flush().unwrap();
-    
-    let mut input = String::new();
-    io::stdin().read_line(&mut input).unwrap();
+    let config_dir = dirs::config_dir().map(|dir| dir.join("portablesource"));
+    let plan = crate::uninstall::plan(install_path, config_dir.as_deref(), scope)?;
+    if plan.is_empty() {
+        output::info(&format!("Nothing to remove in {}", install_path.display()));
+        return Ok(());
+    }
     
-    if input.trim().to_lowercase() != "yes" {
+    println!("Installation path: {}\n", install_path.display());
+    print!("{}", plan.render());
+    println!();
+    if dry_run {
+        output::info("Dry run: nothing was changed");
+        return Ok(());
+    }
+    if !crate::prompt::confirm_typed("Are you sure you want to continue?", "yes") {
         println!("Uninstall cancelled.");
         return Ok(());
     }
     
-    println!("\n[INFO] Removing PortableSource environment...");
-    
-    // Remove the entire installation directory
-    if install_path.exists() {
-        match fs::remove_dir_all(install_path) {
-            Ok(_) => println!("[SUCCESS] Environment directory removed: {}", install_path.display()),
-            Err(e) => {
-                log::error!("Failed to remove environment directory: {}", e);
-                println!("[ERROR] Failed to remove environment directory: {}", e);
-                return Err(e.into());
-            }
-        }
-    } else {
-        println!("[INFO] Environment directory not found: {}", install_path.display());
+    println!();
+    output::info("Removing...");
+    if let Err(e) = plan.execute() {
+        tracing::error!("Uninstall failed: {}", e);
+        output::error(&format!("Uninstall failed: {}", e));
+        return Err(e);
     }
-    
-    // Remove config directory if it exists
-    if let Some(config_dir) = dirs::config_dir() {
-        let portablesource_config = config_dir.join("portablesource");
-        if portablesource_config.exists() {
-            match fs::remove_dir_all(&portablesource_config) {
-                Ok(_) => println!("[SUCCESS] Config directory removed: {}", portablesource_config.display()),
-                Err(e) => println!("[WARNING] Failed to remove config directory: {}", e),
-            }
-        }
+    if !plan.remove_executable {
+        output::success("Selected items removed.");
+        return Ok(());
     }
     
     // Get the current executable path
     let current_exe = std::env::current_exe()?;
-    println!("\n[INFO] Removing executable: {}", current_exe.display());
+    println!();
+    output::info(&format!("Removing executable: {}", current_exe.display()));
     
     // Create a self-deletion script
     let script_path = std::env::temp_dir().join("portablesource_uninstall.sh");
@@ -1340,8 +1424,8 @@ pub async fn uninstall_portablesource(install_path: &PathBuf) -> Result<()> {
     perms.set_mode(0o755);
     fs::set_permissions(&script_path, perms)?;
     
-    println!("[SUCCESS] PortableSource environment has been removed.");
-    println!("[INFO] Executing self-deletion...");
+    output::success("PortableSource environment has been removed.");
+    output::info("Executing self-deletion...");
     
     // Execute the self-deletion script
     std::process::Command::new("bash")
@@ -1352,27 +1436,40 @@ pub async fn uninstall_portablesource(install_path: &PathBuf) -> Result<()> {
     std::process::exit(0);
 }
 
-pub async fn run_repository(repo: &str, install_path: &PathBuf, additional_args: &[String]) -> Result<()> {
+/// Launch options of run-repo
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    /// Block network access for the launched repo
+    pub no_network: bool,
+    /// CUDA_VISIBLE_DEVICES of the launch (`run-repo --gpu`), e.g. a MIG instance UUID
+    pub visible_device: Option<String>,
+    /// Sample GPU usage and append a run record to the repo metadata
+    pub record_stats: bool,
+    /// Temp/home folder for the launched repo instead of `<install>/tmp` (read-only installs)
+    pub tmp_dir: Option<PathBuf>,
+    /// Pipe the repository's stderr to keep its end for out-of-memory advice
+    pub capture_stderr: bool,
+    /// Folder of the last-run logs: `<install>/logs`, or the user's folder of a read-only install
+    pub log_dir: PathBuf,
+}
+
+pub async fn run_repository(repo: &str, install_path: &Path, additional_args: &[String], options: &RunOptions) -> Result<()> {
     let repo_path = install_path.join("repos").join(repo);
     
     if !repo_path.exists() {
-        println!("[ERROR] Repository '{}' not found at: {}", repo, repo_path.display());
+        output::error(&format!("Repository '{}' not found at: {}", repo, repo_path.display()));
         return Err(PortableSourceError::repository(format!("Repository '{}' not installed", repo)));
     }
     
-    // Look for start script based on platform
-    #[cfg(windows)]
-    let start_script = repo_path.join(format!("start_{}.bat", repo));
-    #[cfg(unix)]
-    let start_script = repo_path.join(format!("start_{}.sh", repo));
+    let start_script = crate::launch_command::start_script(install_path, repo);
     
     if !start_script.exists() {
-        println!("[ERROR] Start script not found: {}", start_script.display());
+        output::error(&format!("Start script not found: {}", start_script.display()));
         return Err(PortableSourceError::repository(format!("Start script for '{}' not found", repo)));
     }
     
-    println!("[INFO] Running repository: {}", repo);
-    println!("[INFO] Executing: {}", start_script.display());
+    output::info(&format!("Running repository: {}", repo));
+    output::info(&format!("Executing: {}", start_script.display()));
     
     // Prepare arguments
     #[cfg(unix)]
@@ -1383,112 +1480,215 @@ pub async fn run_repository(repo: &str, install_path: &PathBuf, additional_args:
     // Note: Docker detection and fallback logic is handled in try_run_with_fallback function
     
     if !args.is_empty() {
-        println!("[INFO] Additional arguments: {}", args.join(" "));
+        output::info(&format!("Additional arguments: {}", args.join(" ")));
+    }
+    
+    // Vendor GPU variables (ROCm / oneAPI / MPS) for the launched repo
+    let mut run_env = crate::envs_manager::run_environment();
+    if let Some(device) = &options.visible_device {
+        run_env.insert("CUDA_VISIBLE_DEVICES".to_string(), device.clone());
+        // Number devices the way nvidia-smi does for anything the repo selects by index
+        run_env.insert("CUDA_DEVICE_ORDER".to_string(), "PCI_BUS_ID".to_string());
+    }
+    if let Some(tmp) = &options.tmp_dir {
+        // Start scripts read PORTABLESOURCE_TMP; TMPDIR covers scripts without portable homes
+        fs::create_dir_all(tmp.join("Temp"))?;
+        run_env.insert("PORTABLESOURCE_TMP".to_string(), tmp.to_string_lossy().to_string());
+        run_env.insert("TMPDIR".to_string(), tmp.join("Temp").to_string_lossy().to_string());
     }
     
-    // Execute the start script based on platform
+    // --no-network: isolation lives until the launch returns (Windows firewall rules are removed on drop)
+    let isolation = if options.no_network {
+        match NetworkIsolation::prepare(repo, install_path) {
+            Ok(iso) => {
+                output::info(&format!("Network isolation: {}", iso.describe()));
+                Some(iso)
+            }
+            Err(reason) => {
+                output::warn(&format!("Network isolation cannot be enforced for '{}': {}", repo, reason));
+                net_isolation::confirm_unisolated_launch(repo)?;
+                None
+            }
+        }
+    } else {
+        None
+    };
+    
+    let stats = options.record_stats.then(|| RunStatsRecorder::start(&repo_path, &args));
+    // Ctrl+C stops the app only; the firewall rules are removed and the run recorded after it exits
+    let _ctrl_c = (isolation.is_some() || stats.is_some()).then(crate::system::defer_ctrl_c);
+    
+    // Execute the start script based on platform; the exit code also goes into run statistics
     #[cfg(windows)]
-    {
-        let mut cmd = std::process::Command::new("cmd");
-        cmd.args(["/C", start_script.to_str().unwrap()]);
-        cmd.args(&args);
+    let (exit_code, result) = {
+        let mut cmd_args = vec!["/C".to_string(), start_script.to_string_lossy().to_string()];
+        cmd_args.extend(args.iter().cloned());
+        let mut cmd = net_isolation::command(isolation.as_ref(), "cmd", &cmd_args);
+        cmd.envs(&run_env);
         
-        let status = cmd.status()?;
+        match status_with_stderr_tail(&mut cmd, options.capture_stderr) {
+            Ok((status, tail)) => (status.code(), report_run_exit(repo, status, tail.as_deref(), options)),
+            Err(e) => (None, Err(e.into())),
+        }
+    };
+
+    #[cfg(unix)]
+    let (exit_code, result) = if is_running_in_docker() {
+        // Try with fallback mechanism for Docker
+        let result = try_run_with_fallback(&start_script, additional_args, repo, &run_env, isolation.as_ref());
+        (result.is_ok().then_some(0), result)
+    } else {
+        let mut cmd = bash_command(isolation.as_ref(), &start_script, &args);
+        cmd.envs(&run_env);
         
-        if status.success() {
-            println!("[SUCCESS] Repository '{}' executed successfully", repo);
-        } else {
-            println!("[ERROR] Repository '{}' execution failed with exit code: {:?}", repo, status.code());
-            return Err(PortableSourceError::command(format!("Repository '{}' execution failed", repo)));
+        match status_with_stderr_tail(&mut cmd, options.capture_stderr) {
+            Ok((status, tail)) => (status.code(), report_run_exit(repo, status, tail.as_deref(), options)),
+            Err(e) => (None, Err(e.into())),
         }
+    };
+    
+    if let Some(stats) = stats {
+        stats.finish(exit_code);
     }
+    result
+}
 
+/// Run `cmd`; with `capture`, its stderr is passed through as it arrives and the last
+/// `RUN_LOG_TAIL` bytes are returned with the exit status, otherwise stderr is inherited
+fn status_with_stderr_tail(cmd: &mut Command, capture: bool) -> std::io::Result<(std::process::ExitStatus, Option<String>)> {
+    use std::io::{Read, Write};
+    if !capture {
+        return Ok((cmd.status()?, None));
+    }
+    let mut child = cmd.stderr(std::process::Stdio::piped()).spawn()?;
+    let mut pipe = child.stderr.take().expect("stderr is piped");
+    let mut stderr = std::io::stderr();
+    let mut tail = Vec::new();
+    let mut buf = [0u8; 8192];
+    loop {
+        let n = match pipe.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        };
+        let _ = stderr.write_all(&buf[..n]);
+        let _ = stderr.flush();
+        tail.extend_from_slice(&buf[..n]);
+        if tail.len() > oom_advice::RUN_LOG_TAIL {
+            tail.drain(..tail.len() - oom_advice::RUN_LOG_TAIL);
+        }
+    }
+    Ok((child.wait()?, Some(String::from_utf8_lossy(&tail).into_owned())))
+}
+
+/// The app ended because of Ctrl+C rather than by failing
+fn was_interrupted(status: std::process::ExitStatus) -> bool {
     #[cfg(unix)]
     {
-        // Try with fallback mechanism for Docker
-        if is_running_in_docker() {
-            if let Err(e) = try_run_with_fallback(&start_script, &additional_args, repo) {
-                return Err(e);
-            }
-        } else {
-            let mut cmd = std::process::Command::new("bash");
-            cmd.arg(&start_script);
-            cmd.args(&args);
-            
-            let status = cmd.status()?;
-            
-            if status.success() {
-                println!("[SUCCESS] Repository '{}' executed successfully", repo);
-            } else {
-                println!("[ERROR] Repository '{}' execution failed with exit code: {:?}", repo, status.code());
-                return Err(PortableSourceError::command(format!("Repository '{}' execution failed", repo)));
-            }
+        use std::os::unix::process::ExitStatusExt;
+        status.signal() == Some(libc::SIGINT) || status.code() == Some(130)
+    }
+    #[cfg(windows)]
+    {
+        const STATUS_CONTROL_C_EXIT: u32 = 0xC000013A;
+        status.code().map(|c| c as u32) == Some(STATUS_CONTROL_C_EXIT)
+    }
+}
+
+/// Not a failure to diagnose, and not retried with other arguments
+fn interrupted(repo: &str) -> PortableSourceError {
+    PortableSourceError::command(format!("Repository '{}' was interrupted", repo))
+}
+
+fn report_run_exit(repo: &str, status: std::process::ExitStatus, stderr_tail: Option<&str>, options: &RunOptions) -> Result<()> {
+    if let Some(tail) = stderr_tail {
+        let log = oom_advice::run_log_path(&options.log_dir, repo);
+        if let Err(e) = fs::create_dir_all(&options.log_dir).map_err(Into::into).and_then(|_| crate::atomic_write::write(&log, tail)) {
+            tracing::debug!("Cannot write the run log of {}: {}", repo, e);
         }
     }
-    
-    Ok(())
+    let stderr_tail = stderr_tail.unwrap_or_default();
+    if status.success() {
+        output::success(&format!("Repository '{}' executed successfully", repo));
+        Ok(())
+    } else if was_interrupted(status) {
+        Err(interrupted(repo))
+    } else {
+        output::error(&format!("Repository '{}' execution failed with exit code: {:?}", repo, status.code()));
+        // The log tells a GPU out of memory (or a full disk) from other failures
+        Err(PortableSourceError::from_command_output(format!("Repository '{}' execution failed", repo), stderr_tail))
+    }
+}
+
+#[cfg(unix)]
+fn bash_command(isolation: Option<&NetworkIsolation>, start_script: &Path, args: &[String]) -> std::process::Command {
+    let mut bash_args = vec![start_script.to_string_lossy().to_string()];
+    bash_args.extend(args.iter().cloned());
+    net_isolation::command(isolation, "bash", &bash_args)
 }
 
 #[cfg(unix)]
-fn try_run_with_fallback(start_script: &PathBuf, additional_args: &[String], repo: &str) -> Result<()> {
+fn try_run_with_fallback(start_script: &Path, additional_args: &[String], repo: &str, run_env: &std::collections::HashMap<String, String>, isolation: Option<&NetworkIsolation>) -> Result<()> {
     // Try 1: --listen 0.0.0.0
-    println!("[INFO] Trying with --listen 0.0.0.0");
+    output::info("Trying with --listen 0.0.0.0");
     let mut args_with_listen = additional_args.to_vec();
     args_with_listen.push("--listen".to_string());
     args_with_listen.push("0.0.0.0".to_string());
     
-    let mut cmd = std::process::Command::new("bash");
-    cmd.arg(start_script);
-    cmd.args(&args_with_listen);
+    let mut cmd = bash_command(isolation, start_script, &args_with_listen);
+    cmd.envs(run_env);
     
     match cmd.status() {
         Ok(status) if status.success() => {
-            println!("[SUCCESS] Repository '{}' executed successfully with --listen 0.0.0.0", repo);
+            output::success(&format!("Repository '{}' executed successfully with --listen 0.0.0.0", repo));
             return Ok(());
         }
+        Ok(status) if was_interrupted(status) => return Err(interrupted(repo)),
         Ok(status) => {
-            println!("[WARNING] Failed with --listen 0.0.0.0, exit code: {:?}", status.code());
+            output::warn(&format!("Failed with --listen 0.0.0.0, exit code: {:?}", status.code()));
         }
         Err(e) => {
-            println!("[WARNING] Failed to execute with --listen 0.0.0.0: {}", e);
+            output::warn(&format!("Failed to execute with --listen 0.0.0.0: {}", e));
         }
     }
     
     // Try 2: --listen only
-    println!("[INFO] Trying with --listen only");
+    output::info("Trying with --listen only");
     let mut args_with_listen_only = additional_args.to_vec();
     args_with_listen_only.push("--listen".to_string());
     
-    let mut cmd = std::process::Command::new("bash");
-    cmd.arg(start_script);
-    cmd.args(&args_with_listen_only);
+    let mut cmd = bash_command(isolation, start_script, &args_with_listen_only);
+    cmd.envs(run_env);
     
     match cmd.status() {
         Ok(status) if status.success() => {
-            println!("[SUCCESS] Repository '{}' executed successfully with --listen only", repo);
+            output::success(&format!("Repository '{}' executed successfully with --listen only", repo));
             return Ok(());
         }
+        Ok(status) if was_interrupted(status) => return Err(interrupted(repo)),
         Ok(status) => {
-            println!("[WARNING] Failed with --listen only, exit code: {:?}", status.code());
+            output::warn(&format!("Failed with --listen only, exit code: {:?}", status.code()));
         }
         Err(e) => {
-            println!("[WARNING] Failed to execute with --listen only: {}", e);
+            output::warn(&format!("Failed to execute with --listen only: {}", e));
         }
     }
     
     // Try 3: No additional listen arguments
-    println!("[INFO] Trying without additional listen arguments");
-    let mut cmd = std::process::Command::new("bash");
-    cmd.arg(start_script);
-    cmd.args(additional_args);
+    output::info("Trying without additional listen arguments");
+    let mut cmd = bash_command(isolation, start_script, additional_args);
+    cmd.envs(run_env);
     
     let status = cmd.status()?;
     
     if status.success() {
-        println!("[SUCCESS] Repository '{}' executed successfully without listen arguments", repo);
+        output::success(&format!("Repository '{}' executed successfully without listen arguments", repo));
         Ok(())
+    } else if was_interrupted(status) {
+        Err(interrupted(repo))
     } else {
-        println!("[ERROR] All fallback attempts failed. Repository '{}' execution failed with exit code: {:?}", repo, status.code());
+        output::error(&format!("All fallback attempts failed. Repository '{}' execution failed with exit code: {:?}", repo, status.code()));
         Err(PortableSourceError::command(format!("Repository '{}' execution failed after all fallback attempts", repo)))
     }
 }
@@ -1538,4 +1738,21 @@ mod tests {
         // This should not be available
         assert!(!is_command_available("nonexistent_command_12345"));
     }
+
+    #[test]
+    fn test_is_dir_writable() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(is_dir_writable(dir.path()));
+        assert!(!is_dir_writable(&dir.path().join("missing")));
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0, "probe file must be removed");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn an_app_ended_by_ctrl_c_counts_as_interrupted() {
+        let status = |script: &str| Command::new("sh").args(["-c", script]).status().unwrap();
+        assert!(was_interrupted(status("kill -INT $$")));
+        assert!(was_interrupted(status("exit 130")));
+        assert!(!was_interrupted(status("exit 1")));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,125 @@
+//! Repair of repository venvs whose interpreter links broke
+//!
+//! Linux venvs link `bin/python*` to the base interpreter (micromamba base or system
+//! python3). After that interpreter is upgraded or moved the links dangle and the start
+//! script dies with "No such file or directory". Before a repository is run the links are
+//! checked: with the same Python minor version they are pointed at the current base
+//! interpreter, otherwise the environment has to be rebuilt from the repository's
+//! requirements.
+
+use crate::{PortableSourceError, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const VENV_CONFIG: &str = "pyvenv.cfg";
+
+/// Interpreter links in `<venv>/bin` whose target no longer exists
+pub fn dangling_links(venv: &Path) -> Vec<PathBuf> {
+    let mut links: Vec<PathBuf> = fs::read_dir(venv.join("bin"))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.file_name().is_some_and(|n| n.to_string_lossy().starts_with("python")))
+        .filter(|p| fs::symlink_metadata(p).is_ok_and(|m| m.file_type().is_symlink()) && fs::metadata(p).is_err())
+        .collect();
+    links.sort();
+    links
+}
+
+/// Python `major.minor` the venv was created with (`version` in pyvenv.cfg, else the lib folder)
+pub fn venv_python_version(venv: &Path) -> Option<(u32, u32)> {
+    let parse = |s: &str| {
+        let mut parts = s.trim().split('.').map(|p| p.parse::<u32>().ok());
+        Some((parts.next()??, parts.next()??))
+    };
+    let config = fs::read_to_string(venv.join(VENV_CONFIG)).unwrap_or_default();
+    let from_config = config.lines().find_map(|line| {
+        let (key, value) = line.split_once('=')?;
+        matches!(key.trim(), "version" | "version_info").then(|| parse(value)).flatten()
+    });
+    from_config.or_else(|| {
+        fs::read_dir(venv.join("lib"))
+            .into_iter()
+            .flatten()
+            .flatten()
+            .find_map(|e| e.file_name().to_string_lossy().strip_prefix("python").and_then(parse))
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Repair {
+    /// Point the links at the current base interpreter (same minor version)
+    Relink,
+    /// Installed packages were built for another Python: recreate the environment
+    Rebuild,
+}
+
+pub fn plan_repair(venv_version: Option<(u32, u32)>, base_version: (u32, u32)) -> Repair {
+    if venv_version == Some(base_version) { Repair::Relink } else { Repair::Rebuild }
+}
+
+/// Point the dangling links of `venv` at `base_python` and record its folder in pyvenv.cfg
+#[cfg(unix)]
+pub fn relink(venv: &Path, base_python: &Path) -> Result<()> {
+    let base_python = fs::canonicalize(base_python)
+        .map_err(|e| PortableSourceError::environment(format!("Base Python {:?} is not usable: {}", base_python, e)))?;
+    for link in dangling_links(venv) {
+        fs::remove_file(&link)?;
+        std::os::unix::fs::symlink(&base_python, &link)?;
+    }
+
+    let config_path = venv.join(VENV_CONFIG);
+    let Ok(config) = fs::read_to_string(&config_path) else { return Ok(()) };
+    let home = base_python.parent().unwrap_or(Path::new("/")).display().to_string();
+    let lines: Vec<String> = config
+        .lines()
+        .map(|line| match line.split_once('=').map(|(k, _)| k.trim()) {
+            Some("home") => format!("home = {}", home),
+            Some("executable") => format!("executable = {}", base_python.display()),
+            _ => line.to_string(),
+        })
+        .collect();
+    crate::atomic_write::write(&config_path, lines.join("\n") + "\n")?;
+    Ok(())
+}
+
+/// Windows environments are copies of the interpreter, there is nothing to link
+#[cfg(not(unix))]
+pub fn relink(_venv: &Path, _base_python: &Path) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dangling_interpreter_links_are_pointed_at_the_new_base() {
+        let dir = tempfile::tempdir().unwrap();
+        let venv = dir.path().join("envs").join("comfyui");
+        let bin = venv.join("bin");
+        fs::create_dir_all(&bin).unwrap();
+        fs::create_dir_all(venv.join("lib").join("python3.11")).unwrap();
+        fs::write(venv.join(VENV_CONFIG), "home = /old/mamba_env/bin\ninclude-system-site-packages = false\nversion = 3.11.8\n").unwrap();
+        std::os::unix::fs::symlink("/old/mamba_env/bin/python3.11", bin.join("python")).unwrap();
+        std::os::unix::fs::symlink("python", bin.join("python3")).unwrap();
+        fs::write(bin.join("pip"), "#!/bin/sh\n").unwrap();
+
+        let links = dangling_links(&venv);
+        assert_eq!(links, [bin.join("python"), bin.join("python3")]);
+        assert_eq!(venv_python_version(&venv), Some((3, 11)));
+        assert_eq!(plan_repair(Some((3, 11)), (3, 11)), Repair::Relink);
+        assert_eq!(plan_repair(Some((3, 11)), (3, 12)), Repair::Rebuild);
+        assert_eq!(plan_repair(None, (3, 11)), Repair::Rebuild);
+
+        let base = dir.path().join("mamba_env").join("bin");
+        fs::create_dir_all(&base).unwrap();
+        fs::write(base.join("python3.11"), "").unwrap();
+        relink(&venv, &base.join("python3.11")).unwrap();
+        assert!(dangling_links(&venv).is_empty());
+        let config = fs::read_to_string(venv.join(VENV_CONFIG)).unwrap();
+        assert!(config.starts_with(&format!("home = {}\n", fs::canonicalize(&base).unwrap().display())));
+        assert!(config.contains("version = 3.11.8"));
+    }
+}
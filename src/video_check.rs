@@ -0,0 +1,178 @@
+//! Hardware video encoding check: ffmpeg NVENC on the provisioned CUDA/driver
+//!
+//! Video repositories (facefusion and the like) encode through ffmpeg's NVENC encoders. The
+//! portable ffmpeg may be built without them, or the NVIDIA driver may be older than the
+//! NVENC API the build expects. `check-env` lists the NVENC encoders and runs a one-frame
+//! test encode with the same environment repositories get, then suggests a fix or CPU flags.
+
+use crate::system::{CommandExecutor, CommandRequest};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+pub const NVENC_ENCODERS: &[&str] = &["h264_nvenc", "hevc_nvenc", "av1_nvenc"];
+
+/// ffmpeg flags for software encoding when NVENC is not usable
+pub const CPU_ENCODE_FLAGS: &str = "-c:v libx264 -preset veryfast -crf 18";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NvencFailure {
+    /// The driver is older than the NVENC API the ffmpeg build was compiled against
+    DriverTooOld,
+    /// No NVENC-capable device, or the CUDA driver library could not be loaded
+    NoDevice,
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NvencStatus {
+    /// The test encode went through
+    Working { encoder: String },
+    NoFfmpeg,
+    /// ffmpeg build without NVENC encoders
+    NotCompiled,
+    /// No NVIDIA GPU, the test encode was skipped
+    NoGpu,
+    Failed { encoder: String, reason: NvencFailure, detail: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VideoCheck {
+    pub ffmpeg: Option<PathBuf>,
+    pub encoders: Vec<String>,
+    pub status: NvencStatus,
+}
+
+impl VideoCheck {
+    /// One-line status for check-env
+    pub fn summary(&self) -> String {
+        match &self.status {
+            NvencStatus::Working { encoder } => format!("OK ({} test encode passed)", encoder),
+            NvencStatus::NoFfmpeg => "ffmpeg not found".to_string(),
+            NvencStatus::NotCompiled => "ffmpeg has no NVENC encoders".to_string(),
+            NvencStatus::NoGpu => "skipped (no NVIDIA GPU)".to_string(),
+            NvencStatus::Failed { encoder, detail, .. } => format!("{} test encode failed: {}", encoder, detail),
+        }
+    }
+
+    pub fn remediation(&self) -> Option<String> {
+        let cpu = format!(
+            "or encode on the CPU with '{}' (facefusion: --output-video-encoder libx264)",
+            CPU_ENCODE_FLAGS
+        );
+        match &self.status {
+            NvencStatus::Working { .. } | NvencStatus::NoGpu => None,
+            NvencStatus::NoFfmpeg => Some("Run 'portablesource setup-env' to install the portable ffmpeg".to_string()),
+            NvencStatus::NotCompiled => Some(format!(
+                "Replace ps_env/ffmpeg with a full ffmpeg build that includes NVENC (e.g. gyan.dev 'full' or BtbN GPL builds), {}",
+                cpu
+            )),
+            NvencStatus::Failed { reason: NvencFailure::DriverTooOld, .. } => Some(format!(
+                "Update the NVIDIA driver to one that supports this ffmpeg's NVENC API (it must also match the CUDA version in use), {}",
+                cpu
+            )),
+            NvencStatus::Failed { reason: NvencFailure::NoDevice, .. } => Some(format!(
+                "NVENC could not open the GPU: check that the NVIDIA driver is installed and the card supports NVENC, {}",
+                cpu
+            )),
+            NvencStatus::Failed { reason: NvencFailure::Other, .. } => Some(format!("Hardware encoding is unavailable, {}", cpu)),
+        }
+    }
+}
+
+/// NVENC encoder names from `ffmpeg -encoders`
+pub fn parse_nvenc_encoders(encoders_output: &str) -> Vec<String> {
+    encoders_output
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .filter(|name| NVENC_ENCODERS.contains(name))
+        .map(str::to_string)
+        .collect()
+}
+
+pub fn classify_failure(stderr: &str) -> NvencFailure {
+    let lower = stderr.to_lowercase();
+    if lower.contains("driver does not support the required nvenc api version") || lower.contains("minimum required nvidia driver") {
+        NvencFailure::DriverTooOld
+    } else if lower.contains("no capable devices found")
+        || lower.contains("no nvenc capable devices")
+        || lower.contains("cannot load nvcuda")
+        || lower.contains("cannot load libcuda")
+        || lower.contains("cannot load nvencodeapi")
+        || lower.contains("cannot load libnvidia-encode")
+    {
+        NvencFailure::NoDevice
+    } else {
+        NvencFailure::Other
+    }
+}
+
+/// Last meaningful stderr line, for the summary
+fn last_line(stderr: &str) -> String {
+    stderr.lines().map(str::trim).rfind(|l| !l.is_empty()).unwrap_or("unknown error").to_string()
+}
+
+/// List NVENC encoders and, with an NVIDIA GPU, encode one frame with the first of them
+pub fn run_check(executor: &dyn CommandExecutor, ffmpeg: Option<&Path>, envs: HashMap<String, String>, has_nvidia: bool) -> VideoCheck {
+    let Some(ffmpeg) = ffmpeg else {
+        return VideoCheck { ffmpeg: None, encoders: Vec::new(), status: NvencStatus::NoFfmpeg };
+    };
+    let program = ffmpeg.to_string_lossy().to_string();
+    let request = |args: &[&str]| CommandRequest {
+        program: program.clone(),
+        args: args.iter().map(|a| a.to_string()).collect(),
+        envs: envs.clone(),
+        ..Default::default()
+    };
+
+    let encoders = executor
+        .execute(&request(&["-hide_banner", "-encoders"]))
+        .map(|out| parse_nvenc_encoders(&out.stdout))
+        .unwrap_or_default();
+    let check = |status| VideoCheck { ffmpeg: Some(ffmpeg.to_path_buf()), encoders: encoders.clone(), status };
+
+    let Some(encoder) = encoders.first().cloned() else { return check(NvencStatus::NotCompiled) };
+    if !has_nvidia {
+        return check(NvencStatus::NoGpu);
+    }
+    // NVENC rejects frames smaller than ~145x49, so use a 256x256 test pattern
+    let test = request(&[
+        "-hide_banner", "-loglevel", "error",
+        "-f", "lavfi", "-i", "color=c=black:s=256x256:d=0.1",
+        "-frames:v", "1", "-c:v", &encoder, "-f", "null", "-",
+    ]);
+    match executor.execute(&test) {
+        Ok(out) if out.success() => check(NvencStatus::Working { encoder }),
+        Ok(out) => check(NvencStatus::Failed { encoder, reason: classify_failure(&out.stderr), detail: last_line(&out.stderr) }),
+        Err(e) => check(NvencStatus::Failed { encoder, reason: NvencFailure::Other, detail: e.to_string() }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockExecutor;
+
+    const ENCODERS: &str = "\
+Encoders:
+ V....D libx264              libx264 H.264 / AVC / MPEG-4 AVC
+ V....D h264_nvenc           NVIDIA NVENC H.264 encoder (codec h264)
+ V....D hevc_nvenc           NVIDIA NVENC hevc encoder (codec hevc)
+";
+
+    #[test]
+    fn old_driver_is_reported_with_cpu_fallback() {
+        let executor = MockExecutor::new();
+        executor.succeed_with("-encoders", ENCODERS);
+        executor.fail_on("-c:v h264_nvenc", "[h264_nvenc @ 0x1] Driver does not support the required nvenc API version. Required: 12.1 Found: 11.1\n");
+
+        let check = run_check(&executor, Some(Path::new("ffmpeg")), HashMap::new(), true);
+        assert_eq!(check.encoders, ["h264_nvenc", "hevc_nvenc"]);
+        assert!(matches!(check.status, NvencStatus::Failed { reason: NvencFailure::DriverTooOld, .. }));
+        assert!(check.remediation().unwrap().contains(CPU_ENCODE_FLAGS));
+
+        let plain = MockExecutor::new();
+        plain.succeed_with("-encoders", " V....D libx264   libx264 H.264\n");
+        assert_eq!(run_check(&plain, Some(Path::new("ffmpeg")), HashMap::new(), true).status, NvencStatus::NotCompiled);
+        assert_eq!(plain.calls().len(), 1, "no test encode without NVENC encoders");
+    }
+}
@@ -0,0 +1,311 @@
+//! Warm start of new environments from the most similar installed one
+//!
+//! Most repositories share the bulk of their packages; the torch stack alone is several
+//! GB. Before the requirements of a new repository are installed, the environment in envs/
+//! that already has most of them is picked and its site-packages are cloned into the fresh
+//! venv. Compiled files, the bulk of the size, are hard-linked where the file system allows
+//! it; text files (sources, RECORD, .pth, scripts) are copied, since tools rewrite those in
+//! place and would change the donor through a shared link. The normal install then only
+//! fetches what is missing or pinned differently. `install-repo --clean-env` opts out.
+
+use crate::cache_gc;
+use crate::installer::pip_manager::{normalize_package_name, requirement_name};
+use crate::path_rewrite;
+use crate::{PortableSourceError, Result};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Share of the new requirements a donor must already have installed
+const MIN_COVERAGE: f64 = 0.5;
+/// Below this many shared packages a clone saves less than it carries over
+const MIN_SHARED: usize = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Donor {
+    /// Environment folder name in envs/
+    pub name: String,
+    pub path: PathBuf,
+    /// Requirements of the new repository installed there
+    pub shared: usize,
+    pub required: usize,
+}
+
+impl Donor {
+    pub fn describe(&self) -> String {
+        format!("'{}' ({} of {} requirements installed)", self.name, self.shared, self.required)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CloneReport {
+    pub files: usize,
+    /// Binary files hard-linked instead of copied
+    pub linked: usize,
+    /// Packages the donor installed from a local folder (its own repository); they are
+    /// uninstalled from the clone
+    pub local_packages: Vec<String>,
+    /// `name==version` of packages whose executables name the donor's interpreter inside a
+    /// binary launcher (Windows); reinstalling them writes new launchers
+    pub reinstall: Vec<String>,
+}
+
+/// Project names the requirements `content` declares
+pub fn requirement_names(content: &str) -> BTreeSet<String> {
+    content.lines().filter_map(requirement_name).collect()
+}
+
+/// Normalized names of the distributions installed in `env`
+pub fn installed_names(env: &Path) -> BTreeSet<String> {
+    cache_gc::site_packages_dirs(env)
+        .into_iter()
+        .flat_map(|dir| fs::read_dir(dir).into_iter().flatten().flatten())
+        .filter_map(|e| cache_gc::parse_dist_info(&e.file_name().to_string_lossy()))
+        .map(|(name, _)| normalize_package_name(&name))
+        .collect()
+}
+
+/// Python `major.minor` of an environment: pyvenv.cfg or lib/pythonX.Y of a venv, the
+/// pythonXY.dll of a copied Windows interpreter
+pub fn python_version(env: &Path) -> Option<(u32, u32)> {
+    crate::venv_repair::venv_python_version(env).or_else(|| {
+        fs::read_dir(env).into_iter().flatten().flatten().find_map(|e| {
+            let name = e.file_name().to_string_lossy().to_lowercase();
+            let digits = name.strip_prefix("python3")?.strip_suffix(".dll")?;
+            Some((3, digits.parse().ok()?))
+        })
+    })
+}
+
+/// The environment in `envs` other than `target` with most of `required` installed and the
+/// same Python; ties go to the smaller environment, which carries fewer unneeded packages
+pub fn find_donor(envs: &Path, target: &str, python: Option<(u32, u32)>, required: &BTreeSet<String>) -> Option<Donor> {
+    let python = python?;
+    let mut best: Option<(Donor, usize)> = None;
+    for entry in fs::read_dir(envs).ok()?.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let path = entry.path();
+        if name.eq_ignore_ascii_case(target) || !path.is_dir() || python_version(&path) != Some(python) {
+            continue;
+        }
+        let installed = installed_names(&path);
+        let shared = required.intersection(&installed).count();
+        if shared < MIN_SHARED || (shared as f64) < MIN_COVERAGE * required.len() as f64 {
+            continue;
+        }
+        let better = match &best {
+            Some((donor, size)) => shared > donor.shared || (shared == donor.shared && installed.len() < *size),
+            None => true,
+        };
+        if better {
+            best = Some((Donor { name, path, shared, required: required.len() }, installed.len()));
+        }
+    }
+    best.map(|(donor, _)| donor)
+}
+
+/// Hard-link a binary `from` to `to`, copying text files and when linking is not possible;
+/// true when linked
+fn link_binary_or_copy(from: &Path, to: &Path) -> Result<bool> {
+    if path_rewrite::is_binary(from)? && fs::hard_link(from, to).is_ok() {
+        return Ok(true);
+    }
+    fs::copy(from, to)?;
+    Ok(false)
+}
+
+fn scripts_dir(env: &Path) -> PathBuf {
+    if cfg!(windows) { env.join("Scripts") } else { env.join("bin") }
+}
+
+/// Distribution (`name==version`) that installed each executable, by file name, from the
+/// `../`-relative entries of `*.dist-info/RECORD`
+fn script_owners(site_packages: &Path) -> HashMap<String, String> {
+    let mut owners = HashMap::new();
+    for entry in fs::read_dir(site_packages).into_iter().flatten().flatten() {
+        let Some((name, version)) = cache_gc::parse_dist_info(&entry.file_name().to_string_lossy()) else { continue };
+        let Ok(record) = fs::read_to_string(entry.path().join("RECORD")) else { continue };
+        for file in record.lines().filter_map(|line| line.split(',').next()).filter(|f| f.starts_with("..")) {
+            if let Some(script) = file.rsplit(['/', '\\']).next() {
+                owners.insert(script.to_string(), format!("{}=={}", normalize_package_name(&name), version));
+            }
+        }
+    }
+    owners
+}
+
+/// Distributions in `site_packages` installed from a local folder (`dir_info` in direct_url.json)
+fn local_packages(site_packages: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(site_packages)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|e| {
+            fs::read_to_string(e.path().join("direct_url.json"))
+                .ok()
+                .and_then(|json| serde_json::from_str::<serde_json::Value>(&json).ok())
+                .is_some_and(|url| url.get("dir_info").is_some())
+        })
+        .filter_map(|e| cache_gc::parse_dist_info(&e.file_name().to_string_lossy()))
+        .map(|(name, _)| normalize_package_name(&name))
+        .collect();
+    names.sort();
+    names
+}
+
+/// Replace the site-packages of the fresh environment `target` with a clone of those of
+/// `donor`, and carry over the executables the fresh environment does not have
+pub fn clone_env(donor: &Path, target: &Path) -> Result<CloneReport> {
+    let (Some(from), Some(to)) = (cache_gc::site_packages_dirs(donor).into_iter().next(), cache_gc::site_packages_dirs(target).into_iter().next()) else {
+        return Err(PortableSourceError::environment(format!("No site-packages in {:?} or {:?}", donor, target)));
+    };
+    let mut report = CloneReport::default();
+    fs::remove_dir_all(&to)?;
+    for entry in WalkDir::new(&from).follow_links(false) {
+        let entry = entry.map_err(|e| PortableSourceError::environment(format!("Failed to read {:?}: {}", from, e)))?;
+        let dest = to.join(entry.path().strip_prefix(&from).unwrap_or(entry.path()));
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&dest)?;
+        } else {
+            report.files += 1;
+            if link_binary_or_copy(entry.path(), &dest)? {
+                report.linked += 1;
+            }
+        }
+    }
+
+    // Console scripts name their interpreter: text ones are rewritten, binary launchers
+    // come back when their package is reinstalled
+    let (donor_prefix, target_prefix) = (donor.to_string_lossy().to_string(), target.to_string_lossy().to_string());
+    let owners = script_owners(&to);
+    let target_scripts = scripts_dir(target);
+    for entry in fs::read_dir(scripts_dir(donor)).into_iter().flatten().flatten() {
+        let dest = target_scripts.join(entry.file_name());
+        if dest.exists() || !entry.file_type().is_ok_and(|t| t.is_file()) {
+            continue;
+        }
+        let binary = path_rewrite::is_binary(&entry.path())?;
+        match fs::read_to_string(entry.path()) {
+            Ok(text) if !binary => {
+                fs::write(&dest, path_rewrite::replace_path(&text, &donor_prefix, &target_prefix).unwrap_or(text))?;
+                #[cfg(unix)]
+                fs::set_permissions(&dest, fs::metadata(entry.path())?.permissions())?;
+            }
+            _ => match owners.get(entry.file_name().to_string_lossy().as_ref()) {
+                Some(owner) if !report.reinstall.contains(owner) => report.reinstall.push(owner.clone()),
+                Some(_) => {}
+                None => {
+                    fs::copy(entry.path(), &dest)?;
+                }
+            },
+        }
+    }
+    report.local_packages = local_packages(&to);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(windows)]
+    const SITE_PACKAGES: &str = "Lib/site-packages";
+    #[cfg(not(windows))]
+    const SITE_PACKAGES: &str = "lib/python3.11/site-packages";
+
+    fn site_packages(env: &Path) -> PathBuf {
+        env.join(SITE_PACKAGES)
+    }
+
+    fn make_env(envs: &Path, name: &str, packages: &[&str]) -> PathBuf {
+        let env = envs.join(name);
+        fs::create_dir_all(site_packages(&env)).unwrap();
+        fs::create_dir_all(scripts_dir(&env)).unwrap();
+        fs::write(env.join("pyvenv.cfg"), "home = /usr/bin\nversion = 3.11.9\n").unwrap();
+        for package in packages {
+            fs::create_dir_all(site_packages(&env).join(format!("{}-1.0.dist-info", package))).unwrap();
+        }
+        env
+    }
+
+    fn comfy_and_fresh(envs: &Path) -> (PathBuf, PathBuf) {
+        let comfy = make_env(envs, "comfyui", &["torch", "torchvision", "numpy", "safetensors", "aiohttp", "comfyui_frontend"]);
+        (comfy, make_env(envs, "forge", &["pip"]))
+    }
+
+    const REQUIREMENTS: &str = "torch>=2.1\ntorchvision\nnumpy<2  # pinned\nsafetensors\ngradio==4.44\n";
+
+    #[test]
+    fn donor_is_the_env_with_most_requirements() {
+        let dir = tempfile::tempdir().unwrap();
+        let envs = dir.path().join("envs");
+        let (_, fresh) = comfy_and_fresh(&envs);
+        make_env(&envs, "small", &["torch", "numpy", "safetensors"]);
+        make_env(&envs, "other", &["flask", "requests"]);
+
+        let donor = find_donor(&envs, "forge", python_version(&fresh), &requirement_names(REQUIREMENTS)).unwrap();
+        assert_eq!((donor.name.as_str(), donor.shared, donor.required), ("comfyui", 4, 5));
+    }
+
+    #[test]
+    fn donor_needs_the_same_python() {
+        let dir = tempfile::tempdir().unwrap();
+        let envs = dir.path().join("envs");
+        comfy_and_fresh(&envs);
+        assert!(find_donor(&envs, "forge", Some((3, 12)), &requirement_names(REQUIREMENTS)).is_none());
+    }
+
+    #[test]
+    fn clone_carries_the_packages() {
+        let dir = tempfile::tempdir().unwrap();
+        let (comfy, fresh) = comfy_and_fresh(&dir.path().join("envs"));
+
+        let report = clone_env(&comfy, &fresh).unwrap();
+        assert_eq!(installed_names(&fresh), installed_names(&comfy));
+        assert!(report.reinstall.is_empty());
+    }
+
+    #[test]
+    fn packages_installed_from_a_local_folder_are_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let (comfy, fresh) = comfy_and_fresh(&dir.path().join("envs"));
+        let site = site_packages(&comfy);
+        fs::write(site.join("comfyui_frontend-1.0.dist-info/direct_url.json"), r#"{"url": "file:///x", "dir_info": {}}"#).unwrap();
+
+        assert_eq!(clone_env(&comfy, &fresh).unwrap().local_packages, ["comfyui-frontend"]);
+    }
+
+    #[test]
+    fn only_binaries_are_shared_with_the_donor() {
+        let dir = tempfile::tempdir().unwrap();
+        let (comfy, fresh) = comfy_and_fresh(&dir.path().join("envs"));
+        let site = site_packages(&comfy);
+        fs::create_dir_all(site.join("numpy")).unwrap();
+        fs::write(site.join("numpy/__init__.py"), "version = 1\n").unwrap();
+        fs::write(site.join("numpy/_core.so"), b"\x7fELF\0\0compiled").unwrap();
+
+        let report = clone_env(&comfy, &fresh).unwrap();
+        assert_eq!((report.files, report.linked), (2, 1));
+        let cloned = site_packages(&fresh).join("numpy/__init__.py");
+        fs::write(&cloned, "version = 2\n").unwrap();
+        assert_eq!(fs::read_to_string(site.join("numpy/__init__.py")).unwrap(), "version = 1\n");
+    }
+
+    #[test]
+    fn scripts_are_retargeted_by_whole_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let (comfy, fresh) = comfy_and_fresh(&dir.path().join("envs"));
+        fs::write(
+            site_packages(&comfy).join("numpy-1.0.dist-info/RECORD"),
+            "numpy/__init__.py,,\n../../../bin/f2py,,\n",
+        )
+        .unwrap();
+        let script = format!("#!{0}/bin/python\n# see {0}-old/notes\n", comfy.display());
+        fs::write(scripts_dir(&comfy).join("f2py"), &script).unwrap();
+
+        clone_env(&comfy, &fresh).unwrap();
+        let cloned = fs::read_to_string(scripts_dir(&fresh).join("f2py")).unwrap();
+        assert_eq!(cloned, format!("#!{}/bin/python\n# see {}-old/notes\n", fresh.display(), comfy.display()));
+    }
+}
@@ -0,0 +1,68 @@
+//! Project-local workspaces
+//!
+//! `portablesource init` creates `.portablesource/` in a project, the way a `.venv` sits
+//! next to the code that uses it: repositories, environments, tools and the configuration
+//! of that project live there instead of in the machine-wide install. Every command run in
+//! the project or any folder below it uses the nearest workspace; `--install-path` and
+//! `PORTABLESOURCE_INSTALL_PATH` still take precedence.
+
+use crate::Result;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const WORKSPACE_DIR: &str = ".portablesource";
+/// Tells a workspace apart from other `.portablesource` entries (the Linux install path
+/// record in the home folder is a file of that name)
+pub const WORKSPACE_MARKER: &str = ".portablesource_workspace";
+
+/// Whether `dir` is a workspace created by `init`
+pub fn is_workspace(dir: &Path) -> bool {
+    dir.join(WORKSPACE_MARKER).is_file()
+}
+
+/// Nearest workspace in `start` or one of its parents
+pub fn find(start: &Path) -> Option<PathBuf> {
+    start.ancestors().map(|dir| dir.join(WORKSPACE_DIR)).find(|dir| is_workspace(dir))
+}
+
+/// Nearest workspace of the current directory
+pub fn from_current_dir() -> Option<PathBuf> {
+    find(&std::env::current_dir().ok()?)
+}
+
+/// Create the workspace of `project`; returns its path and whether it was new
+pub fn init(project: &Path) -> Result<(PathBuf, bool)> {
+    let workspace = project.join(WORKSPACE_DIR);
+    if is_workspace(&workspace) {
+        return Ok((workspace, false));
+    }
+    fs::create_dir_all(&workspace)?;
+    crate::utils::create_directory_structure(&workspace)?;
+    // Environments and tools are machine-specific; keep them out of version control
+    crate::atomic_write::write(workspace.join(".gitignore"), "*\n")?;
+    crate::atomic_write::write(workspace.join(WORKSPACE_MARKER), "")?;
+    Ok((workspace, true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nearest_workspace_is_found_from_subfolders() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = dir.path().join("project");
+        let nested = project.join("src").join("models");
+        fs::create_dir_all(&nested).unwrap();
+        // A plain .portablesource entry (the Linux install path record) is not a workspace
+        fs::write(dir.path().join(WORKSPACE_DIR), "/opt/portablesource").unwrap();
+        assert_eq!(find(&nested), None);
+
+        let (workspace, created) = init(&project).unwrap();
+        assert!(created && workspace.join("repos").is_dir() && workspace.join(".gitignore").is_file());
+        assert_eq!(find(&nested), Some(workspace.clone()));
+        assert_eq!(find(&project), Some(workspace.clone()));
+        assert_eq!(init(&project).unwrap(), (workspace, false));
+        assert_eq!(find(dir.path()), None);
+    }
+}
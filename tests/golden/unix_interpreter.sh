@@ -0,0 +1,39 @@
+#!/usr/bin/env bash
+set -Eeuo pipefail
+
+INSTALL="/opt/portablesource"
+ENV_PATH="$INSTALL/ps_env"
+BASE_PREFIX="$ENV_PATH/mamba_env"
+REPO_PATH="/opt/portablesource/repos/ComfyUI"
+VENV="$INSTALL/envs/comfyui"
+PYEXE="$VENV/bin/python"
+
+# Detect mode: allow override via PORTABLESOURCE_MODE
+MODE="${PORTABLESOURCE_MODE:-}"
+if [[ -z "$MODE" ]]; then
+  if command -v git >/dev/null 2>&1 && command -v python3 >/dev/null 2>&1 && command -v ffmpeg >/dev/null 2>&1; then
+    MODE=cloud
+  else
+    MODE=desk
+  fi
+fi
+
+# prepend micromamba base bin to PATH (no activation) in DESK mode
+if [[ "$MODE" == "desk" ]]; then
+  export PATH="$BASE_PREFIX/bin:$PATH"
+fi
+
+# activate project venv if present (be tolerant to unset vars)
+if [[ -f "$VENV/bin/activate" ]]; then
+  set +u
+  source "$VENV/bin/activate" || true
+  set -u
+fi
+
+
+cd "$REPO_PATH"
+if [[ -x "$PYEXE" ]]; then
+  exec "$PYEXE"
+else
+  exec python3
+fi
@@ -0,0 +1,44 @@
+#!/usr/bin/env bash
+set -Eeuo pipefail
+
+INSTALL="/opt/portablesource"
+ENV_PATH="$INSTALL/ps_env"
+BASE_PREFIX="$ENV_PATH/mamba_env"
+REPO_PATH="/opt/portablesource/repos/ComfyUI"
+VENV="$INSTALL/envs/comfyui"
+PYEXE="$VENV/bin/python"
+
+# Detect mode: allow override via PORTABLESOURCE_MODE
+MODE="${PORTABLESOURCE_MODE:-}"
+if [[ -z "$MODE" ]]; then
+  if command -v git >/dev/null 2>&1 && command -v python3 >/dev/null 2>&1 && command -v ffmpeg >/dev/null 2>&1; then
+    MODE=cloud
+  else
+    MODE=desk
+  fi
+fi
+
+# prepend micromamba base bin to PATH (no activation) in DESK mode
+if [[ "$MODE" == "desk" ]]; then
+  export PATH="$BASE_PREFIX/bin:$PATH"
+fi
+
+# activate project venv if present (be tolerant to unset vars)
+if [[ -f "$VENV/bin/activate" ]]; then
+  set +u
+  source "$VENV/bin/activate" || true
+  set -u
+fi
+
+export CUDA_PATH="/opt/portablesource/ps_env/mamba_env"
+export CUDA_HOME="/opt/portablesource/ps_env/mamba_env"
+export CUDA_ROOT="/opt/portablesource/ps_env/mamba_env"
+export PATH="/opt/portablesource/ps_env/mamba_env/bin:$PATH"
+export LD_LIBRARY_PATH="/opt/portablesource/ps_env/mamba_env/lib:/opt/portablesource/ps_env/mamba_env/lib64:${LD_LIBRARY_PATH:-}"
+
+cd "$REPO_PATH"
+if [[ -x "$PYEXE" ]]; then
+  exec "$PYEXE" "main.py" --listen
+else
+  exec python3 "main.py" --listen
+fi
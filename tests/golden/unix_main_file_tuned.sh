@@ -0,0 +1,43 @@
+#!/usr/bin/env bash
+set -Eeuo pipefail
+
+INSTALL="/opt/portablesource"
+ENV_PATH="$INSTALL/ps_env"
+BASE_PREFIX="$ENV_PATH/mamba_env"
+REPO_PATH="/opt/portablesource/repos/ComfyUI"
+VENV="$INSTALL/envs/comfyui"
+PYEXE="$VENV/bin/python"
+
+# Detect mode: allow override via PORTABLESOURCE_MODE
+MODE="${PORTABLESOURCE_MODE:-}"
+if [[ -z "$MODE" ]]; then
+  if command -v git >/dev/null 2>&1 && command -v python3 >/dev/null 2>&1 && command -v ffmpeg >/dev/null 2>&1; then
+    MODE=cloud
+  else
+    MODE=desk
+  fi
+fi
+
+# prepend micromamba base bin to PATH (no activation) in DESK mode
+if [[ "$MODE" == "desk" ]]; then
+  export PATH="$BASE_PREFIX/bin:$PATH"
+fi
+
+# activate project venv if present (be tolerant to unset vars)
+if [[ -f "$VENV/bin/activate" ]]; then
+  set +u
+  source "$VENV/bin/activate" || true
+  set -u
+fi
+
+
+cd "$REPO_PATH"
+# Performance profile: low-vram
+export PYTORCH_CUDA_ALLOC_CONF="${PYTORCH_CUDA_ALLOC_CONF:-expandable_segments:True,garbage_collection_threshold:0.6}"
+export OMP_NUM_THREADS="${OMP_NUM_THREADS:-8}"
+
+if [[ -x "$PYEXE" ]]; then
+  exec "$PYEXE" "main.py" --listen
+else
+  exec python3 "main.py" --listen
+fi
@@ -0,0 +1,50 @@
+#!/usr/bin/env bash
+set -Eeuo pipefail
+
+INSTALL="$(cd "$(dirname "${BASH_SOURCE[0]}")/../.." && pwd)"
+ENV_PATH="$INSTALL/ps_env"
+BASE_PREFIX="$ENV_PATH/mamba_env"
+REPO_PATH="$INSTALL/repos/ComfyUI"
+VENV="$INSTALL/envs/comfyui"
+PYEXE="$VENV/bin/python"
+
+# Detect mode: allow override via PORTABLESOURCE_MODE
+MODE="${PORTABLESOURCE_MODE:-}"
+if [[ -z "$MODE" ]]; then
+  if command -v git >/dev/null 2>&1 && command -v python3 >/dev/null 2>&1 && command -v ffmpeg >/dev/null 2>&1; then
+    MODE=cloud
+  else
+    MODE=desk
+  fi
+fi
+
+# prepend micromamba base bin to PATH (no activation) in DESK mode
+if [[ "$MODE" == "desk" ]]; then
+  export PATH="$BASE_PREFIX/bin:$PATH"
+fi
+
+# activate project venv if present (be tolerant to unset vars)
+if [[ -f "$VENV/bin/activate" ]]; then
+  set +u
+  source "$VENV/bin/activate" || true
+  set -u
+fi
+
+TMP_ROOT="${PORTABLESOURCE_TMP:-$INSTALL/tmp}"
+export HOME="$TMP_ROOT/home"
+mkdir -p "$HOME"
+export XDG_CACHE_HOME="$TMP_ROOT/cache"
+mkdir -p "$XDG_CACHE_HOME"
+export XDG_CONFIG_HOME="$TMP_ROOT/config"
+mkdir -p "$XDG_CONFIG_HOME"
+export XDG_DATA_HOME="$TMP_ROOT/data"
+mkdir -p "$XDG_DATA_HOME"
+export TMPDIR="$TMP_ROOT/tmp"
+mkdir -p "$TMPDIR"
+
+cd "$REPO_PATH"
+if [[ -x "$PYEXE" ]]; then
+  exec "$PYEXE" -m comfy.cli --listen
+else
+  exec python3 -m comfy.cli --listen
+fi
@@ -0,0 +1,63 @@
+@echo off
+echo Launch facefusion...
+
+set base_path=C:\\portablesource
+set env_path=%base_path%\ps_env
+set envs_path=%base_path%\envs
+set repos_path=%base_path%\repos
+set ffmpeg_path=%env_path%\ffmpeg
+set git_path=%env_path%\git\bin
+set python_path=%envs_path%\facefusion
+set python_exe=%python_path%\python.exe
+set repo_path=%repos_path%\facefusion
+
+set tmp_path=%base_path%\tmp
+if defined PORTABLESOURCE_TMP set "tmp_path=%PORTABLESOURCE_TMP%"
+set USERPROFILE=%tmp_path%
+set TEMP=%tmp_path%\Temp
+set TMP=%tmp_path%\Temp
+set APPDATA=%tmp_path%\AppData\Roaming
+set LOCALAPPDATA=%tmp_path%\AppData\Local
+set HF_HOME=%repo_path%\huggingface_home
+set XDG_CACHE_HOME=%tmp_path%
+set HF_DATASETS_CACHE=%HF_HOME%\datasets
+
+set PYTHONIOENCODING=utf-8
+set PYTHONUNBUFFERED=1
+set PYTHONDONTWRITEBYTECODE=1
+
+REM === CUDA PATHS ===
+set cuda_bin=%env_path%\CUDA\bin
+set cuda_lib=%env_path%\CUDA\lib
+set cuda_lib_64=%env_path%\CUDA\lib\x64
+set cuda_nvml_bin=%env_path%\CUDA\nvml\bin
+set cuda_nvml_lib=%env_path%\CUDA\nvml\lib
+set cuda_nvvm_bin=%env_path%\CUDA\nvvm\bin
+set cuda_nvvm_lib=%env_path%\CUDA\nvvm\lib
+
+set PATH=%cuda_bin%;%PATH%
+set PATH=%cuda_lib%;%PATH%
+set PATH=%cuda_lib_64%;%PATH%
+set PATH=%cuda_nvml_bin%;%PATH%
+set PATH=%cuda_nvml_lib%;%PATH%
+set PATH=%cuda_nvvm_bin%;%PATH%
+set PATH=%cuda_nvvm_lib%;%PATH%
+
+set PATH=%python_path%;%PATH%
+set PATH=%python_path%\Scripts;%PATH%
+set PATH=%git_path%;%PATH%
+set PATH=%ffmpeg_path%;%PATH%
+
+cd /d "%repo_path%"
+"%python_exe%" facefusion.py run
+set EXIT_CODE=%ERRORLEVEL%
+
+if %EXIT_CODE% neq 0 (
+    echo.
+    echo Program finished with error (code: %EXIT_CODE%)
+) else (
+    echo.
+    echo Program finished successfully
+)
+
+pause
@@ -0,0 +1,62 @@
+@echo off
+echo Launch facefusion...
+
+REM Check if X: drive exists and unmount it
+if exist X:\ (
+    echo Unmounting existing X: drive...
+    subst X: /D >nul 2>&1
+)
+
+set "ROOT_PATH=%~dp0\..\..\"
+subst X: "%ROOT_PATH%"
+X:
+
+set base_path=X:
+set env_path=%base_path%\ps_env
+set envs_path=%base_path%\envs
+set repos_path=%base_path%\repos
+set ffmpeg_path=%env_path%\ffmpeg
+set git_path=%env_path%\git\bin
+set python_path=%envs_path%\facefusion
+set python_exe=%python_path%\python.exe
+set repo_path=%repos_path%\facefusion
+
+set tmp_path=%base_path%\tmp
+if defined PORTABLESOURCE_TMP set "tmp_path=%PORTABLESOURCE_TMP%"
+set USERPROFILE=%tmp_path%
+set TEMP=%tmp_path%\Temp
+set TMP=%tmp_path%\Temp
+set APPDATA=%tmp_path%\AppData\Roaming
+set LOCALAPPDATA=%tmp_path%\AppData\Local
+set HF_HOME=%repo_path%\huggingface_home
+set XDG_CACHE_HOME=%tmp_path%
+set HF_DATASETS_CACHE=%HF_HOME%\datasets
+
+set PYTHONIOENCODING=utf-8
+set PYTHONUNBUFFERED=1
+set PYTHONDONTWRITEBYTECODE=1
+
+REM === CUDA PATHS ===
+REM No CUDA paths configured
+
+set PATH=%python_path%;%PATH%
+set PATH=%python_path%\Scripts;%PATH%
+set PATH=%git_path%;%PATH%
+set PATH=%ffmpeg_path%;%PATH%
+
+cd /d "%repo_path%"
+"%python_exe%" -m facefusion.cli run
+set EXIT_CODE=%ERRORLEVEL%
+
+echo Cleaning up...
+subst X: /D
+
+if %EXIT_CODE% neq 0 (
+    echo.
+    echo Program finished with error (code: %EXIT_CODE%)
+) else (
+    echo.
+    echo Program finished successfully
+)
+
+pause
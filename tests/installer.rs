@@ -0,0 +1,620 @@
+//! Installer logic against mocked network, processes and clock
+
+use portablesource_rs::config::ConfigManager;
+use portablesource_rs::envs_manager::PortableEnvironmentManager;
+use portablesource_rs::gpu::ComputeCapability;
+use portablesource_rs::installer::pip_manager::{
+    is_installed_separately, nightly_channel_for, requirement_name, CONSTRAINTS_FILE, ENGINE_LOG_FILE,
+};
+use portablesource_rs::installer::script_generator::{render_unix_script, render_windows_script};
+use portablesource_rs::installer::{
+    CommandRunner, GitManager, InstallationPlan, LaunchTarget, NumpyDecision, OnStepError, PackageType, PipManager,
+    RequirementsAnalyzer, RequirementsIndexes, ScriptContext, ENGINE_MARKER_FILE,
+};
+use portablesource_rs::repo_metadata::{upstream_name, validate_instance_name, RepoMetadata};
+use portablesource_rs::repo_state;
+use portablesource_rs::repository_installer::RepositoryInstaller;
+use portablesource_rs::PortableSourceError;
+use portablesource_rs::system::{CommandOutput, Downloader};
+use portablesource_rs::testing::{MockDownloader, MockServices};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tempfile::TempDir;
+
+struct Fixture {
+    _dir: TempDir,
+    install_path: PathBuf,
+    config: ConfigManager,
+    mocks: MockServices,
+}
+
+impl Fixture {
+    fn new() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let install_path = dir.path().to_path_buf();
+        let mut config = ConfigManager::new(Some(install_path.join("portablesource_config.json"))).unwrap();
+        config.set_install_path(install_path.clone()).unwrap();
+        Self { _dir: dir, install_path, config, mocks: MockServices::new() }
+    }
+
+    fn env_manager(&self) -> PortableEnvironmentManager {
+        PortableEnvironmentManager::with_config(self.install_path.clone(), self.config.clone())
+            .with_services(self.mocks.services())
+    }
+
+    /// Create repos/<name> with an engine marker
+    fn repo(&self, name: &str, engine: &str) -> PathBuf {
+        let repo_path = self.install_path.join("repos").join(name);
+        fs::create_dir_all(&repo_path).unwrap();
+        repo_state::write(&repo_path, ENGINE_MARKER_FILE, engine).unwrap();
+        repo_path
+    }
+}
+
+#[test]
+fn requirement_line_gives_name_version_and_type() {
+    let fx = Fixture::new();
+    let pkg = RequirementsAnalyzer::new(&fx.config).parse_requirement_line("Pillow[extra]>=9.0  # images").unwrap();
+    assert_eq!((pkg.name.as_str(), pkg.version.as_deref(), pkg.package_type), ("pillow", Some("9.0"), PackageType::Regular));
+}
+
+#[test]
+fn torch_and_onnxruntime_lines_get_their_package_type() {
+    let fx = Fixture::new();
+    let analyzer = RequirementsAnalyzer::new(&fx.config);
+    assert_eq!(analyzer.parse_requirement_line("torch==2.1.0").unwrap().package_type, PackageType::Torch);
+    assert_eq!(analyzer.parse_requirement_line("onnxruntime-gpu").unwrap().package_type, PackageType::Onnxruntime);
+    assert_eq!(analyzer.parse_requirement_line("onnxruntime-extensions").unwrap().package_type, PackageType::Regular);
+}
+
+#[test]
+fn comments_includes_and_options_are_not_requirements() {
+    let fx = Fixture::new();
+    let analyzer = RequirementsAnalyzer::new(&fx.config);
+    for line in ["# comment", "-r base.txt", "--extra-index-url https://example.com"] {
+        assert!(analyzer.parse_requirement_line(line).is_none(), "{}", line);
+    }
+}
+
+#[test]
+fn requirement_names_come_from_pep508_not_substrings() {
+    assert_eq!(requirement_name("Pytorch_Lightning>=2.0 ; python_version >= '3.8'").as_deref(), Some("pytorch-lightning"));
+    assert_eq!(requirement_name("torch @ https://download.pytorch.org/whl/cu121/torch-2.3.1.whl").as_deref(), Some("torch"));
+    assert_eq!(requirement_name("https://example.com/wheels/triton-3.0.0-cp311-cp311-linux_x86_64.whl").as_deref(), Some("triton"));
+    assert_eq!(requirement_name("git+https://github.com/user/repo.git#egg=insightface").as_deref(), Some("insightface"));
+    assert_eq!(requirement_name("./local/package"), None);
+    assert_eq!(requirement_name("-e ."), None);
+}
+
+#[test]
+fn requirement_line_with_parenthesized_version_and_marker() {
+    let fx = Fixture::new();
+    let pkg = RequirementsAnalyzer::new(&fx.config)
+        .parse_requirement_line("torchvision (>=0.18) ; sys_platform != 'darwin'  # vision")
+        .unwrap();
+    assert_eq!((pkg.name.as_str(), pkg.version.as_deref(), pkg.package_type), ("torchvision", Some("0.18"), PackageType::Torch));
+}
+
+#[test]
+fn only_packages_installed_in_their_own_step_are_dropped_from_requirements() {
+    let kept: Vec<&str> = [
+        "torch==2.3.1",
+        "torchmetrics>=1.0",
+        "pytorch-lightning",
+        "torchsde",
+        "open_clip_torch",
+        "onnxruntime_gpu==1.18.0",
+        "onnxruntime-extensions",
+        "insightface==0.7.3",
+        "tritonclient[http]",
+        "triton ; sys_platform == 'linux'",
+        "# torch is installed separately",
+        "--extra-index-url https://download.pytorch.org/whl/cu121",
+    ]
+    .into_iter()
+    .filter(|line| !is_installed_separately(line))
+    .collect();
+    assert_eq!(
+        kept,
+        [
+            "torchmetrics>=1.0",
+            "pytorch-lightning",
+            "torchsde",
+            "open_clip_torch",
+            "onnxruntime-extensions",
+            "tritonclient[http]",
+            "# torch is installed separately",
+            "--extra-index-url https://download.pytorch.org/whl/cu121",
+        ]
+    );
+}
+
+#[test]
+fn plan_groups_packages_by_install_step() {
+    let fx = Fixture::new();
+    let analyzer = RequirementsAnalyzer::new(&fx.config);
+    let packages: Vec<_> = ["numpy", "torchvision", "insightface==0.7.3", "triton", "onnxruntime", "gradio"]
+        .iter()
+        .filter_map(|l| analyzer.parse_requirement_line(l))
+        .collect();
+
+    let plan = analyzer.create_installation_plan(&packages);
+    let names = |v: &[portablesource_rs::installer::PackageInfo]| v.iter().map(|p| p.name.clone()).collect::<Vec<_>>();
+    assert_eq!(names(&plan.regular_packages), ["numpy", "gradio"]);
+    assert_eq!(names(&plan.torch_packages), ["torchvision"]);
+    assert_eq!(names(&plan.insightface_packages), ["insightface"]);
+    assert_eq!(names(&plan.triton_packages), ["triton"]);
+    assert_eq!(names(&plan.onnx_packages), ["onnxruntime"]);
+    assert!(plan.torch_index_url.unwrap().starts_with("https://download.pytorch.org/whl/"));
+}
+
+#[test]
+fn cpu_only_session_installs_cpu_torch() {
+    let fx = Fixture::new();
+    let mut config = fx.config.clone();
+    config.set_cpu_only();
+    assert!(!config.has_cuda());
+    let packages: Vec<_> = ["torch"].iter().filter_map(|l| RequirementsAnalyzer::new(&config).parse_requirement_line(l)).collect();
+    let plan = RequirementsAnalyzer::new(&config).create_installation_plan(&packages);
+    assert_eq!(plan.torch_index_url.as_deref(), Some("https://download.pytorch.org/whl/cpu"));
+    assert!(plan.nightly.is_none());
+}
+
+#[test]
+fn compute_capability_setting_overrides_the_driver() {
+    let mut fx = Fixture::new();
+    fx.config.get_config_mut().compute_capability = Some("12.0".into());
+    let (cc, source) = fx.config.compute_capability().unwrap();
+    assert_eq!((cc.to_string().as_str(), source), ("12.0", "config"));
+}
+
+#[test]
+fn blackwell_capability_selects_the_nightly_channel() {
+    let fx = Fixture::new();
+    let cc: ComputeCapability = "12.0".parse().unwrap();
+    let note = nightly_channel_for(&fx.config, Some((cc, "driver")), "NVIDIA GeForce RTX 5090").unwrap();
+    assert_eq!(note.index_url, "https://download.pytorch.org/whl/nightly/cu128");
+    assert_eq!(note.compute_capability, Some(cc));
+    assert_eq!(note.reason, "compute capability 12.0 (driver) is 10.0 or newer, which stable wheels have no kernels for");
+}
+
+#[test]
+fn older_capability_stays_on_stable_whatever_the_name() {
+    let fx = Fixture::new();
+    let cc = "8.9".parse().unwrap();
+    assert_eq!(nightly_channel_for(&fx.config, Some((cc, "config")), "NVIDIA GeForce RTX 5090"), None);
+}
+
+#[test]
+fn unknown_capability_falls_back_on_the_gpu_name() {
+    let fx = Fixture::new();
+    let note = nightly_channel_for(&fx.config, None, "NVIDIA GeForce RTX 5080").unwrap();
+    assert_eq!(note.compute_capability, None);
+    assert_eq!(note.reason, "compute capability unknown and 'NVIDIA GeForce RTX 5080' looks like a Blackwell GPU");
+    assert_eq!(nightly_channel_for(&fx.config, None, "NVIDIA GeForce RTX 4090"), None);
+}
+
+fn requirements_indexes() -> RequirementsIndexes {
+    RequirementsIndexes::from_lines([
+        "--extra-index-url https://download.pytorch.org/whl/cu118",
+        "--extra-index-url=https://wheels.example.com/simple  # custom kernels",
+        "-f https://github.com/user/repo/releases/expanded_assets/v1",
+        "-fhttps://download.pytorch.org/whl/cu121/",
+        "-i https://mirror.example.com/simple",
+        "gradio",
+    ])
+}
+
+#[test]
+fn index_options_of_requirements_are_collected() {
+    let indexes = requirements_indexes();
+    assert_eq!(indexes.index_url.as_deref(), Some("https://mirror.example.com/simple"));
+    assert_eq!(indexes.extra_index_urls.len(), 2);
+    assert_eq!(indexes.find_links.len(), 2);
+    assert!(RequirementsIndexes::is_index_line("--find-links=./wheels"));
+    assert!(!RequirementsIndexes::is_index_line("-r base.txt"));
+}
+
+#[test]
+fn plan_keeps_requirements_indexes_but_not_foreign_torch_channels() {
+    let mut plan = InstallationPlan { torch_index_url: Some("https://download.pytorch.org/whl/cu121".into()), ..Default::default() };
+    plan.merge_indexes(requirements_indexes());
+    assert_eq!(
+        plan.indexes.args(),
+        [
+            "--index-url", "https://mirror.example.com/simple",
+            "--extra-index-url", "https://wheels.example.com/simple",
+            "--find-links", "https://github.com/user/repo/releases/expanded_assets/v1",
+            "--find-links", "https://download.pytorch.org/whl/cu121/",
+        ]
+    );
+    assert_eq!(plan.index_conflicts.len(), 1);
+    assert_eq!(plan.index_conflicts[0].url, "https://download.pytorch.org/whl/cu118");
+    assert!(plan.index_conflicts[0].to_string().starts_with("--extra-index-url https://download.pytorch.org/whl/cu118 ignored"));
+}
+
+#[test]
+fn plan_pins_numpy_for_numpy1_only_packages() {
+    let fx = Fixture::new();
+    let analyzer = RequirementsAnalyzer::new(&fx.config);
+    let plan = |lines: &[&str]| {
+        let packages: Vec<_> = lines.iter().filter_map(|l| analyzer.parse_requirement_line(l)).collect();
+        analyzer.create_installation_plan(&packages).numpy
+    };
+
+    assert_eq!(plan(&["torch==2.5.1", "onnxruntime-gpu", "gradio"]), NumpyDecision::Unpinned);
+    assert_eq!(
+        plan(&["torch==2.1.2+cu118", "onnxruntime-gpu==1.18.0", "insightface", "gradio"]),
+        NumpyDecision::Pinned(vec!["torch==2.1.2+cu118".into(), "onnxruntime-gpu==1.18.0".into(), "insightface".into()])
+    );
+    assert_eq!(plan(&["tensorflow"]), NumpyDecision::Pinned(vec!["tensorflow==2.15.0".into()]));
+    assert_eq!(plan(&["insightface", "numpy==1.24.4"]), NumpyDecision::Explicit("numpy==1.24.4".into()));
+}
+
+#[test]
+fn numpy_pin_constrains_later_install_steps() {
+    let fx = Fixture::new();
+    let repo_path = fx.repo("demo", "pip");
+    fs::write(repo_path.join("requirements.txt"), "insightface==0.7.3\nnumpy\n").unwrap();
+    let env = fx.env_manager();
+    let runner = CommandRunner::new(&env);
+    let pip = PipManager::new(&runner, &fx.config);
+
+    pip.install_requirements_with_uv_or_pip("demo", &repo_path.join("requirements.txt"), Some(&repo_path)).unwrap();
+
+    let constraints = repo_state::path(&repo_path, CONSTRAINTS_FILE);
+    assert_eq!(fs::read_to_string(&constraints).unwrap(), "numpy<2\n");
+    let flag = format!("install -c {}", constraints.display());
+    let installs: Vec<_> = fx.mocks.executor.command_lines().into_iter().filter(|l| l.contains(" install ")).collect();
+    assert!(!installs.is_empty());
+    assert!(installs.iter().all(|l| l.contains(&flag)), "{:?}", installs);
+}
+
+fn script_context(target: LaunchTarget) -> ScriptContext {
+    ScriptContext {
+        repo_name: "demo".into(),
+        repo_dir_name: "Demo".into(),
+        install_path: PathBuf::from("/opt/ps"),
+        repo_path: PathBuf::from("/opt/ps/repos/Demo"),
+        target,
+        program_args: "--port 7860".into(),
+        cuda: None,
+        virtual_drive: false,
+        portable: false,
+        tuning: None,
+        launch_env: Vec::new(),
+        system_git: None,
+        components: Vec::new(),
+        shared_prefix: None,
+    }
+}
+
+#[test]
+fn unix_script_runs_the_main_file_from_the_repo() {
+    let unix = render_unix_script(&script_context(LaunchTarget::MainFile("webapp/app.py".into())));
+    assert!(unix.starts_with("#!/usr/bin/env bash\n"));
+    assert!(unix.contains("REPO_PATH=\"/opt/ps/repos/Demo\""));
+    assert!(unix.contains("exec \"$PYEXE\" \"webapp/app.py\" --port 7860"));
+}
+
+#[test]
+fn windows_script_runs_a_module_target_with_its_arguments() {
+    let module = render_windows_script(&script_context(LaunchTarget::Module("demo.cli".into())));
+    assert!(module.contains("-m demo.cli --port 7860"));
+}
+
+#[test]
+fn interpreter_target_gets_no_program_arguments() {
+    let interpreter = render_unix_script(&script_context(LaunchTarget::Interpreter));
+    assert!(!interpreter.contains("--port"));
+}
+
+#[tokio::test]
+async fn clone_runs_git_in_repos_dir() {
+    let fx = Fixture::new();
+    let env = fx.env_manager();
+    let runner = CommandRunner::new(&env);
+    let git = GitManager::new(&runner, &env);
+    let repo_path = fx.install_path.join("repos").join("demo");
+
+    git.clone_or_update_repository_from_url("https://github.com/acme/demo.git", &repo_path).await.unwrap();
+
+    let calls = fx.mocks.executor.calls();
+    assert_eq!(calls.len(), 1);
+    assert!(calls[0].command_line().ends_with("clone https://github.com/acme/demo.git demo"));
+    assert_eq!(calls[0].cwd.as_deref(), Some(fx.install_path.join("repos").as_path()));
+}
+
+/// Error of `check_remote` when git fails with `stderr`
+fn remote_check_error(stderr: &str) -> String {
+    let fx = Fixture::new();
+    fx.mocks.executor.fail_on("ls-remote", stderr);
+    let env = fx.env_manager();
+    let runner = CommandRunner::new(&env);
+    let err = GitManager::new(&runner, &env).check_remote("https://example.com/acme/demo.git").unwrap_err().to_string();
+    assert!(!fx.install_path.join("repos").exists());
+    err
+}
+
+#[test]
+fn remote_is_checked_without_prompts() {
+    let fx = Fixture::new();
+    let env = fx.env_manager();
+    let runner = CommandRunner::new(&env);
+
+    GitManager::new(&runner, &env).check_remote("https://github.com/acme/demo.git").unwrap();
+    let call = &fx.mocks.executor.calls()[0];
+    assert!(call.command_line().ends_with("ls-remote https://github.com/acme/demo.git HEAD"));
+    assert_eq!(call.envs.get("GIT_TERMINAL_PROMPT").map(String::as_str), Some("0"));
+}
+
+#[test]
+fn missing_remote_is_a_repository_not_found_error() {
+    let err = remote_check_error("remote: Repository not found.\nfatal: repository 'https://example.com/acme/demo/' not found");
+    assert!(err.starts_with("Repository not found: https://example.com/acme/demo.git"), "{}", err);
+}
+
+#[test]
+fn private_remote_needs_credentials() {
+    let err = remote_check_error("fatal: could not read Username for 'https://example.com': terminal prompts disabled");
+    assert!(err.contains("needs credentials"), "{}", err);
+}
+
+#[test]
+fn unreachable_remote_names_the_url() {
+    let err = remote_check_error("fatal: unable to access 'https://example.com/acme/demo.git/': Could not resolve host: example.com");
+    assert!(err.contains("Cannot reach https://example.com/acme/demo.git: fatal: unable to access"), "{}", err);
+}
+
+#[test]
+fn worktrees_use_relative_links_when_git_supports_them() {
+    let fx = Fixture::new();
+    let base = fx.install_path.join("repos").join("demo");
+    let worktree = fx.install_path.join("repos").join("demo-dev");
+    fx.mocks.executor.succeed_with("--version", "git version 2.48.1\n");
+    let env = fx.env_manager();
+    let runner = CommandRunner::new(&env);
+
+    GitManager::new(&runner, &env).with_branch(Some("dev".into())).add_worktree(&base, &worktree).unwrap();
+    let lines = fx.mocks.executor.command_lines();
+    assert!(lines.last().unwrap().ends_with(&format!("worktree add --track --relative-paths -B dev {} origin/dev", worktree.display())));
+}
+
+#[test]
+fn existing_worktree_is_repaired_on_update() {
+    let fx = Fixture::new();
+    let base = fx.install_path.join("repos").join("demo");
+    let worktree = fx.install_path.join("repos").join("demo-dev");
+    fs::create_dir_all(&worktree).unwrap();
+    fs::write(worktree.join(".git"), "gitdir: /old/install/repos/demo/.git/worktrees/demo-dev\n").unwrap();
+    let env = fx.env_manager();
+    let runner = CommandRunner::new(&env);
+
+    GitManager::new(&runner, &env).with_branch(Some("dev".into())).add_worktree(&base, &worktree).unwrap();
+    let call = fx.mocks.executor.calls().into_iter().find(|c| c.command_line().contains("worktree repair")).unwrap();
+    assert!(call.command_line().ends_with(&format!("worktree repair {}", worktree.display())));
+    assert_eq!(call.cwd.as_deref(), Some(base.as_path()));
+}
+
+#[tokio::test]
+async fn corrupted_repo_is_removed_and_recloned() {
+    let fx = Fixture::new();
+    let repo_path = fx.install_path.join("repos").join("demo");
+    fs::create_dir_all(repo_path.join(".git")).unwrap();
+    fx.mocks.executor.respond(
+        " pull",
+        CommandOutput { code: Some(128), stdout: String::new(), stderr: "fatal: not a git repository".into() },
+    );
+
+    let env = fx.env_manager();
+    let runner = CommandRunner::new(&env);
+    GitManager::new(&runner, &env)
+        .clone_or_update_repository_from_url("https://github.com/acme/demo.git", &repo_path)
+        .await
+        .unwrap();
+
+    assert!(!repo_path.exists(), "corrupted checkout should be removed before re-clone");
+    let lines = fx.mocks.executor.command_lines();
+    assert!(lines[0].ends_with(" pull"));
+    assert!(lines.last().unwrap().contains(" clone "));
+}
+
+#[test]
+fn pip_engine_step_is_logged_with_clock_time() {
+    let fx = Fixture::new();
+    let repo_path = fx.repo("demo", "pip");
+    let env = fx.env_manager();
+    let runner = CommandRunner::new(&env);
+    let pip = PipManager::new(&runner, &fx.config);
+
+    pip.install_repo_as_package("demo", &repo_path).unwrap();
+
+    let lines = fx.mocks.executor.command_lines();
+    assert_eq!(lines.len(), 1, "pip engine must not probe uv: {:?}", lines);
+    assert!(lines[0].ends_with("-m pip install ."));
+    let log = fs::read_to_string(repo_state::path(&repo_path, ENGINE_LOG_FILE)).unwrap();
+    assert_eq!(log, "1700000000 repo-package pip ok\n");
+}
+
+#[test]
+fn auto_engine_falls_back_to_pip_when_uv_fails() {
+    let fx = Fixture::new();
+    let repo_path = fx.repo("demo", "auto");
+    fx.mocks.executor.fail_on("uv pip install", "error: resolution failed");
+    let env = fx.env_manager();
+    let runner = CommandRunner::new(&env);
+    let pip = PipManager::new(&runner, &fx.config);
+
+    pip.install_repo_as_package("demo", &repo_path).unwrap();
+
+    let lines = fx.mocks.executor.command_lines();
+    assert!(lines.iter().any(|l| l.ends_with("-m uv --version")));
+    assert!(lines.last().unwrap().ends_with("-m pip install ."));
+    let log = fs::read_to_string(repo_state::path(&repo_path, ENGINE_LOG_FILE)).unwrap();
+    assert_eq!(log.lines().collect::<Vec<_>>(), ["1700000000 repo-package uv failed", "1700000000 repo-package pip ok"]);
+}
+
+#[test]
+fn failed_optional_step_is_skipped_and_recorded_in_metadata() {
+    let fx = Fixture::new();
+    let repo_path = fx.repo("demo", "pip");
+    fx.mocks.executor.fail_on("pip install triton", "ERROR: No matching distribution found for triton");
+    let env = fx.env_manager();
+    let runner = CommandRunner::new(&env);
+    let pip = PipManager::new(&runner, &fx.config).with_on_error(OnStepError::SkipOptional);
+
+    let step = serde_json::json!({ "type": "pip_install", "packages": ["triton"] });
+    pip.process_server_step("demo", &step, Some(&repo_path)).unwrap();
+
+    let skipped = pip.skipped_steps();
+    assert_eq!(skipped.len(), 1);
+    assert_eq!(skipped[0].step, "triton");
+    assert!(skipped[0].error.contains("No matching distribution"), "{}", skipped[0].error);
+    assert_eq!(skipped[0].skipped_at, 1700000000);
+
+    let mut metadata = RepoMetadata { name: "demo".into(), ..Default::default() };
+    metadata.record_skipped_steps(&repo_path, skipped.clone()).unwrap();
+    assert_eq!(RepoMetadata::load(&repo_path).unwrap().unwrap().skipped_steps, skipped);
+}
+
+#[test]
+fn failed_required_step_aborts_under_skip_optional() {
+    let fx = Fixture::new();
+    let repo_path = fx.repo("demo", "pip");
+    fx.mocks.executor.fail_on("pip install . --no-deps", "ERROR: invalid pyproject.toml");
+    let env = fx.env_manager();
+    let runner = CommandRunner::new(&env);
+    let pip = PipManager::new(&runner, &fx.config).with_on_error(OnStepError::SkipOptional);
+
+    assert!(pip.install_repo_without_deps("demo", &repo_path).is_err());
+    assert!(pip.skipped_steps().is_empty());
+}
+
+#[tokio::test]
+async fn update_all_attempts_every_repository_and_reports_the_failures_together() {
+    let fx = Fixture::new();
+    for name in ["comfyui", "forge"] {
+        let repo_path = fx.repo(name, "pip");
+        let metadata = RepoMetadata { name: name.into(), branch: Some("dev".into()), ..Default::default() };
+        metadata.save(&repo_path).unwrap();
+    }
+    fx.mocks.executor.fail_on("reset --hard origin/dev", "fatal: ambiguous argument 'origin/dev'");
+    let mut installer = RepositoryInstaller::new(fx.install_path.clone(), fx.config.clone()).with_services(fx.mocks.services());
+
+    let Err(PortableSourceError::Multi(failed)) = installer.update_all(false).await else { panic!("expected a multi error") };
+    assert_eq!((failed.operation.as_str(), failed.total), ("update", 2));
+    let mut items: Vec<&str> = failed.failures.iter().map(|f| f.item.as_str()).collect();
+    items.sort();
+    assert_eq!(items, ["comfyui", "forge"]);
+    assert_eq!(PortableSourceError::Multi(failed).exit_code(), 1);
+    let resets = fx.mocks.executor.command_lines().iter().filter(|l| l.contains("reset --hard origin/dev")).count();
+    assert_eq!(resets, 2, "the second repository is updated after the first failed");
+
+    assert!(installer.update_all(true).await.is_ok());
+}
+
+#[tokio::test]
+async fn mock_downloader_serves_registered_urls_only() {
+    let dir = tempfile::tempdir().unwrap();
+    let downloader = MockDownloader::new().with_file("https://example.com/tool.tar.zst", b"archive".to_vec());
+    let dest = dir.path().join("nested").join("tool.tar.zst");
+
+    downloader.download("https://example.com/tool.tar.zst", &dest).await.unwrap();
+    assert_eq!(fs::read(&dest).unwrap(), b"archive");
+    assert!(downloader.download("https://example.com/missing", &dest).await.is_err());
+    assert_eq!(downloader.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn setup_with_progress_downloads_exactly_the_planned_files() {
+    let fx = Fixture::new();
+    let env = fx.env_manager();
+    let mut planned: Vec<String> = env.planned_downloads().items.into_iter().map(|d| d.url).collect();
+    assert!(!planned.is_empty());
+
+    let totals = Arc::new(Mutex::new(Vec::new()));
+    let sink = totals.clone();
+    // Nothing is registered with the mock downloader, so every task stops after its request
+    assert!(env.setup_environment_with_progress(move |e| sink.lock().unwrap().push(e.total)).await.is_err());
+
+    assert_eq!(totals.lock().unwrap()[0], planned.len() * 2);
+    let mut requested = fx.mocks.downloader.requests();
+    requested.sort();
+    planned.sort();
+    assert_eq!(requested, planned);
+}
+
+#[test]
+fn instances_resolve_to_their_upstream_name() {
+    let fx = Fixture::new();
+    let plain = fx.repo("ComfyUI", "auto");
+    let instance = fx.repo("comfyui-video", "auto");
+    RepoMetadata { name: "comfyui-video".into(), upstream: Some("ComfyUI".into()), ..Default::default() }
+        .save(&instance)
+        .unwrap();
+
+    assert_eq!(upstream_name(&plain), "comfyui");
+    assert_eq!(upstream_name(&instance), "comfyui");
+}
+
+#[test]
+fn instance_names_stay_inside_the_repos_folder() {
+    assert!(validate_instance_name("comfyui-video").is_ok());
+    for bad in ["", "..", "../comfyui", "a/b", ".hidden"] {
+        assert!(validate_instance_name(bad).is_err(), "{:?} should be rejected", bad);
+    }
+}
+
+#[test]
+fn tools_that_are_not_installed_are_not_found() {
+    let env = Fixture::new().env_manager();
+    assert_eq!(env.find_tool_executable("ffmpeg"), None);
+    assert_eq!(env.find_tool_executable("unknown"), None);
+}
+
+/// Installing `fallback` then `preferred` under ps_env switches git to each in turn
+fn assert_git_search_order(fallback: &str, preferred: &str) {
+    let fx = Fixture::new();
+    let env = fx.env_manager();
+    for rel in [fallback, preferred] {
+        let path = fx.install_path.join("ps_env").join(rel);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, b"").unwrap();
+        assert_eq!(env.get_git_executable(), Some(path));
+    }
+}
+
+#[cfg(windows)]
+#[test]
+fn git_cmd_wrapper_wins_over_git_bin() {
+    assert_git_search_order("git/bin/git.exe", "git/cmd/git.exe");
+}
+
+#[cfg(unix)]
+#[test]
+fn git_of_the_mamba_env_wins_over_portable_git() {
+    assert_git_search_order("git/bin/git", "mamba_env/bin/git");
+}
+
+#[cfg(unix)]
+#[test]
+fn deleting_a_repository_removes_what_its_plan_lists_and_keeps_shared_models() {
+    let fx = Fixture::new();
+    let repo_path = fx.repo("comfyui", "uv");
+    let shared = fx.install_path.join("shared_models/checkpoints");
+    fs::create_dir_all(&shared).unwrap();
+    fs::write(shared.join("sd15.safetensors"), "weights").unwrap();
+    fs::create_dir_all(repo_path.join("models")).unwrap();
+    std::os::unix::fs::symlink(&shared, repo_path.join("models/checkpoints")).unwrap();
+    fs::create_dir_all(fx.install_path.join("envs/comfyui")).unwrap();
+
+    let installer = RepositoryInstaller::new(fx.install_path.clone(), fx.config.clone());
+    let planned: Vec<PathBuf> = installer.plan_delete("comfyui").unwrap().items.into_iter().map(|i| i.path).collect();
+    installer.delete_repository("comfyui").unwrap();
+
+    assert_eq!(planned.len(), 4);
+    assert!(planned.iter().all(|path| fs::symlink_metadata(path).is_err()), "{:?}", planned);
+    assert!(!repo_state::dir(&repo_path).exists());
+    assert_eq!(fs::read_to_string(shared.join("sd15.safetensors")).unwrap(), "weights");
+}